@@ -44,9 +44,11 @@
 
 use aer::{
     discovery::DiscoveryServiceCommands, grpc::HealthCommands,
-    observe::ObserveServiceCommands, runtime::CellServiceCommands,
+    images::ImageServiceCommands, observe::ObserveServiceCommands,
+    runtime::CellServiceCommands, vms::VmServiceCommands,
 };
 use clap::{Parser, Subcommand};
+use client::quota::Quota;
 
 #[derive(Debug, Parser)]
 #[command(name = "aer")]
@@ -73,10 +75,20 @@ enum Commands {
         command: HealthCommands,
     },
     #[command(arg_required_else_help = true)]
+    Images {
+        #[command(subcommand)]
+        command: ImageServiceCommands,
+    },
+    #[command(arg_required_else_help = true)]
     Observe {
         #[command(subcommand)]
         command: ObserveServiceCommands,
     },
+    #[command(arg_required_else_help = true)]
+    Vms {
+        #[command(subcommand)]
+        command: VmServiceCommands,
+    },
 }
 
 #[tokio::main]
@@ -87,8 +99,13 @@ async fn main() {
         Commands::Cell { command } => command.execute().await,
         Commands::Discovery { command } => command.execute().await,
         Commands::Health { command } => command.execute().await,
+        Commands::Images { command } => command.execute().await,
         Commands::Observe { command } => command.execute().await,
+        Commands::Vms { command } => command.execute().await,
     } {
         eprintln!("{e:#?}");
+        if let Some(quota) = Quota::from_error(&e) {
+            eprintln!("{quota}");
+        }
     }
 }
\ No newline at end of file
@@ -0,0 +1,37 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+macros::subcommand!(
+    "../api/v0/images/images.proto",
+    images,
+    ImageService,
+    Pull {
+        image[required = true],
+    },
+    PullStream {
+        image[required = true],
+        resume_token[long, default_value = ""],
+    },
+    Preload {
+        path[required = true],
+    },
+    PrepareSnapshot {
+        image[required = true],
+        key[required = true],
+    },
+    RemoveSnapshot {
+        key[required = true],
+    },
+);
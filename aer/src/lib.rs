@@ -73,8 +73,10 @@ unused_results
 pub mod cri;
 pub mod discovery;
 pub mod grpc;
+pub mod images;
 pub mod observe;
 pub mod runtime;
+pub mod vms;
 
 /// Executes an rpc call with the default `Client` and prints the results.
 #[macro_export]
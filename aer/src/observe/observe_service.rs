@@ -46,5 +46,6 @@ macros::subcommand!("../api/v0/observe/observe.proto", observe, ObserveService,
     GetSubProcessStream {
         process_id[required = true, alias = "pid"],
         channel_type[default_value = "1"],  // default to stdout
+        resume_token[long, default_value = ""],
     },
 );
\ No newline at end of file
@@ -0,0 +1,114 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The VmService console is a bidirectional stream, which the
+//! `macros::subcommand!` generated commands do not support yet.
+
+use clap::Subcommand;
+use client::{vms::vm_service::VmServiceClient, Client};
+use futures_util::{stream, StreamExt};
+use proto::vms::{
+    VmServiceConsoleLogRequest, VmServiceConsoleRequest,
+    VmServiceGuestEventsRequest,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[derive(Debug, Subcommand)]
+pub enum VmServiceCommands {
+    /// Attach to the serial console of a VM. Input is read from stdin,
+    /// end it (Ctrl-D) to detach.
+    #[command(arg_required_else_help = true)]
+    Console { vm_id: String },
+    /// Print the output auraed kept of the serial console of a VM, e.g. to
+    /// find out why it failed to boot.
+    #[command(arg_required_else_help = true)]
+    ConsoleLog { vm_id: String },
+    /// Print the events the guests of VMs push to auraed, or only those of
+    /// the VM `vm_id`.
+    GuestEvents { vm_id: Option<String> },
+}
+
+impl VmServiceCommands {
+    pub async fn execute(self) -> anyhow::Result<()> {
+        match self {
+            Self::Console { vm_id } => console(vm_id).await,
+            Self::ConsoleLog { vm_id } => console_log(vm_id).await,
+            Self::GuestEvents { vm_id } => guest_events(vm_id).await,
+        }
+    }
+}
+
+async fn console(vm_id: String) -> anyhow::Result<()> {
+    let client = Client::default().await?;
+
+    let first = VmServiceConsoleRequest { vm_id, ..Default::default() };
+    let input = stream::unfold(tokio::io::stdin(), |mut stdin| async move {
+        let mut buf = vec![0; 1024];
+        match stdin.read(&mut buf).await {
+            Ok(0) | Err(_) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                let req = VmServiceConsoleRequest {
+                    input: buf,
+                    ..Default::default()
+                };
+                Some((req, stdin))
+            }
+        }
+    });
+    let requests = stream::once(async { first }).chain(input);
+
+    let mut output = client.console(Box::pin(requests)).await?.into_inner();
+    let mut stdout = tokio::io::stdout();
+    while let Some(res) = output.next().await {
+        stdout.write_all(&res?.output).await?;
+        stdout.flush().await?;
+    }
+
+    Ok(())
+}
+
+async fn console_log(vm_id: String) -> anyhow::Result<()> {
+    let client = Client::default().await?;
+
+    let res = client
+        .console_log(VmServiceConsoleLogRequest { vm_id })
+        .await?
+        .into_inner();
+    if res.truncated {
+        eprintln!("(older output was dropped)");
+    }
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(&res.output).await?;
+    stdout.flush().await?;
+
+    Ok(())
+}
+
+async fn guest_events(vm_id: Option<String>) -> anyhow::Result<()> {
+    let client = Client::default().await?;
+
+    let req = VmServiceGuestEventsRequest {
+        vm_id: vm_id.unwrap_or_default(),
+        ..Default::default()
+    };
+    let mut events = client.guest_events(req).await?.into_inner();
+    while let Some(event) = events.next().await {
+        let event = event?;
+        println!("{event:#?}");
+    }
+
+    Ok(())
+}
@@ -34,9 +34,18 @@
 )]
 #![warn(clippy::unwrap_used)]
 
-use auraed::{prep_oci_spec_for_spawn, run, AuraedRuntime};
+use auraed::{
+    capture_core_dump, keep_executable_pipes, prep_oci_spec_for_spawn, run,
+    Arch, AuraedRuntime, CniConfig, ContainerLogConfig, EbpfConfig, EbpfProbes,
+    GrpcLimits, ImagePullConfig, IpamConfig, JailerConfig, LogFormat,
+    LogJournalConfig, PodVmConfig, Preflight, SubsystemsConfig, SyslogEndpoint,
+    TokioConfig, UtilizationConfig,
+};
 use clap::{Parser, Subcommand};
-use std::path::PathBuf;
+use ipnetwork::Ipv4Network;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tracing::{error, info};
 
 /// Default exit code for successful termination of auraed.
@@ -94,6 +103,178 @@ struct AuraedOptions {
     /// should respect this value.
     #[clap(short, long, value_parser)]
     library_dir: Option<String>,
+    /// Maximum number of image blobs fetched at the same time. Defaults to 3.
+    #[clap(long, value_parser)]
+    max_concurrent_downloads: Option<usize>,
+    /// Maximum number of image layers unpacked at the same time. Defaults to
+    /// the number of CPUs.
+    #[clap(long, value_parser)]
+    max_concurrent_unpacks: Option<usize>,
+    /// Maximum bytes per second fetched by all image pulls. Unlimited by
+    /// default.
+    #[clap(long, value_parser)]
+    max_download_bandwidth: Option<u64>,
+    /// JSON file with the credentials and mirrors of registries. Defaults to
+    /// /etc/aurae/registries.json.
+    #[clap(long, value_parser)]
+    registries_config: Option<String>,
+    /// docker config.json to read the credentials of registries from.
+    /// Defaults to config.json in $DOCKER_CONFIG or /root/.docker.
+    #[clap(long, value_parser)]
+    docker_config: Option<String>,
+    /// Worker threads of the tokio runtime. Defaults to the number of CPUs.
+    #[clap(long, value_parser)]
+    worker_threads: Option<usize>,
+    /// Maximum number of threads running blocking operations. Defaults
+    /// to 512.
+    #[clap(long, value_parser)]
+    max_blocking_threads: Option<usize>,
+    /// Worker threads of a separate tokio runtime driving the VMM. VM
+    /// operations share the main runtime by default.
+    #[clap(long, value_parser)]
+    vm_worker_threads: Option<usize>,
+    /// Run the VMM threads without the seccomp filters of the VMM.
+    #[clap(long)]
+    no_vmm_seccomp: bool,
+    /// Let the VMM threads keep all capabilities of auraed.
+    #[clap(long)]
+    vmm_keep_capabilities: bool,
+    /// Empty directory the VMM threads are chrooted into once their VM
+    /// booted. Chrooted VMs cannot be snapshotted, checkpointed or migrated
+    /// over unix sockets. Not chrooted by default.
+    #[clap(long, value_parser)]
+    vmm_chroot: Option<String>,
+    /// Largest gRPC request message accepted, in bytes. Defaults to 4 MiB.
+    #[clap(long, value_parser)]
+    grpc_max_request_size: Option<usize>,
+    /// Largest gRPC response message sent, in bytes. Defaults to 64 MiB.
+    #[clap(long, value_parser)]
+    grpc_max_response_size: Option<usize>,
+    /// How deep messages may be nested within a gRPC request. Defaults to
+    /// 32.
+    #[clap(long, value_parser)]
+    grpc_max_recursion_depth: Option<usize>,
+    /// Reject gRPC requests setting fields unknown to this auraed instead
+    /// of ignoring them.
+    #[clap(long)]
+    grpc_reject_unknown_fields: bool,
+    /// Seconds between two samples of the utilization of the node and its
+    /// cells. Defaults to 5.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    utilization_interval: Option<u64>,
+    /// Comma separated windows, in seconds, of the moving averages of the
+    /// utilization of the node and its cells. Defaults to 60,300,900.
+    #[clap(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        value_delimiter = ','
+    )]
+    utilization_windows: Vec<u64>,
+    /// Directory of the CNI network configurations of pod sandboxes.
+    /// Defaults to /etc/cni/net.d.
+    #[clap(long, value_parser)]
+    cni_conf_dir: Option<String>,
+    /// Comma separated directories searched for CNI plugins. Defaults to
+    /// /opt/cni/bin.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    cni_bin_dirs: Vec<String>,
+    /// CNI network configuration written to the configuration directory
+    /// once the kubelet sets the pod CIDR of the node, with `{{.PodCIDR}}`
+    /// replaced by it.
+    #[clap(long, value_parser)]
+    cni_conf_template: Option<String>,
+    /// Pool of the addresses of cells isolating their network. Defaults to
+    /// 10.250.0.0/16.
+    #[clap(long, value_parser)]
+    cell_address_pool: Option<Ipv4Network>,
+    /// Pool of the addresses of pods while no CNI network is configured.
+    /// Defaults to 10.251.0.0/16.
+    #[clap(long, value_parser)]
+    pod_address_pool: Option<Ipv4Network>,
+    /// Comma separated addresses of the pools that are never leased.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    reserved_addresses: Vec<Ipv4Addr>,
+    /// Address the sessions of CRI Exec calls are served on, on an ephemeral
+    /// port if it is 0. Defaults to 127.0.0.1:0.
+    #[clap(long, value_parser)]
+    cri_streaming_address: Option<SocketAddr>,
+    /// Size in bytes past which the CRI log of a container is rotated.
+    /// Defaults to 10 MiB.
+    #[clap(long, value_parser)]
+    container_log_max_size: Option<u64>,
+    /// Files kept of the CRI log of a container. Defaults to 5.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    container_log_max_files: Option<u64>,
+    /// Seconds after which the CRI log of a container is rotated, whatever
+    /// its size. Only rotated by size by default.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    container_log_max_age: Option<u64>,
+    /// Size in bytes past which the oldest items of the journal of a log
+    /// channel are removed. Defaults to 8 MiB.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    log_journal_max_size: Option<u64>,
+    /// Seconds after which the items of the journal of a log channel are
+    /// removed, whatever its size. Only bounded by size by default.
+    #[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+    log_journal_max_age: Option<u64>,
+    /// Syslog endpoint the logs of auraed and the output of its workloads
+    /// are forwarded to, as unix:///path, udp://host:port or
+    /// tcp://host:port. Not forwarded by default.
+    #[clap(long, value_parser)]
+    syslog_endpoint: Option<SyslogEndpoint>,
+    /// Format of the logs auraed writes to stdout, text or json. Defaults
+    /// to text.
+    #[clap(long, value_parser)]
+    log_format: Option<LogFormat>,
+    /// Config of the exporters shipping the logs of auraed and its
+    /// workloads to Loki or fluent-forward endpoints. Defaults to
+    /// /etc/aurae/log-exporters.json.
+    #[clap(long, value_parser)]
+    log_exporters_config: Option<String>,
+    /// Comma separated subsystems not to start: vms, cri, ebpf and
+    /// discovery. Their services are not served. All start by default.
+    #[clap(long, value_parser)]
+    disable: Option<SubsystemsConfig>,
+    /// Only report the eBPF events of processes in cells, dropping the
+    /// others in the kernel, e.g. to spare busy hosts. Streams of the events
+    /// of the whole host then only carry the events of cells.
+    #[clap(long)]
+    ebpf_cells_only: bool,
+    /// Comma separated eBPF probes not to load: forks, exits, signals,
+    /// execs, tcp_connects, oom_kills, network and cpu_profiles. All load by
+    /// default.
+    #[clap(long, value_parser)]
+    disable_probes: Option<EbpfProbes>,
+    /// Config of the eBPF probes, combined with the flags. Defaults to
+    /// /etc/aurae/ebpf.json.
+    #[clap(long, value_parser)]
+    ebpf_config: Option<String>,
+    /// Config of the webhooks notified about workload events. Defaults to
+    /// /etc/aurae/webhooks.json.
+    #[clap(long, value_parser)]
+    webhooks_config: Option<String>,
+    /// Kernel of the VMs pods with the aurae-vm runtime handler run in.
+    /// Defaults to /var/lib/aurae/vm/kernel/vmlinux.bin.
+    #[clap(long, value_parser)]
+    pod_vm_kernel: Option<String>,
+    /// Comma separated arguments of the kernel of pod VMs. Defaults to
+    /// console=hvc0,root=/dev/vda1,rw.
+    #[clap(long, value_parser, value_delimiter = ',')]
+    pod_vm_kernel_args: Vec<String>,
+    /// Root filesystem image of pod VMs, with auraed as its init. Each VM
+    /// boots a copy of it. Defaults to /var/lib/aurae/vm/image/disk.raw.
+    #[clap(long, value_parser)]
+    pod_vm_image: Option<String>,
+    /// Memory of each pod VM in MiB. Defaults to 512.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pod_vm_memory_mb: Option<u32>,
+    /// vCPUs of each pod VM. Defaults to 1.
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    pod_vm_vcpus: Option<u32>,
+    /// OCI lifecycle hooks run around pod sandboxes, in the format of the
+    /// hooks of an OCI spec. Defaults to /etc/aurae/oci-hooks.json.
+    #[clap(long, value_parser)]
+    oci_hooks_config: Option<String>,
     /// Toggle verbosity. Default false
     #[clap(short, long, alias = "ritz")]
     verbose: bool,
@@ -110,26 +291,89 @@ enum SubCommands {
     Spawn {
         #[clap(short, long, value_parser, default_value = ".")]
         output: String,
+        /// Architecture of the bundle, x86_64 or aarch64. Bundles for
+        /// another architecture than the host's package the auraed binary
+        /// at <library-dir>/bin/<arch>/auraed. Defaults to the host's.
+        #[clap(long, value_parser)]
+        arch: Option<String>,
+    },
+    /// Check that the host can run auraed: its kernel, cgroups, namespaces,
+    /// seccomp, vsock, KVM, eBPF and PKI files. Exits with an error if any
+    /// check fails.
+    Check {
+        /// Print the report as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Store a core dump read from stdin. Invoked by the kernel through
+    /// kernel.core_pattern, which auraed sets up when running as pid 1.
+    #[clap(hide = true)]
+    CoreDump {
+        pid: i32,
+        signal: i32,
+        timestamp: i64,
+        /// The executable name, split by the kernel if it contains spaces
+        #[clap(num_args = 1..)]
+        executable: Vec<String>,
     },
+    /// Hold the output pipes of the executables released for an upgrade
+    /// until the next auraed takes them over through `socket`. Started by
+    /// auraed itself, with the listening socket as stdin.
+    #[clap(hide = true)]
+    KeepPipes { socket: PathBuf },
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Parse command line arguments into AuraedOptions
     let options = AuraedOptions::parse();
 
+    let tokio = TokioConfig {
+        worker_threads: options.worker_threads,
+        max_blocking_threads: options.max_blocking_threads,
+        vm_worker_threads: options.vm_worker_threads,
+    };
+    tokio.build()?.block_on(async_main(options, tokio))
+}
+
+async fn async_main(
+    options: AuraedOptions,
+    tokio: TokioConfig,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Match on the subcommand and handle accordingly
     let exit_code = match &options.subcmd {
-        Some(SubCommands::Spawn { output }) => {
-            handle_spawn_subcommand(output).await
+        Some(SubCommands::Spawn { output, arch }) => {
+            handle_spawn_subcommand(
+                options.library_dir.as_deref(),
+                output,
+                arch.as_deref(),
+            )
+            .await
+        }
+        Some(SubCommands::Check { json }) => handle_check_subcommand(
+            options.server_crt.as_deref(),
+            options.server_key.as_deref(),
+            options.ca_crt.as_deref(),
+            *json,
+        ),
+        Some(SubCommands::CoreDump { pid, signal, timestamp, executable }) => {
+            handle_core_dump_subcommand(
+                options.runtime_dir.as_deref(),
+                *pid,
+                *signal,
+                *timestamp,
+                executable.join(" "),
+            )
+        }
+        Some(SubCommands::KeepPipes { socket }) => {
+            handle_keep_pipes_subcommand(socket)
         }
-        None => handle_default(options).await,
+        None => handle_default(options, tokio).await,
     };
 
     std::process::exit(exit_code);
 }
 
-async fn handle_default(options: AuraedOptions) -> i32 {
+async fn handle_default(options: AuraedOptions, tokio: TokioConfig) -> i32 {
     info!("Starting Aurae Daemon Runtime");
     info!("Aurae Daemon is pid {}", std::process::id());
 
@@ -141,6 +385,49 @@ async fn handle_default(options: AuraedOptions) -> i32 {
         socket,
         runtime_dir,
         library_dir,
+        max_concurrent_downloads,
+        max_concurrent_unpacks,
+        max_download_bandwidth,
+        registries_config,
+        docker_config,
+        worker_threads: _,
+        max_blocking_threads: _,
+        vm_worker_threads: _,
+        no_vmm_seccomp,
+        vmm_keep_capabilities,
+        vmm_chroot,
+        grpc_max_request_size,
+        grpc_max_response_size,
+        grpc_max_recursion_depth,
+        grpc_reject_unknown_fields,
+        utilization_interval,
+        utilization_windows,
+        cni_conf_dir,
+        cni_bin_dirs,
+        cni_conf_template,
+        cell_address_pool,
+        pod_address_pool,
+        reserved_addresses,
+        cri_streaming_address,
+        container_log_max_size,
+        container_log_max_files,
+        container_log_max_age,
+        log_journal_max_size,
+        log_journal_max_age,
+        syslog_endpoint,
+        log_format,
+        log_exporters_config,
+        disable,
+        ebpf_cells_only,
+        disable_probes,
+        ebpf_config,
+        webhooks_config,
+        pod_vm_kernel,
+        pod_vm_kernel_args,
+        pod_vm_image,
+        pod_vm_memory_mb,
+        pod_vm_vcpus,
+        oci_hooks_config,
         verbose,
         nested,
         subcmd: _,
@@ -154,6 +441,25 @@ async fn handle_default(options: AuraedOptions) -> i32 {
         server_key: default_server_key,
         runtime_dir: default_runtime_dir,
         library_dir: default_library_dir,
+        image_pull: default_image_pull,
+        tokio: _,
+        jailer: _,
+        grpc_limits: default_grpc_limits,
+        utilization: default_utilization,
+        cni: default_cni,
+        cri_streaming_address: default_cri_streaming_address,
+        container_logs: default_container_logs,
+        log_journal: default_log_journal,
+        syslog: default_syslog,
+        log_format: default_log_format,
+        log_exporters: default_log_exporters,
+        subsystems: default_subsystems,
+        ebpf: _,
+        ebpf_config: default_ebpf_config,
+        webhooks: default_webhooks,
+        ipam: default_ipam,
+        pod_vm: default_pod_vm,
+        oci_hooks: default_oci_hooks,
     } = AuraedRuntime::default();
 
     // Create a new runtime configuration, using provided options or defaults
@@ -168,6 +474,123 @@ async fn handle_default(options: AuraedOptions) -> i32 {
         library_dir: library_dir
             .map(PathBuf::from)
             .unwrap_or(default_library_dir),
+        image_pull: ImagePullConfig {
+            max_concurrent_downloads: max_concurrent_downloads
+                .unwrap_or(default_image_pull.max_concurrent_downloads),
+            max_concurrent_unpacks: max_concurrent_unpacks
+                .unwrap_or(default_image_pull.max_concurrent_unpacks),
+            max_download_bandwidth: max_download_bandwidth
+                .or(default_image_pull.max_download_bandwidth),
+            registries: registries_config
+                .map(PathBuf::from)
+                .unwrap_or(default_image_pull.registries),
+            docker_config: docker_config
+                .map(PathBuf::from)
+                .unwrap_or(default_image_pull.docker_config),
+        },
+        tokio,
+        jailer: JailerConfig {
+            seccomp: !no_vmm_seccomp,
+            drop_capabilities: !vmm_keep_capabilities,
+            chroot: vmm_chroot.map(PathBuf::from),
+        },
+        grpc_limits: GrpcLimits {
+            max_decoding_message_size: grpc_max_request_size
+                .unwrap_or(default_grpc_limits.max_decoding_message_size),
+            max_encoding_message_size: grpc_max_response_size
+                .unwrap_or(default_grpc_limits.max_encoding_message_size),
+            max_recursion_depth: grpc_max_recursion_depth
+                .unwrap_or(default_grpc_limits.max_recursion_depth),
+            reject_unknown_fields: grpc_reject_unknown_fields,
+        },
+        utilization: UtilizationConfig {
+            interval: utilization_interval
+                .map(Duration::from_secs)
+                .unwrap_or(default_utilization.interval),
+            windows: if utilization_windows.is_empty() {
+                default_utilization.windows
+            } else {
+                utilization_windows
+                    .into_iter()
+                    .map(Duration::from_secs)
+                    .collect()
+            },
+        },
+        cni: CniConfig {
+            conf_dir: cni_conf_dir
+                .map(PathBuf::from)
+                .unwrap_or(default_cni.conf_dir),
+            bin_dirs: if cni_bin_dirs.is_empty() {
+                default_cni.bin_dirs
+            } else {
+                cni_bin_dirs.into_iter().map(PathBuf::from).collect()
+            },
+            conf_template: cni_conf_template
+                .map(PathBuf::from)
+                .or(default_cni.conf_template),
+        },
+        cri_streaming_address: cri_streaming_address
+            .unwrap_or(default_cri_streaming_address),
+        container_logs: ContainerLogConfig {
+            max_size: container_log_max_size
+                .unwrap_or(default_container_logs.max_size),
+            max_files: container_log_max_files
+                .map(|files| files as usize)
+                .unwrap_or(default_container_logs.max_files),
+            max_age: container_log_max_age
+                .map(Duration::from_secs)
+                .or(default_container_logs.max_age),
+        },
+        log_journal: LogJournalConfig {
+            max_size: log_journal_max_size
+                .unwrap_or(default_log_journal.max_size),
+            max_age: log_journal_max_age
+                .map(Duration::from_secs)
+                .or(default_log_journal.max_age),
+        },
+        syslog: syslog_endpoint.or(default_syslog),
+        log_format: log_format.unwrap_or(default_log_format),
+        log_exporters: log_exporters_config
+            .map(PathBuf::from)
+            .unwrap_or(default_log_exporters),
+        subsystems: disable.unwrap_or(default_subsystems),
+        ebpf: EbpfConfig {
+            cells_only: ebpf_cells_only,
+            probes: disable_probes.unwrap_or_default(),
+        },
+        ebpf_config: ebpf_config
+            .map(PathBuf::from)
+            .unwrap_or(default_ebpf_config),
+        webhooks: webhooks_config
+            .map(PathBuf::from)
+            .unwrap_or(default_webhooks),
+        ipam: IpamConfig {
+            cell_pool: cell_address_pool.unwrap_or(default_ipam.cell_pool),
+            pod_pool: pod_address_pool.unwrap_or(default_ipam.pod_pool),
+            reserved: if reserved_addresses.is_empty() {
+                default_ipam.reserved
+            } else {
+                reserved_addresses
+            },
+        },
+        pod_vm: PodVmConfig {
+            kernel: pod_vm_kernel
+                .map(PathBuf::from)
+                .unwrap_or(default_pod_vm.kernel),
+            kernel_args: if pod_vm_kernel_args.is_empty() {
+                default_pod_vm.kernel_args
+            } else {
+                pod_vm_kernel_args
+            },
+            image: pod_vm_image
+                .map(PathBuf::from)
+                .unwrap_or(default_pod_vm.image),
+            mem_size_mb: pod_vm_memory_mb.unwrap_or(default_pod_vm.mem_size_mb),
+            vcpu_count: pod_vm_vcpus.unwrap_or(default_pod_vm.vcpu_count),
+        },
+        oci_hooks: oci_hooks_config
+            .map(PathBuf::from)
+            .unwrap_or(default_oci_hooks),
     };
 
     // Run the auraed daemon with the configured runtime
@@ -179,8 +602,91 @@ async fn handle_default(options: AuraedOptions) -> i32 {
     }
 }
 
-async fn handle_spawn_subcommand(output: &str) -> i32 {
-    info!("Spawning Auraed OCI bundle: {}", output);
-    prep_oci_spec_for_spawn(output); // Prepare the OCI spec for spawning
+async fn handle_spawn_subcommand(
+    library_dir: Option<&str>,
+    output: &str,
+    arch: Option<&str>,
+) -> i32 {
+    let mut runtime = AuraedRuntime::default();
+    if let Some(library_dir) = library_dir {
+        runtime.library_dir = PathBuf::from(library_dir);
+    }
+    let arch = match arch.map(str::parse).unwrap_or(Ok(Arch::host())) {
+        Ok(arch) => arch,
+        Err(e) => {
+            error!("{e}");
+            return EXIT_ERROR;
+        }
+    };
+
+    info!("Spawning Auraed OCI bundle for {arch}: {}", output);
+    // Prepare the OCI spec for spawning
+    if let Err(e) = prep_oci_spec_for_spawn(&runtime, output, arch) {
+        error!("{e:?}");
+        return EXIT_ERROR;
+    }
     EXIT_OKAY // Return success exit code
-}
\ No newline at end of file
+}
+
+fn handle_check_subcommand(
+    server_crt: Option<&str>,
+    server_key: Option<&str>,
+    ca_crt: Option<&str>,
+    json: bool,
+) -> i32 {
+    let mut runtime = AuraedRuntime::default();
+    if let Some(server_crt) = server_crt {
+        runtime.server_crt = PathBuf::from(server_crt);
+    }
+    if let Some(server_key) = server_key {
+        runtime.server_key = PathBuf::from(server_key);
+    }
+    if let Some(ca_crt) = ca_crt {
+        runtime.ca_crt = PathBuf::from(ca_crt);
+    }
+
+    let preflight = Preflight::run(&runtime);
+    if json {
+        match serde_json::to_string_pretty(&preflight) {
+            Ok(report) => println!("{report}"),
+            Err(e) => {
+                error!("{e}");
+                return EXIT_ERROR;
+            }
+        }
+    } else {
+        print!("{preflight}");
+    }
+    if preflight.passed() {
+        EXIT_OKAY
+    } else {
+        EXIT_ERROR
+    }
+}
+
+fn handle_keep_pipes_subcommand(socket: &Path) -> i32 {
+    // Nobody reads our output, auraed discards it
+    match keep_executable_pipes(socket) {
+        Ok(()) => EXIT_OKAY,
+        Err(_) => EXIT_ERROR,
+    }
+}
+
+fn handle_core_dump_subcommand(
+    runtime_dir: Option<&str>,
+    pid: i32,
+    signal: i32,
+    timestamp: i64,
+    executable: String,
+) -> i32 {
+    let mut runtime = AuraedRuntime::default();
+    if let Some(runtime_dir) = runtime_dir {
+        runtime.runtime_dir = PathBuf::from(runtime_dir);
+    }
+
+    // Nobody reads our output, the kernel discards it
+    match capture_core_dump(&runtime, pid, signal, timestamp, executable) {
+        Ok(()) => EXIT_OKAY,
+        Err(_) => EXIT_ERROR,
+    }
+}
@@ -0,0 +1,198 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Single node autoscaling of the executables of a cell.
+//!
+//! The [CellService](super::CellService) evaluates the rule of a cell
+//! allocated with an autoscaler every interval and starts or stops replicas of
+//! its executable accordingly, following the algorithm of the Kubernetes
+//! horizontal pod autoscaler.
+
+use super::cells::{cgroups::Cgroup, CellName};
+use proto::cells::{
+    CellServiceStartRequest, CellServiceStopRequest, Executable,
+};
+use std::{path::PathBuf, time::Duration, time::Instant};
+
+/// Time between two evaluations of the rule when the request does not set one.
+pub(crate) const DEFAULT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Deviation from the target that does not change the number of replicas.
+const TOLERANCE: f64 = 0.1;
+
+#[derive(Debug, Clone)]
+pub(crate) struct AutoscalerSpec {
+    /// The executable replicated, validated when the cell was allocated
+    pub executable: Executable,
+    pub min_replicas: u32,
+    pub max_replicas: u32,
+    pub interval: Duration,
+    pub metric: Metric,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Metric {
+    /// Target CPU usage per replica, in percent of one CPU
+    CpuPercent(f64),
+    /// Target value per replica of the counter in the file `path`
+    Counter { path: PathBuf, target: f64 },
+}
+
+impl AutoscalerSpec {
+    /// The number of replicas the metric having `value` calls for, given the
+    /// `current` number of replicas.
+    pub fn desired_replicas(&self, current: u32, value: f64) -> u32 {
+        let target = match &self.metric {
+            Metric::CpuPercent(target) => *target,
+            Metric::Counter { target, .. } => *target,
+        };
+        let desired = if current > 0
+            && (value / (f64::from(current) * target) - 1.0).abs() <= TOLERANCE
+        {
+            current
+        } else {
+            (value / target).ceil() as u32
+        };
+        desired.clamp(self.min_replicas, self.max_replicas)
+    }
+
+    /// The name of the replica `index`
+    fn replica_name(&self, index: u32) -> String {
+        format!("{}-{index}", self.executable.name)
+    }
+
+    /// The request starting the replica `index` in the cell
+    pub fn start_request(&self, index: u32) -> CellServiceStartRequest {
+        CellServiceStartRequest {
+            cell_name: None,
+            executable: Some(Executable {
+                name: self.replica_name(index),
+                ..self.executable.clone()
+            }),
+            uid: None,
+            gid: None,
+            dry_run: false,
+        }
+    }
+
+    /// The request stopping the replica `index` in the cell
+    pub fn stop_request(&self, index: u32) -> CellServiceStopRequest {
+        CellServiceStopRequest {
+            cell_name: None,
+            executable_name: self.replica_name(index),
+        }
+    }
+}
+
+/// Samples the metric of a cell. CPU usage is the difference between two
+/// readings of the cgroup of the cell, so the first sample has no value.
+#[derive(Debug)]
+pub(crate) struct Sampler {
+    cpu_stat: PathBuf,
+    last_usage: Option<(Instant, u64)>,
+}
+
+impl Sampler {
+    pub fn new(cell_name: &CellName) -> Self {
+        Self {
+            cpu_stat: Cgroup::path(cell_name).join("cpu.stat"),
+            last_usage: None,
+        }
+    }
+
+    pub fn sample(&mut self, metric: &Metric) -> std::io::Result<Option<f64>> {
+        match metric {
+            Metric::CpuPercent(_) => {
+                let now = Instant::now();
+                let usage =
+                    cpu_usage_usec(&std::fs::read_to_string(&self.cpu_stat)?)
+                        .ok_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "cpu.stat has no usage_usec",
+                        )
+                    })?;
+                let last = self.last_usage.replace((now, usage));
+                Ok(last.map(|(then, last_usage)| {
+                    let elapsed = now.duration_since(then).as_micros() as f64;
+                    100.0 * usage.saturating_sub(last_usage) as f64 / elapsed
+                }))
+            }
+            Metric::Counter { path, .. } => {
+                let value = std::fs::read_to_string(path)?;
+                value.trim().parse().map(Some).map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("{} holds no number", path.display()),
+                    )
+                })
+            }
+        }
+    }
+}
+
+pub(super) fn cpu_usage_usec(cpu_stat: &str) -> Option<u64> {
+    cpu_stat
+        .lines()
+        .find_map(|line| line.strip_prefix("usage_usec "))
+        .and_then(|usage| usage.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(metric: Metric) -> AutoscalerSpec {
+        AutoscalerSpec {
+            executable: Executable {
+                name: "worker".into(),
+                command: "sleep 60".into(),
+                ..Default::default()
+            },
+            min_replicas: 1,
+            max_replicas: 5,
+            interval: DEFAULT_INTERVAL,
+            metric,
+        }
+    }
+
+    #[test]
+    fn test_desired_replicas() {
+        let spec = spec(Metric::CpuPercent(50.0));
+        assert_eq!(spec.desired_replicas(0, 0.0), 1);
+        assert_eq!(spec.desired_replicas(2, 160.0), 4);
+        // Within the tolerance
+        assert_eq!(spec.desired_replicas(2, 105.0), 2);
+        assert_eq!(spec.desired_replicas(2, 95.0), 2);
+        assert_eq!(spec.desired_replicas(4, 60.0), 2);
+        assert_eq!(spec.desired_replicas(4, 1000.0), 5);
+    }
+
+    #[test]
+    fn test_replica_requests() {
+        let spec =
+            spec(Metric::Counter { path: "/tmp/depth".into(), target: 10.0 });
+        let start = spec.start_request(2);
+        assert_eq!(start.executable.map(|e| e.name), Some("worker-2".into()));
+        assert_eq!(spec.stop_request(2).executable_name, "worker-2");
+    }
+
+    #[test]
+    fn test_cpu_usage_usec() {
+        let cpu_stat = "usage_usec 1234\nuser_usec 1000\nsystem_usec 234\n";
+        assert_eq!(cpu_usage_usec(cpu_stat), Some(1234));
+        assert_eq!(cpu_usage_usec("user_usec 1000\n"), None);
+    }
+}
@@ -14,37 +14,63 @@
 \* -------------------------------------------------------------------------- */
 
 use super::{
-    cells::{CellName, Cells, CellsCache},
+    autoscaler::{AutoscalerSpec, Sampler},
+    cells::{
+        cgroups::Cgroup, CellName, CellSpec, Cells, CellsCache, ReleasedCell,
+    },
     error::CellsServiceError,
-    executables::Executables,
+    executables::{
+        pipe_keeper, ExecutableName, ExecutablePipes, Executables,
+        ExecutablesError, ReleasedExecutable,
+    },
+    feasibility,
+    left_running::{
+        self, LeftRunningCell, LeftRunningExecutable, LeftRunningNestedAuraed,
+    },
+    utilization::{
+        cell_cpu_usec, cell_oom_kills, UtilizationConfig, Utilizations,
+    },
     validation::{
-        ValidatedCellServiceAllocateRequest, ValidatedCellServiceFreeRequest,
-        ValidatedCellServiceStartRequest, ValidatedCellServiceStopRequest,
+        ValidatedCell, ValidatedCellServiceAllocateRequest,
+        ValidatedCellServiceFreeRequest, ValidatedCellServiceStartRequest,
+        ValidatedCellServiceStopRequest,
     },
     Result,
 };
-use crate::{cells::cell_service::cells::CellsError, observe::ObserveService};
-use ::validation::ValidatedType;
+use crate::{
+    cells::cell_service::cells::CellsError,
+    cordon::Cordon,
+    cri::checkpoint,
+    logging::log_channel::LogChannel,
+    network::{Ipam, Pool, Veth},
+    observe::{ObserveService, Workload, WorkloadEvent, WorkloadEventKind},
+};
+use ::validation::{ValidatedField, ValidatedType};
 use backoff::backoff::Backoff;
 use client::{cells::cell_service::CellServiceClient, Client, ClientError};
+use nix::unistd::Pid;
 use proto::{
     cells::{
         cell_service_server, Cell, CellGraphNode, CellServiceAllocateRequest,
         CellServiceAllocateResponse, CellServiceFreeRequest,
         CellServiceFreeResponse, CellServiceListRequest,
         CellServiceListResponse, CellServiceStartRequest,
-        CellServiceStartResponse, CellServiceStopRequest,
-        CellServiceStopResponse, CpuController, CpusetController,
-        MemoryController,
+        CellServiceStartResponse, CellServiceStatsRequest,
+        CellServiceStatsResponse, CellServiceStopRequest,
+        CellServiceStopResponse, CpuController, CpusetController, Executable,
+        ExitRecord, MemoryController, ShutdownPolicy, TimeNamespace,
+        Utilization,
     },
-    observe::LogChannelType,
+    observe::{CellEvent, CellEventType, ExecutableExit, LogChannelType},
 };
-use std::os::unix::fs::MetadataExt;
-use std::time::Duration;
-use std::{process::ExitStatus, sync::Arc};
-use tokio::sync::Mutex;
+use std::collections::HashMap;
+use std::os::unix::{fs::MetadataExt, process::ExitStatusExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{sync::Mutex, task::AbortHandle};
 use tonic::{Code, Request, Response, Status};
-use tracing::{info, trace, warn};
+use tracing::{error, info, trace, warn};
 
 /**
  * Macro to perform an operation within a cell.
@@ -103,12 +129,49 @@ macro_rules! do_in_cell {
     }};
 }
 
+/// Attaches the network namespace of the nested auraed `pid` of the cell
+/// `cell_name` to the host, with an address leased from the cell pool.
+async fn attach_network(
+    ipam: &Ipam,
+    cell_name: &CellName,
+    pid: Pid,
+) -> anyhow::Result<()> {
+    let owner = cell_name.to_string();
+    let lease = ipam.allocate(Pool::Cells, &owner, pid.as_raw()).await?;
+    match Veth::attach_cell(pid.as_raw(), &owner, lease).await {
+        Ok(veth) => {
+            info!("Attached cell {cell_name} with address {}", veth.address());
+            Ok(())
+        }
+        Err(e) => {
+            let _best_effort = ipam.release(Pool::Cells, &owner).await;
+            Err(e)
+        }
+    }
+}
+
+/// Time the executables of an expired cell are given to exit before they are killed.
+const DEFAULT_TTL_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 /// CellService struct manages the lifecycle of cells and executables.
 #[derive(Debug, Clone)]
 pub struct CellService {
     cells: Arc<Mutex<Cells>>,
     executables: Arc<Mutex<Executables>>,
+    expirations: Arc<Mutex<HashMap<CellName, AbortHandle>>>,
+    autoscalers: Arc<Mutex<HashMap<CellName, AbortHandle>>>,
+    /// CPU time used by the cell when each of its executables was started,
+    /// to attribute the usage of the cell to the executables when stopped
+    cell_cpu_at_start: Arc<Mutex<HashMap<(CellName, String), u64>>>,
+    left_running: Arc<Mutex<Vec<CellName>>>,
+    utilizations: Arc<Mutex<Utilizations>>,
+    utilization_interval: Duration,
+    state_dir: PathBuf,
     observe_service: ObserveService,
+    cordon: Cordon,
+    /// Leases the addresses of the cells isolating their network, None in
+    /// nested auraed instances, whose cells are not attached to the host
+    ipam: Option<Ipam>,
 }
 
 impl CellService {
@@ -116,16 +179,39 @@ impl CellService {
     ///
     /// # Arguments
     /// * `observe_service` - An instance of ObserveService to manage log channels.
-    pub fn new(observe_service: ObserveService) -> Self {
+    /// * `state_dir` - Where cells left running on shutdown are recorded.
+    /// * `cordon` - New cells are rejected while the node is cordoned.
+    /// * `utilization` - How the utilization of the cells is smoothed.
+    /// * `ipam` - Leases the addresses of the cells isolating their network.
+    pub fn new(
+        observe_service: ObserveService,
+        state_dir: PathBuf,
+        cordon: Cordon,
+        utilization: &UtilizationConfig,
+        ipam: Option<Ipam>,
+    ) -> Self {
         CellService {
             cells: Default::default(),
             executables: Default::default(),
+            expirations: Default::default(),
+            autoscalers: Default::default(),
+            cell_cpu_at_start: Default::default(),
+            left_running: Default::default(),
+            utilizations: Arc::new(Mutex::new(Utilizations::new(utilization))),
+            utilization_interval: utilization.interval,
+            state_dir,
             observe_service,
+            cordon,
+            ipam,
         }
     }
 
     /// Allocates a new cell based on the provided request.
     ///
+    /// A dry run only checks that the cell could be allocated on this host
+    /// (see [feasibility::check_cell]). Cells are not allocated while the
+    /// node is cordoned.
+    ///
     /// # Arguments
     /// * `request` - A validated request to allocate a cell.
     ///
@@ -138,19 +224,212 @@ impl CellService {
         request: ValidatedCellServiceAllocateRequest,
     ) -> Result<CellServiceAllocateResponse> {
         // Initialize the cell
-        let ValidatedCellServiceAllocateRequest { cell } = request;
+        let ValidatedCellServiceAllocateRequest {
+            cell,
+            ttl_seconds,
+            ttl_grace_period_seconds,
+            shutdown_policy,
+            dry_run,
+            autoscaler,
+        } = request;
+        self.cordon.check()?;
 
         let cell_name = cell.name.clone();
-        let cell_spec = cell.into();
+        let cell_spec: CellSpec = cell.into();
+        let normalized = to_cell(&cell_name, &cell_spec);
+
+        if dry_run {
+            feasibility::check_cell(&cell_name, &cell_spec)?;
+            return Ok(CellServiceAllocateResponse {
+                cell_name: cell_name.to_string(),
+                // Checked by the feasibility checks
+                cgroup_v2: true,
+                cell: Some(normalized),
+                cgroup_path: Cgroup::path(&cell_name).display().to_string(),
+            });
+        }
 
         let mut cells = self.cells.lock().await;
 
-        let cell = cells.allocate(cell_name, cell_spec)?;
+        let cell = cells.allocate(cell_name.clone(), cell_spec)?;
+        // Top-level cells isolating their network are attached to the host
+        let attach_pid = cell
+            .pid()
+            .filter(|_| cell.spec().iso_ctl.isolate_network)
+            .filter(|_| cell_name.is_child(None));
 
-        Ok(CellServiceAllocateResponse {
+        let response = CellServiceAllocateResponse {
             cell_name: cell.name().clone().to_string(),
             cgroup_v2: cell.v2().expect("allocated cell returns `Some`"),
-        })
+            cell: Some(normalized),
+            cgroup_path: Cgroup::path(&cell_name).display().to_string(),
+        };
+
+        if let (Some(ipam), Some(pid)) = (&self.ipam, attach_pid) {
+            if let Err(e) = attach_network(ipam, &cell_name, pid).await {
+                let _best_effort = cells.free(&cell_name);
+                return Err(CellsServiceError::NetworkError {
+                    cell_name,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+
+        // Nested cells are observed through their top-level cell
+        if cell_name.is_child(None) {
+            self.observe_service.attach_cell(&cell_name.to_string());
+        }
+
+        if let Some(ttl) = ttl_seconds {
+            let grace_period =
+                ttl_grace_period_seconds.unwrap_or(DEFAULT_TTL_GRACE_PERIOD);
+            let service = self.clone();
+            let expiry_cell_name = cell_name.clone();
+            let handle = tokio::spawn(async move {
+                service.expire(expiry_cell_name, ttl, grace_period).await
+            });
+            let _ = self
+                .expirations
+                .lock()
+                .await
+                .insert(cell_name.clone(), handle.abort_handle());
+        }
+
+        if let Some(autoscaler) = autoscaler {
+            let service = self.clone();
+            let autoscaled_cell_name = cell_name.clone();
+            let handle = tokio::spawn(async move {
+                service.autoscale(autoscaled_cell_name, autoscaler).await
+            });
+            let _ = self
+                .autoscalers
+                .lock()
+                .await
+                .insert(cell_name.clone(), handle.abort_handle());
+        }
+
+        // Nested cells share the fate of their top-level cell
+        if shutdown_policy == ShutdownPolicy::LeaveRunning
+            && cell_name.is_child(None)
+        {
+            self.left_running.lock().await.push(cell_name);
+        }
+
+        Ok(response)
+    }
+
+    /// Waits for the TTL of a cell to elapse and frees the cell.
+    ///
+    /// The nested auraed of the cell is sent a graceful shutdown signal and
+    /// given `grace_period` to stop its executables before the cell is killed.
+    /// Emits a [CellEventType::Expired] event once the cell has been freed.
+    ///
+    /// # Arguments
+    /// * `cell_name` - The name of the cell to expire.
+    /// * `ttl` - The time after which the cell expires.
+    /// * `grace_period` - The time given to the executables to exit.
+    #[tracing::instrument(skip(self))]
+    async fn expire(
+        &self,
+        cell_name: CellName,
+        ttl: Duration,
+        grace_period: Duration,
+    ) {
+        tokio::time::sleep(ttl).await;
+
+        info!("CellService: cell {cell_name} expired, shutting it down");
+
+        if let Some(autoscaler) =
+            self.autoscalers.lock().await.remove(&cell_name)
+        {
+            autoscaler.abort();
+        }
+
+        if let Err(e) =
+            self.cells.lock().await.get(&cell_name, |cell| cell.terminate())
+        {
+            warn!("failed to shut down expired cell {cell_name}: {e}");
+        }
+
+        tokio::time::sleep(grace_period).await;
+
+        let res = self.cells.lock().await.kill(&cell_name);
+        let _ = self.expirations.lock().await.remove(&cell_name);
+        self.left_running.lock().await.retain(|name| *name != cell_name);
+        if let Err(e) = res {
+            warn!("failed to free expired cell {cell_name}: {e}");
+            return;
+        }
+        self.observe_service.detach_cell(&cell_name.to_string());
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+
+        self.observe_service.emit_cell_event(CellEvent {
+            cell_name: cell_name.to_string(),
+            event_type: CellEventType::Expired as i32,
+            timestamp,
+            executable_exit: None,
+        });
+    }
+
+    /// Evaluates the autoscaler of a cell every interval until the task is
+    /// aborted when the cell is freed, starting and stopping replicas of its
+    /// executable to meet the desired number of replicas.
+    ///
+    /// # Arguments
+    /// * `cell_name` - The name of the autoscaled cell.
+    /// * `spec` - The autoscaler of the cell.
+    #[tracing::instrument(skip(self))]
+    async fn autoscale(&self, cell_name: CellName, spec: AutoscalerSpec) {
+        let mut sampler = Sampler::new(&cell_name);
+        let mut interval = tokio::time::interval(spec.interval);
+        let mut replicas = 0;
+
+        loop {
+            let _ = interval.tick().await;
+
+            let desired = match sampler.sample(&spec.metric) {
+                Ok(Some(value)) => spec.desired_replicas(replicas, value),
+                // Nothing to compare the first CPU usage sample with yet
+                Ok(None) => replicas.max(spec.min_replicas),
+                Err(e) => {
+                    warn!("failed to sample the metric of {cell_name}: {e}");
+                    continue;
+                }
+            };
+            if desired != replicas {
+                info!(
+                    "CellService: scaling cell {cell_name} from {replicas} \
+                     to {desired} replicas"
+                );
+            }
+
+            while replicas < desired {
+                let request = spec.start_request(replicas);
+                if let Err(e) = self.start_in_cell(&cell_name, request).await {
+                    warn!(
+                        "failed to start replica {replicas} in cell \
+                         {cell_name}: {e}"
+                    );
+                    break;
+                }
+                replicas += 1;
+            }
+            while replicas > desired {
+                let request = spec.stop_request(replicas - 1);
+                if let Err(e) = self.stop_in_cell(&cell_name, request).await {
+                    warn!(
+                        "failed to stop replica {} in cell {cell_name}: {e}",
+                        replicas - 1
+                    );
+                    break;
+                }
+                replicas -= 1;
+            }
+        }
     }
 
     /// Frees a cell.
@@ -172,12 +451,474 @@ impl CellService {
         let mut cells = self.cells.lock().await;
 
         cells.free(&cell_name)?;
+        self.observe_service.detach_cell(&cell_name.to_string());
+
+        if let Some(ipam) = &self.ipam {
+            // The veth pair of the cell went away with its namespace
+            if let Err(e) =
+                ipam.release(Pool::Cells, &cell_name.to_string()).await
+            {
+                warn!("Failed to release the address of cell {cell_name}: {e}");
+            }
+        }
+
+        if let Some(expiration) =
+            self.expirations.lock().await.remove(&cell_name)
+        {
+            expiration.abort();
+        }
+        if let Some(autoscaler) =
+            self.autoscalers.lock().await.remove(&cell_name)
+        {
+            autoscaler.abort();
+        }
+        self.cell_cpu_at_start
+            .lock()
+            .await
+            .retain(|(name, _), _| *name != cell_name);
+        self.left_running.lock().await.retain(|name| *name != cell_name);
 
         Ok(CellServiceFreeResponse::default())
     }
 
+    /// Lets go of the cells allocated with [ShutdownPolicy::LeaveRunning]
+    /// without stopping them and records them for the next auraed to adopt
+    /// (see [CellService::adopt_left_running]).
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn release_left_running(&self) -> Result<()> {
+        let cell_names = self.left_running.lock().await.clone();
+        self.release_cells(cell_names).await
+    }
+
+    /// Lets go of the top-level cells `cell_names` without stopping them and
+    /// records them for the next auraed to adopt.
+    async fn release_cells(&self, cell_names: Vec<CellName>) -> Result<()> {
+        let mut cells = self.cells.lock().await;
+        let mut expirations = self.expirations.lock().await;
+        let mut autoscalers = self.autoscalers.lock().await;
+        let mut left_running = self.left_running.lock().await;
+
+        for cell_name in cell_names {
+            // Expirations and autoscalers are not recorded, left running
+            // cells live until they are freed and keep their replicas
+            if let Some(expiration) = expirations.remove(&cell_name) {
+                expiration.abort();
+            }
+            if let Some(autoscaler) = autoscalers.remove(&cell_name) {
+                autoscaler.abort();
+            }
+
+            let cell: CellGraphNode =
+                cells.get(&cell_name, |cell| cell.try_into())?;
+            let nested_auraed =
+                nested_auraed_records(cells.release(&cell_name)?);
+
+            let leave_running = left_running.contains(&cell_name);
+            left_running.retain(|name| *name != cell_name);
+
+            info!("CellService: leaving cell {cell_name} running");
+            LeftRunningCell { cell, nested_auraed, leave_running }
+                .save(&self.state_dir)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets go of all cells and executables without stopping them when
+    /// auraed exits for an upgrade, and records them for the next auraed to
+    /// adopt (see [CellService::adopt_left_running]).
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn release_for_upgrade(&self) -> Result<()> {
+        let cell_names: Vec<CellName> = self
+            .cells
+            .lock()
+            .await
+            .get_all(|cell| Ok(cell.name().clone()))?
+            .into_iter()
+            .filter_map(|cell_name| cell_name.ok())
+            .collect();
+        self.release_cells(cell_names).await?;
+
+        let released = self.executables.lock().await.release_all();
+        let mut pipes = vec![];
+        for ReleasedExecutable { name, description, pid, pipes: kept } in
+            released
+        {
+            info!("CellService: leaving executable {name} running");
+            LeftRunningExecutable::new(
+                name.to_string(),
+                description,
+                pid.as_raw(),
+            )?
+            .save(&self.state_dir)
+            .await?;
+            pipes.push((name, kept));
+        }
+        pipe_keeper::hand_over(
+            &pipe_keeper::socket_path(&self.state_dir),
+            pipes,
+        )?;
+
+        Ok(())
+    }
+
+    /// Dumps the top-level cells into `dir` for a node checkpoint, leaving
+    /// them running, and returns their names. Each cell is recorded as if it
+    /// was left running, next to the CRIU images of the process tree of its
+    /// nested auraed (see [CellService::restore_checkpoint]).
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn checkpoint(&self, dir: &Path) -> Result<Vec<String>> {
+        let mut cells = self.cells.lock().await;
+        let cell_names: Vec<CellName> = cells
+            .get_all(|cell| Ok(cell.name().clone()))?
+            .into_iter()
+            .filter_map(|cell_name| cell_name.ok())
+            .collect();
+        let left_running = self.left_running.lock().await.clone();
+
+        let mut checkpointed = vec![];
+        for cell_name in cell_names {
+            let Some(pid) = cells.get(&cell_name, |cell| Ok(cell.pid()))?
+            else {
+                continue;
+            };
+            let cell: CellGraphNode =
+                cells.get(&cell_name, |cell| cell.try_into())?;
+            let nested_auraed = nested_auraed_records(
+                cells.get(&cell_name, |cell| Ok(cell.nested_auraeds()))?,
+            );
+
+            let name = cell_name.to_string();
+            let images = dir.join(&name);
+            tokio::task::spawn_blocking(move || {
+                checkpoint::dump_cell(pid.as_raw(), &name, &images)
+            })
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(|e| CellsServiceError::CheckpointError {
+                cell_name: cell_name.clone(),
+                error: format!("{e:#}"),
+            })?;
+
+            let leave_running = left_running.contains(&cell_name);
+            LeftRunningCell { cell, nested_auraed, leave_running }
+                .save(dir)
+                .await?;
+            checkpointed.push(cell_name.to_string());
+        }
+
+        Ok(checkpointed)
+    }
+
+    /// Restores the cells of a node checkpoint written to `dir` by
+    /// [CellService::checkpoint] and adopts them, returning their names.
+    /// Cells isolating their network are attached to the host again, with
+    /// the addresses they held if the leases were restored before.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn restore_checkpoint(
+        &self,
+        dir: &Path,
+    ) -> Result<Vec<String>> {
+        let mut restored = vec![];
+        for (record, mut left_running) in LeftRunningCell::read_all(dir).await?
+        {
+            let Some(cell_name) =
+                left_running.cell.cell.as_ref().map(|cell| cell.name.clone())
+            else {
+                continue;
+            };
+
+            // CRIU binds the sockets of the nested auraeds where they were
+            for nested_auraed in left_running.nested_auraed.values() {
+                if let Some(parent) = nested_auraed.client_socket.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+            }
+
+            let images = dir.join(&cell_name);
+            let pid = tokio::task::spawn_blocking(move || {
+                checkpoint::restore_cell(&images)
+            })
+            .await
+            .map_err(std::io::Error::other)?
+            .map_err(|e| CellsServiceError::RestoreError {
+                cell_name: cell_name.clone(),
+                error: format!("{e:#}"),
+            })?;
+            if let Some(nested_auraed) =
+                left_running.nested_auraed.get_mut(&cell_name)
+            {
+                nested_auraed.pid = pid;
+            }
+            restored.push((record, left_running));
+        }
+
+        let adopted = self.adopt_cells(restored).await;
+
+        if let Some(ipam) = &self.ipam {
+            let mut cells = self.cells.lock().await;
+            for cell_name in &adopted {
+                let attach_pid = cells.get(cell_name, |cell| {
+                    Ok(cell
+                        .pid()
+                        .filter(|_| cell.spec().iso_ctl.isolate_network))
+                });
+                if let Ok(Some(pid)) = attach_pid {
+                    if let Err(e) = attach_network(ipam, cell_name, pid).await {
+                        error!(
+                            "Failed to attach restored cell {cell_name}: {e:#}"
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(adopted.iter().map(ToString::to_string).collect())
+    }
+
+    /// Samples the utilization of the node and of its top-level cells every
+    /// interval, for [CellService::stats] to report its moving averages.
+    /// Cells whose processes were killed by the OOM killer since the last
+    /// sample are reported as OOM-killed workload events, unless the eBPF
+    /// probes report the killed processes themselves.
+    pub(crate) fn sample_utilization(&self) {
+        let cells = self.cells.clone();
+        let utilizations = self.utilizations.clone();
+        let interval = self.utilization_interval;
+        let observe_service = self.observe_service.clone();
+        let report_oom_kills = !observe_service.has_oom_kill_probe();
+
+        let _ignored = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            let mut oom_kills: HashMap<CellName, u64> = HashMap::new();
+            loop {
+                let _ = interval.tick().await;
+                let cell_names: Vec<CellName> = cells
+                    .lock()
+                    .await
+                    .get_all(|cell| Ok(cell.name().clone()))
+                    .expect("cells doesn't error")
+                    .into_iter()
+                    .filter_map(|x| x.ok())
+                    .collect();
+
+                oom_kills.retain(|cell_name, _| cell_names.contains(cell_name));
+                // unless the eBPF probes report the killed processes
+                if report_oom_kills {
+                    for cell_name in &cell_names {
+                        let Some(kills) = cell_oom_kills(cell_name) else {
+                            continue;
+                        };
+                        let seen = oom_kills.insert(cell_name.clone(), kills);
+                        if !seen.is_some_and(|seen| kills > seen) {
+                            continue;
+                        }
+                        let event = WorkloadEvent::new(
+                            WorkloadEventKind::OomKilled,
+                            Workload::Cell { cell_name: cell_name.to_string() },
+                            format!(
+                                "OOM killer killed a process of {cell_name}"
+                            ),
+                        );
+                        observe_service.emit_workload_event(event);
+                    }
+                }
+
+                utilizations.lock().await.sample(cell_names);
+            }
+        });
+    }
+
+    /// Adopts the cells and executables left running by a previous auraed
+    /// (see [CellService::release_left_running] and
+    /// [CellService::release_for_upgrade]). Cells whose nested auraed is no
+    /// longer running and executables that exited are dropped.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn adopt_left_running(&self) -> Result<()> {
+        let socket_path = pipe_keeper::socket_path(&self.state_dir);
+        let mut pipes: HashMap<String, ExecutablePipes> =
+            match tokio::task::spawn_blocking(move || {
+                pipe_keeper::take_over(&socket_path)
+            })
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|pipes| pipes)
+            {
+                Ok(pipes) => pipes.into_iter().collect(),
+                Err(e) => {
+                    error!("Failed to take over pipes of executables: {e}");
+                    HashMap::new()
+                }
+            };
+
+        let mut executables = self.executables.lock().await;
+
+        for (record, left_running) in
+            LeftRunningExecutable::read_all(&self.state_dir).await?
+        {
+            if !left_running.is_running() {
+                left_running::remove(&record).await;
+                continue;
+            }
+
+            let LeftRunningExecutable { name, description, pid, .. } =
+                left_running;
+            let name = match ExecutableName::validate(Some(name), "name", None)
+            {
+                Ok(name) => name,
+                Err(e) => {
+                    error!("Failed to adopt executable left running: {e}");
+                    continue;
+                }
+            };
+            let released = ReleasedExecutable {
+                pipes: pipes.remove(&name.to_string()).unwrap_or_default(),
+                name,
+                description,
+                pid: Pid::from_raw(pid),
+            };
+
+            match executables.adopt(released) {
+                Ok(executable) => {
+                    left_running::remove(&record).await;
+                    info!(
+                        "CellService: adopted executable {}",
+                        executable.name
+                    );
+                    self.register_log_channels(
+                        pid,
+                        executable.stdout.clone(),
+                        executable.stderr.clone(),
+                    )
+                    .await;
+                }
+                Err(e) => error!("Failed to adopt executable: {e}"),
+            }
+        }
+        drop(executables);
+
+        let left_running = LeftRunningCell::read_all(&self.state_dir).await?;
+        let _ = self.adopt_cells(left_running).await;
+
+        Ok(())
+    }
+
+    /// Adopts the cells recorded in `left_running` and returns the names of
+    /// the adopted top-level cells, whose records are removed. Cells whose
+    /// nested auraed is not recorded are dropped.
+    async fn adopt_cells(
+        &self,
+        left_running: Vec<(PathBuf, LeftRunningCell)>,
+    ) -> Vec<CellName> {
+        let mut cells = self.cells.lock().await;
+        let mut adopted = vec![];
+
+        for (record, left_running) in left_running {
+            for cell in left_running.cells() {
+                let Some(nested_auraed) =
+                    left_running.nested_auraed.get(&cell.name)
+                else {
+                    continue;
+                };
+
+                let cell = match ValidatedCell::validate(cell, None) {
+                    Ok(cell) => cell,
+                    Err(e) => {
+                        error!("Failed to adopt cell left running: {e}");
+                        continue;
+                    }
+                };
+                let cell_name = cell.name.clone();
+                let released = ReleasedCell {
+                    cell_name: cell_name.clone(),
+                    pid: Pid::from_raw(nested_auraed.pid),
+                    client_socket: nested_auraed.client_socket.clone(),
+                };
+
+                match cells.adopt(cell.into(), released) {
+                    Ok(_) => {
+                        info!("CellService: adopted cell {cell_name}");
+                        if cell_name.is_child(None) {
+                            self.observe_service
+                                .attach_cell(&cell_name.to_string());
+                        }
+                        if cell_name.is_child(None)
+                            && left_running.leave_running
+                        {
+                            self.left_running
+                                .lock()
+                                .await
+                                .push(cell_name.clone());
+                        }
+                        if cell_name.is_child(None) {
+                            left_running::remove(&record).await;
+                            adopted.push(cell_name);
+                        }
+                    }
+                    Err(e) => error!("Failed to adopt cell {cell_name}: {e}"),
+                }
+            }
+        }
+
+        adopted
+    }
+
+    /// Allocates `cell` as the cell of a pod sandbox of the CRI, whose
+    /// cgroup is the parent of the cgroups of the containers of the pod.
+    /// Returns the path of the cgroup relative to the cgroup root.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn allocate_for_pod(
+        &self,
+        cell: Cell,
+    ) -> anyhow::Result<PathBuf> {
+        let request = ValidatedCellServiceAllocateRequest::validate(
+            CellServiceAllocateRequest {
+                cell: Some(cell),
+                ..Default::default()
+            },
+            None,
+        )?;
+        let cell_name = request.cell.name.clone();
+        let _ = self.allocate(request).await?;
+        Ok(cell_name.into_inner())
+    }
+
+    /// Frees the cell of a pod sandbox of the CRI, once the containers of
+    /// the pod are deleted.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn free_for_pod(
+        &self,
+        cell_name: &str,
+    ) -> anyhow::Result<()> {
+        let request = ValidatedCellServiceFreeRequest::validate(
+            CellServiceFreeRequest { cell_name: cell_name.to_string() },
+            None,
+        )?;
+        let _ = self.free(request).await?;
+        Ok(())
+    }
+
+    /// Whether the cell of a pod sandbox of the CRI is allocated. The cells
+    /// of an auraed that stopped without releasing them are not.
+    pub(crate) async fn has_pod_cell(&self, cell_name: &str) -> bool {
+        let Ok(cell_name) =
+            CellName::validate(Some(cell_name.to_string()), "cell_name", None)
+        else {
+            return false;
+        };
+        self.cells.lock().await.get(&cell_name, |_| Ok(())).is_ok()
+    }
+
     #[tracing::instrument(skip(self))]
     pub(crate) async fn free_all(&self) -> Result<()> {
+        for (_, expiration) in self.expirations.lock().await.drain() {
+            expiration.abort();
+        }
+        for (_, autoscaler) in self.autoscalers.lock().await.drain() {
+            autoscaler.abort();
+        }
+
         let mut cells = self.cells.lock().await;
 
         // Attempt to gracefully free all cells
@@ -191,7 +932,8 @@ impl CellService {
     }
 
     #[tracing::instrument(skip(self))]
-    /// Handles a start request.
+    /// Handles a start request. A dry run only checks that the executable
+    /// could be started.
     ///
     /// # Arguments
     /// * `request` - A request containing CellServiceStartRequest.
@@ -207,13 +949,43 @@ impl CellService {
             executable,
             uid,
             gid,
+            dry_run,
         } = request;
 
         assert!(cell_name.is_none());
         info!("CellService: start() executable={:?}", executable);
 
+        let normalized = Executable {
+            name: executable.name.to_string(),
+            command: executable.command.to_string_lossy().into_owned(),
+            description: executable.description.clone(),
+            log_ring_size_kb: executable
+                .log_ring_size_kb
+                .map_or(0, |size| (size / 1024) as u32),
+        };
+
+        let (self_uid, self_gid) =
+            std::fs::metadata("/proc/self").map(|m| (m.uid(), m.gid()))?;
+
         let mut executables = self.executables.lock().await;
 
+        if dry_run {
+            if executables.get(&executable.name).is_ok() {
+                return Err(CellsServiceError::ExecutablesError(
+                    ExecutablesError::ExecutableExists {
+                        executable_name: executable.name,
+                    },
+                )
+                .into());
+            }
+            return Ok(Response::new(CellServiceStartResponse {
+                pid: 0,
+                uid: uid.unwrap_or(self_uid),
+                gid: gid.unwrap_or(self_gid),
+                executable: Some(normalized),
+            }));
+        }
+
         // Start the executable and handle any errors
         let executable = executables
             .start(executable, uid, gid)
@@ -226,14 +998,32 @@ impl CellService {
             .expect("pid")
             .as_raw();
 
+        self.register_log_channels(
+            pid,
+            executable.stdout.clone(),
+            executable.stderr.clone(),
+        )
+        .await;
+
+        Ok(Response::new(CellServiceStartResponse {
+            pid,
+            uid: uid.unwrap_or(self_uid),
+            gid: gid.unwrap_or(self_gid),
+            executable: Some(normalized),
+        }))
+    }
+
+    /// Registers the log channels of the executable with the given PID.
+    async fn register_log_channels(
+        &self,
+        pid: i32,
+        stdout: LogChannel,
+        stderr: LogChannel,
+    ) {
         // Register the stdout log channel for the executable's PID
         if let Err(e) = self
             .observe_service
-            .register_sub_process_channel(
-                pid,
-                LogChannelType::Stdout,
-                executable.stdout.clone(),
-            )
+            .register_sub_process_channel(pid, LogChannelType::Stdout, stdout)
             .await
         {
             warn!("failed to register stdout channel for pid {pid}: {e}");
@@ -242,24 +1032,11 @@ impl CellService {
         // Register the stderr log channel for the executable's PID
         if let Err(e) = self
             .observe_service
-            .register_sub_process_channel(
-                pid,
-                LogChannelType::Stderr,
-                executable.stderr.clone(),
-            )
+            .register_sub_process_channel(pid, LogChannelType::Stderr, stderr)
             .await
         {
             warn!("failed to register stderr channel for pid {pid}: {e}");
         }
-
-        let (self_uid, self_gid) =
-            std::fs::metadata("/proc/self").map(|m| (m.uid(), m.gid()))?;
-
-        Ok(Response::new(CellServiceStartResponse {
-            pid,
-            uid: uid.unwrap_or(self_uid),
-            gid: gid.unwrap_or(self_gid),
-        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -268,7 +1045,34 @@ impl CellService {
         cell_name: &CellName,
         request: CellServiceStartRequest,
     ) -> std::result::Result<Response<CellServiceStartResponse>, Status> {
-        do_in_cell!(self, cell_name, start, request)
+        let executable_name = request
+            .executable
+            .as_ref()
+            .map(|executable| executable.name.clone())
+            .unwrap_or_default();
+        let dry_run = request.dry_run;
+
+        let response = do_in_cell!(self, cell_name, start, request)?;
+
+        if !dry_run {
+            self.observe_service.emit_workload_event(WorkloadEvent::new(
+                WorkloadEventKind::Started,
+                Workload::Executable {
+                    cell_name: cell_name.to_string(),
+                    executable_name: executable_name.clone(),
+                },
+                format!("started executable {executable_name}"),
+            ));
+            if let Some(usage) = cell_cpu_usec(cell_name) {
+                let _ = self
+                    .cell_cpu_at_start
+                    .lock()
+                    .await
+                    .insert((cell_name.clone(), executable_name), usage);
+            }
+        }
+
+        Ok(response)
     }
 
     #[tracing::instrument(skip(self))]
@@ -301,10 +1105,20 @@ impl CellService {
             .as_raw();
 
         // Stop the executable and handle any errors
-        let _: ExitStatus = executables
+        let exit_record = executables
             .stop(&executable_name)
             .await
             .map_err(CellsServiceError::ExecutablesError)?;
+        let exit_record = to_exit_record(&executable_name, &exit_record);
+        info!(
+            "CellService: executable {executable_name} exited, \
+             exit_code={} user_cpu_usec={} system_cpu_usec={} \
+             max_rss_bytes={}",
+            exit_record.exit_code,
+            exit_record.user_cpu_usec,
+            exit_record.system_cpu_usec,
+            exit_record.max_rss_bytes,
+        );
 
         // Remove the executable's logs from the observe service.
         if let Err(e) = self
@@ -322,7 +1136,9 @@ impl CellService {
             warn!("failed to unregister stderr channel for pid {pid}: {e}");
         }
 
-        Ok(Response::new(CellServiceStopResponse::default()))
+        Ok(Response::new(CellServiceStopResponse {
+            exit_record: Some(exit_record),
+        }))
     }
 
     #[tracing::instrument(skip(self))]
@@ -331,7 +1147,52 @@ impl CellService {
         cell_name: &CellName,
         request: CellServiceStopRequest,
     ) -> std::result::Result<Response<CellServiceStopResponse>, Status> {
-        do_in_cell!(self, cell_name, stop, request)
+        let key = (cell_name.clone(), request.executable_name.clone());
+
+        let mut response = do_in_cell!(self, cell_name, stop, request)?;
+
+        let cpu_at_start = self.cell_cpu_at_start.lock().await.remove(&key);
+        if let Some(exit_record) = response.get_mut().exit_record.as_mut() {
+            if let (Some(start), Some(end)) =
+                (cpu_at_start, cell_cpu_usec(cell_name))
+            {
+                exit_record.cell_cpu_usec = end.saturating_sub(start);
+            }
+            self.observe_service.emit_cell_event(CellEvent {
+                cell_name: cell_name.to_string(),
+                event_type: CellEventType::ExecutableExited as i32,
+                timestamp: exit_record.exited_at,
+                executable_exit: Some(to_executable_exit(exit_record)),
+            });
+            let workload = Workload::Executable {
+                cell_name: cell_name.to_string(),
+                executable_name: exit_record.executable_name.clone(),
+            };
+            // executables killed by the stop signal exited as asked to
+            let event = if exit_record.signal == 0 && exit_record.exit_code != 0
+            {
+                WorkloadEvent::new(
+                    WorkloadEventKind::Crashed,
+                    workload,
+                    format!(
+                        "executable {} exited with code {}",
+                        exit_record.executable_name, exit_record.exit_code
+                    ),
+                )
+            } else {
+                WorkloadEvent::new(
+                    WorkloadEventKind::Stopped,
+                    workload,
+                    format!(
+                        "stopped executable {}",
+                        exit_record.executable_name
+                    ),
+                )
+            };
+            self.observe_service.emit_workload_event(event);
+        }
+
+        Ok(response)
     }
 
     #[tracing::instrument(skip(self))]
@@ -356,6 +1217,36 @@ impl CellService {
 
         Ok(CellServiceListResponse { cells })
     }
+
+    /// Reports the smoothed utilization of the node and of its top-level
+    /// cells, sampled by [CellService::sample_utilization].
+    #[tracing::instrument(skip(self))]
+    async fn stats(&self) -> Result<CellServiceStatsResponse> {
+        Ok(self.utilizations.lock().await.to_response())
+    }
+
+    /// The smoothed utilization of the node, None until it was sampled
+    /// twice by [CellService::sample_utilization].
+    pub(crate) async fn node_utilization(&self) -> Option<Utilization> {
+        self.utilizations.lock().await.to_response().node
+    }
+
+    /// How often the OOM killer killed a process of each top-level cell,
+    /// from the memory.events of its cgroup.
+    pub(crate) async fn oom_kills(&self) -> HashMap<String, u64> {
+        self.cells
+            .lock()
+            .await
+            .get_all(|cell| Ok(cell.name().clone()))
+            .expect("cells doesn't error")
+            .into_iter()
+            .filter_map(|x| x.ok())
+            .filter_map(|cell_name| {
+                let kills = cell_oom_kills(&cell_name)?;
+                Some((cell_name.to_string(), kills))
+            })
+            .collect()
+    }
 }
 
 impl TryFrom<&super::cells::Cell> for CellGraphNode {
@@ -380,24 +1271,96 @@ impl TryFrom<&super::cells::Cell> for CellGraphNode {
             .filter_map(|x| x.ok())
             .collect();
 
-        // Extract cgroup and isolation specifications
-        let super::cells::CellSpec { cgroup_spec, iso_ctl } = spec;
-        // Extract CPU, cpuset, and memory specifications
-        let super::cells::cgroups::CgroupSpec { cpu, cpuset, memory } =
-            cgroup_spec;
-
-        Ok(Self {
-            // Create a new Cell instance with the extracted specifications
-            cell: Some(Cell {
-                name: name.to_string(),
-                cpu: cpu.as_ref().map(|x| x.into()),
-                cpuset: cpuset.as_ref().map(|x| x.into()),
-                memory: memory.as_ref().map(|x| x.into()),
-                isolate_process: iso_ctl.isolate_process,
-                isolate_network: iso_ctl.isolate_network,
-            }),
-            children,
+        Ok(Self { cell: Some(to_cell(name, spec)), children })
+    }
+}
+
+/// Converts the name and specification of a cell into a Cell.
+/// The records of the nested auraeds of `released` cells by cell name.
+fn nested_auraed_records(
+    released: Vec<ReleasedCell>,
+) -> HashMap<String, LeftRunningNestedAuraed> {
+    released
+        .into_iter()
+        .map(|ReleasedCell { cell_name, pid, client_socket }| {
+            let nested_auraed =
+                LeftRunningNestedAuraed { pid: pid.as_raw(), client_socket };
+            (cell_name.to_string(), nested_auraed)
         })
+        .collect()
+}
+
+fn to_cell(name: &CellName, spec: &CellSpec) -> Cell {
+    // Extract cgroup and isolation specifications
+    let CellSpec { cgroup_spec, iso_ctl } = spec;
+    // Extract CPU, cpuset, and memory specifications
+    let super::cells::cgroups::CgroupSpec { cpu, cpuset, memory } = cgroup_spec;
+
+    Cell {
+        name: name.to_string(),
+        cpu: cpu.as_ref().map(|x| x.into()),
+        cpuset: cpuset.as_ref().map(|x| x.into()),
+        memory: memory.as_ref().map(|x| x.into()),
+        isolate_process: iso_ctl.isolate_process,
+        isolate_network: iso_ctl.isolate_network,
+        time_namespace: iso_ctl.time_offsets.map(|offsets| TimeNamespace {
+            monotonic_offset_seconds: offsets.monotonic,
+            boottime_offset_seconds: offsets.boottime,
+        }),
+    }
+}
+
+/// Reads the CPU time used by the cell so far from its cgroup.
+fn to_exit_record(
+    name: &ExecutableName,
+    record: &super::executables::ExitRecord,
+) -> ExitRecord {
+    let super::executables::ExitRecord { status, usage, started_at, exited_at } =
+        record;
+    let usage = usage.unwrap_or_default();
+    let epoch_seconds = |time: SystemTime| {
+        time.duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default()
+    };
+
+    ExitRecord {
+        executable_name: name.to_string(),
+        exit_code: status.code().unwrap_or(-1),
+        signal: status.signal().unwrap_or_default(),
+        user_cpu_usec: usage.user_cpu.as_micros() as u64,
+        system_cpu_usec: usage.system_cpu.as_micros() as u64,
+        max_rss_bytes: usage.max_rss_bytes,
+        // Filled in by the auraed the cell was allocated on
+        cell_cpu_usec: 0,
+        started_at: started_at.map(epoch_seconds).unwrap_or_default(),
+        exited_at: epoch_seconds(*exited_at),
+    }
+}
+
+fn to_executable_exit(record: &ExitRecord) -> ExecutableExit {
+    let ExitRecord {
+        executable_name,
+        exit_code,
+        signal,
+        user_cpu_usec,
+        system_cpu_usec,
+        max_rss_bytes,
+        cell_cpu_usec,
+        started_at,
+        exited_at,
+    } = record.clone();
+
+    ExecutableExit {
+        executable_name,
+        exit_code,
+        signal,
+        user_cpu_usec,
+        system_cpu_usec,
+        max_rss_bytes,
+        cell_cpu_usec,
+        started_at,
+        exited_at,
     }
 }
 
@@ -554,6 +1517,13 @@ impl cell_service_server::CellService for CellService {
     ) -> std::result::Result<Response<CellServiceListResponse>, Status> {
         Ok(Response::new(self.list().await?))
     }
+
+    async fn stats(
+        &self,
+        _request: Request<CellServiceStatsRequest>,
+    ) -> std::result::Result<Response<CellServiceStatsResponse>, Status> {
+        Ok(Response::new(self.stats().await?))
+    }
 }
 
 #[cfg(test)]
@@ -580,10 +1550,17 @@ mod tests {
         let _ = AURAED_RUNTIME.set(AuraedRuntime::default());
 
         // Create a new instance of CellService for testing
-        let service = CellService::new(ObserveService::new(
-            Arc::new(LogChannel::new(String::from("test"))),
-            (None, None, None),
-        ));
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let service = CellService::new(
+            ObserveService::new(
+                Arc::new(LogChannel::new(String::from("test"))),
+                (None, None, None, None, None, None),
+            ),
+            AuraedRuntime::default().cells_dir(),
+            Cordon::open(scratch.path().join("cordon")).expect("open cordon"),
+            &UtilizationConfig::default(),
+            None,
+        );
 
         // Allocate a parent cell for testing
         let parent_cell_name = format!("ae-test-{}", uuid::Uuid::new_v4());
@@ -672,8 +1649,16 @@ mod tests {
             }),
             isolate_process: false,
             isolate_network: false,
+            time_namespace: None,
         };
         // Return the validated allocate request
-        ValidatedCellServiceAllocateRequest { cell }
+        ValidatedCellServiceAllocateRequest {
+            cell,
+            ttl_seconds: None,
+            ttl_grace_period_seconds: None,
+            shutdown_policy: ShutdownPolicy::Stop,
+            dry_run: false,
+            autoscaler: None,
+        }
     }
 }
@@ -15,9 +15,10 @@
 
 use super::{
     cgroups::Cgroup, nested_auraed::NestedAuraed, CellName, CellSpec, Cells,
-    CellsCache, CellsError, Result,
+    CellsCache, CellsError, ReleasedCell, Result,
 };
 use client::AuraeSocket;
+use nix::unistd::Pid;
 use tracing::info;
 
 // TODO https://github.com/aurae-runtime/aurae/issues/199 &&
@@ -135,6 +136,77 @@ impl Cell {
         Ok(())
     }
 
+    /// Creates an allocated [Cell] for a cgroup and [NestedAuraed] that were
+    /// left running by a previous auraed (see [Cell::release]).
+    pub fn adopt(cell_spec: CellSpec, released: ReleasedCell) -> Result<Self> {
+        let ReleasedCell { cell_name, pid, client_socket } = released;
+
+        if !Cgroup::exists(&cell_name) {
+            return Err(CellsError::CgroupNotFound { cell_name });
+        }
+
+        let nested_auraed =
+            NestedAuraed::adopt(pid, client_socket, cell_spec.iso_ctl.clone())
+                .map_err(|e| CellsError::FailedToAllocateCell {
+                    cell_name: cell_name.clone(),
+                    source: e,
+                })?;
+
+        Ok(Self {
+            state: CellState::Allocated {
+                cgroup: Cgroup::adopt(cell_name.clone()),
+                nested_auraed,
+                children: Cells::new(cell_name.clone()),
+            },
+            cell_name,
+            spec: cell_spec,
+        })
+    }
+
+    /// Lets go of the [NestedAuraed] and cgroup of the [Cell] and all its
+    /// descendants without stopping them, so they outlive auraed and can be
+    /// adopted again with [Cell::adopt].
+    ///
+    /// The [Cell::state] will be set to [CellState::Freed] regardless of it's state prior to this call.
+    pub fn release(&mut self) -> Vec<ReleasedCell> {
+        let CellState::Allocated { nested_auraed, mut children, .. } =
+            std::mem::replace(&mut self.state, CellState::Freed)
+        else {
+            return vec![];
+        };
+
+        let mut released = vec![];
+        if let AuraeSocket::Path(client_socket) = &nested_auraed.client_socket {
+            released.push(ReleasedCell {
+                cell_name: self.cell_name.clone(),
+                pid: nested_auraed.pid(),
+                client_socket: client_socket.clone(),
+            });
+        }
+        released.extend(children.release_all());
+        released
+    }
+
+    /// The [NestedAuraed] of the [Cell] and all its descendants as
+    /// [Cell::release] would let go of them, leaving them in place.
+    pub fn nested_auraeds(&self) -> Vec<ReleasedCell> {
+        let CellState::Allocated { nested_auraed, children, .. } = &self.state
+        else {
+            return vec![];
+        };
+
+        let mut nested = vec![];
+        if let AuraeSocket::Path(client_socket) = &nested_auraed.client_socket {
+            nested.push(ReleasedCell {
+                cell_name: self.cell_name.clone(),
+                pid: nested_auraed.pid(),
+                client_socket: client_socket.clone(),
+            });
+        }
+        nested.extend(children.nested_auraeds());
+        nested
+    }
+
     /// Broadcasts a graceful shutdown signal to all [NestedAuraed] and
     /// deletes the underlying cgroup and all descendants.
     ///
@@ -152,6 +224,23 @@ impl Cell {
         do_free!(self, kill(), broadcast_kill())
     }
 
+    /// Sends a graceful shutdown signal to the [NestedAuraed] without waiting for it to exit.
+    /// The [Cell] remains allocated until it is freed or killed.
+    pub fn terminate(&self) -> Result<()> {
+        let CellState::Allocated { nested_auraed, .. } = &self.state else {
+            return Err(CellsError::CellNotAllocated {
+                cell_name: self.cell_name.clone(),
+            })
+        };
+
+        nested_auraed.terminate().map_err(|e| {
+            CellsError::FailedToKillCellChildren {
+                cell_name: self.cell_name.clone(),
+                source: e,
+            }
+        })
+    }
+
     pub fn client_socket(&self) -> Result<AuraeSocket> {
         let CellState::Allocated { nested_auraed, .. } = &self.state else {
             return Err(CellsError::CellNotAllocated {
@@ -171,6 +260,16 @@ impl Cell {
         &self.spec
     }
 
+    /// The pid of the [NestedAuraed] of the [Cell], [None] if the [Cell] is
+    /// not allocated.
+    pub fn pid(&self) -> Option<Pid> {
+        let CellState::Allocated { nested_auraed, .. } = &self.state else {
+            return None;
+        };
+
+        Some(nested_auraed.pid())
+    }
+
     /// Returns [None] if the [Cell] is not allocated.
     pub fn v2(&self) -> Option<bool> {
         let CellState::Allocated { cgroup, ..} = &self.state else {
@@ -194,6 +293,18 @@ impl CellsCache for Cell {
         children.allocate(cell_name, cell_spec)
     }
 
+    fn adopt(
+        &mut self,
+        cell_spec: CellSpec,
+        released: ReleasedCell,
+    ) -> Result<&Cell> {
+        let CellState::Allocated { children, .. } = &mut self.state else {
+            return Err(CellsError::CellNotAllocated { cell_name: self.cell_name.clone() })
+        };
+
+        children.adopt(cell_spec, released)
+    }
+
     fn free(&mut self, cell_name: &CellName) -> Result<()> {
         let CellState::Allocated { children, .. } = &mut self.state else {
             return Err(CellsError::CellNotAllocated { cell_name: self.cell_name.clone() })
@@ -202,6 +313,14 @@ impl CellsCache for Cell {
         children.free(cell_name)
     }
 
+    fn kill(&mut self, cell_name: &CellName) -> Result<()> {
+        let CellState::Allocated { children, .. } = &mut self.state else {
+            return Err(CellsError::CellNotAllocated { cell_name: self.cell_name.clone() })
+        };
+
+        children.kill(cell_name)
+    }
+
     fn get<F, R>(&mut self, cell_name: &CellName, f: F) -> Result<R>
     where
         F: Fn(&Cell) -> Result<R>,
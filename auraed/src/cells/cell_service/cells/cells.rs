@@ -13,7 +13,9 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use super::{cgroups::Cgroup, Cell, CellName, CellSpec, CellsError, Result};
+use super::{
+    cgroups::Cgroup, Cell, CellName, CellSpec, CellsError, ReleasedCell, Result,
+};
 use crate::cells::cell_service::cells::cells_cache::CellsCache;
 use std::collections::HashMap;
 use tracing::warn;
@@ -97,6 +99,47 @@ impl Cells {
         })
     }
 
+    fn adopt(
+        &mut self,
+        cell_spec: CellSpec,
+        released: ReleasedCell,
+    ) -> Result<&Cell> {
+        let cell_name = released.cell_name.clone();
+        proxy_if_needed!(self, cell_name, adopt(cell_spec, released), {
+            if self.cache.contains_key(&cell_name) {
+                return Err(CellsError::CellExists { cell_name });
+            }
+
+            let cell = Cell::adopt(cell_spec, released)?;
+            Ok(self.cache.entry(cell_name).or_insert(cell))
+        })
+    }
+
+    /// Removes a cell that is a direct child from the cache without stopping
+    /// it (see [Cell::release]).
+    pub fn release(
+        &mut self,
+        cell_name: &CellName,
+    ) -> Result<Vec<ReleasedCell>> {
+        let Some(mut cell) = self.cache.remove(cell_name) else {
+            return Err(CellsError::CellNotFound {
+                cell_name: cell_name.clone(),
+            });
+        };
+
+        Ok(cell.release())
+    }
+
+    /// Removes all cells from the cache without stopping them (see [Cell::release]).
+    pub fn release_all(&mut self) -> Vec<ReleasedCell> {
+        self.cache.drain().flat_map(|(_, mut cell)| cell.release()).collect()
+    }
+
+    /// The nested auraeds of all cells, see [Cell::nested_auraeds].
+    pub fn nested_auraeds(&self) -> Vec<ReleasedCell> {
+        self.cache.values().flat_map(Cell::nested_auraeds).collect()
+    }
+
     fn free(&mut self, cell_name: &CellName) -> Result<()> {
         proxy_if_needed!(self, cell_name, free(cell_name), {
             self.handle_cgroup_does_not_exist(cell_name)?;
@@ -106,6 +149,15 @@ impl Cells {
         })
     }
 
+    fn kill(&mut self, cell_name: &CellName) -> Result<()> {
+        proxy_if_needed!(self, cell_name, kill(cell_name), {
+            self.handle_cgroup_does_not_exist(cell_name)?;
+            self.get_mut(cell_name, |cell| cell.kill())?;
+            let _ = self.cache.remove(cell_name);
+            Ok(())
+        })
+    }
+
     fn get<F, R>(&mut self, cell_name: &CellName, f: F) -> Result<R>
     where
         F: Fn(&Cell) -> Result<R>,
@@ -238,10 +290,22 @@ impl CellsCache for Cells {
         self.allocate(cell_name, cell_spec)
     }
 
+    fn adopt(
+        &mut self,
+        cell_spec: CellSpec,
+        released: ReleasedCell,
+    ) -> Result<&Cell> {
+        self.adopt(cell_spec, released)
+    }
+
     fn free(&mut self, cell_name: &CellName) -> Result<()> {
         self.free(cell_name)
     }
 
+    fn kill(&mut self, cell_name: &CellName) -> Result<()> {
+        self.kill(cell_name)
+    }
+
     fn get<F, R>(&mut self, cell_name: &CellName, f: F) -> Result<R>
     where
         F: Fn(&Cell) -> Result<R>,
@@ -42,7 +42,7 @@
  *                                                                            *
 \* -------------------------------------------------------------------------- */
 
-use super::{Cell, CellName, CellSpec, Result};
+use super::{Cell, CellName, CellSpec, ReleasedCell, Result};
 
 pub trait CellsCache {
     /// Calls [Cell::allocate] on a new [Cell] and adds it to it's cache with key [CellName].
@@ -57,6 +57,17 @@ pub trait CellsCache {
         cell_spec: CellSpec,
     ) -> Result<&Cell>;
 
+    /// Adds a [Cell] left running by a previous auraed to it's cache (see [Cell::adopt]).
+    ///
+    /// # Errors
+    /// * If cell exists -> [CellsError::CellExists]
+    /// * If cgroup does not exist -> [CellsError::CgroupNotFound]
+    fn adopt(
+        &mut self,
+        cell_spec: CellSpec,
+        released: ReleasedCell,
+    ) -> Result<&Cell>;
+
     /// Calls [Cell::free] on a [Cell] and removes it from the cache.
     ///
     /// # Errors
@@ -67,6 +78,12 @@ pub trait CellsCache {
     /// * If cell fails to free (see [Cell::free])
     fn free(&mut self, cell_name: &CellName) -> Result<()>;
 
+    /// Calls [Cell::kill] on a [Cell] and removes it from the cache.
+    ///
+    /// # Errors
+    /// * Same as [CellsCache::free]
+    fn kill(&mut self, cell_name: &CellName) -> Result<()>;
+
     fn get<F, R>(&mut self, cell_name: &CellName, f: F) -> Result<R>
     where
         F: Fn(&Cell) -> Result<R>;
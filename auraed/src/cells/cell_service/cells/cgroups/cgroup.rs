@@ -22,7 +22,7 @@ use libcgroups::stats::Stats;
 use libcgroups::v2;
 use nix::unistd::Pid;
 use oci_spec::runtime::{
-    LinuxCpuBuilder, LinuxMemoryBuilder, LinuxResourcesBuilder,
+    LinuxCpuBuilder, LinuxMemoryBuilder, LinuxResources, LinuxResourcesBuilder,
 };
 use std::path::PathBuf;
 use std::str::FromStr;
@@ -147,27 +147,25 @@ impl Cgroup {
         };
 
         let options = builder.build().expect("valid options");
-        let options = ControllerOpt {
-            resources: &options,
-            disable_oom_killer: false,
-            oom_score_adj: None,
-            freezer_state: None,
-        };
 
-        if let Err(e) = non_leaf.apply(&options) {
+        if let Err(e) =
+            update_resources(cell_name.clone().into_inner(), &options)
+        {
             // try to remove, but ignore the error as the original error is more appropriate to return
             // libcgroups takes care of killing any processes it finds
             let _ = leaf.remove();
             let _ = non_leaf.remove();
-            return Err(CgroupsError::CreateCgroup {
-                cell_name,
-                source: e.into(),
-            });
+            return Err(CgroupsError::CreateCgroup { cell_name, source: e });
         }
 
         Ok(Self { cell_name })
     }
 
+    /// Takes over an existing cgroup created for the cell by a previous auraed.
+    pub fn adopt(cell_name: CellName) -> Self {
+        Self { cell_name }
+    }
+
     pub fn add_task(&self, pid: Pid) -> Result<()> {
         let manager = v2::manager::Manager::new(
             DEFAULT_CGROUP_ROOT.into(),
@@ -230,14 +228,36 @@ impl Cgroup {
     }
 
     pub fn exists(cell_name: &CellName) -> bool {
+        Self::path(cell_name).exists()
+    }
+
+    /// The path of the cgroup of the cell `cell_name`.
+    pub fn path(cell_name: &CellName) -> PathBuf {
         let mut path =
             PathBuf::from_str(DEFAULT_CGROUP_ROOT).expect("valid path");
         path.push(cell_name.as_inner());
-        path.exists()
+        path
     }
 }
 
+/// Rewrites the limits of the cgroup at `path`, relative to the cgroup root,
+/// while its processes keep running. Limits missing from `resources` are
+/// left as they are.
+pub fn update_resources(
+    path: PathBuf,
+    resources: &LinuxResources,
+) -> anyhow::Result<()> {
+    let manager = v2::manager::Manager::new(DEFAULT_CGROUP_ROOT.into(), path)?;
+    manager.apply(&ControllerOpt {
+        resources,
+        disable_oom_killer: false,
+        oom_score_adj: None,
+        freezer_state: None,
+    })?;
+    Ok(())
+}
+
 fn get_leaf_path(cell_name: &CellName) -> PathBuf {
     // '_' is an invalid character in CellName, making it safe to use
     cell_name.as_inner().join("_")
-}
\ No newline at end of file
+}
@@ -13,7 +13,7 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-pub use cgroup::Cgroup;
+pub use cgroup::{update_resources, Cgroup};
 pub use cpu::CpuController;
 pub use cpuset::CpusetController;
 pub use limit::Limit;
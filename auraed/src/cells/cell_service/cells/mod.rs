@@ -19,7 +19,9 @@ pub use cells::Cells;
 pub use cells_cache::CellsCache;
 use cgroups::CgroupSpec;
 pub use error::{CellsError, Result};
-pub use nested_auraed::IsolationControls;
+pub use nested_auraed::{IsolationControls, TimeOffsets};
+use nix::unistd::Pid;
+use std::path::PathBuf;
 
 mod cell;
 mod cell_name;
@@ -30,6 +32,15 @@ pub mod cgroups;
 mod error;
 mod nested_auraed;
 
+/// The nested auraed of a [Cell] that was released to outlive auraed (see
+/// [Cell::release]) and that can be adopted again (see [CellsCache::adopt]).
+#[derive(Debug, Clone)]
+pub struct ReleasedCell {
+    pub cell_name: CellName,
+    pub pid: Pid,
+    pub client_socket: PathBuf,
+}
+
 #[derive(Debug, Clone)]
 pub struct CellSpec {
     pub cgroup_spec: CgroupSpec,
@@ -61,6 +72,7 @@ impl CellSpec {
             iso_ctl: IsolationControls {
                 isolate_network: false,
                 isolate_process: false,
+                time_offsets: None,
             },
         }
     }
@@ -22,6 +22,15 @@ use tracing::info;
 pub struct IsolationControls {
     pub isolate_process: bool,
     pub isolate_network: bool,
+    /// Unshare the time namespace with these offsets if set
+    pub time_offsets: Option<TimeOffsets>,
+}
+
+/// Offsets of the clocks of a time namespace from the host, in seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimeOffsets {
+    pub monotonic: i64,
+    pub boottime: i64,
 }
 
 #[derive(Default)]
@@ -110,4 +119,29 @@ impl Isolation {
         // Insert pre_exec network logic here
         Ok(())
     }
+
+    pub fn isolate_time(
+        &mut self,
+        iso_ctl: &IsolationControls,
+    ) -> io::Result<()> {
+        let Some(offsets) = iso_ctl.time_offsets else {
+            return Ok(());
+        };
+
+        // Unlike the other namespaces, a new time namespace is only entered by
+        // the children of the process unsharing it, as the offsets have to be
+        // written before any process uses its clocks.
+        if unsafe { libc::unshare(libc::CLONE_NEWTIME) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        std::fs::write(
+            "/proc/self/timens_offsets",
+            format!(
+                "monotonic {} 0\nboottime {} 0\n",
+                offsets.monotonic, offsets.boottime
+            ),
+        )?;
+        info!("Isolation: Unshared time namespace with offsets {offsets:?}");
+        Ok(())
+    }
 }
\ No newline at end of file
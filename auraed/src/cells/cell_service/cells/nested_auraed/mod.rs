@@ -13,7 +13,7 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-pub use isolation_controls::IsolationControls;
+pub use isolation_controls::{IsolationControls, TimeOffsets};
 pub use nested_auraed::NestedAuraed;
 
 mod isolation_controls;
@@ -27,6 +27,7 @@ use std::{
     io::{self, ErrorKind},
     os::unix::process::{CommandExt, ExitStatusExt},
     process::{Command, ExitStatus},
+    time::Duration,
 };
 use tracing::{error, info, trace};
 
@@ -146,6 +147,7 @@ impl NestedAuraed {
                         command.pre_exec(move || {
                             isolation.isolate_process(&iso_ctl)?;
                             isolation.isolate_network(&iso_ctl)?;
+                            isolation.isolate_time(&iso_ctl)?;
                             Ok(())
                         })
                     }
@@ -166,6 +168,26 @@ impl NestedAuraed {
         }
     }
 
+    /// Takes over a nested auraed that was started by a previous auraed and
+    /// left running when it exited.
+    pub fn adopt(
+        pid: Pid,
+        client_socket: PathBuf,
+        iso_ctl: IsolationControls,
+    ) -> io::Result<Self> {
+        let process = procfs::process::Process::new(pid.as_raw())
+            .map_err(|e| io::Error::new(ErrorKind::Other, e))?;
+
+        info!("Adopting nested auraed running with host pid {pid}");
+
+        Ok(Self {
+            process,
+            pidfd: -1,
+            iso_ctl,
+            client_socket: AuraeSocket::Path(client_socket),
+        })
+    }
+
     /// Sends a graceful shutdown signal to the nested process.
     pub fn shutdown(&mut self) -> io::Result<ExitStatus> {
         // TODO: Here, SIGTERM works when using auraescript, but hangs(?) during unit tests.
@@ -175,16 +197,18 @@ impl NestedAuraed {
         self.wait()
     }
 
+    /// Sends a graceful shutdown signal to the nested process without waiting for it to exit.
+    pub fn terminate(&self) -> io::Result<()> {
+        self.do_kill(Some(SIGTERM))
+    }
+
     /// Sends a [SIGKILL] signal to the nested process.
     pub fn kill(&mut self) -> io::Result<ExitStatus> {
         self.do_kill(Some(SIGKILL))?;
         self.wait()
     }
 
-    fn do_kill<T: Into<Option<Signal>>>(
-        &mut self,
-        signal: T,
-    ) -> io::Result<()> {
+    fn do_kill<T: Into<Option<Signal>>>(&self, signal: T) -> io::Result<()> {
         let signal = signal.into();
         let pid = Pid::from_raw(self.process.pid);
 
@@ -202,8 +226,16 @@ impl NestedAuraed {
 
             if res == -1 {
                 let err = io::Error::last_os_error();
-                match err.kind() {
-                    ErrorKind::Interrupted => continue,
+                match err.raw_os_error() {
+                    Some(libc::EINTR) => continue,
+                    // An adopted nested auraed is not our child, so we can
+                    // only wait for it to disappear and not learn its status
+                    Some(libc::ECHILD) => {
+                        while self.process.is_alive() {
+                            std::thread::sleep(Duration::from_millis(10));
+                        }
+                        break Ok(pid.as_raw());
+                    }
                     _ => break Err(err),
                 }
             }
@@ -221,4 +253,4 @@ impl NestedAuraed {
     pub fn pid(&self) -> Pid {
         Pid::from_raw(self.process.pid)
     }
-}
\ No newline at end of file
+}
@@ -13,8 +13,11 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use super::{cells::CellsError, executables::ExecutablesError};
-use crate::observe::ObserveServiceError;
+use super::{
+    cells::{CellName, CellsError},
+    executables::ExecutablesError,
+};
+use crate::{cordon::NodeCordoned, observe::ObserveServiceError};
 use client::ClientError;
 use thiserror::Error;
 use tonic::Status;
@@ -34,6 +37,16 @@ pub(crate) enum CellsServiceError {
     ClientError(#[from] ClientError),
     #[error(transparent)]
     ObserveServiceError(#[from] ObserveServiceError),
+    #[error("cell '{cell_name}' cannot be allocated on this host: {reason}")]
+    Infeasible { cell_name: CellName, reason: String },
+    #[error(transparent)]
+    Cordoned(#[from] NodeCordoned),
+    #[error("failed to set up the network of cell '{cell_name}': {error}")]
+    NetworkError { cell_name: CellName, error: String },
+    #[error("failed to checkpoint cell '{cell_name}': {error}")]
+    CheckpointError { cell_name: CellName, error: String },
+    #[error("failed to restore cell '{cell_name}': {error}")]
+    RestoreError { cell_name: String, error: String },
 }
 
 impl From<CellsServiceError> for Status {
@@ -71,12 +84,19 @@ impl From<CellsServiceError> for Status {
                     Status::internal(msg)
                 }
             },
-            CellsServiceError::Io(_) => Status::internal(msg),
+            CellsServiceError::Io(_)
+            | CellsServiceError::NetworkError { .. }
+            | CellsServiceError::CheckpointError { .. }
+            | CellsServiceError::RestoreError { .. } => Status::internal(msg),
             CellsServiceError::ClientError(e) => match e {
                 ClientError::ConnectionError(_) => Status::unavailable(msg),
                 ClientError::Other(_) => Status::unknown(msg),
             },
             CellsServiceError::ObserveServiceError(e) => e.into(),
+            CellsServiceError::Infeasible { .. }
+            | CellsServiceError::Cordoned(_) => {
+                Status::failed_precondition(msg)
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -12,18 +12,35 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
-use super::{ExecutableName, ExecutableSpec};
-use crate::logging::log_channel::LogChannel;
-use nix::unistd::Pid;
+use super::{
+    ExecutableName, ExecutablePipes, ExecutableSpec, ExitRecord,
+    ReleasedExecutable, ResourceUsage,
+};
+use crate::logging::log_channel::{LogChannel, LogLabels};
+use nix::{
+    errno::Errno,
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use proto::observe::LogSeverity;
 use std::{
     ffi::OsString,
     io,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::process::ExitStatusExt,
+    },
     process::{ExitStatus, Stdio},
+    time::{Duration, SystemTime},
 };
-use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::unix::pipe;
 use tokio::process::{Child, Command};
 use tokio::task::JoinHandle;
-use tracing::info_span;
+use tracing::{info_span, Instrument};
+
+/// Bytes read from the pipe at once when forwarding to a log ring
+const RING_READ_SIZE: usize = 64 * 1024;
 
 // TODO: decide if we're going to use the description or not.  Remove if not.
 #[allow(dead_code)]
@@ -47,18 +64,69 @@ enum ExecutableState {
         #[allow(unused)]
         args: Vec<OsString>,
         child: Child,
+        started_at: SystemTime,
         stdout: JoinHandle<()>,
         stderr: JoinHandle<()>,
+        /// Handed over when released, see [Executable::release]
+        pipes: ExecutablePipes,
+    },
+    /// Started by a previous auraed, see [Executable::adopt]
+    Adopted {
+        pid: Pid,
+        stdout: Option<JoinHandle<()>>,
+        stderr: Option<JoinHandle<()>>,
+        /// Handed over when released, see [Executable::release]
+        pipes: ExecutablePipes,
     },
-    Stopped(ExitStatus),
+    Stopped(ExitRecord),
 }
 
 impl Executable {
     pub fn new<T: Into<ExecutableSpec>>(spec: T) -> Self {
-        let ExecutableSpec { name, description, command } = spec.into();
+        let ExecutableSpec { name, description, command, log_ring_size } =
+            spec.into();
         let state = ExecutableState::Init { command };
-        let stdout = LogChannel::new(format!("{name}::stdout"));
-        let stderr = LogChannel::new(format!("{name}::stderr"));
+        let channel = |stream: &str, severity| {
+            let channel_name = format!("{name}::{stream}");
+            let channel = match log_ring_size {
+                Some(size) => LogChannel::with_ring(channel_name, size),
+                None => LogChannel::new(channel_name),
+            };
+            channel.with_labels(labels(&name, severity))
+        };
+        let stdout = channel("stdout", LogSeverity::Info);
+        let stderr = channel("stderr", LogSeverity::Error);
+        Self { name, description, stdout, stderr, state }
+    }
+
+    /// Adopts an executable released by a previous auraed (see
+    /// [Executable::release]). Its output is read again from the pipes
+    /// handed over along with it, or else by opening the pipes it writes to
+    /// through `/proc/<pid>/fd`.
+    pub fn adopt(released: ReleasedExecutable) -> Self {
+        let ReleasedExecutable { name, description, pid, pipes } = released;
+        let stdout = LogChannel::new(format!("{name}::stdout"))
+            .with_labels(labels(&name, LogSeverity::Info));
+        let stderr = LogChannel::new(format!("{name}::stderr"))
+            .with_labels(labels(&name, LogSeverity::Error));
+
+        let (kept_stdout, stdout_task) = adopt_pipe(pid, 1, pipes.stdout)
+            .map(|(kept, pipe)| {
+                (kept, forward_lines(pipe, stdout.clone(), &name))
+            })
+            .unzip();
+        let (kept_stderr, stderr_task) = adopt_pipe(pid, 2, pipes.stderr)
+            .map(|(kept, pipe)| {
+                (kept, forward_lines(pipe, stderr.clone(), &name))
+            })
+            .unzip();
+        let state = ExecutableState::Adopted {
+            pid,
+            stdout: stdout_task,
+            stderr: stderr_task,
+            pipes: ExecutablePipes { stdout: kept_stdout, stderr: kept_stderr },
+        };
+
         Self { name, description, stdout, stderr, state }
     }
 
@@ -86,41 +154,16 @@ impl Executable {
         }
         let mut child = command.spawn()?;
 
-        let log_channel = self.stdout.clone();
-        let stdout = child.stdout.take().expect("stdout");
-        let span = info_span!("running process", name = ?self.name);
-        let stdout = tokio::spawn(async move {
-            let log_channel = log_channel;
-            let mut span = Some(span);
-            let mut stdout = BufReader::new(stdout).lines();
-            while let Ok(Some(line)) = stdout.next_line().await {
-                let entered_span = span.take().expect("span").entered();
-                //info!(level = "info", channel = log_channel.name, line);
-                // if std::env::var("AER").is_ok() {
-                //     println!("{line}");
-                // }
-                log_channel.send(line);
-                span = Some(entered_span.exit());
-            }
-        });
-
-        let log_channel = self.stderr.clone();
-        let stderr = child.stderr.take().expect("stderr");
-        let span = info_span!("running process", name = ?self.name);
-        let stderr = tokio::spawn(async move {
-            let log_channel = log_channel;
-            let mut span = Some(span);
-            let mut stderr = BufReader::new(stderr).lines();
-            while let Ok(Some(line)) = stderr.next_line().await {
-                let entered_span = span.take().expect("span").entered();
-                // info!(level = "error", channel = log_channel.name, line);
-                // if std::env::var("AER").is_ok() {
-                //     println!("{line}");
-                // }
-                log_channel.send(line);
-                span = Some(entered_span.exit());
-            }
-        });
+        let stdout_pipe = child.stdout.take().expect("stdout");
+        let stderr_pipe = child.stderr.take().expect("stderr");
+        let pipes = ExecutablePipes {
+            stdout: stdout_pipe.as_fd().try_clone_to_owned().ok(),
+            stderr: stderr_pipe.as_fd().try_clone_to_owned().ok(),
+        };
+        let stdout =
+            forward_lines(stdout_pipe, self.stdout.clone(), &self.name);
+        let stderr =
+            forward_lines(stderr_pipe, self.stderr.clone(), &self.name);
 
         self.state = ExecutableState::Started {
             program: command.as_std().get_program().to_os_string(),
@@ -130,36 +173,206 @@ impl Executable {
                 .map(|arg| arg.to_os_string())
                 .collect(),
             child,
+            started_at: SystemTime::now(),
             stdout,
             stderr,
+            pipes,
         };
 
         Ok(())
     }
 
-    /// Stops the executable and returns the [ExitStatus].
+    /// Stops the executable and returns its [ExitRecord].
     /// If the executable has never been started, returns [None].
-    pub async fn kill(&mut self) -> io::Result<Option<ExitStatus>> {
+    pub async fn kill(&mut self) -> io::Result<Option<ExitRecord>> {
         Ok(match &mut self.state {
             ExecutableState::Init { .. } => None,
-            ExecutableState::Started { child, stdout, stderr, .. } => {
-                child.kill().await?;
-                let exit_status = child.wait().await?;
+            ExecutableState::Started {
+                child,
+                started_at,
+                stdout,
+                stderr,
+                ..
+            } => {
+                let started_at = *started_at;
+                let pid = child.id().expect("child is not reaped before");
+                child.start_kill()?;
+                // Reaped with wait4 rather than Child::wait to collect the
+                // resources it used
+                let (status, usage) =
+                    tokio::task::spawn_blocking(move || wait4(pid as i32))
+                        .await
+                        .map_err(io::Error::other)??;
                 let _ = tokio::join!(stdout, stderr);
-                self.state = ExecutableState::Stopped(exit_status);
-                Some(exit_status)
+                let record = ExitRecord {
+                    status,
+                    usage: Some(usage),
+                    started_at: Some(started_at),
+                    exited_at: SystemTime::now(),
+                };
+                let ExecutableState::Started { child, .. } = std::mem::replace(
+                    &mut self.state,
+                    ExecutableState::Stopped(record),
+                ) else {
+                    unreachable!("executable is started");
+                };
+                // The pid may be reused now that the child is reaped, it must
+                // not be killed on drop
+                std::mem::forget(child);
+                Some(record)
             }
-            ExecutableState::Stopped(status) => Some(*status),
+            ExecutableState::Adopted { pid, stdout, stderr, .. } => {
+                match kill(*pid, Signal::SIGKILL) {
+                    Ok(()) | Err(Errno::ESRCH) => {}
+                    Err(e) => return Err(e.into()),
+                }
+                // The process is not our child, wait for its new parent to
+                // reap it. Its exit status is not ours to collect.
+                while procfs::process::Process::new(pid.as_raw())
+                    .map(|process| process.is_alive())
+                    .unwrap_or(false)
+                {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                for handle in
+                    [stdout.take(), stderr.take()].into_iter().flatten()
+                {
+                    let _ = handle.await;
+                }
+                let record = ExitRecord {
+                    status: ExitStatus::from_raw(Signal::SIGKILL as i32),
+                    usage: None,
+                    started_at: None,
+                    exited_at: SystemTime::now(),
+                };
+                self.state = ExecutableState::Stopped(record);
+                Some(record)
+            }
+            ExecutableState::Stopped(record) => Some(*record),
         })
     }
 
+    /// Lets go of the running process without stopping it, so it outlives
+    /// auraed and can be adopted again with [Executable::adopt].
+    /// The read ends of its output pipes are released along with it, to be
+    /// kept open until it is adopted (see [super::pipe_keeper]), as writing
+    /// to a pipe without readers raises SIGPIPE.
+    /// Returns [None] if the executable was not running.
+    pub fn release(self) -> Option<ReleasedExecutable> {
+        let Self { name, description, state, .. } = self;
+        let (pid, pipes) = match state {
+            ExecutableState::Started { child, pipes, .. } => {
+                let pid = child.id().map(|id| Pid::from_raw(id as i32));
+                // The child is killed on drop
+                std::mem::forget(child);
+                (pid?, pipes)
+            }
+            ExecutableState::Adopted { pid, pipes, .. } => (pid, pipes),
+            ExecutableState::Init { .. } | ExecutableState::Stopped(_) => {
+                return None
+            }
+        };
+        Some(ReleasedExecutable { name, description, pid, pipes })
+    }
+
     /// Returns the [Pid] while [Executable] is running, otherwise returns [None].
     pub fn pid(&self) -> io::Result<Option<Pid>> {
-        let ExecutableState::Started { child: process, .. } = &self.state
-        else {
-            return Ok(None);
-        };
+        match &self.state {
+            ExecutableState::Started { child: process, .. } => {
+                Ok(process.id().map(|id| Pid::from_raw(id as i32)))
+            }
+            ExecutableState::Adopted { pid, .. } => Ok(Some(*pid)),
+            ExecutableState::Init { .. } | ExecutableState::Stopped(_) => {
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// The read end of the pipe the adopted process `pid` writes `fd` to, as
+/// handed over by the previous auraed or else opened through /proc, along
+/// with a duplicate kept to hand it over again.
+fn adopt_pipe(
+    pid: Pid,
+    fd: i32,
+    handed_over: Option<OwnedFd>,
+) -> Option<(OwnedFd, pipe::Receiver)> {
+    let pipe = match handed_over {
+        Some(pipe) => pipe,
+        None => {
+            std::fs::File::open(format!("/proc/{pid}/fd/{fd}")).ok()?.into()
+        }
+    };
+    let kept = pipe.try_clone().ok()?;
+    // Fails unless it is the read end of a pipe
+    let pipe = pipe::Receiver::from_owned_fd(pipe).ok()?;
+    Some((kept, pipe))
+}
 
-        Ok(process.id().map(|id| Pid::from_raw(id as i32)))
+/// Waits for the child `pid` to exit and returns its [ExitStatus] along with
+/// the resources it used.
+/// Output to stdout is info, while output to stderr is an error. The cell of
+/// the executable is only known once its process is running.
+fn labels(name: &ExecutableName, severity: LogSeverity) -> LogLabels {
+    LogLabels {
+        executable_name: name.to_string(),
+        severity,
+        ..Default::default()
     }
 }
+
+fn wait4(pid: i32) -> io::Result<(ExitStatus, ResourceUsage)> {
+    let mut status = 0;
+    // SAFETY: rusage is plain old data, all zeroes is a valid value
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+    loop {
+        // SAFETY: status and rusage are valid for writes
+        if unsafe { libc::wait4(pid, &mut status, 0, &mut rusage) } != -1 {
+            return Ok((ExitStatus::from_raw(status), rusage.into()));
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Sends each line read from `reader` to `log_channel` until EOF.
+/// Channels with a ring get the raw output instead, split into lines by
+/// each consumer.
+fn forward_lines<R>(
+    reader: R,
+    log_channel: LogChannel,
+    name: &ExecutableName,
+) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let span = info_span!("running process", name = ?name);
+    if let Some(ring) = log_channel.ring().cloned() {
+        return tokio::spawn(
+            async move {
+                let mut reader = reader;
+                let mut buf = vec![0; RING_READ_SIZE];
+                while let Ok(n @ 1..) = reader.read(&mut buf).await {
+                    ring.write(&buf[..n]);
+                }
+                ring.close();
+            }
+            .instrument(span),
+        );
+    }
+    tokio::spawn(async move {
+        let mut span = Some(span);
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            let entered_span = span.take().expect("span").entered();
+            //info!(level = "info", channel = log_channel.name, line);
+            // if std::env::var("AER").is_ok() {
+            //     println!("{line}");
+            // }
+            log_channel.send(line);
+            span = Some(entered_span.exit());
+        }
+    })
+}
@@ -14,9 +14,10 @@
 \* -------------------------------------------------------------------------- */
 
 use super::{
-    Executable, ExecutableName, ExecutableSpec, ExecutablesError, Result,
+    Executable, ExecutableName, ExecutableSpec, ExecutablesError, ExitRecord,
+    ReleasedExecutable, Result,
 };
-use std::{collections::HashMap, process::ExitStatus};
+use std::collections::HashMap;
 
 type Cache = HashMap<ExecutableName, Executable>;
 
@@ -63,6 +64,27 @@ impl Executables {
         Ok(inserted_executable)
     }
 
+    /// Adopts an executable released by a previous auraed (see
+    /// [Executables::release_all]).
+    pub fn adopt(
+        &mut self,
+        released: ReleasedExecutable,
+    ) -> Result<&Executable> {
+        if self.cache.contains_key(&released.name) {
+            return Err(ExecutablesError::ExecutableExists {
+                executable_name: released.name,
+            });
+        }
+
+        let executable_name = released.name.clone();
+        let executable = Executable::adopt(released);
+
+        let inserted_executable =
+            self.cache.entry(executable_name).or_insert_with(|| executable);
+
+        Ok(inserted_executable)
+    }
+
     pub fn get(&self, executable_name: &ExecutableName) -> Result<&Executable> {
         let Some(executable) = self.cache.get(executable_name) else {
             return Err(ExecutablesError::ExecutableNotFound {
@@ -75,21 +97,21 @@ impl Executables {
     pub async fn stop(
         &mut self,
         executable_name: &ExecutableName,
-    ) -> Result<ExitStatus> {
+    ) -> Result<ExitRecord> {
         let Some(executable) = self.cache.get_mut(executable_name) else {
             return Err(ExecutablesError::ExecutableNotFound {
                 executable_name: executable_name.clone(),
             });
         };
 
-        let exit_status = executable.kill().await.map_err(|e| {
+        let exit_record = executable.kill().await.map_err(|e| {
             ExecutablesError::FailedToStopExecutable {
                 executable_name: executable_name.clone(),
                 source: e,
             }
         })?;
 
-        let Some(exit_status) = exit_status else {
+        let Some(exit_record) = exit_record else {
             // Exes that never started return None
             let executable =
                 self.cache.remove(executable_name).expect("exe in cache");
@@ -105,7 +127,7 @@ impl Executables {
             }
         })?;
 
-        Ok(exit_status)
+        Ok(exit_record)
     }
 
     /// Stops all executables concurrently
@@ -120,4 +142,13 @@ impl Executables {
             let _ = self.cache.remove(&name);
         }
     }
+
+    /// Removes all executables from the cache without stopping them (see
+    /// [Executable::release]).
+    pub fn release_all(&mut self) -> Vec<ReleasedExecutable> {
+        self.cache
+            .drain()
+            .filter_map(|(_, executable)| executable.release())
+            .collect()
+    }
 }
@@ -17,6 +17,12 @@ pub use error::{ExecutablesError, Result};
 pub use executable::Executable;
 pub use executable_name::ExecutableName;
 pub use executables::Executables;
+use nix::unistd::Pid;
+use std::{
+    os::fd::OwnedFd,
+    process::ExitStatus,
+    time::{Duration, SystemTime},
+};
 use tokio::process::Command;
 
 mod error;
@@ -24,9 +30,67 @@ mod executable;
 mod executable_name;
 #[allow(clippy::module_inception)]
 mod executables;
+pub(crate) mod pipe_keeper;
 
 pub struct ExecutableSpec {
     pub name: ExecutableName,
     pub description: String,
     pub command: Command,
+    /// Size in bytes of the ring the output is shared through, see
+    /// [crate::logging::log_ring::LogRing]
+    pub log_ring_size: Option<usize>,
+}
+
+/// What is known about an [Executable] once it has been stopped.
+#[derive(Debug, Clone, Copy)]
+pub struct ExitRecord {
+    pub status: ExitStatus,
+    /// [None] for executables adopted from a previous auraed, as their
+    /// resource usage is collected by whoever reaps them.
+    pub usage: Option<ResourceUsage>,
+    /// [None] for adopted executables
+    pub started_at: Option<SystemTime>,
+    pub exited_at: SystemTime,
+}
+
+/// Resources used by an [Executable] and the children it waited for, as
+/// reported by `wait4(2)`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub user_cpu: Duration,
+    pub system_cpu: Duration,
+    pub max_rss_bytes: u64,
+}
+
+impl From<libc::rusage> for ResourceUsage {
+    fn from(rusage: libc::rusage) -> Self {
+        let duration = |time: libc::timeval| {
+            Duration::from_secs(time.tv_sec as u64)
+                + Duration::from_micros(time.tv_usec as u64)
+        };
+        Self {
+            user_cpu: duration(rusage.ru_utime),
+            system_cpu: duration(rusage.ru_stime),
+            // ru_maxrss is in kilobytes on Linux
+            max_rss_bytes: rusage.ru_maxrss as u64 * 1024,
+        }
+    }
+}
+
+/// The read ends of the pipes an [Executable] writes its output to.
+#[derive(Debug, Default)]
+pub struct ExecutablePipes {
+    pub stdout: Option<OwnedFd>,
+    pub stderr: Option<OwnedFd>,
+}
+
+/// An [Executable] that was let go of without stopping it, so it outlives
+/// auraed and can be adopted again by pid.
+#[derive(Debug)]
+pub struct ReleasedExecutable {
+    pub name: ExecutableName,
+    pub description: String,
+    pub pid: Pid,
+    /// Kept open while no auraed reads them, see [pipe_keeper]
+    pub pipes: ExecutablePipes,
 }
@@ -0,0 +1,257 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Keeps the output pipes of executables released for an upgrade open until
+//! the next auraed adopts them.
+//!
+//! Writing to a pipe without readers raises SIGPIPE, which kills processes
+//! that do not handle it. Instead of leaving the pipes without readers once
+//! auraed exits, [hand_over] passes their read ends to a keeper process,
+//! `auraed keep-pipes`, which inherits the listening socket they are passed
+//! through. The keeper holds them until the next auraed takes them over with
+//! [take_over], and then exits. Output written in between waits in the
+//! pipes, once they are full the executables block until they are adopted.
+
+use super::{ExecutableName, ExecutablePipes};
+use nix::sys::socket::{
+    accept, bind, connect, listen, recvmsg, sendmsg, socket, AddressFamily,
+    Backlog, ControlMessage, ControlMessageOwned, MsgFlags, SockFlag, SockType,
+    UnixAddr,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, IoSlice, IoSliceMut},
+    os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd},
+    os::unix::process::CommandExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+/// Precedes the read ends of the pipes of one executable
+#[derive(Debug, Serialize, Deserialize)]
+struct Header {
+    name: String,
+    stdout: bool,
+    stderr: bool,
+}
+
+/// Largest [Header] received
+const MAX_HEADER_SIZE: usize = 4096;
+
+/// The socket the pipes released by the auraed using `state_dir` are handed
+/// over through.
+pub(crate) fn socket_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("pipes.sock")
+}
+
+/// Passes the pipes of the released executables to a keeper process, which
+/// holds them until the next auraed takes them over through `socket_path`.
+pub(crate) fn hand_over(
+    socket_path: &Path,
+    released: Vec<(ExecutableName, ExecutablePipes)>,
+) -> io::Result<()> {
+    if released.is_empty() {
+        return Ok(());
+    }
+
+    // Bound before auraed exits, so the next auraed finds it
+    match std::fs::remove_file(socket_path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = seqpacket()?;
+    let address = UnixAddr::new(socket_path)?;
+    bind(listener.as_raw_fd(), &address)?;
+    listen(&listener, Backlog::new(1)?)?;
+
+    // The keeper must outlive auraed, even if its binary was replaced
+    let _keeper = Command::new("/proc/self/exe")
+        .arg("keep-pipes")
+        .arg(socket_path)
+        .stdin(Stdio::from(listener))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+
+    // The keeper accepts the pipes on the first connection
+    let connection = seqpacket()?;
+    connect(connection.as_raw_fd(), &address)?;
+    for (name, pipes) in &released {
+        send(&connection, &name.to_string(), pipes)?;
+    }
+    Ok(())
+}
+
+/// Takes over the pipes handed over by the previous auraed through
+/// `socket_path`, if any, by the name of their executable.
+pub(crate) fn take_over(
+    socket_path: &Path,
+) -> io::Result<Vec<(String, ExecutablePipes)>> {
+    if !socket_path.exists() {
+        return Ok(vec![]);
+    }
+
+    let connection = seqpacket()?;
+    connect(connection.as_raw_fd(), &UnixAddr::new(socket_path)?)?;
+    let mut pipes = vec![];
+    while let Some(received) = receive(&connection)? {
+        pipes.push(received);
+    }
+    Ok(pipes)
+}
+
+/// Runs the keeper with the listening socket as stdin: holds the pipes sent
+/// over the first connection until they are sent over the second.
+pub(crate) fn keep(socket_path: &Path) -> io::Result<()> {
+    let listener = io::stdin().as_fd().try_clone_to_owned()?;
+
+    let mut kept = vec![];
+    let connection = accept_connection(&listener)?;
+    while let Some(received) = receive(&connection)? {
+        kept.push(received);
+    }
+    drop(connection);
+
+    let connection = accept_connection(&listener)?;
+    let _ = std::fs::remove_file(socket_path);
+    for (name, pipes) in &kept {
+        send(&connection, name, pipes)?;
+    }
+    Ok(())
+}
+
+fn seqpacket() -> io::Result<OwnedFd> {
+    Ok(socket(
+        AddressFamily::Unix,
+        SockType::SeqPacket,
+        SockFlag::SOCK_CLOEXEC,
+        None,
+    )?)
+}
+
+fn accept_connection(listener: &OwnedFd) -> io::Result<OwnedFd> {
+    let fd = accept(listener.as_raw_fd())?;
+    // SAFETY: accept returned a new file descriptor we now own
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn send(
+    connection: &OwnedFd,
+    name: &str,
+    pipes: &ExecutablePipes,
+) -> io::Result<()> {
+    let header = serde_json::to_vec(&Header {
+        name: name.into(),
+        stdout: pipes.stdout.is_some(),
+        stderr: pipes.stderr.is_some(),
+    })?;
+    let fds: Vec<RawFd> = [&pipes.stdout, &pipes.stderr]
+        .into_iter()
+        .flatten()
+        .map(|fd| fd.as_raw_fd())
+        .collect();
+    let rights = [ControlMessage::ScmRights(&fds)];
+    let cmsgs = if fds.is_empty() { &[][..] } else { &rights[..] };
+
+    let _ = sendmsg::<()>(
+        connection.as_raw_fd(),
+        &[IoSlice::new(&header)],
+        cmsgs,
+        MsgFlags::empty(),
+        None,
+    )?;
+    Ok(())
+}
+
+/// Receives the pipes of the next executable, [None] once the peer is done.
+fn receive(
+    connection: &OwnedFd,
+) -> io::Result<Option<(String, ExecutablePipes)>> {
+    let mut buf = [0u8; MAX_HEADER_SIZE];
+    let mut cmsg_buf = nix::cmsg_space!([RawFd; 2]);
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let msg = recvmsg::<()>(
+        connection.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg_buf),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )?;
+
+    let mut fds = vec![];
+    for cmsg in msg.cmsgs() {
+        if let ControlMessageOwned::ScmRights(received) = cmsg {
+            // SAFETY: the kernel installed the received file descriptors
+            // for us to own
+            fds.extend(
+                received
+                    .into_iter()
+                    .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            );
+        }
+    }
+    let len = msg.bytes;
+    if len == 0 {
+        return Ok(None);
+    }
+
+    let header: Header = serde_json::from_slice(&buf[..len])?;
+    let mut fds = fds.into_iter();
+    let pipes = ExecutablePipes {
+        stdout: header.stdout.then(|| fds.next()).flatten(),
+        stderr: header.stderr.then(|| fds.next()).flatten(),
+    };
+    Ok(Some((header.name, pipes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nix::sys::socket::socketpair;
+    use std::{
+        io::{Read, Write},
+        os::unix::net::UnixStream,
+    };
+
+    #[test]
+    fn pipes_are_received_as_sent() {
+        let (sender, receiver) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::SOCK_CLOEXEC,
+        )
+        .expect("socketpair");
+        let (reader, mut writer) = UnixStream::pair().expect("pipe");
+
+        let pipes =
+            ExecutablePipes { stdout: None, stderr: Some(reader.into()) };
+        send(&sender, "sleeper", &pipes).expect("send");
+        drop(sender);
+
+        let (name, received) =
+            receive(&receiver).expect("receive").expect("pipes");
+        assert_eq!(name, "sleeper");
+        assert!(received.stdout.is_none());
+        assert!(receive(&receiver).expect("receive").is_none());
+
+        drop(pipes);
+        writer.write_all(b"out").expect("write");
+        let mut output = [0u8; 3];
+        UnixStream::from(received.stderr.expect("stderr"))
+            .read_exact(&mut output)
+            .expect("read");
+        assert_eq!(&output, b"out");
+    }
+}
@@ -0,0 +1,167 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Host feasibility checks for dry runs, which tell whether a cell could be
+//! allocated on this host without creating anything.
+
+use super::{
+    cells::{cgroups::Cgroup, CellName, CellSpec, CellsError},
+    error::CellsServiceError,
+    Result,
+};
+use libcgroups::common::DEFAULT_CGROUP_ROOT;
+use std::{collections::BTreeSet, path::Path};
+
+/// Checks that `spec` can be allocated as `cell_name` on this host: the cell
+/// does not exist yet, its parent does, the parent delegates the controllers
+/// the cell uses, the cpus and memory nodes of its cpuset are online, the
+/// memory it is guaranteed is available, and the kernel supports the
+/// namespaces it isolates with.
+pub(crate) fn check_cell(cell_name: &CellName, spec: &CellSpec) -> Result<()> {
+    let infeasible = |reason: String| CellsServiceError::Infeasible {
+        cell_name: cell_name.clone(),
+        reason,
+    };
+
+    if Cgroup::exists(cell_name) {
+        return Err(
+            CellsError::CellExists { cell_name: cell_name.clone() }.into()
+        );
+    }
+
+    let parent = Path::new(DEFAULT_CGROUP_ROOT)
+        .join(cell_name.as_inner().parent().unwrap_or_else(|| Path::new("")));
+    if !parent.exists() {
+        return Err(infeasible(format!(
+            "parent cgroup {} does not exist",
+            parent.display()
+        )));
+    }
+
+    let controllers = read(&parent, "cgroup.controllers").ok_or_else(|| {
+        infeasible("the host does not use the cgroup v2 hierarchy".into())
+    })?;
+    let controllers: Vec<&str> = controllers.split_whitespace().collect();
+    let cgroup_spec = &spec.cgroup_spec;
+    for (controller, used) in [
+        ("cpu", cgroup_spec.cpu.is_some()),
+        ("cpuset", cgroup_spec.cpuset.is_some()),
+        ("memory", cgroup_spec.memory.is_some()),
+    ] {
+        if used && !controllers.contains(&controller) {
+            return Err(infeasible(format!(
+                "the {controller} controller is not available"
+            )));
+        }
+    }
+
+    if let Some(cpuset) = &cgroup_spec.cpuset {
+        for (file, requested) in [
+            ("cpuset.cpus.effective", cpuset.cpus.as_deref()),
+            ("cpuset.mems.effective", cpuset.mems.as_deref()),
+        ] {
+            let Some(requested) = requested.filter(|r| !r.is_empty()) else {
+                continue;
+            };
+            let (Some(requested), Some(available)) = (
+                parse_list(requested),
+                read(&parent, file).and_then(|a| parse_list(&a)),
+            ) else {
+                return Err(infeasible(format!("cannot compare with {file}")));
+            };
+            if let Some(missing) = requested.difference(&available).next() {
+                return Err(infeasible(format!(
+                    "{missing} is not in {file} of the parent"
+                )));
+            }
+        }
+    }
+
+    if let Some(memory) = &cgroup_spec.memory {
+        let guaranteed = [memory.min.map(|x| *x), memory.low.map(|x| *x)]
+            .into_iter()
+            .flatten()
+            .max()
+            .unwrap_or(0);
+        let available = mem_available().unwrap_or(u64::MAX);
+        if guaranteed as u64 > available {
+            return Err(infeasible(format!(
+                "{guaranteed} bytes of protected memory exceed the \
+                 {available} bytes available"
+            )));
+        }
+    }
+
+    for (namespace, used) in [
+        ("pid", spec.iso_ctl.isolate_process),
+        ("mnt", spec.iso_ctl.isolate_process),
+        ("net", spec.iso_ctl.isolate_network),
+        ("time", spec.iso_ctl.time_offsets.is_some()),
+    ] {
+        if used && !Path::new("/proc/self/ns").join(namespace).exists() {
+            return Err(infeasible(format!(
+                "the kernel does not support {namespace} namespaces"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn read(cgroup: &Path, file: &str) -> Option<String> {
+    std::fs::read_to_string(cgroup.join(file)).ok()
+}
+
+/// Parses a list of ids in the format of the cpuset files (e.g. `0-3,8`).
+fn parse_list(list: &str) -> Option<BTreeSet<u32>> {
+    let mut ids = BTreeSet::new();
+    for range in list.trim().split(',').filter(|r| !r.is_empty()) {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (first.parse().ok()?, last.parse().ok()?),
+            None => {
+                let id = range.parse().ok()?;
+                (id, id)
+            }
+        };
+        ids.extend(first..=last);
+    }
+    Some(ids)
+}
+
+/// The memory of the host available to new workloads in bytes.
+fn mem_available() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb << 10)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(parse_list("0-2,5\n"), Some(BTreeSet::from([0, 1, 2, 5])));
+        assert_eq!(parse_list(""), Some(BTreeSet::new()));
+        assert_eq!(parse_list("0-a"), None);
+    }
+}
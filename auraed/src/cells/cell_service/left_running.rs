@@ -0,0 +1,212 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use crate::process_start;
+use proto::cells::CellGraphNode;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    io,
+    path::{Path, PathBuf},
+};
+use tracing::error;
+
+/// A top-level cell that was left running when auraed exited, as persisted
+/// for the next auraed on the node to adopt.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LeftRunningCell {
+    /// The cell and its nested cells
+    pub cell: CellGraphNode,
+    /// The nested auraed of the cell and each of its nested cells by cell name
+    pub nested_auraed: HashMap<String, LeftRunningNestedAuraed>,
+    /// Whether the cell was allocated with [ShutdownPolicy::LeaveRunning]
+    /// rather than released for an upgrade
+    ///
+    /// [ShutdownPolicy::LeaveRunning]: proto::cells::ShutdownPolicy::LeaveRunning
+    pub leave_running: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LeftRunningNestedAuraed {
+    pub pid: i32,
+    pub client_socket: PathBuf,
+}
+
+impl LeftRunningCell {
+    /// Reads all cells persisted below `dir` along with the path of their
+    /// record, to [remove] once adopted. Invalid records are logged and
+    /// left in place.
+    pub async fn read_all(dir: &Path) -> io::Result<Vec<(PathBuf, Self)>> {
+        read_all(dir).await
+    }
+
+    pub async fn save(&self, dir: &Path) -> io::Result<()> {
+        let name = self.cell.cell.as_ref().map(|c| c.name.as_str());
+        let Some(name) = name else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cell without name",
+            ));
+        };
+
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(
+            dir.join(format!("{name}.json")),
+            serde_json::to_vec(self)?,
+        )
+        .await
+    }
+
+    /// The cell followed by all its nested cells, parents before children
+    pub fn cells(&self) -> Vec<proto::cells::Cell> {
+        fn walk(node: &CellGraphNode, cells: &mut Vec<proto::cells::Cell>) {
+            cells.extend(node.cell.clone());
+            for child in &node.children {
+                walk(child, cells);
+            }
+        }
+
+        let mut cells = vec![];
+        walk(&self.cell, &mut cells);
+        cells
+    }
+}
+
+/// An executable started directly by auraed that was left running when
+/// auraed exited for an upgrade, as persisted for the next auraed to adopt.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct LeftRunningExecutable {
+    pub name: String,
+    pub description: String,
+    pub pid: i32,
+    /// The start time of the process in clock ticks after boot, which tells
+    /// the process apart from a later one reusing its pid
+    pub start_time: u64,
+}
+
+impl LeftRunningExecutable {
+    /// Records the process `pid` along with its start time
+    pub fn new(
+        name: String,
+        description: String,
+        pid: i32,
+    ) -> io::Result<Self> {
+        let start_time = start_time(pid)?;
+        Ok(Self { name, description, pid, start_time })
+    }
+
+    /// Reads all executables persisted below `dir` along with the path of
+    /// their record, to [remove] once adopted. Invalid records are logged
+    /// and left in place.
+    pub async fn read_all(dir: &Path) -> io::Result<Vec<(PathBuf, Self)>> {
+        read_all(&dir.join(EXECUTABLES_DIR)).await
+    }
+
+    pub async fn save(&self, dir: &Path) -> io::Result<()> {
+        let dir = dir.join(EXECUTABLES_DIR);
+        tokio::fs::create_dir_all(&dir).await?;
+        tokio::fs::write(
+            dir.join(format!("{}.json", self.name)),
+            serde_json::to_vec(self)?,
+        )
+        .await
+    }
+
+    /// Whether the recorded process is still running
+    pub fn is_running(&self) -> bool {
+        let alive = procfs::process::Process::new(self.pid)
+            .map(|process| process.is_alive())
+            .unwrap_or(false);
+        alive && start_time(self.pid).ok() == Some(self.start_time)
+    }
+}
+
+const EXECUTABLES_DIR: &str = "executables";
+
+/// Reads the records in the files of `dir`, skipping the invalid ones.
+async fn read_all<T: DeserializeOwned>(
+    dir: &Path,
+) -> io::Result<Vec<(PathBuf, T)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut records = vec![];
+    let mut entries = tokio::fs::read_dir(dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        // Released executables are recorded in a directory of their own,
+        // below the records of the cells
+        if !entry.file_type().await?.is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let parsed = tokio::fs::read(&path).await.and_then(|contents| {
+            serde_json::from_slice(&contents).map_err(io::Error::from)
+        });
+        match parsed {
+            Ok(record) => records.push((path, record)),
+            Err(e) => error!(
+                "Skipping invalid left running record '{}': {e}",
+                path.display()
+            ),
+        }
+    }
+    Ok(records)
+}
+
+/// Removes the record at `path`, once what it records was adopted or is
+/// gone.
+pub(crate) async fn remove(path: &Path) {
+    if let Err(e) = tokio::fs::remove_file(path).await {
+        error!(
+            "Failed to remove left running record '{}': {e}",
+            path.display()
+        );
+    }
+}
+
+fn start_time(pid: i32) -> io::Result<u64> {
+    process_start::start_time(pid)
+        .map_err(|e| io::Error::new(io::ErrorKind::NotFound, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn invalid_records_are_skipped_and_kept() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let executable = LeftRunningExecutable {
+            name: "ae-sleeper".into(),
+            description: "sleeps".into(),
+            pid: 1,
+            start_time: 0,
+        };
+        executable.save(dir.path()).await.expect("save");
+        let invalid = dir.path().join(EXECUTABLES_DIR).join("invalid.json");
+        std::fs::write(&invalid, "{").expect("write invalid record");
+
+        let records =
+            LeftRunningExecutable::read_all(dir.path()).await.expect("read");
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].1.name, "ae-sleeper");
+        assert!(records[0].0.exists(), "records are kept until adopted");
+        assert!(invalid.exists());
+
+        remove(&records[0].0).await;
+        assert!(!records[0].0.exists());
+    }
+}
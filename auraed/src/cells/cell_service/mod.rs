@@ -13,11 +13,18 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 pub use cell_service::CellService;
+pub(crate) use cells::{cgroups::update_resources, CellName};
 use error::Result;
+pub(crate) use executables::pipe_keeper::keep as keep_executable_pipes;
+pub use utilization::UtilizationConfig;
 
+mod autoscaler;
 #[allow(clippy::module_inception)]
 mod cell_service;
 mod cells;
 mod error;
 mod executables;
+mod feasibility;
+mod left_running;
+mod utilization;
 mod validation;
\ No newline at end of file
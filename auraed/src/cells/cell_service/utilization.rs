@@ -0,0 +1,331 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Smoothed CPU and memory utilization of the node and of its cells.
+//!
+//! The [CellService](super::CellService) samples the utilization every
+//! interval and keeps an exponential moving average of the samples over each
+//! configured window, so that placement decisions follow the trend of the
+//! load rather than momentary spikes.
+
+use super::{
+    autoscaler::cpu_usage_usec,
+    cells::{cgroups::Cgroup, CellName},
+};
+use proto::cells::{
+    CellServiceStatsResponse, CellUtilization, Utilization, UtilizationAverage,
+};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// Windows of the moving averages and how often they are sampled.
+#[derive(Debug, Clone)]
+pub struct UtilizationConfig {
+    /// Time between two samples. Defaults to 5 seconds.
+    pub interval: Duration,
+    /// One moving average is kept per window. Defaults to 1, 5 and 15
+    /// minutes.
+    pub windows: Vec<Duration>,
+}
+
+impl Default for UtilizationConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            windows: vec![
+                Duration::from_secs(60),
+                Duration::from_secs(300),
+                Duration::from_secs(900),
+            ],
+        }
+    }
+}
+
+/// Exponential moving average of samples taken at irregular intervals. The
+/// weight of a sample grows with the time elapsed since the previous one, so
+/// that the average covers the same window whatever the sampling rate.
+#[derive(Debug, Clone)]
+struct Ema {
+    window: Duration,
+    value: Option<f64>,
+}
+
+impl Ema {
+    fn new(window: Duration) -> Self {
+        Self { window, value: None }
+    }
+
+    fn update(&mut self, sample: f64, elapsed: Duration) -> f64 {
+        let value = match self.value {
+            None => sample,
+            Some(previous) => {
+                let alpha = 1.0
+                    - (-elapsed.as_secs_f64() / self.window.as_secs_f64())
+                        .exp();
+                previous + alpha * (sample - previous)
+            }
+        };
+        self.value = Some(value);
+        value
+    }
+}
+
+/// The utilization of the node or of one cell.
+#[derive(Debug, Clone)]
+struct Tracker {
+    /// Cumulative CPU usage at the previous sample, and when it was read
+    previous: Option<(u64, Instant)>,
+    /// CPU usage in percent of one CPU, and memory usage in bytes
+    latest: Option<(f64, u64)>,
+    cpu: Vec<Ema>,
+    memory: Vec<Ema>,
+}
+
+impl Tracker {
+    fn new(windows: &[Duration]) -> Self {
+        Self {
+            previous: None,
+            latest: None,
+            cpu: windows.iter().copied().map(Ema::new).collect(),
+            memory: windows.iter().copied().map(Ema::new).collect(),
+        }
+    }
+
+    /// Records the cumulative CPU usage `cpu_usec` and the memory usage read
+    /// at `now`. The CPU usage is a rate, the first sample only sets the
+    /// baseline.
+    fn record(&mut self, cpu_usec: u64, memory_bytes: u64, now: Instant) {
+        let Some((previous_usec, previous_at)) =
+            self.previous.replace((cpu_usec, now))
+        else {
+            return;
+        };
+        let elapsed = now.saturating_duration_since(previous_at);
+        if elapsed.is_zero() {
+            return;
+        }
+
+        let cpu_percent = cpu_usec.saturating_sub(previous_usec) as f64
+            / elapsed.as_micros() as f64
+            * 100.0;
+        self.latest = Some((cpu_percent, memory_bytes));
+        for ema in &mut self.cpu {
+            let _ = ema.update(cpu_percent, elapsed);
+        }
+        for ema in &mut self.memory {
+            let _ = ema.update(memory_bytes as f64, elapsed);
+        }
+    }
+
+    /// None until two samples were recorded
+    fn to_utilization(&self) -> Option<Utilization> {
+        let (cpu_percent, memory_bytes) = self.latest?;
+        let averages = self
+            .cpu
+            .iter()
+            .zip(&self.memory)
+            .map(|(cpu, memory)| UtilizationAverage {
+                window_seconds: cpu.window.as_secs(),
+                cpu_percent: cpu.value.unwrap_or_default(),
+                memory_bytes: memory.value.unwrap_or_default(),
+            })
+            .collect();
+        Some(Utilization { cpu_percent, memory_bytes, averages })
+    }
+}
+
+/// The smoothed utilization of the node and of its top-level cells.
+#[derive(Debug)]
+pub(crate) struct Utilizations {
+    windows: Vec<Duration>,
+    node: Tracker,
+    cells: HashMap<CellName, Tracker>,
+}
+
+impl Utilizations {
+    pub fn new(config: &UtilizationConfig) -> Self {
+        Self {
+            windows: config.windows.clone(),
+            node: Tracker::new(&config.windows),
+            cells: HashMap::new(),
+        }
+    }
+
+    /// Samples the node and the cells `cell_names`. Cells not in
+    /// `cell_names` anymore are forgotten.
+    pub fn sample(&mut self, cell_names: Vec<CellName>) {
+        let now = Instant::now();
+        if let (Some(cpu_usec), Some(memory_bytes)) =
+            (node_cpu_usec(), node_memory_bytes())
+        {
+            self.node.record(cpu_usec, memory_bytes, now);
+        }
+
+        self.cells.retain(|cell_name, _| cell_names.contains(cell_name));
+        for cell_name in cell_names {
+            let (Some(cpu_usec), Some(memory_bytes)) =
+                (cell_cpu_usec(&cell_name), cell_memory_bytes(&cell_name))
+            else {
+                continue;
+            };
+            self.cells
+                .entry(cell_name)
+                .or_insert_with(|| Tracker::new(&self.windows))
+                .record(cpu_usec, memory_bytes, now);
+        }
+    }
+
+    pub fn to_response(&self) -> CellServiceStatsResponse {
+        let mut cells: Vec<CellUtilization> = self
+            .cells
+            .iter()
+            .filter_map(|(cell_name, tracker)| {
+                Some(CellUtilization {
+                    cell_name: cell_name.to_string(),
+                    utilization: Some(tracker.to_utilization()?),
+                })
+            })
+            .collect();
+        cells.sort_by(|a, b| a.cell_name.cmp(&b.cell_name));
+
+        CellServiceStatsResponse { node: self.node.to_utilization(), cells }
+    }
+}
+
+/// CPU time used by the cell since it was allocated
+pub(super) fn cell_cpu_usec(cell_name: &CellName) -> Option<u64> {
+    let cpu_stat =
+        std::fs::read_to_string(Cgroup::path(cell_name).join("cpu.stat"))
+            .ok()?;
+    cpu_usage_usec(&cpu_stat)
+}
+
+/// How often the kernel's OOM killer killed a process of the cell.
+pub(super) fn cell_oom_kills(cell_name: &CellName) -> Option<u64> {
+    let memory_events =
+        std::fs::read_to_string(Cgroup::path(cell_name).join("memory.events"))
+            .ok()?;
+    memory_events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+fn cell_memory_bytes(cell_name: &CellName) -> Option<u64> {
+    std::fs::read_to_string(Cgroup::path(cell_name).join("memory.current"))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+fn node_cpu_usec() -> Option<u64> {
+    let stat = std::fs::read_to_string("/proc/stat").ok()?;
+    // SAFETY: sysconf has no preconditions
+    let ticks_per_second = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+    let ticks_per_second = u64::try_from(ticks_per_second).ok()?;
+    Some(busy_ticks(&stat)? * 1_000_000 / ticks_per_second.max(1))
+}
+
+fn node_memory_bytes() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    used_memory_bytes(&meminfo)
+}
+
+/// The time all CPUs spent busy, in clock ticks, read from /proc/stat
+fn busy_ticks(stat: &str) -> Option<u64> {
+    let fields = stat.lines().find_map(|line| line.strip_prefix("cpu "))?;
+    let fields: Vec<u64> = fields
+        .split_whitespace()
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    // user nice system idle iowait irq softirq steal, guest time is already
+    // accounted for in user and nice
+    let busy = [0, 1, 2, 5, 6, 7].iter().filter_map(|&i| fields.get(i)).sum();
+    Some(busy)
+}
+
+/// The memory not available to new workloads, read from /proc/meminfo
+fn used_memory_bytes(meminfo: &str) -> Option<u64> {
+    let field = |name: &str| {
+        meminfo.lines().find_map(|line| {
+            let value = line.strip_prefix(name)?.strip_prefix(':')?;
+            value.trim().strip_suffix("kB")?.trim().parse::<u64>().ok()
+        })
+    };
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable")?;
+    Some(total.saturating_sub(available) * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ema_converges_to_the_samples_over_its_window() {
+        let mut ema = Ema::new(Duration::from_secs(60));
+        assert_eq!(ema.update(100.0, Duration::from_secs(5)), 100.0);
+
+        // After one window the average covered 1 - 1/e of the step
+        let value = ema.update(0.0, Duration::from_secs(60));
+        assert!((value - 100.0 / std::f64::consts::E).abs() < 1e-9);
+
+        for _ in 0..100 {
+            let _ = ema.update(0.0, Duration::from_secs(60));
+        }
+        assert!(ema.value.expect("updated") < 1e-9);
+    }
+
+    #[test]
+    fn tracker_smooths_cpu_spikes() {
+        let windows = [Duration::from_secs(60), Duration::from_secs(300)];
+        let mut tracker = Tracker::new(&windows);
+        let start = Instant::now();
+        tracker.record(0, 1024, start);
+        assert!(tracker.to_utilization().is_none());
+
+        // Half a CPU for a minute
+        tracker.record(30_000_000, 1024, start + Duration::from_secs(60));
+        // Then four CPUs for 5 seconds
+        tracker.record(50_000_000, 2048, start + Duration::from_secs(65));
+
+        let utilization = tracker.to_utilization().expect("sampled twice");
+        assert_eq!(utilization.cpu_percent, 400.0);
+        assert_eq!(utilization.memory_bytes, 2048);
+        assert_eq!(utilization.averages.len(), 2);
+
+        let minute = &utilization.averages[0];
+        let five_minutes = &utilization.averages[1];
+        assert_eq!(minute.window_seconds, 60);
+        assert!(minute.cpu_percent > 50.0 && minute.cpu_percent < 400.0);
+        assert!(five_minutes.cpu_percent < minute.cpu_percent);
+        assert!(minute.memory_bytes > 1024.0 && minute.memory_bytes < 2048.0);
+    }
+
+    #[test]
+    fn reads_the_node_from_procfs() {
+        let stat = "cpu  10 20 30 400 50 60 70 80 90 100\n\
+                    cpu0 10 20 30 400 50 60 70 80 90 100\n";
+        assert_eq!(busy_ticks(stat), Some(10 + 20 + 30 + 60 + 70 + 80));
+
+        let meminfo = "MemTotal:       16000 kB\n\
+                       MemFree:         2000 kB\n\
+                       MemAvailable:    6000 kB\n";
+        assert_eq!(used_memory_bytes(meminfo), Some(10000 * 1024));
+        assert_eq!(used_memory_bytes("MemTotal: 16000 kB\n"), None);
+    }
+}
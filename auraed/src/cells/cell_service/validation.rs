@@ -12,26 +12,35 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
+use super::autoscaler::{self, AutoscalerSpec, Metric};
 use super::cells::{
     cgroups::{
         self,
         cpuset::{Cpus, Mems},
         CgroupSpec, Limit, Protection, Weight,
     },
-    IsolationControls,
+    IsolationControls, TimeOffsets,
 };
 use super::executables::ExecutableName;
 use crate::cells::cell_service::cells::CellName;
 use proto::cells::{
-    Cell, CellServiceAllocateRequest, CellServiceFreeRequest,
+    autoscaler::Metric as ProtoMetric, Autoscaler, Cell,
+    CellServiceAllocateRequest, CellServiceFreeRequest,
     CellServiceStartRequest, CellServiceStopRequest, CpuController,
-    CpusetController, Executable, MemoryController,
+    CpusetController, Executable, MemoryController, ShutdownPolicy,
+    TimeNamespace,
 };
-use std::ffi::OsString;
+use std::{ffi::OsString, time::Duration};
 use tokio::process::Command;
 use validation::{ValidatedType, ValidationError};
 use validation_macros::ValidatedType;
 
+/// Largest log ring an executable may share each of its outputs through
+const MAX_LOG_RING_SIZE_KB: u32 = 64 * 1024;
+
+/// Largest shift of the clocks of a cell from the host, ten years
+const MAX_TIME_OFFSET_SECONDS: i64 = 10 * 365 * 24 * 60 * 60;
+
 // TODO: Following the discord discussion of wanting to keep the logic on CellService,
 //  versus on the validated request structs, we may not want to create a file per endpoint,
 //  so I'm (future-highway) grouping it all here at least temporarily.
@@ -41,6 +50,21 @@ use validation_macros::ValidatedType;
 pub struct ValidatedCellServiceAllocateRequest {
     #[field_type(Option<Cell>)]
     pub cell: ValidatedCell,
+
+    #[field_type(Option<u64>)]
+    pub ttl_seconds: Option<Duration>,
+
+    #[field_type(Option<u64>)]
+    pub ttl_grace_period_seconds: Option<Duration>,
+
+    #[field_type(i32)]
+    pub shutdown_policy: ShutdownPolicy,
+
+    #[validate(none)]
+    pub dry_run: bool,
+
+    #[field_type(Option<Autoscaler>)]
+    pub autoscaler: Option<AutoscalerSpec>,
 }
 
 impl CellServiceAllocateRequestTypeValidator
@@ -58,6 +82,135 @@ impl CellServiceAllocateRequestTypeValidator
             Some(&validation::field_name(field_name, parent_name)),
         )
     }
+
+    fn validate_ttl_seconds(
+        ttl_seconds: Option<u64>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Option<Duration>, ValidationError> {
+        let Some(ttl_seconds) = ttl_seconds else {
+            return Ok(None);
+        };
+
+        validation::minimum_value(
+            ttl_seconds,
+            1,
+            "seconds",
+            field_name,
+            parent_name,
+        )?;
+
+        Ok(Some(Duration::from_secs(ttl_seconds)))
+    }
+
+    fn validate_ttl_grace_period_seconds(
+        ttl_grace_period_seconds: Option<u64>,
+        _field_name: &str,
+        _parent_name: Option<&str>,
+    ) -> Result<Option<Duration>, ValidationError> {
+        Ok(ttl_grace_period_seconds.map(Duration::from_secs))
+    }
+
+    fn validate_shutdown_policy(
+        shutdown_policy: i32,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<ShutdownPolicy, ValidationError> {
+        match ShutdownPolicy::from_i32(shutdown_policy) {
+            Some(ShutdownPolicy::Unspecified) => Ok(ShutdownPolicy::Stop),
+            // Cells cannot be checkpointed yet
+            None | Some(ShutdownPolicy::Checkpoint) => {
+                Err(ValidationError::Invalid {
+                    field: validation::field_name(field_name, parent_name),
+                })
+            }
+            Some(policy) => Ok(policy),
+        }
+    }
+
+    fn validate_autoscaler(
+        autoscaler: Option<Autoscaler>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Option<AutoscalerSpec>, ValidationError> {
+        let Some(autoscaler) = autoscaler else {
+            return Ok(None);
+        };
+        let parent_name = validation::field_name(field_name, parent_name);
+        let parent_name = Some(parent_name.as_str());
+
+        let executable = validation::required(
+            autoscaler.executable,
+            "executable",
+            parent_name,
+        )?;
+        let _ = ValidatedExecutable::validate(
+            executable.clone(),
+            Some(&*validation::field_name("executable", parent_name)),
+        )?;
+
+        validation::minimum_value(
+            autoscaler.max_replicas,
+            1,
+            "replicas",
+            "max_replicas",
+            parent_name,
+        )?;
+        validation::maximum_value(
+            autoscaler.min_replicas,
+            autoscaler.max_replicas,
+            "replicas",
+            "min_replicas",
+            parent_name,
+        )?;
+
+        let metric = match validation::required(
+            autoscaler.metric,
+            "metric",
+            parent_name,
+        )? {
+            ProtoMetric::CpuPercent(target) => {
+                validation::minimum_value(
+                    target,
+                    1,
+                    "percent",
+                    "cpu_percent",
+                    parent_name,
+                )?;
+                Metric::CpuPercent(f64::from(target))
+            }
+            ProtoMetric::Counter(counter) => {
+                let counter_name =
+                    validation::field_name("counter", parent_name);
+                let path = validation::required_not_empty(
+                    Some(counter.path),
+                    "path",
+                    Some(counter_name.as_str()),
+                )?;
+                let target = counter.target_per_replica;
+                if !target.is_finite() || target <= 0.0 {
+                    return Err(ValidationError::Invalid {
+                        field: validation::field_name(
+                            "target_per_replica",
+                            Some(counter_name.as_str()),
+                        ),
+                    });
+                }
+                Metric::Counter { path: path.into(), target }
+            }
+        };
+
+        Ok(Some(AutoscalerSpec {
+            executable,
+            min_replicas: autoscaler.min_replicas,
+            max_replicas: autoscaler.max_replicas,
+            interval: match autoscaler.interval_seconds {
+                0 => autoscaler::DEFAULT_INTERVAL,
+                seconds => Duration::from_secs(u64::from(seconds)),
+            },
+            metric,
+        }))
+    }
 }
 
 #[derive(ValidatedType, Debug, Clone)]
@@ -80,6 +233,9 @@ pub struct ValidatedCell {
 
     #[validate(none)]
     pub isolate_network: bool,
+
+    #[field_type(Option<TimeNamespace>)]
+    pub time_namespace: Option<TimeOffsets>,
 }
 
 impl CellTypeValidator for CellValidator {
@@ -127,6 +283,45 @@ impl CellTypeValidator for CellValidator {
             Some(&*validation::field_name(field_name, parent_name)),
         )?))
     }
+
+    fn validate_time_namespace(
+        time_namespace: Option<TimeNamespace>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Option<TimeOffsets>, ValidationError> {
+        let Some(time_namespace) = time_namespace else {
+            return Ok(None);
+        };
+        let parent_name = validation::field_name(field_name, parent_name);
+
+        for (offset, field_name) in [
+            (
+                time_namespace.monotonic_offset_seconds,
+                "monotonic_offset_seconds",
+            ),
+            (time_namespace.boottime_offset_seconds, "boottime_offset_seconds"),
+        ] {
+            validation::minimum_value(
+                offset,
+                -MAX_TIME_OFFSET_SECONDS,
+                "seconds",
+                field_name,
+                Some(&parent_name),
+            )?;
+            validation::maximum_value(
+                offset,
+                MAX_TIME_OFFSET_SECONDS,
+                "seconds",
+                field_name,
+                Some(&parent_name),
+            )?;
+        }
+
+        Ok(Some(TimeOffsets {
+            monotonic: time_namespace.monotonic_offset_seconds,
+            boottime: time_namespace.boottime_offset_seconds,
+        }))
+    }
 }
 
 impl From<ValidatedCell> for super::cells::CellSpec {
@@ -138,6 +333,7 @@ impl From<ValidatedCell> for super::cells::CellSpec {
             memory,
             isolate_process,
             isolate_network,
+            time_namespace,
         } = x;
 
         Self {
@@ -146,7 +342,11 @@ impl From<ValidatedCell> for super::cells::CellSpec {
                 cpuset: cpuset.map(|x| x.into()),
                 memory: memory.map(|x| x.into()),
             },
-            iso_ctl: IsolationControls { isolate_process, isolate_network },
+            iso_ctl: IsolationControls {
+                isolate_process,
+                isolate_network,
+                time_offsets: time_namespace,
+            },
         }
     }
 }
@@ -242,6 +442,8 @@ pub struct ValidatedCellServiceStartRequest {
     pub uid: Option<u32>,
     #[validate(none)]
     pub gid: Option<u32>,
+    #[validate(none)]
+    pub dry_run: bool,
 }
 
 impl CellServiceStartRequestTypeValidator for CellServiceStartRequestValidator {
@@ -283,6 +485,10 @@ pub struct ValidatedExecutable {
     // TODO: `#[validate(none)] is used to skip validation. Actually validate when restrictions are known.
     #[validate(none)]
     pub description: String,
+
+    /// Validated into bytes, [None] if no log ring is used
+    #[field_type(u32)]
+    pub log_ring_size_kb: Option<usize>,
 }
 
 impl ExecutableTypeValidator for ExecutableValidator {
@@ -299,11 +505,32 @@ impl ExecutableTypeValidator for ExecutableValidator {
 
         Ok(OsString::from(command))
     }
+
+    fn validate_log_ring_size_kb(
+        log_ring_size_kb: u32,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Option<usize>, ValidationError> {
+        validation::maximum_value(
+            log_ring_size_kb,
+            MAX_LOG_RING_SIZE_KB,
+            "KiB",
+            field_name,
+            parent_name,
+        )?;
+
+        Ok((log_ring_size_kb > 0).then(|| log_ring_size_kb as usize * 1024))
+    }
 }
 
 impl From<ValidatedExecutable> for super::executables::ExecutableSpec {
     fn from(x: ValidatedExecutable) -> Self {
-        let ValidatedExecutable { name, command, description } = x;
+        let ValidatedExecutable {
+            name,
+            command,
+            description,
+            log_ring_size_kb,
+        } = x;
 
         let mut c = Command::new("sh");
         let _ = c.args([OsString::from("-c"), command]);
@@ -312,7 +539,7 @@ impl From<ValidatedExecutable> for super::executables::ExecutableSpec {
         // mutates command, and is not making a clone to return
         assert_eq!(c.as_std().get_args().len(), 2);
 
-        Self { name, command: c, description }
+        Self { name, command: c, description, log_ring_size: log_ring_size_kb }
     }
 }
 
@@ -344,6 +571,39 @@ mod tests {
         assert_eq!(controller.period, None);
     }
 
+    #[test]
+    fn test_cell_type_time_namespace_valid() {
+        let validated = CellValidator::validate_time_namespace(
+            Some(TimeNamespace {
+                monotonic_offset_seconds: -3600,
+                boottime_offset_seconds: 86400,
+            }),
+            "field",
+            Some("parent"),
+        );
+        assert_eq!(
+            validated.unwrap(),
+            Some(TimeOffsets { monotonic: -3600, boottime: 86400 })
+        );
+    }
+
+    #[test]
+    fn test_cell_type_time_namespace_offset_too_large() {
+        let validated = CellValidator::validate_time_namespace(
+            Some(TimeNamespace {
+                monotonic_offset_seconds: 0,
+                boottime_offset_seconds: MAX_TIME_OFFSET_SECONDS + 1,
+            }),
+            "field",
+            Some("parent"),
+        );
+        assert!(matches!(
+            validated,
+            Err(ValidationError::Maximum { field, .. })
+                if field == "parent.field.boottime_offset_seconds"
+        ));
+    }
+
     #[test]
     fn test_cell_type_cpu_weight_too_small() {
         let validated = CellValidator::validate_cpu(
@@ -478,6 +738,50 @@ mod tests {
         assert!(validated.is_err());
     }
 
+    #[test]
+    fn test_cell_service_allocate_request_zero_ttl() {
+        let validated =
+            CellServiceAllocateRequestValidator::validate_ttl_seconds(
+                Some(0),
+                "field",
+                Some("parent"),
+            );
+        assert!(validated.is_err());
+    }
+
+    #[test]
+    fn test_cell_service_allocate_request_ttl_valid() {
+        let validated =
+            CellServiceAllocateRequestValidator::validate_ttl_seconds(
+                Some(30),
+                "field",
+                Some("parent"),
+            );
+        assert_eq!(validated.unwrap(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_cell_service_allocate_request_checkpoint_invalid() {
+        let validated =
+            CellServiceAllocateRequestValidator::validate_shutdown_policy(
+                ShutdownPolicy::Checkpoint as i32,
+                "field",
+                Some("parent"),
+            );
+        assert!(validated.is_err());
+    }
+
+    #[test]
+    fn test_cell_service_allocate_request_unspecified_shutdown_policy() {
+        let validated =
+            CellServiceAllocateRequestValidator::validate_shutdown_policy(
+                ShutdownPolicy::Unspecified as i32,
+                "field",
+                Some("parent"),
+            );
+        assert_eq!(validated.unwrap(), ShutdownPolicy::Stop);
+    }
+
     #[test]
     fn test_cell_service_start_request_empty_executable() {
         let validated = CellServiceStartRequestValidator::validate_executable(
@@ -495,6 +799,7 @@ mod tests {
                 command: String::from(""),
                 name: String::from("name"),
                 description: String::from("description"),
+                log_ring_size_kb: 0,
             }),
             "field",
             Some("parent"),
@@ -509,6 +814,7 @@ mod tests {
                 command: String::from("command"),
                 name: String::from("name"),
                 description: String::from("description"),
+                log_ring_size_kb: 0,
             }),
             "field",
             Some("parent"),
@@ -520,6 +826,7 @@ mod tests {
                 name: ExecutableName::new(String::from("name")),
                 description: String::from("description"),
                 command: OsString::from("command"),
+                log_ring_size_kb: None,
             },
         );
     }
@@ -544,4 +851,18 @@ mod tests {
         assert!(validated.is_ok());
         assert_eq!(validated.unwrap(), OsString::from("command"));
     }
+
+    #[test]
+    fn test_executable_log_ring_size() {
+        let validate = |size_kb| {
+            ExecutableValidator::validate_log_ring_size_kb(
+                size_kb,
+                "field",
+                Some("parent"),
+            )
+        };
+        assert_eq!(validate(0).unwrap(), None);
+        assert_eq!(validate(64).unwrap(), Some(64 * 1024));
+        assert!(validate(MAX_LOG_RING_SIZE_KB + 1).is_err());
+    }
 }
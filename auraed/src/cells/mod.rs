@@ -13,6 +13,9 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-pub(crate) use cell_service::CellService;
+pub use cell_service::UtilizationConfig;
+pub(crate) use cell_service::{
+    keep_executable_pipes, update_resources, CellName, CellService,
+};
 
 mod cell_service;
\ No newline at end of file
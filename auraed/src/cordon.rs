@@ -0,0 +1,120 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Cordoning marks the node unschedulable, e.g. for a maintenance window.
+//!
+//! A cordoned node rejects requests placing new cells, VMs or pods on it
+//! with FAILED_PRECONDITION, so they can be placed on another node. The
+//! workloads it already runs are left untouched. The cordon is recorded in
+//! the runtime directory, so it outlives restarts of auraed but not of the
+//! node.
+
+use std::{
+    fs, io,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+};
+use thiserror::Error;
+use tracing::info;
+
+#[derive(Debug, Error)]
+#[error("node is cordoned: {reason}")]
+pub struct NodeCordoned {
+    pub reason: String,
+}
+
+/// Whether the node is cordoned, shared by all services.
+#[derive(Debug, Clone)]
+pub struct Cordon {
+    path: PathBuf,
+    reason: Arc<RwLock<Option<String>>>,
+}
+
+impl Cordon {
+    /// Opens the cordon recorded at `path`. The node is cordoned if the file
+    /// exists, it holds the reason.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        let reason = match fs::read_to_string(&path) {
+            Ok(reason) => {
+                info!("Node is cordoned: {reason}");
+                Some(reason)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, reason: Arc::new(RwLock::new(reason)) })
+    }
+
+    /// Cordons the node, replacing the reason if it is cordoned already.
+    pub fn cordon(&self, reason: String) -> io::Result<()> {
+        let mut current = self.reason.write().expect("cordon lock poisoned");
+        fs::write(&self.path, &reason)?;
+        info!("Node cordoned: {reason}");
+        *current = Some(reason);
+        Ok(())
+    }
+
+    /// Uncordons the node, returning whether it was cordoned.
+    pub fn uncordon(&self) -> io::Result<bool> {
+        let mut current = self.reason.write().expect("cordon lock poisoned");
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        let was_cordoned = current.take().is_some();
+        if was_cordoned {
+            info!("Node uncordoned");
+        }
+        Ok(was_cordoned)
+    }
+
+    /// Why the node is cordoned, [None] if it is not.
+    pub fn reason(&self) -> Option<String> {
+        self.reason.read().expect("cordon lock poisoned").clone()
+    }
+
+    /// Fails if the node is cordoned, to reject new workloads.
+    pub fn check(&self) -> Result<(), NodeCordoned> {
+        match self.reason() {
+            Some(reason) => Err(NodeCordoned { reason }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cordon_outlives_reopening() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("cordon");
+        let cordon = Cordon::open(path.clone()).expect("open");
+        assert!(cordon.check().is_ok());
+
+        cordon.cordon("kernel upgrade".into()).expect("cordon");
+        assert!(matches!(
+            cordon.check(),
+            Err(NodeCordoned { reason }) if reason == "kernel upgrade"
+        ));
+
+        let reopened = Cordon::open(path.clone()).expect("reopen");
+        assert_eq!(reopened.reason().as_deref(), Some("kernel upgrade"));
+
+        assert!(reopened.uncordon().expect("uncordon"));
+        assert!(!reopened.uncordon().expect("uncordon again"));
+        assert!(Cordon::open(path).expect("reopen").check().is_ok());
+    }
+}
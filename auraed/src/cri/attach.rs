@@ -0,0 +1,35 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Clients of the Attach call of the CRI runtime service, following the
+//! output of a running container.
+//!
+//! Containers are run without a terminal and auraed does not keep their
+//! stdin, so attached clients get the output the container writes once they
+//! connected, as it is copied to its [ContainerLog], and cannot write to
+//! the container. Earlier output is in the log.
+
+use super::container_log::ContainerLog;
+
+/// The container to attach to, as requested by an Attach call.
+#[derive(Debug, Clone)]
+pub(crate) struct Attach {
+    pub container_id: String,
+    pub log: ContainerLog,
+    pub stdout: bool,
+    pub stderr: bool,
+    /// Whether the client expects a terminal, on which stderr is merged
+    /// into stdout
+    pub tty: bool,
+}
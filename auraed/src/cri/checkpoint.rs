@@ -0,0 +1,286 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Checkpoint and restore of containers with CRIU, for the
+//! CheckpointContainer call of the CRI runtime service.
+//!
+//! A running container is dumped into a directory of CRIU images while it
+//! keeps running. The directory can be copied to another node, where a pod
+//! is restored from the checkpoint of its init container by running the pod
+//! with the [RESTORE_ANNOTATION] pointing at the directory, instead of
+//! starting a fresh init container. This is how pods are relocated between
+//! nodes without losing their state.
+//!
+//! The cells of a node checkpoint are dumped the same way, as the process
+//! tree of the nested auraed of each top-level cell (see
+//! [crate::vms::checkpoint]).
+
+use super::stats::{cgroup_of, CGROUP_ROOT};
+use anyhow::{anyhow, Context};
+use libcontainer::container::{Container, ContainerStatus};
+use oci_spec::runtime::Spec;
+use rust_criu::Criu;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    fs::File,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// Pod annotation naming the checkpoint directory the init container of the
+/// pod is restored from
+pub(crate) const RESTORE_ANNOTATION: &str = "checkpoint.aurae.io/restore-from";
+
+/// File describing the checkpoint next to the CRIU images
+const MANIFEST: &str = "checkpoint.json";
+/// Verbosity of the CRIU logs written next to the images
+const CRIU_LOG_LEVEL: i32 = 4;
+
+/// What is needed to restore a checkpoint besides the CRIU images.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Manifest {
+    /// The ID of the checkpointed container, or the name of the cell
+    #[serde(alias = "container_id")]
+    id: String,
+    /// The cgroup of the processes relative to the cgroup root, CRIU
+    /// restores the processes into the same cgroup
+    cgroup: PathBuf,
+}
+
+/// Dumps the running `container` into the CRIU images directory `images`,
+/// leaving it running.
+pub(crate) fn dump(container: &Container, images: &Path) -> anyhow::Result<()> {
+    let pid = container
+        .pid()
+        .ok_or_else(|| anyhow!("container {} has no pid", container.id()))?
+        .as_raw();
+
+    dump_tree(pid, container.id(), images, |dir| {
+        let mut criu = criu(dir, "dump.log")?;
+        criu.set_root(rootfs(container.bundle())?);
+        for (destination, _) in bind_mounts(container.bundle())? {
+            criu.set_external_mount(destination.clone(), destination);
+        }
+        Ok(criu)
+    })
+}
+
+/// Dumps the process tree of the nested auraed `pid` of the top-level cell
+/// `cell_name` into the CRIU images directory `images`, leaving it running.
+/// The tree holds the executables and nested cells of the cell.
+pub(crate) fn dump_cell(
+    pid: i32,
+    cell_name: &str,
+    images: &Path,
+) -> anyhow::Result<()> {
+    dump_tree(pid, cell_name, images, |dir| {
+        let mut criu = criu(dir, "dump.log")?;
+        criu.set_shell_job(true);
+        Ok(criu)
+    })
+}
+
+/// Restores the process tree of a cell dumped into `images` with
+/// [dump_cell] into the cgroup of the cell, and returns the pid of its
+/// nested auraed.
+pub(crate) fn restore_cell(images: &Path) -> anyhow::Result<i32> {
+    let manifest = manifest(images)?;
+    let dir = File::open(images)?;
+    info!("Restoring cell '{}' from {}", manifest.id, images.display());
+
+    let mut criu = criu(&dir, "restore.log")?;
+    criu.set_shell_job(true);
+    criu.restore().map_err(|e| {
+        anyhow!(
+            "criu failed to restore, see {}: {e}",
+            log(images, "restore.log")
+        )
+    })?;
+
+    restored_pid(&manifest.cgroup)
+}
+
+/// Dumps the process tree of `pid` into `images` with the CRIU client
+/// `criu` builds for the opened directory, leaving it running, and records
+/// its cgroup along with `id` in the manifest.
+fn dump_tree(
+    pid: i32,
+    id: &str,
+    images: &Path,
+    criu: impl FnOnce(&File) -> anyhow::Result<Criu>,
+) -> anyhow::Result<()> {
+    let cgroup = cgroup_of(pid)
+        .ok_or_else(|| anyhow!("cgroup of process {pid} not found"))?;
+
+    std::fs::create_dir_all(images).with_context(|| {
+        format!("failed to create checkpoint directory {}", images.display())
+    })?;
+    let dir = File::open(images)?;
+
+    let mut criu = criu(&dir)?;
+    criu.set_pid(pid);
+    criu.set_leave_running(true);
+    criu.dump().map_err(|e| {
+        anyhow!("criu failed to dump, see {}: {e}", log(images, "dump.log"))
+    })?;
+
+    let manifest = Manifest {
+        id: id.to_string(),
+        cgroup: cgroup.strip_prefix(CGROUP_ROOT)?.to_path_buf(),
+    };
+    std::fs::write(images.join(MANIFEST), serde_json::to_vec(&manifest)?)?;
+    Ok(())
+}
+
+/// Restores the container checkpointed into `images` as the container
+/// `container_id` below `root`, with the root filesystem and mounts of
+/// `bundle`, and returns it running.
+pub(crate) fn restore(
+    images: &Path,
+    bundle: &Path,
+    root: &Path,
+    container_id: &str,
+) -> anyhow::Result<Container> {
+    let manifest = manifest(images)?;
+    let dir = File::open(images)?;
+    info!(
+        "Restoring container '{}' as '{container_id}' from {}",
+        manifest.id,
+        images.display()
+    );
+
+    let mut criu = criu(&dir, "restore.log")?;
+    criu.set_root(rootfs(bundle)?);
+    for (destination, source) in bind_mounts(bundle)? {
+        criu.set_external_mount(destination, source);
+    }
+    criu.restore().map_err(|e| {
+        anyhow!(
+            "criu failed to restore, see {}: {e}",
+            log(images, "restore.log")
+        )
+    })?;
+
+    let pid = restored_pid(&manifest.cgroup)?;
+    let container_root = root.join(container_id);
+    std::fs::create_dir_all(&container_root)?;
+    let container = Container::new(
+        container_id,
+        ContainerStatus::Running,
+        Some(pid),
+        bundle,
+        &container_root,
+    )?;
+    container.save()?;
+    Ok(container)
+}
+
+/// The manifest of the checkpoint in `images`.
+fn manifest(images: &Path) -> anyhow::Result<Manifest> {
+    serde_json::from_slice(&std::fs::read(images.join(MANIFEST))?)
+        .with_context(|| format!("{} is not a checkpoint", images.display()))
+}
+
+/// A CRIU client for the images directory `dir`, logging to `log_file` in
+/// it, with the options shared by dumps and restores of containers and
+/// cells.
+fn criu(dir: &File, log_file: &str) -> anyhow::Result<Criu> {
+    let mut criu = Criu::new().map_err(|e| anyhow!("criu not found: {e}"))?;
+    criu.set_images_dir_fd(dir.as_raw_fd());
+    criu.set_work_dir_fd(dir.as_raw_fd());
+    criu.set_log_file(log_file.to_string());
+    criu.set_log_level(CRIU_LOG_LEVEL);
+    criu.set_manage_cgroups(true);
+    criu.set_orphan_pts_master(true);
+    criu.set_ext_unix_sk(true);
+    criu.set_tcp_established(true);
+    criu.set_file_locks(true);
+    Ok(criu)
+}
+
+/// The root filesystem of the containers of `bundle`.
+fn rootfs(bundle: &Path) -> anyhow::Result<String> {
+    Ok(bundle.join("rootfs").canonicalize()?.to_string_lossy().to_string())
+}
+
+/// The bind mounts of the OCI spec of `bundle` as pairs of their destination
+/// and source. CRIU does not dump them but expects them to be mounted again
+/// on restore, identified by their destination.
+fn bind_mounts(bundle: &Path) -> anyhow::Result<Vec<(String, String)>> {
+    let spec = Spec::load(bundle.join("config.json"))?;
+    Ok(spec
+        .mounts()
+        .iter()
+        .flatten()
+        .filter(|mount| {
+            mount.typ().as_deref() == Some("bind")
+                || mount
+                    .options()
+                    .iter()
+                    .flatten()
+                    .any(|option| option == "bind" || option == "rbind")
+        })
+        .map(|mount| {
+            let destination = mount.destination().to_string_lossy();
+            let source = mount
+                .source()
+                .as_ref()
+                .map(|source| source.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (destination.to_string(), source)
+        })
+        .collect())
+}
+
+/// The pid of the root of the process tree CRIU restored into `cgroup`.
+fn restored_pid(cgroup: &Path) -> anyhow::Result<i32> {
+    let procs = Path::new(CGROUP_ROOT).join(cgroup).join("cgroup.procs");
+    let processes = std::fs::read_to_string(&procs)?
+        .lines()
+        .filter_map(|pid| pid.parse().ok())
+        .filter_map(|pid| {
+            let stat = procfs::process::Process::new(pid).ok()?.stat().ok()?;
+            Some((pid, stat.ppid))
+        })
+        .collect::<Vec<_>>();
+    root_process(&processes).ok_or_else(|| {
+        anyhow!("no restored process found in {}", procs.display())
+    })
+}
+
+/// The process of `processes`, given as pairs of their pid and parent pid,
+/// whose parent is not among them.
+fn root_process(processes: &[(i32, i32)]) -> Option<i32> {
+    let pids: HashSet<i32> = processes.iter().map(|(pid, _)| *pid).collect();
+    processes.iter().find(|(_, ppid)| !pids.contains(ppid)).map(|(pid, _)| *pid)
+}
+
+/// Path of the CRIU log `log_file` in `images`, for error messages.
+fn log(images: &Path, log_file: &str) -> String {
+    images.join(log_file).display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn root_process_is_the_one_whose_parent_is_outside() {
+        let processes = [(12, 10), (10, 1), (13, 12)];
+        assert_eq!(root_process(&processes), Some(10));
+        assert_eq!(root_process(&[]), None);
+    }
+}
@@ -0,0 +1,388 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! CNI plugins networking pod sandboxes.
+//!
+//! The network of a sandbox is set up by invoking the plugins of the first
+//! network configuration in the CNI config directory with `ADD`, in the
+//! network namespace of the sandbox. The result of the last plugin is kept
+//! with the sandbox, for its IPs and to tear the network down again with
+//! `DEL`, see the [CNI specification].
+//!
+//! [CNI specification]: https://github.com/containernetworking/cni/blob/main/SPEC.md
+
+use anyhow::{anyhow, Context};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+use tracing::info;
+
+/// Interface the network of a sandbox is attached to in its namespace.
+const IFNAME: &str = "eth0";
+/// Extensions of the files in the config directory, as by libcni.
+const CONFIG_EXTENSIONS: [&str; 3] = ["conflist", "conf", "json"];
+/// The config rendered from the template of the config, sorting first in
+/// the config directory.
+const TEMPLATE_CONFIG: &str = "10-aurae.conflist";
+/// Replaced by the pod CIDR of the node in the template of the config.
+const POD_CIDR_PLACEHOLDER: &str = "{{.PodCIDR}}";
+
+/// Where the CNI network configurations and plugins are found.
+#[derive(Debug, Clone)]
+pub struct CniConfig {
+    /// Directory of the network configurations, the first one in lexical
+    /// order is used.
+    pub conf_dir: PathBuf,
+    /// Directories searched for the plugins, in order.
+    pub bin_dirs: Vec<PathBuf>,
+    /// A network configuration the first pod CIDR the kubelet sets is
+    /// substituted for `{{.PodCIDR}}` in, written to the config directory.
+    /// None if the configurations are left to others.
+    pub conf_template: Option<PathBuf>,
+}
+
+impl Default for CniConfig {
+    fn default() -> Self {
+        Self {
+            conf_dir: PathBuf::from("/etc/cni/net.d"),
+            bin_dirs: vec![PathBuf::from("/opt/cni/bin")],
+            conf_template: None,
+        }
+    }
+}
+
+/// A network configuration list, the plugins of which are invoked in order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Network {
+    name: String,
+    cni_version: String,
+    plugins: Vec<Value>,
+    bin_dirs: Vec<PathBuf>,
+}
+
+impl Network {
+    /// Loads the first network configuration of `config`, None if there is
+    /// none.
+    pub fn load(config: &CniConfig) -> anyhow::Result<Option<Self>> {
+        let entries = match std::fs::read_dir(&config.conf_dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| {
+                path.extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        CONFIG_EXTENSIONS.contains(&extension)
+                    })
+            })
+            .collect();
+        paths.sort();
+        let Some(path) = paths.into_iter().next() else {
+            return Ok(None);
+        };
+
+        let config_json = std::fs::read(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let network = Self::parse(&config_json, config.bin_dirs.clone())
+            .with_context(|| {
+                format!("invalid CNI config {}", path.display())
+            })?;
+        Ok(Some(network))
+    }
+
+    /// Parses a network configuration list, or a single network
+    /// configuration as a list of one plugin.
+    fn parse(
+        config_json: &[u8],
+        bin_dirs: Vec<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let config: Value = serde_json::from_slice(config_json)?;
+        let field = |name: &str| {
+            config
+                .get(name)
+                .and_then(Value::as_str)
+                .map(str::to_owned)
+                .ok_or_else(|| anyhow!("missing '{name}'"))
+        };
+        let plugins = match config.get("plugins") {
+            Some(Value::Array(plugins)) if !plugins.is_empty() => {
+                plugins.clone()
+            }
+            Some(_) => return Err(anyhow!("'plugins' is empty")),
+            None => vec![config.clone()],
+        };
+        Ok(Self {
+            name: field("name")?,
+            cni_version: field("cniVersion")?,
+            plugins,
+            bin_dirs,
+        })
+    }
+
+    /// Attaches the sandbox `container_id` with the network namespace
+    /// `netns` to the network. `args` are passed to the plugins as
+    /// `CNI_ARGS`.
+    pub async fn add(
+        &self,
+        container_id: &str,
+        netns: &Path,
+        args: &[(&str, &str)],
+    ) -> anyhow::Result<Attachment> {
+        let mut attachment = Attachment {
+            network: self.clone(),
+            container_id: container_id.into(),
+            netns: netns.into(),
+            args: cni_args(args),
+            result: None,
+        };
+        for plugin in &self.plugins {
+            let result = attachment.invoke("ADD", plugin).await?;
+            attachment.result = Some(result);
+        }
+        info!(
+            "Attached sandbox '{container_id}' to network '{}' with IPs {:?}",
+            self.name,
+            attachment.ips()
+        );
+        Ok(attachment)
+    }
+
+    /// Checks that the plugins of the network are installed.
+    pub fn check_plugins(&self) -> anyhow::Result<()> {
+        for plugin in &self.plugins {
+            let _ = self.plugin_binary(plugin)?;
+        }
+        Ok(())
+    }
+
+    /// The binary of `plugin`, the first one found in the plugin
+    /// directories.
+    fn plugin_binary(&self, plugin: &Value) -> anyhow::Result<PathBuf> {
+        let typ = plugin
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("plugin without 'type'"))?;
+        self.bin_dirs
+            .iter()
+            .map(|dir| dir.join(typ))
+            .find(|binary| binary.is_file())
+            .ok_or_else(|| anyhow!("CNI plugin '{typ}' not found"))
+    }
+}
+
+/// Renders the template of `config` with the pod CIDR of the node
+/// `pod_cidr` into the config directory. Does nothing without a template.
+pub(crate) fn render_template(
+    config: &CniConfig,
+    pod_cidr: &str,
+) -> anyhow::Result<()> {
+    let Some(template) = &config.conf_template else {
+        return Ok(());
+    };
+    let template = std::fs::read_to_string(template)
+        .with_context(|| format!("failed to read {}", template.display()))?;
+    let rendered = template.replace(POD_CIDR_PLACEHOLDER, pod_cidr);
+    // Only valid configs are written, for plugins to be invoked with
+    let _ = Network::parse(rendered.as_bytes(), config.bin_dirs.clone())
+        .context("invalid CNI config template")?;
+
+    std::fs::create_dir_all(&config.conf_dir)?;
+    let path = config.conf_dir.join(TEMPLATE_CONFIG);
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, rendered)
+        .with_context(|| format!("failed to write {}", tmp.display()))?;
+    std::fs::rename(&tmp, &path)
+        .with_context(|| format!("failed to write {}", path.display()))?;
+    info!("Wrote CNI config {} for pod CIDR {pod_cidr}", path.display());
+    Ok(())
+}
+
+/// A sandbox attached to a [Network].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Attachment {
+    /// The network as it was configured when the sandbox was attached
+    network: Network,
+    container_id: String,
+    netns: PathBuf,
+    args: String,
+    /// The result of the last plugin
+    result: Option<Value>,
+}
+
+impl Attachment {
+    /// The IPs assigned to the sandbox, without their prefix length.
+    pub fn ips(&self) -> Vec<String> {
+        self.result.as_ref().map(result_ips).unwrap_or_default()
+    }
+
+    /// Detaches the sandbox from the network, invoking the plugins in
+    /// reverse order. Plugins clean up even if the namespace is gone.
+    pub async fn remove(&self) -> anyhow::Result<()> {
+        for plugin in self.network.plugins.iter().rev() {
+            let _ = self.invoke("DEL", plugin).await?;
+        }
+        Ok(())
+    }
+
+    /// Invokes `plugin` with `command`, passing it the result so far.
+    async fn invoke(
+        &self,
+        command: &str,
+        plugin: &Value,
+    ) -> anyhow::Result<Value> {
+        let binary = self.network.plugin_binary(plugin)?;
+        let typ = plugin["type"].as_str().unwrap_or_default();
+
+        let mut config = plugin.clone();
+        config["name"] = json!(self.network.name);
+        config["cniVersion"] = json!(self.network.cni_version);
+        if let Some(result) = &self.result {
+            config["prevResult"] = result.clone();
+        }
+
+        let cni_path = std::env::join_paths(&self.network.bin_dirs)?;
+        let mut child = Command::new(&binary)
+            .env_clear()
+            .env("CNI_COMMAND", command)
+            .env("CNI_CONTAINERID", &self.container_id)
+            .env("CNI_NETNS", &self.netns)
+            .env("CNI_IFNAME", IFNAME)
+            .env("CNI_ARGS", &self.args)
+            .env("CNI_PATH", cni_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to run {}", binary.display()))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&serde_json::to_vec(&config)?).await?;
+        }
+        let output = child.wait_with_output().await?;
+
+        if !output.status.success() {
+            // Plugins report errors as JSON on stdout
+            let msg = serde_json::from_slice::<Value>(&output.stdout)
+                .ok()
+                .and_then(|error| Some(error.get("msg")?.as_str()?.to_owned()))
+                .unwrap_or_else(|| {
+                    String::from_utf8_lossy(&output.stderr).trim().to_owned()
+                });
+            return Err(anyhow!(
+                "CNI plugin '{typ}' failed to {command}: {msg}"
+            ));
+        }
+        if output.stdout.iter().all(u8::is_ascii_whitespace) {
+            return Ok(Value::Null);
+        }
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+}
+
+/// `CNI_ARGS` of `args`, ignoring those the plugins do not know.
+fn cni_args(args: &[(&str, &str)]) -> String {
+    std::iter::once("IgnoreUnknown=1".to_owned())
+        .chain(args.iter().map(|(key, value)| format!("{key}={value}")))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The IPs of a result, either in the `ips` of current results or in the
+/// `ip4` and `ip6` of results before CNI 0.3.0.
+fn result_ips(result: &Value) -> Vec<String> {
+    let addresses: Vec<&str> = match result.get("ips") {
+        Some(Value::Array(ips)) => {
+            ips.iter().filter_map(|ip| ip.get("address")?.as_str()).collect()
+        }
+        _ => ["ip4", "ip6"]
+            .iter()
+            .filter_map(|version| result.get(version)?.get("ip")?.as_str())
+            .collect(),
+    };
+    addresses
+        .into_iter()
+        .map(|address| {
+            address.split_once('/').map_or(address, |(ip, _)| ip).to_owned()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_config_is_a_list_of_one_plugin() {
+        let network = Network::parse(
+            br#"{"cniVersion": "1.0.0", "name": "pods", "type": "bridge"}"#,
+            vec![],
+        )
+        .expect("valid config");
+        assert_eq!(network.name, "pods");
+        assert_eq!(network.plugins.len(), 1);
+        assert_eq!(network.plugins[0]["type"], "bridge");
+
+        let network = Network::parse(
+            br#"{"cniVersion": "1.0.0", "name": "pods", "plugins": [
+                {"type": "bridge"}, {"type": "portmap"}
+            ]}"#,
+            vec![],
+        )
+        .expect("valid config list");
+        assert_eq!(network.plugins.len(), 2);
+    }
+
+    #[test]
+    fn ips_of_current_and_legacy_results() {
+        let result = json!({"ips": [
+            {"address": "10.22.0.5/16"},
+            {"address": "fd00::5/64"}
+        ]});
+        assert_eq!(result_ips(&result), vec!["10.22.0.5", "fd00::5"]);
+
+        let result = json!({"ip4": {"ip": "10.22.0.5/16"}});
+        assert_eq!(result_ips(&result), vec!["10.22.0.5"]);
+    }
+
+    #[test]
+    fn templates_are_rendered_with_the_pod_cidr() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let template = dir.path().join("template.conflist");
+        std::fs::write(
+            &template,
+            r#"{"cniVersion": "1.0.0", "name": "pods", "type": "bridge",
+                "ipam": {"type": "host-local", "subnet": "{{.PodCIDR}}"}}"#,
+        )
+        .expect("template");
+        let config = CniConfig {
+            conf_dir: dir.path().join("net.d"),
+            bin_dirs: vec![],
+            conf_template: Some(template),
+        };
+
+        render_template(&config, "10.244.1.0/24").expect("rendered template");
+        let network = Network::load(&config)
+            .expect("valid config")
+            .expect("rendered config");
+        assert_eq!(network.plugins[0]["ipam"]["subnet"], "10.244.1.0/24");
+    }
+}
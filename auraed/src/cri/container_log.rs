@@ -0,0 +1,460 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Container logs in the CRI format, read by kubelet and `crictl logs`.
+//!
+//! Every line a container writes to stdout or stderr is stored as
+//! `<RFC 3339 timestamp> <stream> <tag> <content>`, the tag being `F` for a
+//! full line and `P` for the part of a line longer than [MAX_LINE] that is
+//! continued by the next entry. Logs are rotated to
+//! `<path>.<YYYYMMDD-HHMMSS.ffffff>` once they outgrow the size or age of the
+//! [ContainerLogConfig], keeping at most its number of files. The lines are
+//! also sent to the [LogChannel] of the log, if any, for the log stream of
+//! the observe API, and the output as it is read to the clients attached to
+//! the container.
+
+use crate::logging::log_channel::LogChannel;
+use chrono::{SecondsFormat, Utc};
+use proto::observe::LogSeverity;
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    os::fd::{FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    net::unix::pipe,
+    sync::broadcast,
+};
+use tracing::warn;
+
+/// Longest part of a line stored in one entry.
+const MAX_LINE: usize = 16 * 1024;
+/// Reads of the output buffered for each attached client, past which a
+/// slow client misses output.
+const ATTACHED_CAPACITY: usize = 256;
+
+/// Output read from a stream of a container, empty once it was closed.
+pub(crate) type Output = (LogStream, Vec<u8>);
+
+/// When container logs are rotated.
+#[derive(Debug, Clone)]
+pub struct ContainerLogConfig {
+    /// Size in bytes past which a log is rotated.
+    pub max_size: u64,
+    /// Files kept of a log, including the one written to.
+    pub max_files: usize,
+    /// Age past which a log is rotated, whatever its size. None to only
+    /// rotate by size.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for ContainerLogConfig {
+    fn default() -> Self {
+        Self { max_size: 10 * 1024 * 1024, max_files: 5, max_age: None }
+    }
+}
+
+/// The stream of a container a line was written to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+impl LogStream {
+    fn as_str(self) -> &'static str {
+        match self {
+            LogStream::Stdout => "stdout",
+            LogStream::Stderr => "stderr",
+        }
+    }
+
+    fn severity(self) -> LogSeverity {
+        match self {
+            LogStream::Stdout => LogSeverity::Info,
+            LogStream::Stderr => LogSeverity::Error,
+        }
+    }
+}
+
+/// The log file of a container, shared by the copiers of its streams.
+#[derive(Debug, Clone)]
+pub(crate) struct ContainerLog {
+    inner: Arc<Mutex<LogFile>>,
+    channel: Option<LogChannel>,
+    output: broadcast::Sender<Output>,
+    /// Streams copied to the log that were not closed yet
+    open_streams: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened: Instant,
+    config: ContainerLogConfig,
+}
+
+impl ContainerLog {
+    /// Opens the log at `path` for appending, creating it and its
+    /// directory.
+    pub fn open(path: PathBuf, config: ContainerLogConfig) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let (file, size) = open(&path)?;
+        let log = LogFile { path, file, size, opened: Instant::now(), config };
+        Ok(Self {
+            inner: Arc::new(Mutex::new(log)),
+            channel: None,
+            output: broadcast::channel(ATTACHED_CAPACITY).0,
+            open_streams: Default::default(),
+        })
+    }
+
+    /// Also sends the lines written to the log to `channel`. Set before the
+    /// output of the container is copied.
+    pub fn with_channel(mut self, channel: LogChannel) -> Self {
+        self.channel = Some(channel);
+        self
+    }
+
+    pub fn channel(&self) -> Option<&LogChannel> {
+        self.channel.as_ref()
+    }
+
+    /// Subscribes to the output of the container as it is read, for a
+    /// client attached to it. None once all its streams were closed.
+    pub fn attach(&self) -> Option<broadcast::Receiver<Output>> {
+        let output = self.output.subscribe();
+        (!self.is_closed()).then_some(output)
+    }
+
+    /// Whether all the streams copied to the log were closed.
+    pub fn is_closed(&self) -> bool {
+        self.open_streams.load(Ordering::SeqCst) == 0
+    }
+
+    /// Opens the log at its path again, for logs moved away by someone
+    /// else, as on a ReopenContainerLog call.
+    pub fn reopen(&self) -> io::Result<()> {
+        let mut log = self.inner.lock().expect("container log lock");
+        let (file, size) = open(&log.path)?;
+        log.file = file;
+        log.size = size;
+        log.opened = Instant::now();
+        Ok(())
+    }
+
+    /// Pipes copied to the log, to be the stdout and stderr of a container.
+    pub fn pipes(&self) -> io::Result<(OwnedFd, OwnedFd)> {
+        let (stdout, stdout_writer) = pipe_fds()?;
+        let (stderr, stderr_writer) = pipe_fds()?;
+        let _ = self
+            .copy(pipe::Receiver::from_owned_fd(stdout)?, LogStream::Stdout);
+        let _ = self
+            .copy(pipe::Receiver::from_owned_fd(stderr)?, LogStream::Stderr);
+        Ok((stdout_writer, stderr_writer))
+    }
+
+    /// Copies the lines read from `output` to the log until it is closed.
+    pub fn copy(
+        &self,
+        mut output: impl AsyncRead + Send + Unpin + 'static,
+        stream: LogStream,
+    ) -> tokio::task::JoinHandle<()> {
+        let log = self.clone();
+        let _ = self.open_streams.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(async move {
+            let mut line = Vec::new();
+            let mut buf = [0u8; 4096];
+            loop {
+                let n = match output.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                if log.output.receiver_count() > 0 {
+                    let _ = log.output.send((stream, buf[..n].to_vec()));
+                }
+                line.extend_from_slice(&buf[..n]);
+                if let Err(e) = log.write_lines(stream, &mut line) {
+                    warn!("Failed to write container log: {e}");
+                }
+            }
+            if !line.is_empty() {
+                if let Err(e) = log.write(stream, false, &line) {
+                    warn!("Failed to write container log: {e}");
+                }
+            }
+            // closed before the end is sent, for attached clients to
+            // tell the last stream
+            let _ = log.open_streams.fetch_sub(1, Ordering::SeqCst);
+            let _ = log.output.send((stream, vec![]));
+        })
+    }
+
+    /// Writes the full lines and overlong parts of `pending`, leaving the
+    /// rest of the last line in it. At least one byte is left after a
+    /// partial entry, so the line always ends with a full entry, at the
+    /// latest when the rest is flushed at the end of the output.
+    fn write_lines(
+        &self,
+        stream: LogStream,
+        pending: &mut Vec<u8>,
+    ) -> io::Result<()> {
+        let mut start = 0;
+        while let Some(end) = pending[start..].iter().position(|b| *b == b'\n')
+        {
+            let line = &pending[start..start + end];
+            for (i, part) in line.chunks(MAX_LINE).enumerate() {
+                let partial = (i + 1) * MAX_LINE < line.len();
+                self.write(stream, partial, part)?;
+            }
+            start += end + 1;
+        }
+        while pending.len() - start > MAX_LINE {
+            self.write(stream, true, &pending[start..start + MAX_LINE])?;
+            start += MAX_LINE;
+        }
+        let _ = pending.drain(..start);
+        Ok(())
+    }
+
+    /// Writes one entry of `content`, rotating the log first if due.
+    fn write(
+        &self,
+        stream: LogStream,
+        partial: bool,
+        content: &[u8],
+    ) -> io::Result<()> {
+        let mut entry = format!(
+            "{} {} {} ",
+            Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true),
+            stream.as_str(),
+            if partial { "P" } else { "F" }
+        )
+        .into_bytes();
+        entry.extend_from_slice(content);
+        entry.push(b'\n');
+
+        let mut log = self.inner.lock().expect("container log lock");
+        let outgrown =
+            log.size > 0 && log.size + entry.len() as u64 > log.config.max_size;
+        let aged =
+            log.config.max_age.is_some_and(|age| log.opened.elapsed() >= age);
+        if outgrown || aged {
+            log.rotate()?;
+        }
+        log.file.write_all(&entry)?;
+        log.size += entry.len() as u64;
+        drop(log);
+
+        if let Some(channel) = &self.channel {
+            let line = String::from_utf8_lossy(content).into_owned();
+            channel.log(stream.severity(), line);
+        }
+        Ok(())
+    }
+}
+
+impl LogFile {
+    /// Moves the log aside, removes the rotated logs beyond the number of
+    /// files kept and starts a new log.
+    fn rotate(&mut self) -> io::Result<()> {
+        let suffix = Utc::now().format("%Y%m%d-%H%M%S%.6f");
+        let mut rotated = self.path.clone().into_os_string();
+        rotated.push(format!(".{suffix}"));
+        std::fs::rename(&self.path, &rotated)?;
+
+        let mut rotated = rotated_logs(&self.path)?;
+        rotated.sort();
+        let excess = (rotated.len() + 1).saturating_sub(self.config.max_files);
+        for path in rotated.into_iter().take(excess) {
+            std::fs::remove_file(path)?;
+        }
+
+        let (file, size) = open(&self.path)?;
+        self.file = file;
+        self.size = size;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+/// A pipe not inherited by the processes auraed spawns, as its read and
+/// write end. Containers get the write end as their stdio.
+fn pipe_fds() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: pipe2 fills fds with two new file descriptors on success
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: the file descriptors are owned by nothing else
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn open(path: &Path) -> io::Result<(File, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let size = file.metadata()?.len();
+    Ok((file, size))
+}
+
+/// The rotated logs of the log at `path`, oldest first once sorted.
+fn rotated_logs(path: &Path) -> io::Result<Vec<PathBuf>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Ok(vec![]);
+    };
+    let prefix = format!("{}.", name.to_string_lossy());
+    Ok(std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_name().to_string_lossy().starts_with(&prefix)
+        })
+        .map(|entry| entry.path())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn entries(path: &Path) -> Vec<(String, String, String)> {
+        std::fs::read_to_string(path)
+            .expect("log")
+            .lines()
+            .map(|line| {
+                let mut fields = line.splitn(4, ' ').skip(1);
+                let mut next = || fields.next().unwrap_or_default().to_string();
+                (next(), next(), next())
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn writes_lines_in_the_cri_format() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("c/0.log");
+        let log =
+            ContainerLog::open(path.clone(), Default::default()).expect("open");
+
+        let long = "x".repeat(MAX_LINE + 1);
+        let output = format!("hello\n{long}\nno newline");
+        log.copy(io::Cursor::new(output.into_bytes()), LogStream::Stderr)
+            .await
+            .expect("copy");
+
+        let entries = entries(&path);
+        let expected = [
+            ("stderr", "F", "hello"),
+            ("stderr", "P", &long[..MAX_LINE]),
+            ("stderr", "F", "x"),
+            ("stderr", "F", "no newline"),
+        ];
+        assert_eq!(entries.len(), expected.len());
+        for (entry, (stream, tag, content)) in entries.iter().zip(expected) {
+            assert_eq!((entry.0.as_str(), entry.1.as_str()), (stream, tag));
+            assert_eq!(entry.2, content);
+        }
+    }
+
+    #[tokio::test]
+    async fn ends_overlong_lines_with_a_full_entry() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("c/0.log");
+        let log =
+            ContainerLog::open(path.clone(), Default::default()).expect("open");
+
+        let long = "x".repeat(2 * MAX_LINE);
+        log.copy(io::Cursor::new(long.clone().into_bytes()), LogStream::Stdout)
+            .await
+            .expect("copy");
+
+        let entries = entries(&path);
+        let tags: Vec<_> = entries.iter().map(|e| e.1.as_str()).collect();
+        assert_eq!(tags, ["P", "F"]);
+        assert_eq!(entries[0].2, long[..MAX_LINE]);
+        assert_eq!(entries[1].2, long[MAX_LINE..]);
+    }
+
+    #[tokio::test]
+    async fn sends_lines_to_its_channel() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("c/0.log");
+        let channel = LogChannel::new("c".into());
+        let mut rx = channel.subscribe();
+        let log = ContainerLog::open(path, Default::default())
+            .expect("open")
+            .with_channel(channel);
+
+        log.copy(io::Cursor::new(b"hello\n".to_vec()), LogStream::Stderr)
+            .await
+            .expect("copy");
+
+        let item = rx.recv().await.expect("item");
+        assert_eq!(item.line, "hello");
+        assert_eq!(item.severity, LogSeverity::Error as i32);
+    }
+
+    #[tokio::test]
+    async fn sends_output_to_attached_clients() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let log =
+            ContainerLog::open(dir.path().join("c/0.log"), Default::default())
+                .expect("open");
+        let (reader, mut writer) = tokio::io::duplex(64);
+        let copy = log.copy(reader, LogStream::Stdout);
+        let mut output = log.attach().expect("open streams");
+
+        writer.write_all(b"hel").await.expect("write");
+        assert_eq!(
+            output.recv().await.expect("output"),
+            (LogStream::Stdout, b"hel".to_vec())
+        );
+        drop(writer);
+        copy.await.expect("copy");
+
+        assert_eq!(
+            output.recv().await.expect("end"),
+            (LogStream::Stdout, vec![])
+        );
+        assert!(log.is_closed());
+        assert!(log.attach().is_none());
+    }
+
+    #[test]
+    fn rotates_logs_past_their_size() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("0.log");
+        let config =
+            ContainerLogConfig { max_size: 64, max_files: 2, max_age: None };
+        let log = ContainerLog::open(path.clone(), config).expect("open");
+
+        for i in 0..3 {
+            log.write(LogStream::Stdout, false, format!("line {i}").as_bytes())
+                .expect("write");
+        }
+
+        assert_eq!(rotated_logs(&path).expect("rotated").len(), 1);
+        assert_eq!(entries(&path)[0].2, "line 2");
+    }
+}
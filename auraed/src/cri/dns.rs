@@ -0,0 +1,122 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! DNS of pod sandboxes.
+//!
+//! The DNS config the kubelet gives a sandbox is written as a resolv.conf
+//! in the directory of the sandbox, and bind mounted at /etc/resolv.conf
+//! in each of its containers. Sandboxes without a DNS config leave the
+//! containers with the resolv.conf of their image.
+
+use oci_spec::runtime::{Mount, MountBuilder};
+use proto::cri::DnsConfig;
+use std::path::{Path, PathBuf};
+
+/// Where the resolv.conf of a sandbox is written in its directory
+const RESOLV_CONF: &str = "resolv.conf";
+/// Where the resolv.conf is mounted in containers
+const CONTAINER_PATH: &str = "/etc/resolv.conf";
+
+/// The resolv.conf of a sandbox, see resolv.conf(5).
+pub(crate) fn resolv_conf(config: &DnsConfig) -> String {
+    let mut resolv_conf = String::new();
+    for server in &config.servers {
+        resolv_conf.push_str(&format!("nameserver {server}\n"));
+    }
+    if !config.searches.is_empty() {
+        resolv_conf
+            .push_str(&format!("search {}\n", config.searches.join(" ")));
+    }
+    if !config.options.is_empty() {
+        resolv_conf
+            .push_str(&format!("options {}\n", config.options.join(" ")));
+    }
+    resolv_conf
+}
+
+/// Writes the resolv.conf of `config` to the directory of the sandbox
+/// `pod_dir` and returns its path.
+pub(crate) fn write(
+    pod_dir: &Path,
+    config: &DnsConfig,
+) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(pod_dir)?;
+    let path = pod_dir.join(RESOLV_CONF);
+    std::fs::write(&path, resolv_conf(config))?;
+    Ok(path)
+}
+
+/// The resolv.conf of the sandbox in `pod_dir`, None if it has none.
+pub(crate) fn find(pod_dir: &Path) -> Option<PathBuf> {
+    Some(pod_dir.join(RESOLV_CONF)).filter(|path| path.is_file())
+}
+
+/// The mount of the resolv.conf at `path` in a container, unless the
+/// container mounts its own from `mounts`. It is read-only if the root
+/// filesystem of the container is.
+pub(crate) fn mount(
+    path: &Path,
+    mounts: &[proto::cri::Mount],
+    readonly: bool,
+) -> Option<Mount> {
+    if mounts.iter().any(|mount| mount.container_path == CONTAINER_PATH) {
+        return None;
+    }
+    let options = ["rbind", "rprivate", if readonly { "ro" } else { "rw" }];
+    Some(
+        MountBuilder::default()
+            .destination(CONTAINER_PATH)
+            .typ("bind")
+            .source(path)
+            .options(options.map(String::from).to_vec())
+            .build()
+            .expect("resolv.conf mount"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_the_dns_config_of_the_kubelet() {
+        let config = DnsConfig {
+            servers: vec!["10.96.0.10".into()],
+            searches: vec![
+                "default.svc.cluster.local".into(),
+                "svc.cluster.local".into(),
+            ],
+            options: vec!["ndots:5".into()],
+        };
+        assert_eq!(
+            resolv_conf(&config),
+            "nameserver 10.96.0.10\n\
+             search default.svc.cluster.local svc.cluster.local\n\
+             options ndots:5\n"
+        );
+        assert_eq!(resolv_conf(&DnsConfig::default()), "");
+    }
+
+    #[test]
+    fn containers_may_mount_their_own() {
+        let path = Path::new("/run/aurae/pods/nginx/resolv.conf");
+        assert!(mount(path, &[], false).is_some());
+        let own = proto::cri::Mount {
+            container_path: CONTAINER_PATH.into(),
+            host_path: "/etc/resolv.conf".into(),
+            ..Default::default()
+        };
+        assert!(mount(path, &[own], false).is_none());
+    }
+}
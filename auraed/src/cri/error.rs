@@ -13,6 +13,10 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use crate::cordon::NodeCordoned;
+use crate::cri::hooks::HooksError;
+use crate::cri::volumes::VolumeError;
+use crate::images::ImageServiceError;
 use client::ClientError;
 use thiserror::Error;
 use tonic::Status;
@@ -28,10 +32,66 @@ pub enum RuntimeServiceError {
     SandboxNotFound { sandbox_id: String },
     #[error("sandobx '{sandbox_id}' not in exited state")]
     SandboxNotExited { sandbox_id: String },
+    #[error("Failed to start sandbox '{sandbox_id}': {error}")]
+    StartError { sandbox_id: String, error: String },
+    #[error("Failed to delete sandbox '{sandbox_id}': {error}")]
+    DeleteError { sandbox_id: String, error: String },
     #[error("Failed to kill sandbox '{sandbox_id}': {error}")]
     KillError { sandbox_id: String, error: String },
+    #[error("Failed to set up the network of sandbox '{sandbox_id}': {error}")]
+    NetworkError { sandbox_id: String, error: String },
+    #[error("Failed to set up the cell of sandbox '{sandbox_id}': {error}")]
+    PodCellError { sandbox_id: String, error: String },
+    #[error("Failed to set up the DNS of sandbox '{sandbox_id}': {error}")]
+    DnsError { sandbox_id: String, error: String },
+    #[error("invalid pod CIDR '{pod_cidr}': {error}")]
+    InvalidPodCidr { pod_cidr: String, error: String },
+    #[error("Failed to update the runtime config: {error}")]
+    RuntimeConfigError { error: String },
+    #[error("container '{container_id}' already exists")]
+    ContainerExists { container_id: String },
+    #[error("invalid config of container '{container_id}': {error}")]
+    InvalidContainerConfig { container_id: String, error: String },
+    #[error("Failed to create container '{container_id}': {error}")]
+    CreateError { container_id: String, error: String },
+    #[error("container '{container_id}' not found")]
+    ContainerNotFound { container_id: String },
+    #[error("container '{container_id}' is not running")]
+    ContainerNotRunning { container_id: String },
+    #[error("invalid stream request for '{container_id}': {reason}")]
+    InvalidStreamRequest { container_id: String, reason: &'static str },
+    #[error("Failed to exec in container '{container_id}': {error}")]
+    ExecError { container_id: String, error: String },
+    #[error("exec in container '{container_id}' timed out after {timeout}s")]
+    ExecTimeout { container_id: String, timeout: i64 },
+    #[error("container '{container_id}' has no log")]
+    NoContainerLog { container_id: String },
+    #[error("Failed to open the log of container '{container_id}': {error}")]
+    LogError { container_id: String, error: String },
+    #[error("Failed to update resources of '{container_id}': {error}")]
+    UpdateResourcesError { container_id: String, error: String },
+    #[error("checkpoint of '{container_id}' has no location")]
+    MissingCheckpointLocation { container_id: String },
+    #[error("Failed to checkpoint container '{container_id}': {error}")]
+    CheckpointError { container_id: String, error: String },
+    #[error("Failed to restore sandbox '{sandbox_id}': {error}")]
+    RestoreError { sandbox_id: String, error: String },
+    #[error("unknown runtime handler '{handler}'")]
+    UnknownRuntimeHandler { handler: String },
+    #[error("runtime handler '{handler}' needs the vms subsystem")]
+    VmsDisabled { handler: String },
+    #[error("Failed to run the vm of sandbox '{sandbox_id}': {error}")]
+    VmPodError { sandbox_id: String, error: String },
     #[error(transparent)]
     ClientError(#[from] ClientError),
+    #[error(transparent)]
+    Cordoned(#[from] NodeCordoned),
+    #[error(transparent)]
+    Hooks(#[from] HooksError),
+    #[error(transparent)]
+    Volume(#[from] VolumeError),
+    #[error(transparent)]
+    Image(#[from] ImageServiceError),
 }
 
 impl From<RuntimeServiceError> for Status {
@@ -39,16 +99,51 @@ impl From<RuntimeServiceError> for Status {
         let msg = err.to_string();
         error!("{msg}");
         match err {
-            RuntimeServiceError::SandboxExists { .. } => {
+            RuntimeServiceError::SandboxExists { .. }
+            | RuntimeServiceError::ContainerExists { .. } => {
                 Status::already_exists(msg)
             }
-            RuntimeServiceError::SandboxNotFound { .. } => {
+            RuntimeServiceError::SandboxNotFound { .. }
+            | RuntimeServiceError::ContainerNotFound { .. } => {
                 Status::not_found(msg)
             }
-            RuntimeServiceError::SandboxNotExited { .. } => {
-                Status::failed_precondition(msg)
+            RuntimeServiceError::InvalidStreamRequest { .. }
+            | RuntimeServiceError::MissingCheckpointLocation { .. }
+            | RuntimeServiceError::UnknownRuntimeHandler { .. }
+            | RuntimeServiceError::InvalidPodCidr { .. }
+            | RuntimeServiceError::InvalidContainerConfig { .. } => {
+                Status::invalid_argument(msg)
+            }
+            RuntimeServiceError::SandboxNotExited { .. }
+            | RuntimeServiceError::ContainerNotRunning { .. }
+            | RuntimeServiceError::NoContainerLog { .. }
+            | RuntimeServiceError::VmsDisabled { .. }
+            | RuntimeServiceError::Cordoned(_)
+            | RuntimeServiceError::Hooks(_) => Status::failed_precondition(msg),
+            RuntimeServiceError::StartError { .. }
+            | RuntimeServiceError::DeleteError { .. }
+            | RuntimeServiceError::CreateError { .. }
+            | RuntimeServiceError::KillError { .. }
+            | RuntimeServiceError::NetworkError { .. }
+            | RuntimeServiceError::PodCellError { .. }
+            | RuntimeServiceError::DnsError { .. }
+            | RuntimeServiceError::RuntimeConfigError { .. }
+            | RuntimeServiceError::LogError { .. }
+            | RuntimeServiceError::UpdateResourcesError { .. }
+            | RuntimeServiceError::CheckpointError { .. }
+            | RuntimeServiceError::RestoreError { .. }
+            | RuntimeServiceError::VmPodError { .. }
+            | RuntimeServiceError::ExecError { .. } => Status::internal(msg),
+            RuntimeServiceError::ExecTimeout { .. } => {
+                Status::deadline_exceeded(msg)
             }
-            RuntimeServiceError::KillError { .. } => Status::internal(msg),
+            RuntimeServiceError::Volume(e) => match e {
+                VolumeError::NotAbsolute { .. } => {
+                    Status::invalid_argument(msg)
+                }
+                VolumeError::Io { .. } => Status::internal(msg),
+            },
+            RuntimeServiceError::Image(e) => e.into(),
             RuntimeServiceError::ClientError(e) => match e {
                 ClientError::ConnectionError(_) => Status::unavailable(msg),
                 ClientError::Other(_) => Status::unknown(msg),
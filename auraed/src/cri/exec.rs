@@ -0,0 +1,315 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Processes run by the Exec call of the CRI runtime service, in a running
+//! container.
+//!
+//! Like `runc exec`, the process joins the cgroup and the namespaces of the
+//! init process of the container and gets its environment, and then the
+//! [ExecContext] of the container. To join the PID namespace, which only
+//! applies to the children of a process, the process forks once more before
+//! exec and its parent passes on its exit status.
+
+use super::exec_context::ExecContext;
+use nix::{
+    pty::{openpty, Winsize},
+    sched::{setns, CloneFlags},
+};
+use std::{
+    fs::File,
+    io::{self, Write},
+    os::fd::{AsRawFd, OwnedFd},
+    process::{ExitStatus, Stdio},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    process::{Child, Command},
+};
+
+/// Most output of each stream kept by [ExecProcess::output], as other
+/// runtimes keep for ExecSync
+const MAX_OUTPUT: u64 = 16 * 1024 * 1024;
+
+/// Namespaces joined by the process, after those of the [ExecContext], the
+/// mount namespace last since it changes the root of the process.
+const NAMESPACES: [(&str, CloneFlags); 5] = [
+    ("ipc", CloneFlags::CLONE_NEWIPC),
+    ("uts", CloneFlags::CLONE_NEWUTS),
+    ("net", CloneFlags::CLONE_NEWNET),
+    ("pid", CloneFlags::CLONE_NEWPID),
+    ("mnt", CloneFlags::CLONE_NEWNS),
+];
+
+/// A command to run in a container, as requested by an Exec call.
+#[derive(Debug, Clone)]
+pub(crate) struct Exec {
+    /// PID of the init process of the container
+    pub pid: i32,
+    pub cmd: Vec<String>,
+    /// Run the command on a terminal, its stderr is then merged into stdout
+    pub tty: bool,
+    pub stdin: bool,
+    pub stdout: bool,
+    pub stderr: bool,
+    pub context: ExecContext,
+}
+
+type Reader = Box<dyn AsyncRead + Send + Unpin>;
+type Writer = Box<dyn AsyncWrite + Send + Unpin>;
+
+/// A process spawned for an [Exec].
+#[derive(Debug)]
+pub(crate) struct ExecProcess {
+    child: Child,
+    /// The master side of the terminal of the process, if any
+    terminal: Option<OwnedFd>,
+}
+
+/// The stdio of an [ExecProcess], None for the streams not requested.
+pub(crate) struct ExecStdio {
+    pub stdin: Option<Writer>,
+    pub stdout: Option<Reader>,
+    pub stderr: Option<Reader>,
+}
+
+/// The output of an [Exec] run to completion, see [ExecProcess::output].
+#[derive(Debug)]
+pub(crate) struct ExecOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    pub exit_code: i32,
+}
+
+impl ExecProcess {
+    pub fn spawn(exec: &Exec) -> io::Result<(Self, ExecStdio)> {
+        let Some((program, args)) = exec.cmd.split_first() else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "missing command",
+            ));
+        };
+
+        let proc = format!("/proc/{}", exec.pid);
+        let namespaces = exec
+            .context
+            .namespaces()
+            .iter()
+            .chain(&NAMESPACES)
+            .map(|(name, flag)| {
+                Ok((File::open(format!("{proc}/ns/{name}"))?, *flag))
+            })
+            .collect::<io::Result<Vec<_>>>()?;
+        let cgroup = cgroup_procs(exec.pid)?;
+        let environ = std::fs::read(format!("{proc}/environ"))?;
+
+        let mut command = Command::new(program);
+        let _ = command.args(args).env_clear().kill_on_drop(true);
+        for var in environ.split(|b| *b == 0) {
+            let var = String::from_utf8_lossy(var);
+            if let Some((key, value)) = var.split_once('=') {
+                let _ = command.env(key, value);
+            }
+        }
+
+        let terminal = if exec.tty {
+            let pty = openpty(None, None)?;
+            let slave = File::from(pty.slave);
+            let _ = command
+                .stdin(slave.try_clone()?)
+                .stdout(slave.try_clone()?)
+                .stderr(slave);
+            Some(pty.master)
+        } else {
+            let stdio = |requested| {
+                if requested {
+                    Stdio::piped()
+                } else {
+                    Stdio::null()
+                }
+            };
+            let _ = command
+                .stdin(stdio(exec.stdin))
+                .stdout(stdio(exec.stdout))
+                .stderr(stdio(exec.stderr));
+            None
+        };
+
+        let tty = exec.tty;
+        let context = exec.context.clone();
+        // SAFETY: only async-signal-safe calls between fork and exec
+        unsafe {
+            let _ = command
+                .pre_exec(move || enter(&cgroup, &namespaces, tty, &context));
+        }
+        let mut child = command.spawn()?;
+
+        let stdio = match &terminal {
+            Some(master) => {
+                let master = File::from(master.try_clone()?);
+                let output = tokio::fs::File::from_std(master.try_clone()?);
+                ExecStdio {
+                    stdin: exec.stdin.then(|| {
+                        Box::new(tokio::fs::File::from_std(master)) as Writer
+                    }),
+                    stdout: Some(Box::new(output)),
+                    stderr: None,
+                }
+            }
+            None => ExecStdio {
+                stdin: child.stdin.take().map(|s| Box::new(s) as Writer),
+                stdout: child.stdout.take().map(|s| Box::new(s) as Reader),
+                stderr: child.stderr.take().map(|s| Box::new(s) as Reader),
+            },
+        };
+        Ok((Self { child, terminal }, stdio))
+    }
+
+    /// Runs `exec` to completion and returns its output, keeping at most
+    /// [MAX_OUTPUT] bytes of each stream. Returns None if it ran past the
+    /// `timeout`, in which case it is killed.
+    pub async fn output(
+        exec: &Exec,
+        timeout: Option<Duration>,
+    ) -> io::Result<Option<ExecOutput>> {
+        let (mut process, stdio) = Self::spawn(exec)?;
+        let output = async {
+            tokio::try_join!(
+                read_output(stdio.stdout),
+                read_output(stdio.stderr),
+                process.wait(),
+            )
+        };
+        let output = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, output).await,
+            None => Ok(output.await),
+        };
+        // The process is killed on drop
+        let Ok(output) = output else {
+            return Ok(None);
+        };
+        let (stdout, stderr, status) = output?;
+        Ok(Some(ExecOutput {
+            stdout,
+            stderr,
+            exit_code: status.code().unwrap_or(-1),
+        }))
+    }
+
+    /// Resizes the terminal of the process, if it has one.
+    pub fn resize(&self, width: u16, height: u16) -> io::Result<()> {
+        let Some(terminal) = &self.terminal else {
+            return Ok(());
+        };
+        let size = Winsize {
+            ws_row: height,
+            ws_col: width,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        // SAFETY: TIOCSWINSZ only reads the winsize passed
+        let res = unsafe {
+            libc::ioctl(terminal.as_raw_fd(), libc::TIOCSWINSZ, &size)
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub async fn wait(&mut self) -> io::Result<ExitStatus> {
+        self.child.wait().await
+    }
+
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.child.start_kill()
+    }
+}
+
+/// Reads `stream` to the end, keeping at most [MAX_OUTPUT] bytes of it.
+async fn read_output(stream: Option<Reader>) -> io::Result<Vec<u8>> {
+    let mut output = vec![];
+    let Some(mut stream) = stream else {
+        return Ok(output);
+    };
+    let _ = (&mut stream).take(MAX_OUTPUT).read_to_end(&mut output).await?;
+    // The rest is discarded, so the process does not block writing it
+    let _ = tokio::io::copy(&mut stream, &mut tokio::io::sink()).await?;
+    Ok(output)
+}
+
+/// Opens the `cgroup.procs` of the cgroup of the process `pid`.
+fn cgroup_procs(pid: i32) -> io::Result<File> {
+    let cgroups = std::fs::read_to_string(format!("/proc/{pid}/cgroup"))?;
+    let path = cgroups
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "no cgroup v2 of process")
+        })?;
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(format!("/sys/fs/cgroup{path}/cgroup.procs"))
+}
+
+/// Moves the forked process into the `cgroup` and `namespaces` of the
+/// container and forks the process that execs, in the `context` of the
+/// container.
+fn enter(
+    cgroup: &File,
+    namespaces: &[(File, CloneFlags)],
+    tty: bool,
+    context: &ExecContext,
+) -> io::Result<()> {
+    // "0" is the process writing it
+    (&*cgroup).write_all(b"0")?;
+    for (namespace, flag) in namespaces {
+        setns(namespace, *flag)?;
+    }
+
+    // SAFETY: the forked process is single threaded
+    unsafe {
+        // auraed may ignore SIGCHLD, which would reap the child below
+        let _ = libc::signal(libc::SIGCHLD, libc::SIG_DFL);
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            child => {
+                let mut status = 0;
+                while libc::waitpid(child, &mut status, 0) == -1 {
+                    if io::Error::last_os_error().kind()
+                        != io::ErrorKind::Interrupted
+                    {
+                        libc::_exit(127);
+                    }
+                }
+                if libc::WIFSIGNALED(status) {
+                    libc::_exit(128 + libc::WTERMSIG(status));
+                }
+                libc::_exit(libc::WEXITSTATUS(status));
+            }
+        }
+        // dies with its parent, which is what auraed kills
+        if libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if tty
+            && (libc::setsid() == -1
+                || libc::ioctl(0, libc::TIOCSCTTY, 0) == -1)
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    context.apply()
+}
@@ -0,0 +1,645 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! The context of a container applied to the processes Exec runs in it, as
+//! `runc exec` does: the user, capabilities, no_new_privs flag, working
+//! directory and AppArmor profile of the `process` of its OCI spec, its
+//! seccomp profile, and its user and cgroup namespaces.
+//!
+//! The seccomp filters seccompiler compiles take one action on a match, so
+//! a profile is compiled to a stack of filters, of which the kernel takes
+//! the action with the highest precedence: one that allows the syscalls of
+//! the rules not taking the default action and takes the default action on
+//! the others, and one per action of the rules that takes it on their
+//! syscalls and allows the others.
+
+use nix::sched::CloneFlags;
+use oci_spec::runtime::{
+    Capabilities, Capability, LinuxCapabilities, LinuxNamespaceType,
+    LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArg, LinuxSeccompOperator,
+    Spec,
+};
+use seccompiler::{BpfProgram, TargetArch};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    ffi::{CStr, CString, NulError},
+    io,
+    os::unix::ffi::OsStrExt,
+    str::FromStr,
+};
+use thiserror::Error;
+
+/// Where AppArmor takes the profile to switch to on exec from, and where
+/// kernels without the per-LSM directories of /proc take it from.
+const APPARMOR_EXEC: [&CStr; 2] =
+    [c"/proc/self/attr/apparmor/exec", c"/proc/self/attr/exec"];
+/// Longest BPF program the kernel loads
+const BPF_MAXINSNS: usize = 4096;
+/// Of the capability sets passed to capset
+const LINUX_CAPABILITY_VERSION_3: u32 = 0x2008_0522;
+
+#[derive(Debug, Error)]
+pub(crate) enum ExecContextError {
+    #[error("unknown capability '{0}'")]
+    UnknownCapability(String),
+    #[error("seccomp action {0:?} is not supported")]
+    UnsupportedSeccompAction(LinuxSeccompAction),
+    #[error("invalid seccomp profile: {0}")]
+    InvalidSeccompProfile(String),
+    #[error(transparent)]
+    Nul(#[from] NulError),
+}
+
+/// How a process Exec runs in a container is set up once it joined the
+/// namespaces of the container, see [ExecContext::apply].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ExecContext {
+    user_namespace: bool,
+    cgroup_namespace: bool,
+    uid: libc::uid_t,
+    gid: libc::gid_t,
+    additional_gids: Vec<libc::gid_t>,
+    /// None to keep the capabilities of auraed
+    capabilities: Option<CapabilitySets>,
+    no_new_privileges: bool,
+    /// Written to switch to the AppArmor profile on exec
+    apparmor_profile: Option<CString>,
+    cwd: Option<CString>,
+    /// Installed in order
+    seccomp: Vec<BpfProgram>,
+}
+
+impl ExecContext {
+    /// The context of the processes run in the container of the OCI `spec`.
+    pub(crate) fn from_spec(spec: &Spec) -> Result<Self, ExecContextError> {
+        let mut context = Self::default();
+        if let Some(linux) = spec.linux() {
+            let namespaces = linux.namespaces().as_deref().unwrap_or_default();
+            let has = |typ| namespaces.iter().any(|ns| ns.typ() == typ);
+            context.user_namespace = has(LinuxNamespaceType::User);
+            context.cgroup_namespace = has(LinuxNamespaceType::Cgroup);
+            if let Some(seccomp) = linux.seccomp() {
+                context.seccomp = seccomp_filters(seccomp)?;
+            }
+        }
+
+        let Some(process) = spec.process() else {
+            return Ok(context);
+        };
+        let user = process.user();
+        context.uid = user.uid();
+        context.gid = user.gid();
+        context.additional_gids =
+            user.additional_gids().clone().unwrap_or_default();
+        context.capabilities = process
+            .capabilities()
+            .as_ref()
+            .map(CapabilitySets::new)
+            .transpose()?;
+        context.no_new_privileges =
+            process.no_new_privileges().unwrap_or(false);
+        context.apparmor_profile = match process.apparmor_profile() {
+            Some(profile) if apparmor_enabled() => {
+                Some(CString::new(format!("exec {profile}"))?)
+            }
+            _ => None,
+        };
+        context.cwd = Some(CString::new(process.cwd().as_os_str().as_bytes())?);
+        Ok(context)
+    }
+
+    /// The namespaces of the container joined besides those of every
+    /// container, the user namespace first as it owns the others.
+    pub(crate) fn namespaces(&self) -> Vec<(&'static str, CloneFlags)> {
+        let mut namespaces = vec![];
+        if self.user_namespace {
+            namespaces.push(("user", CloneFlags::CLONE_NEWUSER));
+        }
+        if self.cgroup_namespace {
+            namespaces.push(("cgroup", CloneFlags::CLONE_NEWCGROUP));
+        }
+        namespaces
+    }
+
+    /// Sets up the calling process, forked to exec in the namespaces of the
+    /// container. Only makes async-signal-safe calls.
+    pub(crate) fn apply(&self) -> io::Result<()> {
+        if let Some(profile) = &self.apparmor_profile {
+            apply_apparmor_profile(profile)?;
+        }
+        if let Some(cwd) = &self.cwd {
+            // SAFETY: cwd is a C string
+            check(unsafe { libc::chdir(cwd.as_ptr()) })?;
+        }
+        if self.no_new_privileges {
+            // SAFETY: PR_SET_NO_NEW_PRIVS takes no pointers
+            check(unsafe {
+                libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0)
+            })?;
+        } else {
+            // Without no_new_privs, installing filters takes CAP_SYS_ADMIN,
+            // which the container may not keep
+            self.install_seccomp()?;
+        }
+
+        if let Some(capabilities) = &self.capabilities {
+            capabilities.drop_bounding()?;
+            // SAFETY: PR_SET_KEEPCAPS takes no pointers
+            check(unsafe { libc::prctl(libc::PR_SET_KEEPCAPS, 1, 0, 0, 0) })?;
+        }
+        // Supplementary groups can only be set in a user namespace allowed to
+        if !self.user_namespace || !self.additional_gids.is_empty() {
+            // SAFETY: the groups are read from additional_gids
+            check(unsafe {
+                libc::setgroups(
+                    self.additional_gids.len(),
+                    self.additional_gids.as_ptr(),
+                )
+            })?;
+        }
+        // SAFETY: setgid and setuid take no pointers
+        check(unsafe { libc::setgid(self.gid) })?;
+        check(unsafe { libc::setuid(self.uid) })?;
+        if let Some(capabilities) = &self.capabilities {
+            capabilities.set()?;
+        }
+        // Changing the credentials cleared the parent-death signal
+        // SAFETY: PR_SET_PDEATHSIG takes no pointers
+        check(unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) })?;
+
+        if self.no_new_privileges {
+            self.install_seccomp()?;
+        }
+        Ok(())
+    }
+
+    fn install_seccomp(&self) -> io::Result<()> {
+        for filter in &self.seccomp {
+            let program = libc::sock_fprog {
+                // at most BPF_MAXINSNS, see seccomp_filters
+                len: filter.len() as libc::c_ushort,
+                filter: filter.as_ptr().cast_mut().cast(),
+            };
+            // SAFETY: seccompiler's sock_filter has the layout of the
+            // kernel's, which copies the program
+            let res = unsafe {
+                libc::syscall(
+                    libc::SYS_seccomp,
+                    libc::SECCOMP_SET_MODE_FILTER,
+                    0,
+                    std::ptr::addr_of!(program),
+                )
+            };
+            if res == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The capability sets of a process as bit masks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct CapabilitySets {
+    bounding: u64,
+    effective: u64,
+    permitted: u64,
+    inheritable: u64,
+    ambient: u64,
+}
+
+#[repr(C)]
+struct CapUserHeader {
+    version: u32,
+    pid: libc::c_int,
+}
+
+#[repr(C)]
+struct CapUserData {
+    effective: u32,
+    permitted: u32,
+    inheritable: u32,
+}
+
+impl CapabilitySets {
+    fn new(capabilities: &LinuxCapabilities) -> Result<Self, ExecContextError> {
+        let mask = |set: &Option<Capabilities>| {
+            set.iter().flatten().try_fold(0, |bits, capability| {
+                Ok::<_, ExecContextError>(bits | capability_bit(capability)?)
+            })
+        };
+        Ok(Self {
+            bounding: mask(capabilities.bounding())?,
+            effective: mask(capabilities.effective())?,
+            permitted: mask(capabilities.permitted())?,
+            inheritable: mask(capabilities.inheritable())?,
+            ambient: mask(capabilities.ambient())?,
+        })
+    }
+
+    /// Drops the capabilities not in the bounding set from the bounding set
+    /// of the calling process.
+    fn drop_bounding(&self) -> io::Result<()> {
+        for capability in 0..64u32 {
+            if self.bounding & (1 << capability) != 0 {
+                continue;
+            }
+            // SAFETY: PR_CAPBSET_DROP takes no pointers
+            let res = unsafe {
+                libc::prctl(
+                    libc::PR_CAPBSET_DROP,
+                    libc::c_ulong::from(capability),
+                    0,
+                    0,
+                    0,
+                )
+            };
+            if res == -1 {
+                let error = io::Error::last_os_error();
+                // past the last capability of the kernel
+                if error.raw_os_error() == Some(libc::EINVAL) {
+                    break;
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the effective, permitted, inheritable and ambient sets of the
+    /// calling process.
+    fn set(&self) -> io::Result<()> {
+        let header =
+            CapUserHeader { version: LINUX_CAPABILITY_VERSION_3, pid: 0 };
+        let data = [0, 32].map(|shift| CapUserData {
+            effective: (self.effective >> shift) as u32,
+            permitted: (self.permitted >> shift) as u32,
+            inheritable: (self.inheritable >> shift) as u32,
+        });
+        // SAFETY: capset reads the header and the two sets of data
+        let res = unsafe {
+            libc::syscall(
+                libc::SYS_capset,
+                std::ptr::addr_of!(header),
+                data.as_ptr(),
+            )
+        };
+        if res == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: PR_CAP_AMBIENT takes no pointers
+        check(unsafe {
+            libc::prctl(
+                libc::PR_CAP_AMBIENT,
+                libc::PR_CAP_AMBIENT_CLEAR_ALL,
+                0,
+                0,
+                0,
+            )
+        })?;
+        for capability in 0..64u32 {
+            if self.ambient & (1 << capability) == 0 {
+                continue;
+            }
+            // SAFETY: PR_CAP_AMBIENT takes no pointers
+            check(unsafe {
+                libc::prctl(
+                    libc::PR_CAP_AMBIENT,
+                    libc::PR_CAP_AMBIENT_RAISE,
+                    libc::c_ulong::from(capability),
+                    0,
+                    0,
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// The bit of `capability` in the capability sets of the kernel.
+fn capability_bit(capability: &Capability) -> Result<u64, ExecContextError> {
+    let unknown =
+        || ExecContextError::UnknownCapability(format!("{capability:?}"));
+    // Named as the kernel names them, CAP_ prefix included
+    let name = serde_json::to_value(capability).map_err(|_| unknown())?;
+    let name = name.as_str().ok_or_else(unknown)?;
+    caps::Capability::from_str(name)
+        .map(|capability| capability.bitmask())
+        .map_err(|_| unknown())
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn apparmor_enabled() -> bool {
+    std::fs::read_to_string("/sys/module/apparmor/parameters/enabled")
+        .is_ok_and(|enabled| enabled.starts_with('Y'))
+}
+
+/// Switches the calling process to the AppArmor `profile` on exec. Only
+/// makes async-signal-safe calls.
+fn apply_apparmor_profile(profile: &CStr) -> io::Result<()> {
+    for path in APPARMOR_EXEC {
+        // SAFETY: path is a C string
+        let fd = unsafe {
+            libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC)
+        };
+        if fd == -1 {
+            let error = io::Error::last_os_error();
+            if error.raw_os_error() == Some(libc::ENOENT) {
+                continue;
+            }
+            return Err(error);
+        }
+        let profile = profile.to_bytes();
+        // SAFETY: writes the bytes of profile to the file opened above
+        let written =
+            unsafe { libc::write(fd, profile.as_ptr().cast(), profile.len()) };
+        let error = io::Error::last_os_error();
+        // SAFETY: fd was opened above and is not used after
+        let _ = unsafe { libc::close(fd) };
+        if written == -1 {
+            return Err(error);
+        }
+        return Ok(());
+    }
+    Err(io::Error::from_raw_os_error(libc::ENOENT))
+}
+
+/// The rules of one seccomp filter by syscall: None for a syscall matched
+/// whatever its arguments, else the conditions of each of its rules.
+#[derive(Debug, Default)]
+struct Rules(BTreeMap<String, Option<Vec<Value>>>);
+
+impl Rules {
+    fn add(&mut self, syscall: &str, conditions: Option<&[Value]>) {
+        let rules = self.0.entry(syscall.to_string()).or_insert(Some(vec![]));
+        match (rules, conditions) {
+            (Some(rules), Some(conditions)) if !conditions.is_empty() => {
+                rules.push(Value::from(conditions))
+            }
+            (rules, _) => *rules = None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The filter taking `match_action` on the syscalls of the rules and
+    /// `mismatch_action` on the others, in seccompiler's JSON format.
+    fn filter(&self, mismatch_action: &Value, match_action: &Value) -> Value {
+        let rules: Vec<Value> = self
+            .0
+            .iter()
+            .flat_map(|(syscall, rules)| match rules {
+                None => vec![json!({ "syscall": syscall })],
+                Some(rules) => rules
+                    .iter()
+                    .map(|args| json!({ "syscall": syscall, "args": args }))
+                    .collect(),
+            })
+            .collect();
+        json!({
+            "mismatch_action": mismatch_action,
+            "match_action": match_action,
+            "filter": rules,
+        })
+    }
+}
+
+/// Compiles the seccomp profile to a stack of filters, see the module
+/// documentation.
+fn seccomp_filters(
+    seccomp: &LinuxSeccomp,
+) -> Result<Vec<BpfProgram>, ExecContextError> {
+    let invalid = |e: seccompiler::Error| {
+        ExecContextError::InvalidSeccompProfile(e.to_string())
+    };
+    let arch = TargetArch::try_from(std::env::consts::ARCH)
+        .map_err(|e| invalid(e.into()))?;
+    let allow = json!("allow");
+    let default_action =
+        action(seccomp.default_action(), seccomp.default_errno_ret())?;
+
+    let mut not_default = Rules::default();
+    let mut by_action: Vec<(Value, Rules)> = vec![];
+    for syscall in seccomp.syscalls().as_deref().unwrap_or_default() {
+        let action = action(syscall.action(), syscall.errno_ret())?;
+        let conditions = syscall
+            .args()
+            .as_ref()
+            .map(|args| args.iter().map(condition).collect::<Vec<_>>());
+        // The allowed syscalls are those of the first filter
+        let index = (action != allow).then(|| {
+            by_action.iter().position(|(a, _)| *a == action).unwrap_or_else(
+                || {
+                    by_action.push((action.clone(), Rules::default()));
+                    by_action.len() - 1
+                },
+            )
+        });
+        // Profiles name the syscalls of every architecture
+        let names =
+            syscall.names().iter().filter(|name| is_syscall(name, arch));
+        for name in names {
+            if action != default_action {
+                not_default.add(name, conditions.as_deref());
+            }
+            if let Some(index) = index {
+                by_action[index].1.add(name, conditions.as_deref());
+            }
+        }
+    }
+
+    let mut filters = vec![];
+    if default_action != allow {
+        filters.push(not_default.filter(&default_action, &allow));
+    }
+    for (action, rules) in &by_action {
+        if !rules.is_empty() {
+            filters.push(rules.filter(&allow, action));
+        }
+    }
+    filters
+        .into_iter()
+        .map(|filter| {
+            let filters = json!({ "filter": filter }).to_string();
+            let mut programs =
+                seccompiler::compile_from_json(filters.as_bytes(), arch)
+                    .map_err(invalid)?;
+            let program = programs.remove("filter").unwrap_or_default();
+            if program.len() > BPF_MAXINSNS {
+                return Err(ExecContextError::InvalidSeccompProfile(format!(
+                    "a filter has {} instructions, more than the kernel loads",
+                    program.len()
+                )));
+            }
+            Ok(program)
+        })
+        .collect()
+}
+
+/// Whether seccompiler knows the syscall `name` of `arch`.
+fn is_syscall(name: &str, arch: TargetArch) -> bool {
+    let probe = json!({
+        "probe": {
+            "mismatch_action": "allow",
+            "match_action": "log",
+            "filter": [{ "syscall": name }],
+        }
+    });
+    seccompiler::compile_from_json(probe.to_string().as_bytes(), arch).is_ok()
+}
+
+/// The seccompiler action of a seccomp `action` of an OCI spec.
+fn action(
+    action: LinuxSeccompAction,
+    errno_ret: Option<u32>,
+) -> Result<Value, ExecContextError> {
+    let errno = errno_ret.unwrap_or(libc::EPERM as u32);
+    Ok(match action {
+        LinuxSeccompAction::ScmpActAllow => json!("allow"),
+        LinuxSeccompAction::ScmpActErrno => json!({ "errno": errno }),
+        LinuxSeccompAction::ScmpActKill => json!("kill_thread"),
+        LinuxSeccompAction::ScmpActKillProcess => json!("kill_process"),
+        LinuxSeccompAction::ScmpActTrap => json!("trap"),
+        LinuxSeccompAction::ScmpActTrace => json!({ "trace": errno }),
+        LinuxSeccompAction::ScmpActLog => json!("log"),
+        LinuxSeccompAction::ScmpActNotify => {
+            return Err(ExecContextError::UnsupportedSeccompAction(action))
+        }
+    })
+}
+
+/// The seccompiler condition of an argument of a seccomp rule of an OCI
+/// spec. A masked comparison compares the argument masked with the value
+/// to the second value.
+fn condition(arg: &LinuxSeccompArg) -> Value {
+    let (op, value) = match arg.op() {
+        LinuxSeccompOperator::ScmpCmpNe => (json!("ne"), arg.value()),
+        LinuxSeccompOperator::ScmpCmpLt => (json!("lt"), arg.value()),
+        LinuxSeccompOperator::ScmpCmpLe => (json!("le"), arg.value()),
+        LinuxSeccompOperator::ScmpCmpEq => (json!("eq"), arg.value()),
+        LinuxSeccompOperator::ScmpCmpGe => (json!("ge"), arg.value()),
+        LinuxSeccompOperator::ScmpCmpGt => (json!("gt"), arg.value()),
+        LinuxSeccompOperator::ScmpCmpMaskedEq => {
+            (json!({ "masked_eq": arg.value() }), arg.value_two().unwrap_or(0))
+        }
+    };
+    json!({ "index": arg.index(), "type": "qword", "op": op, "val": value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::runtime::{
+        LinuxCapabilitiesBuilder, LinuxSeccompArgBuilder, LinuxSeccompBuilder,
+        LinuxSyscallBuilder,
+    };
+    use std::collections::HashSet;
+
+    fn profile(
+        default_action: LinuxSeccompAction,
+        rules: &[(LinuxSeccompAction, &[&str])],
+    ) -> LinuxSeccomp {
+        let syscalls = rules
+            .iter()
+            .map(|(action, names)| {
+                LinuxSyscallBuilder::default()
+                    .names(
+                        names.iter().map(|n| n.to_string()).collect::<Vec<_>>(),
+                    )
+                    .action(*action)
+                    .build()
+                    .expect("syscall rule")
+            })
+            .collect::<Vec<_>>();
+        LinuxSeccompBuilder::default()
+            .default_action(default_action)
+            .syscalls(syscalls)
+            .build()
+            .expect("seccomp profile")
+    }
+
+    #[test]
+    fn deny_list_is_one_filter() {
+        let seccomp = profile(
+            LinuxSeccompAction::ScmpActAllow,
+            &[(LinuxSeccompAction::ScmpActErrno, &["reboot", "not_a_syscall"])],
+        );
+        let filters = seccomp_filters(&seccomp).expect("compiled");
+        assert_eq!(filters.len(), 1);
+    }
+
+    #[test]
+    fn allow_list_is_stacked_with_the_actions_of_its_rules() {
+        let seccomp = profile(
+            LinuxSeccompAction::ScmpActErrno,
+            &[
+                (LinuxSeccompAction::ScmpActAllow, &["read", "write"]),
+                (LinuxSeccompAction::ScmpActLog, &["openat"]),
+            ],
+        );
+        let filters = seccomp_filters(&seccomp).expect("compiled");
+        assert_eq!(filters.len(), 2);
+    }
+
+    #[test]
+    fn notify_is_rejected() {
+        let seccomp = profile(
+            LinuxSeccompAction::ScmpActAllow,
+            &[(LinuxSeccompAction::ScmpActNotify, &["mount"])],
+        );
+        assert!(matches!(
+            seccomp_filters(&seccomp),
+            Err(ExecContextError::UnsupportedSeccompAction(_))
+        ));
+    }
+
+    #[test]
+    fn syscalls_matched_without_conditions_ignore_the_conditions() {
+        let arg = LinuxSeccompArgBuilder::default()
+            .index(0usize)
+            .value(1u64)
+            .op(LinuxSeccompOperator::ScmpCmpEq)
+            .build()
+            .expect("seccomp arg");
+        let mut rules = Rules::default();
+        rules.add("personality", Some(&[condition(&arg)]));
+        rules.add("personality", None);
+        rules.add("personality", Some(&[condition(&arg)]));
+        assert_eq!(rules.0.get("personality"), Some(&None));
+    }
+
+    #[test]
+    fn capabilities_are_bit_masks() {
+        let capabilities = LinuxCapabilitiesBuilder::default()
+            .bounding(HashSet::from([Capability::Chown, Capability::NetAdmin]))
+            .effective(HashSet::from([Capability::Chown]))
+            .ambient(HashSet::new())
+            .build()
+            .expect("capabilities");
+        let sets = CapabilitySets::new(&capabilities).expect("sets");
+        assert_eq!(sets.bounding, 1 << 0 | 1 << 12);
+        assert_eq!(sets.effective, 1 << 0);
+        assert_eq!(sets.ambient, 0);
+    }
+}
@@ -0,0 +1,151 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Lifecycle hooks of the OCI runtime spec run around pod sandboxes.
+//!
+//! The hooks of the node are read from a JSON file in the format of the
+//! `hooks` of an OCI spec, e.g.
+//!
+//! ```json
+//! {
+//!   "createRuntime": [{"path": "/usr/libexec/setup-net", "timeout": 5}],
+//!   "poststop": [{"path": "/usr/libexec/teardown-net"}]
+//! }
+//! ```
+//!
+//! and added to the spec of each sandbox by the [AuraeOCIBuilder], the
+//! container runtime runs them. The file is read for each sandbox, so
+//! hooks can be changed without restarting auraed. A failing prestart,
+//! createRuntime, createContainer or startContainer hook fails the
+//! sandbox, poststart and poststop hooks only log their failures.
+//!
+//! [AuraeOCIBuilder]: crate::cri::oci::AuraeOCIBuilder
+
+use oci_spec::runtime::{Hook, Hooks};
+use std::{io::ErrorKind, path::Path};
+use thiserror::Error;
+
+/// Seconds a hook without a timeout of its own may run, so that a stuck
+/// hook does not hang the sandbox forever.
+const DEFAULT_TIMEOUT: i64 = 30;
+
+#[derive(Debug, Error)]
+pub(crate) enum HooksError {
+    #[error("failed to read OCI hooks config '{path}': {source}")]
+    Read { path: String, source: std::io::Error },
+    #[error("invalid OCI hooks config '{path}': {reason}")]
+    InvalidConfig { path: String, reason: String },
+}
+
+/// Reads the hooks configured in `path`, none if it does not exist.
+pub(crate) fn load(path: &Path) -> Result<Option<Hooks>, HooksError> {
+    match std::fs::read(path) {
+        Ok(contents) => parse(&contents).map(Some).map_err(|reason| {
+            HooksError::InvalidConfig {
+                path: path.display().to_string(),
+                reason,
+            }
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(source) => {
+            Err(HooksError::Read { path: path.display().to_string(), source })
+        }
+    }
+}
+
+fn parse(contents: &[u8]) -> Result<Hooks, String> {
+    let hooks: Hooks =
+        serde_json::from_slice(contents).map_err(|e| e.to_string())?;
+
+    let mut checked = Hooks::default();
+    let _ = checked
+        .set_prestart(check(hooks.prestart().clone())?)
+        .set_create_runtime(check(hooks.create_runtime().clone())?)
+        .set_create_container(check(hooks.create_container().clone())?)
+        .set_start_container(check(hooks.start_container().clone())?)
+        .set_poststart(check(hooks.poststart().clone())?)
+        .set_poststop(check(hooks.poststop().clone())?);
+    Ok(checked)
+}
+
+/// Checks `hooks` against the OCI runtime spec, and bounds the ones without
+/// a timeout by [DEFAULT_TIMEOUT].
+fn check(hooks: Option<Vec<Hook>>) -> Result<Option<Vec<Hook>>, String> {
+    let Some(hooks) = hooks else {
+        return Ok(None);
+    };
+    hooks
+        .into_iter()
+        .map(|mut hook| {
+            if !hook.path().is_absolute() {
+                return Err(format!(
+                    "hook path '{}' is not absolute",
+                    hook.path().display()
+                ));
+            }
+            match hook.timeout() {
+                Some(timeout) if timeout <= 0 => {
+                    return Err(format!(
+                        "timeout of hook '{}' is not positive",
+                        hook.path().display()
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    let _ = hook.set_timeout(Some(DEFAULT_TIMEOUT));
+                }
+            }
+            Ok(hook)
+        })
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_hooks_without_a_timeout() {
+        let hooks = parse(
+            br#"{
+                "createRuntime": [
+                    {"path": "/usr/libexec/setup-net", "timeout": 5},
+                    {"path": "/usr/libexec/setup-dev", "args": ["setup-dev"]}
+                ],
+                "poststop": [{"path": "/usr/libexec/teardown-net"}]
+            }"#,
+        )
+        .expect("valid config");
+
+        let create_runtime =
+            hooks.create_runtime().as_ref().expect("createRuntime hooks");
+        assert_eq!(create_runtime[0].timeout(), Some(5));
+        assert_eq!(create_runtime[1].timeout(), Some(DEFAULT_TIMEOUT));
+        assert_eq!(
+            hooks.poststop().as_ref().expect("poststop hooks")[0].timeout(),
+            Some(DEFAULT_TIMEOUT)
+        );
+        assert!(hooks.prestart().is_none());
+    }
+
+    #[test]
+    fn rejects_hooks_against_the_spec() {
+        assert!(parse(br#"{"prestart": [{"path": "setup-net"}]}"#).is_err());
+        assert!(parse(
+            br#"{"poststart": [{"path": "/usr/bin/true", "timeout": 0}]}"#
+        )
+        .is_err());
+    }
+}
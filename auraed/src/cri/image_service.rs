@@ -41,51 +41,292 @@
  *   limitations under the License.                                           *
  *                                                                            *
 \* -------------------------------------------------------------------------- */
+//! The CRI image service, backed by the image store of the node.
+//!
+//! The CRI identifies an image by the digest of its config. Images pulled by
+//! several references are listed once, with each reference as a repo tag and
+//! the digest of each manifest as a repo digest.
 
+use crate::images::{
+    self, distribution::Reference, ImageServiceError, StoredImage,
+};
 use proto::cri::{
-    image_service_server, ImageFsInfoRequest, ImageFsInfoResponse,
-    ImageStatusRequest, ImageStatusResponse, ListImagesRequest,
-    ListImagesResponse, PullImageRequest, PullImageResponse,
-    RemoveImageRequest, RemoveImageResponse,
+    image_service_server, FilesystemIdentifier, FilesystemUsage, Image,
+    ImageFsInfoRequest, ImageFsInfoResponse, ImageSpec, ImageStatusRequest,
+    ImageStatusResponse, ListImagesRequest, ListImagesResponse,
+    PullImageRequest, PullImageResponse, RemoveImageRequest,
+    RemoveImageResponse, UInt64Value,
 };
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tonic::{Request, Response, Status};
 
-pub struct ImageService {}
+type Result<T> = std::result::Result<T, ImageServiceError>;
+
+/// ImageService implements the CRI image service on top of the
+/// [images::ImageService] of the node, sharing its store and pull limits.
+#[derive(Debug, Clone)]
+pub struct ImageService {
+    images: images::ImageService,
+}
+
+impl ImageService {
+    pub(crate) fn new(images: images::ImageService) -> Self {
+        Self { images }
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn list_images(
+        &self,
+        request: ListImagesRequest,
+    ) -> Result<ListImagesResponse> {
+        let mut images = to_cri_images(&self.images.images()?);
+        if let Some(ImageSpec { image, .. }) =
+            request.filter.and_then(|filter| filter.image)
+        {
+            if !image.is_empty() {
+                images.retain(|candidate| matches(candidate, &image));
+            }
+        }
+        Ok(ListImagesResponse { images })
+    }
+
+    /// An image that was not pulled is not an error, its status is empty.
+    #[tracing::instrument(skip(self))]
+    async fn image_status(
+        &self,
+        request: ImageStatusRequest,
+    ) -> Result<ImageStatusResponse> {
+        let image = requested_image(request.image)?;
+        let image = to_cri_images(&self.images.images()?)
+            .into_iter()
+            .find(|candidate| matches(candidate, &image));
+        Ok(ImageStatusResponse { image, info: Default::default() })
+    }
+
+    /// Pulls the image from its registry, verifying the digest of each blob.
+    /// Returns the id of the image.
+    #[tracing::instrument(skip(self))]
+    async fn pull_image(
+        &self,
+        request: PullImageRequest,
+    ) -> Result<PullImageResponse> {
+        let image = requested_image(request.image)?;
+        let image = self.images.pull_image(&image).await?;
+        Ok(PullImageResponse { image_ref: image.id().into() })
+    }
+
+    /// Removes every reference to the image. Removing an image that was not
+    /// pulled succeeds, as the CRI requires.
+    #[tracing::instrument(skip(self))]
+    async fn remove_image(
+        &self,
+        request: RemoveImageRequest,
+    ) -> Result<RemoveImageResponse> {
+        let image = requested_image(request.image)?;
+        let stored = self.images.images()?;
+        let Some(removed) = to_cri_images(&stored)
+            .into_iter()
+            .find(|candidate| matches(candidate, &image))
+        else {
+            return Ok(RemoveImageResponse {});
+        };
+
+        for image in stored.iter().filter(|image| image.id() == removed.id) {
+            let _ = self.images.remove_image(&image.reference)?;
+        }
+        Ok(RemoveImageResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn image_fs_info(
+        &self,
+        _request: ImageFsInfoRequest,
+    ) -> Result<ImageFsInfoResponse> {
+        let (used_bytes, inodes_used) = self.images.usage()?;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as i64)
+            .unwrap_or_default();
+        Ok(ImageFsInfoResponse {
+            image_filesystems: vec![FilesystemUsage {
+                timestamp,
+                fs_id: Some(FilesystemIdentifier {
+                    mountpoint: self.images.root().display().to_string(),
+                }),
+                used_bytes: Some(UInt64Value { value: used_bytes }),
+                inodes_used: Some(UInt64Value { value: inodes_used }),
+            }],
+        })
+    }
+}
+
+fn requested_image(spec: Option<ImageSpec>) -> Result<String> {
+    match spec {
+        Some(ImageSpec { image, .. }) if !image.is_empty() => Ok(image),
+        _ => Err(ImageServiceError::InvalidReference { reference: "".into() }),
+    }
+}
+
+/// The stored images grouped by id, sorted by id.
+fn to_cri_images(stored: &[StoredImage]) -> Vec<Image> {
+    let mut images: BTreeMap<&str, Image> = BTreeMap::new();
+    for stored in stored {
+        let image = images.entry(stored.id()).or_insert_with(|| Image {
+            id: stored.id().into(),
+            size: stored.size(),
+            spec: Some(ImageSpec {
+                image: stored.id().into(),
+                annotations: Default::default(),
+            }),
+            ..Default::default()
+        });
+
+        let Ok(reference) = stored.reference.parse::<Reference>() else {
+            continue;
+        };
+        let repository =
+            format!("{}/{}", reference.registry(), reference.repository());
+        if let Some(tag) = reference.tag() {
+            let tag = format!("{repository}:{tag}");
+            if !image.repo_tags.contains(&tag) {
+                image.repo_tags.push(tag);
+            }
+        }
+        let digest = format!("{repository}@{}", stored.digest);
+        if !image.repo_digests.contains(&digest) {
+            image.repo_digests.push(digest);
+        }
+    }
+    images.into_values().collect()
+}
+
+/// Whether `image` is the image the kubelet asked for by id, tag or digest.
+fn matches(image: &Image, requested: &str) -> bool {
+    if image.id == requested
+        || image.id.strip_prefix("sha256:") == Some(requested)
+    {
+        return true;
+    }
+
+    let Ok(reference) = requested.parse::<Reference>() else {
+        return false;
+    };
+    let repository =
+        format!("{}/{}", reference.registry(), reference.repository());
+    match (reference.digest(), reference.tag()) {
+        (Some(digest), _) => {
+            image.repo_digests.contains(&format!("{repository}@{digest}"))
+        }
+        (None, Some(tag)) => {
+            image.repo_tags.contains(&format!("{repository}:{tag}"))
+        }
+        (None, None) => false,
+    }
+}
 
 #[tonic::async_trait]
 impl image_service_server::ImageService for ImageService {
     async fn list_images(
         &self,
-        _request: Request<ListImagesRequest>,
-    ) -> Result<Response<ListImagesResponse>, Status> {
-        todo!()
+        request: Request<ListImagesRequest>,
+    ) -> std::result::Result<Response<ListImagesResponse>, Status> {
+        Ok(Response::new(self.list_images(request.into_inner()).await?))
     }
 
     async fn image_status(
         &self,
-        _request: Request<ImageStatusRequest>,
-    ) -> Result<Response<ImageStatusResponse>, Status> {
-        todo!()
+        request: Request<ImageStatusRequest>,
+    ) -> std::result::Result<Response<ImageStatusResponse>, Status> {
+        Ok(Response::new(self.image_status(request.into_inner()).await?))
     }
 
     async fn pull_image(
         &self,
-        _request: Request<PullImageRequest>,
-    ) -> Result<Response<PullImageResponse>, Status> {
-        todo!()
+        request: Request<PullImageRequest>,
+    ) -> std::result::Result<Response<PullImageResponse>, Status> {
+        Ok(Response::new(self.pull_image(request.into_inner()).await?))
     }
 
     async fn remove_image(
         &self,
-        _request: Request<RemoveImageRequest>,
-    ) -> Result<Response<RemoveImageResponse>, Status> {
-        todo!()
+        request: Request<RemoveImageRequest>,
+    ) -> std::result::Result<Response<RemoveImageResponse>, Status> {
+        Ok(Response::new(self.remove_image(request.into_inner()).await?))
     }
 
     async fn image_fs_info(
         &self,
-        _request: Request<ImageFsInfoRequest>,
-    ) -> Result<Response<ImageFsInfoResponse>, Status> {
-        todo!()
+        request: Request<ImageFsInfoRequest>,
+    ) -> std::result::Result<Response<ImageFsInfoResponse>, Status> {
+        Ok(Response::new(self.image_fs_info(request.into_inner()).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::distribution::manifest::{
+        OciDescriptor, OciImageManifest,
+    };
+
+    fn stored(reference: &str, digest: char, config: char) -> StoredImage {
+        StoredImage {
+            reference: reference.into(),
+            digest: format!("sha256:{}", digest.to_string().repeat(64)),
+            manifest: OciImageManifest {
+                config: OciDescriptor {
+                    digest: format!("sha256:{}", config.to_string().repeat(64)),
+                    size: 10,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn images_are_listed_once_per_id() {
+        let images = to_cri_images(&[
+            stored("docker.io/library/busybox:1.36", 'a', 'c'),
+            stored("docker.io/library/busybox:latest", 'a', 'c'),
+            stored("docker.io/library/nginx:latest", 'b', 'd'),
+        ]);
+        assert_eq!(images.len(), 2);
+
+        let busybox = &images[0];
+        assert_eq!(busybox.id, format!("sha256:{}", "c".repeat(64)));
+        assert_eq!(
+            busybox.repo_tags,
+            [
+                "docker.io/library/busybox:1.36",
+                "docker.io/library/busybox:latest"
+            ]
+        );
+        assert_eq!(
+            busybox.repo_digests,
+            [format!("docker.io/library/busybox@sha256:{}", "a".repeat(64))]
+        );
+    }
+
+    #[test]
+    fn images_match_by_id_tag_or_digest() {
+        let images = to_cri_images(&[stored(
+            "docker.io/library/busybox:latest",
+            'a',
+            'c',
+        )]);
+        let busybox = &images[0];
+
+        assert!(matches(busybox, &format!("sha256:{}", "c".repeat(64))));
+        assert!(matches(busybox, &"c".repeat(64)));
+        assert!(matches(busybox, "busybox"));
+        assert!(matches(busybox, "docker.io/library/busybox:latest"));
+        assert!(matches(
+            busybox,
+            &format!("busybox@sha256:{}", "a".repeat(64))
+        ));
+        assert!(!matches(busybox, "busybox:1.36"));
+        assert!(!matches(busybox, "nginx"));
     }
-}
\ No newline at end of file
+}
@@ -42,10 +42,30 @@
  *                                                                            *
 \* -------------------------------------------------------------------------- */
 
+pub mod cni;
 pub mod image_service;
 pub mod oci;
 pub mod runtime_service;
 
+pub(crate) mod checkpoint;
+pub(crate) mod container_log;
+pub(crate) mod vm_pod;
+
+mod attach;
+mod dns;
 mod error;
+mod exec;
+mod exec_context;
+mod hooks;
+mod pod_cell;
+mod port_forward;
+mod resources;
 mod sandbox;
-mod sandbox_cache;
\ No newline at end of file
+mod sandbox_cache;
+mod sandbox_record;
+mod security_context;
+mod stats;
+mod streaming;
+mod validation;
+mod volumes;
+mod websocket;
\ No newline at end of file
@@ -42,26 +42,53 @@
  *                                                                            *
 \* -------------------------------------------------------------------------- */
 
+use super::security_context;
+use oci_spec::image::Config as ImageConfig;
 use oci_spec::runtime::{
-    Capability, LinuxBuilder, LinuxDeviceCgroupBuilder, LinuxNamespaceBuilder,
-    LinuxNamespaceType, LinuxResourcesBuilder, PosixRlimitBuilder,
-    PosixRlimitType,
+    Capability, Hooks, LinuxBuilder, LinuxDeviceCgroupBuilder,
+    LinuxNamespaceBuilder, LinuxNamespaceType, LinuxResourcesBuilder,
+    PosixRlimitBuilder, PosixRlimitType,
 };
 use oci_spec::runtime::{
-    LinuxCapabilitiesBuilder, MountBuilder, ProcessBuilder, RootBuilder, Spec,
-    SpecBuilder, UserBuilder,
+    LinuxCapabilitiesBuilder, Mount, MountBuilder, ProcessBuilder, RootBuilder,
+    Spec, SpecBuilder, UserBuilder,
 };
 use oci_spec::OciSpecError;
-use proto::cri::PodSandboxConfig;
+use proto::cri::{
+    ContainerConfig, LinuxContainerSecurityContext, PodSandboxConfig,
+};
 use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 
 pub struct AuraeOCIBuilder {
     spec_builder: SpecBuilder,
+    /// The container of a sandbox the spec is for, None for the init
+    /// container of a sandbox
+    tenant: Option<Tenant>,
+    /// The cgroup of the container relative to the cgroup root, the one
+    /// picked by the container runtime if None
+    cgroups_path: Option<PathBuf>,
+}
+
+/// How the spec of a container of a sandbox differs from the one of the
+/// init container.
+struct Tenant {
+    rootfs: PathBuf,
+    args: Vec<String>,
+    env: Vec<String>,
+    cwd: PathBuf,
+    /// The init process of the sandbox, whose network, IPC and UTS
+    /// namespaces the container joins
+    sandbox_pid: i32,
+    mounts: Vec<Mount>,
+    security_context: Option<LinuxContainerSecurityContext>,
 }
 
 impl AuraeOCIBuilder {
     pub fn new() -> AuraeOCIBuilder {
         AuraeOCIBuilder {
+            tenant: None,
+            cgroups_path: None,
             spec_builder: SpecBuilder::default()
                 .version("1.0.2-dev")
                 .root(
@@ -267,7 +294,275 @@ impl AuraeOCIBuilder {
         // Appends the current pod config to the SpecBuilder
         self
     }
+
+    /// Turns the spec into the one of the container of `config` in the
+    /// sandbox of the init process `sandbox_pid`, running from `rootfs`
+    /// with `mounts` added and the security context of `config` applied.
+    /// What `config` leaves unset is taken from the `image` config.
+    pub(crate) fn overload_container_config(
+        self,
+        config: &ContainerConfig,
+        image: Option<&ImageConfig>,
+        rootfs: PathBuf,
+        sandbox_pid: i32,
+        mounts: Vec<Mount>,
+    ) -> AuraeOCIBuilder {
+        let tenant = Tenant {
+            rootfs,
+            args: container_args(config, image),
+            env: container_env(config, image),
+            cwd: PathBuf::from(if !config.working_dir.is_empty() {
+                config.working_dir.as_str()
+            } else {
+                image
+                    .and_then(|image| image.working_dir().as_deref())
+                    .filter(|dir| !dir.is_empty())
+                    .unwrap_or("/")
+            }),
+            sandbox_pid,
+            mounts,
+            security_context: config
+                .linux
+                .as_ref()
+                .and_then(|linux| linux.security_context.clone()),
+        };
+        AuraeOCIBuilder { tenant: Some(tenant), ..self }
+    }
+
+    /// Places the container in the cgroup at `path`, relative to the cgroup
+    /// root, or in the default cgroup of the container runtime with None.
+    pub(crate) fn with_cgroups_path(
+        self,
+        path: Option<PathBuf>,
+    ) -> AuraeOCIBuilder {
+        AuraeOCIBuilder { cgroups_path: path, ..self }
+    }
+
+    /// Adds the lifecycle `hooks` to the spec, the container runtime runs
+    /// them around the container.
+    pub fn with_hooks(self, hooks: Option<Hooks>) -> AuraeOCIBuilder {
+        match hooks {
+            Some(hooks) => AuraeOCIBuilder {
+                spec_builder: self.spec_builder.hooks(hooks),
+                ..self
+            },
+            None => self,
+        }
+    }
+
     pub fn build(self) -> Result<Spec, OciSpecError> {
-        self.spec_builder.build()
+        let mut spec = self.spec_builder.build()?;
+        if let Some(tenant) = self.tenant {
+            tenant.apply(&mut spec)?;
+        }
+        if let Some(path) = self.cgroups_path {
+            let mut linux = spec.linux().clone().unwrap_or_default();
+            // Absolute, as relative paths are relative to the cgroup of the
+            // container runtime
+            let _ = linux.set_cgroups_path(Some(Path::new("/").join(path)));
+            let _ = spec.set_linux(Some(linux));
+        }
+        Ok(spec)
+    }
+}
+
+impl Tenant {
+    fn apply(self, spec: &mut Spec) -> Result<(), OciSpecError> {
+        if self.args.is_empty() {
+            return Err(OciSpecError::Other(
+                "neither the container nor its image has a command".into(),
+            ));
+        }
+        let _ = spec.set_root(Some(
+            RootBuilder::default().path(self.rootfs).readonly(false).build()?,
+        ));
+
+        let mut process = spec.process().clone().unwrap_or_default();
+        let _ = process
+            .set_args(Some(self.args))
+            .set_env(Some(self.env))
+            .set_cwd(self.cwd);
+        let _ = spec.set_process(Some(process));
+
+        let mut linux = spec.linux().clone().unwrap_or_default();
+        let namespaces = linux
+            .namespaces()
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|mut namespace| {
+                let shared = match namespace.typ() {
+                    LinuxNamespaceType::Network => Some("net"),
+                    LinuxNamespaceType::Ipc => Some("ipc"),
+                    LinuxNamespaceType::Uts => Some("uts"),
+                    _ => None,
+                };
+                if let Some(shared) = shared {
+                    let _ = namespace.set_path(Some(PathBuf::from(format!(
+                        "/proc/{}/ns/{shared}",
+                        self.sandbox_pid
+                    ))));
+                }
+                namespace
+            })
+            .collect();
+        let _ = linux.set_namespaces(Some(namespaces));
+        let _ = spec.set_linux(Some(linux));
+        // The hostname is the one of the UTS namespace of the sandbox
+        let _ = spec.set_hostname(None);
+
+        // Only the nested auraed of the init container needs the PKI
+        let mut mounts: Vec<Mount> = spec
+            .mounts()
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|mount| mount.destination() != Path::new("/etc/aurae"))
+            .collect();
+        mounts.extend(self.mounts);
+        let _ = spec.set_mounts(Some(mounts));
+
+        if let Some(context) = &self.security_context {
+            security_context::apply(spec, context)
+                .map_err(|e| OciSpecError::Other(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// The command line of the container of `config`. The command of the
+/// container replaces the entrypoint of the image, its args replace the cmd
+/// of the image.
+fn container_args(
+    config: &ContainerConfig,
+    image: Option<&ImageConfig>,
+) -> Vec<String> {
+    if !config.command.is_empty() {
+        return [config.command.clone(), config.args.clone()].concat();
+    }
+    let entrypoint =
+        image.and_then(|image| image.entrypoint().clone()).unwrap_or_default();
+    let args = if config.args.is_empty() {
+        image.and_then(|image| image.cmd().clone()).unwrap_or_default()
+    } else {
+        config.args.clone()
+    };
+    [entrypoint, args].concat()
+}
+
+/// The environment of the container of `config`, the one of the image with
+/// the variables of the container set.
+fn container_env(
+    config: &ContainerConfig,
+    image: Option<&ImageConfig>,
+) -> Vec<String> {
+    let mut env = image
+        .and_then(|image| image.env().clone())
+        .unwrap_or_else(|| {
+            vec![
+                "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin"
+                    .to_string(),
+            ]
+        });
+    for var in &config.envs {
+        let prefix = format!("{}=", var.key);
+        env.retain(|set| !set.starts_with(&prefix));
+        env.push(format!("{}={}", var.key, var.value));
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use oci_spec::image::ConfigBuilder as ImageConfigBuilder;
+    use proto::cri::KeyValue;
+
+    fn image() -> ImageConfig {
+        ImageConfigBuilder::default()
+            .entrypoint(vec!["/docker-entrypoint.sh".to_string()])
+            .cmd(vec!["nginx".to_string()])
+            .env(vec!["PATH=/bin".to_string(), "MODE=image".to_string()])
+            .build()
+            .expect("valid image config")
+    }
+
+    #[test]
+    fn container_command_overrides_the_image() {
+        let image = image();
+        let config = |command: &[&str], args: &[&str]| ContainerConfig {
+            command: command.iter().map(|s| s.to_string()).collect(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            container_args(&config(&[], &[]), Some(&image)),
+            ["/docker-entrypoint.sh", "nginx"]
+        );
+        assert_eq!(
+            container_args(&config(&[], &["nginx", "-T"]), Some(&image)),
+            ["/docker-entrypoint.sh", "nginx", "-T"]
+        );
+        assert_eq!(
+            container_args(
+                &config(&["/bin/sh"], &["-c", "true"]),
+                Some(&image)
+            ),
+            ["/bin/sh", "-c", "true"]
+        );
+        assert!(container_args(&config(&[], &[]), None).is_empty());
+    }
+
+    #[test]
+    fn container_env_overrides_the_image() {
+        let config = ContainerConfig {
+            envs: vec![KeyValue { key: "MODE".into(), value: "pod".into() }],
+            ..Default::default()
+        };
+        assert_eq!(
+            container_env(&config, Some(&image())),
+            ["PATH=/bin", "MODE=pod"]
+        );
+    }
+
+    #[test]
+    fn containers_join_the_namespaces_of_the_sandbox() {
+        let spec = AuraeOCIBuilder::new()
+            .overload_container_config(
+                &ContainerConfig {
+                    command: vec!["/bin/true".into()],
+                    ..Default::default()
+                },
+                None,
+                PathBuf::from("/snapshots/c/rootfs"),
+                42,
+                vec![],
+            )
+            .build()
+            .expect("valid spec");
+
+        let namespaces = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().clone())
+            .expect("namespaces");
+        let path = |typ| {
+            namespaces
+                .iter()
+                .find(|namespace| namespace.typ() == typ)
+                .and_then(|namespace| namespace.path().clone())
+        };
+        assert_eq!(
+            path(LinuxNamespaceType::Network),
+            Some(PathBuf::from("/proc/42/ns/net"))
+        );
+        assert_eq!(path(LinuxNamespaceType::Pid), None);
+        assert!(spec
+            .mounts()
+            .as_ref()
+            .expect("mounts")
+            .iter()
+            .all(|mount| mount.destination() != Path::new("/etc/aurae")));
     }
 }
\ No newline at end of file
@@ -0,0 +1,179 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! The cells of pod sandboxes.
+//!
+//! Each pod sandbox is given a cell whose cgroup is the parent of the
+//! cgroups of its init container and of its containers, so the limits of
+//! the pod apply to its containers collectively and the usage of the pod is
+//! the one of its cell. The limits of the cell are the resources of the pod
+//! with its overhead added, a pod without a limit of its own leaves the
+//! cell unlimited.
+
+use proto::cells::{Cell, CpuController, CpusetController, MemoryController};
+use proto::cri::{LinuxContainerResources, LinuxPodSandboxConfig};
+use sha2::{Digest, Sha256};
+
+/// The default period of the CPU quotas of cgroups, in microseconds
+const DEFAULT_CPU_PERIOD: i64 = 100_000;
+/// Cell names are DNS labels
+const MAX_CELL_NAME_LEN: usize = 63;
+
+/// The name of the cell of the pod sandbox `sandbox_id`. Sandbox IDs are
+/// not all valid cell names, so the name is the sanitized ID followed by a
+/// hash of the ID, which keeps the names of distinct pods distinct.
+pub(crate) fn cell_name(sandbox_id: &str) -> String {
+    let hash = hex::encode(&Sha256::digest(sandbox_id.as_bytes())[..4]);
+    let sanitized: String = sandbox_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .take(MAX_CELL_NAME_LEN - "pod--".len() - hash.len())
+        .collect();
+    let sanitized = sanitized.trim_matches('-');
+    if sanitized.is_empty() {
+        format!("pod-{hash}")
+    } else {
+        format!("pod-{sanitized}-{hash}")
+    }
+}
+
+/// The cell of the pod sandbox `sandbox_id` with the Linux config `linux`.
+pub(crate) fn cell(
+    sandbox_id: &str,
+    linux: Option<&LinuxPodSandboxConfig>,
+) -> Cell {
+    let resources = linux.and_then(|linux| linux.resources.as_ref());
+    let overhead = linux.and_then(|linux| linux.overhead.as_ref());
+
+    let cpu = resources
+        .and_then(|resources| {
+            let period =
+                positive(resources.cpu_period).unwrap_or(DEFAULT_CPU_PERIOD);
+            let pod_quota = quota(resources, period)?;
+            let overhead = overhead
+                .and_then(|overhead| quota(overhead, period))
+                .unwrap_or(0);
+            Some((pod_quota + overhead, period))
+        })
+        .map(|(max, period)| CpuController {
+            weight: None,
+            max: Some(max),
+            period: Some(period as u64),
+        });
+
+    let memory = resources
+        .and_then(|resources| positive(resources.memory_limit_in_bytes))
+        .map(|limit| {
+            let overhead = overhead
+                .and_then(|overhead| positive(overhead.memory_limit_in_bytes))
+                .unwrap_or(0);
+            MemoryController {
+                max: Some(limit + overhead),
+                ..Default::default()
+            }
+        });
+
+    let not_empty = |s: &String| (!s.is_empty()).then(|| s.clone());
+    let cpuset = resources
+        .map(|resources| CpusetController {
+            cpus: not_empty(&resources.cpuset_cpus),
+            mems: not_empty(&resources.cpuset_mems),
+        })
+        .filter(|cpuset| cpuset.cpus.is_some() || cpuset.mems.is_some());
+
+    Cell {
+        name: cell_name(sandbox_id),
+        cpu,
+        cpuset,
+        memory,
+        ..Default::default()
+    }
+}
+
+/// The CPU quota of `resources` over `period`.
+fn quota(resources: &LinuxContainerResources, period: i64) -> Option<i64> {
+    let quota = positive(resources.cpu_quota)?;
+    let own_period =
+        positive(resources.cpu_period).unwrap_or(DEFAULT_CPU_PERIOD);
+    Some(quota * period / own_period)
+}
+
+fn positive(value: i64) -> Option<i64> {
+    Some(value).filter(|value| *value > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_names_are_dns_labels() {
+        let name = cell_name("default_nginx.Example_0");
+        assert!(name.starts_with("pod-default-nginx-example-0-"));
+        assert_ne!(name, cell_name("default_nginx_example_0"));
+
+        let long = cell_name(&"a".repeat(300));
+        assert!(long.len() <= MAX_CELL_NAME_LEN);
+        assert_eq!(cell_name("__").len(), "pod-".len() + 8);
+    }
+
+    #[test]
+    fn limits_include_the_overhead() {
+        let linux = LinuxPodSandboxConfig {
+            resources: Some(LinuxContainerResources {
+                cpu_quota: 200_000,
+                cpu_period: 100_000,
+                memory_limit_in_bytes: 1 << 30,
+                ..Default::default()
+            }),
+            overhead: Some(LinuxContainerResources {
+                cpu_quota: 5_000,
+                cpu_period: 50_000,
+                memory_limit_in_bytes: 1 << 20,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let cell = cell("pod", Some(&linux));
+
+        let cpu = cell.cpu.expect("cpu");
+        assert_eq!(cpu.max, Some(210_000));
+        assert_eq!(cpu.period, Some(100_000));
+        assert_eq!(
+            cell.memory.expect("memory").max,
+            Some((1 << 30) + (1 << 20))
+        );
+        assert!(cell.cpuset.is_none());
+    }
+
+    #[test]
+    fn pods_without_limits_are_unlimited() {
+        let linux = LinuxPodSandboxConfig {
+            overhead: Some(LinuxContainerResources {
+                memory_limit_in_bytes: 1 << 20,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let cell = cell("pod", Some(&linux));
+        assert!(cell.cpu.is_none());
+        assert!(cell.memory.is_none());
+    }
+}
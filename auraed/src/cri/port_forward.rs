@@ -0,0 +1,55 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Connections of the PortForward call of the CRI runtime service, to the
+//! ports of a pod sandbox.
+//!
+//! Ports are connected to on the loopback interface of the network namespace
+//! of the sandbox, from a thread spawned to enter it. Sockets stay in the
+//! namespace they were created in, so the thread can exit once connected.
+
+use nix::sched::{setns, CloneFlags};
+use std::{
+    fs::File,
+    io,
+    net::{Ipv4Addr, TcpStream as StdTcpStream},
+};
+use tokio::net::TcpStream;
+
+/// Ports of a sandbox to forward, as requested by a PortForward call.
+#[derive(Debug, Clone)]
+pub(crate) struct PortForward {
+    /// PID of the init process of the sandbox
+    pub pid: i32,
+    /// Ports forwarded unless the client names others when connecting
+    pub ports: Vec<u16>,
+}
+
+impl PortForward {
+    /// Connects to `port` of the sandbox.
+    pub async fn connect(&self, port: u16) -> io::Result<TcpStream> {
+        let netns = File::open(format!("/proc/{}/ns/net", self.pid))?;
+        let stream = tokio::task::spawn_blocking(move || {
+            std::thread::spawn(move || {
+                setns(&netns, CloneFlags::CLONE_NEWNET)?;
+                StdTcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            })
+            .join()
+            .map_err(|_| io::Error::other("connecting thread panicked"))?
+        })
+        .await??;
+        stream.set_nonblocking(true)?;
+        TcpStream::from_std(stream)
+    }
+}
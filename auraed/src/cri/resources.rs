@@ -0,0 +1,136 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Live updates of the resources of containers, for the
+//! UpdateContainerResources call of the CRI runtime service.
+//!
+//! The limits are rewritten in the unified cgroup of the init process of a
+//! container the way the limits of cells are applied, so containers are
+//! scaled vertically without being restarted. Fields left at their default
+//! are not specified by the caller and keep their current limit.
+
+use super::stats::{cgroup_of, CGROUP_ROOT};
+use crate::cells::update_resources;
+use anyhow::anyhow;
+use oci_spec::{
+    runtime::{
+        LinuxCpuBuilder, LinuxHugepageLimitBuilder, LinuxMemoryBuilder,
+        LinuxResources, LinuxResourcesBuilder,
+    },
+    OciSpecError,
+};
+use proto::cri::LinuxContainerResources;
+
+/// Applies `resources` to the container whose init process is `pid`.
+pub(crate) fn update(
+    pid: i32,
+    resources: &LinuxContainerResources,
+) -> anyhow::Result<()> {
+    let cgroup = cgroup_of(pid)
+        .ok_or_else(|| anyhow!("cgroup of process {pid} not found"))?;
+    let cgroup = cgroup.strip_prefix(CGROUP_ROOT)?.to_path_buf();
+    update_resources(cgroup, &linux_resources(resources)?)?;
+
+    // The OOM score is a property of the process rather than its cgroup
+    if resources.oom_score_adj != 0 {
+        std::fs::write(
+            format!("/proc/{pid}/oom_score_adj"),
+            resources.oom_score_adj.to_string(),
+        )?;
+    }
+    Ok(())
+}
+
+fn linux_resources(
+    resources: &LinuxContainerResources,
+) -> Result<LinuxResources, OciSpecError> {
+    let mut cpu = LinuxCpuBuilder::default();
+    if let Some(shares) = specified(resources.cpu_shares) {
+        cpu = cpu.shares(shares);
+    }
+    if resources.cpu_quota > 0 {
+        cpu = cpu.quota(resources.cpu_quota);
+    }
+    if let Some(period) = specified(resources.cpu_period) {
+        cpu = cpu.period(period);
+    }
+    if !resources.cpuset_cpus.is_empty() {
+        cpu = cpu.cpus(resources.cpuset_cpus.clone());
+    }
+    if !resources.cpuset_mems.is_empty() {
+        cpu = cpu.mems(resources.cpuset_mems.clone());
+    }
+
+    let mut memory = LinuxMemoryBuilder::default();
+    if resources.memory_limit_in_bytes > 0 {
+        memory = memory.limit(resources.memory_limit_in_bytes);
+    }
+    if resources.memory_swap_limit_in_bytes > 0 {
+        memory = memory.swap(resources.memory_swap_limit_in_bytes);
+    }
+
+    let hugepage_limits = resources
+        .hugepage_limits
+        .iter()
+        .map(|limit| {
+            LinuxHugepageLimitBuilder::default()
+                .page_size(limit.page_size.clone())
+                .limit(i64::try_from(limit.limit).unwrap_or(i64::MAX))
+                .build()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut builder = LinuxResourcesBuilder::default()
+        .cpu(cpu.build()?)
+        .memory(memory.build()?);
+    if !hugepage_limits.is_empty() {
+        builder = builder.hugepage_limits(hugepage_limits);
+    }
+    if !resources.unified.is_empty() {
+        builder = builder.unified(resources.unified.clone());
+    }
+    builder.build()
+}
+
+fn specified(value: i64) -> Option<u64> {
+    u64::try_from(value).ok().filter(|value| *value > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unspecified_limits_are_left_out() {
+        let resources = linux_resources(&LinuxContainerResources {
+            cpu_quota: 50_000,
+            cpu_period: 100_000,
+            memory_limit_in_bytes: 1 << 30,
+            ..Default::default()
+        })
+        .expect("resources");
+
+        let cpu = resources.cpu().as_ref().expect("cpu");
+        assert_eq!(cpu.quota(), Some(50_000));
+        assert_eq!(cpu.period(), Some(100_000));
+        assert_eq!(cpu.shares(), None);
+        assert_eq!(cpu.cpus(), &None);
+
+        let memory = resources.memory().as_ref().expect("memory");
+        assert_eq!(memory.limit(), Some(1 << 30));
+        assert_eq!(memory.swap(), None);
+        assert!(resources.hugepage_limits().is_none());
+        assert!(resources.unified().is_none());
+    }
+}
@@ -42,59 +42,662 @@
  *                                                                            *
 \* -------------------------------------------------------------------------- */
 
+use crate::cells::CellService;
+use crate::cordon::Cordon;
+use crate::cri::attach::Attach;
+use crate::cri::checkpoint::{self, RESTORE_ANNOTATION};
+use crate::cri::cni::{self, CniConfig, Network};
+use crate::cri::container_log::ContainerLog;
+use crate::cri::dns;
+use crate::cri::exec::{Exec, ExecProcess};
+use crate::cri::exec_context::ExecContext;
+use crate::cri::hooks;
 #[allow(unused_imports)]
 use crate::cri::oci::AuraeOCIBuilder;
-use crate::cri::sandbox::SandboxBuilder;
-use crate::spawn_auraed_oci_to;
+use crate::cri::pod_cell;
+use crate::cri::port_forward::PortForward;
+use crate::cri::resources;
+use crate::cri::sandbox::{PodNetwork, Sandbox, SandboxBuilder};
+use crate::cri::sandbox_record::SandboxRecord;
+use crate::cri::stats::{container_stats, CpuSamples};
+use crate::cri::streaming::{Session, StreamingServer};
+use crate::cri::validation::{
+    ValidatedCreateContainerRequest, ValidatedRunPodSandboxRequest,
+    ValidatedUpdateContainerResourcesRequest,
+};
+use crate::cri::vm_pod::{PodVms, VmPod, VM_RUNTIME_HANDLER};
+use crate::cri::volumes;
+use crate::images::ImageService;
+use crate::logging::log_channel::{LogChannel, LogLabels};
+use crate::network::{Ipam, Pool, Veth};
+use crate::observe::{
+    ObserveService, Workload, WorkloadEvent, WorkloadEventKind,
+};
+use crate::readiness;
+use crate::spawn::{self, spawn_auraed_oci_to, Arch};
+use anyhow::{anyhow, Context};
 use chrono::Utc;
+use client::cri::{
+    image_service::ImageServiceClient, runtime_service::RuntimeServiceClient,
+};
 use libcontainer;
 use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
 use libcontainer::syscall::syscall::SyscallType;
 use nix::sys::signal::Signal::SIGKILL;
+use nix::unistd::Pid;
+use oci_spec::runtime::Spec;
 use proto::cri::{
     runtime_service_server, AttachRequest, AttachResponse,
     CheckpointContainerRequest, CheckpointContainerResponse,
-    ContainerEventResponse, ContainerStatsRequest, ContainerStatsResponse,
-    ContainerStatusRequest, ContainerStatusResponse, CreateContainerRequest,
-    CreateContainerResponse, ExecRequest, ExecResponse, ExecSyncRequest,
-    ExecSyncResponse, GetEventsRequest, ListContainerStatsRequest,
-    ListContainerStatsResponse, ListContainersRequest, ListContainersResponse,
+    ContainerEventResponse, ContainerEventType, ContainerStatsRequest,
+    ContainerStatsResponse, ContainerStatusRequest, ContainerStatusResponse,
+    CreateContainerRequest, CreateContainerResponse, ExecRequest, ExecResponse,
+    ExecSyncRequest, ExecSyncResponse, GetEventsRequest,
+    ListContainerStatsRequest, ListContainerStatsResponse,
+    ListContainersRequest, ListContainersResponse,
     ListMetricDescriptorsRequest, ListMetricDescriptorsResponse,
     ListPodSandboxMetricsRequest, ListPodSandboxMetricsResponse,
     ListPodSandboxRequest, ListPodSandboxResponse, ListPodSandboxStatsRequest,
-    ListPodSandboxStatsResponse, PodSandbox, PodSandboxStatsRequest,
-    PodSandboxStatsResponse, PodSandboxStatusRequest, PodSandboxStatusResponse,
-    PortForwardRequest, PortForwardResponse, RemoveContainerRequest,
-    RemoveContainerResponse, RemovePodSandboxRequest, RemovePodSandboxResponse,
-    ReopenContainerLogRequest, ReopenContainerLogResponse,
-    RunPodSandboxRequest, RunPodSandboxResponse, StartContainerRequest,
+    ListPodSandboxStatsResponse, NamespaceMode, PodIp, PodSandbox,
+    PodSandboxConfig, PodSandboxMetadata, PodSandboxNetworkStatus,
+    PodSandboxState, PodSandboxStatsRequest, PodSandboxStatsResponse,
+    PodSandboxStatus, PodSandboxStatusRequest, PodSandboxStatusResponse,
+    PortForwardRequest, PortForwardResponse, PullImageRequest,
+    RemoveContainerRequest, RemoveContainerResponse, RemovePodSandboxRequest,
+    RemovePodSandboxResponse, ReopenContainerLogRequest,
+    ReopenContainerLogResponse, RunPodSandboxRequest, RunPodSandboxResponse,
+    RuntimeCondition, RuntimeStatus, StartContainerRequest,
     StartContainerResponse, StatusRequest, StatusResponse,
     StopContainerRequest, StopContainerResponse, StopPodSandboxRequest,
     StopPodSandboxResponse, UpdateContainerResourcesRequest,
     UpdateContainerResourcesResponse, UpdateRuntimeConfigRequest,
     UpdateRuntimeConfigResponse, VersionRequest, VersionResponse,
 };
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+use validation::ValidatedType;
 
 use super::{error::RuntimeServiceError, sandbox_cache::SandboxCache};
 
 // The string to refer to the nested runtime spaces for recursive Auraed environments.
 const AURAE_SELF_IDENTIFIER: &str = "_aurae";
+/// The condition of the runtime being ready to run pods
+const RUNTIME_READY: &str = "RuntimeReady";
+/// The condition of the network of pods being ready
+const NETWORK_READY: &str = "NetworkReady";
+/// Container events kept for clients of GetContainerEvents that fall behind
+const EVENTS_CAPACITY: usize = 128;
 
 #[derive(Debug, Clone)]
 pub struct RuntimeService {
     sandboxes: Arc<Mutex<SandboxCache>>,
+    /// New pods are rejected while the node is cordoned
+    cordon: Cordon,
+    /// Serves the sessions of Exec and PortForward calls
+    streaming: StreamingServer,
+    /// CPU usage seen by the previous stats calls
+    cpu_samples: CpuSamples,
+    /// Leases the addresses of pods while no CNI network is configured
+    ipam: Option<Ipam>,
+    /// Runs the pods of the aurae-vm runtime handler, None without the vms
+    /// subsystem
+    pod_vms: Option<PodVms>,
+    /// The pods running in VMs, by sandbox ID, None while their VM boots
+    vm_pods: Arc<Mutex<HashMap<String, Option<VmPod>>>>,
+    /// The sandbox IDs of the pods running in VMs, by the IDs of their
+    /// containers
+    vm_containers: Arc<Mutex<HashMap<String, String>>>,
+    /// Prepares the root filesystems of containers from pulled images
+    images: ImageService,
+    /// Allocates the cells the cgroups of the containers of pods are nested
+    /// in
+    cells: CellService,
+    /// The pod CIDR of the node last set by the kubelet
+    pod_cidr: Arc<Mutex<String>>,
+    /// Lifecycle events of sandboxes and containers, streamed to the
+    /// clients of GetContainerEvents
+    events: broadcast::Sender<ContainerEventResponse>,
+    /// Streams the output of containers to the clients of the observe API
+    observe_service: ObserveService,
 }
 
 impl RuntimeService {
-    pub fn new() -> Self {
-        RuntimeService { sandboxes: Default::default() }
+    /// Create a new RuntimeService, serving the sessions of Exec and
+    /// PortForward calls on `streaming_address`. Pods are attached to the
+    /// host with addresses leased by `ipam` while no CNI network is
+    /// configured, and have no network without it. Pods of the aurae-vm
+    /// runtime handler are run with `pod_vms`. Containers run from the
+    /// images pulled by `images`, in the cgroup of a cell allocated by
+    /// `cells` for their pod. The output of containers is part of the log
+    /// stream of `observe_service`.
+    pub fn new(
+        cordon: Cordon,
+        streaming_address: SocketAddr,
+        ipam: Option<Ipam>,
+        pod_vms: Option<PodVms>,
+        images: ImageService,
+        cells: CellService,
+        observe_service: ObserveService,
+    ) -> std::io::Result<Self> {
+        Ok(RuntimeService {
+            sandboxes: Default::default(),
+            cordon,
+            streaming: StreamingServer::bind(streaming_address)?,
+            cpu_samples: Default::default(),
+            ipam,
+            pod_vms,
+            vm_pods: Default::default(),
+            vm_containers: Default::default(),
+            images,
+            cells,
+            pod_cidr: Default::default(),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            observe_service,
+        })
+    }
+
+    /// Makes the lines written to `log` part of the log stream of the
+    /// observe API, labelled with the pod and container they are of. The
+    /// init containers of sandboxes go by the ID of their sandbox.
+    async fn stream_log(&self, log: &ContainerLog) {
+        if let Some(channel) = log.channel() {
+            let labels = channel.labels();
+            let key =
+                log_channel_key(&labels.pod_sandbox_id, &labels.container_id);
+            self.observe_service
+                .register_log_channel(key, channel.clone())
+                .await;
+        }
+    }
+
+    /// Send the `event_type` event of the container `container_id` of the
+    /// sandbox with `status` to the clients of GetContainerEvents, and the
+    /// starts and stops to the workload events of the observe API. The init
+    /// containers of sandboxes go by the ID of their sandbox.
+    fn send_event(
+        &self,
+        container_id: &str,
+        event_type: ContainerEventType,
+        status: PodSandboxStatus,
+    ) {
+        let lifecycle = match event_type {
+            ContainerEventType::ContainerStartedEvent => {
+                Some((WorkloadEventKind::Started, "started"))
+            }
+            ContainerEventType::ContainerStoppedEvent => {
+                Some((WorkloadEventKind::Stopped, "stopped"))
+            }
+            _ => None,
+        };
+        if let Some((kind, verb)) = lifecycle {
+            let (workload, message) = if container_id == status.id {
+                (
+                    Workload::PodSandbox { pod_sandbox_id: status.id.clone() },
+                    format!("{verb} pod sandbox {container_id}"),
+                )
+            } else {
+                (
+                    Workload::Container {
+                        pod_sandbox_id: status.id.clone(),
+                        container_id: container_id.to_string(),
+                    },
+                    format!("{verb} container {container_id}"),
+                )
+            };
+            self.observe_service.emit_workload_event(WorkloadEvent::new(
+                kind, workload, message,
+            ));
+        }
+
+        // Without clients the event is dropped
+        let _ = self.events.send(ContainerEventResponse {
+            container_id: container_id.to_string(),
+            container_event_type: event_type as i32,
+            created_at: Utc::now().timestamp_nanos_opt().unwrap_or_default(),
+            pod_sandbox_status: Some(status),
+            containers_statuses: vec![],
+        });
+    }
+
+    /// Runs the pod of `config` in a VM of its own, the way the
+    /// [VM_RUNTIME_HANDLER] runtime handler isolates pods.
+    async fn run_vm_pod(
+        &self,
+        config: PodSandboxConfig,
+    ) -> Result<Response<RunPodSandboxResponse>, Status> {
+        let Some(pod_vms) = &self.pod_vms else {
+            return Err(RuntimeServiceError::VmsDisabled {
+                handler: VM_RUNTIME_HANDLER.to_string(),
+            }
+            .into());
+        };
+        let metadata = config.metadata.clone().expect("validated metadata");
+        let sandbox_id = metadata.name.clone();
+
+        if self.sandboxes.lock().await.get(&sandbox_id).is_ok() {
+            return Err(
+                RuntimeServiceError::SandboxExists { sandbox_id }.into()
+            );
+        }
+        // The ID is taken while the VM boots, without holding the pods for
+        // the whole boot
+        {
+            let mut vm_pods = self.vm_pods.lock().await;
+            if vm_pods.contains_key(&sandbox_id) {
+                return Err(
+                    RuntimeServiceError::SandboxExists { sandbox_id }.into()
+                );
+            }
+            let _ = vm_pods.insert(sandbox_id.clone(), None);
+        }
+        let vm_pod = match VmPod::run(pod_vms, &sandbox_id, config).await {
+            Ok(vm_pod) => vm_pod,
+            Err(e) => {
+                let _ = self.vm_pods.lock().await.remove(&sandbox_id);
+                return Err(RuntimeServiceError::VmPodError {
+                    sandbox_id,
+                    error: format!("{e:#}"),
+                }
+                .into());
+            }
+        };
+        let _ =
+            self.vm_pods.lock().await.insert(sandbox_id.clone(), Some(vm_pod));
+
+        let status = PodSandboxStatus {
+            id: sandbox_id.clone(),
+            metadata: Some(metadata),
+            state: PodSandboxState::SandboxReady as i32,
+            ..Default::default()
+        };
+        for event_type in [
+            ContainerEventType::ContainerCreatedEvent,
+            ContainerEventType::ContainerStartedEvent,
+        ] {
+            self.send_event(&sandbox_id, event_type, status.clone());
+        }
+
+        Ok(Response::new(RunPodSandboxResponse { pod_sandbox_id: sandbox_id }))
+    }
+
+    /// The pod `sandbox_id` if it runs in a VM that booted.
+    async fn vm_pod(&self, sandbox_id: &str) -> Option<VmPod> {
+        self.vm_pods.lock().await.get(sandbox_id).cloned().flatten()
+    }
+
+    /// The sandbox ID and VM of the pod the container `container_id` was
+    /// created in, if it runs in a VM.
+    async fn vm_pod_of(&self, container_id: &str) -> Option<(String, VmPod)> {
+        let sandbox_id =
+            self.vm_containers.lock().await.get(container_id).cloned()?;
+        let vm_pod = self.vm_pod(&sandbox_id).await?;
+        Some((sandbox_id, vm_pod))
+    }
+
+    /// Creates the container of `request` in the sandbox the agent of
+    /// `vm_pod` runs in the guest, once the agent pulled its image.
+    async fn create_vm_pod_container(
+        &self,
+        vm_pod: &VmPod,
+        request: CreateContainerRequest,
+    ) -> Result<Response<CreateContainerResponse>, Status> {
+        let sandbox_id = request.pod_sandbox_id.clone();
+        let image =
+            request.config.as_ref().and_then(|config| config.image.clone());
+        let _ = vm_pod
+            .agent()
+            .pull_image(PullImageRequest {
+                image,
+                auth: None,
+                sandbox_config: request.sandbox_config.clone(),
+            })
+            .await?;
+        let response = vm_pod.agent().create_container(request).await?;
+        let container_id = response.get_ref().container_id.clone();
+        let _ = self
+            .vm_containers
+            .lock()
+            .await
+            .insert(container_id.clone(), sandbox_id.clone());
+        self.send_event(
+            &container_id,
+            ContainerEventType::ContainerCreatedEvent,
+            vm_pod_status(&sandbox_id, PodSandboxState::SandboxReady),
+        );
+        Ok(response)
+    }
+
+    /// Attaches the network namespace of the init container `pid` of the pod
+    /// `metadata` to the CNI network, if one is configured in `cni`, or else
+    /// directly to the host.
+    async fn attach_network(
+        &self,
+        cni: &CniConfig,
+        metadata: &PodSandboxMetadata,
+        pid: Pid,
+    ) -> anyhow::Result<Option<PodNetwork>> {
+        if let Some(network) = Network::load(cni)? {
+            let netns = PathBuf::from(format!("/proc/{pid}/ns/net"));
+            let args = [
+                ("K8S_POD_NAMESPACE", metadata.namespace.as_str()),
+                ("K8S_POD_NAME", metadata.name.as_str()),
+                ("K8S_POD_UID", metadata.uid.as_str()),
+                ("K8S_POD_INFRA_CONTAINER_ID", metadata.name.as_str()),
+            ];
+            let attachment = network.add(&metadata.name, &netns, &args).await?;
+            return Ok(Some(PodNetwork::Cni(attachment)));
+        }
+
+        let Some(ipam) = &self.ipam else {
+            warn!(
+                "No CNI network configured, pod '{}' has no network",
+                metadata.name
+            );
+            return Ok(None);
+        };
+        let lease =
+            ipam.allocate(Pool::Pods, &metadata.name, pid.as_raw()).await?;
+        match Veth::attach(pid.as_raw(), lease).await {
+            Ok(veth) => Ok(Some(PodNetwork::Veth(veth))),
+            Err(e) => {
+                let _best_effort =
+                    ipam.release(Pool::Pods, &metadata.name).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Detaches the network namespace of the sandbox `sandbox_id`, while it
+    /// is still around, and returns its address to the pool.
+    async fn detach_network(
+        &self,
+        sandbox_id: &str,
+        network: &PodNetwork,
+    ) -> anyhow::Result<()> {
+        match network {
+            PodNetwork::Cni(attachment) => attachment.remove().await,
+            PodNetwork::Veth(veth) => {
+                veth.remove().await?;
+                if let Some(ipam) = &self.ipam {
+                    ipam.release(Pool::Pods, sandbox_id).await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether pods can be attached to a network, see [Self::attach_network].
+    fn network_ready(&self, cni: &CniConfig) -> anyhow::Result<()> {
+        match Network::load(cni)? {
+            Some(network) => network.check_plugins(),
+            None if self.ipam.is_some() => Ok(()),
+            None => Err(anyhow!(
+                "no CNI network config in {}",
+                cni.conf_dir.display()
+            )),
+        }
+    }
+
+    /// Rebuilds the sandboxes left by a previous auraed from the pods
+    /// directory, so that a restart does not strand the pods of the node.
+    /// Sandboxes whose init container is gone are recovered nonetheless, not
+    /// ready, for the kubelet to stop and remove them. What is left of
+    /// sandboxes that cannot be recovered, without a record or an init
+    /// container, is cleaned up.
+    pub async fn reconcile(&self) -> anyhow::Result<()> {
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        let entries = match std::fs::read_dir(runtime.pods_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut sandboxes = self.sandboxes.lock().await;
+        for entry in entries {
+            let entry = entry?;
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let sandbox_id = entry.file_name().to_string_lossy().into_owned();
+            let pod_dir = entry.path();
+            match self.recover(&sandbox_id, &pod_dir).await {
+                Ok(sandbox) => {
+                    info!(
+                        "Recovered pod {sandbox_id} with {} containers",
+                        sandbox.tenants.len()
+                    );
+                    sandboxes.add(sandbox_id, sandbox)?;
+                }
+                Err(e) => {
+                    warn!(
+                        "Cleaning up pod {sandbox_id}, which cannot be \
+                        recovered: {e:#}"
+                    );
+                    self.clean_up(&sandbox_id, &pod_dir).await;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Recovers the sandbox `sandbox_id` from its record and the containers
+    /// in its directory `pod_dir`.
+    async fn recover(
+        &self,
+        sandbox_id: &str,
+        pod_dir: &Path,
+    ) -> anyhow::Result<Sandbox> {
+        let record = SandboxRecord::load(pod_dir)
+            .context("failed to read the record of the sandbox")?;
+        let init = Container::load(pod_dir.join(AURAE_SELF_IDENTIFIER))
+            .context("failed to load the init container")?;
+        let tenants = load_tenants(pod_dir);
+
+        // The cells of an auraed that stopped without releasing them are
+        // not adopted, their cgroups are left behind
+        let cell = match record.cell {
+            Some(cell)
+                if self
+                    .cells
+                    .has_pod_cell(&cell.display().to_string())
+                    .await =>
+            {
+                Some(cell)
+            }
+            Some(cell) => {
+                warn!(
+                    "The cell {} of pod {sandbox_id} was not adopted",
+                    cell.display()
+                );
+                None
+            }
+            None => None,
+        };
+
+        // The output of the init container was copied by the previous
+        // auraed, the pipes it wrote to are closed
+        Ok(SandboxBuilder::new(sandbox_id.to_string(), init)
+            .with_metadata(record.metadata)
+            .with_network(record.network)
+            .with_log_directory(record.log_directory)
+            .with_cell(cell)
+            .with_resolv_conf(dns::find(pod_dir))
+            .with_tenants(tenants)
+            .build())
+    }
+
+    /// Removes what is left of the sandbox `sandbox_id` in `pod_dir`, its
+    /// containers, its network and cell and the directory itself.
+    async fn clean_up(&self, sandbox_id: &str, pod_dir: &Path) {
+        for mut container in load_tenants(pod_dir)
+            .into_iter()
+            .chain(Container::load(pod_dir.join(AURAE_SELF_IDENTIFIER)).ok())
+        {
+            if let Err(e) = container.delete(true) {
+                warn!("Failed to delete container {}: {e}", container.id());
+            }
+        }
+        if let Ok(record) = SandboxRecord::load(pod_dir) {
+            if let Some(network) = &record.network {
+                if let Err(e) = self.detach_network(sandbox_id, network).await {
+                    warn!("Failed to detach pod {sandbox_id}: {e:#}");
+                }
+            }
+            if let Some(cell) = record.cell {
+                let cell = cell.display().to_string();
+                if self.cells.has_pod_cell(&cell).await {
+                    if let Err(e) = self.cells.free_for_pod(&cell).await {
+                        warn!("Failed to free the cell {cell}: {e:#}");
+                    }
+                }
+            }
+        }
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        if let Err(e) = volumes::remove(&runtime.volumes_dir().join(sandbox_id))
+        {
+            warn!("Failed to remove the volumes of pod {sandbox_id}: {e}");
+        }
+        if let Err(e) = std::fs::remove_dir_all(pod_dir) {
+            warn!("Failed to remove the directory of pod {sandbox_id}: {e}");
+        }
+    }
+
+    /// The PID of the init process of the running container `container_id`
+    /// and the context of the processes exec runs in it, from the OCI spec
+    /// of its bundle.
+    async fn exec_target(
+        &self,
+        container_id: &String,
+    ) -> Result<(i32, ExecContext), RuntimeServiceError> {
+        let (pid, bundle) = {
+            let sandboxes = self.sandboxes.lock().await;
+            (
+                sandboxes.container_pid(container_id)?,
+                sandboxes.container_bundle(container_id)?,
+            )
+        };
+        let context = Spec::load(bundle.join("config.json"))
+            .map_err(anyhow::Error::from)
+            .and_then(|spec| Ok(ExecContext::from_spec(&spec)?))
+            .map_err(|e| RuntimeServiceError::ExecError {
+                container_id: container_id.clone(),
+                error: format!("{e:#}"),
+            })?;
+        Ok((pid, context))
     }
 }
 
+/// The runtime condition `typ`, true if `ready` is Ok and false with
+/// `reason` otherwise.
+fn condition(
+    typ: &str,
+    reason: &str,
+    ready: anyhow::Result<()>,
+) -> RuntimeCondition {
+    match ready {
+        Ok(()) => RuntimeCondition {
+            r#type: typ.to_string(),
+            status: true,
+            ..Default::default()
+        },
+        Err(e) => RuntimeCondition {
+            r#type: typ.to_string(),
+            status: false,
+            reason: reason.to_string(),
+            message: format!("{e:#}"),
+        },
+    }
+}
+
+/// The channel the output of a container is sent to, for the log stream of
+/// the observe API.
+fn log_channel(pod_sandbox_id: &str, container_id: &str) -> LogChannel {
+    LogChannel::new(format!("{pod_sandbox_id}::{container_id}")).with_labels(
+        LogLabels {
+            pod_sandbox_id: pod_sandbox_id.to_string(),
+            container_id: container_id.to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+/// The key the channel of a container is registered by with the observe
+/// API.
+fn log_channel_key(pod_sandbox_id: &str, container_id: &str) -> String {
+    format!("{pod_sandbox_id}/{container_id}")
+}
+
+/// The status of the sandbox `sandbox_id`, ready while its init container
+/// runs.
+/// The status of the pod `sandbox_id` running in a VM, sent along with its
+/// events
+fn vm_pod_status(sandbox_id: &str, state: PodSandboxState) -> PodSandboxStatus {
+    PodSandboxStatus {
+        id: sandbox_id.to_string(),
+        state: state as i32,
+        ..Default::default()
+    }
+}
+
+fn sandbox_status(sandbox_id: &str, sandbox: &Sandbox) -> PodSandboxStatus {
+    let mut ips = sandbox
+        .network
+        .as_ref()
+        .map(|network| network.ips())
+        .unwrap_or_default()
+        .into_iter();
+    let network = ips.next().map(|ip| PodSandboxNetworkStatus {
+        ip,
+        additional_ips: ips.map(|ip| PodIp { ip }).collect(),
+    });
+    let ready = sandbox.init.status()
+        == libcontainer::container::ContainerStatus::Running;
+    PodSandboxStatus {
+        id: sandbox_id.to_string(),
+        metadata: sandbox.metadata.clone(),
+        state: if ready {
+            PodSandboxState::SandboxReady
+        } else {
+            PodSandboxState::SandboxNotready
+        } as i32,
+        network,
+        ..Default::default()
+    }
+}
+
+/// Loads the containers of the sandbox in `pod_dir` besides its init
+/// container. Containers the state of which is unreadable are skipped.
+fn load_tenants(pod_dir: &Path) -> Vec<Container> {
+    let Ok(entries) = std::fs::read_dir(pod_dir) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_name() != AURAE_SELF_IDENTIFIER
+                && entry.file_type().is_ok_and(|typ| typ.is_dir())
+        })
+        .filter_map(|entry| match Container::load(entry.path()) {
+            Ok(container) => Some(container),
+            Err(e) => {
+                warn!(
+                    "Failed to load container {}: {e}",
+                    entry.path().display()
+                );
+                None
+            }
+        })
+        .collect()
+}
+
 #[tonic::async_trait]
 impl runtime_service_server::RuntimeService for RuntimeService {
     async fn version(
@@ -110,25 +713,60 @@ impl runtime_service_server::RuntimeService for RuntimeService {
         request: Request<RunPodSandboxRequest>,
     ) -> Result<Response<RunPodSandboxResponse>, Status> {
         // TODO: RuntimeServiceErrors
+        self.cordon.check().map_err(RuntimeServiceError::from)?;
 
         // Handle Request
-        let r = request.into_inner();
-        // Handle Config
-        let config = r.config.expect("config from pod sandbox request");
+        let ValidatedRunPodSandboxRequest { config, runtime_handler } =
+            ValidatedRunPodSandboxRequest::validate(
+                request.into_inner(),
+                None,
+            )?;
         // Check for Windows config (currently unsupported)
         let windows = config.clone().windows;
         if windows.is_some() {
             panic!("Windows architecture is currently unsupported.") // TODO Unsure if we want to panic here?
         }
 
+        // The runtime handler picks what isolates the pod
+        match runtime_handler.as_str() {
+            "" => {}
+            VM_RUNTIME_HANDLER => return self.run_vm_pod(config).await,
+            handler => {
+                return Err(RuntimeServiceError::UnknownRuntimeHandler {
+                    handler: handler.to_string(),
+                }
+                .into())
+            }
+        }
+
         let mut sandboxes = self.sandboxes.lock().await;
 
         // Extract the metadata (name, uid, etc)
-        let metadata = config.clone().metadata.expect("metadata from config");
-        let sandbox_id = metadata.name;
+        let metadata = config.clone().metadata.expect("validated metadata");
+        let sandbox_id = metadata.name.clone();
+        if self.vm_pods.lock().await.contains_key(&sandbox_id) {
+            return Err(
+                RuntimeServiceError::SandboxExists { sandbox_id }.into()
+            );
+        }
+        // Where kubelet expects the logs of the containers of the pod
+        let log_directory = config.log_directory.clone();
+        // The checkpoint the init container is restored from, if any
+        let restore_from = config.annotations.get(RESTORE_ANNOTATION).cloned();
+        // The DNS of the containers of the pod, if the kubelet sets one
+        let dns_config = config.dns_config.clone();
         // Extract the Linux config (OCI and runtime parameters, security context, etc)
-        let _linux =
+        let linux =
             config.clone().linux.expect("linux from pod sandbox config");
+        // The cell of the pod, with the resources and overhead of the pod
+        let cell = pod_cell::cell(&sandbox_id, Some(&linux));
+        // Pods on the host network are not attached to the CNI network
+        let host_network = linux
+            .security_context
+            .and_then(|security_context| security_context.namespace_options)
+            .is_some_and(|namespace_options| {
+                namespace_options.network() == NamespaceMode::Node
+            });
         let oci_builder =
             AuraeOCIBuilder::new().overload_pod_sandbox_config(config);
 
@@ -137,51 +775,183 @@ impl runtime_service_server::RuntimeService for RuntimeService {
         // TODO Switch on "WASM" which is a field that we will add to the RunPodSandboxRequest
         // TODO We made the decision to create a "KernelSpec" *name structure that will be how we distinguish between VMs and Containers
 
-        let sandbox = {
+        let cell_cgroup =
+            self.cells.allocate_for_pod(cell).await.map_err(|e| {
+                RuntimeServiceError::PodCellError {
+                    sandbox_id: sandbox_id.clone(),
+                    error: format!("{e:#}"),
+                }
+            })?;
+
+        let sandbox = async {
             // Initialize a new container builder with the AURAE_SELF_IDENTIFIER name as the "init" container running a recursive Auraed
-            let container_builder = ContainerBuilder::new(
+            let mut container_builder = ContainerBuilder::new(
                 AURAE_SELF_IDENTIFIER.to_string(),
                 SyscallType::default(),
             );
 
-            let bundle_path = crate::AURAED_RUNTIME
-                .get()
-                .expect("runtime")
-                .bundles_dir()
-                .join(AURAE_SELF_IDENTIFIER);
+            let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+            let hooks = hooks::load(&runtime.oci_hooks)
+                .map_err(RuntimeServiceError::from)?;
+            let oci_builder = oci_builder.with_hooks(hooks).with_cgroups_path(
+                Some(cell_cgroup.join(AURAE_SELF_IDENTIFIER)),
+            );
+
+            // Copy the output of the init container to its CRI log. A
+            // restored init container keeps writing to the pipes it was
+            // checkpointed with.
+            let log = if log_directory.is_empty() || restore_from.is_some() {
+                None
+            } else {
+                let path = PathBuf::from(&log_directory)
+                    .join(AURAE_SELF_IDENTIFIER)
+                    .join("0.log");
+                let log_error =
+                    |e: std::io::Error| RuntimeServiceError::LogError {
+                        container_id: sandbox_id.clone(),
+                        error: e.to_string(),
+                    };
+                let log =
+                    ContainerLog::open(path, runtime.container_logs.clone())
+                        .map_err(log_error)?
+                        .with_channel(log_channel(&sandbox_id, &sandbox_id));
+                let (stdout, stderr) = log.pipes().map_err(log_error)?;
+                container_builder =
+                    container_builder.with_stdout(stdout).with_stderr(stderr);
+                Some(log)
+            };
+            let bundle_path = runtime.bundles_dir().join(AURAE_SELF_IDENTIFIER);
 
             // Spawn auraed here
             // TODO Check if sandbox already exists?
-            let _spawned = spawn_auraed_oci_to(
-                bundle_path.clone(),
-                oci_builder.build().expect("building pod oci spec"),
-            );
+            let _spawned =
+                spawn::auraed_binary(Arch::host(), &runtime.library_dir)
+                    .and_then(|auraed| {
+                        spawn_auraed_oci_to(
+                            bundle_path.clone(),
+                            oci_builder.build().expect("building pod oci spec"),
+                            Arch::host(),
+                            &auraed,
+                        )
+                    });
 
-            let pod_path = crate::AURAED_RUNTIME
-                .get()
-                .expect("runtime")
-                .pods_dir()
-                .join(sandbox_id.clone());
+            let pod_path = runtime.pods_dir().join(sandbox_id.clone());
+            let resolv_conf = match &dns_config {
+                Some(dns_config) => {
+                    Some(dns::write(&pod_path, dns_config).map_err(|e| {
+                        RuntimeServiceError::DnsError {
+                            sandbox_id: sandbox_id.clone(),
+                            error: e.to_string(),
+                        }
+                    })?)
+                }
+                None => None,
+            };
 
-            // Define the init container startup environment
-            let mut init_container = container_builder
-                .with_root_path(pod_path)
-                .expect("Setting pods directory")
-                .as_init(bundle_path)
-                .with_systemd(false)
-                .build()
-                .expect("failed building pod sandbox: ensure valid OCI spec and proper container starting point");
+            let mut init_container = match restore_from {
+                // Restore the init container from its checkpoint
+                Some(images) => checkpoint::restore(
+                    Path::new(&images),
+                    &bundle_path,
+                    &pod_path,
+                    AURAE_SELF_IDENTIFIER,
+                )
+                .map_err(|e| {
+                    RuntimeServiceError::RestoreError {
+                        sandbox_id: sandbox_id.clone(),
+                        error: format!("{e:#}"),
+                    }
+                })?,
+                None => {
+                    let start_error =
+                        |error: String| RuntimeServiceError::StartError {
+                            sandbox_id: sandbox_id.clone(),
+                            error,
+                        };
 
-            // Start the init container
-            init_container.start().expect("starting pod sandbox");
+                    // Define the init container startup environment, which
+                    // runs its createRuntime and createContainer hooks
+                    let mut init_container = container_builder
+                        .with_root_path(pod_path)
+                        .expect("Setting pods directory")
+                        .as_init(bundle_path)
+                        .with_systemd(false)
+                        .build()
+                        .map_err(|e| start_error(e.to_string()))?;
+
+                    // Start the init container, which runs its startContainer
+                    // and poststart hooks
+                    if let Err(e) = init_container.start() {
+                        let _ = init_container.delete(true);
+                        return Err(start_error(e.to_string()).into());
+                    }
+                    init_container
+                }
+            };
+
+            // Attach the network namespace of the init container
+            let network = match (host_network, init_container.pid()) {
+                (false, Some(pid)) => {
+                    match self
+                        .attach_network(&runtime.cni, &metadata, pid)
+                        .await
+                    {
+                        Ok(network) => network,
+                        Err(e) => {
+                            let _ = init_container.kill(SIGKILL, false);
+                            return Err(RuntimeServiceError::NetworkError {
+                                sandbox_id: sandbox_id.clone(),
+                                error: format!("{e:#}"),
+                            }
+                            .into());
+                        }
+                    }
+                }
+                _ => None,
+            };
 
             // Assemble the pod sandbox from the init container
             let sandbox_builder =
-                SandboxBuilder::new(sandbox_id.clone(), init_container);
-            sandbox_builder.build()
+                SandboxBuilder::new(sandbox_id.clone(), init_container)
+                    .with_metadata(Some(metadata.clone()))
+                    .with_network(network)
+                    .with_log(log)
+                    .with_log_directory(
+                        Some(PathBuf::from(&log_directory))
+                            .filter(|_| !log_directory.is_empty()),
+                    )
+                    .with_cell(Some(cell_cgroup.clone()))
+                    .with_resolv_conf(resolv_conf);
+            Ok::<_, Status>(sandbox_builder.build())
+        }
+        .await;
+        let sandbox = match sandbox {
+            Ok(sandbox) => sandbox,
+            Err(e) => {
+                let cell_name = cell_cgroup.display().to_string();
+                if let Err(e) = self.cells.free_for_pod(&cell_name).await {
+                    warn!("Failed to free the cell of pod {sandbox_id}: {e:#}");
+                }
+                return Err(e);
+            }
         };
 
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        let pod_dir = runtime.pods_dir().join(&sandbox_id);
+        if let Err(e) = SandboxRecord::new(&sandbox).save(&pod_dir) {
+            warn!("Pod {sandbox_id} will not be recovered by a restart: {e:#}");
+        }
+        let status = sandbox_status(&sandbox_id, &sandbox);
+        if let Some(log) = &sandbox.log {
+            self.stream_log(log).await;
+        }
         sandboxes.add(sandbox_id.clone(), sandbox)?;
+        for event_type in [
+            ContainerEventType::ContainerCreatedEvent,
+            ContainerEventType::ContainerStartedEvent,
+        ] {
+            self.send_event(&sandbox_id, event_type, status.clone());
+        }
 
         Ok(Response::new(RunPodSandboxResponse { pod_sandbox_id: sandbox_id }))
     }
@@ -192,11 +962,53 @@ impl runtime_service_server::RuntimeService for RuntimeService {
     ) -> Result<Response<StopPodSandboxResponse>, Status> {
         let sandbox_id = request.into_inner().pod_sandbox_id;
 
+        if let Some(vm_pod) = self.vm_pod(&sandbox_id).await {
+            vm_pod.stop().await.map_err(|e| {
+                RuntimeServiceError::KillError {
+                    sandbox_id: sandbox_id.clone(),
+                    error: format!("{e:#}"),
+                }
+            })?;
+            self.send_event(
+                &sandbox_id,
+                ContainerEventType::ContainerStoppedEvent,
+                vm_pod_status(&sandbox_id, PodSandboxState::SandboxNotready),
+            );
+            return Ok(Response::new(StopPodSandboxResponse {}));
+        }
+
         let mut sandboxes = self.sandboxes.lock().await;
         let sandbox = sandboxes.get_mut(&sandbox_id)?;
+        // Detach from the network while its namespace is still around
+        if let Some(network) = &sandbox.network {
+            self.detach_network(&sandbox_id, network).await.map_err(|e| {
+                RuntimeServiceError::NetworkError {
+                    sandbox_id: sandbox_id.clone(),
+                    error: format!("{e:#}"),
+                }
+            })?;
+            sandbox.network = None;
+            let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+            let pod_dir = runtime.pods_dir().join(&sandbox_id);
+            if let Err(e) = SandboxRecord::new(sandbox).save(&pod_dir) {
+                warn!("Failed to update the record of pod {sandbox_id}: {e:#}");
+            }
+        }
         sandbox.init.kill(SIGKILL, false).map_err(|e| {
-            RuntimeServiceError::KillError { sandbox_id, error: e.to_string() }
+            RuntimeServiceError::KillError {
+                sandbox_id: sandbox_id.clone(),
+                error: e.to_string(),
+            }
         })?;
+        let status = PodSandboxStatus {
+            state: PodSandboxState::SandboxNotready as i32,
+            ..sandbox_status(&sandbox_id, sandbox)
+        };
+        self.send_event(
+            &sandbox_id,
+            ContainerEventType::ContainerStoppedEvent,
+            status,
+        );
         Ok(Response::new(StopPodSandboxResponse {}))
     }
 
@@ -205,6 +1017,30 @@ impl runtime_service_server::RuntimeService for RuntimeService {
         request: Request<RemovePodSandboxRequest>,
     ) -> Result<Response<RemovePodSandboxResponse>, Status> {
         let sandbox_id = request.into_inner().pod_sandbox_id;
+
+        if let Some(vm_pod) = self.vm_pod(&sandbox_id).await {
+            if vm_pod.is_running().await {
+                return Err(RuntimeServiceError::SandboxNotExited {
+                    sandbox_id,
+                }
+                .into());
+            }
+            vm_pod.remove().await.map_err(|e| {
+                RuntimeServiceError::VmPodError {
+                    sandbox_id: sandbox_id.clone(),
+                    error: format!("{e:#}"),
+                }
+            })?;
+            let _ = self.vm_pods.lock().await.remove(&sandbox_id);
+            self.vm_containers.lock().await.retain(|_, id| *id != sandbox_id);
+            self.send_event(
+                &sandbox_id,
+                ContainerEventType::ContainerDeletedEvent,
+                vm_pod_status(&sandbox_id, PodSandboxState::SandboxNotready),
+            );
+            return Ok(Response::new(RemovePodSandboxResponse {}));
+        }
+
         let mut sandboxes = self.sandboxes.lock().await;
         if sandboxes.get(&sandbox_id)?.init.status()
             != libcontainer::container::ContainerStatus::Stopped
@@ -213,7 +1049,64 @@ impl runtime_service_server::RuntimeService for RuntimeService {
                 RuntimeServiceError::SandboxNotExited { sandbox_id }.into()
             );
         }
+        let delete_error = |e: libcontainer::error::LibcontainerError| {
+            RuntimeServiceError::DeleteError {
+                sandbox_id: sandbox_id.clone(),
+                error: e.to_string(),
+            }
+        };
+        let sandbox = sandboxes.get_mut(&sandbox_id)?;
+        // The cgroups of the containers are removed before the one of the
+        // cell they are nested in
+        for tenant in &mut sandbox.tenants {
+            tenant.delete(true).map_err(delete_error)?;
+        }
+        // Deleting the init container runs its poststop hooks
+        sandbox.init.delete(false).map_err(delete_error)?;
+        if let Some(cell) = &sandbox.cell {
+            self.cells
+                .free_for_pod(&cell.display().to_string())
+                .await
+                .map_err(|e| RuntimeServiceError::PodCellError {
+                    sandbox_id: sandbox_id.clone(),
+                    error: format!("{e:#}"),
+                })?;
+        }
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        volumes::remove(&runtime.volumes_dir().join(&sandbox_id))
+            .map_err(RuntimeServiceError::from)?;
+        // Along with its record
+        match std::fs::remove_dir_all(runtime.pods_dir().join(&sandbox_id)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!("Failed to remove the directory of pod {sandbox_id}: {e}")
+            }
+            _ => {}
+        }
+        let status = PodSandboxStatus {
+            state: PodSandboxState::SandboxNotready as i32,
+            ..sandbox_status(&sandbox_id, sandbox)
+        };
+        let tenants: Vec<_> = sandbox
+            .tenants
+            .iter()
+            .map(|tenant| tenant.id().to_string())
+            .collect();
         sandboxes.remove(&sandbox_id)?;
+        for container_id in
+            tenants.iter().map(String::as_str).chain([sandbox_id.as_str()])
+        {
+            self.observe_service
+                .unregister_log_channel(&log_channel_key(
+                    &sandbox_id,
+                    container_id,
+                ))
+                .await;
+            self.send_event(
+                container_id,
+                ContainerEventType::ContainerDeletedEvent,
+                status.clone(),
+            );
+        }
         Ok(Response::new(RemovePodSandboxResponse {}))
     }
 
@@ -222,8 +1115,33 @@ impl runtime_service_server::RuntimeService for RuntimeService {
         request: Request<PodSandboxStatusRequest>,
     ) -> Result<Response<PodSandboxStatusResponse>, Status> {
         let sandbox_id = request.into_inner().pod_sandbox_id;
+
+        if let Some(vm_pod) = self.vm_pod(&sandbox_id).await {
+            let status = PodSandboxStatus {
+                id: sandbox_id,
+                state: if vm_pod.is_ready().await {
+                    PodSandboxState::SandboxReady
+                } else {
+                    PodSandboxState::SandboxNotready
+                } as i32,
+                network: vm_pod.ip.clone().map(|ip| PodSandboxNetworkStatus {
+                    ip,
+                    additional_ips: vec![],
+                }),
+                ..Default::default()
+            };
+            return Ok(Response::new(PodSandboxStatusResponse {
+                status: Some(status),
+                info: Default::default(),
+                containers_statuses: vec![],
+                timestamp: Utc::now().timestamp(),
+            }));
+        }
+
         let sandboxes = self.sandboxes.lock().await;
-        let state = sandboxes.get(&sandbox_id)?.init.status();
+        let sandbox = sandboxes.get(&sandbox_id)?;
+        let state = sandbox.init.status();
+        let status = sandbox_status(&sandbox_id, sandbox);
         // FIXME: this needs to be mapped more correctly.
         let container_status = proto::cri::ContainerStatus {
             id: sandbox_id,
@@ -232,7 +1150,7 @@ impl runtime_service_server::RuntimeService for RuntimeService {
             ..Default::default()
         };
         Ok(Response::new(PodSandboxStatusResponse {
-            status: None,
+            status: Some(status),
             info: Default::default(),
             containers_statuses: vec![container_status],
             timestamp: Utc::now().timestamp(),
@@ -246,53 +1164,236 @@ impl runtime_service_server::RuntimeService for RuntimeService {
         // TODO: filter
         let sandboxes = self.sandboxes.lock().await;
         let all_sandboxes = sandboxes.list()?;
+        let vm_pods = self.vm_pods.lock().await;
         Ok(Response::new(ListPodSandboxResponse {
             items: all_sandboxes
                 .iter()
-                .map(|s| PodSandbox {
+                .map(|s| s.init.id().to_string())
+                .chain(vm_pods.keys().cloned())
+                .map(|id| PodSandbox {
                     // TODO: other fields
-                    id: s.init.id().to_string(),
+                    runtime_handler: if vm_pods.contains_key(&id) {
+                        VM_RUNTIME_HANDLER.to_string()
+                    } else {
+                        String::new()
+                    },
+                    id,
                     ..Default::default()
                 })
                 .collect(),
         }))
     }
 
+    /// Create a container in the namespaces of the init container of its
+    /// sandbox, from a snapshot of its image and with its mounts. The
+    /// container is started by StartContainer.
     async fn create_container(
         &self,
-        _request: Request<CreateContainerRequest>,
+        request: Request<CreateContainerRequest>,
     ) -> Result<Response<CreateContainerResponse>, Status> {
-        // Handle Request
-        // let r = request.into_inner();
-        // // Handle Config
-        // let config = r.config.expect("config from create container request");
-        // // Metadata
-        // let metadata = config.metadata.expect("metadata from config");
-        //
-        // // TODO: Pull sandbox from cache
-        // // TODO schedule as tenant container
+        let request = request.into_inner();
+        let ValidatedCreateContainerRequest { pod_sandbox_id, config, .. } =
+            ValidatedCreateContainerRequest::validate(request.clone(), None)?;
+        let metadata = config.metadata.clone().expect("validated metadata");
+        let container_id =
+            format!("{pod_sandbox_id}_{}_{}", metadata.name, metadata.attempt);
 
-        todo!()
+        // The containers of pods running in VMs are created by their agent
+        if let Some(vm_pod) = self.vm_pod(&pod_sandbox_id).await {
+            return self.create_vm_pod_container(&vm_pod, request).await;
+        }
+
+        let mut sandboxes = self.sandboxes.lock().await;
+        let sandbox = sandboxes.get_mut(&pod_sandbox_id)?;
+        if sandbox.tenants.iter().any(|tenant| tenant.id() == container_id) {
+            return Err(
+                RuntimeServiceError::ContainerExists { container_id }.into()
+            );
+        }
+        let Some(sandbox_pid) = sandbox.init.pid() else {
+            return Err(RuntimeServiceError::ContainerNotRunning {
+                container_id: pod_sandbox_id,
+            }
+            .into());
+        };
+
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        let image = config.image.as_ref().map(|image| image.image.as_str());
+        let (rootfs, image_config) = self
+            .images
+            .prepare_container(image.unwrap_or_default(), &container_id)
+            .map_err(RuntimeServiceError::from)?;
+        // The cgroup of the container is nested in the one of the cell of its
+        // pod, so the limits of the pod apply to its containers together
+        let cgroups_path =
+            sandbox.cell.as_ref().map(|cell| cell.join(&container_id));
+        let resolv_conf = sandbox.resolv_conf.clone();
+        // The log path of the container is relative to the log directory of
+        // its pod
+        let log_path = sandbox
+            .log_directory
+            .as_ref()
+            .filter(|_| !config.log_path.is_empty())
+            .map(|log_directory| log_directory.join(&config.log_path));
+        let created = (|| -> Result<
+            (Container, Option<ContainerLog>),
+            RuntimeServiceError,
+        > {
+            let mut mounts = volumes::mounts(
+                &config.mounts,
+                &runtime.volumes_dir().join(&pod_sandbox_id),
+            )?;
+            let readonly_rootfs = config
+                .linux
+                .as_ref()
+                .and_then(|linux| linux.security_context.as_ref())
+                .is_some_and(|context| context.readonly_rootfs);
+            mounts.extend(resolv_conf.and_then(|path| {
+                dns::mount(&path, &config.mounts, readonly_rootfs)
+            }));
+            let spec = AuraeOCIBuilder::new()
+                .overload_container_config(
+                    &config,
+                    image_config.as_ref(),
+                    rootfs,
+                    sandbox_pid.as_raw(),
+                    mounts,
+                )
+                .with_cgroups_path(cgroups_path)
+                .build()
+                .map_err(|e| RuntimeServiceError::InvalidContainerConfig {
+                    container_id: container_id.clone(),
+                    error: e.to_string(),
+                })?;
+
+            let create_error =
+                |error: String| RuntimeServiceError::CreateError {
+                    container_id: container_id.clone(),
+                    error,
+                };
+            let bundle_path = runtime.bundles_dir().join(&container_id);
+            std::fs::create_dir_all(&bundle_path)
+                .map_err(|e| create_error(e.to_string()))?;
+            spec.save(bundle_path.join("config.json"))
+                .map_err(|e| create_error(e.to_string()))?;
+            let mut container_builder = ContainerBuilder::new(
+                container_id.clone(),
+                SyscallType::default(),
+            )
+            .with_root_path(runtime.pods_dir().join(&pod_sandbox_id))
+            .map_err(|e| create_error(e.to_string()))?;
+
+            // Copy the output of the container to its CRI log
+            let log = match log_path {
+                Some(path) => {
+                    let log_error =
+                        |e: std::io::Error| RuntimeServiceError::LogError {
+                            container_id: container_id.clone(),
+                            error: e.to_string(),
+                        };
+                    let log = ContainerLog::open(
+                        path,
+                        runtime.container_logs.clone(),
+                    )
+                    .map_err(log_error)?
+                    .with_channel(log_channel(&pod_sandbox_id, &container_id));
+                    let (stdout, stderr) = log.pipes().map_err(log_error)?;
+                    container_builder = container_builder
+                        .with_stdout(stdout)
+                        .with_stderr(stderr);
+                    Some(log)
+                }
+                None => None,
+            };
+
+            let tenant = container_builder
+                .as_init(bundle_path)
+                .with_systemd(false)
+                .build()
+                .map_err(|e| create_error(e.to_string()))?;
+            Ok((tenant, log))
+        })();
+        match created {
+            Ok((tenant, log)) => {
+                sandbox.tenants.push(tenant);
+                if let Some(log) = log {
+                    self.stream_log(&log).await;
+                    let _ =
+                        sandbox.tenant_logs.insert(container_id.clone(), log);
+                }
+            }
+            Err(e) => {
+                let _ = self.images.remove_container(&container_id);
+                return Err(e.into());
+            }
+        }
+        self.send_event(
+            &container_id,
+            ContainerEventType::ContainerCreatedEvent,
+            sandbox_status(&pod_sandbox_id, sandbox),
+        );
+
+        Ok(Response::new(CreateContainerResponse { container_id }))
     }
 
     async fn start_container(
         &self,
-        _request: Request<StartContainerRequest>,
+        request: Request<StartContainerRequest>,
     ) -> Result<Response<StartContainerResponse>, Status> {
+        let request = request.into_inner();
+        if let Some((sandbox_id, vm_pod)) =
+            self.vm_pod_of(&request.container_id).await
+        {
+            let container_id = request.container_id.clone();
+            let response = vm_pod.agent().start_container(request).await?;
+            self.send_event(
+                &container_id,
+                ContainerEventType::ContainerStartedEvent,
+                vm_pod_status(&sandbox_id, PodSandboxState::SandboxReady),
+            );
+            return Ok(response);
+        }
         todo!()
     }
 
     async fn stop_container(
         &self,
-        _request: Request<StopContainerRequest>,
+        request: Request<StopContainerRequest>,
     ) -> Result<Response<StopContainerResponse>, Status> {
+        let request = request.into_inner();
+        if let Some((sandbox_id, vm_pod)) =
+            self.vm_pod_of(&request.container_id).await
+        {
+            let container_id = request.container_id.clone();
+            let response = vm_pod.agent().stop_container(request).await?;
+            self.send_event(
+                &container_id,
+                ContainerEventType::ContainerStoppedEvent,
+                vm_pod_status(&sandbox_id, PodSandboxState::SandboxReady),
+            );
+            return Ok(response);
+        }
         todo!()
     }
 
     async fn remove_container(
         &self,
-        _request: Request<RemoveContainerRequest>,
+        request: Request<RemoveContainerRequest>,
     ) -> Result<Response<RemoveContainerResponse>, Status> {
+        let request = request.into_inner();
+        if let Some((sandbox_id, vm_pod)) =
+            self.vm_pod_of(&request.container_id).await
+        {
+            let container_id = request.container_id.clone();
+            let response = vm_pod.agent().remove_container(request).await?;
+            let _ = self.vm_containers.lock().await.remove(&container_id);
+            self.send_event(
+                &container_id,
+                ContainerEventType::ContainerDeletedEvent,
+                vm_pod_status(&sandbox_id, PodSandboxState::SandboxReady),
+            );
+            return Ok(response);
+        }
         todo!()
     }
 
@@ -305,65 +1406,269 @@ impl runtime_service_server::RuntimeService for RuntimeService {
 
     async fn container_status(
         &self,
-        _request: Request<ContainerStatusRequest>,
+        request: Request<ContainerStatusRequest>,
     ) -> Result<Response<ContainerStatusResponse>, Status> {
+        let request = request.into_inner();
+        if let Some((_, vm_pod)) = self.vm_pod_of(&request.container_id).await {
+            return vm_pod.agent().container_status(request).await;
+        }
         todo!()
     }
 
+    /// Rewrite the cgroup limits of a running container, without restarting
+    /// it.
     async fn update_container_resources(
         &self,
-        _request: Request<UpdateContainerResourcesRequest>,
+        request: Request<UpdateContainerResourcesRequest>,
     ) -> Result<Response<UpdateContainerResourcesResponse>, Status> {
-        todo!()
+        let ValidatedUpdateContainerResourcesRequest {
+            container_id,
+            linux,
+            ..
+        } = ValidatedUpdateContainerResourcesRequest::validate(
+            request.into_inner(),
+            None,
+        )?;
+        let sandboxes = self.sandboxes.lock().await;
+        let Some((_, _, container)) =
+            sandboxes.containers().find(|(_, id, _)| *id == container_id)
+        else {
+            return Err(RuntimeServiceError::ContainerNotFound {
+                container_id,
+            }
+            .into());
+        };
+        let pid = match (container.status(), container.pid()) {
+            (libcontainer::container::ContainerStatus::Running, Some(pid)) => {
+                pid
+            }
+            _ => {
+                return Err(RuntimeServiceError::ContainerNotRunning {
+                    container_id,
+                }
+                .into())
+            }
+        };
+        // Windows resources do not apply to Linux containers
+        if let Some(linux) = linux {
+            resources::update(pid.as_raw(), &linux).map_err(|e| {
+                RuntimeServiceError::UpdateResourcesError {
+                    container_id,
+                    error: format!("{e:#}"),
+                }
+            })?;
+        }
+        Ok(Response::new(UpdateContainerResourcesResponse {}))
     }
 
+    /// Reopen the log of a container at its path, after kubelet rotated it.
     async fn reopen_container_log(
         &self,
-        _request: Request<ReopenContainerLogRequest>,
+        request: Request<ReopenContainerLogRequest>,
     ) -> Result<Response<ReopenContainerLogResponse>, Status> {
-        todo!()
+        let container_id = request.into_inner().container_id;
+        let sandboxes = self.sandboxes.lock().await;
+        sandboxes.container_log(&container_id)?.reopen().map_err(|e| {
+            RuntimeServiceError::LogError { container_id, error: e.to_string() }
+        })?;
+        Ok(Response::new(ReopenContainerLogResponse {}))
     }
 
+    /// Run a command in a running container and return its output once it
+    /// exits. The command is killed once it runs past the timeout of the
+    /// request, if any.
     async fn exec_sync(
         &self,
-        _request: Request<ExecSyncRequest>,
+        request: Request<ExecSyncRequest>,
     ) -> Result<Response<ExecSyncResponse>, Status> {
-        todo!()
+        let r = request.into_inner();
+        let container_id = r.container_id;
+        if r.cmd.is_empty() {
+            return Err(RuntimeServiceError::InvalidStreamRequest {
+                container_id,
+                reason: "missing command",
+            }
+            .into());
+        }
+
+        let (pid, context) = self.exec_target(&container_id).await?;
+        let exec = Exec {
+            pid,
+            cmd: r.cmd,
+            tty: false,
+            stdin: false,
+            stdout: true,
+            stderr: true,
+            context,
+        };
+        let timeout = u64::try_from(r.timeout)
+            .ok()
+            .filter(|timeout| *timeout > 0)
+            .map(Duration::from_secs);
+        let output =
+            ExecProcess::output(&exec, timeout).await.map_err(|e| {
+                RuntimeServiceError::ExecError {
+                    container_id: container_id.clone(),
+                    error: e.to_string(),
+                }
+            })?;
+        let Some(output) = output else {
+            return Err(RuntimeServiceError::ExecTimeout {
+                container_id,
+                timeout: r.timeout,
+            }
+            .into());
+        };
+        Ok(Response::new(ExecSyncResponse {
+            stdout: output.stdout,
+            stderr: output.stderr,
+            exit_code: output.exit_code,
+        }))
     }
 
+    /// Run a command in a running container, returning the URL of the
+    /// streaming session running it.
     async fn exec(
         &self,
-        _request: Request<ExecRequest>,
+        request: Request<ExecRequest>,
     ) -> Result<Response<ExecResponse>, Status> {
-        todo!()
+        let r = request.into_inner();
+        let container_id = r.container_id;
+        let invalid = |reason| RuntimeServiceError::InvalidStreamRequest {
+            container_id: container_id.clone(),
+            reason,
+        };
+        if r.cmd.is_empty() {
+            return Err(invalid("missing command").into());
+        }
+        if !(r.stdin || r.stdout || r.stderr) {
+            return Err(
+                invalid("one of stdin, stdout or stderr is required").into()
+            );
+        }
+        if r.tty && r.stderr {
+            return Err(invalid("stderr is not supported with a tty").into());
+        }
+
+        let (pid, context) = self.exec_target(&container_id).await?;
+        let url = self.streaming.url(Session::Exec(Exec {
+            pid,
+            cmd: r.cmd,
+            tty: r.tty,
+            stdin: r.stdin,
+            stdout: r.stdout,
+            stderr: r.stderr,
+            context,
+        }));
+        Ok(Response::new(ExecResponse { url }))
     }
 
+    /// Attach to the output of a running container, returning the URL of
+    /// the streaming session following it. The stdin of containers is not
+    /// kept, so it cannot be attached to.
     async fn attach(
         &self,
-        _request: Request<AttachRequest>,
+        request: Request<AttachRequest>,
     ) -> Result<Response<AttachResponse>, Status> {
-        todo!()
+        let r = request.into_inner();
+        let container_id = r.container_id;
+        let invalid = |reason| RuntimeServiceError::InvalidStreamRequest {
+            container_id: container_id.clone(),
+            reason,
+        };
+        if r.stdin {
+            return Err(invalid("the stdin of containers is not kept").into());
+        }
+        if !(r.stdout || r.stderr) {
+            return Err(invalid("one of stdout or stderr is required").into());
+        }
+        if r.tty && r.stderr {
+            return Err(invalid("stderr is not supported with a tty").into());
+        }
+
+        let log =
+            self.sandboxes.lock().await.container_log(&container_id)?.clone();
+        let url = self.streaming.url(Session::Attach(Attach {
+            container_id,
+            log,
+            stdout: r.stdout,
+            stderr: r.stderr,
+            tty: r.tty,
+        }));
+        Ok(Response::new(AttachResponse { url }))
     }
 
+    /// Forward ports of the network namespace of a pod sandbox, returning
+    /// the URL of the streaming session forwarding them.
     async fn port_forward(
         &self,
-        _request: Request<PortForwardRequest>,
+        request: Request<PortForwardRequest>,
     ) -> Result<Response<PortForwardResponse>, Status> {
-        todo!()
+        let r = request.into_inner();
+        let pod_sandbox_id = r.pod_sandbox_id;
+        let ports = r
+            .port
+            .into_iter()
+            .map(u16::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| RuntimeServiceError::InvalidStreamRequest {
+                container_id: pod_sandbox_id.clone(),
+                reason: "invalid port",
+            })?;
+
+        // the network namespace of a sandbox is the one of its init container
+        let pid = self.sandboxes.lock().await.container_pid(&pod_sandbox_id)?;
+        let url = self
+            .streaming
+            .url(Session::PortForward(PortForward { pid, ports }));
+        Ok(Response::new(PortForwardResponse { url }))
     }
 
+    /// Resource usage of a container, read from its cgroup and rootfs.
     async fn container_stats(
         &self,
-        _request: Request<ContainerStatsRequest>,
+        request: Request<ContainerStatsRequest>,
     ) -> Result<Response<ContainerStatsResponse>, Status> {
-        todo!()
+        let container_id = request.into_inner().container_id;
+        let sandboxes = self.sandboxes.lock().await;
+        let Some((_, _, container)) =
+            sandboxes.containers().find(|(_, id, _)| *id == container_id)
+        else {
+            return Err(RuntimeServiceError::ContainerNotFound {
+                container_id,
+            }
+            .into());
+        };
+        let stats =
+            container_stats(&container_id, container, &self.cpu_samples);
+        Ok(Response::new(ContainerStatsResponse { stats: Some(stats) }))
     }
 
+    /// Resource usage of the containers matching the filter.
     async fn list_container_stats(
         &self,
-        _request: Request<ListContainerStatsRequest>,
+        request: Request<ListContainerStatsRequest>,
     ) -> Result<Response<ListContainerStatsResponse>, Status> {
-        todo!()
+        let filter = request.into_inner().filter.unwrap_or_default();
+        let sandboxes = self.sandboxes.lock().await;
+        let ids: Vec<&str> =
+            sandboxes.containers().map(|(_, id, _)| id).collect();
+        // Containers have no labels, so no selector matches any of them
+        let stats = sandboxes
+            .containers()
+            .filter(|(sandbox_id, id, _)| {
+                (filter.id.is_empty() || filter.id == *id)
+                    && (filter.pod_sandbox_id.is_empty()
+                        || filter.pod_sandbox_id == **sandbox_id)
+                    && filter.label_selector.is_empty()
+            })
+            .map(|(_, id, container)| {
+                container_stats(id, container, &self.cpu_samples)
+            })
+            .collect();
+        // Forget the containers that are gone
+        self.cpu_samples.retain(&ids);
+        Ok(Response::new(ListContainerStatsResponse { stats }))
     }
 
     async fn pod_sandbox_stats(
@@ -380,35 +1685,166 @@ impl runtime_service_server::RuntimeService for RuntimeService {
         todo!()
     }
 
+    /// Sets the pod CIDR of the node, which the CNI config template is
+    /// rendered with. An empty CIDR leaves it as it is.
     async fn update_runtime_config(
         &self,
-        _request: Request<UpdateRuntimeConfigRequest>,
+        request: Request<UpdateRuntimeConfigRequest>,
     ) -> Result<Response<UpdateRuntimeConfigResponse>, Status> {
-        todo!()
+        let pod_cidr = request
+            .into_inner()
+            .runtime_config
+            .and_then(|config| config.network_config)
+            .map(|network_config| network_config.pod_cidr)
+            .unwrap_or_default();
+        if pod_cidr.is_empty() {
+            return Ok(Response::new(UpdateRuntimeConfigResponse {}));
+        }
+        // Dual-stack nodes have a CIDR per IP family
+        for cidr in pod_cidr.split(',') {
+            if let Err(e) = cidr.trim().parse::<ipnetwork::IpNetwork>() {
+                return Err(RuntimeServiceError::InvalidPodCidr {
+                    pod_cidr,
+                    error: e.to_string(),
+                }
+                .into());
+            }
+        }
+
+        let mut current = self.pod_cidr.lock().await;
+        if *current == pod_cidr {
+            return Ok(Response::new(UpdateRuntimeConfigResponse {}));
+        }
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        let first = pod_cidr.split(',').next().unwrap_or_default().trim();
+        cni::render_template(&runtime.cni, first).map_err(|e| {
+            RuntimeServiceError::RuntimeConfigError { error: format!("{e:#}") }
+        })?;
+        info!("Pod CIDR of the node set to {pod_cidr}");
+        *current = pod_cidr;
+        Ok(Response::new(UpdateRuntimeConfigResponse {}))
     }
 
+    /// The conditions of the runtime: RuntimeReady while the cgroup tree
+    /// cells and containers are created in is usable, NetworkReady while
+    /// pods can be attached to a network, the CNI network of the node or,
+    /// without one, the pool of addresses auraed leases itself.
     async fn status(
         &self,
-        _request: Request<StatusRequest>,
+        request: Request<StatusRequest>,
     ) -> Result<Response<StatusResponse>, Status> {
-        todo!()
+        let runtime = crate::AURAED_RUNTIME.get().expect("runtime");
+        let conditions = vec![
+            condition(
+                RUNTIME_READY,
+                "CgroupsNotReady",
+                readiness::cgroup_tree(),
+            ),
+            condition(
+                NETWORK_READY,
+                "NetworkPluginNotReady",
+                self.network_ready(&runtime.cni),
+            ),
+        ];
+
+        let info = if request.into_inner().verbose {
+            let config = serde_json::json!({
+                "podCIDR": *self.pod_cidr.lock().await,
+                "cniConfDir": runtime.cni.conf_dir,
+                "cniBinDirs": runtime.cni.bin_dirs,
+            });
+            HashMap::from([("config".to_string(), config.to_string())])
+        } else {
+            HashMap::new()
+        };
+        Ok(Response::new(StatusResponse {
+            status: Some(RuntimeStatus { conditions }),
+            info,
+        }))
     }
 
+    /// Dump a running container into the directory at the location of the
+    /// request with CRIU, leaving it running. A pod is restored from the
+    /// checkpoint of its init container, going by the ID of the pod, when
+    /// it is run with the [RESTORE_ANNOTATION] naming the directory.
     async fn checkpoint_container(
         &self,
-        _request: Request<CheckpointContainerRequest>,
+        request: Request<CheckpointContainerRequest>,
     ) -> Result<Response<CheckpointContainerResponse>, Status> {
-        todo!()
+        let CheckpointContainerRequest { container_id, location, timeout } =
+            request.into_inner();
+        if location.is_empty() {
+            return Err(RuntimeServiceError::MissingCheckpointLocation {
+                container_id,
+            }
+            .into());
+        }
+        let container = {
+            let sandboxes = self.sandboxes.lock().await;
+            let _ = sandboxes.container_pid(&container_id)?;
+            let Some((_, _, container)) =
+                sandboxes.containers().find(|(_, id, _)| *id == container_id)
+            else {
+                return Err(RuntimeServiceError::ContainerNotFound {
+                    container_id,
+                }
+                .into());
+            };
+            container.clone()
+        };
+
+        let dump = tokio::task::spawn_blocking(move || {
+            checkpoint::dump(&container, Path::new(&location))
+        });
+        // A timeout of 0 leaves the duration to the runtime
+        let dump = if timeout > 0 {
+            let timed_out = |_| RuntimeServiceError::CheckpointError {
+                container_id: container_id.clone(),
+                error: format!("timed out after {timeout}s"),
+            };
+            tokio::time::timeout(Duration::from_secs(timeout as u64), dump)
+                .await
+                .map_err(timed_out)?
+        } else {
+            dump.await
+        };
+        dump.map_err(anyhow::Error::from).and_then(|dumped| dumped).map_err(
+            |e| RuntimeServiceError::CheckpointError {
+                container_id,
+                error: format!("{e:#}"),
+            },
+        )?;
+        Ok(Response::new(CheckpointContainerResponse {}))
     }
 
     type GetContainerEventsStream =
         ReceiverStream<Result<ContainerEventResponse, Status>>;
 
+    /// Stream the lifecycle events of sandboxes and containers from now on,
+    /// until the client goes away.
     async fn get_container_events(
         &self,
         _request: Request<GetEventsRequest>,
     ) -> Result<Response<Self::GetContainerEventsStream>, Status> {
-        todo!()
+        let mut events = self.events.subscribe();
+        let (tx, rx) = mpsc::channel(EVENTS_CAPACITY);
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Container events client missed {missed} events");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if tx.send(Ok(event)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
     }
 
     async fn list_metric_descriptors(
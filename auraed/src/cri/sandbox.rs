@@ -14,7 +14,14 @@
 \* -------------------------------------------------------------------------- */
 #![allow(dead_code)]
 
+use super::cni::Attachment;
+use super::container_log::ContainerLog;
+use crate::network::Veth;
 use libcontainer::container::Container;
+use proto::cri::PodSandboxMetadata;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone, Default)]
 pub struct Sandbox {
@@ -27,6 +34,10 @@ pub struct Sandbox {
     /// to access the Pod sandbox in the internal cache mechanism.
     name: String,
 
+    /// The metadata of the pod the sandbox was run for, None for sandboxes
+    /// recovered from records without it.
+    pub(crate) metadata: Option<PodSandboxMetadata>,
+
     /// Init containers are the "preliminary" container that is used to begin
     /// the isolation process in a sandbox.
     ///
@@ -44,21 +55,137 @@ pub struct Sandbox {
     /// In the case of large enterprise workload management, these specifically
     /// are "your app".
     pub(crate) tenants: Vec<Container>,
+
+    /// The attachment of the network namespace of the init container, None
+    /// for pods on the host network.
+    pub(crate) network: Option<PodNetwork>,
+
+    /// The CRI log the output of the init container is copied to, None if
+    /// the pod has no log directory.
+    pub(crate) log: Option<ContainerLog>,
+
+    /// Where kubelet expects the logs of the containers of the pod, below
+    /// the log paths of the containers. None if the pod has no log
+    /// directory.
+    pub(crate) log_directory: Option<PathBuf>,
+
+    /// The CRI logs the output of the tenants is copied to, by container ID.
+    /// Tenants without a log path have none.
+    pub(crate) tenant_logs: HashMap<String, ContainerLog>,
+
+    /// The cell of the pod, whose cgroup is the parent of the cgroups of its
+    /// containers, None for pods restored without one.
+    pub(crate) cell: Option<PathBuf>,
+
+    /// The resolv.conf mounted in the containers of the pod, None if the
+    /// pod has no DNS config.
+    pub(crate) resolv_conf: Option<PathBuf>,
+}
+
+/// How the network namespace of a sandbox is attached.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum PodNetwork {
+    /// To the CNI network configured on the node
+    Cni(Attachment),
+    /// Directly to the host, with an address of the pod pool, while no CNI
+    /// network is configured
+    Veth(Veth),
+}
+
+impl PodNetwork {
+    /// The IPs assigned to the sandbox, without their prefix length.
+    pub fn ips(&self) -> Vec<String> {
+        match self {
+            PodNetwork::Cni(attachment) => attachment.ips(),
+            PodNetwork::Veth(veth) => vec![veth.address().to_string()],
+        }
+    }
 }
 
 pub struct SandboxBuilder {
     name: String,
     init: Container,
+    metadata: Option<PodSandboxMetadata>,
+    network: Option<PodNetwork>,
+    log: Option<ContainerLog>,
+    log_directory: Option<PathBuf>,
+    cell: Option<PathBuf>,
+    resolv_conf: Option<PathBuf>,
+    tenants: Vec<Container>,
 }
 
 impl SandboxBuilder {
     // TODO: Consider embedding the ContainerBuilder directly into this SandboxBuilder. For now just require a started init container.
     pub fn new(name: String, init: Container) -> SandboxBuilder {
-        SandboxBuilder { name, init }
+        SandboxBuilder {
+            name,
+            init,
+            metadata: None,
+            network: None,
+            log: None,
+            log_directory: None,
+            cell: None,
+            resolv_conf: None,
+            tenants: vec![],
+        }
+    }
+
+    pub fn with_metadata(
+        mut self,
+        metadata: Option<PodSandboxMetadata>,
+    ) -> Self {
+        self.metadata = metadata;
+        self
     }
+
+    pub fn with_network(mut self, network: Option<PodNetwork>) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn with_log(mut self, log: Option<ContainerLog>) -> Self {
+        self.log = log;
+        self
+    }
+
+    pub fn with_log_directory(
+        mut self,
+        log_directory: Option<PathBuf>,
+    ) -> Self {
+        self.log_directory = log_directory;
+        self
+    }
+
+    pub fn with_cell(mut self, cell: Option<PathBuf>) -> Self {
+        self.cell = cell;
+        self
+    }
+
+    pub fn with_resolv_conf(mut self, resolv_conf: Option<PathBuf>) -> Self {
+        self.resolv_conf = resolv_conf;
+        self
+    }
+
+    /// The containers of a sandbox recovered after a restart of auraed.
+    pub fn with_tenants(mut self, tenants: Vec<Container>) -> Self {
+        self.tenants = tenants;
+        self
+    }
+
     /// The SandboxBuilder will require that the libcontainer::Container be built before
     /// we can build the Sandbox.
     pub fn build(self) -> Sandbox {
-        Sandbox { name: self.name, init: self.init, tenants: vec![] }
+        Sandbox {
+            name: self.name,
+            metadata: self.metadata,
+            init: self.init,
+            tenants: self.tenants,
+            network: self.network,
+            log: self.log,
+            log_directory: self.log_directory,
+            tenant_logs: HashMap::new(),
+            cell: self.cell,
+            resolv_conf: self.resolv_conf,
+        }
     }
 }
\ No newline at end of file
@@ -14,8 +14,11 @@
 \* -------------------------------------------------------------------------- */
 
 use super::error::{Result, RuntimeServiceError};
+use crate::cri::container_log::ContainerLog;
 use crate::cri::sandbox::Sandbox;
+use libcontainer::container::Container;
 use std::collections::HashMap;
+use std::path::PathBuf;
 
 /// Cache is the in-memory cache which is embedded
 /// into the SandboxCache structure which provides access
@@ -50,6 +53,104 @@ impl SandboxCache {
         Ok(sandbox)
     }
 
+    /// PID of the init process of the container `container_id`, the init
+    /// container of a sandbox going by the ID of the sandbox.
+    pub fn container_pid(&self, container_id: &String) -> Result<i32> {
+        Ok(self.running_container(container_id)?.0)
+    }
+
+    /// The bundle of the running container `container_id`, the init
+    /// container of a sandbox going by the ID of the sandbox.
+    pub fn container_bundle(&self, container_id: &String) -> Result<PathBuf> {
+        Ok(self.running_container(container_id)?.1.bundle().clone())
+    }
+
+    /// The PID of the init process of the running container `container_id`
+    /// along with the container.
+    fn running_container(
+        &self,
+        container_id: &String,
+    ) -> Result<(i32, &Container)> {
+        let container = match self.cache.get(container_id) {
+            Some(sandbox) => Some(&sandbox.init),
+            None => self
+                .cache
+                .values()
+                .flat_map(|sandbox| &sandbox.tenants)
+                .find(|tenant| tenant.id() == container_id.as_str()),
+        };
+        let Some(container) = container else {
+            return Err(RuntimeServiceError::ContainerNotFound {
+                container_id: container_id.clone(),
+            });
+        };
+        match container.pid() {
+            Some(pid)
+                if container.status()
+                    == libcontainer::container::ContainerStatus::Running =>
+            {
+                Ok((pid.as_raw(), container))
+            }
+            _ => Err(RuntimeServiceError::ContainerNotRunning {
+                container_id: container_id.clone(),
+            }),
+        }
+    }
+
+    /// The CRI log of the container `container_id`. The init containers of
+    /// sandboxes go by the ID of the sandbox. Containers of sandboxes
+    /// without a log directory and containers without a log path have
+    /// none.
+    pub fn container_log(
+        &self,
+        container_id: &String,
+    ) -> Result<&ContainerLog> {
+        if let Some(sandbox) = self.cache.get(container_id) {
+            return sandbox.log.as_ref().ok_or_else(|| {
+                RuntimeServiceError::NoContainerLog {
+                    container_id: container_id.clone(),
+                }
+            });
+        }
+        if let Some(log) = self
+            .cache
+            .values()
+            .find_map(|sandbox| sandbox.tenant_logs.get(container_id))
+        {
+            return Ok(log);
+        }
+        let tenant = self
+            .cache
+            .values()
+            .flat_map(|sandbox| &sandbox.tenants)
+            .any(|tenant| tenant.id() == container_id.as_str());
+        Err(if tenant {
+            RuntimeServiceError::NoContainerLog {
+                container_id: container_id.clone(),
+            }
+        } else {
+            RuntimeServiceError::ContainerNotFound {
+                container_id: container_id.clone(),
+            }
+        })
+    }
+
+    /// The containers of all sandboxes with the ID of their sandbox and
+    /// their own, the init containers going by the ID of their sandbox.
+    pub fn containers(
+        &self,
+    ) -> impl Iterator<Item = (&String, &str, &Container)> {
+        self.cache.iter().flat_map(|(sandbox_id, sandbox)| {
+            std::iter::once((sandbox_id, sandbox_id.as_str(), &sandbox.init))
+                .chain(
+                    sandbox
+                        .tenants
+                        .iter()
+                        .map(move |tenant| (sandbox_id, tenant.id(), tenant)),
+                )
+        })
+    }
+
     pub fn list(&self) -> Result<Vec<&Sandbox>> {
         Ok(self.cache.values().collect())
     }
@@ -0,0 +1,108 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! The records of pod sandboxes, kept in the directory of each sandbox.
+//!
+//! The containers of a sandbox are recovered from the state the container
+//! runtime keeps of them, what auraed attached to the sandbox besides is
+//! recovered from its record, so that a restart of auraed does not strand
+//! the pods of the node.
+
+use super::sandbox::{PodNetwork, Sandbox};
+use proto::cri::PodSandboxMetadata;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The file of the record in the directory of a sandbox
+const RECORD_FILE: &str = "sandbox.json";
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SandboxRecord {
+    pub network: Option<PodNetwork>,
+    pub cell: Option<PathBuf>,
+    /// Missing in the records of sandboxes run before events were sent
+    #[serde(default)]
+    pub metadata: Option<PodSandboxMetadata>,
+    #[serde(default)]
+    pub log_directory: Option<PathBuf>,
+}
+
+impl SandboxRecord {
+    pub fn new(sandbox: &Sandbox) -> Self {
+        Self {
+            network: sandbox.network.clone(),
+            cell: sandbox.cell.clone(),
+            metadata: sandbox.metadata.clone(),
+            log_directory: sandbox.log_directory.clone(),
+        }
+    }
+
+    /// Reads the record of the sandbox in `pod_dir`.
+    pub fn load(pod_dir: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read(pod_dir.join(RECORD_FILE))?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Writes the record to the directory of the sandbox `pod_dir`,
+    /// replacing the file so a crash leaves either record.
+    pub fn save(&self, pod_dir: &Path) -> anyhow::Result<()> {
+        std::fs::create_dir_all(pod_dir)?;
+        let tmp = pod_dir.join(format!("{RECORD_FILE}.tmp"));
+        std::fs::write(&tmp, serde_json::to_vec(self)?)?;
+        std::fs::rename(tmp, pod_dir.join(RECORD_FILE))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_of_older_sandboxes_are_read() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        std::fs::write(
+            dir.path().join(RECORD_FILE),
+            r#"{"network":null,"cell":"pod-nginx-0123abcd"}"#,
+        )
+        .expect("wrote record");
+
+        let loaded = SandboxRecord::load(dir.path()).expect("loaded record");
+        assert!(loaded.metadata.is_none());
+        assert!(loaded.log_directory.is_none());
+    }
+
+    #[test]
+    fn records_round_trip() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let record = SandboxRecord {
+            network: None,
+            cell: Some(PathBuf::from("pod-nginx-0123abcd")),
+            metadata: Some(PodSandboxMetadata {
+                name: "nginx".into(),
+                uid: "0123abcd".into(),
+                namespace: "default".into(),
+                attempt: 1,
+            }),
+            log_directory: Some(PathBuf::from("/var/log/pods/nginx")),
+        };
+        record.save(dir.path()).expect("saved record");
+
+        let loaded = SandboxRecord::load(dir.path()).expect("loaded record");
+        assert!(loaded.network.is_none());
+        assert_eq!(loaded.cell, record.cell);
+        assert_eq!(loaded.metadata, record.metadata);
+        assert_eq!(loaded.log_directory, record.log_directory);
+    }
+}
@@ -0,0 +1,494 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! The security context of the containers of pod sandboxes.
+//!
+//! The [LinuxContainerSecurityContext] of a container is translated into
+//! its OCI spec: its capabilities, its seccomp and AppArmor profiles, the
+//! no_new_privs flag, the paths of /proc and /sys masked or made read-only,
+//! its user and the read-only root filesystem. A privileged container is
+//! given all capabilities and access to all devices, and is neither
+//! confined by seccomp nor AppArmor nor has any path masked.
+//!
+//! Seccomp profiles of the node are JSON files in the format of the
+//! `seccomp` of an OCI spec, which the seccomp profiles of Docker also
+//! follow.
+
+use oci_spec::runtime::{
+    Capability, LinuxCapabilities, LinuxDeviceCgroupBuilder, LinuxSeccomp,
+    LinuxSeccompAction, LinuxSeccompBuilder, LinuxSyscallBuilder, Spec,
+};
+use proto::cri::security_profile::ProfileType;
+use proto::cri::{LinuxContainerSecurityContext, SecurityProfile};
+use std::collections::HashSet;
+use std::path::Path;
+use thiserror::Error;
+
+/// The capabilities of Linux, without their CAP_ prefix
+const ALL_CAPABILITIES: &[&str] = &[
+    "CHOWN",
+    "DAC_OVERRIDE",
+    "DAC_READ_SEARCH",
+    "FOWNER",
+    "FSETID",
+    "KILL",
+    "SETGID",
+    "SETUID",
+    "SETPCAP",
+    "LINUX_IMMUTABLE",
+    "NET_BIND_SERVICE",
+    "NET_BROADCAST",
+    "NET_ADMIN",
+    "NET_RAW",
+    "IPC_LOCK",
+    "IPC_OWNER",
+    "SYS_MODULE",
+    "SYS_RAWIO",
+    "SYS_CHROOT",
+    "SYS_PTRACE",
+    "SYS_PACCT",
+    "SYS_ADMIN",
+    "SYS_BOOT",
+    "SYS_NICE",
+    "SYS_RESOURCE",
+    "SYS_TIME",
+    "SYS_TTY_CONFIG",
+    "MKNOD",
+    "LEASE",
+    "AUDIT_WRITE",
+    "AUDIT_CONTROL",
+    "SETFCAP",
+    "MAC_OVERRIDE",
+    "MAC_ADMIN",
+    "SYSLOG",
+    "WAKE_ALARM",
+    "BLOCK_SUSPEND",
+    "AUDIT_READ",
+    "PERFMON",
+    "BPF",
+    "CHECKPOINT_RESTORE",
+];
+
+/// The syscalls the runtime/default seccomp profile denies, those that
+/// reach beyond the namespaces of a container.
+const DENIED_SYSCALLS: &[&str] = &[
+    "acct",
+    "add_key",
+    "bpf",
+    "clock_adjtime",
+    "clock_settime",
+    "create_module",
+    "delete_module",
+    "finit_module",
+    "get_kernel_syms",
+    "get_mempolicy",
+    "init_module",
+    "ioperm",
+    "iopl",
+    "kcmp",
+    "kexec_file_load",
+    "kexec_load",
+    "keyctl",
+    "lookup_dcookie",
+    "mbind",
+    "mount",
+    "move_pages",
+    "name_to_handle_at",
+    "nfsservctl",
+    "open_by_handle_at",
+    "perf_event_open",
+    "personality",
+    "pivot_root",
+    "process_vm_readv",
+    "process_vm_writev",
+    "ptrace",
+    "query_module",
+    "quotactl",
+    "reboot",
+    "request_key",
+    "set_mempolicy",
+    "setns",
+    "settimeofday",
+    "stime",
+    "swapoff",
+    "swapon",
+    "sysfs",
+    "umount",
+    "umount2",
+    "unshare",
+    "uselib",
+    "userfaultfd",
+    "ustat",
+    "vm86",
+    "vm86old",
+];
+
+#[derive(Debug, Error)]
+pub(crate) enum SecurityContextError {
+    #[error("unknown capability '{0}'")]
+    UnknownCapability(String),
+    #[error("a localhost profile requires a localhost_ref")]
+    MissingLocalhostRef,
+    #[error("failed to read seccomp profile '{path}': {source}")]
+    ReadSeccompProfile { path: String, source: std::io::Error },
+    #[error("invalid seccomp profile '{path}': {source}")]
+    InvalidSeccompProfile { path: String, source: serde_json::Error },
+}
+
+/// Applies the security `context` of a container to its `spec`.
+pub(crate) fn apply(
+    spec: &mut Spec,
+    context: &LinuxContainerSecurityContext,
+) -> Result<(), SecurityContextError> {
+    let mut process = spec.process().clone().unwrap_or_default();
+    let mut linux = spec.linux().clone().unwrap_or_default();
+
+    let capabilities = if context.privileged {
+        all_capabilities()
+    } else {
+        let current = process
+            .capabilities()
+            .as_ref()
+            .and_then(|capabilities| capabilities.bounding().clone())
+            .unwrap_or_default();
+        capabilities(current, context)?
+    };
+    let ambient = match &context.capabilities {
+        Some(requested) if !context.privileged => requested
+            .add_ambient_capabilities
+            .iter()
+            .map(|name| capability(name))
+            .collect::<Result<HashSet<_>, _>>()?,
+        _ => HashSet::new(),
+    };
+    let capabilities: HashSet<Capability> =
+        capabilities.union(&ambient).cloned().collect();
+    let mut linux_capabilities = LinuxCapabilities::default();
+    let _ = linux_capabilities
+        .set_bounding(Some(capabilities.clone()))
+        .set_effective(Some(capabilities.clone()))
+        .set_permitted(Some(capabilities))
+        .set_inheritable(Some(ambient.clone()))
+        .set_ambient(Some(ambient));
+    let _ = process
+        .set_capabilities(Some(linux_capabilities))
+        .set_no_new_privileges(Some(context.no_new_privs));
+
+    let mut user = process.user().clone();
+    if let Some(uid) = &context.run_as_user {
+        let _ = user.set_uid(uid.value as u32);
+    }
+    if let Some(gid) = &context.run_as_group {
+        let _ = user.set_gid(gid.value as u32);
+    }
+    if !context.supplemental_groups.is_empty() {
+        let _ = user.set_additional_gids(Some(
+            context.supplemental_groups.iter().map(|gid| *gid as u32).collect(),
+        ));
+    }
+    // TODO: Resolve run_as_username from the /etc/passwd of the rootfs
+    let _ = process.set_user(user);
+
+    if context.privileged {
+        let _ = process.set_apparmor_profile(None);
+        let _ = linux
+            .set_seccomp(None)
+            .set_masked_paths(None)
+            .set_readonly_paths(None);
+        let mut resources = linux.resources().clone().unwrap_or_default();
+        let _ = resources.set_devices(Some(vec![
+            LinuxDeviceCgroupBuilder::default()
+                .allow(true)
+                .access("rwm".to_string())
+                .build()
+                .expect("allow all devices"),
+        ]));
+        let _ = linux.set_resources(Some(resources));
+        // /sys and its cgroup mount are read-write
+        let mounts = spec.mounts().clone().map(|mounts| {
+            mounts
+                .into_iter()
+                .map(|mut mount| {
+                    if mount.destination().starts_with("/sys") {
+                        let options = mount.options().clone().map(|options| {
+                            options
+                                .into_iter()
+                                .filter(|option| option != "ro")
+                                .collect()
+                        });
+                        let _ = mount.set_options(options);
+                    }
+                    mount
+                })
+                .collect()
+        });
+        let _ = spec.set_mounts(mounts);
+    } else {
+        let _ = process.set_apparmor_profile(apparmor_profile(context)?);
+        let _ = linux.set_seccomp(seccomp(context)?);
+        // The paths of the spec stay masked unless the context names others
+        if !context.masked_paths.is_empty() {
+            let _ = linux.set_masked_paths(Some(context.masked_paths.clone()));
+        }
+        if !context.readonly_paths.is_empty() {
+            let _ =
+                linux.set_readonly_paths(Some(context.readonly_paths.clone()));
+        }
+    }
+
+    if let Some(mut root) = spec.root().clone() {
+        let _ = root.set_readonly(Some(context.readonly_rootfs));
+        let _ = spec.set_root(Some(root));
+    }
+    let _ = spec.set_process(Some(process)).set_linux(Some(linux));
+    Ok(())
+}
+
+/// The `current` capabilities with those the `context` drops removed and
+/// those it adds added. "ALL" drops or adds every capability.
+fn capabilities(
+    current: HashSet<Capability>,
+    context: &LinuxContainerSecurityContext,
+) -> Result<HashSet<Capability>, SecurityContextError> {
+    let Some(requested) = &context.capabilities else {
+        return Ok(current);
+    };
+    let is_all = |name: &String| name.eq_ignore_ascii_case("ALL");
+
+    let mut capabilities = if requested.drop_capabilities.iter().any(is_all) {
+        HashSet::new()
+    } else {
+        let dropped = requested
+            .drop_capabilities
+            .iter()
+            .map(|name| capability(name))
+            .collect::<Result<HashSet<_>, _>>()?;
+        current.difference(&dropped).cloned().collect()
+    };
+    if requested.add_capabilities.iter().any(is_all) {
+        capabilities.extend(all_capabilities());
+    } else {
+        for name in &requested.add_capabilities {
+            let _ = capabilities.insert(capability(name)?);
+        }
+    }
+    Ok(capabilities)
+}
+
+/// The capability named `name`, with or without its CAP_ prefix, as
+/// Kubernetes names them without.
+fn capability(name: &str) -> Result<Capability, SecurityContextError> {
+    let upper = name.to_ascii_uppercase();
+    let prefixed =
+        if upper.starts_with("CAP_") { upper } else { format!("CAP_{upper}") };
+    serde_json::from_value(serde_json::Value::String(prefixed))
+        .map_err(|_| SecurityContextError::UnknownCapability(name.to_string()))
+}
+
+fn all_capabilities() -> HashSet<Capability> {
+    ALL_CAPABILITIES.iter().filter_map(|name| capability(name).ok()).collect()
+}
+
+/// The profile of `profile`, falling back to the deprecated profile path
+/// of the form "runtime/default", "unconfined" or "localhost/<ref>". No
+/// profile at all is unconfined.
+fn profile(
+    profile: Option<&SecurityProfile>,
+    deprecated: &str,
+) -> Option<(ProfileType, String)> {
+    if let Some(profile) = profile {
+        return Some((profile.profile_type(), profile.localhost_ref.clone()));
+    }
+    match deprecated {
+        "" | "unconfined" => None,
+        "runtime/default" | "docker/default" => {
+            Some((ProfileType::RuntimeDefault, String::new()))
+        }
+        _ => deprecated.strip_prefix("localhost/").map(|localhost_ref| {
+            (ProfileType::Localhost, localhost_ref.into())
+        }),
+    }
+}
+
+/// The seccomp filter of the `context`, None for unconfined containers.
+fn seccomp(
+    context: &LinuxContainerSecurityContext,
+) -> Result<Option<LinuxSeccomp>, SecurityContextError> {
+    match profile(context.seccomp.as_ref(), &context.seccomp_profile_path) {
+        None | Some((ProfileType::Unconfined, _)) => Ok(None),
+        Some((ProfileType::RuntimeDefault, _)) => Ok(Some(default_seccomp())),
+        Some((ProfileType::Localhost, path)) if path.is_empty() => {
+            Err(SecurityContextError::MissingLocalhostRef)
+        }
+        Some((ProfileType::Localhost, path)) => {
+            let contents =
+                std::fs::read(Path::new(&path)).map_err(|source| {
+                    SecurityContextError::ReadSeccompProfile {
+                        path: path.clone(),
+                        source,
+                    }
+                })?;
+            serde_json::from_slice(&contents).map(Some).map_err(|source| {
+                SecurityContextError::InvalidSeccompProfile { path, source }
+            })
+        }
+    }
+}
+
+/// The runtime/default seccomp profile, which allows all syscalls but the
+/// [DENIED_SYSCALLS].
+fn default_seccomp() -> LinuxSeccomp {
+    LinuxSeccompBuilder::default()
+        .default_action(LinuxSeccompAction::ScmpActAllow)
+        .syscalls(vec![LinuxSyscallBuilder::default()
+            .names(
+                DENIED_SYSCALLS
+                    .iter()
+                    .map(|syscall| syscall.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .action(LinuxSeccompAction::ScmpActErrno)
+            .errno_ret(libc::EPERM as u32)
+            .build()
+            .expect("runtime/default seccomp: denied syscalls")])
+        .build()
+        .expect("runtime/default seccomp")
+}
+
+/// The AppArmor profile of the `context`. Aurae has no AppArmor profile of
+/// its own, so runtime/default leaves the container unconfined, as no
+/// profile at all does.
+fn apparmor_profile(
+    context: &LinuxContainerSecurityContext,
+) -> Result<Option<String>, SecurityContextError> {
+    match profile(context.apparmor.as_ref(), &context.apparmor_profile) {
+        None
+        | Some((ProfileType::Unconfined, _))
+        | Some((ProfileType::RuntimeDefault, _)) => Ok(None),
+        Some((ProfileType::Localhost, name)) if name.is_empty() => {
+            Err(SecurityContextError::MissingLocalhostRef)
+        }
+        Some((ProfileType::Localhost, name)) => Ok(Some(name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cri::oci::AuraeOCIBuilder;
+    use proto::cri::Capability as CriCapability;
+
+    fn spec(context: LinuxContainerSecurityContext) -> Spec {
+        let mut spec = AuraeOCIBuilder::new().build().expect("default spec");
+        apply(&mut spec, &context).expect("applied security context");
+        spec
+    }
+
+    fn bounding(spec: &Spec) -> HashSet<Capability> {
+        spec.process()
+            .as_ref()
+            .and_then(|process| process.capabilities().clone())
+            .and_then(|capabilities| capabilities.bounding().clone())
+            .expect("bounding capabilities")
+    }
+
+    #[test]
+    fn capabilities_are_dropped_then_added() {
+        let spec = spec(LinuxContainerSecurityContext {
+            capabilities: Some(CriCapability {
+                add_capabilities: vec!["NET_ADMIN".into()],
+                drop_capabilities: vec!["ALL".into()],
+                add_ambient_capabilities: vec![],
+            }),
+            ..Default::default()
+        });
+        assert_eq!(bounding(&spec), HashSet::from([Capability::NetAdmin]));
+    }
+
+    #[test]
+    fn unknown_capabilities_are_rejected() {
+        let mut spec = AuraeOCIBuilder::new().build().expect("default spec");
+        let context = LinuxContainerSecurityContext {
+            capabilities: Some(CriCapability {
+                add_capabilities: vec!["FLY".into()],
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            apply(&mut spec, &context),
+            Err(SecurityContextError::UnknownCapability(_))
+        ));
+    }
+
+    #[test]
+    fn privileged_containers_are_not_confined() {
+        let spec = spec(LinuxContainerSecurityContext {
+            privileged: true,
+            seccomp: Some(SecurityProfile {
+                profile_type: ProfileType::RuntimeDefault as i32,
+                localhost_ref: String::new(),
+            }),
+            ..Default::default()
+        });
+        let linux = spec.linux().as_ref().expect("linux");
+        assert!(linux.seccomp().is_none());
+        assert!(linux.masked_paths().is_none());
+        assert!(bounding(&spec).contains(&Capability::SysAdmin));
+    }
+
+    #[test]
+    fn seccomp_profiles() {
+        let seccomp = |profile_type: ProfileType, deprecated: &str| {
+            let spec = spec(LinuxContainerSecurityContext {
+                seccomp: (profile_type != ProfileType::Unconfined).then(|| {
+                    SecurityProfile {
+                        profile_type: profile_type as i32,
+                        localhost_ref: String::new(),
+                    }
+                }),
+                seccomp_profile_path: deprecated.to_string(),
+                ..Default::default()
+            });
+            spec.linux().as_ref().and_then(|linux| linux.seccomp().clone())
+        };
+
+        assert_eq!(seccomp(ProfileType::Unconfined, ""), None);
+        assert_eq!(
+            seccomp(ProfileType::RuntimeDefault, ""),
+            Some(default_seccomp())
+        );
+        assert_eq!(
+            seccomp(ProfileType::Unconfined, "runtime/default"),
+            Some(default_seccomp())
+        );
+    }
+
+    #[test]
+    fn no_new_privs_and_readonly_rootfs() {
+        let spec = spec(LinuxContainerSecurityContext {
+            no_new_privs: false,
+            readonly_rootfs: true,
+            masked_paths: vec!["/proc/kcore".into()],
+            ..Default::default()
+        });
+        let spec = serde_json::to_value(&spec).expect("spec as JSON");
+        assert_eq!(spec["process"]["noNewPrivileges"], false);
+        assert_eq!(spec["root"]["readonly"], true);
+        assert_eq!(
+            spec["linux"]["maskedPaths"],
+            serde_json::json!(["/proc/kcore"])
+        );
+    }
+}
@@ -0,0 +1,215 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Resource usage of containers for the stats calls of the CRI runtime
+//! service, read from their cgroup and the rootfs of their bundle.
+//!
+//! CPU and memory come from the unified cgroup of the init process of a
+//! container, following the semantics of kubelet: the working set is the
+//! memory in use minus the inactive page cache. The CPU rate in nano cores
+//! is derived from the usage seen by the previous call for the container.
+
+use libcontainer::container::{Container, ContainerStatus};
+use oci_spec::runtime::Spec;
+use proto::cri::{
+    ContainerAttributes, ContainerMetadata, ContainerStats, CpuUsage,
+    FilesystemIdentifier, FilesystemUsage, MemoryUsage, UInt64Value,
+};
+use std::{
+    collections::HashMap,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use walkdir::WalkDir;
+
+pub(super) const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// The CPU usage seen by the previous stats call, by container.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CpuSamples {
+    samples: Arc<Mutex<HashMap<String, (i64, u64)>>>,
+}
+
+impl CpuSamples {
+    /// Records `usage_ns` at `timestamp` for `container_id`, returning the
+    /// rate since the previous sample, in nano cores.
+    fn rate(
+        &self,
+        container_id: &str,
+        timestamp: i64,
+        usage_ns: u64,
+    ) -> Option<u64> {
+        let mut samples = self.samples.lock().expect("cpu samples lock");
+        let previous =
+            samples.insert(container_id.to_string(), (timestamp, usage_ns));
+        let (then, used) = previous?;
+        let elapsed =
+            u64::try_from(timestamp - then).ok().filter(|e| *e > 0)?;
+        Some(usage_ns.checked_sub(used)? * 1_000_000_000 / elapsed)
+    }
+
+    /// Forgets the samples of the containers not in `container_ids`.
+    pub fn retain(&self, container_ids: &[&str]) {
+        let mut samples = self.samples.lock().expect("cpu samples lock");
+        samples.retain(|id, _| container_ids.contains(&id.as_str()));
+    }
+}
+
+/// The stats of `container`, going by `container_id` for the runtime
+/// service. Only running containers have CPU and memory usage.
+pub(crate) fn container_stats(
+    container_id: &str,
+    container: &Container,
+    samples: &CpuSamples,
+) -> ContainerStats {
+    let timestamp = now();
+    let cgroup = match (container.status(), container.pid()) {
+        (ContainerStatus::Running, Some(pid)) => cgroup_of(pid.as_raw()),
+        _ => None,
+    };
+    let cpu = cgroup.as_deref().and_then(|cgroup| {
+        let usage_ns = stat(&read(cgroup, "cpu.stat")?, "usage_usec")? * 1000;
+        Some(CpuUsage {
+            timestamp,
+            usage_core_nano_seconds: Some(UInt64Value { value: usage_ns }),
+            usage_nano_cores: samples
+                .rate(container_id, timestamp, usage_ns)
+                .map(|value| UInt64Value { value }),
+        })
+    });
+    let memory =
+        cgroup.as_deref().and_then(|cgroup| memory_usage(cgroup, timestamp));
+    let writable_layer = rootfs(container).map(|rootfs| {
+        let (used_bytes, inodes_used) = disk_usage(&rootfs);
+        FilesystemUsage {
+            timestamp,
+            fs_id: Some(FilesystemIdentifier {
+                mountpoint: rootfs.display().to_string(),
+            }),
+            used_bytes: Some(UInt64Value { value: used_bytes }),
+            inodes_used: Some(UInt64Value { value: inodes_used }),
+        }
+    });
+
+    ContainerStats {
+        attributes: Some(ContainerAttributes {
+            id: container_id.to_string(),
+            metadata: Some(ContainerMetadata {
+                name: container.id().to_string(),
+                attempt: 0,
+            }),
+            ..Default::default()
+        }),
+        cpu,
+        memory,
+        writable_layer,
+    }
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+}
+
+/// The unified cgroup of `pid`, from its `0::<path>` line in
+/// /proc/<pid>/cgroup.
+pub(super) fn cgroup_of(pid: i32) -> Option<PathBuf> {
+    let cgroups =
+        std::fs::read_to_string(format!("/proc/{pid}/cgroup")).ok()?;
+    let path = cgroups.lines().find_map(|line| line.strip_prefix("0::"))?;
+    Some(Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')))
+}
+
+fn read(cgroup: &Path, file: &str) -> Option<String> {
+    std::fs::read_to_string(cgroup.join(file)).ok()
+}
+
+/// The value of `key` in a flat keyed cgroup file such as memory.stat.
+fn stat(stats: &str, key: &str) -> Option<u64> {
+    stats.lines().find_map(|line| {
+        let (k, v) = line.split_once(' ')?;
+        if k == key {
+            v.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+fn memory_usage(cgroup: &Path, timestamp: i64) -> Option<MemoryUsage> {
+    let usage: u64 = read(cgroup, "memory.current")?.trim().parse().ok()?;
+    let memory_stat = read(cgroup, "memory.stat").unwrap_or_default();
+    let working_set =
+        usage.saturating_sub(stat(&memory_stat, "inactive_file").unwrap_or(0));
+    // "max" when the memory of the container is not limited
+    let available = read(cgroup, "memory.max")
+        .and_then(|max| max.trim().parse::<u64>().ok())
+        .map(|max| max.saturating_sub(working_set));
+    let value = |value| Some(UInt64Value { value });
+    Some(MemoryUsage {
+        timestamp,
+        working_set_bytes: value(working_set),
+        available_bytes: available.and_then(value),
+        usage_bytes: value(usage),
+        rss_bytes: stat(&memory_stat, "anon").and_then(value),
+        page_faults: stat(&memory_stat, "pgfault").and_then(value),
+        major_page_faults: stat(&memory_stat, "pgmajfault").and_then(value),
+    })
+}
+
+/// The rootfs in the bundle of `container`, as named by its OCI spec.
+fn rootfs(container: &Container) -> Option<PathBuf> {
+    let bundle = container.bundle();
+    let spec = Spec::load(bundle.join("config.json")).ok()?;
+    let root = spec.root().as_ref()?.path();
+    Some(if root.is_absolute() { root.clone() } else { bundle.join(root) })
+}
+
+/// Bytes on disk and inodes used by the files under `dir`, not crossing
+/// into other file systems.
+fn disk_usage(dir: &Path) -> (u64, u64) {
+    WalkDir::new(dir)
+        .same_file_system(true)
+        .into_iter()
+        .filter_map(|entry| entry.ok()?.metadata().ok())
+        .fold((0, 0), |(bytes, inodes), metadata| {
+            (bytes + metadata.blocks() * 512, inodes + 1)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_flat_keyed_stats() {
+        let memory_stat = "anon 4096\nfile 8192\ninactive_file 1024\n";
+        assert_eq!(stat(memory_stat, "inactive_file"), Some(1024));
+        assert_eq!(stat(memory_stat, "file"), Some(8192));
+        assert_eq!(stat(memory_stat, "pgfault"), None);
+    }
+
+    #[test]
+    fn rates_cpu_usage_between_samples() {
+        let samples = CpuSamples::default();
+        assert_eq!(samples.rate("c", 0, 0), None);
+        // half a core over one second
+        assert_eq!(
+            samples.rate("c", 1_000_000_000, 500_000_000),
+            Some(500_000_000)
+        );
+        samples.retain(&[]);
+        assert_eq!(samples.rate("c", 2_000_000_000, 600_000_000), None);
+    }
+}
@@ -0,0 +1,517 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Streaming server of the Exec, Attach and PortForward calls of the CRI
+//! runtime service.
+//!
+//! The calls only return the URL of a session on this server, which the
+//! client (kubelet, `crictl exec --transport websocket`) then connects to
+//! with a WebSocket speaking the Kubernetes channel protocol. Every message
+//! starts with the channel it belongs to. For Exec, versions
+//! `v4.channel.k8s.io` and `v5.channel.k8s.io`, these are stdin, stdout,
+//! stderr, the status reported once the command exited, and the resizes of
+//! the terminal. Attach speaks the same versions, without stdin and resizes
+//! since containers are run without a terminal. For PortForward, version
+//! `v4.channel.k8s.io`, each port has a data and an error channel, both
+//! opened with the port number. SPDY is not supported. A session can be
+//! connected to once, within [SESSION_TIMEOUT].
+
+use super::{
+    attach::Attach,
+    container_log::LogStream,
+    exec::{Exec, ExecProcess},
+    port_forward::PortForward,
+    websocket::{self, Sender},
+};
+use hyper::{
+    header::{
+        HeaderName, CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY,
+        SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+    },
+    service::{make_service_fn, service_fn},
+    upgrade::Upgraded,
+    Body, Request, Response, StatusCode,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    io,
+    net::{SocketAddr, TcpListener},
+    process::ExitStatus,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWriteExt, WriteHalf},
+    sync::broadcast::error::RecvError,
+};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// How long the URL of a session can be connected to.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Supported versions of the protocol of Exec, preferred first.
+const EXEC_PROTOCOLS: &[&str] = &["v5.channel.k8s.io", "v4.channel.k8s.io"];
+/// Supported versions of the protocol of PortForward.
+const PORT_FORWARD_PROTOCOLS: &[&str] = &["v4.channel.k8s.io"];
+
+const STDIN: u8 = 0;
+const STDOUT: u8 = 1;
+const STDERR: u8 = 2;
+const STATUS: u8 = 3;
+const RESIZE: u8 = 4;
+/// Closes the channel that follows it, since v5
+const CLOSE: u8 = 255;
+/// Each forwarded port takes a data and an error channel.
+const MAX_FORWARDED_PORTS: usize = 128;
+
+type WsSender = Sender<WriteHalf<Upgraded>>;
+
+/// What a session streams.
+#[derive(Debug, Clone)]
+pub(crate) enum Session {
+    Exec(Exec),
+    Attach(Attach),
+    PortForward(PortForward),
+}
+
+impl Session {
+    /// The first segment of the path of the URL of the session.
+    fn kind(&self) -> &'static str {
+        match self {
+            Session::Exec(_) => "exec",
+            Session::Attach(_) => "attach",
+            Session::PortForward(_) => "portforward",
+        }
+    }
+
+    fn protocols(&self) -> &'static [&'static str] {
+        match self {
+            Session::Exec(_) | Session::Attach(_) => EXEC_PROTOCOLS,
+            Session::PortForward(_) => PORT_FORWARD_PROTOCOLS,
+        }
+    }
+}
+
+/// The server, and the sessions not connected to yet by their token.
+#[derive(Debug, Clone)]
+pub(crate) struct StreamingServer {
+    address: SocketAddr,
+    sessions: Arc<Mutex<HashMap<String, (Instant, Session)>>>,
+}
+
+impl StreamingServer {
+    /// Starts serving on `address`, on an ephemeral port if it is 0.
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let server = Self {
+            address: listener.local_addr()?,
+            sessions: Default::default(),
+        };
+
+        let builder =
+            hyper::Server::from_tcp(listener).map_err(io::Error::other)?;
+        let sessions = server.clone();
+        let _ignored = tokio::spawn(async move {
+            let make_service = make_service_fn(move |_| {
+                let sessions = sessions.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |req| {
+                        let sessions = sessions.clone();
+                        async move { Ok::<_, Infallible>(sessions.handle(req)) }
+                    }))
+                }
+            });
+            if let Err(e) = builder.serve(make_service).await {
+                error!("CRI streaming server failed: {e}");
+            }
+        });
+        info!("CRI streaming server listening on {}", server.address);
+        Ok(server)
+    }
+
+    /// Registers `session`, returning its URL.
+    pub fn url(&self, session: Session) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        let kind = session.kind();
+        let mut sessions = self.sessions.lock().expect("sessions lock");
+        sessions.retain(|_, (created, _)| created.elapsed() < SESSION_TIMEOUT);
+        let _ = sessions.insert(token.clone(), (Instant::now(), session));
+        format!("http://{}/{kind}/{token}", self.address)
+    }
+
+    /// Takes the session of the URL `path`, if it did not time out.
+    fn take(&self, path: &str) -> Option<Session> {
+        let (kind, token) = path.strip_prefix('/')?.split_once('/')?;
+        let mut sessions = self.sessions.lock().expect("sessions lock");
+        let (created, session) = sessions.remove(token)?;
+        (created.elapsed() < SESSION_TIMEOUT && session.kind() == kind)
+            .then_some(session)
+    }
+
+    /// Upgrades the connection to a session to a WebSocket running it.
+    fn handle(&self, mut req: Request<Body>) -> Response<Body> {
+        let reply = |status: StatusCode, body: &'static str| {
+            let mut res = Response::new(Body::from(body));
+            *res.status_mut() = status;
+            res
+        };
+
+        let Some(mut session) = self.take(req.uri().path()) else {
+            return reply(StatusCode::NOT_FOUND, "unknown or expired session");
+        };
+        if let Session::PortForward(forward) = &mut session {
+            // clients may name the ports in the query, `?port=80&port=443`
+            let ports: Option<Vec<u16>> = req
+                .uri()
+                .query()
+                .unwrap_or_default()
+                .split('&')
+                .filter_map(|pair| pair.strip_prefix("port="))
+                .map(|port| port.parse().ok())
+                .collect();
+            match ports {
+                Some(ports) if !ports.is_empty() => forward.ports = ports,
+                Some(_) => {}
+                None => return reply(StatusCode::BAD_REQUEST, "invalid port"),
+            }
+            if forward.ports.is_empty() {
+                return reply(StatusCode::BAD_REQUEST, "no port to forward");
+            }
+            if forward.ports.len() > MAX_FORWARDED_PORTS {
+                return reply(StatusCode::BAD_REQUEST, "too many ports");
+            }
+        }
+        let header = |name: HeaderName| {
+            req.headers().get(name).and_then(|value| value.to_str().ok())
+        };
+        let Some(key) = header(SEC_WEBSOCKET_KEY) else {
+            return reply(
+                StatusCode::BAD_REQUEST,
+                "only WebSocket is supported",
+            );
+        };
+        let requested = header(SEC_WEBSOCKET_PROTOCOL).unwrap_or_default();
+        let Some(&protocol) = session.protocols().iter().find(|protocol| {
+            requested.split(',').any(|p| p.trim() == **protocol)
+        }) else {
+            return reply(StatusCode::BAD_REQUEST, "unsupported protocol");
+        };
+        let accept = websocket::accept_key(key);
+
+        let upgrade = hyper::upgrade::on(&mut req);
+        let _ignored = tokio::spawn(async move {
+            let upgraded = match upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(e) => {
+                    warn!("Failed to upgrade streaming session: {e}");
+                    return;
+                }
+            };
+            match session {
+                Session::Exec(exec) => run(upgraded, exec, protocol).await,
+                Session::Attach(attach) => run_attach(upgraded, attach).await,
+                Session::PortForward(forward) => {
+                    run_port_forward(upgraded, forward).await
+                }
+            }
+        });
+
+        Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(UPGRADE, "websocket")
+            .header(CONNECTION, "Upgrade")
+            .header(SEC_WEBSOCKET_ACCEPT, accept)
+            .header(SEC_WEBSOCKET_PROTOCOL, protocol)
+            .body(Body::empty())
+            .expect("valid response")
+    }
+}
+
+/// Runs `exec` for the client of `upgraded`, until the command exits or
+/// the client goes away, which kills the command.
+async fn run(upgraded: Upgraded, exec: Exec, protocol: &str) {
+    let (reader, writer) = tokio::io::split(upgraded);
+    let sender = Sender::new(writer);
+    let mut receiver = websocket::Receiver::new(reader, sender.clone());
+
+    let (mut process, stdio) = match ExecProcess::spawn(&exec) {
+        Ok(spawned) => spawned,
+        Err(e) => {
+            let message = format!("failed to exec {:?}: {e}", exec.cmd);
+            warn!("{message}");
+            let status = json!({
+                "metadata": {},
+                "status": "Failure",
+                "message": message,
+            });
+            let _ = send(&sender, STATUS, status.to_string().as_bytes()).await;
+            let _ = sender.close().await;
+            return;
+        }
+    };
+
+    let stdout =
+        stdio.stdout.map(|s| forward_output(s, STDOUT, sender.clone()));
+    let stderr =
+        stdio.stderr.map(|s| forward_output(s, STDERR, sender.clone()));
+    let mut stdin = stdio.stdin;
+    let v5 = protocol == EXEC_PROTOCOLS[0];
+
+    let exited = loop {
+        tokio::select! {
+            status = process.wait() => break status,
+            message = receiver.recv() => {
+                let Ok(Some(message)) = message else {
+                    info!("Client of exec {:?} went away", exec.cmd);
+                    let _ = process.kill();
+                    break process.wait().await;
+                };
+                match message.split_first() {
+                    Some((&STDIN, input)) => {
+                        if let Some(writer) = &mut stdin {
+                            if writer.write_all(input).await.is_err() {
+                                stdin = None;
+                            }
+                        }
+                    }
+                    Some((&RESIZE, size)) => resize(&process, size),
+                    Some((&CLOSE, [STDIN])) if v5 => stdin = None,
+                    _ => {}
+                }
+            }
+        }
+    };
+
+    // all output is sent before the status
+    for output in [stdout, stderr].into_iter().flatten() {
+        let _ = output.await;
+    }
+    let _ = send(&sender, STATUS, status(exited).to_string().as_bytes()).await;
+    let _ = sender.close().await;
+}
+
+/// Sends the output of the container of `attach` to the client of
+/// `upgraded`, until the container closed its stdout and stderr or the
+/// client goes away, which leaves the container running.
+async fn run_attach(upgraded: Upgraded, attach: Attach) {
+    let (reader, writer) = tokio::io::split(upgraded);
+    let sender = Sender::new(writer);
+    let mut receiver = websocket::Receiver::new(reader, sender.clone());
+
+    let channel = |stream| match stream {
+        LogStream::Stdout if attach.stdout => Some(STDOUT),
+        LogStream::Stderr if attach.tty && attach.stdout => Some(STDOUT),
+        LogStream::Stderr if attach.stderr => Some(STDERR),
+        _ => None,
+    };
+    if let Some(mut output) = attach.log.attach() {
+        loop {
+            tokio::select! {
+                output = output.recv() => match output {
+                    Ok((_, data)) if data.is_empty() => {
+                        if attach.log.is_closed() {
+                            break;
+                        }
+                    }
+                    Ok((stream, data)) => {
+                        let Some(channel) = channel(stream) else {
+                            continue;
+                        };
+                        if send(&sender, channel, &data).await.is_err() {
+                            // client is gone
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => warn!(
+                        "Client attached to container {} missed {} outputs",
+                        attach.container_id,
+                        skipped
+                    ),
+                    Err(RecvError::Closed) => break,
+                },
+                message = receiver.recv() => {
+                    // attached clients cannot write to the container
+                    let Ok(Some(_)) = message else {
+                        info!(
+                            "Client attached to container {} went away",
+                            attach.container_id
+                        );
+                        break;
+                    };
+                }
+            }
+        }
+    }
+
+    let status = json!({ "metadata": {}, "status": "Success" });
+    let _ = send(&sender, STATUS, status.to_string().as_bytes()).await;
+    let _ = sender.close().await;
+}
+
+/// Forwards the ports of `forward` for the client of `upgraded`, until the
+/// client goes away. Port `i` of the session has data channel `2i` and error
+/// channel `2i + 1`, on which the port is first sent, as a little endian u16.
+async fn run_port_forward(upgraded: Upgraded, forward: PortForward) {
+    let (reader, writer) = tokio::io::split(upgraded);
+    let sender = Sender::new(writer);
+    let mut receiver = websocket::Receiver::new(reader, sender.clone());
+
+    let mut connections = Vec::with_capacity(forward.ports.len());
+    let mut outputs = Vec::with_capacity(forward.ports.len());
+    for (&port, channel) in forward.ports.iter().zip((0u8..=254).step_by(2)) {
+        let header = port.to_le_bytes();
+        if send(&sender, channel, &header).await.is_err()
+            || send(&sender, channel + 1, &header).await.is_err()
+        {
+            return;
+        }
+        match forward.connect(port).await {
+            Ok(stream) => {
+                let (read, write) = stream.into_split();
+                outputs.push(forward_output(
+                    Box::new(read),
+                    channel,
+                    sender.clone(),
+                ));
+                connections.push(Some(write));
+            }
+            Err(e) => {
+                let message = format!(
+                    "failed to connect to port {port} of the pod sandbox: {e}"
+                );
+                warn!("{message}");
+                let _ = send(&sender, channel + 1, message.as_bytes()).await;
+                connections.push(None);
+            }
+        }
+    }
+
+    while let Ok(Some(message)) = receiver.recv().await {
+        // clients only write to data channels
+        let Some((&channel, data)) = message.split_first() else {
+            continue;
+        };
+        if channel % 2 != 0 {
+            continue;
+        }
+        let Some(connection) = connections.get_mut(usize::from(channel / 2))
+        else {
+            continue;
+        };
+        if let Some(writer) = connection {
+            if writer.write_all(data).await.is_err() {
+                *connection = None;
+            }
+        }
+    }
+
+    info!("Client of port forward to {:?} went away", forward.ports);
+    for output in outputs {
+        output.abort();
+    }
+    let _ = sender.close().await;
+}
+
+/// Sends the output read from `output` on `channel`.
+fn forward_output(
+    mut output: Box<dyn AsyncRead + Send + Unpin>,
+    channel: u8,
+    sender: WsSender,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        loop {
+            // a terminal fails with EIO once the command exited
+            let n = match output.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if send(&sender, channel, &buf[..n]).await.is_err() {
+                // client is gone
+                break;
+            }
+        }
+    })
+}
+
+async fn send(sender: &WsSender, channel: u8, data: &[u8]) -> io::Result<()> {
+    let mut message = Vec::with_capacity(data.len() + 1);
+    message.push(channel);
+    message.extend_from_slice(data);
+    sender.send(&message).await
+}
+
+/// Applies a resize message, `{"Width": 80, "Height": 24}`.
+fn resize(process: &ExecProcess, size: &[u8]) {
+    let Ok(size) = serde_json::from_slice::<Value>(size) else {
+        return;
+    };
+    let dimension = |name| {
+        size.get(name)
+            .and_then(Value::as_u64)
+            .and_then(|v| u16::try_from(v).ok())
+    };
+    if let (Some(width), Some(height)) =
+        (dimension("Width"), dimension("Height"))
+    {
+        if let Err(e) = process.resize(width, height) {
+            warn!("Failed to resize terminal of exec: {e}");
+        }
+    }
+}
+
+/// The Kubernetes status reporting how the command exited.
+fn status(exited: io::Result<ExitStatus>) -> Value {
+    match exited {
+        Ok(exited) if exited.success() => {
+            json!({ "metadata": {}, "status": "Success" })
+        }
+        Ok(exited) => {
+            let code = exited.code().unwrap_or(-1);
+            let message =
+                format!("command terminated with non-zero exit code: {code}");
+            let cause =
+                json!({ "reason": "ExitCode", "message": code.to_string() });
+            json!({
+                "metadata": {},
+                "status": "Failure",
+                "message": message,
+                "reason": "NonZeroExitCode",
+                "details": { "causes": [cause] },
+            })
+        }
+        Err(e) => json!({
+            "metadata": {},
+            "status": "Failure",
+            "message": format!("failed to wait for command: {e}"),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn reports_the_exit_code() {
+        let failed = status(Ok(ExitStatus::from_raw(2 << 8)));
+        assert_eq!(failed["reason"], "NonZeroExitCode");
+        assert_eq!(failed["details"]["causes"][0]["message"], "2");
+        assert_eq!(status(Ok(ExitStatus::from_raw(0)))["status"], "Success");
+    }
+}
@@ -0,0 +1,566 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Validated requests of the CRI runtime service. Malformed names, paths
+//! and resource values are rejected before they reach the runtime, which
+//! uses names in paths and writes resources to cgroups.
+
+use fancy_regex::Regex;
+use lazy_static::lazy_static;
+use proto::cri::{
+    ContainerConfig, CreateContainerRequest, LinuxContainerResources,
+    PodSandboxConfig, RunPodSandboxRequest, UpdateContainerResourcesRequest,
+    WindowsContainerResources,
+};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::{Component, Path};
+use validation::{ValidatedType, ValidationError};
+use validation_macros::ValidatedType;
+
+/// Largest port number of a port mapping
+const MAX_PORT: i32 = u16::MAX as i32;
+
+lazy_static! {
+    /// Names of sandboxes and containers end up in paths and IDs, so they
+    /// may not contain separators or start with a dot.
+    static ref NAME_REGEX: Regex =
+        Regex::new(r"^(?=.{1,253}$)[a-zA-Z0-9][a-zA-Z0-9_.-]*$")
+            .expect("failed to parse 'NAME_REGEX'");
+}
+
+#[derive(Debug, ValidatedType)]
+pub struct ValidatedRunPodSandboxRequest {
+    #[field_type(Option<PodSandboxConfig>)]
+    pub config: PodSandboxConfig,
+
+    #[validate(none)]
+    pub runtime_handler: String,
+}
+
+impl RunPodSandboxRequestTypeValidator for RunPodSandboxRequestValidator {
+    fn validate_config(
+        config: Option<PodSandboxConfig>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<PodSandboxConfig, ValidationError> {
+        let config = validation::required(config, field_name, parent_name)?;
+        sandbox_config(
+            &config,
+            &validation::field_name(field_name, parent_name),
+        )?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, ValidatedType)]
+pub struct ValidatedCreateContainerRequest {
+    pub pod_sandbox_id: String,
+
+    #[field_type(Option<ContainerConfig>)]
+    pub config: ContainerConfig,
+
+    #[validate(none)]
+    pub sandbox_config: Option<PodSandboxConfig>,
+}
+
+impl CreateContainerRequestTypeValidator for CreateContainerRequestValidator {
+    fn validate_pod_sandbox_id(
+        pod_sandbox_id: String,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<String, ValidationError> {
+        name(&pod_sandbox_id, field_name, parent_name)?;
+        Ok(pod_sandbox_id)
+    }
+
+    fn validate_config(
+        config: Option<ContainerConfig>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<ContainerConfig, ValidationError> {
+        let config = validation::required(config, field_name, parent_name)?;
+        let parent_name = validation::field_name(field_name, parent_name);
+        let parent_name = Some(parent_name.as_str());
+
+        let metadata = validation::required(
+            config.metadata.as_ref(),
+            "metadata",
+            parent_name,
+        )?;
+        let metadata_name = validation::field_name("metadata", parent_name);
+        name(&metadata.name, "name", Some(&metadata_name))?;
+
+        let image =
+            validation::required(config.image.as_ref(), "image", parent_name)?;
+        let image_name = validation::field_name("image", parent_name);
+        let _ = validation::required_not_empty(
+            Some(image.image.as_str()),
+            "image",
+            Some(&image_name),
+        )?;
+
+        if !config.working_dir.is_empty() {
+            absolute_path(&config.working_dir, "working_dir", parent_name)?;
+        }
+
+        // The log path is joined to the log directory of the pod
+        if !Path::new(&config.log_path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(ValidationError::Invalid {
+                field: validation::field_name("log_path", parent_name),
+            });
+        }
+
+        for (i, env) in config.envs.iter().enumerate() {
+            if env.key.is_empty() || env.key.contains('=') {
+                return Err(ValidationError::Invalid {
+                    field: validation::field_name(
+                        &format!("envs[{i}].key"),
+                        parent_name,
+                    ),
+                });
+            }
+        }
+
+        for (i, mount) in config.mounts.iter().enumerate() {
+            let mount_name =
+                validation::field_name(&format!("mounts[{i}]"), parent_name);
+            absolute_path(
+                &mount.container_path,
+                "container_path",
+                Some(&mount_name),
+            )?;
+            if !mount.host_path.is_empty() {
+                absolute_path(
+                    &mount.host_path,
+                    "host_path",
+                    Some(&mount_name),
+                )?;
+            }
+        }
+
+        if let Some(resources) =
+            config.linux.as_ref().and_then(|linux| linux.resources.as_ref())
+        {
+            let linux_name = validation::field_name("linux", parent_name);
+            container_resources(resources, "resources", Some(&linux_name))?;
+        }
+
+        Ok(config)
+    }
+}
+
+#[derive(Debug, ValidatedType)]
+pub struct ValidatedUpdateContainerResourcesRequest {
+    pub container_id: String,
+
+    pub linux: Option<LinuxContainerResources>,
+
+    #[validate(none)]
+    pub windows: Option<WindowsContainerResources>,
+
+    #[validate(none)]
+    pub annotations: HashMap<String, String>,
+}
+
+impl UpdateContainerResourcesRequestTypeValidator
+    for UpdateContainerResourcesRequestValidator
+{
+    fn validate_container_id(
+        container_id: String,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<String, ValidationError> {
+        name(&container_id, field_name, parent_name)?;
+        Ok(container_id)
+    }
+
+    fn validate_linux(
+        linux: Option<LinuxContainerResources>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Option<LinuxContainerResources>, ValidationError> {
+        if let Some(linux) = &linux {
+            container_resources(linux, field_name, parent_name)?;
+        }
+        Ok(linux)
+    }
+}
+
+/// Validates the metadata, log directory, DNS config, port mappings and
+/// resources of the config of a sandbox.
+fn sandbox_config(
+    config: &PodSandboxConfig,
+    parent_name: &str,
+) -> Result<(), ValidationError> {
+    let parent_name = Some(parent_name);
+
+    let metadata = validation::required(
+        config.metadata.as_ref(),
+        "metadata",
+        parent_name,
+    )?;
+    let metadata_name = validation::field_name("metadata", parent_name);
+    name(&metadata.name, "name", Some(&metadata_name))?;
+
+    if !config.log_directory.is_empty() {
+        absolute_path(&config.log_directory, "log_directory", parent_name)?;
+    }
+
+    if let Some(dns_config) = &config.dns_config {
+        let dns_name = validation::field_name("dns_config", parent_name);
+        for (i, server) in dns_config.servers.iter().enumerate() {
+            if server.parse::<IpAddr>().is_err() {
+                return Err(ValidationError::Invalid {
+                    field: validation::field_name(
+                        &format!("servers[{i}]"),
+                        Some(&dns_name),
+                    ),
+                });
+            }
+        }
+    }
+
+    for (i, port_mapping) in config.port_mappings.iter().enumerate() {
+        let port_name =
+            validation::field_name(&format!("port_mappings[{i}]"), parent_name);
+        let port_name = Some(port_name.as_str());
+        validation::minimum_value(
+            port_mapping.container_port,
+            1,
+            "",
+            "container_port",
+            port_name,
+        )?;
+        validation::maximum_value(
+            port_mapping.container_port,
+            MAX_PORT,
+            "",
+            "container_port",
+            port_name,
+        )?;
+        validation::minimum_value(
+            port_mapping.host_port,
+            0,
+            "",
+            "host_port",
+            port_name,
+        )?;
+        validation::maximum_value(
+            port_mapping.host_port,
+            MAX_PORT,
+            "",
+            "host_port",
+            port_name,
+        )?;
+        if !port_mapping.host_ip.is_empty()
+            && port_mapping.host_ip.parse::<IpAddr>().is_err()
+        {
+            return Err(ValidationError::Invalid {
+                field: validation::field_name("host_ip", port_name),
+            });
+        }
+    }
+
+    if let Some(linux) = &config.linux {
+        let linux_name = validation::field_name("linux", parent_name);
+        if let Some(resources) = &linux.resources {
+            container_resources(resources, "resources", Some(&linux_name))?;
+        }
+        if let Some(overhead) = &linux.overhead {
+            container_resources(overhead, "overhead", Some(&linux_name))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates that the CPU and memory values of `resources` can be written
+/// to a cgroup. Zero leaves a value unset.
+fn container_resources(
+    resources: &LinuxContainerResources,
+    field_name: &str,
+    parent_name: Option<&str>,
+) -> Result<(), ValidationError> {
+    let parent_name = validation::field_name(field_name, parent_name);
+    let parent_name = Some(parent_name.as_str());
+
+    validation::minimum_value(
+        resources.cpu_period,
+        0,
+        "microseconds",
+        "cpu_period",
+        parent_name,
+    )?;
+    // A quota of -1 is unlimited
+    validation::minimum_value(
+        resources.cpu_quota,
+        -1,
+        "microseconds",
+        "cpu_quota",
+        parent_name,
+    )?;
+    validation::minimum_value(
+        resources.cpu_shares,
+        0,
+        "",
+        "cpu_shares",
+        parent_name,
+    )?;
+    validation::minimum_value(
+        resources.memory_limit_in_bytes,
+        0,
+        validation::UNIT_BYTES,
+        "memory_limit_in_bytes",
+        parent_name,
+    )?;
+    validation::minimum_value(
+        resources.memory_swap_limit_in_bytes,
+        0,
+        validation::UNIT_BYTES,
+        "memory_swap_limit_in_bytes",
+        parent_name,
+    )?;
+    validation::minimum_value(
+        resources.oom_score_adj,
+        -1000,
+        "",
+        "oom_score_adj",
+        parent_name,
+    )?;
+    validation::maximum_value(
+        resources.oom_score_adj,
+        1000,
+        "",
+        "oom_score_adj",
+        parent_name,
+    )?;
+
+    Ok(())
+}
+
+fn name(
+    value: &str,
+    field_name: &str,
+    parent_name: Option<&str>,
+) -> Result<(), ValidationError> {
+    validation::allow_regex(value, &NAME_REGEX, field_name, parent_name)
+}
+
+fn absolute_path(
+    value: &str,
+    field_name: &str,
+    parent_name: Option<&str>,
+) -> Result<(), ValidationError> {
+    if Path::new(value).is_absolute() {
+        Ok(())
+    } else {
+        Err(ValidationError::Invalid {
+            field: validation::field_name(field_name, parent_name),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::cri::{
+        ContainerMetadata, ImageSpec, KeyValue, LinuxPodSandboxConfig, Mount,
+        PodSandboxMetadata, PortMapping,
+    };
+
+    fn sandbox_request() -> RunPodSandboxRequest {
+        RunPodSandboxRequest {
+            config: Some(PodSandboxConfig {
+                metadata: Some(PodSandboxMetadata {
+                    name: "nginx-7b4f".into(),
+                    ..Default::default()
+                }),
+                log_directory: "/var/log/pods/nginx".into(),
+                ..Default::default()
+            }),
+            runtime_handler: String::new(),
+        }
+    }
+
+    fn container_request() -> CreateContainerRequest {
+        CreateContainerRequest {
+            pod_sandbox_id: "nginx-7b4f".into(),
+            config: Some(ContainerConfig {
+                metadata: Some(ContainerMetadata {
+                    name: "nginx".into(),
+                    attempt: 0,
+                }),
+                image: Some(ImageSpec {
+                    image: "docker.io/library/nginx:latest".into(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            sandbox_config: None,
+        }
+    }
+
+    #[test]
+    fn test_run_pod_sandbox_request_valid() {
+        let validated =
+            ValidatedRunPodSandboxRequest::validate(sandbox_request(), None)
+                .expect("valid request");
+        assert_eq!(validated.config.log_directory, "/var/log/pods/nginx");
+    }
+
+    #[test]
+    fn test_run_pod_sandbox_request_requires_config() {
+        let mut request = sandbox_request();
+        request.config = None;
+        assert!(matches!(
+            ValidatedRunPodSandboxRequest::validate(request, None),
+            Err(ValidationError::Required { field }) if field == "config"
+        ));
+    }
+
+    #[test]
+    fn test_run_pod_sandbox_request_rejects_path_in_name() {
+        for name in ["", "../etc", "a/b", ".hidden"] {
+            let mut request = sandbox_request();
+            request.config.as_mut().unwrap().metadata.as_mut().unwrap().name =
+                name.into();
+            assert!(matches!(
+                ValidatedRunPodSandboxRequest::validate(request, None),
+                Err(ValidationError::AllowRegexViolation { field, .. })
+                    if field == "config.metadata.name"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_run_pod_sandbox_request_rejects_relative_log_directory() {
+        let mut request = sandbox_request();
+        request.config.as_mut().unwrap().log_directory = "var/log".into();
+        assert!(matches!(
+            ValidatedRunPodSandboxRequest::validate(request, None),
+            Err(ValidationError::Invalid { field })
+                if field == "config.log_directory"
+        ));
+    }
+
+    #[test]
+    fn test_run_pod_sandbox_request_rejects_bad_ports() {
+        let mut request = sandbox_request();
+        request.config.as_mut().unwrap().port_mappings =
+            vec![PortMapping { container_port: 70000, ..Default::default() }];
+        assert!(matches!(
+            ValidatedRunPodSandboxRequest::validate(request, None),
+            Err(ValidationError::Maximum { field, .. })
+                if field == "config.port_mappings[0].container_port"
+        ));
+    }
+
+    #[test]
+    fn test_run_pod_sandbox_request_rejects_negative_overhead() {
+        let mut request = sandbox_request();
+        request.config.as_mut().unwrap().linux = Some(LinuxPodSandboxConfig {
+            overhead: Some(LinuxContainerResources {
+                memory_limit_in_bytes: -1,
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        assert!(matches!(
+            ValidatedRunPodSandboxRequest::validate(request, None),
+            Err(ValidationError::Minimum { field, .. })
+                if field == "config.linux.overhead.memory_limit_in_bytes"
+        ));
+    }
+
+    #[test]
+    fn test_create_container_request_valid() {
+        let validated = ValidatedCreateContainerRequest::validate(
+            container_request(),
+            None,
+        )
+        .expect("valid request");
+        assert_eq!(validated.pod_sandbox_id, "nginx-7b4f");
+    }
+
+    #[test]
+    fn test_create_container_request_requires_image() {
+        let mut request = container_request();
+        request.config.as_mut().unwrap().image = None;
+        assert!(matches!(
+            ValidatedCreateContainerRequest::validate(request, None),
+            Err(ValidationError::Required { field }) if field == "config.image"
+        ));
+    }
+
+    #[test]
+    fn test_create_container_request_rejects_relative_mount() {
+        let mut request = container_request();
+        request.config.as_mut().unwrap().mounts = vec![Mount {
+            container_path: "data".into(),
+            host_path: "/srv/data".into(),
+            ..Default::default()
+        }];
+        assert!(matches!(
+            ValidatedCreateContainerRequest::validate(request, None),
+            Err(ValidationError::Invalid { field })
+                if field == "config.mounts[0].container_path"
+        ));
+    }
+
+    #[test]
+    fn test_create_container_request_rejects_log_path_outside_log_directory() {
+        for log_path in ["/var/log/nginx.log", "../nginx/0.log"] {
+            let mut request = container_request();
+            request.config.as_mut().unwrap().log_path = log_path.into();
+            assert!(matches!(
+                ValidatedCreateContainerRequest::validate(request, None),
+                Err(ValidationError::Invalid { field })
+                    if field == "config.log_path"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_create_container_request_rejects_bad_env() {
+        let mut request = container_request();
+        request.config.as_mut().unwrap().envs =
+            vec![KeyValue { key: "A=B".into(), value: "C".into() }];
+        assert!(matches!(
+            ValidatedCreateContainerRequest::validate(request, None),
+            Err(ValidationError::Invalid { field })
+                if field == "config.envs[0].key"
+        ));
+    }
+
+    #[test]
+    fn test_update_container_resources_request_rejects_oom_score_adj() {
+        let request = UpdateContainerResourcesRequest {
+            container_id: "nginx-7b4f_nginx_0".into(),
+            linux: Some(LinuxContainerResources {
+                oom_score_adj: 1001,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert!(matches!(
+            ValidatedUpdateContainerResourcesRequest::validate(request, None),
+            Err(ValidationError::Maximum { field, .. })
+                if field == "linux.oom_score_adj"
+        ));
+    }
+}
@@ -0,0 +1,248 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Pods isolated in microVMs, run with the [VM_RUNTIME_HANDLER] runtime
+//! handler.
+//!
+//! Instead of an init container, the sandbox of such a pod is a VM of the
+//! [VmService] booting the guest image of the [PodVmConfig], whose init is
+//! a nested auraed. The host auraed drives it as the agent of the pod over
+//! the vsock device of the VM, so the pod is isolated by the hypervisor
+//! rather than by namespaces, the way Kata Containers isolates pods, but
+//! with in-tree components only. The agent runs the sandbox of the pod in the
+//! guest, and the containers of the pod are scheduled in it through the CRI
+//! of the agent.
+
+use crate::vms::VmService;
+use anyhow::Context;
+use client::{
+    cri::runtime_service::RuntimeServiceClient,
+    grpc::health::health::HealthClient, Client,
+};
+use proto::{
+    cri::{PodSandboxConfig, RunPodSandboxRequest},
+    grpc::health::{health_check_response::ServingStatus, HealthCheckRequest},
+    vms::{
+        NetworkInterface, RootDrive, VirtualMachine, VmServiceAllocateRequest,
+        VmServiceFreeRequest, VmServiceListRequest, VmServiceStartRequest,
+        VmServiceStopRequest,
+    },
+};
+use std::{path::PathBuf, time::Duration};
+
+/// The runtime handler pods are run in a VM of their own with
+pub(crate) const VM_RUNTIME_HANDLER: &str = "aurae-vm";
+/// How often and how long to wait for the agent in a booting pod VM.
+const AGENT_CONNECT_ATTEMPTS: u32 = 30;
+const AGENT_CONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The VMs pods run with the [VM_RUNTIME_HANDLER] are booted as.
+#[derive(Debug, Clone)]
+pub struct PodVmConfig {
+    /// The kernel of the VMs.
+    pub kernel: PathBuf,
+    /// Arguments passed to the kernel.
+    pub kernel_args: Vec<String>,
+    /// The root filesystem image with auraed as its init, each VM boots a
+    /// copy of it.
+    pub image: PathBuf,
+    /// Memory of each VM in MiB.
+    pub mem_size_mb: u32,
+    /// vCPUs of each VM.
+    pub vcpu_count: u32,
+}
+
+impl Default for PodVmConfig {
+    fn default() -> Self {
+        Self {
+            kernel: PathBuf::from("/var/lib/aurae/vm/kernel/vmlinux.bin"),
+            kernel_args: ["console=hvc0", "root=/dev/vda1", "rw"]
+                .map(String::from)
+                .to_vec(),
+            image: PathBuf::from("/var/lib/aurae/vm/image/disk.raw"),
+            mem_size_mb: 512,
+            vcpu_count: 1,
+        }
+    }
+}
+
+/// What the CRI needs to run pods in VMs: the VMs subsystem and how the VMs
+/// are booted.
+#[derive(Debug, Clone)]
+pub(crate) struct PodVms {
+    vms: VmService,
+    config: PodVmConfig,
+    /// Where the root drives of the VMs are copied to
+    dir: PathBuf,
+}
+
+impl PodVms {
+    pub(crate) fn new(
+        vms: VmService,
+        config: PodVmConfig,
+        dir: PathBuf,
+    ) -> Self {
+        Self { vms, config, dir }
+    }
+}
+
+/// The VM a pod sandbox runs in.
+#[derive(Debug, Clone)]
+pub(crate) struct VmPod {
+    vm_id: String,
+    /// The copy of the image the VM boots
+    root_drive: PathBuf,
+    /// The address of the guest, None if it has no leased one
+    pub(crate) ip: Option<String>,
+    /// The auraed in the guest
+    agent: Client,
+    vms: VmService,
+}
+
+impl VmPod {
+    /// Boots the VM of the pod `sandbox_id` from a copy of the image, waits
+    /// for its agent to be reachable and has it run the sandbox of
+    /// `sandbox_config`.
+    pub(crate) async fn run(
+        pod_vms: &PodVms,
+        sandbox_id: &str,
+        sandbox_config: PodSandboxConfig,
+    ) -> anyhow::Result<Self> {
+        let PodVms { vms, config, dir } = pod_vms;
+        let vm_id = format!("pod-{sandbox_id}");
+        let dir = dir.join(sandbox_id);
+        let root_drive = dir.join("root.raw");
+        tokio::fs::create_dir_all(&dir).await?;
+        let _ =
+            tokio::fs::copy(&config.image, &root_drive).await.with_context(
+                || format!("failed to copy {}", config.image.display()),
+            )?;
+
+        let allocated = vms
+            .allocate(VmServiceAllocateRequest {
+                machine: Some(VirtualMachine {
+                    id: vm_id.clone(),
+                    mem_size_mb: config.mem_size_mb,
+                    vcpu_count: config.vcpu_count,
+                    kernel_img_path: config.kernel.display().to_string(),
+                    kernel_args: config.kernel_args.clone(),
+                    root_drive: Some(RootDrive {
+                        image_path: root_drive.display().to_string(),
+                        read_only: false,
+                    }),
+                    // addressed from the VM address pool, the pod ip
+                    network_interfaces: vec![NetworkInterface::default()],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+            .await?;
+        let ip = allocated
+            .machine
+            .and_then(|machine| machine.network_interfaces.into_iter().next())
+            .map(|interface| interface.guest_ip_address)
+            .filter(|ip| !ip.is_empty());
+
+        let booted = async {
+            let _ = vms
+                .start(VmServiceStartRequest { vm_id: vm_id.clone() })
+                .await?;
+            let agent = connect_agent(vms, &vm_id).await?;
+            let _ = agent
+                .run_pod_sandbox(RunPodSandboxRequest {
+                    config: Some(sandbox_config),
+                    runtime_handler: String::new(),
+                })
+                .await
+                .context("agent failed to run the pod sandbox")?;
+            Ok::<_, anyhow::Error>(agent)
+        };
+        let agent = match booted.await {
+            Ok(agent) => agent,
+            Err(e) => {
+                let _best_effort = vms
+                    .free(VmServiceFreeRequest { vm_id: vm_id.clone() })
+                    .await;
+                return Err(e);
+            }
+        };
+
+        Ok(Self { vm_id, root_drive, ip, agent, vms: vms.clone() })
+    }
+
+    /// The auraed in the guest, which the containers of the pod are created
+    /// in and driven through.
+    pub(crate) fn agent(&self) -> &Client {
+        &self.agent
+    }
+
+    /// Whether the VM runs and its agent serves.
+    pub(crate) async fn is_ready(&self) -> bool {
+        if !self.is_running().await {
+            return false;
+        }
+        self.agent
+            .check(HealthCheckRequest { service: String::new() })
+            .await
+            .is_ok_and(|res| {
+                res.into_inner().status() == ServingStatus::Serving
+            })
+    }
+
+    /// Whether the VM runs.
+    pub(crate) async fn is_running(&self) -> bool {
+        let Ok(vms) = self.vms.list().await else {
+            return false;
+        };
+        vms.machines
+            .iter()
+            .any(|vm| vm.id == self.vm_id && vm.status == "Running")
+    }
+
+    /// Stops the VM, leaving its root drive for it to be removed.
+    pub(crate) async fn stop(&self) -> anyhow::Result<()> {
+        let _ = self
+            .vms
+            .stop(VmServiceStopRequest { vm_id: self.vm_id.clone() })
+            .await?;
+        Ok(())
+    }
+
+    /// Frees the stopped VM and removes its root drive.
+    pub(crate) async fn remove(&self) -> anyhow::Result<()> {
+        let _ = self
+            .vms
+            .free(VmServiceFreeRequest { vm_id: self.vm_id.clone() })
+            .await?;
+        if let Some(dir) = self.root_drive.parent() {
+            tokio::fs::remove_dir_all(dir).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Connects to the agent of the VM `vm_id`, while the guest is booting.
+async fn connect_agent(vms: &VmService, vm_id: &str) -> anyhow::Result<Client> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match vms.connect_agent(vm_id).await {
+            Ok(agent) => return Ok(agent),
+            Err(e) if attempt >= AGENT_CONNECT_ATTEMPTS => {
+                return Err(e.context("agent of the pod vm is unreachable"))
+            }
+            Err(_) => tokio::time::sleep(AGENT_CONNECT_INTERVAL).await,
+        }
+    }
+}
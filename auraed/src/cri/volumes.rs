@@ -0,0 +1,207 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Mounts of the containers of pod sandboxes.
+//!
+//! CRI mounts with a host path bind mount it, creating it as a directory if
+//! it does not exist. Mounts without a host path are volumes of the pod,
+//! like emptyDir volumes: a tmpfs mounted in a directory of the pod under
+//! `<runtime_dir>/volumes/<sandbox>/`, shared by the containers of the pod
+//! mounting the same path and removed with the pod.
+
+use nix::mount::{MntFlags, MsFlags};
+use oci_spec::runtime::{Mount, MountBuilder};
+use proto::cri::MountPropagation;
+use sha2::{Digest, Sha256};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub(crate) enum VolumeError {
+    #[error("mount path '{path}' is not absolute")]
+    NotAbsolute { path: String },
+    #[error("failed to set up volume '{path}': {source}")]
+    Io { path: String, source: std::io::Error },
+}
+
+/// The OCI mounts of the CRI `mounts` of a container, with the volumes of
+/// its pod in `volumes_dir`.
+pub(crate) fn mounts(
+    mounts: &[proto::cri::Mount],
+    volumes_dir: &Path,
+) -> Result<Vec<Mount>, VolumeError> {
+    mounts
+        .iter()
+        .map(|mount| {
+            if !Path::new(&mount.container_path).is_absolute() {
+                return Err(VolumeError::NotAbsolute {
+                    path: mount.container_path.clone(),
+                });
+            }
+            let source = if mount.host_path.is_empty() {
+                volume(volumes_dir, &mount.container_path)?
+            } else {
+                let source = PathBuf::from(&mount.host_path);
+                if !source.is_absolute() {
+                    return Err(VolumeError::NotAbsolute {
+                        path: mount.host_path.clone(),
+                    });
+                }
+                if !source.exists() {
+                    std::fs::create_dir_all(&source).map_err(|e| {
+                        VolumeError::Io {
+                            path: mount.host_path.clone(),
+                            source: e,
+                        }
+                    })?;
+                }
+                source
+            };
+            Ok(bind(&mount.container_path, source, mount))
+        })
+        .collect()
+}
+
+/// Unmounts and removes the volumes of a pod in `volumes_dir`.
+pub(crate) fn remove(volumes_dir: &Path) -> Result<(), VolumeError> {
+    let io_error = |source| VolumeError::Io {
+        path: volumes_dir.display().to_string(),
+        source,
+    };
+    let entries = match std::fs::read_dir(volumes_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(io_error(e)),
+    };
+    for entry in entries {
+        let path = entry.map_err(io_error)?.path();
+        match nix::mount::umount2(&path, MntFlags::MNT_DETACH) {
+            Ok(()) | Err(nix::errno::Errno::EINVAL) => {}
+            Err(e) => return Err(io_error(e.into())),
+        }
+    }
+    std::fs::remove_dir_all(volumes_dir).map_err(io_error)
+}
+
+/// The volume of the pod mounted at `container_path`, mounting its tmpfs the
+/// first time a container of the pod mounts it.
+fn volume(
+    volumes_dir: &Path,
+    container_path: &str,
+) -> Result<PathBuf, VolumeError> {
+    let path = volumes_dir.join(volume_name(container_path));
+    let io_error =
+        |source| VolumeError::Io { path: path.display().to_string(), source };
+    match std::fs::create_dir_all(volumes_dir)
+        .and_then(|()| std::fs::create_dir(&path))
+    {
+        Ok(()) => nix::mount::mount(
+            Some("tmpfs"),
+            &path,
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+            Some("mode=1777"),
+        )
+        .map_err(|e| io_error(e.into()))?,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(io_error(e)),
+    }
+    Ok(path)
+}
+
+/// The name of the directory of the volume mounted at `container_path`,
+/// the sha256 of the path so that distinct paths never share a volume while
+/// the name stays short, whatever the length of the path.
+fn volume_name(container_path: &str) -> String {
+    format!("{:x}", Sha256::digest(container_path.trim_end_matches('/')))
+}
+
+fn bind(
+    container_path: &str,
+    source: PathBuf,
+    mount: &proto::cri::Mount,
+) -> Mount {
+    let propagation = match mount.propagation() {
+        MountPropagation::PropagationPrivate => "rprivate",
+        MountPropagation::PropagationHostToContainer => "rslave",
+        MountPropagation::PropagationBidirectional => "rshared",
+    };
+    let access = if mount.readonly { "ro" } else { "rw" };
+    MountBuilder::default()
+        .destination(container_path)
+        .typ("bind")
+        .source(source)
+        .options(vec![
+            "rbind".to_string(),
+            access.to_string(),
+            propagation.to_string(),
+        ])
+        .build()
+        .expect("valid bind mount")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_mounts_host_paths() {
+        let host = std::env::temp_dir();
+        let mounts = mounts(
+            &[proto::cri::Mount {
+                container_path: "/data".into(),
+                host_path: host.display().to_string(),
+                readonly: true,
+                propagation: MountPropagation::PropagationHostToContainer
+                    as i32,
+                ..Default::default()
+            }],
+            Path::new("/nonexistent"),
+        )
+        .expect("valid mounts");
+
+        assert_eq!(mounts[0].destination(), Path::new("/data"));
+        assert_eq!(mounts[0].source().as_deref(), Some(host.as_path()));
+        assert_eq!(
+            mounts[0].options().as_deref(),
+            Some(&["rbind".into(), "ro".into(), "rslave".into()][..])
+        );
+    }
+
+    #[test]
+    fn rejects_relative_paths() {
+        let mount = |container_path: &str, host_path: &str| {
+            mounts(
+                &[proto::cri::Mount {
+                    container_path: container_path.into(),
+                    host_path: host_path.into(),
+                    ..Default::default()
+                }],
+                Path::new("/nonexistent"),
+            )
+        };
+        assert!(mount("data", "/tmp").is_err());
+        assert!(mount("/data", "tmp").is_err());
+    }
+
+    #[test]
+    fn names_volumes_by_their_path() {
+        assert_eq!(volume_name("/cache/"), volume_name("/cache"));
+        assert_ne!(volume_name("/a-b/c"), volume_name("/a/b-c"));
+        assert_eq!(volume_name(&"/deep".repeat(100)).len(), 64);
+    }
+}
@@ -0,0 +1,215 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Just enough of WebSockets ([RFC 6455]) for the streaming server: the
+//! accept key of the opening handshake, and the messages of a connection.
+//!
+//! [RFC 6455]: https://www.rfc-editor.org/rfc/rfc6455
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use sha1::{Digest, Sha1};
+use std::{io, sync::Arc};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::Mutex,
+};
+
+/// Appended to the key of the client to compute the accept key.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// Largest message accepted from a client.
+const MAX_MESSAGE: u64 = 1 << 20;
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// The `Sec-WebSocket-Accept` answering the `Sec-WebSocket-Key` `key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.trim().as_bytes());
+    sha1.update(GUID.as_bytes());
+    STANDARD.encode(sha1.finalize())
+}
+
+/// Sends messages to the client, shared by the tasks of a connection.
+#[derive(Debug)]
+pub(crate) struct Sender<W> {
+    inner: Arc<Mutex<W>>,
+}
+
+impl<W> Clone for Sender<W> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sender<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner: Arc::new(Mutex::new(inner)) }
+    }
+
+    /// Sends `payload` as a binary message.
+    pub async fn send(&self, payload: &[u8]) -> io::Result<()> {
+        self.frame(BINARY, payload).await
+    }
+
+    /// Sends a close message, after which nothing else may be sent.
+    pub async fn close(&self) -> io::Result<()> {
+        // 1000, normal closure
+        self.frame(CLOSE, &1000u16.to_be_bytes()).await
+    }
+
+    async fn frame(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(len as u8),
+            len @ 126..=0xFFFF => {
+                frame.push(126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+
+        let mut inner = self.inner.lock().await;
+        inner.write_all(&frame).await?;
+        inner.flush().await
+    }
+}
+
+/// Receives the messages of the client, answering its pings.
+#[derive(Debug)]
+pub(crate) struct Receiver<R, W> {
+    inner: R,
+    sender: Sender<W>,
+}
+
+impl<R: AsyncRead + Unpin, W: AsyncWrite + Unpin> Receiver<R, W> {
+    pub fn new(inner: R, sender: Sender<W>) -> Self {
+        Self { inner, sender }
+    }
+
+    /// The payload of the next text or binary message, None once the
+    /// client closed the connection.
+    pub async fn recv(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut message = Vec::new();
+        loop {
+            let (fin, opcode, payload) = match self.frame().await {
+                Ok(frame) => frame,
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                    return Ok(None)
+                }
+                Err(e) => return Err(e),
+            };
+            match opcode {
+                TEXT | BINARY | CONTINUATION => {
+                    if message.len() + payload.len() > MAX_MESSAGE as usize {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "message too large",
+                        ));
+                    }
+                    message.extend_from_slice(&payload);
+                    if fin {
+                        return Ok(Some(message));
+                    }
+                }
+                CLOSE => return Ok(None),
+                PING => self.sender.frame(PONG, &payload).await?,
+                _ => {}
+            }
+        }
+    }
+
+    /// Reads the next frame, unmasking its payload.
+    async fn frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0u8; 2];
+        let _ = self.inner.read_exact(&mut head).await?;
+        let fin = head[0] & 0x80 != 0;
+        let opcode = head[0] & 0x0F;
+        let masked = head[1] & 0x80 != 0;
+        let len = match head[1] & 0x7F {
+            126 => u64::from(self.inner.read_u16().await?),
+            127 => self.inner.read_u64().await?,
+            len => u64::from(len),
+        };
+        if len > MAX_MESSAGE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "message too large",
+            ));
+        }
+        let mut mask = [0u8; 4];
+        if masked {
+            let _ = self.inner.read_exact(&mut mask).await?;
+        }
+        let mut payload = vec![0u8; len as usize];
+        let _ = self.inner.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        Ok((fin, opcode, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computes_the_accept_key() {
+        // the example of RFC 6455, section 1.3
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn receives_fragmented_masked_messages() {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let masked = |payload: &[u8]| -> Vec<u8> {
+            payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]).collect()
+        };
+        let mut frames = vec![TEXT, 0x80 | 3];
+        frames.extend_from_slice(&mask);
+        frames.extend(masked(b"Hel"));
+        frames.extend_from_slice(&[0x80 | CONTINUATION, 0x80 | 2]);
+        frames.extend_from_slice(&mask);
+        frames.extend(masked(b"lo"));
+        frames.extend_from_slice(&[0x80 | CLOSE, 0]);
+
+        let mut receiver =
+            Receiver::new(frames.as_slice(), Sender::new(Vec::new()));
+        assert_eq!(
+            receiver.recv().await.expect("recv"),
+            Some(b"Hello".to_vec())
+        );
+        assert_eq!(receiver.recv().await.expect("recv"), None);
+    }
+
+    #[tokio::test]
+    async fn sends_binary_messages() {
+        let sender = Sender::new(Vec::new());
+        sender.send(&[1, b'h', b'i']).await.expect("send");
+        let sent = sender.inner.lock().await.clone();
+        assert_eq!(sent, vec![0x80 | BINARY, 3, 1, b'h', b'i']);
+    }
+}
@@ -13,8 +13,11 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use crate::cordon::Cordon;
+use crate::ebpf::EbpfSupport;
 use proto::discovery::{
-    discovery_service_server, DiscoverRequest, DiscoverResponse,
+    discovery_service_server, CordonRequest, CordonResponse, DiscoverRequest,
+    DiscoverResponse, EbpfProgramSupport, UncordonRequest, UncordonResponse,
 };
 use thiserror::Error;
 use tonic::{Request, Response, Status};
@@ -41,20 +44,61 @@ impl From<DiscoveryServiceError> for Status {
 }
 
 #[derive(Debug, Clone)]
-pub struct DiscoveryService {}
+pub struct DiscoveryService {
+    cordon: Cordon,
+    ebpf: Option<EbpfSupport>,
+}
 
 impl DiscoveryService {
-    pub fn new() -> Self {
-        DiscoveryService {}
+    pub fn new(cordon: Cordon) -> Self {
+        DiscoveryService { cordon, ebpf: None }
+    }
+
+    /// Reports which eBPF programs the kernel supports.
+    pub fn with_ebpf_support(self, ebpf: EbpfSupport) -> Self {
+        Self { ebpf: Some(ebpf), ..self }
     }
 
     #[tracing::instrument(skip(self))]
     fn discover(&self, request: DiscoverRequest) -> Result<DiscoverResponse> {
+        let cordon_reason = self.cordon.reason();
         Ok(DiscoverResponse {
             healthy: true,
             version: VERSION.unwrap_or("unknown").into(),
+            cordoned: cordon_reason.is_some(),
+            cordon_reason: cordon_reason.unwrap_or_default(),
+            ebpf: self.ebpf.as_ref().map(|ebpf| {
+                proto::discovery::EbpfSupport {
+                    kernel_btf: ebpf.kernel_btf,
+                    programs: ebpf
+                        .programs
+                        .iter()
+                        .map(|p| EbpfProgramSupport {
+                            name: p.name.into(),
+                            supported: p.supported,
+                            detail: p.detail.clone(),
+                        })
+                        .collect(),
+                }
+            }),
         })
     }
+
+    #[tracing::instrument(skip(self))]
+    fn cordon(&self, request: CordonRequest) -> Result<CordonResponse> {
+        let reason = if request.reason.is_empty() {
+            "no reason given".into()
+        } else {
+            request.reason
+        };
+        self.cordon.cordon(reason)?;
+        Ok(CordonResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    fn uncordon(&self, request: UncordonRequest) -> Result<UncordonResponse> {
+        Ok(UncordonResponse { was_cordoned: self.cordon.uncordon()? })
+    }
 }
 
 #[tonic::async_trait]
@@ -66,22 +110,87 @@ impl discovery_service_server::DiscoveryService for DiscoveryService {
         let request = request.into_inner();
         Ok(Response::new(self.discover(request)?))
     }
+
+    async fn cordon(
+        &self,
+        request: Request<CordonRequest>,
+    ) -> std::result::Result<Response<CordonResponse>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.cordon(request)?))
+    }
+
+    async fn uncordon(
+        &self,
+        request: Request<UncordonRequest>,
+    ) -> std::result::Result<Response<UncordonResponse>, Status> {
+        let request = request.into_inner();
+        Ok(Response::new(self.uncordon(request)?))
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use proto::discovery::DiscoverRequest;
+    use proto::discovery::{CordonRequest, DiscoverRequest, UncordonRequest};
 
+    use crate::cordon::Cordon;
     use crate::discovery::{DiscoveryService, VERSION};
+    use crate::ebpf::{EbpfSupport, ProgramSupport};
+
+    fn discovery_service() -> (DiscoveryService, tempfile::TempDir) {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let cordon = Cordon::open(dir.path().join("cordon")).expect("open");
+        (DiscoveryService::new(cordon), dir)
+    }
 
     #[test]
     fn test_discover() {
-        let resp = DiscoveryService::new().discover(DiscoverRequest {});
+        let (service, _dir) = discovery_service();
+        let resp = service.discover(DiscoverRequest {});
         assert!(resp.is_ok());
 
         let resp = resp.unwrap();
 
         assert!(resp.healthy);
         assert_eq!(resp.version, VERSION.expect("valid version"));
+        assert!(!resp.cordoned);
+        assert!(resp.ebpf.is_none());
+    }
+
+    #[test]
+    fn discover_must_report_ebpf_support() {
+        let (service, _dir) = discovery_service();
+        let service = service.with_ebpf_support(EbpfSupport {
+            kernel_btf: true,
+            programs: vec![ProgramSupport {
+                name: "oom_kill_process",
+                supported: false,
+                detail: "kernel function oom_kill_process is not available"
+                    .into(),
+            }],
+        });
+
+        let resp = service.discover(DiscoverRequest {}).expect("discover");
+        let ebpf = resp.ebpf.expect("eBPF support");
+        assert!(ebpf.kernel_btf);
+        assert_eq!(ebpf.programs.len(), 1);
+        assert_eq!(ebpf.programs[0].name, "oom_kill_process");
+        assert!(!ebpf.programs[0].supported);
+    }
+
+    #[test]
+    fn discover_must_report_cordon() {
+        let (service, _dir) = discovery_service();
+        let _ = service
+            .cordon(CordonRequest { reason: "maintenance".into() })
+            .expect("cordon");
+
+        let resp = service.discover(DiscoverRequest {}).expect("discover");
+        assert!(resp.cordoned);
+        assert_eq!(resp.cordon_reason, "maintenance");
+
+        let resp = service.uncordon(UncordonRequest {}).expect("uncordon");
+        assert!(resp.was_cordoned);
+        let resp = service.discover(DiscoverRequest {}).expect("discover");
+        assert!(!resp.cordoned);
     }
 }
\ No newline at end of file
@@ -16,7 +16,7 @@
 use super::{
     kprobe::KProbeProgram, perf_buffer_reader::PerfBufferReader,
     perf_event_broadcast::PerfEventBroadcast, tracepoint::TracepointProgram,
-    BpfFile,
+    BpfFile, CgroupFilter,
 };
 
 use aya::Bpf;
@@ -89,4 +89,10 @@ impl BpfContext {
             }
         }
     }
+
+    /// The cgroup filter of the loaded programs reporting the events of
+    /// processes.
+    pub fn cgroup_filter(&mut self) -> Result<CgroupFilter, anyhow::Error> {
+        CgroupFilter::take(&mut self.0)
+    }
 }
\ No newline at end of file
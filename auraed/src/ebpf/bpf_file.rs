@@ -13,17 +13,47 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use super::relocation::{self, RelocatedOffset};
 use crate::AURAED_RUNTIME;
-use aya::{Bpf, BpfError};
-use tracing::trace;
+use aya::{Bpf, BpfError, BpfLoader};
+use tracing::{trace, warn};
 
 pub trait BpfFile {
     const OBJ_NAME: &'static str;
+    /// The offsets the programs of the object read, relocated to the ones of
+    /// the running kernel when the object is loaded.
+    const RELOCATIONS: &'static [RelocatedOffset] = &[];
 
     fn load() -> Result<Bpf, BpfError> {
+        Self::load_with_globals(&[])
+    }
+
+    /// Loads the object with its `#[no_mangle]` statics named in `globals`
+    /// set to the given values.
+    fn load_with_globals(globals: &[(&str, u64)]) -> Result<Bpf, BpfError> {
         trace!("Loading eBPF file: {}", Self::OBJ_NAME);
 
-        Bpf::load_file(format!(
+        let mut offsets = Vec::new();
+        for (global, offset) in relocation::resolve(Self::RELOCATIONS) {
+            match offset {
+                Ok(offset) => offsets.push((global, offset)),
+                Err(e) => warn!(
+                    "Using the default {} of eBPF file {}: {}",
+                    global,
+                    Self::OBJ_NAME,
+                    e
+                ),
+            }
+        }
+
+        let mut loader = BpfLoader::new();
+        for (global, offset) in &offsets {
+            loader.set_global(global, offset, true);
+        }
+        for (global, value) in globals {
+            loader.set_global(global, value, true);
+        }
+        loader.load_file(format!(
             "{}/ebpf/{}",
             AURAED_RUNTIME
                 .get()
@@ -0,0 +1,200 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! A minimal reader of the BTF of the kernel, for the offsets of the members
+//! of the kernel structs the probes read, see
+//! <linux>/Documentation/bpf/btf.rst.
+
+use std::ops::Range;
+use thiserror::Error;
+
+/// The BTF of the running kernel, exposed by kernels built with
+/// CONFIG_DEBUG_INFO_BTF.
+pub(crate) const KERNEL_BTF: &str = "/sys/kernel/btf/vmlinux";
+
+const BTF_MAGIC: u16 = 0xeb9f;
+
+const BTF_KIND_INT: u32 = 1;
+const BTF_KIND_ARRAY: u32 = 3;
+const BTF_KIND_STRUCT: u32 = 4;
+const BTF_KIND_UNION: u32 = 5;
+const BTF_KIND_ENUM: u32 = 6;
+const BTF_KIND_FUNC_PROTO: u32 = 13;
+const BTF_KIND_VAR: u32 = 14;
+const BTF_KIND_DATASEC: u32 = 15;
+const BTF_KIND_DECL_TAG: u32 = 17;
+const BTF_KIND_ENUM64: u32 = 19;
+
+#[derive(Debug, Error)]
+pub(crate) enum BtfError {
+    #[error("failed to read {KERNEL_BTF}: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("invalid BTF: {0}")]
+    Invalid(&'static str),
+}
+
+/// The types of a BTF blob, in the byte order of the host.
+pub(crate) struct Btf {
+    data: Vec<u8>,
+    types: Range<usize>,
+    strings: Range<usize>,
+}
+
+impl Btf {
+    /// Reads the BTF of the running kernel.
+    pub(crate) fn kernel() -> Result<Self, BtfError> {
+        Self::parse(std::fs::read(KERNEL_BTF)?)
+    }
+
+    fn parse(data: Vec<u8>) -> Result<Self, BtfError> {
+        if data.get(..2) != Some(&BTF_MAGIC.to_ne_bytes()[..]) {
+            return Err(BtfError::Invalid("bad magic"));
+        }
+        let header = |at| u32_at(&data, at).map(|v| v as usize);
+        let hdr_len = header(4)?;
+        // The offsets and lengths of the sections follow the length of the
+        // header
+        let section = |at| {
+            let start = hdr_len + header(at)?;
+            let end = start + header(at + 4)?;
+            if end > data.len() {
+                return Err(BtfError::Invalid("truncated section"));
+            }
+            Ok(start..end)
+        };
+        let types = section(8)?;
+        let strings = section(16)?;
+        Ok(Self { data, types, strings })
+    }
+
+    /// The offset in bytes of `member` in `struct name`, None if there is no
+    /// such struct or member.
+    pub(crate) fn member_offset(
+        &self,
+        name: &str,
+        member: &str,
+    ) -> Result<Option<u32>, BtfError> {
+        let mut at = self.types.start;
+        while at < self.types.end {
+            let name_off = self.u32_at(at)?;
+            let info = self.u32_at(at + 4)?;
+            let kind = (info >> 24) & 0x1f;
+            let vlen = (info & 0xffff) as usize;
+            at += 12;
+
+            if kind == BTF_KIND_STRUCT && self.string(name_off)? == name {
+                for i in 0..vlen {
+                    let m = at + i * 12;
+                    if self.string(self.u32_at(m)?)? != member {
+                        continue;
+                    }
+                    // In bits, with the size of bitfields in the high byte if
+                    // the kind flag is set
+                    let mut offset = self.u32_at(m + 8)?;
+                    if info >> 31 == 1 {
+                        offset &= 0xff_ffff;
+                    }
+                    return Ok(Some(offset / 8));
+                }
+                return Ok(None);
+            }
+
+            // Skip the data following the type
+            at += match kind {
+                BTF_KIND_INT | BTF_KIND_VAR | BTF_KIND_DECL_TAG => 4,
+                BTF_KIND_ARRAY => 12,
+                BTF_KIND_STRUCT | BTF_KIND_UNION | BTF_KIND_DATASEC
+                | BTF_KIND_ENUM64 => vlen * 12,
+                BTF_KIND_ENUM | BTF_KIND_FUNC_PROTO => vlen * 8,
+                1..=BTF_KIND_ENUM64 => 0,
+                _ => return Err(BtfError::Invalid("unknown type kind")),
+            };
+        }
+        Ok(None)
+    }
+
+    fn u32_at(&self, at: usize) -> Result<u32, BtfError> {
+        u32_at(&self.data, at)
+    }
+
+    fn string(&self, offset: u32) -> Result<&str, BtfError> {
+        let start = self.strings.start + offset as usize;
+        let strings = self
+            .data
+            .get(start..self.strings.end)
+            .ok_or(BtfError::Invalid("string out of bounds"))?;
+        let len = strings.iter().position(|b| *b == 0).unwrap_or(strings.len());
+        std::str::from_utf8(&strings[..len])
+            .map_err(|_| BtfError::Invalid("string is not UTF-8"))
+    }
+}
+
+fn u32_at(data: &[u8], at: usize) -> Result<u32, BtfError> {
+    data.get(at..at + 4)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_ne_bytes)
+        .ok_or(BtfError::Invalid("truncated"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// BTF of `struct point { int x; int y; }`, with the int first.
+    fn point() -> Vec<u8> {
+        let strings = b"\0int\0point\0x\0y\0";
+        let mut types: Vec<u32> = vec![
+            // int, 4 bytes, 32 bits
+            1,
+            BTF_KIND_INT << 24,
+            4,
+            32,
+            // struct point, 8 bytes, 2 members
+            5,
+            BTF_KIND_STRUCT << 24 | 2,
+            8,
+        ];
+        // x at bit 0 and y at bit 32, both ints
+        types.extend([11, 1, 0, 13, 1, 32]);
+
+        let types: Vec<u8> =
+            types.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        let mut data = Vec::new();
+        data.extend(BTF_MAGIC.to_ne_bytes());
+        data.extend([1, 0]);
+        for v in [24, 0, types.len() as u32, types.len() as u32, 15] {
+            data.extend(v.to_ne_bytes());
+        }
+        data.extend(types);
+        data.extend(strings);
+        data
+    }
+
+    #[test]
+    fn must_find_member_offsets() {
+        let btf = Btf::parse(point()).expect("valid BTF");
+        assert_eq!(btf.member_offset("point", "x").expect("offset"), Some(0));
+        assert_eq!(btf.member_offset("point", "y").expect("offset"), Some(4));
+        assert_eq!(btf.member_offset("point", "z").expect("offset"), None);
+        assert_eq!(btf.member_offset("line", "x").expect("offset"), None);
+    }
+
+    #[test]
+    fn must_reject_invalid_btf() {
+        assert!(Btf::parse(b"ELF\0".to_vec()).is_err());
+        let mut truncated = point();
+        truncated.truncate(30);
+        assert!(Btf::parse(truncated).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Drops the events of processes outside of the top-level cells in the
+//! kernel, rather than reading every event of the host into auraed to filter
+//! it there.
+//!
+//! Each probe object reporting the events of processes has its own cgroup
+//! filter map, all of them are kept in sync.
+
+use aurae_ebpf_shared::{CGROUP_FILTER_ENABLED, CGROUP_FILTER_MAP};
+use aya::maps::{HashMap as BpfHashMap, MapData};
+use aya::Bpf;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+const CGROUPFS_ROOT: &str = "/sys/fs/cgroup";
+
+/// The top-level cells the eBPF probes report the events of.
+#[derive(Debug)]
+pub struct CgroupFilter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    maps: Vec<BpfHashMap<MapData, u64, u8>>,
+    /// The ids of the cgroups of the cells added to the maps, by cell, as
+    /// the cgroups are gone by the time the cells are removed
+    cells: HashMap<String, u64>,
+}
+
+impl CgroupFilter {
+    /// Takes the cgroup filter maps of the loaded probe objects, the events
+    /// of all processes are reported until [CgroupFilter::enable].
+    pub(crate) fn take(bpfs: &mut [Bpf]) -> anyhow::Result<Self> {
+        let mut maps = vec![];
+        for bpf in bpfs {
+            if let Some(map) = bpf.take_map(CGROUP_FILTER_MAP) {
+                maps.push(BpfHashMap::try_from(map)?);
+            }
+        }
+        Ok(Self { state: Mutex::new(State { maps, cells: HashMap::new() }) })
+    }
+
+    /// Drops the events of processes outside of the cells added to the
+    /// filter from now on.
+    pub fn enable(&self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().expect("cgroup filter lock");
+        for map in &mut state.maps {
+            map.insert(CGROUP_FILTER_ENABLED, 1, 0)?;
+        }
+        Ok(())
+    }
+
+    /// Reports the events of the processes of the top-level cell
+    /// `cell_name`, including the ones of its nested cells and containers.
+    pub fn add(&self, cell_name: &str) -> anyhow::Result<()> {
+        // the id of a cgroup is the inode number of its directory
+        let cgroup_id =
+            Path::new(CGROUPFS_ROOT).join(cell_name).metadata()?.ino();
+        let mut state = self.state.lock().expect("cgroup filter lock");
+        for map in &mut state.maps {
+            map.insert(cgroup_id, 1, 0)?;
+        }
+        let _ = state.cells.insert(cell_name.into(), cgroup_id);
+        Ok(())
+    }
+
+    /// Stops reporting the events of `cell_name`, once freed.
+    pub fn remove(&self, cell_name: &str) -> anyhow::Result<()> {
+        let mut state = self.state.lock().expect("cgroup filter lock");
+        let Some(cgroup_id) = state.cells.remove(cell_name) else {
+            return Ok(());
+        };
+        for map in &mut state.maps {
+            map.remove(&cgroup_id)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,216 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Which eBPF probes auraed loads and which events they report, from the
+//! flags of auraed and its eBPF config file, e.g.
+//!
+//! ```json
+//! {
+//!   "cells_only": true,
+//!   "disabled_probes": ["signals", "tcp_connects"]
+//! }
+//! ```
+//!
+//! Some probes are unavailable or too costly on some kernels, the events of
+//! a probe that is not loaded are not available to ObserveService.
+
+use serde::Deserialize;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum EbpfConfigError {
+    #[error("failed to read eBPF config '{path}': {source}")]
+    Read { path: String, source: std::io::Error },
+    #[error("invalid eBPF config '{path}': {reason}")]
+    InvalidConfig { path: String, reason: String },
+}
+
+/// Which eBPF probes auraed loads and which events they report.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EbpfConfig {
+    /// Only report the events of processes in cells, dropping the others in
+    /// the kernel. Streams of the events of the whole host then only carry
+    /// the events of cells.
+    pub cells_only: bool,
+    /// The probes auraed loads.
+    pub probes: EbpfProbes,
+}
+
+impl EbpfConfig {
+    /// Reads the config in `path`, the default config if it does not exist.
+    pub fn load(path: &Path) -> Result<Self, EbpfConfigError> {
+        match std::fs::read(path) {
+            Ok(contents) => parse(&contents).map_err(|reason| {
+                EbpfConfigError::InvalidConfig {
+                    path: path.display().to_string(),
+                    reason,
+                }
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(source) => Err(EbpfConfigError::Read {
+                path: path.display().to_string(),
+                source,
+            }),
+        }
+    }
+
+    /// Combines two configs, such as the ones of the flags and of the config
+    /// file: a probe disabled by either is not loaded, and the events of
+    /// processes outside of cells are dropped if either says so.
+    pub fn combine(self, other: Self) -> Self {
+        let (a, b) = (self.probes, other.probes);
+        Self {
+            cells_only: self.cells_only || other.cells_only,
+            probes: EbpfProbes {
+                forks: a.forks && b.forks,
+                exits: a.exits && b.exits,
+                signals: a.signals && b.signals,
+                execs: a.execs && b.execs,
+                tcp_connects: a.tcp_connects && b.tcp_connects,
+                oom_kills: a.oom_kills && b.oom_kills,
+                network: a.network && b.network,
+                cpu_profiles: a.cpu_profiles && b.cpu_profiles,
+            },
+        }
+    }
+}
+
+/// The keys of the eBPF config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct EbpfConfigFile {
+    cells_only: bool,
+    disabled_probes: Vec<String>,
+}
+
+fn parse(contents: &[u8]) -> Result<EbpfConfig, String> {
+    let file: EbpfConfigFile =
+        serde_json::from_slice(contents).map_err(|e| e.to_string())?;
+    let mut probes = EbpfProbes::default();
+    for name in &file.disabled_probes {
+        probes.disable(name).map_err(|e| e.to_string())?;
+    }
+    Ok(EbpfConfig { cells_only: file.cells_only, probes })
+}
+
+/// Which eBPF probes auraed loads. All of them by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EbpfProbes {
+    /// sched_process_fork, which along with the exits maps the PIDs of
+    /// events to the PID namespaces of workloads.
+    pub forks: bool,
+    /// taskstats_exit.
+    pub exits: bool,
+    /// signal_generate, behind GetPosixSignalsStream.
+    pub signals: bool,
+    /// sched_process_exec, behind GetProcessExecStream.
+    pub execs: bool,
+    /// inet_sock_set_state, behind GetTcpConnectStream.
+    pub tcp_connects: bool,
+    /// oom_kill_process, behind GetOomKillStream.
+    pub oom_kills: bool,
+    /// The cgroup_skb programs counting the network traffic of cells.
+    pub network: bool,
+    /// perf_event, behind GetCpuProfile, loaded for each profile.
+    pub cpu_profiles: bool,
+}
+
+impl Default for EbpfProbes {
+    fn default() -> Self {
+        Self {
+            forks: true,
+            exits: true,
+            signals: true,
+            execs: true,
+            tcp_connects: true,
+            oom_kills: true,
+            network: true,
+            cpu_profiles: true,
+        }
+    }
+}
+
+impl EbpfProbes {
+    fn disable(&mut self, name: &str) -> Result<(), UnknownProbe> {
+        let enabled = match name {
+            "forks" => &mut self.forks,
+            "exits" => &mut self.exits,
+            "signals" => &mut self.signals,
+            "execs" => &mut self.execs,
+            "tcp_connects" => &mut self.tcp_connects,
+            "oom_kills" => &mut self.oom_kills,
+            "network" => &mut self.network,
+            "cpu_profiles" => &mut self.cpu_profiles,
+            _ => return Err(UnknownProbe(name.to_string())),
+        };
+        *enabled = false;
+        Ok(())
+    }
+}
+
+impl FromStr for EbpfProbes {
+    type Err = UnknownProbe;
+
+    /// Parses a comma separated list of the probes to disable.
+    fn from_str(disabled: &str) -> Result<Self, Self::Err> {
+        let mut probes = Self::default();
+        for name in disabled.split(',').map(str::trim).filter(|n| !n.is_empty())
+        {
+            probes.disable(name)?;
+        }
+        Ok(probes)
+    }
+}
+
+/// An eBPF probe auraed does not know of.
+#[derive(Debug, Error)]
+#[error("unknown eBPF probe '{0}', expected one of forks, exits, signals, execs, tcp_connects, oom_kills, network or cpu_profiles")]
+pub struct UnknownProbe(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_disabled_probes() {
+        let probes: EbpfProbes = "signals, network".parse().expect("parse");
+        assert!(!probes.signals && !probes.network);
+        assert!(probes.forks && probes.exits && probes.oom_kills);
+        assert!("".parse::<EbpfProbes>().is_ok());
+        assert!("kprobes".parse::<EbpfProbes>().is_err());
+    }
+
+    #[test]
+    fn flags_and_config_file_combine() {
+        let file = parse(
+            br#"{"cells_only": true, "disabled_probes": ["tcp_connects"]}"#,
+        )
+        .expect("parse");
+        let flags = EbpfConfig {
+            cells_only: false,
+            probes: "execs".parse().expect("parse"),
+        };
+
+        let config = flags.combine(file);
+        assert!(config.cells_only);
+        assert!(!config.probes.execs && !config.probes.tcp_connects);
+        assert!(config.probes.signals);
+
+        assert!(parse(br#"{"disabled_probes": ["kprobes"]}"#).is_err());
+        assert!(parse(br#"{"probes": []}"#).is_err());
+    }
+}
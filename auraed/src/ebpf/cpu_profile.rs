@@ -0,0 +1,221 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! CPU profiles of the processes of a cgroup or of one process, taken on
+//! demand by a perf_event program attached to the CPU clock of each CPU.
+//!
+//! The program samples the stacks of the target and counts the samples of
+//! each stack in the kernel, the stacks are read and symbolized once the
+//! profile is over. Processes that exit meanwhile are not symbolized.
+
+use super::bpf_file::BpfFile;
+use super::symbols::Symbolizer;
+use anyhow::Context;
+use aurae_ebpf_shared::{
+    ProfileStack, PROFILE_KERNEL_STACK, PROFILE_STACK_COUNTS_MAP,
+    PROFILE_STACK_TRACES_MAP, PROFILE_TGID, PROFILE_USER_STACK,
+};
+use aya::maps::{MapData, PerCpuHashMap, StackTraceMap};
+use aya::programs::perf_event::{
+    perf_sw_ids, PerfEventScope, PerfTypeId, SamplePolicy,
+};
+use aya::programs::PerfEvent;
+use aya::util::online_cpus;
+use aya::Bpf;
+use std::collections::{BTreeMap, HashMap};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const CGROUPFS_ROOT: &str = "/sys/fs/cgroup";
+const PROGRAM: &str = "perf_event_cpu_profile";
+
+pub struct CpuProfileProgram;
+
+impl BpfFile for CpuProfileProgram {
+    /// Definition of the Aurae eBPF probe to sample the stacks of CPU
+    /// profiles.
+    const OBJ_NAME: &'static str = "instrument-perf-event-cpu-profile";
+}
+
+/// The processes a profile samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileTarget {
+    /// The processes within a cgroup, including its descendants, by the id
+    /// of the cgroup and its level in the hierarchy
+    Cgroup { id: u64, level: u64 },
+    /// The threads of a process, by its pid on the host
+    Process { pid: i32 },
+}
+
+impl ProfileTarget {
+    /// The processes within `cgroup`, a directory below /sys/fs/cgroup.
+    pub fn cgroup(cgroup: &Path) -> anyhow::Result<Self> {
+        let level = cgroup
+            .strip_prefix(CGROUPFS_ROOT)
+            .with_context(|| format!("'{}' is not a cgroup", cgroup.display()))?
+            .components()
+            .count();
+        // the id of a cgroup is the inode number of its directory
+        let id = cgroup.metadata()?.ino();
+        Ok(Self::Cgroup { id, level: level as u64 })
+    }
+}
+
+/// A stack of a process and how often it was sampled. The frames are
+/// instruction pointers, from the outermost to the innermost.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampledStack {
+    pub pid: i32,
+    pub user: Vec<u64>,
+    pub kernel: Vec<u64>,
+    pub samples: u64,
+}
+
+/// A profile being sampled, the program is detached once dropped.
+#[derive(Debug)]
+pub struct CpuProfile {
+    bpf: Bpf,
+}
+
+impl CpuProfile {
+    /// Starts sampling the stacks of `target` `frequency_hz` times per
+    /// second on each CPU.
+    pub fn start(
+        target: ProfileTarget,
+        frequency_hz: u64,
+    ) -> anyhow::Result<Self> {
+        let globals = match target {
+            ProfileTarget::Cgroup { id, level } => {
+                vec![("TARGET_CGROUP_ID", id), ("TARGET_CGROUP_LEVEL", level)]
+            }
+            ProfileTarget::Process { pid } => {
+                vec![("TARGET_TGID", u64::try_from(pid)?)]
+            }
+        };
+        let mut bpf = CpuProfileProgram::load_with_globals(&globals)?;
+
+        let program: &mut PerfEvent = bpf
+            .program_mut(PROGRAM)
+            .context("failed to get eBPF program")?
+            .try_into()?;
+        program.load()?;
+        for cpu in online_cpus()? {
+            let _ = program.attach(
+                PerfTypeId::Software,
+                perf_sw_ids::PERF_COUNT_SW_CPU_CLOCK as u64,
+                PerfEventScope::AllProcessesOneCpu { cpu },
+                SamplePolicy::Frequency(frequency_hz),
+            )?;
+        }
+
+        Ok(Self { bpf })
+    }
+
+    /// Stops sampling, returning the sampled stacks.
+    pub fn stop(self) -> anyhow::Result<Vec<SampledStack>> {
+        let counts: PerCpuHashMap<&MapData, ProfileStack, u64> =
+            PerCpuHashMap::try_from(
+                self.bpf
+                    .map(PROFILE_STACK_COUNTS_MAP)
+                    .context("failed to get eBPF map")?,
+            )?;
+        let traces: StackTraceMap<&MapData> = StackTraceMap::try_from(
+            self.bpf
+                .map(PROFILE_STACK_TRACES_MAP)
+                .context("failed to get eBPF map")?,
+        )?;
+
+        let mut stacks = vec![];
+        for entry in counts.iter() {
+            let (stack, per_cpu) = entry?;
+            stacks.push(SampledStack {
+                pid: stack[PROFILE_TGID],
+                user: frames(&traces, stack[PROFILE_USER_STACK]),
+                kernel: frames(&traces, stack[PROFILE_KERNEL_STACK]),
+                samples: per_cpu.iter().sum(),
+            });
+        }
+        Ok(stacks)
+    }
+}
+
+/// The frames of the stack `stack_id`, negative if the stack could not be
+/// walked, outermost first.
+fn frames(traces: &StackTraceMap<&MapData>, stack_id: i32) -> Vec<u64> {
+    let Ok(stack_id) = u32::try_from(stack_id) else {
+        return vec![];
+    };
+    traces
+        .get(&stack_id, 0)
+        .map(|trace| trace.frames().iter().rev().map(|f| f.ip).collect())
+        .unwrap_or_default()
+}
+
+/// Folds `stacks` into one line per distinct stack, e.g.
+/// "nginx;main;epoll_wait;do_syscall_64_[k] 12", from the process to the
+/// innermost frame. Kernel frames end in "_[k]".
+pub fn fold_stacks(stacks: &[SampledStack]) -> String {
+    fold(stacks, &mut Symbolizer::new())
+}
+
+fn fold(stacks: &[SampledStack], symbolizer: &mut Symbolizer) -> String {
+    let mut comms: HashMap<i32, String> = HashMap::new();
+    let mut folded: BTreeMap<String, u64> = BTreeMap::new();
+    for stack in stacks {
+        let comm = comms.entry(stack.pid).or_insert_with(|| {
+            std::fs::read_to_string(format!("/proc/{}/comm", stack.pid))
+                .map(|comm| comm.trim().to_string())
+                .unwrap_or_else(|_| format!("pid {}", stack.pid))
+        });
+        let mut frames = vec![comm.clone()];
+        for ip in &stack.user {
+            frames.push(
+                symbolizer
+                    .user(stack.pid, *ip)
+                    .unwrap_or_else(|| "[unknown]".into()),
+            );
+        }
+        for ip in &stack.kernel {
+            let name = symbolizer.kernel(*ip).unwrap_or("[unknown]");
+            frames.push(format!("{name}_[k]"));
+        }
+        // the separators of the format are not to appear in frames
+        let line = frames.join(";").replace(' ', "_");
+        *folded.entry(line).or_default() += stack.samples;
+    }
+
+    folded
+        .into_iter()
+        .map(|(stack, samples)| format!("{stack} {samples}\n"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_identical_stacks() {
+        let stack = |samples| SampledStack {
+            // no process has the pid -1, its frames are unknown
+            pid: -1,
+            user: vec![0x1000, 0x2000],
+            kernel: vec![],
+            samples,
+        };
+        let folded = fold(&[stack(2), stack(3)], &mut Symbolizer::default());
+        assert_eq!(folded, "pid_-1;[unknown];[unknown] 5\n");
+    }
+}
@@ -12,6 +12,7 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
+use crate::ebpf::tracepoint::{load_and_attach_tracepoint, CompanionProgram};
 use aya::programs::{KProbe, ProgramError};
 use aya::Bpf;
 use tracing::{trace, warn};
@@ -20,6 +21,9 @@ pub trait KProbeProgram<T: Clone + Send + 'static> {
     const PROGRAM_NAME: &'static str;
     const FUNCTION_NAME: &'static str;
     const PERF_BUFFER: &'static str;
+    /// Tracepoint programs of the same object attached after the program,
+    /// such as ones reporting state the program collects.
+    const COMPANION_TRACEPOINTS: &'static [CompanionProgram] = &[];
 
     fn load_and_attach(bpf: &mut Bpf) -> Result<(), anyhow::Error> {
         trace!("Loading eBPF program: {}", Self::PROGRAM_NAME);
@@ -50,6 +54,10 @@ pub trait KProbeProgram<T: Clone + Send + 'static> {
             Err(e) => Err(e),
         }?;
 
+        for (program_name, category, event) in Self::COMPANION_TRACEPOINTS {
+            load_and_attach_tracepoint(bpf, program_name, category, event)?;
+        }
+
         Ok(())
     }
 }
\ No newline at end of file
@@ -12,8 +12,13 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
-use super::{bpf_file::BpfFile, perf_buffer_reader::PerfBufferReader};
-use aurae_ebpf_shared::ProcessExit;
+use super::{
+    bpf_file::BpfFile,
+    perf_buffer_reader::PerfBufferReader,
+    relocation::{RelocatedOffset, Relocation},
+    tracepoint::CompanionProgram,
+};
+use aurae_ebpf_shared::{OomKill, ProcessExit};
 pub use kprobe_program::KProbeProgram;
 
 mod kprobe_program;
@@ -32,4 +37,31 @@ impl BpfFile for TaskstatsExitKProbeProgram {
     const OBJ_NAME: &'static str = "instrument-kprobe-taskstats-exit";
 }
 
-impl PerfBufferReader<ProcessExit> for TaskstatsExitKProbeProgram {}
\ No newline at end of file
+impl PerfBufferReader<ProcessExit> for TaskstatsExitKProbeProgram {}
+pub struct OomKillProcessKProbeProgram;
+
+impl KProbeProgram<OomKill> for OomKillProcessKProbeProgram {
+    const PROGRAM_NAME: &'static str = "kprobe_oom_kill_process";
+    const FUNCTION_NAME: &'static str = "oom_kill_process";
+    const PERF_BUFFER: &'static str = "OOM_KILLS";
+    // Reports the kills with their victim
+    const COMPANION_TRACEPOINTS: &'static [CompanionProgram] =
+        &[("oom_mark_victim", "oom", "mark_victim")];
+}
+
+impl BpfFile for OomKillProcessKProbeProgram {
+    /// Definition of the Aurae eBPF probe to capture the processes killed by
+    /// the OOM killer at runtime.
+    const OBJ_NAME: &'static str = "instrument-kprobe-oom-kill-process";
+    const RELOCATIONS: &'static [RelocatedOffset] = &[
+        ("TOTALPAGES_OFFSET", Relocation::member("oom_control", "totalpages")),
+        (
+            "CHOSEN_POINTS_OFFSET",
+            Relocation::member("oom_control", "chosen_points"),
+        ),
+        ("CONSTRAINT_OFFSET", Relocation::member("oom_control", "constraint")),
+        ("VICTIM_PID_OFFSET", Relocation::field("oom", "mark_victim", "pid")),
+    ];
+}
+
+impl PerfBufferReader<OomKill> for OomKillProcessKProbeProgram {}
\ No newline at end of file
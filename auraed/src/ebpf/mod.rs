@@ -15,13 +15,29 @@
 
 pub use bpf_context::BpfContext;
 use bpf_file::BpfFile;
+pub use cgroup_filter::CgroupFilter;
+pub use config::{EbpfConfig, EbpfConfigError, EbpfProbes, UnknownProbe};
+pub use cpu_profile::{fold_stacks, CpuProfile, ProfileTarget, SampledStack};
+pub use kprobe::OomKillProcessKProbeProgram;
 pub use kprobe::TaskstatsExitKProbeProgram;
+pub use network_accounting::NetworkAccounting;
+pub use support::{EbpfSupport, ProgramSupport};
+pub use tracepoint::SchedProcessExecTracepointProgram;
 pub use tracepoint::SchedProcessForkTracepointProgram;
 pub use tracepoint::SignalSignalGenerateTracepointProgram;
+pub use tracepoint::SockInetSockSetStateTracepointProgram;
 
 mod bpf_context;
 mod bpf_file;
+mod btf;
+mod cgroup_filter;
+mod config;
+mod cpu_profile;
 pub(crate) mod kprobe;
+mod network_accounting;
 pub(crate) mod perf_buffer_reader;
 pub(crate) mod perf_event_broadcast;
+mod relocation;
+mod support;
+mod symbols;
 pub(crate) mod tracepoint;
\ No newline at end of file
@@ -0,0 +1,220 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Counts the bytes and packets cells send and receive with cgroup_skb
+//! programs attached to the cgroups of top-level cells, without iptables
+//! accounting rules.
+//!
+//! The programs count by the cgroup of the socket, the counters are summed
+//! up by cell in userspace. Counters of nested cells and of the containers
+//! of pods count towards their top-level cell.
+
+use super::bpf_file::BpfFile;
+use crate::observe::cgroup_cache::CgroupCache;
+use anyhow::Context;
+use aurae_ebpf_shared::NetworkCounters;
+use aya::maps::{MapData, PerCpuHashMap};
+use aya::programs::{
+    cgroup_skb::CgroupSkbLinkId, CgroupSkb, CgroupSkbAttachType,
+};
+use aya::Bpf;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::warn;
+
+const CGROUPFS_ROOT: &str = "/sys/fs/cgroup";
+const INGRESS_PROGRAM: &str = "cgroup_skb_ingress";
+const EGRESS_PROGRAM: &str = "cgroup_skb_egress";
+const COUNTERS_MAP: &str = "NETWORK_COUNTERS";
+
+pub struct NetworkAccountingProgram;
+
+impl BpfFile for NetworkAccountingProgram {
+    /// Definition of the Aurae eBPF probe to count the network traffic of
+    /// cells.
+    const OBJ_NAME: &'static str = "instrument-cgroup-skb-network-accounting";
+}
+
+/// The network counters of the top-level cells.
+#[derive(Debug)]
+pub struct NetworkAccounting {
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    bpf: Bpf,
+    cgroup_cache: CgroupCache,
+    /// The links of the programs, by the cell they are attached to
+    links: HashMap<String, (CgroupSkbLinkId, CgroupSkbLinkId)>,
+    /// Counters of cgroups of cells that are gone, such as nested cells that
+    /// were freed, so the counters of the cell keep growing
+    retired: HashMap<String, NetworkCounters>,
+}
+
+impl NetworkAccounting {
+    pub fn load() -> anyhow::Result<Self> {
+        let mut bpf = NetworkAccountingProgram::load()?;
+        for name in [INGRESS_PROGRAM, EGRESS_PROGRAM] {
+            let program: &mut CgroupSkb = bpf
+                .program_mut(name)
+                .context("failed to get eBPF program")?
+                .try_into()?;
+            program.load()?;
+        }
+
+        Ok(Self {
+            state: Mutex::new(State {
+                bpf,
+                cgroup_cache: CgroupCache::new(OsString::from(CGROUPFS_ROOT)),
+                links: HashMap::new(),
+                retired: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Starts counting the traffic of the top-level cell `cell_name`.
+    pub fn attach(&self, cell_name: &str) -> anyhow::Result<()> {
+        let cgroup = File::open(Path::new(CGROUPFS_ROOT).join(cell_name))?;
+        let mut state = self.state.lock().expect("network accounting lock");
+
+        let ingress = program(&mut state.bpf, INGRESS_PROGRAM)?
+            .attach(&cgroup, CgroupSkbAttachType::Ingress)?;
+        let egress = match program(&mut state.bpf, EGRESS_PROGRAM)?
+            .attach(&cgroup, CgroupSkbAttachType::Egress)
+        {
+            Ok(egress) => egress,
+            Err(e) => {
+                let _ =
+                    program(&mut state.bpf, INGRESS_PROGRAM)?.detach(ingress);
+                return Err(e.into());
+            }
+        };
+
+        let _ = state.links.insert(cell_name.into(), (ingress, egress));
+        Ok(())
+    }
+
+    /// Stops counting the traffic of `cell_name` and forgets its counters.
+    pub fn detach(&self, cell_name: &str) {
+        let mut state = self.state.lock().expect("network accounting lock");
+        let _ = state.retired.remove(cell_name);
+        let Some((ingress, egress)) = state.links.remove(cell_name) else {
+            return;
+        };
+        for (name, link) in
+            [(INGRESS_PROGRAM, ingress), (EGRESS_PROGRAM, egress)]
+        {
+            if let Err(e) =
+                program(&mut state.bpf, name).and_then(|p| Ok(p.detach(link)?))
+            {
+                warn!("Failed to detach {name} from cell {cell_name}: {e}");
+            }
+        }
+    }
+
+    /// The counters of the attached cells, by cell.
+    pub fn sample(&self) -> anyhow::Result<HashMap<String, NetworkCounters>> {
+        let mut state = self.state.lock().expect("network accounting lock");
+        let State { bpf, cgroup_cache, links, retired } = &mut *state;
+
+        let mut counters: PerCpuHashMap<&mut MapData, u64, NetworkCounters> =
+            PerCpuHashMap::try_from(
+                bpf.map_mut(COUNTERS_MAP).context("failed to get eBPF map")?,
+            )?;
+
+        let mut cells = retired.clone();
+        let mut gone = vec![];
+        for entry in counters.iter() {
+            let (cgroup_id, per_cpu) = entry?;
+            let Some(path) = cgroup_cache.get(cgroup_id).map(PathBuf::from)
+            else {
+                continue;
+            };
+            let Some(cell_name) =
+                top_level_cell(Path::new(CGROUPFS_ROOT), &path)
+                    .filter(|cell_name| links.contains_key(cell_name))
+            else {
+                continue;
+            };
+
+            let total =
+                per_cpu.iter().fold([0; 4], |total, cpu| add(total, *cpu));
+            let cell = cells.entry(cell_name.clone()).or_insert([0; 4]);
+            *cell = add(*cell, total);
+            if !path.exists() {
+                gone.push((cgroup_id, cell_name, total));
+            }
+        }
+
+        // Keep the counters of removed cgroups out of the map, which would
+        // fill up otherwise
+        for (cgroup_id, cell_name, total) in gone {
+            counters.remove(&cgroup_id)?;
+            let retired = retired.entry(cell_name).or_insert([0; 4]);
+            *retired = add(*retired, total);
+        }
+
+        for cell_name in links.keys() {
+            let _ = cells.entry(cell_name.clone()).or_insert([0; 4]);
+        }
+        Ok(cells)
+    }
+}
+
+fn program<'a>(
+    bpf: &'a mut Bpf,
+    name: &str,
+) -> anyhow::Result<&'a mut CgroupSkb> {
+    Ok(bpf
+        .program_mut(name)
+        .context("failed to get eBPF program")?
+        .try_into()?)
+}
+
+/// The top-level cell a cgroup below `root` belongs to.
+fn top_level_cell(root: &Path, cgroup: &Path) -> Option<String> {
+    cgroup
+        .strip_prefix(root)
+        .ok()?
+        .components()
+        .next()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+}
+
+fn add(a: NetworkCounters, b: NetworkCounters) -> NetworkCounters {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cgroups_belong_to_their_top_level_cell() {
+        let root = Path::new("/sys/fs/cgroup");
+        for cgroup in ["ae-1/_", "ae-1/ae-2/_", "ae-1/container"] {
+            assert_eq!(
+                top_level_cell(root, &root.join(cgroup)).as_deref(),
+                Some("ae-1")
+            );
+        }
+        assert_eq!(top_level_cell(root, root), None);
+        assert_eq!(top_level_cell(root, Path::new("/tmp/ae-1")), None);
+    }
+}
@@ -0,0 +1,192 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Relocation of the offsets of the kernel fields the probes read, which vary
+//! between kernel versions: each offset is a global of a probe, see
+//! `ebpf/src/offsets.rs`, set when the probe is loaded to the offset on the
+//! running kernel. The offsets of the fields of trace events are read from
+//! their formats in tracefs, the ones of kernel structs from the BTF of the
+//! kernel. A probe whose offsets can not be relocated keeps the offsets it
+//! was written against.
+
+use super::btf::Btf;
+use std::io;
+use thiserror::Error;
+
+/// Where tracefs is mounted, by default and on older kernels.
+const TRACEFS: [&str; 2] = ["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+
+/// A global of a probe holding an offset, and where the offset is read from.
+pub type RelocatedOffset = (&'static str, Relocation);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relocation {
+    /// A field of the events of the tracepoint `category`/`event`.
+    TracepointField {
+        category: &'static str,
+        event: &'static str,
+        field: &'static str,
+    },
+    /// A member of `struct name`.
+    StructMember { name: &'static str, member: &'static str },
+}
+
+impl Relocation {
+    pub const fn field(
+        category: &'static str,
+        event: &'static str,
+        field: &'static str,
+    ) -> Self {
+        Self::TracepointField { category, event, field }
+    }
+
+    pub const fn member(name: &'static str, member: &'static str) -> Self {
+        Self::StructMember { name, member }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum RelocationError {
+    #[error(
+        "failed to read the format of tracepoint {category}/{event}: {source}"
+    )]
+    Format { category: String, event: String, source: io::Error },
+    #[error("tracepoint {category}/{event} has no field {field}")]
+    MissingField { category: String, event: String, field: String },
+    #[error("{0}")]
+    Btf(String),
+    #[error("struct {name} has no member {member} in the BTF of the kernel")]
+    MissingMember { name: String, member: String },
+}
+
+/// Resolves the offsets of `relocations` on the running kernel.
+pub(crate) fn resolve(
+    relocations: &[RelocatedOffset],
+) -> Vec<(&'static str, Result<u32, RelocationError>)> {
+    // The BTF of the kernel is several MB, only read it if needed
+    let btf = relocations
+        .iter()
+        .any(|(_, r)| matches!(r, Relocation::StructMember { .. }))
+        .then(Btf::kernel);
+
+    relocations
+        .iter()
+        .map(|(global, relocation)| {
+            let offset = match *relocation {
+                Relocation::TracepointField { category, event, field } => {
+                    field_offset(category, event, field)
+                }
+                Relocation::StructMember { name, member } => match &btf {
+                    Some(Ok(btf)) => btf
+                        .member_offset(name, member)
+                        .map_err(|e| RelocationError::Btf(e.to_string()))
+                        .and_then(|offset| {
+                            offset.ok_or_else(|| {
+                                RelocationError::MissingMember {
+                                    name: name.into(),
+                                    member: member.into(),
+                                }
+                            })
+                        }),
+                    Some(Err(e)) => Err(RelocationError::Btf(e.to_string())),
+                    None => unreachable!("the BTF is read for struct members"),
+                },
+            };
+            (*global, offset)
+        })
+        .collect()
+}
+
+/// The format of the tracepoint `category`/`event`, which fails if the
+/// kernel does not have the tracepoint.
+pub(crate) fn tracepoint_format(
+    category: &str,
+    event: &str,
+) -> io::Result<String> {
+    let mut result = Err(io::ErrorKind::NotFound.into());
+    for tracefs in TRACEFS {
+        result = std::fs::read_to_string(format!(
+            "{tracefs}/events/{category}/{event}/format"
+        ));
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+fn field_offset(
+    category: &str,
+    event: &str,
+    field: &str,
+) -> Result<u32, RelocationError> {
+    let format = tracepoint_format(category, event).map_err(|source| {
+        RelocationError::Format {
+            category: category.into(),
+            event: event.into(),
+            source,
+        }
+    })?;
+    parse_field_offset(&format, field).ok_or_else(|| {
+        RelocationError::MissingField {
+            category: category.into(),
+            event: event.into(),
+            field: field.into(),
+        }
+    })
+}
+
+/// Parses the offset of `field` in a format, whose fields are lines such as
+/// `field:pid_t pid; offset:24; size:4; signed:1;`, separated by tabs.
+fn parse_field_offset(format: &str, field: &str) -> Option<u32> {
+    format.lines().find_map(|line| {
+        let mut parts = line.trim().split(';');
+        let declaration = parts.next()?.strip_prefix("field:")?;
+        // The name is the last word, without the size of arrays
+        let name = declaration.rsplit([' ', '*']).next()?;
+        if name.split('[').next()? != field {
+            return None;
+        }
+        parts.find_map(|p| p.trim().strip_prefix("offset:"))?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: &str = "name: sched_process_exec
+ID: 312
+format:
+	field:unsigned short common_type;	offset:0;	size:2;	signed:0;
+	field:int common_pid;	offset:4;	size:4;	signed:1;
+
+	field:__data_loc char[] filename;	offset:8;	size:4;	signed:0;
+	field:pid_t pid;	offset:12;	size:4;	signed:1;
+	field:const char *const * argv;	offset:16;	size:8;	signed:0;
+	field:__u8 saddr_v6[16];	offset:24;	size:16;	signed:0;
+
+print fmt: \"filename=%s pid=%d\", __get_str(filename), REC->pid
+";
+
+    #[test]
+    fn must_parse_field_offsets() {
+        assert_eq!(parse_field_offset(FORMAT, "common_pid"), Some(4));
+        assert_eq!(parse_field_offset(FORMAT, "filename"), Some(8));
+        assert_eq!(parse_field_offset(FORMAT, "pid"), Some(12));
+        assert_eq!(parse_field_offset(FORMAT, "argv"), Some(16));
+        assert_eq!(parse_field_offset(FORMAT, "saddr_v6"), Some(24));
+        assert_eq!(parse_field_offset(FORMAT, "old_pid"), None);
+    }
+}
@@ -0,0 +1,199 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Which eBPF programs of auraed the running kernel supports, reported by
+//! DiscoveryService. A program is supported if the kernel has the
+//! tracepoints and functions it attaches to, whether its offsets are
+//! relocated or not, see the relocation module.
+
+use super::bpf_file::BpfFile;
+use super::btf::KERNEL_BTF;
+use super::kprobe::{
+    KProbeProgram, OomKillProcessKProbeProgram, TaskstatsExitKProbeProgram,
+};
+use super::relocation::{self, RelocatedOffset};
+use super::tracepoint::{
+    SchedProcessExecTracepointProgram, SchedProcessForkTracepointProgram,
+    SignalSignalGenerateTracepointProgram,
+    SockInetSockSetStateTracepointProgram, TracepointProgram,
+};
+use crate::preflight::parse_release;
+use anyhow::anyhow;
+use aurae_ebpf_shared::{
+    ForkedProcess, OomKill, ProcessExec, ProcessExit, Signal, TcpConnect,
+};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+const CGROUPFS_ROOT: &str = "/sys/fs/cgroup";
+
+/// The release adding bpf_get_current_ancestor_cgroup_id, which the cgroup
+/// filter of the probes calls.
+const CGROUP_FILTER_KERNEL: (u32, u32) = (5, 7);
+
+/// Which eBPF programs of auraed the running kernel supports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EbpfSupport {
+    /// Whether the kernel exposes its BTF, which the offsets of kernel
+    /// structs are relocated against.
+    pub kernel_btf: bool,
+    /// The programs, in the order auraed loads them
+    pub programs: Vec<ProgramSupport>,
+}
+
+/// Whether the running kernel supports an eBPF program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramSupport {
+    /// Name of the program, such as `sched_process_fork`
+    pub name: &'static str,
+    /// Whether the program can be loaded
+    pub supported: bool,
+    /// Why the program is not supported, or where its offsets come from
+    pub detail: String,
+}
+
+impl EbpfSupport {
+    /// Probes the running kernel for the programs of auraed.
+    pub fn probe() -> Self {
+        Self {
+            kernel_btf: Path::new(KERNEL_BTF).exists(),
+            programs: vec![
+                tracepoint::<SchedProcessForkTracepointProgram, ForkedProcess>(
+                    "sched_process_fork",
+                ),
+                kprobe::<TaskstatsExitKProbeProgram, ProcessExit>(
+                    "taskstats_exit",
+                ),
+                tracepoint::<SignalSignalGenerateTracepointProgram, Signal>(
+                    "signal_generate",
+                ),
+                tracepoint::<SchedProcessExecTracepointProgram, ProcessExec>(
+                    "sched_process_exec",
+                ),
+                tracepoint::<SockInetSockSetStateTracepointProgram, TcpConnect>(
+                    "inet_sock_set_state",
+                ),
+                kprobe::<OomKillProcessKProbeProgram, OomKill>(
+                    "oom_kill_process",
+                ),
+                support("cgroup_skb", &[], cgroup_v2),
+                support("cgroup_filter", &[], ancestor_cgroup_id),
+            ],
+        }
+    }
+}
+
+fn tracepoint<P, T>(name: &'static str) -> ProgramSupport
+where
+    P: BpfFile + TracepointProgram<T>,
+    T: Clone + Send + 'static,
+{
+    support(name, P::RELOCATIONS, || {
+        let companions = P::COMPANION_PROGRAMS.iter().map(|(_, c, e)| (*c, *e));
+        std::iter::once((P::CATEGORY, P::EVENT)).chain(companions).try_for_each(
+            |(category, event)| tracepoint_exists(category, event),
+        )
+    })
+}
+
+fn kprobe<P, T>(name: &'static str) -> ProgramSupport
+where
+    P: BpfFile + KProbeProgram<T>,
+    T: Clone + Send + 'static,
+{
+    support(name, P::RELOCATIONS, || {
+        if !kernel_function_exists(P::FUNCTION_NAME)? {
+            return Err(anyhow!(
+                "kernel function {} is not available",
+                P::FUNCTION_NAME
+            ));
+        }
+        P::COMPANION_TRACEPOINTS.iter().try_for_each(|(_, category, event)| {
+            tracepoint_exists(category, event)
+        })
+    })
+}
+
+fn support(
+    name: &'static str,
+    relocations: &[RelocatedOffset],
+    check: impl FnOnce() -> anyhow::Result<()>,
+) -> ProgramSupport {
+    if let Err(e) = check() {
+        return ProgramSupport {
+            name,
+            supported: false,
+            detail: format!("{e:#}"),
+        };
+    }
+    let defaults: Vec<String> = relocation::resolve(relocations)
+        .into_iter()
+        .filter_map(|(global, offset)| {
+            offset.err().map(|e| format!("{global} ({e})"))
+        })
+        .collect();
+    let detail = if relocations.is_empty() {
+        "supported".into()
+    } else if defaults.is_empty() {
+        "offsets relocated".into()
+    } else {
+        format!("default offsets of {}", defaults.join(", "))
+    };
+    ProgramSupport { name, supported: true, detail }
+}
+
+fn cgroup_v2() -> anyhow::Result<()> {
+    if Path::new(CGROUPFS_ROOT).join("cgroup.controllers").exists() {
+        Ok(())
+    } else {
+        Err(anyhow!("cgroup v2 is not mounted at {CGROUPFS_ROOT}"))
+    }
+}
+
+fn ancestor_cgroup_id() -> anyhow::Result<()> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease")?;
+    match parse_release(&release) {
+        Some(version) if version >= CGROUP_FILTER_KERNEL => Ok(()),
+        _ => Err(anyhow!(
+            "kernel {} is older than {}.{}",
+            release.trim(),
+            CGROUP_FILTER_KERNEL.0,
+            CGROUP_FILTER_KERNEL.1
+        )),
+    }
+}
+
+fn tracepoint_exists(category: &str, event: &str) -> anyhow::Result<()> {
+    relocation::tracepoint_format(category, event)
+        .map(|_| ())
+        .map_err(|_| anyhow!("tracepoint {category}/{event} is not available"))
+}
+
+/// Whether the kernel has the function `name`, which kprobes attach to.
+fn kernel_function_exists(name: &str) -> anyhow::Result<bool> {
+    let kallsyms = BufReader::new(File::open("/proc/kallsyms")?);
+    for line in kallsyms.lines() {
+        // Lines are "<address> <type> <name> [<module>]"
+        let line = line?;
+        let mut fields = line.split_whitespace().skip(1);
+        if let (Some("t" | "T"), Some(symbol)) = (fields.next(), fields.next())
+        {
+            if symbol == name {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,233 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Names of the functions of the instruction pointers of sampled stacks.
+//!
+//! Kernel addresses are looked up in /proc/kallsyms, the addresses of
+//! processes in the symbol tables of the ELF files they map, read through
+//! the root of the process as the files may be in its mount namespace only.
+//! Rust symbols are demangled.
+
+use object::{Object, ObjectSegment};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Symbols sorted by address, each reaching up to the next.
+type Symbols = Vec<(u64, String)>;
+
+#[derive(Debug, Default)]
+pub struct Symbolizer {
+    kernel: Symbols,
+    /// The executable mappings of the processes seen so far, by pid
+    mappings: HashMap<i32, Vec<Mapping>>,
+    /// The ELF files seen so far, None if they could not be read
+    files: HashMap<PathBuf, Option<ElfSymbols>>,
+}
+
+/// An executable mapping of a file in /proc/<pid>/maps
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    /// The offset in the file mapped at `start`
+    offset: u64,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+struct ElfSymbols {
+    /// The loaded segments as (offset in the file, size, virtual address)
+    segments: Vec<(u64, u64, u64)>,
+    symbols: Symbols,
+}
+
+impl Symbolizer {
+    /// A symbolizer knowing the symbols of the running kernel, if it may
+    /// read their addresses.
+    pub fn new() -> Self {
+        let kernel = std::fs::read_to_string("/proc/kallsyms")
+            .map(|kallsyms| parse_kallsyms(&kallsyms))
+            .unwrap_or_default();
+        Self { kernel, ..Default::default() }
+    }
+
+    /// The function of the kernel address `ip`.
+    pub fn kernel(&self, ip: u64) -> Option<&str> {
+        lookup(&self.kernel, ip)
+    }
+
+    /// The function of the address `ip` of the process `pid`, else the name
+    /// of the file mapped at `ip`.
+    pub fn user(&mut self, pid: i32, ip: u64) -> Option<String> {
+        let mappings = self.mappings.entry(pid).or_insert_with(|| {
+            std::fs::read_to_string(format!("/proc/{pid}/maps"))
+                .map(|maps| parse_maps(&maps))
+                .unwrap_or_default()
+        });
+        let mapping =
+            mappings.iter().find(|m| (m.start..m.end).contains(&ip))?;
+
+        let root = PathBuf::from(format!("/proc/{pid}/root"));
+        let path = root.join(mapping.path.strip_prefix("/").ok()?);
+        let file = self
+            .files
+            .entry(path)
+            .or_insert_with_key(|path| ElfSymbols::read(path));
+        let name = file
+            .as_ref()
+            .and_then(|file| file.name(ip - mapping.start + mapping.offset));
+        match name {
+            Some(name) => Some(name.to_string()),
+            None => Some(format!(
+                "[{}]",
+                mapping.path.file_name()?.to_string_lossy()
+            )),
+        }
+    }
+}
+
+impl ElfSymbols {
+    fn read(path: &Path) -> Option<Self> {
+        let data = std::fs::read(path).ok()?;
+        let file = object::File::parse(&*data).ok()?;
+        let segments = file
+            .segments()
+            .map(|segment| {
+                let (offset, size) = segment.file_range();
+                (offset, size, segment.address())
+            })
+            .collect();
+        // The symbols of stripped files are the dynamic ones only
+        let symbols = file
+            .symbol_map()
+            .symbols()
+            .iter()
+            .map(|symbol| (symbol.address(), demangle(symbol.name())))
+            .collect();
+        Some(Self { segments, symbols })
+    }
+
+    /// The function at `offset` in the file.
+    fn name(&self, offset: u64) -> Option<&str> {
+        let (start, _, address) =
+            self.segments.iter().find(|(start, size, _)| {
+                (*start..start + size).contains(&offset)
+            })?;
+        lookup(&self.symbols, address + (offset - start))
+    }
+}
+
+fn lookup(symbols: &Symbols, address: u64) -> Option<&str> {
+    let next = symbols.partition_point(|(start, _)| *start <= address);
+    symbols.get(next.checked_sub(1)?).map(|(_, name)| name.as_str())
+}
+
+fn demangle(name: &str) -> String {
+    // names that are not mangled by Rust are kept as they are
+    format!("{:#}", rustc_demangle::demangle(name))
+}
+
+/// The functions of the kernel and its modules, e.g.
+/// "ffffffffc0a01000 t nf_hook_slow [nf_tables]". Addresses are all 0 to
+/// processes without CAP_SYSLOG.
+fn parse_kallsyms(kallsyms: &str) -> Symbols {
+    let mut symbols: Symbols = kallsyms
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let address = u64::from_str_radix(fields.next()?, 16).ok()?;
+            let kind = fields.next()?;
+            let name = fields.next()?;
+            (address != 0 && (kind == "t" || kind == "T"))
+                .then(|| (address, name.to_string()))
+        })
+        .collect();
+    symbols.sort();
+    symbols
+}
+
+/// The executable mappings of files in /proc/<pid>/maps, e.g.
+/// "7f2a1c028000-7f2a1c1bd000 r-xp 00028000 08:01 1835 /usr/lib/libc.so.6".
+fn parse_maps(maps: &str) -> Vec<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let perms = fields.next()?;
+            let offset = fields.next()?;
+            let path = fields.nth(2)?;
+            if !perms.contains('x') || !path.starts_with('/') {
+                return None;
+            }
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+                offset: u64::from_str_radix(offset, 16).ok()?,
+                path: PathBuf::from(path),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn looks_up_kernel_functions() {
+        let symbolizer = Symbolizer {
+            kernel: parse_kallsyms(
+                "ffffffff81000200 T do_syscall_64\n\
+                 ffffffff81000000 T _stext\n\
+                 ffffffff81000100 D some_data\n\
+                 0000000000000000 T hidden\n",
+            ),
+            ..Default::default()
+        };
+        assert_eq!(symbolizer.kernel(0xffffffff81000150), Some("_stext"));
+        assert_eq!(
+            symbolizer.kernel(0xffffffff81000210),
+            Some("do_syscall_64")
+        );
+        assert_eq!(symbolizer.kernel(0x1000), None);
+    }
+
+    #[test]
+    fn parses_the_executable_mappings_of_files() {
+        let mappings = parse_maps(
+            "55d0c4a00000-55d0c4a28000 r--p 00000000 08:01 1835 /usr/bin/app\n\
+             55d0c4a28000-55d0c4b00000 r-xp 00028000 08:01 1835 /usr/bin/app\n\
+             7ffd5e9f0000-7ffd5e9f2000 r-xp 00000000 00:00 0 [vdso]\n",
+        );
+        assert_eq!(
+            mappings,
+            vec![Mapping {
+                start: 0x55d0c4a28000,
+                end: 0x55d0c4b00000,
+                offset: 0x28000,
+                path: PathBuf::from("/usr/bin/app"),
+            }]
+        );
+    }
+
+    #[test]
+    fn demangles_rust_symbols() {
+        assert_eq!(
+            demangle("_ZN4core3fmt5write17h2a8d7c6c3b4a1e0fE"),
+            "core::fmt::write"
+        );
+        assert_eq!(demangle("malloc"), "malloc");
+    }
+}
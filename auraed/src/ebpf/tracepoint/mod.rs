@@ -15,9 +15,11 @@
 
 use super::bpf_file::BpfFile;
 use super::perf_buffer_reader::PerfBufferReader;
+use super::relocation::{RelocatedOffset, Relocation};
 pub use crate::ebpf::perf_event_broadcast::PerfEventBroadcast;
-use aurae_ebpf_shared::{ForkedProcess, Signal};
-pub use tracepoint_program::TracepointProgram;
+use aurae_ebpf_shared::{ForkedProcess, ProcessExec, Signal, TcpConnect};
+pub(crate) use tracepoint_program::load_and_attach_tracepoint;
+pub use tracepoint_program::{CompanionProgram, TracepointProgram};
 
 mod tracepoint_program;
 
@@ -35,6 +37,13 @@ impl BpfFile for SignalSignalGenerateTracepointProgram {
     /// kernel signals at runtime.
     const OBJ_NAME: &'static str =
         "instrument-tracepoint-signal-signal-generate";
+    const RELOCATIONS: &'static [RelocatedOffset] = &[
+        (
+            "SIGNAL_OFFSET",
+            Relocation::field("signal", "signal_generate", "sig"),
+        ),
+        ("PID_OFFSET", Relocation::field("signal", "signal_generate", "pid")),
+    ];
 }
 
 impl PerfBufferReader<Signal> for SignalSignalGenerateTracepointProgram {}
@@ -53,6 +62,121 @@ impl BpfFile for SchedProcessForkTracepointProgram {
     /// kernel signals at runtime.
     const OBJ_NAME: &'static str =
         "instrument-tracepoint-sched-sched-process-fork";
+    const RELOCATIONS: &'static [RelocatedOffset] = &[
+        (
+            "PARENT_PID_OFFSET",
+            Relocation::field("sched", "sched_process_fork", "parent_pid"),
+        ),
+        (
+            "CHILD_PID_OFFSET",
+            Relocation::field("sched", "sched_process_fork", "child_pid"),
+        ),
+    ];
 }
 
-impl PerfBufferReader<ForkedProcess> for SchedProcessForkTracepointProgram {}
\ No newline at end of file
+impl PerfBufferReader<ForkedProcess> for SchedProcessForkTracepointProgram {}
+pub struct SchedProcessExecTracepointProgram;
+
+impl TracepointProgram<ProcessExec> for SchedProcessExecTracepointProgram {
+    const PROGRAM_NAME: &'static str = "sched_process_exec";
+    const CATEGORY: &'static str = "sched";
+    const EVENT: &'static str = "sched_process_exec";
+    const PERF_BUFFER: &'static str = "PROCESS_EXECS";
+    // Hash the arguments of execs before they are gone
+    const COMPANION_PROGRAMS: &'static [CompanionProgram] = &[
+        ("sys_enter_execve", "syscalls", "sys_enter_execve"),
+        ("sys_enter_execveat", "syscalls", "sys_enter_execveat"),
+    ];
+}
+
+impl BpfFile for SchedProcessExecTracepointProgram {
+    /// Definition of the Aurae eBPF probe to capture the binaries executed
+    /// at runtime.
+    const OBJ_NAME: &'static str =
+        "instrument-tracepoint-sched-sched-process-exec";
+    const RELOCATIONS: &'static [RelocatedOffset] = &[
+        (
+            "FILENAME_OFFSET",
+            Relocation::field("sched", "sched_process_exec", "filename"),
+        ),
+        ("PID_OFFSET", Relocation::field("sched", "sched_process_exec", "pid")),
+        (
+            "OLD_PID_OFFSET",
+            Relocation::field("sched", "sched_process_exec", "old_pid"),
+        ),
+        (
+            "EXECVE_ARGV_OFFSET",
+            Relocation::field("syscalls", "sys_enter_execve", "argv"),
+        ),
+        (
+            "EXECVEAT_ARGV_OFFSET",
+            Relocation::field("syscalls", "sys_enter_execveat", "argv"),
+        ),
+    ];
+}
+
+impl PerfBufferReader<ProcessExec> for SchedProcessExecTracepointProgram {}
+
+pub struct SockInetSockSetStateTracepointProgram;
+
+impl TracepointProgram<TcpConnect> for SockInetSockSetStateTracepointProgram {
+    const PROGRAM_NAME: &'static str = "inet_sock_set_state";
+    const CATEGORY: &'static str = "sock";
+    const EVENT: &'static str = "inet_sock_set_state";
+    const PERF_BUFFER: &'static str = "TCP_CONNECTS";
+}
+
+impl BpfFile for SockInetSockSetStateTracepointProgram {
+    /// Definition of the Aurae eBPF probe to capture the outbound TCP
+    /// connections at runtime.
+    const OBJ_NAME: &'static str =
+        "instrument-tracepoint-sock-inet-sock-set-state";
+    const RELOCATIONS: &'static [RelocatedOffset] = &[
+        (
+            "SKADDR_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "skaddr"),
+        ),
+        (
+            "OLDSTATE_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "oldstate"),
+        ),
+        (
+            "NEWSTATE_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "newstate"),
+        ),
+        (
+            "SPORT_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "sport"),
+        ),
+        (
+            "DPORT_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "dport"),
+        ),
+        (
+            "FAMILY_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "family"),
+        ),
+        (
+            "PROTOCOL_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "protocol"),
+        ),
+        (
+            "SADDR_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "saddr"),
+        ),
+        (
+            "DADDR_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "daddr"),
+        ),
+        (
+            "SADDR_V6_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "saddr_v6"),
+        ),
+        (
+            "DADDR_V6_OFFSET",
+            Relocation::field("sock", "inet_sock_set_state", "daddr_v6"),
+        ),
+    ];
+}
+
+impl PerfBufferReader<TcpConnect> for SockInetSockSetStateTracepointProgram {}
\ No newline at end of file
@@ -18,41 +18,64 @@ use aya::programs::{ProgramError, TracePoint};
 use aya::Bpf;
 use tracing::{trace, warn};
 
+/// A program attached to a trace event, as (program name, category, event).
+pub type CompanionProgram = (&'static str, &'static str, &'static str);
+
 pub trait TracepointProgram<T: Clone + Send + 'static> {
     const PROGRAM_NAME: &'static str;
     const CATEGORY: &'static str;
     const EVENT: &'static str;
     const PERF_BUFFER: &'static str;
+    /// Programs of the same object attached before the program, such as
+    /// ones collecting state the program reads.
+    const COMPANION_PROGRAMS: &'static [CompanionProgram] = &[];
 
     fn load_and_attach(bpf: &mut Bpf) -> Result<(), anyhow::Error> {
-        trace!("Loading eBPF program: {}", Self::PROGRAM_NAME);
-
-        // Load the eBPF TracePoint program
-        let program: &mut TracePoint = bpf
-            .program_mut(Self::PROGRAM_NAME)
-            .context("failed to get eBPF program")?
-            .try_into()?;
-
-        // Load the program
-        match program.load() {
-            Ok(_) => Ok(()),
-            Err(ProgramError::AlreadyLoaded) => {
-                warn!("Already loaded eBPF program {}", Self::PROGRAM_NAME);
-                Ok(())
-            }
-            other => other,
-        }?;
-
-        // Attach to kernel trace event
-        match program.attach(Self::CATEGORY, Self::EVENT) {
-            Ok(_) => Ok(()),
-            Err(ProgramError::AlreadyAttached) => {
-                warn!("Already attached eBPF program {}", Self::PROGRAM_NAME);
-                Ok(())
-            }
-            Err(e) => Err(e),
-        }?;
-
-        Ok(())
+        for (program_name, category, event) in Self::COMPANION_PROGRAMS {
+            load_and_attach_tracepoint(bpf, program_name, category, event)?;
+        }
+        load_and_attach_tracepoint(
+            bpf,
+            Self::PROGRAM_NAME,
+            Self::CATEGORY,
+            Self::EVENT,
+        )
     }
+}
+
+pub(crate) fn load_and_attach_tracepoint(
+    bpf: &mut Bpf,
+    program_name: &str,
+    category: &str,
+    event: &str,
+) -> Result<(), anyhow::Error> {
+    trace!("Loading eBPF program: {}", program_name);
+
+    // Load the eBPF TracePoint program
+    let program: &mut TracePoint = bpf
+        .program_mut(program_name)
+        .context("failed to get eBPF program")?
+        .try_into()?;
+
+    // Load the program
+    match program.load() {
+        Ok(_) => Ok(()),
+        Err(ProgramError::AlreadyLoaded) => {
+            warn!("Already loaded eBPF program {}", program_name);
+            Ok(())
+        }
+        other => other,
+    }?;
+
+    // Attach to kernel trace event
+    match program.attach(category, event) {
+        Ok(_) => Ok(()),
+        Err(ProgramError::AlreadyAttached) => {
+            warn!("Already attached eBPF program {}", program_name);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }?;
+
+    Ok(())
 }
\ No newline at end of file
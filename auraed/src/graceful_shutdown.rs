@@ -13,7 +13,10 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use crate::{cells::CellService, discovery::DiscoveryService};
+use crate::{
+    cells::CellService, discovery::DiscoveryService, vms::VmService,
+    SubsystemsConfig,
+};
 use proto::{
     cells::cell_service_server::CellServiceServer,
     discovery::discovery_service_server::DiscoveryServiceServer,
@@ -23,12 +26,14 @@ use tokio::{
     signal::unix::SignalKind,
     sync::watch::{channel, Receiver, Sender},
 };
-use tonic_health::server::HealthReporter;
-use tracing::error;
+use tonic_health::{server::HealthReporter, ServingStatus};
+use tracing::{error, info, warn};
 
 pub(crate) struct GracefulShutdown {
     health_reporter: HealthReporter,
     cell_service: CellService,
+    vm_service: Option<VmService>,
+    subsystems: SubsystemsConfig,
     shutdown_broadcaster: Sender<()>,
 }
 
@@ -36,9 +41,17 @@ impl GracefulShutdown {
     pub fn new(
         health_reporter: HealthReporter,
         cell_service: CellService,
+        vm_service: Option<VmService>,
+        subsystems: SubsystemsConfig,
     ) -> Self {
         let (tx, _) = channel(());
-        Self { health_reporter, cell_service, shutdown_broadcaster: tx }
+        Self {
+            health_reporter,
+            cell_service,
+            vm_service,
+            subsystems,
+            shutdown_broadcaster: tx,
+        }
     }
 
     /// Subscribe to the shutdown broadcast channel
@@ -49,27 +62,47 @@ impl GracefulShutdown {
     /// Waits for a signal and then...
     /// * Broadcasts a shutdown signal to all subscribers. See [subscribe]
     /// * Waits for all subscribers to drop
+    /// * Calls [CellService::release_left_running] and
+    ///   [VmService::checkpoint_for_shutdown] to apply the shutdown policies
+    ///   of the workloads, or on [SIGUSR2] calls
+    ///   [CellService::release_for_upgrade] and
+    ///   [VmService::checkpoint_for_upgrade] to hand all workloads over to
+    ///   the next auraed
     /// * Calls [CellService::free_all]
     /// ---
     /// Signals:
     /// * [SIGTERM]
     /// * [SIGINT]
+    /// * [SIGUSR2] (upgrade)
     /// ---
     /// Returns after processing the first received signal.
     pub async fn wait(mut self) {
-        tokio::select! {
-            _ = wait_for_sigterm() => {},
-            _ = wait_for_sigint() => {},
+        let mut upgrade = tokio::select! {
+            _ = wait_for_sigterm() => false,
+            _ = wait_for_sigint() => false,
+            _ = wait_for_sigusr2() => true,
+        };
+
+        // The kernel panics if pid 1 exits, so there is no next auraed
+        if upgrade && std::process::id() == 1 {
+            warn!(
+                "auraed is pid 1 and cannot exit for an upgrade, shutting down"
+            );
+            upgrade = false;
         }
 
         // update health reporter
         let health_reporter = self.health_reporter.borrow_mut();
+        health_reporter.set_service_status("", ServingStatus::NotServing).await;
         health_reporter
             .set_not_serving::<CellServiceServer<CellService>>()
             .await;
-        health_reporter
-            .set_not_serving::<DiscoveryServiceServer<DiscoveryService>>()
-            .await;
+        // disabled services stay unknown to the health service
+        if self.subsystems.discovery {
+            health_reporter
+                .set_not_serving::<DiscoveryServiceServer<DiscoveryService>>()
+                .await;
+        }
 
         // health_reporter.set_not_serving::<PodServiceServer<PodService>>().await;
 
@@ -77,6 +110,34 @@ impl GracefulShutdown {
         // wait for all subscribers to drop
         self.shutdown_broadcaster.closed().await;
 
+        if upgrade {
+            info!("Handing workloads over for an upgrade");
+
+            if let Err(e) = self.cell_service.release_for_upgrade().await {
+                error!("Attempt to leave cells and executables running for upgrade resulted in error: {e}")
+            }
+
+            if let Some(vm_service) = &self.vm_service {
+                if let Err(e) = vm_service.checkpoint_for_upgrade().await {
+                    error!(
+                        "Attempt to checkpoint vms for upgrade resulted in error: {e}"
+                    )
+                }
+            }
+        } else {
+            if let Err(e) = self.cell_service.release_left_running().await {
+                error!("Attempt to leave cells running on terminate resulted in error: {e}")
+            }
+
+            if let Some(vm_service) = &self.vm_service {
+                if let Err(e) = vm_service.checkpoint_for_shutdown().await {
+                    error!(
+                        "Attempt to checkpoint vms on terminate resulted in error: {e}"
+                    )
+                }
+            }
+        }
+
         if let Err(e) = self.cell_service.free_all().await {
             error!(
                 "Attempt to free all cells on terminate resulted in error: {e}"
@@ -98,6 +159,13 @@ pub async fn wait_for_sigterm() {
     let _ = stream.recv().await;
 }
 
+pub async fn wait_for_sigusr2() {
+    let mut stream = tokio::signal::unix::signal(SignalKind::user_defined2())
+        .expect("failed to listen for SIGUSR2");
+
+    let _ = stream.recv().await;
+}
+
 pub async fn wait_for_sigint() {
     let mut stream = tokio::signal::unix::signal(SignalKind::interrupt())
         .expect("failed to listen for SIGINT");
@@ -0,0 +1,523 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Limits on the requests the gRPC services accept.
+//!
+//! auraed is privileged and parses whatever a client that reaches its socket
+//! sends, so requests are bounded in size and in how deep they nest messages
+//! before the generated code decodes them. Protobuf ignores fields it does
+//! not know, which can hide a mistyped or forged manifest. Such requests can
+//! optionally be rejected instead.
+//!
+//! The checks walk the wire format of each request against the schemas
+//! compiled into the proto crate, without decoding it into messages.
+
+use bytes::{Bytes, BytesMut};
+use client::quota::Quota;
+use prost::encoding::{decode_key, decode_varint, WireType};
+use prost::Message;
+use prost_types::{
+    field_descriptor_proto::Type, DescriptorProto, FileDescriptorSet,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tonic::body::BoxBody;
+use tonic::codegen::http::{Request, Response};
+use tonic::codegen::Body as HttpBody;
+use tonic::codegen::{BoxFuture, Service};
+use tonic::transport::Body;
+use tonic::Status;
+use tower::Layer;
+use tracing::error;
+
+/// Length of the header preceding each message in a gRPC request body: a
+/// compression flag and the length of the message.
+const FRAME_HEADER_LEN: usize = 5;
+
+/// The schemas of all services served by auraed.
+const FILE_DESCRIPTOR_SETS: [&[u8]; 8] = [
+    proto::cells::FILE_DESCRIPTOR_SET,
+    proto::cri::FILE_DESCRIPTOR_SET,
+    proto::discovery::FILE_DESCRIPTOR_SET,
+    proto::grpc::health::FILE_DESCRIPTOR_SET,
+    proto::images::FILE_DESCRIPTOR_SET,
+    proto::network::FILE_DESCRIPTOR_SET,
+    proto::observe::FILE_DESCRIPTOR_SET,
+    proto::vms::FILE_DESCRIPTOR_SET,
+];
+
+/// Limits on the messages of all gRPC services.
+#[derive(Debug, Clone)]
+pub struct GrpcLimits {
+    /// Largest request message the services decode, in bytes.
+    pub max_decoding_message_size: usize,
+    /// Largest response message the services encode, in bytes.
+    pub max_encoding_message_size: usize,
+    /// How deep messages may be nested within a request.
+    pub max_recursion_depth: usize,
+    /// Rejects requests setting fields auraed does not know, instead of
+    /// ignoring them.
+    pub reject_unknown_fields: bool,
+}
+
+impl Default for GrpcLimits {
+    fn default() -> Self {
+        Self {
+            max_decoding_message_size: 4 * 1024 * 1024,
+            max_encoding_message_size: 64 * 1024 * 1024,
+            max_recursion_depth: 32,
+            reject_unknown_fields: false,
+        }
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub(crate) enum GrpcLimitsError {
+    #[error("request of at least {size} bytes exceeds the limit of {limit}")]
+    TooLarge { size: usize, limit: usize },
+    #[error("request nests messages deeper than {limit} levels")]
+    TooDeep { limit: usize },
+    #[error("field {field} is unknown to message '{message}'")]
+    UnknownField { message: String, field: u32 },
+    #[error("request is not a valid gRPC message: {reason}")]
+    Malformed { reason: String },
+}
+
+impl From<prost::DecodeError> for GrpcLimitsError {
+    fn from(err: prost::DecodeError) -> Self {
+        Self::Malformed { reason: err.to_string() }
+    }
+}
+
+impl From<GrpcLimitsError> for Status {
+    fn from(err: GrpcLimitsError) -> Self {
+        let msg = err.to_string();
+        error!("{msg}");
+        match err {
+            GrpcLimitsError::TooLarge { size, limit } => Quota {
+                name: "max_decoding_message_size".into(),
+                subject: "request".into(),
+                current: size as u64,
+                limit: limit as u64,
+                retry_after: None,
+            }
+            .into_status(msg),
+            GrpcLimitsError::TooDeep { .. }
+            | GrpcLimitsError::UnknownField { .. }
+            | GrpcLimitsError::Malformed { .. } => {
+                Status::invalid_argument(msg)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Method {
+    /// Fully qualified name of the request message.
+    input: String,
+    client_streaming: bool,
+}
+
+/// The methods and messages of the services, as far as the checks need them.
+#[derive(Debug, Default)]
+struct Schema {
+    /// Keyed by the path of the method, `/package.Service/Method`.
+    methods: HashMap<String, Method>,
+    /// The fields of each message keyed by the fully qualified name of the
+    /// message. Fields holding a message map to the name of its type.
+    messages: HashMap<String, HashMap<u32, Option<String>>>,
+}
+
+impl Schema {
+    fn new(sets: &[&[u8]]) -> Result<Self, prost::DecodeError> {
+        let mut schema = Self::default();
+        for set in sets {
+            for file in FileDescriptorSet::decode(*set)?.file {
+                let scope = format!(".{}", file.package());
+                for message in &file.message_type {
+                    schema.add_message(&scope, message);
+                }
+                for service in &file.service {
+                    for method in &service.method {
+                        let path = format!(
+                            "/{}.{}/{}",
+                            file.package(),
+                            service.name(),
+                            method.name()
+                        );
+                        let _ = schema.methods.insert(
+                            path,
+                            Method {
+                                input: method.input_type().to_owned(),
+                                client_streaming: method.client_streaming(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+        Ok(schema)
+    }
+
+    fn add_message(&mut self, scope: &str, message: &DescriptorProto) {
+        let name = format!("{scope}.{}", message.name());
+        // Map entries are nested messages as well
+        for nested in &message.nested_type {
+            self.add_message(&name, nested);
+        }
+        let fields = message
+            .field
+            .iter()
+            .map(|field| {
+                let message = (field.r#type() == Type::Message)
+                    .then(|| field.type_name().to_owned());
+                (field.number() as u32, message)
+            })
+            .collect();
+        let _ = self.messages.insert(name, fields);
+    }
+
+    /// Checks each message framed in the body of a request to `message`.
+    fn check_frames(
+        &self,
+        message: &str,
+        mut body: &[u8],
+        limits: &GrpcLimits,
+    ) -> Result<(), GrpcLimitsError> {
+        while !body.is_empty() {
+            if body.len() < FRAME_HEADER_LEN {
+                return Err(GrpcLimitsError::Malformed {
+                    reason: "truncated message header".into(),
+                });
+            }
+            let compressed = body[0] != 0;
+            let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]])
+                as usize;
+            body = &body[FRAME_HEADER_LEN..];
+            if len > body.len() {
+                return Err(GrpcLimitsError::Malformed {
+                    reason: "truncated message".into(),
+                });
+            }
+            let (frame, rest) = body.split_at(len);
+            body = rest;
+            // The services do not accept compressed messages, they reject
+            // them before decoding
+            if !compressed {
+                self.check(message, frame, 0, limits)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the encoded `message` nested `depth` levels deep. Messages
+    /// missing from the schema, like the well known types, are only checked
+    /// for their depth.
+    fn check(
+        &self,
+        message: &str,
+        mut buf: &[u8],
+        depth: usize,
+        limits: &GrpcLimits,
+    ) -> Result<(), GrpcLimitsError> {
+        if depth > limits.max_recursion_depth {
+            return Err(GrpcLimitsError::TooDeep {
+                limit: limits.max_recursion_depth,
+            });
+        }
+        let fields = self.messages.get(message);
+
+        while !buf.is_empty() {
+            let (number, wire_type) = decode_key(&mut buf)?;
+            let field = fields.map(|fields| fields.get(&number));
+            if limits.reject_unknown_fields && matches!(field, Some(None)) {
+                return Err(GrpcLimitsError::UnknownField {
+                    message: message.trim_start_matches('.').to_owned(),
+                    field: number,
+                });
+            }
+
+            let len = match wire_type {
+                WireType::Varint => {
+                    let _ = decode_varint(&mut buf)?;
+                    continue;
+                }
+                WireType::SixtyFourBit => 8,
+                WireType::ThirtyTwoBit => 4,
+                WireType::LengthDelimited => decode_varint(&mut buf)? as usize,
+                WireType::StartGroup | WireType::EndGroup => {
+                    return Err(GrpcLimitsError::Malformed {
+                        reason: format!("field {number} is a group"),
+                    })
+                }
+            };
+            if len > buf.len() {
+                return Err(GrpcLimitsError::Malformed {
+                    reason: format!("field {number} is truncated"),
+                });
+            }
+            let (value, rest) = buf.split_at(len);
+            buf = rest;
+
+            if let Some(Some(Some(nested))) = field {
+                self.check(nested, value, depth + 1, limits)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies [GrpcLimits] to the requests of all services.
+#[derive(Debug, Clone)]
+pub(crate) struct GrpcLimitsLayer {
+    schema: Arc<Schema>,
+    limits: GrpcLimits,
+}
+
+impl GrpcLimitsLayer {
+    pub(crate) fn new(limits: GrpcLimits) -> Result<Self, prost::DecodeError> {
+        let schema = Arc::new(Schema::new(&FILE_DESCRIPTOR_SETS)?);
+        Ok(Self { schema, limits })
+    }
+}
+
+impl<S> Layer<S> for GrpcLimitsLayer {
+    type Service = GrpcLimitsService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcLimitsService {
+            inner,
+            schema: self.schema.clone(),
+            limits: self.limits.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct GrpcLimitsService<S> {
+    inner: S,
+    schema: Arc<Schema>,
+    limits: GrpcLimits,
+}
+
+impl<S> Service<Request<Body>> for GrpcLimitsService<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = S::Error;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // Call the service that was polled ready, a clone takes its place
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let schema = self.schema.clone();
+        let limits = self.limits.clone();
+
+        Box::pin(async move {
+            // Streamed requests are bounded by the size limit of the
+            // services only, they cannot be buffered
+            let Some(method) = schema
+                .methods
+                .get(req.uri().path())
+                .filter(|method| !method.client_streaming)
+            else {
+                return inner.call(req).await;
+            };
+
+            let (parts, body) = req.into_parts();
+            let limit = limits.max_decoding_message_size;
+            let checked = read_body(body, limit).await.and_then(|body| {
+                schema.check_frames(&method.input, &body, &limits)?;
+                Ok(body)
+            });
+            match checked {
+                Ok(body) => {
+                    inner
+                        .call(Request::from_parts(parts, Body::from(body)))
+                        .await
+                }
+                Err(e) => Ok(Status::from(e).to_http()),
+            }
+        })
+    }
+}
+
+/// Reads the body of a request holding a message of up to `limit` bytes.
+async fn read_body(
+    mut body: Body,
+    limit: usize,
+) -> Result<Bytes, GrpcLimitsError> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| GrpcLimitsError::Malformed {
+            reason: e.to_string(),
+        })?;
+        let size = (buf.len() + chunk.len()).saturating_sub(FRAME_HEADER_LEN);
+        if size > limit {
+            return Err(GrpcLimitsError::TooLarge { size, limit });
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    Ok(buf.freeze())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost_types::{
+        FieldDescriptorProto, FileDescriptorProto, MethodDescriptorProto,
+        ServiceDescriptorProto,
+    };
+
+    /// `message Node { string name = 1; Node child = 2; }` in package test,
+    /// served by `service Tree { rpc Plant(Node) returns (Node); }`.
+    fn schema() -> Schema {
+        let field = |name: &str, number, r#type: Type, type_name: &str| {
+            FieldDescriptorProto {
+                name: Some(name.into()),
+                number: Some(number),
+                r#type: Some(r#type as i32),
+                type_name: Some(type_name.into()),
+                ..Default::default()
+            }
+        };
+        let set = FileDescriptorSet {
+            file: vec![FileDescriptorProto {
+                package: Some("test".into()),
+                message_type: vec![DescriptorProto {
+                    name: Some("Node".into()),
+                    field: vec![
+                        field("name", 1, Type::String, ""),
+                        field("child", 2, Type::Message, ".test.Node"),
+                    ],
+                    ..Default::default()
+                }],
+                service: vec![ServiceDescriptorProto {
+                    name: Some("Tree".into()),
+                    method: vec![MethodDescriptorProto {
+                        name: Some("Plant".into()),
+                        input_type: Some(".test.Node".into()),
+                        output_type: Some(".test.Node".into()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+        };
+        Schema::new(&[&set.encode_to_vec()]).expect("valid schema")
+    }
+
+    /// A Node with `depth` nested children.
+    fn nested(depth: usize) -> Vec<u8> {
+        let mut node = vec![0x0a, 0x01, b'a'];
+        for _ in 0..depth {
+            let mut parent = vec![0x12, node.len() as u8];
+            parent.extend(node);
+            node = parent;
+        }
+        node
+    }
+
+    #[test]
+    fn must_know_the_methods_of_all_services() {
+        let schema = Schema::new(&FILE_DESCRIPTOR_SETS).expect("valid schemas");
+        let method = schema
+            .methods
+            .get("/aurae.cells.v0.CellService/Allocate")
+            .expect("method");
+        assert_eq!(method.input, ".aurae.cells.v0.CellServiceAllocateRequest");
+        assert!(schema.messages.contains_key(&method.input));
+    }
+
+    #[test]
+    fn must_reject_messages_nested_too_deep() {
+        let schema = schema();
+        let limits =
+            GrpcLimits { max_recursion_depth: 3, ..Default::default() };
+
+        assert_eq!(schema.check(".test.Node", &nested(3), 0, &limits), Ok(()));
+        assert_eq!(
+            schema.check(".test.Node", &nested(4), 0, &limits),
+            Err(GrpcLimitsError::TooDeep { limit: 3 })
+        );
+    }
+
+    #[test]
+    fn must_reject_unknown_fields_only_if_configured() {
+        let schema = schema();
+        // name = "a", field 5 = 1
+        let node = [0x0a, 0x01, b'a', 0x28, 0x01];
+
+        let limits = GrpcLimits::default();
+        assert_eq!(schema.check(".test.Node", &node, 0, &limits), Ok(()));
+
+        let limits =
+            GrpcLimits { reject_unknown_fields: true, ..Default::default() };
+        assert_eq!(
+            schema.check(".test.Node", &node, 0, &limits),
+            Err(GrpcLimitsError::UnknownField {
+                message: "test.Node".into(),
+                field: 5
+            })
+        );
+    }
+
+    #[test]
+    fn must_reject_truncated_frames() {
+        let schema = schema();
+        let limits = GrpcLimits::default();
+
+        let mut body = vec![0, 0, 0, 0, 3];
+        body.extend(nested(0));
+        assert_eq!(schema.check_frames(".test.Node", &body, &limits), Ok(()));
+
+        // The name claims 3 bytes, only 1 follows
+        body[6] = 0x03;
+        assert!(matches!(
+            schema.check_frames(".test.Node", &body, &limits),
+            Err(GrpcLimitsError::Malformed { .. })
+        ));
+        assert!(matches!(
+            schema.check_frames(".test.Node", &body[..7], &limits),
+            Err(GrpcLimitsError::Malformed { .. })
+        ));
+    }
+
+    #[test]
+    fn must_detail_the_quota_of_requests_too_large() {
+        let status =
+            Status::from(GrpcLimitsError::TooLarge { size: 5000, limit: 4096 });
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        let quota = Quota::from_status(&status).expect("quota details");
+        assert_eq!(quota.name, "max_decoding_message_size");
+        assert_eq!((quota.current, quota.limit), (5000, 4096));
+        assert_eq!(quota.retry_after, None);
+    }
+}
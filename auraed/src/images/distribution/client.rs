@@ -0,0 +1,667 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::{
+    manifest::{
+        OciDescriptor, OciImageIndex, OciImageManifest,
+        IMAGE_MANIFEST_LIST_MEDIA_TYPE, IMAGE_MANIFEST_MEDIA_TYPE,
+        OCI_IMAGE_INDEX_MEDIA_TYPE, OCI_IMAGE_MEDIA_TYPE,
+    },
+    Reference,
+};
+use reqwest::{
+    header::{
+        ACCEPT, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LOCATION,
+        WWW_AUTHENTICATE,
+    },
+    Method, RequestBuilder, Response, StatusCode, Url,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, sync::Mutex, time::Duration};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Largest manifest or token response accepted from a registry.
+const MANIFEST_MAX: u64 = 4 << 20;
+/// Most bytes of an error response included in the error.
+const ERROR_BODY_MAX: u64 = 4 << 10;
+/// Size of the chunks blobs are pushed in, which bounds the memory a push
+/// takes regardless of the size of the blob.
+const UPLOAD_CHUNK_SIZE: usize = 16 << 20;
+/// Largest blob pulled, also for descriptors that do not tell its size.
+const BLOB_MAX: u64 = 64 << 30;
+
+/// How long connecting to a registry may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a request may take, including its response. Blobs are pulled
+/// for as long as they keep coming, see [STALL_TIMEOUT].
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long pushing a chunk of a blob may take.
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long the body of a blob pulled may stall.
+const STALL_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Manifests accepted when pulling an image.
+const MANIFEST_MEDIA_TYPES: [&str; 4] = [
+    OCI_IMAGE_MEDIA_TYPE,
+    OCI_IMAGE_INDEX_MEDIA_TYPE,
+    IMAGE_MANIFEST_MEDIA_TYPE,
+    IMAGE_MANIFEST_LIST_MEDIA_TYPE,
+];
+
+pub(crate) type Result<T> = std::result::Result<T, DistributionError>;
+
+#[derive(Debug, Error)]
+pub(crate) enum DistributionError {
+    #[error("{method} {url} failed with {status}: {body}")]
+    UnexpectedStatus {
+        method: Method,
+        url: Url,
+        status: StatusCode,
+        body: String,
+    },
+    #[error("registry '{registry}' sent an invalid challenge '{challenge}'")]
+    InvalidChallenge { registry: String, challenge: String },
+    #[error(
+        "manifest of '{reference}' has unsupported media type '{media_type}'"
+    )]
+    UnsupportedMediaType { reference: String, media_type: String },
+    #[error("'{reference}' has no manifest for the platform of the node")]
+    NoNativeManifest { reference: String },
+    #[error("manifest '{digest}' does not match its digest, got '{actual}'")]
+    DigestMismatch { digest: String, actual: String },
+    #[error("{url} sent more than {limit} bytes")]
+    TooLarge { url: Url, limit: u64 },
+    #[error("{url} sent nothing for {timeout:?}")]
+    TimedOut { url: String, timeout: Duration },
+    #[error("registry did not return where to upload to after {url}")]
+    MissingLocation { url: Url },
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+}
+
+/// The credentials sent to a registry.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) enum RegistryAuth {
+    Anonymous,
+    /// Username and password
+    Basic(String, String),
+}
+
+impl fmt::Debug for RegistryAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Anonymous => write!(f, "Anonymous"),
+            Self::Basic(username, _) => write!(f, "Basic({username}, ***)"),
+        }
+    }
+}
+
+/// What the client authenticates for, registries may grant pushing only to
+/// some of the clients that may pull.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RegistryOperation {
+    Pull,
+    Push,
+}
+
+impl RegistryOperation {
+    /// The actions of the scope of a bearer token for the operation.
+    fn actions(self) -> &'static str {
+        match self {
+            RegistryOperation::Pull => "pull",
+            RegistryOperation::Push => "pull,push",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ClientProtocol {
+    Https,
+    /// HTTPS, but plain HTTP to the listed registries
+    HttpsExcept(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ClientConfig {
+    pub protocol: ClientProtocol,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self { protocol: ClientProtocol::Https }
+    }
+}
+
+/// How requests to a repository are authorized.
+#[derive(Debug, Clone)]
+enum Authorization {
+    Basic(String, String),
+    Bearer(String),
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+    access_token: Option<String>,
+}
+
+/// Just the media type of a manifest, for registries that do not send it as
+/// the content type.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Versioned {
+    media_type: Option<String>,
+}
+
+/// A client of the distribution API of OCI registries.
+///
+/// Requests to a repository are authorized with the credentials the client
+/// authenticated with last for that repository, see
+/// [Client::authenticate].
+#[derive(Debug)]
+pub(crate) struct Client {
+    config: ClientConfig,
+    http: reqwest::Client,
+    /// Keyed by registry and repository
+    authorizations: Mutex<HashMap<String, Authorization>>,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()?;
+        Ok(Self { config, http, authorizations: Default::default() })
+    }
+
+    /// Authenticate for `operation` on the repository of `reference`, as
+    /// challenged by its registry. Registries asking for a bearer token are
+    /// sent `auth` to the realm of the challenge to get one.
+    pub async fn authenticate(
+        &self,
+        reference: &Reference,
+        auth: &RegistryAuth,
+        operation: RegistryOperation,
+    ) -> Result<()> {
+        let url = format!("{}/v2/", self.base_url(reference));
+        let res = self.http.get(url).timeout(REQUEST_TIMEOUT).send().await?;
+        let authorization = match res.status() {
+            StatusCode::UNAUTHORIZED => {
+                let challenge = res
+                    .headers()
+                    .get(WWW_AUTHENTICATE)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default()
+                    .to_owned();
+                self.authorization(reference, auth, operation, &challenge)
+                    .await?
+            }
+            _ => None,
+        };
+
+        let mut authorizations =
+            self.authorizations.lock().expect("authorizations lock poisoned");
+        let key = repository_key(reference);
+        match authorization {
+            Some(authorization) => {
+                let _ = authorizations.insert(key, authorization);
+            }
+            None => {
+                let _ = authorizations.remove(&key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pull the manifest of `reference`, resolving an image index to the
+    /// manifest for the platform of the node. Returns the manifest and its
+    /// digest.
+    pub async fn pull_image_manifest(
+        &self,
+        reference: &Reference,
+        auth: &RegistryAuth,
+    ) -> Result<(OciImageManifest, String)> {
+        self.authenticate(reference, auth, RegistryOperation::Pull).await?;
+
+        let (media_type, body, digest) =
+            self.pull_manifest(reference, tag_or_digest(reference)).await?;
+        let (media_type, body, digest) = match media_type.as_str() {
+            OCI_IMAGE_INDEX_MEDIA_TYPE | IMAGE_MANIFEST_LIST_MEDIA_TYPE => {
+                let index: OciImageIndex = serde_json::from_slice(&body)?;
+                let entry = index.native().ok_or_else(|| {
+                    DistributionError::NoNativeManifest {
+                        reference: reference.whole(),
+                    }
+                })?;
+                self.pull_manifest(reference, &entry.digest).await?
+            }
+            _ => (media_type, body, digest),
+        };
+
+        match media_type.as_str() {
+            OCI_IMAGE_MEDIA_TYPE | IMAGE_MANIFEST_MEDIA_TYPE => {
+                Ok((serde_json::from_slice(&body)?, digest))
+            }
+            _ => Err(DistributionError::UnsupportedMediaType {
+                reference: reference.whole(),
+                media_type,
+            }),
+        }
+    }
+
+    /// Pull `blob` of the repository of `reference` into `out`, failing
+    /// past its size or [BLOB_MAX]. Callers verify the digest of the blob.
+    pub async fn pull_blob<T: AsyncWrite + Unpin>(
+        &self,
+        reference: &Reference,
+        blob: &OciDescriptor,
+        out: &mut T,
+    ) -> Result<()> {
+        let url = self.url(reference, &format!("blobs/{}", blob.digest));
+        let timed_out =
+            |timeout| DistributionError::TimedOut { url: url.clone(), timeout };
+        let request = self.authorize(self.http.get(&url), reference);
+        let Ok(res) =
+            tokio::time::timeout(REQUEST_TIMEOUT, request.send()).await
+        else {
+            return Err(timed_out(REQUEST_TIMEOUT));
+        };
+        let mut res = successful(Method::GET, res?).await?;
+
+        let limit = u64::try_from(blob.size)
+            .ok()
+            .filter(|size| *size > 0)
+            .map_or(BLOB_MAX, |size| size.min(BLOB_MAX));
+        let mut received = 0u64;
+        loop {
+            let Ok(chunk) =
+                tokio::time::timeout(STALL_TIMEOUT, res.chunk()).await
+            else {
+                return Err(timed_out(STALL_TIMEOUT));
+            };
+            let Some(chunk) = chunk? else {
+                break;
+            };
+            received += chunk.len() as u64;
+            if received > limit {
+                return Err(DistributionError::TooLarge {
+                    url: res.url().clone(),
+                    limit,
+                });
+            }
+            out.write_all(&chunk).await?;
+        }
+        out.flush().await?;
+        Ok(())
+    }
+
+    /// Push the blob with `digest` read from `blob` to the repository of
+    /// `reference`, unless the repository has it already. The blob is
+    /// uploaded in chunks, so it is never buffered as a whole.
+    pub async fn push_blob(
+        &self,
+        reference: &Reference,
+        digest: &str,
+        mut blob: impl AsyncRead + Unpin,
+    ) -> Result<()> {
+        let url = self.url(reference, &format!("blobs/{digest}"));
+        let res = self.request(Method::HEAD, url, reference).send().await?;
+        if res.status().is_success() {
+            return Ok(());
+        }
+
+        let url = self.url(reference, "blobs/uploads/");
+        let res = self
+            .request(Method::POST, url, reference)
+            .header(CONTENT_LENGTH, 0)
+            .send()
+            .await?;
+        let mut location =
+            upload_location(successful(Method::POST, res).await?)?;
+
+        let mut chunk = vec![0u8; UPLOAD_CHUNK_SIZE];
+        let mut offset = 0u64;
+        loop {
+            let n = read_chunk(&mut blob, &mut chunk).await?;
+            if n == 0 {
+                break;
+            }
+            let end = offset + n as u64 - 1;
+            let res = self
+                .request(Method::PATCH, location, reference)
+                .header(CONTENT_TYPE, "application/octet-stream")
+                .header(CONTENT_RANGE, format!("{offset}-{end}"))
+                .body(chunk[..n].to_vec())
+                .timeout(CHUNK_TIMEOUT)
+                .send()
+                .await?;
+            location = upload_location(successful(Method::PATCH, res).await?)?;
+            offset = end + 1;
+        }
+
+        let _ = location.query_pairs_mut().append_pair("digest", digest);
+        let res = self
+            .request(Method::PUT, location, reference)
+            .header(CONTENT_LENGTH, 0)
+            .send()
+            .await?;
+        let _ = successful(Method::PUT, res).await?;
+        Ok(())
+    }
+
+    /// Push `manifest` as `reference`, whose blobs have to be pushed first.
+    /// Returns the URL of the manifest.
+    pub async fn push_manifest(
+        &self,
+        reference: &Reference,
+        manifest: &OciImageManifest,
+    ) -> Result<String> {
+        let url = self
+            .url(reference, &format!("manifests/{}", tag_or_digest(reference)));
+        let media_type =
+            manifest.media_type.as_deref().unwrap_or(OCI_IMAGE_MEDIA_TYPE);
+        let res = self
+            .request(Method::PUT, url.clone(), reference)
+            .header(CONTENT_TYPE, media_type)
+            .body(serde_json::to_vec(manifest)?)
+            .send()
+            .await?;
+        let _ = successful(Method::PUT, res).await?;
+        Ok(url)
+    }
+
+    /// Returns the content type, body and digest of the manifest
+    /// `tag_or_digest` of the repository of `reference`.
+    async fn pull_manifest(
+        &self,
+        reference: &Reference,
+        tag_or_digest: &str,
+    ) -> Result<(String, Vec<u8>, String)> {
+        let url = self.url(reference, &format!("manifests/{tag_or_digest}"));
+        let res = self
+            .request(Method::GET, url, reference)
+            .header(ACCEPT, MANIFEST_MEDIA_TYPES.join(", "))
+            .send()
+            .await?;
+        let res = successful(Method::GET, res).await?;
+        let content_type = res
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_owned());
+
+        let body = read_limited(res, MANIFEST_MAX).await?;
+        let digest = format!("sha256:{:x}", Sha256::digest(&body));
+        if tag_or_digest.starts_with("sha256:") && tag_or_digest != digest {
+            return Err(DistributionError::DigestMismatch {
+                digest: tag_or_digest.into(),
+                actual: digest,
+            });
+        }
+
+        let media_type = match content_type {
+            Some(media_type)
+                if MANIFEST_MEDIA_TYPES.contains(&&*media_type) =>
+            {
+                media_type
+            }
+            _ => serde_json::from_slice::<Versioned>(&body)?
+                .media_type
+                .unwrap_or_else(|| OCI_IMAGE_MEDIA_TYPE.into()),
+        };
+        Ok((media_type, body, digest))
+    }
+
+    /// The authorization answering `challenge`, none if the registry does
+    /// not need one or `auth` is anonymous.
+    async fn authorization(
+        &self,
+        reference: &Reference,
+        auth: &RegistryAuth,
+        operation: RegistryOperation,
+        challenge: &str,
+    ) -> Result<Option<Authorization>> {
+        let invalid = || DistributionError::InvalidChallenge {
+            registry: reference.registry().into(),
+            challenge: challenge.into(),
+        };
+        let (scheme, params) =
+            challenge.split_once(' ').unwrap_or((challenge, ""));
+
+        if scheme.eq_ignore_ascii_case("basic") {
+            return Ok(match auth {
+                RegistryAuth::Basic(username, password) => Some(
+                    Authorization::Basic(username.clone(), password.clone()),
+                ),
+                RegistryAuth::Anonymous => None,
+            });
+        }
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return Err(invalid());
+        }
+
+        let params = challenge_params(params);
+        let realm = params.get("realm").ok_or_else(invalid)?;
+        let mut query = vec![(
+            "scope",
+            format!(
+                "repository:{}:{}",
+                reference.repository(),
+                operation.actions()
+            ),
+        )];
+        if let Some(service) = params.get("service") {
+            query.push(("service", service.clone()));
+        }
+
+        let mut request =
+            self.http.get(realm).query(&query).timeout(REQUEST_TIMEOUT);
+        if let RegistryAuth::Basic(username, password) = auth {
+            request = request.basic_auth(username, Some(password));
+        }
+        let res = successful(Method::GET, request.send().await?).await?;
+        let token: TokenResponse =
+            serde_json::from_slice(&read_limited(res, MANIFEST_MAX).await?)?;
+        let token = token.token.or(token.access_token).ok_or_else(invalid)?;
+        Ok(Some(Authorization::Bearer(token)))
+    }
+
+    fn request(
+        &self,
+        method: Method,
+        url: impl reqwest::IntoUrl,
+        reference: &Reference,
+    ) -> RequestBuilder {
+        let request = self.http.request(method, url).timeout(REQUEST_TIMEOUT);
+        self.authorize(request, reference)
+    }
+
+    /// Authorizes `request` to the repository of `reference`.
+    fn authorize(
+        &self,
+        request: RequestBuilder,
+        reference: &Reference,
+    ) -> RequestBuilder {
+        let authorization = self
+            .authorizations
+            .lock()
+            .expect("authorizations lock poisoned")
+            .get(&repository_key(reference))
+            .cloned();
+        match authorization {
+            Some(Authorization::Basic(username, password)) => {
+                request.basic_auth(username, Some(password))
+            }
+            Some(Authorization::Bearer(token)) => request.bearer_auth(token),
+            None => request,
+        }
+    }
+
+    fn base_url(&self, reference: &Reference) -> String {
+        let insecure = match &self.config.protocol {
+            ClientProtocol::Https => false,
+            ClientProtocol::HttpsExcept(insecure) => {
+                insecure.iter().any(|host| host == reference.registry())
+            }
+        };
+        let scheme = if insecure { "http" } else { "https" };
+        format!("{scheme}://{}", reference.resolve_registry())
+    }
+
+    fn url(&self, reference: &Reference, path: &str) -> String {
+        format!(
+            "{}/v2/{}/{path}",
+            self.base_url(reference),
+            reference.repository()
+        )
+    }
+}
+
+fn repository_key(reference: &Reference) -> String {
+    format!("{}/{}", reference.registry(), reference.repository())
+}
+
+fn tag_or_digest(reference: &Reference) -> &str {
+    reference.digest().or(reference.tag()).unwrap_or("latest")
+}
+
+/// Fails with the status of `res` unless it succeeded.
+async fn successful(method: Method, res: Response) -> Result<Response> {
+    let status = res.status();
+    if status.is_success() {
+        return Ok(res);
+    }
+    let url = res.url().clone();
+    let body = read_limited(res, ERROR_BODY_MAX)
+        .await
+        .map(|body| String::from_utf8_lossy(&body).into_owned())
+        .unwrap_or_default();
+    Err(DistributionError::UnexpectedStatus { method, url, status, body })
+}
+
+/// Reads the body of `res`, failing if it is larger than `limit` bytes.
+async fn read_limited(mut res: Response, limit: u64) -> Result<Vec<u8>> {
+    let mut body = vec![];
+    while let Some(chunk) = res.chunk().await? {
+        if (body.len() + chunk.len()) as u64 > limit {
+            return Err(DistributionError::TooLarge {
+                url: res.url().clone(),
+                limit,
+            });
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Where to continue an upload, the location sent may be relative.
+fn upload_location(res: Response) -> Result<Url> {
+    let missing =
+        || DistributionError::MissingLocation { url: res.url().clone() };
+    let location = res
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(missing)?;
+    res.url().join(location).map_err(|_| missing())
+}
+
+/// Fills `buf` from `reader`, only returning less than its size at EOF.
+async fn read_chunk(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut [u8],
+) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]).await? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// The parameters of a `WWW-Authenticate` challenge, e.g.
+/// `realm="https://auth.docker.io/token",service="registry.docker.io"`.
+fn challenge_params(params: &str) -> HashMap<String, String> {
+    let mut parsed = HashMap::new();
+    let mut rest = params;
+    while let Some((key, value)) = rest.split_once('=') {
+        let key = key.trim_matches(|c: char| c == ',' || c.is_whitespace());
+        let (value, tail) = match value.trim_start().strip_prefix('"') {
+            Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+            None => value.split_once(',').unwrap_or((value, "")),
+        };
+        let _ =
+            parsed.insert(key.to_ascii_lowercase(), value.trim().to_owned());
+        rest = tail;
+    }
+    parsed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bearer_challenges_are_parsed() {
+        let params = challenge_params(
+            r#"realm="https://auth.docker.io/token",service="registry.docker.io",scope="repository:library/busybox:pull,push""#,
+        );
+        assert_eq!(params["realm"], "https://auth.docker.io/token");
+        assert_eq!(params["service"], "registry.docker.io");
+        assert_eq!(params["scope"], "repository:library/busybox:pull,push");
+
+        let params = challenge_params("Realm=http://localhost/token, x=y");
+        assert_eq!(params["realm"], "http://localhost/token");
+        assert_eq!(params["x"], "y");
+    }
+
+    #[test]
+    fn insecure_registries_are_talked_to_over_http() {
+        let client = Client::new(ClientConfig {
+            protocol: ClientProtocol::HttpsExcept(
+                vec!["localhost:5000".into()],
+            ),
+        })
+        .expect("client");
+
+        let reference: Reference =
+            "localhost:5000/team/vm:v1".parse().expect("valid reference");
+        assert_eq!(
+            client.url(&reference, "manifests/v1"),
+            "http://localhost:5000/v2/team/vm/manifests/v1"
+        );
+
+        let reference: Reference = "busybox".parse().expect("valid reference");
+        assert_eq!(
+            client.url(&reference, "manifests/latest"),
+            "https://index.docker.io/v2/library/busybox/manifests/latest"
+        );
+    }
+
+    #[test]
+    fn credentials_are_not_printed() {
+        let auth = RegistryAuth::Basic("aurae".into(), "secret".into());
+        assert_eq!(format!("{auth:?}"), "Basic(aurae, ***)");
+    }
+}
@@ -0,0 +1,108 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Media type of an OCI image manifest.
+pub(crate) const OCI_IMAGE_MEDIA_TYPE: &str =
+    "application/vnd.oci.image.manifest.v1+json";
+/// Media type of an OCI image index, listing a manifest per platform.
+pub(crate) const OCI_IMAGE_INDEX_MEDIA_TYPE: &str =
+    "application/vnd.oci.image.index.v1+json";
+/// Media type of a docker image manifest.
+pub(crate) const IMAGE_MANIFEST_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.v2+json";
+/// Media type of a docker manifest list, listing a manifest per platform.
+pub(crate) const IMAGE_MANIFEST_LIST_MEDIA_TYPE: &str =
+    "application/vnd.docker.distribution.manifest.list.v2+json";
+
+/// A blob referenced by a manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OciDescriptor {
+    pub media_type: String,
+    pub digest: String,
+    pub size: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub urls: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+/// The manifest of an image for a single platform, as defined by the OCI
+/// image spec. Docker image manifests share its layout.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OciImageManifest {
+    pub schema_version: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_type: Option<String>,
+    pub config: OciDescriptor,
+    #[serde(default)]
+    pub layers: Vec<OciDescriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<HashMap<String, String>>,
+}
+
+impl Default for OciImageManifest {
+    fn default() -> Self {
+        Self {
+            schema_version: 2,
+            media_type: None,
+            config: Default::default(),
+            layers: vec![],
+            annotations: None,
+        }
+    }
+}
+
+/// An image index or docker manifest list.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct OciImageIndex {
+    #[serde(default)]
+    pub manifests: Vec<ImageIndexEntry>,
+}
+
+/// The manifest of an image for one platform, listed in an [OciImageIndex].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ImageIndexEntry {
+    pub digest: String,
+    pub platform: Option<Platform>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Platform {
+    pub architecture: String,
+    pub os: String,
+}
+
+impl OciImageIndex {
+    /// The manifest for linux on the architecture auraed runs on.
+    pub fn native(&self) -> Option<&ImageIndexEntry> {
+        let architecture = match std::env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+        self.manifests.iter().find(|entry| {
+            entry.platform.as_ref().is_some_and(|platform| {
+                platform.os == "linux" && platform.architecture == architecture
+            })
+        })
+    }
+}
@@ -0,0 +1,33 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A client of the distribution API of OCI registries, which pulls and
+//! pushes the manifests and blobs of images.
+//!
+//! Only what auraed needs is implemented: pulling image manifests, resolving
+//! image indexes to the platform of the node, and streaming blobs in and
+//! out. Blobs are pushed with chunked uploads, so pushing never buffers a
+//! whole blob, which `oci-distribution` needs for exporting VM drives.
+//! Requests time out, and pulled blobs are bounded by their descriptor and
+//! a hard cap.
+
+pub(crate) use client::{
+    Client, ClientConfig, ClientProtocol, RegistryAuth, RegistryOperation,
+};
+pub(crate) use reference::Reference;
+
+mod client;
+pub(crate) mod manifest;
+mod reference;
@@ -0,0 +1,296 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use std::{fmt, str::FromStr};
+use thiserror::Error;
+
+/// Registry of images named without one.
+const DOCKER_HUB: &str = "docker.io";
+/// Host serving the API of [DOCKER_HUB].
+const DOCKER_HUB_API: &str = "index.docker.io";
+/// Tag of images named with neither a tag nor a digest.
+const DEFAULT_TAG: &str = "latest";
+/// Longest name of a repository including its registry.
+const NAME_MAX: usize = 255;
+/// Longest tag of an image.
+const TAG_MAX: usize = 128;
+
+#[derive(Debug, Error)]
+#[error("'{reference}' is not a valid image reference: {reason}")]
+pub(crate) struct ParseReferenceError {
+    reference: String,
+    reason: &'static str,
+}
+
+/// A reference to an image in a registry, normalized the way docker does:
+/// `busybox` refers to `docker.io/library/busybox:latest`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) struct Reference {
+    registry: String,
+    repository: String,
+    tag: Option<String>,
+    digest: Option<String>,
+}
+
+impl Reference {
+    pub fn with_tag(registry: String, repository: String, tag: String) -> Self {
+        Self { registry, repository, tag: Some(tag), digest: None }
+    }
+
+    pub fn with_digest(
+        registry: String,
+        repository: String,
+        digest: String,
+    ) -> Self {
+        Self { registry, repository, tag: None, digest: Some(digest) }
+    }
+
+    /// The registry the image is stored in, e.g. `docker.io`.
+    pub fn registry(&self) -> &str {
+        &self.registry
+    }
+
+    /// The host serving the API of the registry, which differs from the
+    /// registry only for Docker Hub.
+    pub fn resolve_registry(&self) -> &str {
+        match self.registry.as_str() {
+            DOCKER_HUB => DOCKER_HUB_API,
+            registry => registry,
+        }
+    }
+
+    /// The repository in the registry, e.g. `library/busybox`.
+    pub fn repository(&self) -> &str {
+        &self.repository
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn digest(&self) -> Option<&str> {
+        self.digest.as_deref()
+    }
+
+    /// The fully qualified reference, e.g.
+    /// `docker.io/library/busybox:latest`.
+    pub fn whole(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.registry, self.repository)?;
+        if let Some(tag) = &self.tag {
+            write!(f, ":{tag}")?;
+        }
+        if let Some(digest) = &self.digest {
+            write!(f, "@{digest}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Reference {
+    type Err = ParseReferenceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid =
+            |reason| ParseReferenceError { reference: s.into(), reason };
+
+        let (name, digest) = match s.split_once('@') {
+            Some((name, digest)) if is_digest(digest) => {
+                (name, Some(digest.to_owned()))
+            }
+            Some(_) => return Err(invalid("invalid digest")),
+            None => (s, None),
+        };
+
+        // A colon after the last slash separates the tag, others belong to
+        // the port of the registry
+        let last_component = name.rfind('/').map_or(0, |i| i + 1);
+        let (name, tag) = match name[last_component..].rfind(':') {
+            Some(i) => {
+                let (name, tag) = name.split_at(last_component + i);
+                (name, Some(tag[1..].to_owned()))
+            }
+            None => (name, None),
+        };
+        if tag.as_deref().is_some_and(|tag| !is_tag(tag)) {
+            return Err(invalid("invalid tag"));
+        }
+
+        let (registry, repository) = match name.split_once('/') {
+            Some((host, path)) if is_registry(host) => {
+                (host.to_owned(), path.to_owned())
+            }
+            _ => (DOCKER_HUB.to_owned(), name.to_owned()),
+        };
+        let registry = match registry.as_str() {
+            DOCKER_HUB_API => DOCKER_HUB.to_owned(),
+            _ => registry,
+        };
+        let repository = if registry == DOCKER_HUB && !repository.contains('/')
+        {
+            format!("library/{repository}")
+        } else {
+            repository
+        };
+
+        if !repository.split('/').all(is_path_component) {
+            return Err(invalid("invalid repository"));
+        }
+        if registry.len() + 1 + repository.len() > NAME_MAX {
+            return Err(invalid("name too long"));
+        }
+
+        let tag = match (&tag, &digest) {
+            (None, None) => Some(DEFAULT_TAG.to_owned()),
+            _ => tag,
+        };
+        Ok(Self { registry, repository, tag, digest })
+    }
+}
+
+/// Whether the first component of a name is a registry rather than a path
+/// component, as it is a host name with a domain or port, or localhost.
+fn is_registry(host: &str) -> bool {
+    (host.contains('.') || host.contains(':') || host == "localhost")
+        && host.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '[')
+        && host.chars().all(|c| {
+            c.is_ascii_alphanumeric()
+                || matches!(c, '.' | '-' | ':' | '[' | ']')
+        })
+}
+
+/// Lowercase alphanumerics, separated by single `.` or `-`, or up to two
+/// `_`, or any number of `-`.
+fn is_path_component(component: &str) -> bool {
+    let bytes = component.as_bytes();
+    let alnum = |b: &u8| b.is_ascii_lowercase() || b.is_ascii_digit();
+    if !bytes.first().is_some_and(alnum) || !bytes.last().is_some_and(alnum) {
+        return false;
+    }
+    component.split(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit()).all(
+        |sep| {
+            sep.is_empty()
+                || sep == "."
+                || sep == "_"
+                || sep == "__"
+                || sep.chars().all(|c| c == '-')
+        },
+    )
+}
+
+fn is_tag(tag: &str) -> bool {
+    let mut chars = tag.chars();
+    tag.len() <= TAG_MAX
+        && chars.next().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        && chars
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'))
+}
+
+/// `algorithm:hex`, the hex encoding of the hash being at least 128 bits.
+fn is_digest(digest: &str) -> bool {
+    let Some((algorithm, hex)) = digest.split_once(':') else {
+        return false;
+    };
+    !algorithm.is_empty()
+        && algorithm.chars().all(|c| {
+            c.is_ascii_lowercase()
+                || c.is_ascii_digit()
+                || matches!(c, '+' | '.' | '_' | '-')
+        })
+        && hex.len() >= 32
+        && hex.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(reference: &str) -> Reference {
+        reference.parse().expect("valid reference")
+    }
+
+    #[test]
+    fn short_names_refer_to_docker_hub() {
+        let reference = parse("busybox");
+        assert_eq!(reference.registry(), "docker.io");
+        assert_eq!(reference.resolve_registry(), "index.docker.io");
+        assert_eq!(reference.repository(), "library/busybox");
+        assert_eq!(reference.tag(), Some("latest"));
+        assert_eq!(reference.whole(), "docker.io/library/busybox:latest");
+
+        assert_eq!(
+            parse("aurae/auraed:v1").whole(),
+            "docker.io/aurae/auraed:v1"
+        );
+        assert_eq!(
+            parse("index.docker.io/busybox").whole(),
+            "docker.io/library/busybox:latest"
+        );
+    }
+
+    #[test]
+    fn registries_are_recognized_by_domain_or_port() {
+        let reference = parse("localhost:5000/team/vm:v1");
+        assert_eq!(reference.registry(), "localhost:5000");
+        assert_eq!(reference.resolve_registry(), "localhost:5000");
+        assert_eq!(reference.repository(), "team/vm");
+        assert_eq!(reference.tag(), Some("v1"));
+
+        let reference = parse("ghcr.io/aurae-runtime/auraed");
+        assert_eq!(reference.registry(), "ghcr.io");
+        assert_eq!(reference.repository(), "aurae-runtime/auraed");
+    }
+
+    #[test]
+    fn digests_do_not_default_the_tag() {
+        let digest = format!("sha256:{}", "a".repeat(64));
+        let reference = parse(&format!("registry.example.com:443/vm@{digest}"));
+        assert_eq!(reference.registry(), "registry.example.com:443");
+        assert_eq!(reference.tag(), None);
+        assert_eq!(reference.digest(), Some(digest.as_str()));
+
+        let reference = parse(&format!("busybox:1.36@{digest}"));
+        assert_eq!(reference.tag(), Some("1.36"));
+        assert_eq!(
+            reference.whole(),
+            format!("docker.io/library/busybox:1.36@{digest}")
+        );
+    }
+
+    #[test]
+    fn invalid_references_are_rejected() {
+        for reference in [
+            "",
+            "Busybox",
+            "busybox:",
+            "busybox:-1",
+            "busybox@sha256:abc",
+            "registry.example.com/",
+            "registry.example.com/team//vm",
+            "busybox:1.36 ",
+            "../../etc/passwd",
+        ] {
+            assert!(
+                reference.parse::<Reference>().is_err(),
+                "{reference} should be invalid"
+            );
+        }
+    }
+}
@@ -0,0 +1,126 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use crate::AURAED_RUNTIME;
+use client::quota::Quota;
+use std::{ffi::CString, io, os::unix::ffi::OsStrExt, path::Path};
+use thiserror::Error;
+use tonic::Status;
+use tracing::error;
+
+pub(crate) type Result<T> = std::result::Result<T, ImageServiceError>;
+
+#[derive(Debug, Error)]
+pub(crate) enum ImageServiceError {
+    #[error("'{reference}' is not a valid image reference")]
+    InvalidReference { reference: String },
+    #[error("image '{reference}' could not be pulled: {source}")]
+    FailedToPull { reference: String, source: anyhow::Error },
+    #[error("blob '{digest}' does not match its digest, got '{actual}'")]
+    DigestMismatch { digest: String, actual: String },
+    #[error("blob digest '{digest}' is not supported, only sha256 is")]
+    UnsupportedDigest { digest: String },
+    #[error("layer '{digest}' has unsupported media type '{media_type}'")]
+    UnsupportedMediaType { digest: String, media_type: String },
+    #[error("layer '{digest}' could not be unpacked: {source}")]
+    FailedToUnpack { digest: String, source: std::io::Error },
+    #[error("'{path}' is not a valid OCI image layout: {reason}")]
+    InvalidLayout { path: String, reason: String },
+    #[error("image '{reference}' was not pulled")]
+    ImageNotFound { reference: String },
+    #[error("layer '{digest}' is not unpacked")]
+    LayerNotUnpacked { digest: String },
+    #[error("'{key}' is not a valid snapshot key")]
+    InvalidSnapshotKey { key: String },
+    #[error("snapshot '{key}' already exists")]
+    SnapshotExists { key: String },
+    #[error("registries config '{path}' is invalid: {reason}")]
+    InvalidRegistryConfig { path: String, reason: String },
+    #[error("image '{reference}' was not pulled before the deadline")]
+    DeadlineExceeded { reference: String },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+}
+
+impl From<ImageServiceError> for Status {
+    fn from(err: ImageServiceError) -> Self {
+        let msg = err.to_string();
+        error!("{msg}");
+        match err {
+            ImageServiceError::InvalidReference { .. }
+            | ImageServiceError::InvalidLayout { .. }
+            | ImageServiceError::InvalidSnapshotKey { .. } => {
+                Status::invalid_argument(msg)
+            }
+            ImageServiceError::ImageNotFound { .. } => Status::not_found(msg),
+            ImageServiceError::SnapshotExists { .. } => {
+                Status::already_exists(msg)
+            }
+            ImageServiceError::DeadlineExceeded { .. } => {
+                Status::deadline_exceeded(msg)
+            }
+            ImageServiceError::FailedToPull { .. } => Status::unavailable(msg),
+            ImageServiceError::DigestMismatch { .. } => Status::data_loss(msg),
+            ImageServiceError::UnsupportedDigest { .. }
+            | ImageServiceError::UnsupportedMediaType { .. }
+            | ImageServiceError::LayerNotUnpacked { .. }
+            | ImageServiceError::InvalidRegistryConfig { .. } => {
+                Status::failed_precondition(msg)
+            }
+            ImageServiceError::FailedToUnpack { source: e, .. }
+            | ImageServiceError::IO(e)
+                if e.raw_os_error() == Some(libc::ENOSPC) =>
+            {
+                disk_exhausted(msg)
+            }
+            ImageServiceError::FailedToUnpack { .. }
+            | ImageServiceError::IO(_)
+            | ImageServiceError::Serde(_) => Status::internal(msg),
+        }
+    }
+}
+
+/// The status of a pull that filled the disk the images are stored on.
+fn disk_exhausted(msg: String) -> Status {
+    let images_dir = AURAED_RUNTIME
+        .get()
+        .map(|runtime| runtime.images_dir())
+        .unwrap_or_default();
+    let (current, limit) = disk_usage(&images_dir).unwrap_or_default();
+    Quota {
+        name: "disk".into(),
+        subject: images_dir.display().to_string(),
+        current,
+        limit,
+        retry_after: None,
+    }
+    .into_status(msg)
+}
+
+/// Bytes used and the size of the filesystem of `path`, as available to
+/// unprivileged users.
+fn disk_usage(path: &Path) -> io::Result<(u64, u64)> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: statvfs only writes the struct passed
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let size = stat.f_blocks.saturating_mul(stat.f_frsize);
+    let free = stat.f_bavail.saturating_mul(stat.f_frsize);
+    Ok((size.saturating_sub(free), size))
+}
@@ -0,0 +1,362 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::{
+    distribution::Reference,
+    error::{ImageServiceError, Result},
+    preload,
+    pull::{self, ImagePullConfig, PullLimits},
+    registries::RegistryFiles,
+    snapshot,
+    store::{ImageStore, StoredImage},
+};
+use crate::resumable::{impl_resumable, ResumableStream, ResumableStreams};
+use oci_spec::image::{Config as ImageConfig, ImageConfiguration};
+use proto::images::{
+    image_service_server, ImageServicePreloadRequest,
+    ImageServicePreloadResponse, ImageServicePrepareSnapshotRequest,
+    ImageServicePrepareSnapshotResponse, ImageServicePullRequest,
+    ImageServicePullResponse, ImageServicePullStreamRequest,
+    ImageServicePullStreamResponse, ImageServiceRemoveSnapshotRequest,
+    ImageServiceRemoveSnapshotResponse,
+};
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use tracing::info;
+
+/// Number of progress messages buffered for a client. Progress is dropped
+/// rather than slowing down the pull when a client falls behind.
+const PROGRESS_BUFFER: usize = 16;
+
+type PullStream = ResumableStream<ImageServicePullStreamResponse>;
+
+impl_resumable!(ImageServicePullStreamResponse);
+
+/// ImageService pulls images into the [ImageStore] of the node.
+#[derive(Debug, Clone)]
+pub struct ImageService {
+    store: ImageStore,
+    limits: PullLimits,
+    registries: RegistryFiles,
+    /// Pull streams clients can resume, see [crate::resumable]
+    pulls: ResumableStreams<ImageServicePullStreamResponse>,
+}
+
+impl ImageService {
+    /// Create a new ImageService storing images below `images_dir`. All
+    /// pulls share the limits of `config` and pull from the registries it
+    /// configures.
+    pub fn new(images_dir: PathBuf, config: &ImagePullConfig) -> Self {
+        Self {
+            store: ImageStore::new(images_dir),
+            limits: PullLimits::new(config),
+            registries: RegistryFiles::new(config),
+            pulls: ResumableStreams::new(),
+        }
+    }
+
+    /// Opens the image store. Until this succeeds the service is not
+    /// reported as SERVING.
+    pub(crate) fn ready(&self) -> anyhow::Result<()> {
+        let images = self.store.open()?;
+        info!("Opened image store with {images} images");
+        Ok(())
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn pull(
+        &self,
+        request: ImageServicePullRequest,
+    ) -> Result<ImageServicePullResponse> {
+        let image = self.pull_image(&request.image).await?;
+        Ok(ImageServicePullResponse { image: Some((&image).into()) })
+    }
+
+    /// Pulls `image` within the limits shared with the other pulls, for
+    /// the CRI image service.
+    pub(crate) async fn pull_image(&self, image: &str) -> Result<StoredImage> {
+        let reference = parse_reference(image)?;
+        let registries = self.registries.load()?;
+        pull::pull(&self.store, &self.limits, &registries, &reference, |_| {})
+            .await
+    }
+
+    /// The images in the store, for the CRI image service.
+    pub(crate) fn images(&self) -> Result<Vec<StoredImage>> {
+        self.store.list()
+    }
+
+    /// Removes the image pulled by `reference` and the blobs no other image
+    /// uses. Returns whether the image was pulled.
+    pub(crate) fn remove_image(&self, reference: &str) -> Result<bool> {
+        self.store.remove(reference)
+    }
+
+    /// Bytes and inodes used by the image store.
+    pub(crate) fn usage(&self) -> Result<(u64, u64)> {
+        self.store.usage()
+    }
+
+    /// Prepares the snapshot `key` of the pulled `image` for a container of
+    /// the CRI. Returns the path of its root filesystem and the config of
+    /// the image, None if the image has no usable config.
+    pub(crate) fn prepare_container(
+        &self,
+        image: &str,
+        key: &str,
+    ) -> Result<(PathBuf, Option<ImageConfig>)> {
+        let image = self.stored_image(image)?;
+        let config = self
+            .store
+            .read_blob(image.id())
+            .ok()
+            .and_then(|blob| {
+                serde_json::from_slice::<ImageConfiguration>(&blob).ok()
+            })
+            .and_then(|configuration| configuration.config().clone());
+        let rootfs = snapshot::prepare(&self.store, key, &image)?;
+        Ok((rootfs, config))
+    }
+
+    /// Removes the snapshot `key` of a container of the CRI. Returns whether
+    /// it existed.
+    pub(crate) fn remove_container(&self, key: &str) -> Result<bool> {
+        snapshot::remove(&self.store, key)
+    }
+
+    fn stored_image(&self, image: &str) -> Result<StoredImage> {
+        let reference = parse_reference(image)?.whole();
+        self.store
+            .get(&reference)?
+            .ok_or(ImageServiceError::ImageNotFound { reference })
+    }
+
+    /// Where the images are stored.
+    pub(crate) fn root(&self) -> &Path {
+        self.store.root()
+    }
+
+    /// Pulls the image in a task streaming its progress to the client.
+    ///
+    /// The deadline of unary calls is enforced by tonic, but it stops
+    /// applying once a streaming call returned its response, so the task
+    /// enforces it.
+    #[tracing::instrument(skip(self))]
+    async fn pull_stream(
+        &self,
+        request: ImageServicePullStreamRequest,
+        deadline: Option<Duration>,
+    ) -> Result<PullStream> {
+        let reference = parse_reference(&request.image)?;
+        let registries = self.registries.load()?;
+        let store = self.store.clone();
+        let limits = self.limits.clone();
+
+        let (tx, rx) = mpsc::channel(PROGRESS_BUFFER);
+        let _ignored = tokio::spawn(async move {
+            let progress_tx = tx.clone();
+            let pull = pull::pull(
+                &store,
+                &limits,
+                &registries,
+                &reference,
+                move |progress| {
+                    let _ = progress_tx.try_send(Ok(
+                        ImageServicePullStreamResponse {
+                            progress: Some(progress),
+                            image: None,
+                            ..Default::default()
+                        },
+                    ));
+                },
+            );
+            let deadline = async {
+                match deadline {
+                    Some(deadline) => tokio::time::sleep(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+
+            let res = tokio::select! {
+                res = pull => res,
+                // the client did not resume the stream in time
+                _ = tx.closed() => {
+                    info!("Pull of {reference} cancelled by the client");
+                    return;
+                }
+                _ = deadline => Err(ImageServiceError::DeadlineExceeded {
+                    reference: reference.whole(),
+                }),
+            };
+
+            let res = res
+                .map(|image| ImageServicePullStreamResponse {
+                    progress: None,
+                    image: Some((&image).into()),
+                    ..Default::default()
+                })
+                .map_err(Status::from);
+            let _ = tx.send(res).await;
+        });
+
+        Ok(self.pulls.start(ReceiverStream::new(rx), ()))
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn preload(
+        &self,
+        request: ImageServicePreloadRequest,
+    ) -> Result<ImageServicePreloadResponse> {
+        let images = preload::preload(
+            &self.store,
+            &self.limits,
+            Path::new(&request.path),
+        )
+        .await?;
+        Ok(ImageServicePreloadResponse {
+            images: images.iter().map(Into::into).collect(),
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn prepare_snapshot(
+        &self,
+        request: ImageServicePrepareSnapshotRequest,
+    ) -> Result<ImageServicePrepareSnapshotResponse> {
+        let image = self.stored_image(&request.image)?;
+        let rootfs = snapshot::prepare(&self.store, &request.key, &image)?;
+        Ok(ImageServicePrepareSnapshotResponse {
+            rootfs: rootfs.display().to_string(),
+        })
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_snapshot(
+        &self,
+        request: ImageServiceRemoveSnapshotRequest,
+    ) -> Result<ImageServiceRemoveSnapshotResponse> {
+        let removed = snapshot::remove(&self.store, &request.key)?;
+        Ok(ImageServiceRemoveSnapshotResponse { removed })
+    }
+}
+
+fn parse_reference(image: &str) -> Result<Reference> {
+    image.parse().map_err(|_| ImageServiceError::InvalidReference {
+        reference: image.into(),
+    })
+}
+
+/// The timeout a client set on a call in the `grpc-timeout` header, see
+/// https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-HTTP2.md
+fn grpc_timeout<T>(request: &Request<T>) -> Option<Duration> {
+    let value = request.metadata().get("grpc-timeout")?.to_str().ok()?;
+    parse_grpc_timeout(value)
+}
+
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    // At most 8 digits followed by the unit
+    if value.len() < 2 || value.len() > 9 {
+        return None;
+    }
+    let (amount, unit) = value.split_at(value.len() - 1);
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+#[tonic::async_trait]
+impl image_service_server::ImageService for ImageService {
+    async fn pull(
+        &self,
+        request: Request<ImageServicePullRequest>,
+    ) -> std::result::Result<Response<ImageServicePullResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(self.pull(req).await?))
+    }
+
+    type PullStreamStream = PullStream;
+
+    async fn pull_stream(
+        &self,
+        request: Request<ImageServicePullStreamRequest>,
+    ) -> std::result::Result<Response<Self::PullStreamStream>, Status> {
+        let deadline = grpc_timeout(&request);
+        let req = request.into_inner();
+        if !req.resume_token.is_empty() {
+            let (stream, ()) = self.pulls.resume(&req.resume_token)?;
+            return Ok(Response::new(stream));
+        }
+        Ok(Response::new(self.pull_stream(req, deadline).await?))
+    }
+
+    async fn preload(
+        &self,
+        request: Request<ImageServicePreloadRequest>,
+    ) -> std::result::Result<Response<ImageServicePreloadResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(self.preload(req).await?))
+    }
+
+    async fn prepare_snapshot(
+        &self,
+        request: Request<ImageServicePrepareSnapshotRequest>,
+    ) -> std::result::Result<
+        Response<ImageServicePrepareSnapshotResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.prepare_snapshot(req).await?))
+    }
+
+    async fn remove_snapshot(
+        &self,
+        request: Request<ImageServiceRemoveSnapshotRequest>,
+    ) -> std::result::Result<Response<ImageServiceRemoveSnapshotResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(self.remove_snapshot(req).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_grpc_timeouts() {
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_grpc_timeout("90S"), Some(Duration::from_secs(90)));
+        assert_eq!(
+            parse_grpc_timeout("1500m"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_grpc_timeout("100000000S"), None);
+        assert_eq!(parse_grpc_timeout("10s"), None);
+        assert_eq!(parse_grpc_timeout("S"), None);
+    }
+}
@@ -0,0 +1,51 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! OCI images pulled from registries.
+//!
+//! The [ImageService] pulls images into an [store::ImageStore] below the
+//! library directory of auraed. Pulls can stream their progress to the
+//! client and are cancelled when the client goes away or the deadline of the
+//! call passes.
+//!
+//! Images are pulled with the registry client of [distribution].
+//! Blobs are fetched and layers are unpacked concurrently, within the limits
+//! of the [ImagePullConfig] of the node. Private registries and mirrors are
+//! configured in the files it names, see [registries].
+//!
+//! Nodes without access to a registry can preload images from an OCI image
+//! layout or a `docker save` archive on local media instead.
+//!
+//! Containers run on snapshots of an image, which stack its unpacked layers
+//! with overlayfs instead of copying them, see [snapshot].
+//!
+//! The CRI image service of the node shares the store and the limits of the
+//! [ImageService].
+
+pub(crate) use error::ImageServiceError;
+pub(crate) use image_service::ImageService;
+pub use pull::ImagePullConfig;
+pub(crate) use registries::RegistryFiles;
+pub(crate) use store::StoredImage;
+
+pub(crate) mod distribution;
+mod error;
+mod image_service;
+mod preload;
+mod pull;
+mod registries;
+mod snapshot;
+mod store;
+mod unpack;
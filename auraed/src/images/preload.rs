@@ -0,0 +1,636 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Loads images from local media into the [ImageStore], for air-gapped
+//! nodes that cannot pull from a registry.
+//!
+//! Images are read from an OCI image layout, a directory or a tar archive of
+//! one. All blobs of the layout are imported into the store, verified
+//! against their digest, before the manifests it names are recorded as
+//! images.
+//!
+//! Archives written by `docker save` before docker 25 are not OCI image
+//! layouts. They list their images in a manifest.json instead, by the paths
+//! of their config and layers in the archive. Those files are imported by
+//! the digest of their contents, and a manifest is written to the store for
+//! each image, so they are recorded like the images of a layout.
+
+use super::{
+    distribution::{
+        manifest::{OciDescriptor, OciImageManifest},
+        Reference,
+    },
+    error::{ImageServiceError, Result},
+    pull::PullLimits,
+    store::{ImageStore, StoredImage},
+    unpack,
+};
+use futures::{stream::FuturesUnordered, TryStreamExt};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+use tracing::{info, warn};
+
+/// Annotations naming the image of a manifest in an OCI image layout, in the
+/// order they are looked up. containerd and docker set the first to the full
+/// reference of the image.
+const NAME_ANNOTATIONS: [&str; 2] =
+    ["io.containerd.image.name", "org.opencontainers.image.ref.name"];
+
+/// Media types of the manifests that are stored as images.
+const MANIFEST_MEDIA_TYPES: [&str; 2] = [
+    "application/vnd.oci.image.manifest.v1+json",
+    "application/vnd.docker.distribution.manifest.v2+json",
+];
+
+/// Media types of the config and the layers of images in `docker save`
+/// archives, the layers of which are not compressed.
+const DOCKER_CONFIG_MEDIA_TYPE: &str =
+    "application/vnd.docker.container.image.v1+json";
+const DOCKER_LAYER_MEDIA_TYPE: &str =
+    "application/vnd.docker.image.rootfs.diff.tar";
+
+/// The index.json of an OCI image layout.
+#[derive(Debug, Deserialize)]
+struct Index {
+    manifests: Vec<IndexEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct IndexEntry {
+    media_type: String,
+    digest: String,
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// An image in the manifest.json of a `docker save` archive.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerArchiveImage {
+    config: String,
+    /// Null for images without a tag
+    repo_tags: Option<Vec<String>>,
+    layers: Vec<String>,
+}
+
+impl DockerArchiveImage {
+    fn files(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(&self.config).chain(&self.layers).map(String::as_str)
+    }
+}
+
+impl IndexEntry {
+    fn reference(&self) -> Option<Reference> {
+        NAME_ANNOTATIONS
+            .iter()
+            .filter_map(|name| self.annotations.get(*name))
+            .find_map(|name| name.parse().ok())
+    }
+}
+
+/// Load the images of the OCI image layout at `path` into `store` and
+/// unpack their layers, replacing images stored by the same reference.
+/// Returns the loaded images.
+pub(crate) async fn preload(
+    store: &ImageStore,
+    limits: &PullLimits,
+    path: &Path,
+) -> Result<Vec<StoredImage>> {
+    let index = {
+        let store = store.clone();
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || import(&store, &path))
+            .await
+            .expect("preload task panicked")?
+    };
+
+    let mut images = vec![];
+    for entry in index.manifests {
+        if !MANIFEST_MEDIA_TYPES.contains(&entry.media_type.as_str()) {
+            warn!(
+                "Skipping {} of media type {}, it is not an image manifest",
+                entry.digest, entry.media_type
+            );
+            continue;
+        }
+        let Some(reference) = entry.reference() else {
+            warn!(
+                "Skipping manifest {}, it does not name an image",
+                entry.digest
+            );
+            continue;
+        };
+
+        let manifest: OciImageManifest =
+            serde_json::from_slice(&store.read_blob(&entry.digest)?)?;
+        for blob in std::iter::once(&manifest.config).chain(&manifest.layers) {
+            if !store.has_blob(&blob.digest)? {
+                return Err(invalid(
+                    path,
+                    format!("blob {} of {reference} is missing", blob.digest),
+                ));
+            }
+        }
+
+        let _: Vec<_> = manifest
+            .layers
+            .iter()
+            .filter(|layer| unpack::is_archive(&layer.media_type))
+            .map(|layer| unpack::unpack(store, limits, layer))
+            .collect::<FuturesUnordered<_>>()
+            .try_collect()
+            .await?;
+
+        let image = StoredImage {
+            reference: reference.whole(),
+            digest: entry.digest,
+            manifest,
+        };
+        store.save(&image)?;
+        info!("Preloaded {} ({})", image.reference, image.digest);
+        images.push(image);
+    }
+    Ok(images)
+}
+
+/// Import the blobs of the layout at `path` into `store`, returning the
+/// index of the layout.
+fn import(store: &ImageStore, path: &Path) -> Result<Index> {
+    if !path.exists() {
+        return Err(invalid(path, "it does not exist".into()));
+    }
+
+    let index = if path.is_dir() {
+        let blobs = path.join("blobs").join("sha256");
+        if blobs.is_dir() {
+            for entry in fs::read_dir(blobs)? {
+                let entry = entry?;
+                let digest =
+                    format!("sha256:{}", entry.file_name().to_string_lossy());
+                store.import_blob(&digest, &mut File::open(entry.path())?)?;
+            }
+        }
+        match fs::read(path.join("index.json")) {
+            Ok(index) => Some(index),
+            Err(_) => match fs::read(path.join("manifest.json")) {
+                Ok(manifest) => {
+                    let images = docker_images(path, &manifest)?;
+                    let mut files = HashMap::new();
+                    for name in images.iter().flat_map(|image| image.files()) {
+                        let file = store.add_blob(&mut File::open(
+                            path.join(archive_path(name)),
+                        )?)?;
+                        let _ = files.insert(archive_path(name), file);
+                    }
+                    return docker_index(store, path, &images, &files);
+                }
+                Err(_) => None,
+            },
+        }
+    } else {
+        let mut index = None;
+        let mut manifest = None;
+        let mut archive = tar::Archive::new(File::open(path)?);
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let name = archive_path(&entry.path()?);
+            if name == Path::new("index.json") {
+                let mut buf = vec![];
+                let _ = entry.read_to_end(&mut buf)?;
+                index = Some(buf);
+            } else if name == Path::new("manifest.json") {
+                let mut buf = vec![];
+                let _ = entry.read_to_end(&mut buf)?;
+                manifest = Some(buf);
+            } else if let Some(hex) = name
+                .strip_prefix("blobs/sha256")
+                .ok()
+                .and_then(|hex| hex.to_str())
+                .filter(|hex| !hex.is_empty())
+            {
+                store.import_blob(&format!("sha256:{hex}"), &mut entry)?;
+            }
+        }
+        match (index, manifest) {
+            (None, Some(manifest)) => {
+                return import_docker_archive(store, path, &manifest)
+            }
+            (index, _) => index,
+        }
+    };
+
+    let index =
+        index.ok_or_else(|| invalid(path, "index.json is missing".into()))?;
+    serde_json::from_slice(&index)
+        .map_err(|e| invalid(path, format!("invalid index.json: {e}")))
+}
+
+/// Import the config and layer files of the images listed by `manifest`
+/// from the `docker save` archive at `path` into `store`, returning an
+/// index of the images. The archive is read a second time, since the
+/// manifest.json is written after the files it lists. Layers shared by
+/// images may be symlinks to the layer of another image.
+fn import_docker_archive(
+    store: &ImageStore,
+    path: &Path,
+    manifest: &[u8],
+) -> Result<Index> {
+    let images = docker_images(path, manifest)?;
+    let wanted: Vec<_> = images
+        .iter()
+        .flat_map(|image| image.files())
+        .map(archive_path)
+        .collect();
+
+    let mut files = HashMap::new();
+    let mut links = vec![];
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = archive_path(&entry.path()?);
+        if !wanted.contains(&name) || files.contains_key(&name) {
+            continue;
+        }
+        if entry.header().entry_type().is_symlink() {
+            if let Some(target) = entry.link_name()? {
+                let dir = name.parent().unwrap_or(Path::new(""));
+                links.push((name, resolve(&dir.join(target))));
+            }
+        } else {
+            let file = store.add_blob(&mut entry)?;
+            let _ = files.insert(name, file);
+        }
+    }
+    for (name, target) in links {
+        if let Some(file) = files.get(&target).cloned() {
+            let _ = files.insert(name, file);
+        }
+    }
+    docker_index(store, path, &images, &files)
+}
+
+/// The images listed by the manifest.json of the `docker save` archive at
+/// `path`. Files outside of the archive are rejected.
+fn docker_images(
+    path: &Path,
+    manifest: &[u8],
+) -> Result<Vec<DockerArchiveImage>> {
+    let images: Vec<DockerArchiveImage> = serde_json::from_slice(manifest)
+        .map_err(|e| invalid(path, format!("invalid manifest.json: {e}")))?;
+    for name in images.iter().flat_map(|image| image.files()) {
+        if !archive_path(name)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(invalid(
+                path,
+                format!("{name} is outside of the archive"),
+            ));
+        }
+    }
+    Ok(images)
+}
+
+/// Write a manifest of each of `images` to `store`, with the digests of
+/// their imported `files`, returning an index naming each manifest by the
+/// tags of its image.
+fn docker_index(
+    store: &ImageStore,
+    path: &Path,
+    images: &[DockerArchiveImage],
+    files: &HashMap<PathBuf, (String, u64)>,
+) -> Result<Index> {
+    let descriptor = |name: &str, media_type: &str| {
+        let (digest, size) = files
+            .get(&archive_path(name))
+            .ok_or_else(|| invalid(path, format!("{name} is missing")))?;
+        Ok::<_, ImageServiceError>(OciDescriptor {
+            media_type: media_type.into(),
+            digest: digest.clone(),
+            size: *size as i64,
+            ..Default::default()
+        })
+    };
+
+    let mut manifests = vec![];
+    for image in images {
+        let manifest = OciImageManifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPES[1].into()),
+            config: descriptor(&image.config, DOCKER_CONFIG_MEDIA_TYPE)?,
+            layers: image
+                .layers
+                .iter()
+                .map(|layer| descriptor(layer, DOCKER_LAYER_MEDIA_TYPE))
+                .collect::<Result<_>>()?,
+            ..Default::default()
+        };
+        let (digest, _) =
+            store.add_blob(&mut &serde_json::to_vec(&manifest)?[..])?;
+
+        let tags = image.repo_tags.as_deref().unwrap_or_default();
+        if tags.is_empty() {
+            manifests.push(IndexEntry {
+                media_type: MANIFEST_MEDIA_TYPES[1].into(),
+                digest,
+                annotations: HashMap::new(),
+            });
+            continue;
+        }
+        manifests.extend(tags.iter().map(|tag| IndexEntry {
+            media_type: MANIFEST_MEDIA_TYPES[1].into(),
+            digest: digest.clone(),
+            annotations: HashMap::from([(
+                NAME_ANNOTATIONS[0].to_string(),
+                tag.clone(),
+            )]),
+        }));
+    }
+    Ok(Index { manifests })
+}
+
+/// Resolve the `..` components of the path of an entry of an archive.
+fn resolve(path: &Path) -> PathBuf {
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                let _ = resolved.pop();
+            }
+            Component::Normal(name) => resolved.push(name),
+            _ => {}
+        }
+    }
+    resolved
+}
+
+/// The path of an entry of an archive, relative to its root.
+fn archive_path(name: impl AsRef<Path>) -> PathBuf {
+    let name = name.as_ref();
+    name.strip_prefix("./").unwrap_or(name).to_path_buf()
+}
+
+fn invalid(path: &Path, reason: String) -> ImageServiceError {
+    ImageServiceError::InvalidLayout {
+        path: path.display().to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::distribution::manifest::OciDescriptor;
+    use crate::images::pull::ImagePullConfig;
+    use sha2::{Digest, Sha256};
+
+    /// Writes `blob` to the layout at `dir`, returning its descriptor.
+    fn write_blob(dir: &Path, media_type: &str, blob: &[u8]) -> OciDescriptor {
+        let hex = format!("{:x}", Sha256::digest(blob));
+        fs::write(dir.join("blobs").join("sha256").join(&hex), blob)
+            .expect("write blob");
+        OciDescriptor {
+            media_type: media_type.into(),
+            digest: format!("sha256:{hex}"),
+            size: blob.len() as i64,
+            ..Default::default()
+        }
+    }
+
+    /// A layer with a single file.
+    fn motd_layer() -> Vec<u8> {
+        let mut builder = tar::Builder::new(vec![]);
+        let contents = b"hello from a preloaded layer";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "etc/motd", &contents[..])
+            .expect("append");
+        builder.into_inner().expect("tar")
+    }
+
+    /// Appends a file with `contents` to `builder`.
+    fn append_file(
+        builder: &mut tar::Builder<File>,
+        name: &str,
+        contents: &[u8],
+    ) {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, name, contents).expect("append");
+    }
+
+    /// A `docker save` archive in `dir` with the files of its images listed
+    /// in `manifest`, the second layer linking to the first.
+    fn docker_archive(dir: &Path, manifest: serde_json::Value) -> PathBuf {
+        let archive = dir.join("busybox.tar");
+        let mut builder =
+            tar::Builder::new(File::create(&archive).expect("create archive"));
+        append_file(&mut builder, "abc/layer.tar", &motd_layer());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        builder
+            .append_link(&mut header, "def/layer.tar", "../abc/layer.tar")
+            .expect("append link");
+        append_file(&mut builder, "config.json", b"{}");
+        append_file(
+            &mut builder,
+            "manifest.json",
+            manifest.to_string().as_bytes(),
+        );
+        let _ = builder.into_inner().expect("write archive");
+        archive
+    }
+
+    /// A layout in `dir` holding an image with a single file and a kernel
+    /// artifact.
+    fn layout(dir: &Path) -> &Path {
+        fs::create_dir_all(dir.join("blobs").join("sha256"))
+            .expect("create blobs dir");
+
+        let layer = motd_layer();
+
+        let manifest = OciImageManifest {
+            config: write_blob(
+                &dir,
+                "application/vnd.oci.image.config.v1+json",
+                b"{}",
+            ),
+            layers: vec![
+                write_blob(
+                    &dir,
+                    "application/vnd.oci.image.layer.v1.tar",
+                    &layer,
+                ),
+                write_blob(&dir, "application/octet-stream", b"vmlinux"),
+            ],
+            ..Default::default()
+        };
+        let manifest = write_blob(
+            &dir,
+            MANIFEST_MEDIA_TYPES[0],
+            &serde_json::to_vec(&manifest).expect("manifest"),
+        );
+
+        let index = serde_json::json!({
+            "schemaVersion": 2,
+            "manifests": [{
+                "mediaType": manifest.media_type,
+                "digest": manifest.digest,
+                "size": manifest.size,
+                "annotations": {
+                    "org.opencontainers.image.ref.name":
+                        "docker.io/library/busybox:1.36",
+                },
+            }, {
+                "mediaType": manifest.media_type,
+                "digest": manifest.digest,
+                "size": manifest.size,
+            }],
+        });
+        fs::write(dir.join("index.json"), index.to_string())
+            .expect("write index");
+        dir
+    }
+
+    fn scratch_store() -> (ImageStore, tempfile::TempDir) {
+        let root = tempfile::tempdir().expect("scratch dir");
+        let store = ImageStore::new(root.path());
+        let _ = store.open().expect("open");
+        (store, root)
+    }
+
+    async fn check_preload(path: &Path) {
+        let (store, _root) = scratch_store();
+        let limits = PullLimits::new(&ImagePullConfig::default());
+
+        let images = preload(&store, &limits, path).await.expect("preload");
+        assert_eq!(images.len(), 1);
+        let image = &images[0];
+        assert_eq!(image.reference, "docker.io/library/busybox:1.36");
+        assert_eq!(store.open().expect("reopen"), 1);
+
+        let layer = &image.manifest.layers[0];
+        let unpacked = store.layer_path(&layer.digest).expect("layer path");
+        assert!(unpacked.join("etc/motd").exists());
+        let kernel = &image.manifest.layers[1];
+        assert!(store.has_blob(&kernel.digest).expect("has blob"));
+        assert!(!store.layer_path(&kernel.digest).expect("path").exists());
+    }
+
+    #[tokio::test]
+    async fn preloads_layout_directories() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        check_preload(layout(dir.path())).await;
+    }
+
+    #[tokio::test]
+    async fn preloads_layout_archives() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let archive = dir.path().join("layout.tar");
+        let mut builder =
+            tar::Builder::new(File::create(&archive).expect("create archive"));
+        builder
+            .append_dir_all(".", layout(&dir.path().join("layout")))
+            .expect("append layout");
+        let _ = builder.into_inner().expect("write archive");
+
+        check_preload(&archive).await;
+    }
+
+    #[tokio::test]
+    async fn preloads_docker_archives() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let archive = docker_archive(
+            dir.path(),
+            serde_json::json!([{
+                "Config": "config.json",
+                "RepoTags": ["busybox:1.36"],
+                "Layers": ["abc/layer.tar"],
+            }, {
+                "Config": "config.json",
+                "RepoTags": ["busybox:latest"],
+                "Layers": ["def/layer.tar"],
+            }, {
+                "Config": "config.json",
+                "RepoTags": null,
+                "Layers": ["abc/layer.tar"],
+            }]),
+        );
+        let (store, _root) = scratch_store();
+        let limits = PullLimits::new(&ImagePullConfig::default());
+
+        let images = preload(&store, &limits, &archive).await.expect("preload");
+        let references: Vec<_> =
+            images.iter().map(|image| image.reference.as_str()).collect();
+        assert_eq!(
+            references,
+            [
+                "docker.io/library/busybox:1.36",
+                "docker.io/library/busybox:latest"
+            ]
+        );
+        assert_eq!(store.open().expect("reopen"), 2);
+
+        let layer = &images[0].manifest.layers[0];
+        assert_eq!(layer.media_type, DOCKER_LAYER_MEDIA_TYPE);
+        assert_eq!(layer.digest, images[1].manifest.layers[0].digest);
+        let unpacked = store.layer_path(&layer.digest).expect("layer path");
+        assert!(unpacked.join("etc/motd").exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_docker_archives_naming_outside_files() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let archive = docker_archive(
+            dir.path(),
+            serde_json::json!([{
+                "Config": "../../etc/shadow",
+                "RepoTags": ["busybox:1.36"],
+                "Layers": [],
+            }]),
+        );
+        let (store, _root) = scratch_store();
+        let limits = PullLimits::new(&ImagePullConfig::default());
+
+        assert!(matches!(
+            preload(&store, &limits, &archive).await,
+            Err(ImageServiceError::InvalidLayout { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_layouts() {
+        let (store, _root) = scratch_store();
+        let limits = PullLimits::new(&ImagePullConfig::default());
+
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let missing = dir.path().join("layout.tar");
+        assert!(matches!(
+            preload(&store, &limits, &missing).await,
+            Err(ImageServiceError::InvalidLayout { .. })
+        ));
+    }
+}
@@ -0,0 +1,506 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::{
+    distribution::{manifest::OciDescriptor, Client, Reference},
+    error::{ImageServiceError, Result},
+    registries::{Registries, Source},
+    store::{ImageStore, StoredImage},
+    unpack,
+};
+use futures::{stream::FuturesUnordered, StreamExt};
+use proto::images::ImagePullProgress;
+use sha2::{Digest, Sha256};
+use std::{
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{ready, Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::Semaphore,
+    time::Sleep,
+};
+use tracing::{info, warn};
+
+/// How often progress is reported while blobs are fetched.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Limits on image pulls, shared by all pulls of the node.
+#[derive(Debug, Clone)]
+pub struct ImagePullConfig {
+    /// Maximum number of blobs fetched at the same time.
+    pub max_concurrent_downloads: usize,
+    /// Maximum number of layers unpacked at the same time.
+    pub max_concurrent_unpacks: usize,
+    /// Maximum bytes per second fetched, unlimited if None.
+    pub max_download_bandwidth: Option<u64>,
+    /// The registries config of auraed, with the credentials and mirrors of
+    /// registries.
+    pub registries: PathBuf,
+    /// A docker config.json to read the credentials of registries from.
+    pub docker_config: PathBuf,
+}
+
+impl Default for ImagePullConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_downloads: 3,
+            max_concurrent_unpacks: std::thread::available_parallelism()
+                .map_or(1, |n| n.get()),
+            max_download_bandwidth: None,
+            registries: PathBuf::from("/etc/aurae/registries.json"),
+            docker_config: std::env::var_os("DOCKER_CONFIG")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/root/.docker"))
+                .join("config.json"),
+        }
+    }
+}
+
+/// The limits of an [ImagePullConfig], enforced across all pulls.
+#[derive(Debug, Clone)]
+pub(crate) struct PullLimits {
+    downloads: Arc<Semaphore>,
+    pub unpacks: Arc<Semaphore>,
+    bandwidth: Option<Arc<Bandwidth>>,
+}
+
+impl PullLimits {
+    pub fn new(config: &ImagePullConfig) -> Self {
+        Self {
+            downloads: Arc::new(Semaphore::new(
+                config.max_concurrent_downloads.max(1),
+            )),
+            unpacks: Arc::new(Semaphore::new(
+                config.max_concurrent_unpacks.max(1),
+            )),
+            bandwidth: config
+                .max_download_bandwidth
+                .filter(|bytes_per_sec| *bytes_per_sec > 0)
+                .map(|bytes_per_sec| Arc::new(Bandwidth::new(bytes_per_sec))),
+        }
+    }
+}
+
+/// Pull the image `reference` into `store`, calling `progress` while its
+/// blobs are fetched and its layers are unpacked. Blobs already in the store
+/// are not fetched again.
+///
+/// The image is pulled from the first of the mirrors of its registry in
+/// `registries` that serves its manifest, or from the registry itself. It is
+/// stored by `reference` regardless.
+///
+/// Blobs are fetched concurrently, each layer is unpacked as soon as it is
+/// stored. Dropping the returned future cancels the pull, blobs that were not
+/// completely fetched are discarded.
+pub(crate) async fn pull(
+    store: &ImageStore,
+    limits: &PullLimits,
+    registries: &Registries,
+    reference: &Reference,
+    mut progress: impl FnMut(ImagePullProgress),
+) -> Result<StoredImage> {
+    let failed = |source: anyhow::Error| ImageServiceError::FailedToPull {
+        reference: reference.whole(),
+        source,
+    };
+
+    let client = Client::new(registries.client_config())
+        .map_err(|e| failed(e.into()))?;
+    let mut sources = registries.sources(reference).into_iter().peekable();
+    let (source, manifest, digest) = loop {
+        let Some(Source { reference: source, auth }) = sources.next() else {
+            unreachable!("the registry itself is always a source");
+        };
+        match client.pull_image_manifest(&source, &auth).await {
+            Ok((manifest, digest)) => break (source, manifest, digest),
+            Err(e) if sources.peek().is_some() => {
+                warn!("Failed to pull {source}, trying the next source: {e}");
+            }
+            Err(e) => return Err(failed(e.into())),
+        }
+    };
+    let image = StoredImage { reference: reference.whole(), digest, manifest };
+    info!("Pulling {} ({}) from {}", image.reference, image.digest, source);
+
+    let client = &client;
+    let source = &source;
+    let mut tracker = Tracker::new(&image);
+    let mut fetches = FuturesUnordered::new();
+    let mut unpacks = FuturesUnordered::new();
+    let mut in_flight = vec![];
+
+    let blobs = std::iter::once((&image.manifest.config, false))
+        .chain(image.manifest.layers.iter().map(|layer| (layer, true)));
+    for (blob, is_layer) in blobs {
+        if store.has_blob(&blob.digest)? {
+            tracker.skipped(blob, is_layer);
+            if is_layer {
+                unpacks.push(unpack::unpack(store, limits, blob));
+            }
+            continue;
+        }
+
+        let fetched = Arc::new(AtomicU64::new(0));
+        in_flight.push((blob, fetched.clone()));
+        fetches.push(async move {
+            let res =
+                fetch(client, store, limits, reference, source, blob, fetched)
+                    .await;
+            (blob, is_layer, res)
+        });
+    }
+
+    let mut ticker = tokio::time::interval(PROGRESS_INTERVAL);
+    while !fetches.is_empty() || !unpacks.is_empty() {
+        tokio::select! {
+            Some((blob, is_layer, res)) = fetches.next() => {
+                res?;
+                in_flight.retain(|(b, _)| b.digest != blob.digest);
+                tracker.fetched(blob, is_layer);
+                if is_layer {
+                    unpacks.push(unpack::unpack(store, limits, blob));
+                }
+                progress(ImagePullProgress {
+                    layer_fetched: blob.size.max(0) as u64,
+                    ..tracker.progress(blob, 0, fetching(&in_flight))
+                });
+            }
+            Some(res) = unpacks.next() => {
+                let blob = res?;
+                tracker.unpacked();
+                progress(tracker.progress(blob, 0, fetching(&in_flight)));
+            }
+            _ = ticker.tick() => {
+                let total = fetching(&in_flight);
+                for (blob, fetched) in &in_flight {
+                    let fetched = fetched.load(Ordering::Relaxed);
+                    progress(tracker.progress(blob, fetched, total));
+                }
+            }
+        }
+    }
+
+    store.save(&image)?;
+    info!("Pulled {} in {:?}", image.reference, tracker.started.elapsed());
+    Ok(image)
+}
+
+/// Fetch `blob` of the image `reference` from `source` into the store once a
+/// download slot is free.
+async fn fetch(
+    client: &Client,
+    store: &ImageStore,
+    limits: &PullLimits,
+    reference: &Reference,
+    source: &Reference,
+    blob: &OciDescriptor,
+    fetched: Arc<AtomicU64>,
+) -> Result<()> {
+    let _permit =
+        limits.downloads.acquire().await.expect("semaphore is never closed");
+
+    let mut writer = BlobWriter::create(store, &blob.digest, fetched).await?;
+    writer.bandwidth = limits.bandwidth.clone();
+    client.pull_blob(source, blob, &mut writer).await.map_err(|e| {
+        ImageServiceError::FailedToPull {
+            reference: reference.whole(),
+            source: e.into(),
+        }
+    })?;
+    writer.commit().await
+}
+
+/// Bytes fetched so far of the blobs in flight.
+fn fetching(in_flight: &[(&OciDescriptor, Arc<AtomicU64>)]) -> u64 {
+    in_flight.iter().map(|(_, fetched)| fetched.load(Ordering::Relaxed)).sum()
+}
+
+/// Tracks the progress of a pull across the blobs of an image.
+struct Tracker {
+    started: Instant,
+    size: u64,
+    /// Bytes of the blobs that are stored, including skipped ones.
+    done: u64,
+    /// Bytes of the blobs that were already stored and not fetched.
+    skipped: u64,
+    layers: u32,
+    layers_done: u32,
+    layers_unpacked: u32,
+}
+
+impl Tracker {
+    fn new(image: &StoredImage) -> Self {
+        Self {
+            started: Instant::now(),
+            size: image.size(),
+            done: 0,
+            skipped: 0,
+            layers: image.manifest.layers.len() as u32,
+            layers_done: 0,
+            layers_unpacked: 0,
+        }
+    }
+
+    fn skipped(&mut self, blob: &OciDescriptor, is_layer: bool) {
+        self.skipped += blob.size.max(0) as u64;
+        self.fetched(blob, is_layer);
+    }
+
+    fn fetched(&mut self, blob: &OciDescriptor, is_layer: bool) {
+        self.done += blob.size.max(0) as u64;
+        if is_layer {
+            self.layers_done += 1;
+        }
+    }
+
+    fn unpacked(&mut self) {
+        self.layers_unpacked += 1;
+    }
+
+    /// Progress while `blob_fetched` bytes of `blob` and `fetching` bytes of
+    /// all blobs in flight have been fetched.
+    fn progress(
+        &self,
+        blob: &OciDescriptor,
+        blob_fetched: u64,
+        fetching: u64,
+    ) -> ImagePullProgress {
+        let fetched = self.done + fetching;
+        ImagePullProgress {
+            layer: blob.digest.clone(),
+            layer_fetched: blob_fetched,
+            layer_size: blob.size.max(0) as u64,
+            fetched,
+            size: self.size,
+            layers_done: self.layers_done,
+            layers: self.layers,
+            eta_seconds: eta(
+                self.size.saturating_sub(fetched),
+                fetched - self.skipped,
+                self.started.elapsed(),
+            ),
+            layers_unpacked: self.layers_unpacked,
+        }
+    }
+}
+
+/// Seconds until `remaining` bytes are transferred at the rate `transferred`
+/// bytes were transferred in `elapsed`, or 0 if the rate is not known yet.
+fn eta(remaining: u64, transferred: u64, elapsed: Duration) -> u64 {
+    if transferred == 0 || elapsed.is_zero() {
+        return 0;
+    }
+    let rate = transferred as f64 / elapsed.as_secs_f64();
+    (remaining as f64 / rate).ceil() as u64
+}
+
+/// Writes a blob to a partial file in the store while hashing it. The blob
+/// is only moved into place by [BlobWriter::commit] if it matches its digest,
+/// otherwise the partial file is removed when the writer is dropped.
+///
+/// Writes are delayed to stay within the `bandwidth` of the node, which in
+/// turn makes the HTTP client stop reading from the registry.
+struct BlobWriter {
+    file: tokio::fs::File,
+    hasher: Sha256,
+    fetched: Arc<AtomicU64>,
+    bandwidth: Option<Arc<Bandwidth>>,
+    delay: Option<Pin<Box<Sleep>>>,
+    digest: String,
+    path: PathBuf,
+    partial: PathBuf,
+    committed: bool,
+}
+
+impl BlobWriter {
+    async fn create(
+        store: &ImageStore,
+        digest: &str,
+        fetched: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        let path = store.blob_path(digest)?;
+        let partial =
+            path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+        Ok(Self {
+            file: tokio::fs::File::create(&partial).await?,
+            hasher: Sha256::new(),
+            fetched,
+            bandwidth: None,
+            delay: None,
+            digest: digest.into(),
+            path,
+            partial,
+            committed: false,
+        })
+    }
+
+    async fn commit(mut self) -> Result<()> {
+        self.file.flush().await?;
+        self.file.sync_all().await?;
+
+        let actual = format!("sha256:{:x}", self.hasher.clone().finalize());
+        if actual != self.digest {
+            return Err(ImageServiceError::DigestMismatch {
+                digest: self.digest.clone(),
+                actual,
+            });
+        }
+
+        tokio::fs::rename(&self.partial, &self.path).await?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl AsyncWrite for BlobWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if let Some(delay) = this.delay.as_mut() {
+            ready!(delay.as_mut().poll(cx));
+            this.delay = None;
+        }
+
+        let res = Pin::new(&mut this.file).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.hasher.update(&buf[..n]);
+            let _ = this.fetched.fetch_add(n as u64, Ordering::Relaxed);
+            if let Some(bandwidth) = &this.bandwidth {
+                let wait = bandwidth.reserve(n as u64);
+                if !wait.is_zero() {
+                    this.delay = Some(Box::pin(tokio::time::sleep(wait)));
+                }
+            }
+        }
+        res
+    }
+
+    fn poll_flush(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.file).poll_shutdown(cx)
+    }
+}
+
+/// Spreads the bytes fetched across all pulls over time, so they do not
+/// exceed a number of bytes per second.
+#[derive(Debug)]
+struct Bandwidth {
+    bytes_per_sec: u64,
+    /// When the bytes reserved so far are transferred at the limit.
+    next: Mutex<Instant>,
+}
+
+impl Bandwidth {
+    fn new(bytes_per_sec: u64) -> Self {
+        Self { bytes_per_sec, next: Mutex::new(Instant::now()) }
+    }
+
+    /// Reserve `bytes`, returning how long to wait before transferring any
+    /// more to stay within the limit.
+    fn reserve(&self, bytes: u64) -> Duration {
+        let mut next = self.next.lock().expect("bandwidth lock poisoned");
+        let now = Instant::now();
+        *next = (*next).max(now)
+            + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec as f64);
+        next.saturating_duration_since(now)
+    }
+}
+
+impl Drop for BlobWriter {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.partial);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eta_is_unknown_until_bytes_were_transferred() {
+        assert_eq!(eta(100, 0, Duration::from_secs(1)), 0);
+        assert_eq!(eta(100, 10, Duration::ZERO), 0);
+    }
+
+    #[test]
+    fn eta_extrapolates_the_rate_so_far() {
+        assert_eq!(eta(1000, 100, Duration::from_secs(2)), 20);
+        assert_eq!(eta(1, 100, Duration::from_secs(2)), 1);
+    }
+
+    #[test]
+    fn bandwidth_spreads_reservations_over_time() {
+        let bandwidth = Bandwidth::new(1000);
+        let first = bandwidth.reserve(500);
+        let second = bandwidth.reserve(500);
+        assert!(first <= Duration::from_millis(500));
+        assert!(second > Duration::from_millis(900));
+        assert!(second <= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn blob_writer_only_commits_matching_blobs() {
+        let root = tempfile::tempdir().expect("scratch dir");
+        let store = ImageStore::new(root.path());
+        let _ = store.open().expect("open");
+
+        let blob = b"layer contents";
+        let digest = format!("sha256:{:x}", Sha256::digest(blob));
+        let mut writer =
+            BlobWriter::create(&store, &digest, Default::default())
+                .await
+                .expect("create");
+        writer.write_all(blob).await.expect("write");
+        assert_eq!(writer.fetched.load(Ordering::Relaxed), blob.len() as u64);
+        writer.commit().await.expect("commit");
+        assert!(store.has_blob(&digest).expect("has blob"));
+
+        let other = format!("sha256:{}", "0".repeat(64));
+        let mut writer = BlobWriter::create(&store, &other, Default::default())
+            .await
+            .expect("create");
+        writer.write_all(blob).await.expect("write");
+        let partial = writer.partial.clone();
+        assert!(matches!(
+            writer.commit().await,
+            Err(ImageServiceError::DigestMismatch { .. })
+        ));
+        assert!(!partial.exists());
+        assert!(!store.has_blob(&other).expect("has blob"));
+    }
+}
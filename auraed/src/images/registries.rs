@@ -0,0 +1,305 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Credentials and mirrors of the registries images are pulled from.
+//!
+//! Credentials are read from a docker config.json, as written by `docker
+//! login`, and from the registries config of auraed, which takes precedence
+//! and also lists the mirrors of a registry:
+//!
+//! ```json
+//! {
+//!   "registries": {
+//!     "docker.io": {
+//!       "username": "aurae",
+//!       "password": "secret",
+//!       "mirrors": ["mirror.example.com"]
+//!     },
+//!     "mirror.example.com": { "insecure": true }
+//!   }
+//! }
+//! ```
+//!
+//! Registries that challenge the client for a bearer token are sent the
+//! credentials to the realm of the challenge instead, which is handled by
+//! the client of the registry. Both files are read on every pull, so changed
+//! credentials apply without restarting auraed.
+
+use super::{
+    distribution::{ClientConfig, ClientProtocol, Reference, RegistryAuth},
+    error::{ImageServiceError, Result},
+    pull::ImagePullConfig,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+/// The registries config of auraed.
+#[derive(Debug, Default, Deserialize)]
+struct RegistriesFile {
+    #[serde(default)]
+    registries: HashMap<String, RegistryConfig>,
+}
+
+/// How to pull from one registry, keyed by its host.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default)]
+struct RegistryConfig {
+    username: Option<String>,
+    password: Option<String>,
+    /// Hosts tried in order before the registry itself
+    mirrors: Vec<String>,
+    /// Pull over plain HTTP instead of HTTPS
+    insecure: bool,
+}
+
+/// The subset of a docker config.json auraed reads.
+#[derive(Debug, Default, Deserialize)]
+struct DockerConfig {
+    #[serde(default)]
+    auths: HashMap<String, DockerAuth>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DockerAuth {
+    /// base64 of `username:password`
+    auth: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+/// Where the registries are configured.
+#[derive(Debug, Clone)]
+pub(crate) struct RegistryFiles {
+    registries: PathBuf,
+    docker_config: PathBuf,
+}
+
+impl RegistryFiles {
+    pub fn new(config: &ImagePullConfig) -> Self {
+        Self {
+            registries: config.registries.clone(),
+            docker_config: config.docker_config.clone(),
+        }
+    }
+
+    /// Reads both files, either of them may not exist.
+    pub fn load(&self) -> Result<Registries> {
+        let docker: DockerConfig = read(&self.docker_config)?;
+        let native: RegistriesFile = read(&self.registries)?;
+
+        let mut registries: HashMap<String, RegistryConfig> = HashMap::new();
+        for (server, auth) in docker.auths {
+            let Some((username, password)) =
+                docker_credentials(auth).map_err(|reason| {
+                    ImageServiceError::InvalidRegistryConfig {
+                        path: self.docker_config.display().to_string(),
+                        reason: format!("{server}: {reason}"),
+                    }
+                })?
+            else {
+                continue;
+            };
+            let _ = registries.insert(
+                normalize_host(&server),
+                RegistryConfig {
+                    username: Some(username),
+                    password: Some(password),
+                    ..Default::default()
+                },
+            );
+        }
+        for (host, config) in native.registries {
+            let _ = registries.insert(normalize_host(&host), config);
+        }
+
+        Ok(Registries { registries })
+    }
+}
+
+/// A registry or one of its mirrors an image can be pulled from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Source {
+    /// The reference of the image on the source
+    pub reference: Reference,
+    pub auth: RegistryAuth,
+}
+
+/// The configured registries, see [RegistryFiles::load].
+#[derive(Debug, Default)]
+pub(crate) struct Registries {
+    registries: HashMap<String, RegistryConfig>,
+}
+
+impl Registries {
+    /// The config of the client pulling images, which talks plain HTTP to
+    /// insecure registries.
+    pub fn client_config(&self) -> ClientConfig {
+        let mut insecure: Vec<String> = self
+            .registries
+            .iter()
+            .filter(|(_, config)| config.insecure)
+            .map(|(host, _)| host.clone())
+            .collect();
+        insecure.sort();
+        ClientConfig { protocol: ClientProtocol::HttpsExcept(insecure) }
+    }
+
+    /// The sources to pull `reference` from in order, the mirrors of its
+    /// registry before the registry itself.
+    pub fn sources(&self, reference: &Reference) -> Vec<Source> {
+        let registry = reference.registry();
+        let mirrors = self
+            .registries
+            .get(registry)
+            .map(|config| config.mirrors.as_slice())
+            .unwrap_or_default();
+
+        mirrors
+            .iter()
+            .map(|mirror| normalize_host(mirror))
+            .chain(std::iter::once(registry.to_owned()))
+            .map(|host| {
+                let reference = match reference.digest() {
+                    Some(digest) => Reference::with_digest(
+                        host.clone(),
+                        reference.repository().into(),
+                        digest.into(),
+                    ),
+                    None => Reference::with_tag(
+                        host.clone(),
+                        reference.repository().into(),
+                        reference.tag().unwrap_or("latest").into(),
+                    ),
+                };
+                Source { reference, auth: self.auth(&host) }
+            })
+            .collect()
+    }
+
+    /// The credentials configured for the registry or mirror `host`.
+    pub fn auth(&self, host: &str) -> RegistryAuth {
+        match self.registries.get(host) {
+            Some(RegistryConfig {
+                username: Some(username),
+                password: Some(password),
+                ..
+            }) => RegistryAuth::Basic(username.clone(), password.clone()),
+            _ => RegistryAuth::Anonymous,
+        }
+    }
+}
+
+/// Deserializes the JSON file at `path`, the default if it does not exist.
+fn read<T: Default + for<'de> Deserialize<'de>>(path: &Path) -> Result<T> {
+    let json = match std::fs::read(path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e.into()),
+    };
+    serde_json::from_slice(&json).map_err(|e| {
+        ImageServiceError::InvalidRegistryConfig {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        }
+    })
+}
+
+/// The username and password of an entry of a docker config.json, None if
+/// the entry has no credentials, e.g. because they are kept by a credential
+/// helper.
+fn docker_credentials(
+    auth: DockerAuth,
+) -> std::result::Result<Option<(String, String)>, String> {
+    if let (Some(username), Some(password)) = (auth.username, auth.password) {
+        return Ok(Some((username, password)));
+    }
+    let Some(auth) = auth.auth.filter(|auth| !auth.is_empty()) else {
+        return Ok(None);
+    };
+    let decoded = STANDARD.decode(auth).map_err(|e| e.to_string())?;
+    let decoded = String::from_utf8(decoded).map_err(|e| e.to_string())?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or_else(|| "auth is not 'username:password'".to_string())?;
+    Ok(Some((username.into(), password.into())))
+}
+
+/// The host of a registry as in image references. docker config.json keys
+/// may be URLs, and name Docker Hub by the host of its index.
+fn normalize_host(server: &str) -> String {
+    let host =
+        server.trim_start_matches("https://").trim_start_matches("http://");
+    let host = host.split('/').next().unwrap_or(host);
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io".into(),
+        host => host.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn docker_hub_is_normalized() {
+        assert_eq!(normalize_host("https://index.docker.io/v1/"), "docker.io");
+        assert_eq!(normalize_host("ghcr.io"), "ghcr.io");
+        assert_eq!(normalize_host("http://localhost:5000"), "localhost:5000");
+    }
+
+    #[test]
+    fn docker_auth_is_decoded() {
+        let auth = DockerAuth {
+            auth: Some(STANDARD.encode("aurae:sec:ret")),
+            ..Default::default()
+        };
+        assert_eq!(
+            docker_credentials(auth),
+            Ok(Some(("aurae".into(), "sec:ret".into())))
+        );
+        assert_eq!(docker_credentials(DockerAuth::default()), Ok(None));
+    }
+
+    #[test]
+    fn mirrors_are_tried_before_the_registry() {
+        let native: RegistriesFile = serde_json::from_str(
+            r#"{"registries": {
+                "docker.io": {"mirrors": ["https://mirror.example.com"]},
+                "mirror.example.com": {"username": "u", "password": "p"}
+            }}"#,
+        )
+        .expect("valid config");
+        let registries = Registries { registries: native.registries };
+
+        let reference: Reference = "nginx:1.25".parse().expect("valid");
+        let sources = registries.sources(&reference);
+        assert_eq!(sources.len(), 2);
+        assert_eq!(
+            sources[0].reference.whole(),
+            "mirror.example.com/library/nginx:1.25"
+        );
+        assert_eq!(
+            sources[0].auth,
+            RegistryAuth::Basic("u".into(), "p".into())
+        );
+        assert_eq!(sources[1].reference, reference);
+        assert_eq!(sources[1].auth, RegistryAuth::Anonymous);
+    }
+}
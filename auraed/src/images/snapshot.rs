@@ -0,0 +1,176 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Root filesystems stacked from the unpacked layers of an image.
+//!
+//! A snapshot mounts the layers of an image read-only with overlayfs, below
+//! a writable layer of its own, so that the containers of an image share its
+//! layers instead of each extracting a copy of the image. The layers a
+//! snapshot is stacked from are recorded next to it, and the
+//! [ImageStore] keeps them as long as a snapshot references them.
+//!
+//! Layout on disk:
+//!
+//! ```text
+//! <root>/snapshots/<key>/layers.json
+//! <root>/snapshots/<key>/upper/
+//! <root>/snapshots/<key>/work/
+//! <root>/snapshots/<key>/rootfs/
+//! ```
+
+use super::{
+    error::{ImageServiceError, Result},
+    store::{ImageStore, StoredImage},
+    unpack,
+};
+use nix::mount::{MntFlags, MsFlags};
+use std::{fs, io::ErrorKind, path::PathBuf};
+use tracing::debug;
+
+/// Stack the layers of `image` into a new snapshot named `key`. Returns the
+/// path of its root filesystem.
+pub(crate) fn prepare(
+    store: &ImageStore,
+    key: &str,
+    image: &StoredImage,
+) -> Result<PathBuf> {
+    let dir = snapshot_path(store, key)?;
+    // overlayfs lists the upper-most layer first
+    let mut layers = vec![];
+    for layer in image.manifest.layers.iter().rev() {
+        if !unpack::is_archive(&layer.media_type) {
+            continue;
+        }
+        let path = store.layer_path(&layer.digest)?;
+        if !path.is_dir() {
+            return Err(ImageServiceError::LayerNotUnpacked {
+                digest: layer.digest.clone(),
+            });
+        }
+        layers.push((layer.digest.clone(), path));
+    }
+    if layers.is_empty() {
+        return Err(ImageServiceError::InvalidLayout {
+            path: image.reference.clone(),
+            reason: "the image has no layers".into(),
+        });
+    }
+
+    match fs::create_dir(&dir) {
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(ImageServiceError::SnapshotExists { key: key.into() })
+        }
+        res => res?,
+    }
+    let res = (|| {
+        let digests: Vec<&String> =
+            layers.iter().map(|(digest, _)| digest).collect();
+        fs::write(dir.join("layers.json"), serde_json::to_vec(&digests)?)?;
+        for sub in ["upper", "work", "rootfs"] {
+            fs::create_dir(dir.join(sub))?;
+        }
+
+        let lowerdir = layers
+            .iter()
+            .map(|(_, path)| path.display().to_string())
+            .collect::<Vec<_>>()
+            .join(":");
+        let options = format!(
+            "lowerdir={lowerdir},upperdir={},workdir={}",
+            dir.join("upper").display(),
+            dir.join("work").display()
+        );
+        nix::mount::mount(
+            Some("overlay"),
+            &dir.join("rootfs"),
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(options.as_str()),
+        )
+        .map_err(std::io::Error::from)?;
+        Ok(())
+    })();
+    if res.is_err() {
+        let _ = fs::remove_dir_all(&dir);
+    }
+    res?;
+
+    debug!("Prepared snapshot {key} of {}", image.reference);
+    Ok(dir.join("rootfs"))
+}
+
+/// Unmount the snapshot named `key` and remove its writable layer. Returns
+/// whether the snapshot existed.
+pub(crate) fn remove(store: &ImageStore, key: &str) -> Result<bool> {
+    let dir = snapshot_path(store, key)?;
+    if !dir.exists() {
+        return Ok(false);
+    }
+
+    match nix::mount::umount2(&dir.join("rootfs"), MntFlags::MNT_DETACH) {
+        // Not mounted, e.g. after a reboot
+        Ok(()) | Err(nix::errno::Errno::EINVAL) => {}
+        Err(e) => return Err(std::io::Error::from(e).into()),
+    }
+    fs::remove_dir_all(&dir)?;
+    debug!("Removed snapshot {key}");
+    Ok(true)
+}
+
+/// The digests of the layers each snapshot is stacked from.
+pub(crate) fn layers(store: &ImageStore) -> Result<Vec<Vec<String>>> {
+    let mut layers = vec![];
+    for entry in fs::read_dir(store.snapshots_path())? {
+        let path = entry?.path().join("layers.json");
+        match fs::read(&path) {
+            Ok(json) => layers.push(serde_json::from_slice(&json)?),
+            // A snapshot being prepared, its layers are kept by its image
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(layers)
+}
+
+/// Keys name a directory, they are limited to a safe set of characters.
+fn snapshot_path(store: &ImageStore, key: &str) -> Result<PathBuf> {
+    let valid = !key.is_empty()
+        && key.len() <= 128
+        && !key.starts_with('.')
+        && key
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b));
+    if !valid {
+        return Err(ImageServiceError::InvalidSnapshotKey { key: key.into() });
+    }
+    Ok(store.snapshots_path().join(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_keys_must_be_path_components() {
+        let root = tempfile::tempdir().expect("scratch dir");
+        let store = ImageStore::new(root.path());
+        for key in ["", ".", "..", "../etc", "a/b", ".hidden"] {
+            assert!(matches!(
+                snapshot_path(&store, key),
+                Err(ImageServiceError::InvalidSnapshotKey { .. })
+            ));
+        }
+        assert!(snapshot_path(&store, "pod-1_container.2").is_ok());
+    }
+}
@@ -0,0 +1,551 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::{
+    distribution::manifest::{OciDescriptor, OciImageManifest},
+    error::{ImageServiceError, Result},
+    snapshot,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{ErrorKind, Read, Write},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+use tracing::debug;
+
+/// An image pulled into the [ImageStore].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct StoredImage {
+    /// The reference the image was pulled by.
+    pub reference: String,
+    /// Digest of the image manifest.
+    pub digest: String,
+    pub manifest: OciImageManifest,
+}
+
+impl StoredImage {
+    /// Total size of the config and layer blobs of the image in bytes.
+    pub fn size(&self) -> u64 {
+        self.blobs().map(|blob| blob.size.max(0) as u64).sum()
+    }
+
+    /// Digest of the config of the image, which identifies the image for
+    /// the CRI.
+    pub fn id(&self) -> &str {
+        &self.manifest.config.digest
+    }
+
+    fn blobs(&self) -> impl Iterator<Item = &OciDescriptor> {
+        std::iter::once(&self.manifest.config).chain(&self.manifest.layers)
+    }
+}
+
+impl From<&StoredImage> for proto::images::Image {
+    fn from(image: &StoredImage) -> Self {
+        Self {
+            reference: image.reference.clone(),
+            digest: image.digest.clone(),
+            layers: image
+                .manifest
+                .layers
+                .iter()
+                .map(|layer| layer.digest.clone())
+                .collect(),
+            size: image.size(),
+        }
+    }
+}
+
+/// Stores the blobs of pulled images by their digest, so blobs shared by
+/// images are only stored once. Blobs are referenced by the images and the
+/// [snapshot]s using them, and are removed with the last of them.
+///
+/// Layout on disk:
+///
+/// ```text
+/// <root>/blobs/sha256/<hex digest>
+/// <root>/layers/sha256/<hex digest>/
+/// <root>/images/<sha256 of the reference>.json
+/// <root>/snapshots/<key>/
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct ImageStore {
+    root: PathBuf,
+}
+
+impl ImageStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Create the store below its root if it does not exist yet and remove
+    /// blobs and layers left behind by pulls that did not complete. Returns
+    /// the number of images in the store.
+    pub fn open(&self) -> Result<usize> {
+        let blobs = self.root.join("blobs").join("sha256");
+        let layers = self.root.join("layers").join("sha256");
+        fs::create_dir_all(&blobs)?;
+        fs::create_dir_all(&layers)?;
+        fs::create_dir_all(self.root.join("images"))?;
+        fs::create_dir_all(self.snapshots_path())?;
+
+        for entry in fs::read_dir(&blobs)?.chain(fs::read_dir(&layers)?) {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "partial") {
+                debug!("Removing partial {}", path.display());
+                if path.is_dir() {
+                    fs::remove_dir_all(path)?;
+                } else {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+
+        let mut images = 0;
+        for entry in fs::read_dir(self.root.join("images"))? {
+            if entry?.path().extension().is_some_and(|ext| ext == "json") {
+                images += 1;
+            }
+        }
+        Ok(images)
+    }
+
+    /// Path of the blob with `digest`, which may not be stored yet.
+    pub fn blob_path(&self, digest: &str) -> Result<PathBuf> {
+        Ok(self.root.join("blobs").join("sha256").join(sha256_hex(digest)?))
+    }
+
+    /// Path of the directory the layer with `digest` is unpacked to, which
+    /// may not be unpacked yet.
+    pub fn layer_path(&self, digest: &str) -> Result<PathBuf> {
+        Ok(self.root.join("layers").join("sha256").join(sha256_hex(digest)?))
+    }
+
+    pub fn snapshots_path(&self) -> PathBuf {
+        self.root.join("snapshots")
+    }
+
+    pub fn has_blob(&self, digest: &str) -> Result<bool> {
+        Ok(self.blob_path(digest)?.exists())
+    }
+
+    pub fn read_blob(&self, digest: &str) -> Result<Vec<u8>> {
+        Ok(fs::read(self.blob_path(digest)?)?)
+    }
+
+    /// Store the blob read from `reader` if it matches `digest`, unless it
+    /// is stored already. Like fetched blobs, it is written to a partial
+    /// file first and only moved into place once it matched its digest.
+    pub fn import_blob(
+        &self,
+        digest: &str,
+        reader: &mut impl Read,
+    ) -> Result<()> {
+        let path = self.blob_path(digest)?;
+        if path.exists() {
+            return Ok(());
+        }
+
+        let (partial, actual, _) = self.write_partial(reader)?;
+        if actual != digest {
+            let _ = fs::remove_file(&partial);
+            return Err(ImageServiceError::DigestMismatch {
+                digest: digest.into(),
+                actual,
+            });
+        }
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+
+    /// Store the blob read from `reader`, the digest of which is not known
+    /// up front, like the layers of archives written by `docker save`.
+    /// Returns the digest and the size of the blob.
+    pub fn add_blob(&self, reader: &mut impl Read) -> Result<(String, u64)> {
+        let (partial, digest, size) = self.write_partial(reader)?;
+        let res = self.blob_path(&digest).and_then(|path| {
+            if path.exists() {
+                fs::remove_file(&partial)?;
+            } else {
+                fs::rename(&partial, &path)?;
+            }
+            Ok(())
+        });
+        if res.is_err() {
+            let _ = fs::remove_file(&partial);
+        }
+        res.map(|()| (digest, size))
+    }
+
+    /// Write the blob read from `reader` to a partial file in the blob
+    /// directory, returning its path, the digest and the size of the blob.
+    fn write_partial(
+        &self,
+        reader: &mut impl Read,
+    ) -> Result<(PathBuf, String, u64)> {
+        let partial = self
+            .root
+            .join("blobs")
+            .join("sha256")
+            .join(format!("{}.partial", uuid::Uuid::new_v4()));
+        let res = (|| -> Result<(String, u64)> {
+            let mut file = File::create(&partial)?;
+            let mut hasher = Sha256::new();
+            let mut size = 0;
+            let mut buf = vec![0; 64 * 1024];
+            loop {
+                let n = reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+                size += n as u64;
+            }
+            file.sync_all()?;
+            Ok((format!("sha256:{:x}", hasher.finalize()), size))
+        })();
+        match res {
+            Ok((digest, size)) => Ok((partial, digest, size)),
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                Err(e)
+            }
+        }
+    }
+
+    /// Record `image` as pulled, replacing the image previously pulled by the
+    /// same reference.
+    pub fn save(&self, image: &StoredImage) -> Result<()> {
+        let path = self.image_path(&image.reference);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(image)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// The images in the store, sorted by reference.
+    pub fn list(&self) -> Result<Vec<StoredImage>> {
+        let mut images: Vec<StoredImage> = vec![];
+        for entry in fs::read_dir(self.root.join("images"))? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                images.push(serde_json::from_slice(&fs::read(path)?)?);
+            }
+        }
+        images.sort_by(|a, b| a.reference.cmp(&b.reference));
+        Ok(images)
+    }
+
+    /// The image pulled by `reference`, if any.
+    pub fn get(&self, reference: &str) -> Result<Option<StoredImage>> {
+        match fs::read(self.image_path(reference)) {
+            Ok(json) => Ok(Some(serde_json::from_slice(&json)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The number of images and snapshots referencing each blob.
+    pub fn references(&self) -> Result<HashMap<String, usize>> {
+        let mut references = HashMap::new();
+        for image in self.list()? {
+            for blob in image.blobs() {
+                *references.entry(blob.digest.clone()).or_default() += 1;
+            }
+        }
+        for layers in snapshot::layers(self)? {
+            for digest in layers {
+                *references.entry(digest).or_default() += 1;
+            }
+        }
+        Ok(references)
+    }
+
+    /// Forget the image pulled by `reference` and remove its blobs and
+    /// layers, unless another image or a snapshot references them. Returns
+    /// whether the image was in the store.
+    pub fn remove(&self, reference: &str) -> Result<bool> {
+        let Some(image) = self.get(reference)? else {
+            return Ok(false);
+        };
+        fs::remove_file(self.image_path(reference))?;
+
+        let references = self.references()?;
+        for blob in image.blobs() {
+            if references.contains_key(&blob.digest) {
+                continue;
+            }
+            debug!("Removing blob {}", blob.digest);
+            remove_if_exists(&self.blob_path(&blob.digest)?)?;
+            remove_if_exists(&self.layer_path(&blob.digest)?)?;
+        }
+        Ok(true)
+    }
+
+    /// Bytes and inodes used by the store. The root filesystems of
+    /// snapshots are other filesystems and are not counted.
+    pub fn usage(&self) -> Result<(u64, u64)> {
+        fn walk(
+            path: &Path,
+            dev: u64,
+            usage: &mut (u64, u64),
+        ) -> std::io::Result<()> {
+            let metadata = fs::symlink_metadata(path)?;
+            if metadata.dev() != dev {
+                return Ok(());
+            }
+            usage.0 += metadata.len();
+            usage.1 += 1;
+            if metadata.is_dir() {
+                for entry in fs::read_dir(path)? {
+                    walk(&entry?.path(), dev, usage)?;
+                }
+            }
+            Ok(())
+        }
+
+        let mut usage = (0, 0);
+        let dev = fs::metadata(&self.root)?.dev();
+        walk(&self.root, dev, &mut usage)?;
+        Ok(usage)
+    }
+
+    /// References may contain `/` and `:`, so images are stored by the hash
+    /// of their reference.
+    fn image_path(&self, reference: &str) -> PathBuf {
+        let name = format!("{:x}", Sha256::digest(reference.as_bytes()));
+        self.root.join("images").join(format!("{name}.json"))
+    }
+}
+
+fn remove_if_exists(path: &Path) -> std::io::Result<()> {
+    let res = if path.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    };
+    match res {
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+/// The hex encoded hash of a sha256 `digest`, the only algorithm supported.
+fn sha256_hex(digest: &str) -> Result<&str> {
+    digest
+        .strip_prefix("sha256:")
+        .filter(|hex| {
+            hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+        .ok_or_else(|| ImageServiceError::UnsupportedDigest {
+            digest: digest.into(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_store() -> (ImageStore, tempfile::TempDir) {
+        let root = tempfile::tempdir().expect("scratch dir");
+        (ImageStore::new(root.path()), root)
+    }
+
+    fn descriptor(digest: &str, size: i64) -> OciDescriptor {
+        OciDescriptor { digest: digest.into(), size, ..Default::default() }
+    }
+
+    #[test]
+    fn saved_images_are_counted_on_open() {
+        let (store, _root) = scratch_store();
+        assert_eq!(store.open().expect("open"), 0);
+
+        let image = StoredImage {
+            reference: "docker.io/library/busybox:latest".into(),
+            digest: format!("sha256:{}", "a".repeat(64)),
+            manifest: OciImageManifest {
+                config: descriptor(&format!("sha256:{}", "b".repeat(64)), 10),
+                layers: vec![descriptor(
+                    &format!("sha256:{}", "c".repeat(64)),
+                    100,
+                )],
+                ..Default::default()
+            },
+        };
+        assert_eq!(image.size(), 110);
+
+        store.save(&image).expect("save");
+        store.save(&image).expect("save again");
+        assert_eq!(store.open().expect("reopen"), 1);
+
+        let other = StoredImage {
+            reference: "docker.io/library/nginx:latest".into(),
+            ..image
+        };
+        store.save(&other).expect("save other");
+        assert_eq!(store.open().expect("reopen"), 2);
+    }
+
+    #[test]
+    fn open_removes_partial_blobs() {
+        let (store, _root) = scratch_store();
+        let _ = store.open().expect("open");
+
+        let blob = store
+            .blob_path(&format!("sha256:{}", "d".repeat(64)))
+            .expect("blob path");
+        let partial = blob.with_extension("partial");
+        fs::write(&partial, b"half a layer").expect("write partial blob");
+
+        let _ = store.open().expect("reopen");
+        assert!(!partial.exists());
+    }
+
+    #[test]
+    fn import_blob_only_stores_matching_blobs() {
+        let (store, _root) = scratch_store();
+        let _ = store.open().expect("open");
+
+        let blob = b"kernel image";
+        let digest = format!("sha256:{:x}", Sha256::digest(blob));
+        store.import_blob(&digest, &mut &blob[..]).expect("import");
+        assert_eq!(store.read_blob(&digest).expect("read"), blob);
+
+        let other = format!("sha256:{}", "0".repeat(64));
+        assert!(matches!(
+            store.import_blob(&other, &mut &blob[..]),
+            Err(ImageServiceError::DigestMismatch { .. })
+        ));
+        assert!(!store.has_blob(&other).expect("has blob"));
+    }
+
+    #[test]
+    fn add_blob_stores_blobs_by_their_digest() {
+        let (store, _root) = scratch_store();
+        let _ = store.open().expect("open");
+
+        let blob = b"layer.tar";
+        let (digest, size) = store.add_blob(&mut &blob[..]).expect("add");
+        assert_eq!(digest, format!("sha256:{:x}", Sha256::digest(blob)));
+        assert_eq!(size, blob.len() as u64);
+        assert_eq!(store.read_blob(&digest).expect("read"), blob);
+
+        // Adding a stored blob again leaves no partial file behind
+        let _ = store.add_blob(&mut &blob[..]).expect("add again");
+        assert_eq!(
+            fs::read_dir(store.root().join("blobs").join("sha256"))
+                .expect("read dir")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn remove_keeps_blobs_used_by_other_images() {
+        let (store, _root) = scratch_store();
+        let _ = store.open().expect("open");
+
+        let shared = b"shared layer";
+        let own = b"own layer";
+        let config = b"config";
+        let digest = |blob: &[u8]| format!("sha256:{:x}", Sha256::digest(blob));
+        for blob in [&shared[..], &own[..], &config[..]] {
+            store.import_blob(&digest(blob), &mut &blob[..]).expect("import");
+        }
+
+        let busybox = StoredImage {
+            reference: "docker.io/library/busybox:latest".into(),
+            digest: format!("sha256:{}", "a".repeat(64)),
+            manifest: OciImageManifest {
+                config: descriptor(&digest(config), 6),
+                layers: vec![
+                    descriptor(&digest(shared), 12),
+                    descriptor(&digest(own), 9),
+                ],
+                ..Default::default()
+            },
+        };
+        let nginx = StoredImage {
+            reference: "docker.io/library/nginx:latest".into(),
+            digest: format!("sha256:{}", "b".repeat(64)),
+            manifest: OciImageManifest {
+                config: descriptor(&digest(config), 6),
+                layers: vec![descriptor(&digest(shared), 12)],
+                ..Default::default()
+            },
+        };
+        store.save(&busybox).expect("save busybox");
+        store.save(&nginx).expect("save nginx");
+
+        assert!(store.remove(&busybox.reference).expect("remove"));
+        assert!(!store.remove(&busybox.reference).expect("remove again"));
+        assert!(store.has_blob(&digest(shared)).expect("has blob"));
+        assert!(store.has_blob(&digest(config)).expect("has blob"));
+        assert!(!store.has_blob(&digest(own)).expect("has blob"));
+
+        let images = store.list().expect("list");
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].reference, nginx.reference);
+    }
+
+    #[test]
+    fn remove_keeps_layers_referenced_by_snapshots() {
+        let (store, _root) = scratch_store();
+        let _ = store.open().expect("open");
+
+        let layer = b"layer";
+        let digest = format!("sha256:{:x}", Sha256::digest(layer));
+        store.import_blob(&digest, &mut &layer[..]).expect("import");
+        let image = StoredImage {
+            reference: "docker.io/library/busybox:latest".into(),
+            digest: format!("sha256:{}", "a".repeat(64)),
+            manifest: OciImageManifest {
+                layers: vec![descriptor(&digest, 5)],
+                ..Default::default()
+            },
+        };
+        store.save(&image).expect("save");
+
+        let snapshot = store.snapshots_path().join("container");
+        fs::create_dir(&snapshot).expect("create snapshot");
+        fs::write(snapshot.join("layers.json"), format!("[\"{digest}\"]"))
+            .expect("write snapshot layers");
+        assert_eq!(store.references().expect("references")[&digest], 2);
+
+        assert!(store.remove(&image.reference).expect("remove"));
+        assert!(store.has_blob(&digest).expect("has blob"));
+        assert_eq!(store.references().expect("references")[&digest], 1);
+    }
+
+    #[test]
+    fn blob_path_rejects_unsupported_digests() {
+        let (store, _root) = scratch_store();
+        for digest in ["sha512:abcd", "sha256:../../etc", "sha256:"] {
+            assert!(matches!(
+                store.blob_path(digest),
+                Err(ImageServiceError::UnsupportedDigest { .. })
+            ));
+        }
+    }
+}
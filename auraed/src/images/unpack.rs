@@ -0,0 +1,249 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::{
+    distribution::manifest::OciDescriptor,
+    error::{ImageServiceError, Result},
+    pull::PullLimits,
+    store::ImageStore,
+};
+use flate2::read::GzDecoder;
+use std::{
+    ffi::CString,
+    fs::{self, File},
+    io::Read,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+};
+use tracing::debug;
+
+/// How a layer blob is compressed, derived from its media type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn of(media_type: &str) -> Option<Self> {
+        // OCI: application/vnd.oci.image.layer.v1.tar[+gzip|+zstd]
+        // Docker: application/vnd.docker.image.rootfs.diff.tar[.gzip]
+        if media_type.ends_with("+gzip") || media_type.ends_with(".gzip") {
+            Some(Self::Gzip)
+        } else if media_type.ends_with("+zstd") {
+            Some(Self::Zstd)
+        } else if media_type.ends_with(".tar") {
+            Some(Self::None)
+        } else {
+            None
+        }
+    }
+}
+
+/// Whether layers of `media_type` are tar archives that can be unpacked.
+pub(crate) fn is_archive(media_type: &str) -> bool {
+    Compression::of(media_type).is_some()
+}
+
+/// Unpack the stored blob of `layer` into its own directory in the store
+/// once an unpack slot is free, unless it was unpacked before.
+///
+/// Layers are unpacked independently of each other. Whiteouts are converted
+/// to the whiteouts of overlayfs, which stacks the layers into snapshots.
+pub(crate) async fn unpack<'a>(
+    store: &ImageStore,
+    limits: &PullLimits,
+    layer: &'a OciDescriptor,
+) -> Result<&'a OciDescriptor> {
+    let dest = store.layer_path(&layer.digest)?;
+    if dest.exists() {
+        return Ok(layer);
+    }
+
+    let compression = Compression::of(&layer.media_type).ok_or_else(|| {
+        ImageServiceError::UnsupportedMediaType {
+            digest: layer.digest.clone(),
+            media_type: layer.media_type.clone(),
+        }
+    })?;
+    let blob = store.blob_path(&layer.digest)?;
+
+    let _permit =
+        limits.unpacks.acquire().await.expect("semaphore is never closed");
+    let digest = layer.digest.clone();
+    tokio::task::spawn_blocking(move || {
+        debug!("Unpacking layer {digest}");
+        unpack_layer(&blob, &dest, compression).map_err(|source| {
+            ImageServiceError::FailedToUnpack { digest, source }
+        })
+    })
+    .await
+    .expect("unpack task panicked")?;
+
+    Ok(layer)
+}
+
+/// Unpack into a partial directory first, so an interrupted unpack is never
+/// mistaken for an unpacked layer.
+fn unpack_layer(
+    blob: &Path,
+    dest: &Path,
+    compression: Compression,
+) -> std::io::Result<()> {
+    let partial =
+        dest.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&partial)?;
+
+    let file = File::open(blob)?;
+    let reader: Box<dyn Read> = match compression {
+        Compression::None => Box::new(file),
+        Compression::Gzip => Box::new(GzDecoder::new(file)),
+        Compression::Zstd => Box::new(zstd::Decoder::new(file)?),
+    };
+
+    let mut archive = tar::Archive::new(reader);
+    archive.set_preserve_permissions(true);
+    archive.set_preserve_mtime(true);
+    archive.set_unpack_xattrs(true);
+    let res = archive.unpack(&partial).and_then(|_| {
+        convert_whiteouts(&partial)?;
+        match fs::rename(&partial, dest) {
+            // Another pull unpacked the same layer in the meantime
+            Err(_) if dest.exists() => fs::remove_dir_all(&partial),
+            res => res,
+        }
+    });
+    if res.is_err() {
+        let _ = fs::remove_dir_all(&partial);
+    }
+    res
+}
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+
+/// OCI layers mark removed files with an empty `.wh.<name>` file and
+/// directories replacing the directory of a lower layer with an empty
+/// `.wh..wh..opq` file. overlayfs marks them with a 0/0 character device and
+/// the `trusted.overlay.opaque` attribute respectively.
+fn convert_whiteouts(dir: &Path) -> std::io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if name == OPAQUE_WHITEOUT {
+            fs::remove_file(&path)?;
+            set_opaque(dir)?;
+        } else if let Some(removed) = name.strip_prefix(WHITEOUT_PREFIX) {
+            fs::remove_file(&path)?;
+            whiteout(&dir.join(removed))?;
+        } else if entry.file_type()?.is_dir() {
+            convert_whiteouts(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn whiteout(path: &Path) -> std::io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: path is a valid nul terminated string
+    if unsafe { libc::mknod(path.as_ptr(), libc::S_IFCHR, 0) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn set_opaque(dir: &Path) -> std::io::Result<()> {
+    let path = CString::new(dir.as_os_str().as_bytes())?;
+    let name = c"trusted.overlay.opaque";
+    // SAFETY: path and name are valid nul terminated strings, the value is
+    // one byte long
+    let res = unsafe {
+        libc::setxattr(path.as_ptr(), name.as_ptr(), b"y".as_ptr().cast(), 1, 0)
+    };
+    if res != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::images::pull::ImagePullConfig;
+    use sha2::{Digest, Sha256};
+    use std::io::Write;
+
+    #[test]
+    fn compression_follows_the_media_type() {
+        for (media_type, compression) in [
+            ("application/vnd.oci.image.layer.v1.tar", Compression::None),
+            ("application/vnd.oci.image.layer.v1.tar+gzip", Compression::Gzip),
+            ("application/vnd.oci.image.layer.v1.tar+zstd", Compression::Zstd),
+            (
+                "application/vnd.docker.image.rootfs.diff.tar.gzip",
+                Compression::Gzip,
+            ),
+        ] {
+            assert_eq!(Compression::of(media_type), Some(compression));
+        }
+        assert_eq!(Compression::of("application/octet-stream"), None);
+    }
+
+    #[tokio::test]
+    async fn unpacks_gzip_layers() {
+        let root = tempfile::tempdir().expect("scratch dir");
+        let store = ImageStore::new(root.path());
+        let _ = store.open().expect("open");
+
+        let mut builder = tar::Builder::new(vec![]);
+        let contents = b"hello from a layer";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "etc/motd", &contents[..])
+            .expect("append");
+        let tar = builder.into_inner().expect("tar");
+
+        let mut encoder = flate2::write::GzEncoder::new(
+            vec![],
+            flate2::Compression::default(),
+        );
+        encoder.write_all(&tar).expect("compress");
+        let blob = encoder.finish().expect("compress");
+
+        let layer = OciDescriptor {
+            media_type: "application/vnd.oci.image.layer.v1.tar+gzip".into(),
+            digest: format!("sha256:{:x}", Sha256::digest(&blob)),
+            size: blob.len() as i64,
+            ..Default::default()
+        };
+        fs::write(store.blob_path(&layer.digest).expect("blob path"), &blob)
+            .expect("write blob");
+
+        let limits = PullLimits::new(&ImagePullConfig::default());
+        let _ = unpack(&store, &limits, &layer).await.expect("unpack");
+
+        let dest = store.layer_path(&layer.digest).expect("layer path");
+        assert_eq!(
+            fs::read(dest.join("etc/motd")).expect("read unpacked file"),
+            contents
+        );
+    }
+}
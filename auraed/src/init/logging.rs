@@ -12,17 +12,19 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
+use crate::logging::{
+    log_filter::DAEMON_LOG_FILTER, log_format::LogFormat,
+    stream_logger::StreamLogger,
+};
+use crate::AURAED_RUNTIME;
 use std::ffi::CStr;
-use tracing::{info, Level};
+use tracing::{info, Level, Subscriber};
 use tracing_subscriber::{
-    layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+    layer::SubscriberExt, registry::LookupSpan, util::SubscriberInitExt, Layer,
 };
 
 #[derive(thiserror::Error, Debug)]
 pub(crate) enum LoggingError {
-    #[error("Failed to setup basic tracing: {source:?}")]
-    SetupFailure { source: Box<dyn std::error::Error> },
-
     #[error(transparent)]
     IOError(#[from] std::io::Error),
 
@@ -41,35 +43,42 @@ pub(crate) fn init(verbose: bool, container: bool) -> Result<(), LoggingError> {
     //
     // Normal mode: Info, Warn, Error
     // Verbose mode: Debug, Trace, Info, Warn, Error
+    //
+    // Either level can be changed at runtime through the observe API,
+    // per module as well.
     let tracing_level = if verbose { Level::TRACE } else { Level::INFO };
+    DAEMON_LOG_FILTER.init(format!("auraed={tracing_level}"));
+
+    let log_format = AURAED_RUNTIME
+        .get()
+        .map(|runtime| runtime.log_format)
+        .unwrap_or_default();
 
     if container {
-        init_container_logging(tracing_level)
+        init_container_logging(log_format)
     } else {
         match std::process::id() {
-            1 => init_pid1_logging(tracing_level),
-            _ => init_daemon_logging(tracing_level),
+            1 => init_pid1_logging(log_format),
+            _ => init_daemon_logging(log_format),
         }
     }
 }
 
-fn init_container_logging(tracing_level: Level) -> Result<(), LoggingError> {
+fn init_container_logging(log_format: LogFormat) -> Result<(), LoggingError> {
     info!("initializing container logging");
 
     // Stdout
-    let stdout_layer = Layer::with_filter(
-        tracing_subscriber::fmt::layer().compact(),
-        EnvFilter::new(format!("auraed={tracing_level}")),
-    );
+    let stdout_layer = stdout_layer(log_format);
 
     tracing_subscriber::registry()
         .with(stdout_layer)
+        .with(stream_layer())
         .try_init()
         .map_err(|e| e.into())
 }
 
 /// when we run as a daemon we want to log to stdout and syslog.
-fn init_daemon_logging(tracing_level: Level) -> Result<(), LoggingError> {
+fn init_daemon_logging(log_format: LogFormat) -> Result<(), LoggingError> {
     info!("initializing syslog logging");
 
     // Syslog
@@ -88,14 +97,12 @@ fn init_daemon_logging(tracing_level: Level) -> Result<(), LoggingError> {
     let syslog_layer = tracing_subscriber::fmt::layer().with_writer(syslog);
 
     // Stdout
-    let stdout_layer = Layer::with_filter(
-        tracing_subscriber::fmt::layer().compact(),
-        EnvFilter::new(format!("auraed={tracing_level}")),
-    );
+    let stdout_layer = stdout_layer(log_format);
 
     tracing_subscriber::registry()
         .with(syslog_layer)
         .with(stdout_layer)
+        .with(stream_layer())
         .try_init()
         .map_err(|e| e.into())
 }
@@ -111,11 +118,35 @@ fn init_stdout_logging(tracing_level: Level) -> Result<(), LoggingError> {
         .map_err(|e| e.into())
 }
 
-fn init_pid1_logging(tracing_level: Level) -> Result<(), LoggingError> {
+fn init_pid1_logging(log_format: LogFormat) -> Result<(), LoggingError> {
     info!("initializing pid1 logging");
-    tracing_subscriber::fmt()
-        .compact()
-        .with_env_filter(format!("auraed={tracing_level}"))
+
+    // Stdout
+    let stdout_layer = stdout_layer(log_format);
+
+    tracing_subscriber::registry()
+        .with(stdout_layer)
+        .with(stream_layer())
         .try_init()
-        .map_err(|e| LoggingError::SetupFailure { source: e })
+        .map_err(|e| e.into())
+}
+
+/// Writes the logs of auraed to stdout in `log_format`
+fn stdout_layer<S>(log_format: LogFormat) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span> + 'static,
+{
+    let layer: Box<dyn Layer<S> + Send + Sync> = match log_format {
+        LogFormat::Text => Box::new(tracing_subscriber::fmt::layer().compact()),
+        LogFormat::Json => Box::new(tracing_subscriber::fmt::layer().json()),
+    };
+    Layer::with_filter(layer, DAEMON_LOG_FILTER.layer_filter())
+}
+
+/// Sends the logs of auraed to the observe API
+fn stream_layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    Layer::with_filter(StreamLogger, DAEMON_LOG_FILTER.layer_filter())
 }
\ No newline at end of file
@@ -162,6 +162,14 @@ impl SystemRuntime for Pid1SystemRuntime {
         let socket_addr = socket_address
             .unwrap_or_else(|| DEFAULT_NETWORK_SOCKET_ADDR.into())
             .parse::<SocketAddr>()?;
+
+        // The host reaches the API of an auraed booted in a VM over vsock
+        if Path::new("/dev/vsock").exists() {
+            if let Err(e) = crate::vms::serve_agent(socket_addr.port()) {
+                error!("Failed to serve the agent port to the host: {e}");
+            }
+        }
+
         create_tcp_socket_stream(socket_addr).await
     }
 }
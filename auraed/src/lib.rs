@@ -64,27 +64,56 @@
 #![warn(clippy::unwrap_used)]
 
 pub use crate::auraed_path::AuraedPath;
+pub use crate::cells::UtilizationConfig;
+pub use crate::cri::cni::CniConfig;
+pub use crate::cri::container_log::ContainerLogConfig;
+pub use crate::cri::vm_pod::PodVmConfig;
 use crate::ebpf::{
-    BpfContext, SchedProcessForkTracepointProgram,
-    SignalSignalGenerateTracepointProgram, TaskstatsExitKProbeProgram,
+    BpfContext, EbpfSupport, NetworkAccounting, OomKillProcessKProbeProgram,
+    SchedProcessExecTracepointProgram, SchedProcessForkTracepointProgram,
+    SignalSignalGenerateTracepointProgram,
+    SockInetSockSetStateTracepointProgram, TaskstatsExitKProbeProgram,
 };
+pub use crate::ebpf::{EbpfConfig, EbpfConfigError, EbpfProbes, UnknownProbe};
+pub use crate::grpc_limits::GrpcLimits;
+pub use crate::images::ImagePullConfig;
+pub use crate::logging::log_format::{InvalidLogFormat, LogFormat};
+pub use crate::logging::log_journal::LogJournalConfig;
+pub use crate::logging::syslog_sink::{InvalidSyslogEndpoint, SyslogEndpoint};
+pub use crate::network::IpamConfig;
+pub use crate::preflight::{Check, Preflight};
+pub use crate::spawn::Arch;
+pub use crate::subsystems::{SubsystemsConfig, UnknownSubsystem};
+pub use crate::tokio_config::TokioConfig;
+pub use crate::vms::JailerConfig;
 use crate::{
-    cells::CellService, cri::oci::AuraeOCIBuilder,
-    cri::runtime_service::RuntimeService, discovery::DiscoveryService,
+    cells::CellService, cordon::Cordon,
+    cri::image_service::ImageService as CriImageService,
+    cri::oci::AuraeOCIBuilder, cri::runtime_service::RuntimeService,
+    cri::vm_pod::PodVms, discovery::DiscoveryService,
+    grpc_limits::GrpcLimitsLayer, images::ImageService, images::RegistryFiles,
     init::Context as AuraeContext, init::SocketStream,
-    logging::log_channel::LogChannel, observe::ObserveService,
-    spawn::spawn_auraed_oci_to,
+    log_export::LogExporters, logging::stream_logger::DAEMON_LOG_CHANNEL,
+    logging::syslog_sink::SyslogSink, network::Ipam, network::NetworkService,
+    observe::core_dumps, observe::ObserveService, spawn::spawn_auraed_oci_to,
+    webhooks::Webhooks,
 };
 use anyhow::{anyhow, Context};
-use aurae_ebpf_shared::{ForkedProcess, ProcessExit, Signal};
+use aurae_ebpf_shared::{
+    ForkedProcess, OomKill, ProcessExec, ProcessExit, Signal, TcpConnect,
+};
 use once_cell::sync::OnceCell;
 use proto::{
     cells::cell_service_server::CellServiceServer,
+    cri::image_service_server::ImageServiceServer as CriImageServiceServer,
     cri::runtime_service_server::RuntimeServiceServer,
     discovery::discovery_service_server::DiscoveryServiceServer,
-    observe::observe_service_server::ObserveServiceServer,
+    images::image_service_server::ImageServiceServer,
+    network::network_service_server::NetworkServiceServer,
+    observe::observe_service_server::ObserveServiceServer, observe::LogFilter,
     vms::vm_service_server::VmServiceServer,
 };
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::io::AsyncRead;
@@ -92,20 +121,34 @@ use tokio::io::AsyncWrite;
 use tokio::task::JoinHandle;
 use tonic::transport::server::Connected;
 use tonic::transport::{Certificate, Identity, Server, ServerTlsConfig};
+use tonic_health::ServingStatus;
 use tracing::{error, info, trace, warn};
 use vms::VmService;
 
 mod auraed_path;
 mod cells;
+mod cordon;
 mod cri;
 mod discovery;
 mod ebpf;
 mod graceful_shutdown;
+mod grpc_limits;
+mod images;
 mod init;
+mod log_export;
 mod logging;
+mod network;
 mod observe;
+mod preflight;
+mod process_start;
+mod readiness;
+mod resumable;
+mod snapshots;
 mod spawn;
+mod subsystems;
+mod tokio_config;
 mod vms;
+mod webhooks;
 
 static AURAED_RUNTIME: OnceCell<AuraedRuntime> = OnceCell::new();
 
@@ -129,6 +172,53 @@ pub struct AuraedRuntime {
     pub runtime_dir: PathBuf,
     /// Configurable library directory. Defaults to /var/lib/aurae.
     pub library_dir: PathBuf,
+    /// Concurrency and bandwidth limits shared by all image pulls.
+    pub image_pull: ImagePullConfig,
+    /// Thread topology of the tokio runtimes.
+    pub tokio: TokioConfig,
+    /// Confinement of the VMM threads of each VM.
+    pub jailer: JailerConfig,
+    /// Limits on the messages of the gRPC services.
+    pub grpc_limits: GrpcLimits,
+    /// Smoothing of the utilization reported by the cell service.
+    pub utilization: UtilizationConfig,
+    /// Where the CNI networks of pod sandboxes are configured.
+    pub cni: CniConfig,
+    /// Address the sessions of CRI Exec calls are served on, on an ephemeral
+    /// port if it is 0. Defaults to 127.0.0.1:0.
+    pub cri_streaming_address: SocketAddr,
+    /// Rotation of the CRI logs of containers.
+    pub container_logs: ContainerLogConfig,
+    /// How much the journals of the log channels streamed by the observe
+    /// service keep.
+    pub log_journal: LogJournalConfig,
+    /// Endpoint the logs of auraed and the output of its workloads are
+    /// forwarded to. Not forwarded by default.
+    pub syslog: Option<SyslogEndpoint>,
+    /// Format of the logs auraed writes to stdout. Defaults to text.
+    pub log_format: LogFormat,
+    /// Exporters shipping the logs of auraed and its workloads to Loki or
+    /// fluent-forward endpoints. Defaults to /etc/aurae/log-exporters.json,
+    /// which may not exist.
+    pub log_exporters: PathBuf,
+    /// Subsystems started by auraed.
+    pub subsystems: SubsystemsConfig,
+    /// Which eBPF probes auraed loads and which events they report, as
+    /// combined with the eBPF config file.
+    pub ebpf: EbpfConfig,
+    /// The eBPF config file. Defaults to /etc/aurae/ebpf.json, which may not
+    /// exist.
+    pub ebpf_config: PathBuf,
+    /// Address pools of the cells and pods auraed attaches to the host.
+    pub ipam: IpamConfig,
+    /// Webhooks notified about workload events. Defaults to
+    /// /etc/aurae/webhooks.json, which may not exist.
+    pub webhooks: PathBuf,
+    /// The VMs pods run with the aurae-vm runtime handler are booted as.
+    pub pod_vm: PodVmConfig,
+    /// OCI lifecycle hooks run around pod sandboxes. Defaults to
+    /// /etc/aurae/oci-hooks.json, which may not exist.
+    pub oci_hooks: PathBuf,
     // /// Provides logging channels to expose auraed logging via grpc
     //pub log_collector: Arc<LogChannel>,
 }
@@ -138,10 +228,54 @@ impl AuraedRuntime {
         self.runtime_dir.join("bundles")
     }
 
+    pub(crate) fn cells_dir(&self) -> PathBuf {
+        self.runtime_dir.join("cells")
+    }
+
     pub(crate) fn pods_dir(&self) -> PathBuf {
         self.runtime_dir.join("pods")
     }
 
+    pub(crate) fn volumes_dir(&self) -> PathBuf {
+        self.runtime_dir.join("volumes")
+    }
+
+    pub(crate) fn images_dir(&self) -> PathBuf {
+        self.library_dir.join("images")
+    }
+
+    pub(crate) fn network_dir(&self) -> PathBuf {
+        self.runtime_dir.join("network")
+    }
+
+    pub(crate) fn snapshots_dir(&self) -> PathBuf {
+        self.runtime_dir.join("snapshots")
+    }
+
+    pub(crate) fn vms_dir(&self) -> PathBuf {
+        self.runtime_dir.join("vms")
+    }
+
+    pub(crate) fn pod_vms_dir(&self) -> PathBuf {
+        self.library_dir.join("pod-vms")
+    }
+
+    pub(crate) fn checkpoints_dir(&self) -> PathBuf {
+        self.library_dir.join("checkpoints")
+    }
+
+    pub(crate) fn cores_dir(&self) -> PathBuf {
+        self.runtime_dir.join("cores")
+    }
+
+    pub(crate) fn log_journals_dir(&self) -> PathBuf {
+        self.runtime_dir.join("logs")
+    }
+
+    pub(crate) fn cordon_file(&self) -> PathBuf {
+        self.runtime_dir.join("cordon")
+    }
+
     pub(crate) fn default_socket_address(&self) -> PathBuf {
         self.runtime_dir.join("aurae.sock")
     }
@@ -157,6 +291,25 @@ impl Default for AuraedRuntime {
             server_key: PathBuf::from("/etc/aurae/pki/server.key"),
             runtime_dir: PathBuf::from("/var/run/aurae"),
             library_dir: PathBuf::from("/var/lib/aurae"),
+            image_pull: ImagePullConfig::default(),
+            tokio: TokioConfig::default(),
+            jailer: JailerConfig::default(),
+            grpc_limits: GrpcLimits::default(),
+            utilization: UtilizationConfig::default(),
+            cni: CniConfig::default(),
+            cri_streaming_address: SocketAddr::from(([127, 0, 0, 1], 0)),
+            container_logs: ContainerLogConfig::default(),
+            log_journal: LogJournalConfig::default(),
+            syslog: None,
+            log_format: LogFormat::default(),
+            log_exporters: PathBuf::from("/etc/aurae/log-exporters.json"),
+            subsystems: SubsystemsConfig::default(),
+            ebpf: EbpfConfig::default(),
+            ebpf_config: PathBuf::from("/etc/aurae/ebpf.json"),
+            ipam: IpamConfig::default(),
+            webhooks: PathBuf::from("/etc/aurae/webhooks.json"),
+            pod_vm: PodVmConfig::default(),
+            oci_hooks: PathBuf::from("/etc/aurae/oci-hooks.json"),
         }
     }
 }
@@ -180,6 +333,25 @@ pub async fn run(
     {
         trace!("{:#?}", runtime);
 
+        // Subsystems whose checks fail are not started, or stay NOT_SERVING
+        let subsystems = runtime.subsystems;
+        let preflight = Preflight::run(runtime);
+        let disabled = |check: &Check| match check.name {
+            preflight::BPF => !subsystems.ebpf,
+            preflight::KVM | preflight::VSOCK | preflight::SECCOMP => {
+                !subsystems.vms
+            }
+            _ => false,
+        };
+        for check in
+            preflight.checks.iter().filter(|c| !c.passed && !disabled(c))
+        {
+            warn!(
+                "Pre-flight check {} failed, {} unavailable: {}",
+                check.name, check.required_by, check.detail
+            );
+        }
+
         let runtime_dir = Path::new(&runtime.runtime_dir);
         // Create runtime directory
         tokio::fs::create_dir_all(runtime_dir).await.with_context(|| {
@@ -190,7 +362,8 @@ pub async fn run(
         })?;
 
         // We don't want TLS in cell context
-        let mut server = if context != AuraeContext::Cell {
+        let server = if context != AuraeContext::Cell {
+            preflight.require(&[preflight::PKI])?;
             let server_crt =
                 tokio::fs::read(&runtime.server_crt).await.with_context(|| {
                     format!(
@@ -222,69 +395,438 @@ pub async fn run(
         } else {
             Server::builder()
         };
-
-        // Install eBPF probes in the host Aurae daemon
-        let (_bpf_handle, perf_events) = if context == AuraeContext::Cell
-            || context == AuraeContext::Container
-        {
-            (None, (None, None, None))
-        } else {
-            // TODO: Add flags/options to "opt-out" of the various BPF probes
-            info!("Loading eBPF probes");
-
-            let mut bpf_handle = BpfContext::new();
-            let perf_events = (
-                bpf_handle.load_and_attach_tracepoint_program::<SchedProcessForkTracepointProgram, ForkedProcess>().ok(),
-                bpf_handle.load_and_attach_kprobe_program::<TaskstatsExitKProbeProgram, ProcessExit>().ok(),
-                bpf_handle.load_and_attach_tracepoint_program::<SignalSignalGenerateTracepointProgram, Signal>().ok(),
-            );
-
-            (Some(bpf_handle), perf_events)
-        };
-
-        // Build gRPC Services
+        let cordon = Cordon::open(runtime.cordon_file())
+            .context("Failed to read the cordon of the node")?;
+        let grpc_limits = GrpcLimitsLayer::new(runtime.grpc_limits.clone())
+            .context("Failed to load the schemas of the gRPC services")?;
+        let max_decoding = runtime.grpc_limits.max_decoding_message_size;
+        let max_encoding = runtime.grpc_limits.max_encoding_message_size;
+
+        // Build gRPC Services. None of them is SERVING until the subsystems
+        // it depends on are ready, see the readiness module.
         let (mut health_reporter, health_service) =
             tonic_health::server::health_reporter();
+        health_reporter.set_service_status("", ServingStatus::NotServing).await;
+        health_reporter
+            .set_not_serving::<CellServiceServer<CellService>>()
+            .await;
+        health_reporter
+            .set_not_serving::<DiscoveryServiceServer<DiscoveryService>>()
+            .await;
+        health_reporter
+            .set_not_serving::<ObserveServiceServer<ObserveService>>()
+            .await;
+        health_reporter
+            .set_not_serving::<ImageServiceServer<ImageService>>()
+            .await;
+        health_reporter
+            .set_not_serving::<NetworkServiceServer<NetworkService>>()
+            .await;
+        health_reporter
+            .set_not_serving::<RuntimeServiceServer<RuntimeService>>()
+            .await;
+        health_reporter
+            .set_not_serving::<CriImageServiceServer<CriImageService>>()
+            .await;
+        health_reporter.set_not_serving::<VmServiceServer<VmService>>().await;
 
-        let observe_service = ObserveService::new(
-            Arc::new(LogChannel::new(String::from("auraed"))),
+        // Install eBPF probes in the host Aurae daemon
+        let mut cpu_profiling = false;
+        let (_bpf_handle, perf_events, network_accounting, cgroup_filter) =
+            if context == AuraeContext::Cell
+                || context == AuraeContext::Container
+            {
+                info!("Skipping eBPF probes, the host auraed installs them");
+                (None, (None, None, None, None, None, None), None, None)
+            } else if !subsystems.ebpf {
+                info!("Skipping eBPF probes, they are disabled");
+                (None, (None, None, None, None, None, None), None, None)
+            } else if let Err(e) = preflight.require(&[preflight::BPF]) {
+                info!("Skipping eBPF probes: {e}");
+                (None, (None, None, None, None, None, None), None, None)
+            } else {
+                let ebpf = match EbpfConfig::load(&runtime.ebpf_config) {
+                    Ok(file) => runtime.ebpf.clone().combine(file),
+                    Err(e) => {
+                        error!("Ignoring the eBPF config file: {e}");
+                        runtime.ebpf.clone()
+                    }
+                };
+                let probes = ebpf.probes;
+                info!("Loading eBPF probes");
+
+                // A probe that is disabled or fails to attach is skipped, the
+                // events it would observe are not available to ObserveService
+                fn attached<T>(
+                    probe: &str,
+                    enabled: bool,
+                    load: impl FnOnce() -> Result<T, anyhow::Error>,
+                ) -> Option<T> {
+                    if !enabled {
+                        info!("Skipping eBPF probe {probe}, it is disabled");
+                        return None;
+                    }
+                    load()
+                        .map_err(|e| warn!("Skipping eBPF probe {probe}: {e}"))
+                        .ok()
+                }
+
+                let mut bpf_handle = BpfContext::new();
+                let perf_events = (
+                    attached("sched_process_fork", probes.forks, || {
+                        bpf_handle.load_and_attach_tracepoint_program::<SchedProcessForkTracepointProgram, ForkedProcess>()
+                    }),
+                    attached("taskstats_exit", probes.exits, || {
+                        bpf_handle.load_and_attach_kprobe_program::<TaskstatsExitKProbeProgram, ProcessExit>()
+                    }),
+                    attached("signal_generate", probes.signals, || {
+                        bpf_handle.load_and_attach_tracepoint_program::<SignalSignalGenerateTracepointProgram, Signal>()
+                    }),
+                    attached("sched_process_exec", probes.execs, || {
+                        bpf_handle.load_and_attach_tracepoint_program::<SchedProcessExecTracepointProgram, ProcessExec>()
+                    }),
+                    attached(
+                        "inet_sock_set_state",
+                        probes.tcp_connects,
+                        || {
+                            bpf_handle.load_and_attach_tracepoint_program::<SockInetSockSetStateTracepointProgram, TcpConnect>()
+                        },
+                    ),
+                    attached("oom_kill_process", probes.oom_kills, || {
+                        bpf_handle.load_and_attach_kprobe_program::<OomKillProcessKProbeProgram, OomKill>()
+                    }),
+                );
+
+                let network_accounting = attached(
+                    "cgroup_skb",
+                    probes.network,
+                    NetworkAccounting::load,
+                );
+
+                // Loaded for each profile rather than attached here
+                cpu_profiling = probes.cpu_profiles;
+                if !cpu_profiling {
+                    info!("Skipping eBPF probe perf_event, it is disabled");
+                }
+
+                // Without the filter, the probes report the events of the whole
+                // host
+                let cgroup_filter =
+                    attached("cgroup_filter", ebpf.cells_only, || {
+                        let cgroup_filter = bpf_handle.cgroup_filter()?;
+                        cgroup_filter.enable()?;
+                        Ok(cgroup_filter)
+                    });
+
+                (
+                    Some(bpf_handle),
+                    perf_events,
+                    network_accounting,
+                    cgroup_filter,
+                )
+            };
+
+        let mut observe_service = ObserveService::new(
+            Arc::new(DAEMON_LOG_CHANNEL.clone()),
             perf_events,
         );
+        if let Some(network_accounting) = network_accounting {
+            observe_service =
+                observe_service.with_network_accounting(network_accounting);
+        }
+        if let Some(cgroup_filter) = cgroup_filter {
+            observe_service = observe_service.with_cgroup_filter(cgroup_filter);
+        }
+        if cpu_profiling {
+            observe_service = observe_service.with_cpu_profiling();
+        }
+        observe_service.publish_cell_metrics();
+        observe_service.report_oom_kills();
         let observe_service_server =
-            ObserveServiceServer::new(observe_service.clone());
+            ObserveServiceServer::new(observe_service.clone())
+                .max_decoding_message_size(max_decoding)
+                .max_encoding_message_size(max_encoding);
+
+        // Core dumps are configured host wide, leave them to the host's init
+        // unless we are the init
+        if context == AuraeContext::Pid1 {
+            let installed = PathBuf::try_from(runtime.auraed.clone())
+                .map_err(anyhow::Error::from)
+                .and_then(|auraed| {
+                    observe_service
+                        .listen_for_core_dumps(&runtime.cores_dir())?;
+                    Ok(core_dumps::install(&auraed, &runtime.runtime_dir)?)
+                });
+            if let Err(e) = installed {
+                error!("Failed to set up core dump capture: {e}");
+            }
+        }
 
-        let cell_service = CellService::new(observe_service.clone());
-        let cell_service_server = CellServiceServer::new(cell_service.clone());
-        health_reporter.set_serving::<CellServiceServer<CellService>>().await;
+        // Workloads of nested auraed instances are reported by the host
+        if context != AuraeContext::Cell && context != AuraeContext::Container {
+            match Webhooks::load(&runtime.webhooks) {
+                Ok(webhooks) => webhooks.notify(&observe_service),
+                Err(e) => error!("Failed to set up webhooks: {e}"),
+            }
+        }
 
-        let discovery_service = DiscoveryService::new();
-        let discovery_service_server =
-            DiscoveryServiceServer::new(discovery_service);
-        health_reporter
-            .set_serving::<DiscoveryServiceServer<DiscoveryService>>()
+        // Forward the logs of auraed and its workloads to syslog
+        if let Some(endpoint) = &runtime.syslog {
+            let sink = SyslogSink::spawn(endpoint.clone());
+            if let Err(e) = observe_service
+                .forward_logs(LogFilter::default(), sink.sender(), |item| item)
+                .await
+            {
+                error!("Failed to forward logs to syslog: {e}");
+            }
+        }
+
+        // Ship the logs of auraed and its workloads to centralized storage
+        match LogExporters::load(&runtime.log_exporters) {
+            Ok(exporters) => exporters.export(&observe_service).await,
+            Err(e) => error!("Failed to set up log exporters: {e}"),
+        }
+
+        // Nested auraed instances do not attach namespaces to the host
+        let ipam = if context != AuraeContext::Cell
+            && context != AuraeContext::Container
+        {
+            let leases = runtime.network_dir().join("leases.json");
+            match Ipam::open(runtime.ipam.clone(), leases).await {
+                Ok(ipam) => Some(ipam),
+                Err(e) => {
+                    error!("Failed to load the address leases: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let cell_service = CellService::new(
+            observe_service.clone(),
+            runtime.cells_dir(),
+            cordon.clone(),
+            &runtime.utilization,
+            ipam.clone(),
+        );
+        // Nested auraed instances do not own the node's top-level cells
+        if context != AuraeContext::Cell && context != AuraeContext::Container {
+            if let Err(e) = cell_service.adopt_left_running().await {
+                error!("Failed to adopt cells left running: {e}");
+            }
+        }
+        cell_service.sample_utilization();
+        // An auraed booted as the init of a VM pushes its events to the host
+        if context == AuraeContext::Pid1 && Path::new("/dev/vsock").exists() {
+            vms::report_to_host(cell_service.clone());
+        }
+        let cell_service_server = CellServiceServer::new(cell_service.clone())
+            .max_decoding_message_size(max_decoding)
+            .max_encoding_message_size(max_encoding);
+        let cells_ready = readiness::report::<CellServiceServer<CellService>>(
+            &mut health_reporter,
+            preflight.require(&[
+                preflight::KERNEL,
+                preflight::CGROUP_V2,
+                preflight::NAMESPACES,
+            ]),
+        )
+        .await;
+
+        let discovery_service_server = if subsystems.discovery {
+            // The kernel is probed for the eBPF programs even if they are
+            // not loaded, e.g. in cells or with the ebpf subsystem disabled
+            let discovery_service = DiscoveryService::new(cordon.clone())
+                .with_ebpf_support(EbpfSupport::probe());
+            health_reporter
+                .set_serving::<DiscoveryServiceServer<DiscoveryService>>()
+                .await;
+            Some(
+                DiscoveryServiceServer::new(discovery_service)
+                    .max_decoding_message_size(max_decoding)
+                    .max_encoding_message_size(max_encoding),
+            )
+        } else {
+            readiness::disabled::<DiscoveryServiceServer<DiscoveryService>>(
+                &mut health_reporter,
+            )
             .await;
+            None
+        };
 
+        // The eBPF probes are either attached or were skipped above
         health_reporter
             .set_serving::<ObserveServiceServer<ObserveService>>()
             .await;
 
+        let image_service =
+            ImageService::new(runtime.images_dir(), &runtime.image_pull);
+        let image_service_server =
+            ImageServiceServer::new(image_service.clone())
+                .max_decoding_message_size(max_decoding)
+                .max_encoding_message_size(max_encoding);
+        let images_ready =
+            readiness::report::<ImageServiceServer<ImageService>>(
+                &mut health_reporter,
+                image_service.ready(),
+            )
+            .await;
+
+        let network_service = NetworkService::new(runtime.network_dir());
+        // Nested auraed instances do not own the node's network rules
+        let network_ready = if context != AuraeContext::Cell
+            && context != AuraeContext::Container
+        {
+            let reconciled = network_service
+                .reconcile()
+                .await
+                .context("failed to reconcile network rules");
+            match &ipam {
+                Some(ipam) if reconciled.is_ok() => {
+                    ipam.provision_network(&network_service).await
+                }
+                _ => reconciled,
+            }
+        } else {
+            Ok(())
+        };
+        let network_service_server =
+            NetworkServiceServer::new(network_service.clone())
+                .max_decoding_message_size(max_decoding)
+                .max_encoding_message_size(max_encoding);
+        let _ = readiness::report::<NetworkServiceServer<NetworkService>>(
+            &mut health_reporter,
+            network_ready,
+        )
+        .await;
+
+        let vm_service = if subsystems.vms {
+            let mut vm_checks = vec![preflight::KVM, preflight::VSOCK];
+            if runtime.jailer.seccomp {
+                vm_checks.push(preflight::SECCOMP);
+            }
+            let vm_service = VmService::new(
+                runtime.snapshots_dir(),
+                runtime.vms_dir(),
+                observe_service.clone(),
+                runtime
+                    .tokio
+                    .vm_runtime()
+                    .context("Failed to build the vm runtime")?,
+                runtime.jailer.clone(),
+                cordon.clone(),
+                RegistryFiles::new(&runtime.image_pull),
+            )
+            .with_node_checkpoints(
+                runtime.checkpoints_dir(),
+                cell_service.clone(),
+                ipam.clone(),
+            );
+            if context != AuraeContext::Cell
+                && context != AuraeContext::Container
+            {
+                if let Err(e) = vm_service.restore_shutdown_checkpoint().await {
+                    error!(
+                        "Failed to restore vms checkpointed on shutdown: {e}"
+                    );
+                }
+                if let Err(e) =
+                    vm_service.provision_network(&network_service).await
+                {
+                    error!("Failed to provision the vm network: {e:#}");
+                }
+            }
+            vm_service.publish_metrics();
+            vm_service.supervise_resets();
+            vm_service.serve_guest_channels();
+            vm_service.capture_consoles();
+            let _ = readiness::report::<VmServiceServer<VmService>>(
+                &mut health_reporter,
+                preflight.require(&vm_checks).and(vm_service.ready().await),
+            )
+            .await;
+            Some(vm_service)
+        } else {
+            readiness::disabled::<VmServiceServer<VmService>>(
+                &mut health_reporter,
+            )
+            .await;
+            None
+        };
         // let pod_service = PodService::new(self.runtime_dir.clone());
         // let pod_service_server = PodServiceServer::new(pod_service.clone());
         // health_reporter.set_serving::<PodServiceServer<PodService>>().await;
-        let runtime_service = RuntimeService::new();
-        let runtime_service_server =
-            RuntimeServiceServer::new(runtime_service.clone());
-        health_reporter
-            .set_serving::<RuntimeServiceServer<RuntimeService>>()
+        let (runtime_service_server, cri_image_service_server) = if subsystems
+            .cri
+        {
+            let runtime_service = RuntimeService::new(
+                cordon.clone(),
+                runtime.cri_streaming_address,
+                ipam.clone(),
+                vm_service.clone().map(|vms| {
+                    PodVms::new(
+                        vms,
+                        runtime.pod_vm.clone(),
+                        runtime.pod_vms_dir(),
+                    )
+                }),
+                image_service.clone(),
+                cell_service.clone(),
+                observe_service.clone(),
+            )
+            .context("Failed to start the CRI streaming server")?;
+            if let Err(e) = runtime_service.reconcile().await {
+                error!("Failed to recover the pods left running: {e:#}");
+            }
+            let runtime_service_server =
+                RuntimeServiceServer::new(runtime_service)
+                    .max_decoding_message_size(max_decoding)
+                    .max_encoding_message_size(max_encoding);
+            health_reporter
+                .set_serving::<RuntimeServiceServer<RuntimeService>>()
+                .await;
+            let cri_image_service_server =
+                CriImageServiceServer::new(CriImageService::new(image_service))
+                    .max_decoding_message_size(max_decoding)
+                    .max_encoding_message_size(max_encoding);
+            if images_ready {
+                health_reporter
+                    .set_serving::<CriImageServiceServer<CriImageService>>()
+                    .await;
+            }
+            (Some(runtime_service_server), Some(cri_image_service_server))
+        } else {
+            readiness::disabled::<RuntimeServiceServer<RuntimeService>>(
+                &mut health_reporter,
+            )
             .await;
+            readiness::disabled::<CriImageServiceServer<CriImageService>>(
+                &mut health_reporter,
+            )
+            .await;
+            (None, None)
+        };
+
+        let vm_service_server = vm_service.clone().map(|vm_service| {
+            VmServiceServer::new(vm_service)
+                .max_decoding_message_size(max_decoding)
+                .max_encoding_message_size(max_encoding)
+        });
 
-        let vm_service = VmService::new();
-        let vm_service_server = VmServiceServer::new(vm_service.clone());
-        health_reporter.set_serving::<VmServiceServer<VmService>>().await;
+        // The overall status follows the services every auraed needs to run
+        // workloads, VMs and network rules are optional
+        if cells_ready {
+            health_reporter
+                .set_service_status("", ServingStatus::Serving)
+                .await;
+        }
 
         let graceful_shutdown = graceful_shutdown::GracefulShutdown::new(
             health_reporter,
             cell_service,
+            vm_service,
+            subsystems,
         );
         let graceful_shutdown_signal = graceful_shutdown.subscribe();
 
@@ -292,13 +834,17 @@ pub async fn run(
         // TODO: pass a known-good path to CellService to store any runtime data.
         let server_handle = tokio::spawn(async move {
             server
+                .layer(grpc_limits)
                 .add_service(health_service)
                 .add_service(cell_service_server)
-                .add_service(discovery_service_server)
+                .add_optional_service(discovery_service_server)
+                .add_service(image_service_server)
+                .add_service(network_service_server)
                 .add_service(observe_service_server)
                 // .add_service(pod_service_server)
-                .add_service(runtime_service_server)
-                .add_service(vm_service_server)
+                .add_optional_service(runtime_service_server)
+                .add_optional_service(cri_image_service_server)
+                .add_optional_service(vm_service_server)
                 .serve_with_incoming_shutdown(socket_stream, async {
                     let mut graceful_shutdown_signal = graceful_shutdown_signal;
                     let _ = graceful_shutdown_signal.changed().await;
@@ -347,11 +893,48 @@ pub async fn run(
     }
 }
 
+/// Store a core dump piped to us by the kernel and notify the running
+/// daemon. This is the entrypoint of the core dump helper registered in
+/// kernel.core_pattern.
+pub fn capture_core_dump(
+    runtime: &AuraedRuntime,
+    pid: i32,
+    signal: i32,
+    timestamp: i64,
+    executable: String,
+) -> Result<(), anyhow::Error> {
+    let _ = core_dumps::capture(
+        &runtime.cores_dir(),
+        pid,
+        signal,
+        timestamp,
+        executable,
+        std::io::stdin().lock(),
+    )?;
+    Ok(())
+}
+
+/// Hold the output pipes of the executables released for an upgrade until
+/// the next auraed takes them over. This is the entrypoint of the keeper
+/// auraed starts when it exits for an upgrade.
+pub fn keep_executable_pipes(socket: &Path) -> Result<(), anyhow::Error> {
+    Ok(cells::keep_executable_pipes(socket)?)
+}
+
 /// Write the container OCI spec to the filesystem in preparation for spawning Auraed using a container runtime.
-pub fn prep_oci_spec_for_spawn(output: &str) {
+///
+/// Bundles for another architecture than the host's package the auraed
+/// binary provided for it in the library directory of `runtime`.
+pub fn prep_oci_spec_for_spawn(
+    runtime: &AuraedRuntime,
+    output: &str,
+    arch: Arch,
+) -> Result<(), anyhow::Error> {
+    let auraed = spawn::auraed_binary(arch, &runtime.library_dir)?;
     spawn_auraed_oci_to(
         PathBuf::from(output),
         AuraeOCIBuilder::new().build().expect("building default oci spec"),
+        arch,
+        &auraed,
     )
-    .expect("spawning");
 }
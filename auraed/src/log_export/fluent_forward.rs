@@ -0,0 +1,259 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Forwards log items to Fluentd or Fluent Bit with the
+//! [forward protocol](https://github.com/fluent/fluentd/wiki/Forward-Protocol-Specification-v1),
+//! one message in forward mode per batch.
+
+use super::{labels, severity_name, ExportError, LogExporter};
+use proto::observe::LogItem;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+pub(super) struct FluentForwardConfig {
+    /// The `host:port` of the forward input
+    address: String,
+    /// The tag of the events
+    #[serde(default = "default_tag")]
+    tag: String,
+    /// Whether to wait for the receiver to acknowledge each batch
+    #[serde(default = "default_require_ack")]
+    require_ack: bool,
+}
+
+fn default_tag() -> String {
+    "aurae".into()
+}
+
+fn default_require_ack() -> bool {
+    true
+}
+
+#[derive(Debug)]
+pub(super) struct FluentForward {
+    config: FluentForwardConfig,
+    /// Reused across batches, dropped after a failure
+    connection: Mutex<Option<TcpStream>>,
+}
+
+impl FluentForward {
+    pub fn new(config: FluentForwardConfig) -> Result<Self, String> {
+        if config.address.is_empty() {
+            return Err("missing address".into());
+        }
+        Ok(Self { config, connection: Mutex::new(None) })
+    }
+
+    async fn send(
+        &self,
+        connection: &mut Option<TcpStream>,
+        message: &[u8],
+        ack: Option<&[u8]>,
+    ) -> std::io::Result<()> {
+        if connection.is_none() {
+            *connection = Some(TcpStream::connect(&self.config.address).await?);
+        }
+        let stream = connection.as_mut().expect("connected");
+        stream.write_all(message).await?;
+
+        if let Some(ack) = ack {
+            let mut response = vec![0; ack.len()];
+            let _ = stream.read_exact(&mut response).await?;
+            if response != ack {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "unexpected acknowledgement",
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl LogExporter for FluentForward {
+    fn name(&self) -> String {
+        format!("fluent-forward {}", self.config.address)
+    }
+
+    async fn export(&self, batch: &[LogItem]) -> Result<(), ExportError> {
+        let chunk = uuid::Uuid::new_v4().simple().to_string();
+        let chunk = self.config.require_ack.then_some(chunk.as_str());
+        let message = forward_message(&self.config.tag, batch, chunk);
+        let ack = chunk.map(ack_message);
+
+        let mut connection = self.connection.lock().await;
+        let result = tokio::time::timeout(
+            REQUEST_TIMEOUT,
+            self.send(&mut connection, &message, ack.as_deref()),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))
+        });
+        if result.is_err() {
+            // The connection may be left mid-message
+            *connection = None;
+        }
+        result.map_err(|e| ExportError::Transient(e.to_string()))
+    }
+}
+
+/// Encodes `[tag, [[time, record], ...], {"chunk": chunk}]`.
+fn forward_message(
+    tag: &str,
+    batch: &[LogItem],
+    chunk: Option<&str>,
+) -> Vec<u8> {
+    let mut buf = Vec::new();
+    msgpack::array(&mut buf, if chunk.is_some() { 3 } else { 2 });
+    msgpack::str(&mut buf, tag);
+    msgpack::array(&mut buf, batch.len());
+    for item in batch {
+        msgpack::array(&mut buf, 2);
+        msgpack::int(&mut buf, item.timestamp);
+
+        let labels: Vec<_> = labels(item).collect();
+        msgpack::map(&mut buf, 3 + labels.len());
+        msgpack::str(&mut buf, "log");
+        msgpack::str(&mut buf, &item.line);
+        msgpack::str(&mut buf, "channel");
+        msgpack::str(&mut buf, &item.channel);
+        msgpack::str(&mut buf, "level");
+        msgpack::str(&mut buf, severity_name(item));
+        for (name, value) in labels {
+            msgpack::str(&mut buf, name);
+            msgpack::str(&mut buf, value);
+        }
+    }
+    if let Some(chunk) = chunk {
+        msgpack::map(&mut buf, 1);
+        msgpack::str(&mut buf, "chunk");
+        msgpack::str(&mut buf, chunk);
+    }
+    buf
+}
+
+/// Encodes the `{"ack": chunk}` a receiver acknowledges a chunk with.
+fn ack_message(chunk: &str) -> Vec<u8> {
+    let mut buf = Vec::new();
+    msgpack::map(&mut buf, 1);
+    msgpack::str(&mut buf, "ack");
+    msgpack::str(&mut buf, chunk);
+    buf
+}
+
+/// The parts of [MessagePack](https://msgpack.org) the forward protocol
+/// needs.
+mod msgpack {
+    pub fn str(buf: &mut Vec<u8>, value: &str) {
+        let len = value.len();
+        if len < 32 {
+            buf.push(0xa0 | len as u8);
+        } else if let Ok(len) = u8::try_from(len) {
+            buf.push(0xd9);
+            buf.push(len);
+        } else if let Ok(len) = u16::try_from(len) {
+            buf.push(0xda);
+            buf.extend_from_slice(&len.to_be_bytes());
+        } else {
+            buf.push(0xdb);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+        buf.extend_from_slice(value.as_bytes());
+    }
+
+    pub fn array(buf: &mut Vec<u8>, len: usize) {
+        header(buf, len, 0x90, 0xdc, 0xdd);
+    }
+
+    pub fn map(buf: &mut Vec<u8>, len: usize) {
+        header(buf, len, 0x80, 0xde, 0xdf);
+    }
+
+    pub fn int(buf: &mut Vec<u8>, value: i64) {
+        if (0..128).contains(&value) {
+            buf.push(value as u8);
+        } else {
+            buf.push(0xd3);
+            buf.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+
+    fn header(buf: &mut Vec<u8>, len: usize, fix: u8, len16: u8, len32: u8) {
+        if len < 16 {
+            buf.push(fix | len as u8);
+        } else if let Ok(len) = u16::try_from(len) {
+            buf.push(len16);
+            buf.extend_from_slice(&len.to_be_bytes());
+        } else {
+            buf.push(len32);
+            buf.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::observe::LogSeverity;
+
+    #[test]
+    fn encodes_forward_messages() {
+        let item = LogItem {
+            channel: "web".into(),
+            line: "hi".into(),
+            timestamp: 1_700_000_000,
+            severity: LogSeverity::Error as i32,
+            executable_name: "web".into(),
+            ..Default::default()
+        };
+
+        let mut expected = vec![0x93, 0xa5];
+        expected.extend_from_slice(b"aurae");
+        expected.extend_from_slice(&[0x91, 0x92, 0xd3]);
+        expected.extend_from_slice(&1_700_000_000i64.to_be_bytes());
+        expected.push(0x84);
+        for s in ["log", "hi", "channel", "web", "level", "error"] {
+            expected.push(0xa0 | s.len() as u8);
+            expected.extend_from_slice(s.as_bytes());
+        }
+        expected.extend_from_slice(b"\xaaexecutable\xa3web");
+        expected.extend_from_slice(b"\x81\xa5chunk\xa2id");
+
+        assert_eq!(forward_message("aurae", &[item], Some("id")), expected);
+        assert_eq!(ack_message("id"), b"\x81\xa3ack\xa2id");
+    }
+
+    #[test]
+    fn encodes_long_values() {
+        let mut buf = Vec::new();
+        msgpack::str(&mut buf, &"a".repeat(40));
+        assert_eq!(buf[..2], [0xd9, 40]);
+        assert_eq!(buf.len(), 42);
+
+        let mut buf = Vec::new();
+        msgpack::array(&mut buf, 300);
+        assert_eq!(buf, [0xdc, 0x01, 0x2c]);
+    }
+}
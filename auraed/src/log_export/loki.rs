@@ -0,0 +1,184 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Pushes log items to the push API of [Loki](https://grafana.com/oss/loki/).
+
+use super::{labels, severity_name, ExportError, LogExporter};
+use proto::observe::LogItem;
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, time::Duration};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Deserialize)]
+pub(super) struct LokiConfig {
+    /// The push endpoint, such as `http://loki:3100/loki/api/v1/push`
+    url: Url,
+    /// Sent as `X-Scope-OrgID` to Loki in multi-tenant mode
+    #[serde(default)]
+    tenant: Option<String>,
+    /// Added to the labels of each stream, such as the name of the node
+    #[serde(default)]
+    labels: BTreeMap<String, String>,
+}
+
+#[derive(Debug)]
+pub(super) struct Loki {
+    client: Client,
+    config: LokiConfig,
+}
+
+impl Loki {
+    pub fn new(config: LokiConfig) -> Result<Self, String> {
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        Ok(Self { client, config })
+    }
+}
+
+#[tonic::async_trait]
+impl LogExporter for Loki {
+    fn name(&self) -> String {
+        format!("loki {}", self.config.url)
+    }
+
+    async fn export(&self, batch: &[LogItem]) -> Result<(), ExportError> {
+        let body =
+            serde_json::to_vec(&push_request(&self.config.labels, batch))
+                .map_err(|e| ExportError::Permanent(e.to_string()))?;
+        let mut request = self
+            .client
+            .post(self.config.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .body(body);
+        if let Some(tenant) = &self.config.tenant {
+            request = request.header("X-Scope-OrgID", tenant);
+        }
+
+        let response = request
+            .send()
+            .await
+            .map_err(|e| ExportError::Transient(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else if retryable(status) {
+            Err(ExportError::Transient(format!("loki responded {status}")))
+        } else {
+            Err(ExportError::Permanent(format!("loki responded {status}")))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PushRequest {
+    streams: Vec<Stream>,
+}
+
+#[derive(Debug, Serialize)]
+struct Stream {
+    stream: BTreeMap<String, String>,
+    /// Pairs of a timestamp in nanoseconds and a line
+    values: Vec<[String; 2]>,
+}
+
+/// Groups `batch` into a stream per set of labels.
+fn push_request(
+    extra_labels: &BTreeMap<String, String>,
+    batch: &[LogItem],
+) -> PushRequest {
+    let mut streams: BTreeMap<BTreeMap<String, String>, Vec<[String; 2]>> =
+        BTreeMap::new();
+    for item in batch {
+        let mut stream = extra_labels.clone();
+        let _ = stream.insert("channel".into(), item.channel.clone());
+        let _ = stream.insert("level".into(), severity_name(item).into());
+        for (name, value) in labels(item) {
+            let _ = stream.insert(name.into(), value.into());
+        }
+
+        let nanos = i128::from(item.timestamp) * 1_000_000_000;
+        streams
+            .entry(stream)
+            .or_default()
+            .push([nanos.to_string(), item.line.clone()]);
+    }
+
+    PushRequest {
+        streams: streams
+            .into_iter()
+            .map(|(stream, values)| Stream { stream, values })
+            .collect(),
+    }
+}
+
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::observe::LogSeverity;
+
+    #[test]
+    fn groups_items_into_streams() {
+        let item = |container: &str, line: &str| LogItem {
+            channel: format!("pod::{container}"),
+            line: line.into(),
+            timestamp: 1_700_000_000,
+            severity: LogSeverity::Info as i32,
+            pod_sandbox_id: "pod".into(),
+            container_id: container.into(),
+            ..Default::default()
+        };
+        let extra_labels = BTreeMap::from([("node".into(), "node-1".into())]);
+        let batch = [item("a", "one"), item("b", "two"), item("a", "three")];
+
+        let body = serde_json::to_value(push_request(&extra_labels, &batch))
+            .expect("serializable");
+        assert_eq!(
+            body,
+            serde_json::json!({"streams": [
+                {
+                    "stream": {
+                        "channel": "pod::a",
+                        "container": "a",
+                        "level": "info",
+                        "node": "node-1",
+                        "pod": "pod"
+                    },
+                    "values": [
+                        ["1700000000000000000", "one"],
+                        ["1700000000000000000", "three"]
+                    ]
+                },
+                {
+                    "stream": {
+                        "channel": "pod::b",
+                        "container": "b",
+                        "level": "info",
+                        "node": "node-1",
+                        "pod": "pod"
+                    },
+                    "values": [["1700000000000000000", "two"]]
+                }
+            ]})
+        );
+    }
+}
@@ -0,0 +1,406 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Ships the logs of auraed and the output of its workloads to centralized
+//! log storage, without an agent collecting them on each node.
+//!
+//! Exporters are configured in a JSON file, which may not exist:
+//!
+//! ```json
+//! {
+//!   "exporters": [
+//!     {
+//!       "type": "loki",
+//!       "url": "https://loki.example.com/loki/api/v1/push",
+//!       "tenant": "team-a",
+//!       "labels": { "node": "node-1" }
+//!     },
+//!     {
+//!       "type": "fluent_forward",
+//!       "address": "fluentd.example.com:24224",
+//!       "tag": "aurae"
+//!     }
+//!   ],
+//!   "batching": { "max_batch_size": 512, "flush_interval_ms": 1000 }
+//! }
+//! ```
+//!
+//! Each exporter receives the items of all log channels, in batches of at
+//! most `max_batch_size` items sent once full or every `flush_interval_ms`.
+//! Batches that fail to export for a transient reason are retried with an
+//! exponential backoff. Items arriving while an exporter is behind are
+//! buffered, up to `max_buffered` items past which the oldest are dropped.
+//! New protocols are added by implementing [LogExporter].
+
+use crate::observe::ObserveService;
+use proto::observe::{LogFilter, LogItem};
+use serde::Deserialize;
+use std::{
+    collections::VecDeque,
+    fmt::Debug,
+    io::ErrorKind,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::sync::{mpsc, Notify};
+use tracing::{info, warn};
+
+mod fluent_forward;
+mod loki;
+
+/// Time after which the export of a batch is given up.
+const MAX_EXPORT_TIME: Duration = Duration::from_secs(300);
+/// Items in flight between the log channels and the buffer of an exporter.
+const CHANNEL_CAPACITY: usize = 1024;
+
+#[derive(Debug, Error)]
+pub(crate) enum LogExportError {
+    #[error("failed to read log exporters config '{path}': {source}")]
+    Read { path: String, source: std::io::Error },
+    #[error("invalid log exporters config '{path}': {reason}")]
+    InvalidConfig { path: String, reason: String },
+    #[error("failed to build the {exporter} exporter: {reason}")]
+    Build { exporter: &'static str, reason: String },
+}
+
+/// Why a batch failed to export.
+#[derive(Debug, Error)]
+pub(crate) enum ExportError {
+    /// Worth retrying, such as a network error or an overloaded endpoint
+    #[error("{0}")]
+    Transient(String),
+    /// The batch is dropped
+    #[error("{0}")]
+    Permanent(String),
+}
+
+/// A protocol log items are shipped with.
+#[tonic::async_trait]
+pub(crate) trait LogExporter: Debug + Send + Sync {
+    /// Names the exporter in the logs of auraed.
+    fn name(&self) -> String;
+
+    /// Ships a batch of items, oldest first.
+    async fn export(&self, batch: &[LogItem]) -> Result<(), ExportError>;
+}
+
+/// The log exporters config of auraed.
+#[derive(Debug, Default, Deserialize)]
+struct LogExportersFile {
+    #[serde(default)]
+    exporters: Vec<ExporterConfig>,
+    #[serde(default)]
+    batching: BatchingConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ExporterConfig {
+    Loki(loki::LokiConfig),
+    FluentForward(fluent_forward::FluentForwardConfig),
+}
+
+impl ExporterConfig {
+    fn kind(&self) -> &'static str {
+        match self {
+            ExporterConfig::Loki(_) => "loki",
+            ExporterConfig::FluentForward(_) => "fluent_forward",
+        }
+    }
+
+    fn build(self) -> Result<Arc<dyn LogExporter>, String> {
+        Ok(match self {
+            ExporterConfig::Loki(config) => Arc::new(loki::Loki::new(config)?),
+            ExporterConfig::FluentForward(config) => {
+                Arc::new(fluent_forward::FluentForward::new(config)?)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+struct BatchingConfig {
+    max_batch_size: usize,
+    flush_interval_ms: u64,
+    max_buffered: usize,
+}
+
+impl Default for BatchingConfig {
+    fn default() -> Self {
+        Self {
+            max_batch_size: 512,
+            flush_interval_ms: 1000,
+            max_buffered: 16384,
+        }
+    }
+}
+
+/// The configured log exporters of the node.
+#[derive(Debug)]
+pub(crate) struct LogExporters {
+    exporters: Vec<Arc<dyn LogExporter>>,
+    batching: BatchingConfig,
+}
+
+impl LogExporters {
+    /// Builds the exporters configured in `path`, none if it does not exist.
+    pub fn load(path: &Path) -> Result<Self, LogExportError> {
+        let invalid = |reason| LogExportError::InvalidConfig {
+            path: path.display().to_string(),
+            reason,
+        };
+        let file = match std::fs::read(path) {
+            Ok(contents) => parse(&contents).map_err(invalid)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                LogExportersFile::default()
+            }
+            Err(source) => {
+                return Err(LogExportError::Read {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        };
+
+        let exporters = file
+            .exporters
+            .into_iter()
+            .map(|config| {
+                let exporter = config.kind();
+                config.build().map_err(|reason| LogExportError::Build {
+                    exporter,
+                    reason,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { exporters, batching: file.batching })
+    }
+
+    /// Ships the items of the log channels of `observe_service` until it is
+    /// dropped. Does nothing if no exporter is configured.
+    pub async fn export(self, observe_service: &ObserveService) {
+        if self.exporters.is_empty() {
+            return;
+        }
+        info!("Exporting logs to {} exporters", self.exporters.len());
+
+        for exporter in self.exporters {
+            let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+            if let Err(e) = observe_service
+                .forward_logs(LogFilter::default(), tx, |item| item)
+                .await
+            {
+                warn!("Failed to export logs to {}: {e}", exporter.name());
+                continue;
+            }
+            ship(exporter, self.batching, rx);
+        }
+    }
+}
+
+fn parse(contents: &[u8]) -> Result<LogExportersFile, String> {
+    let file: LogExportersFile =
+        serde_json::from_slice(contents).map_err(|e| e.to_string())?;
+    if file.batching.max_batch_size == 0 || file.batching.max_buffered == 0 {
+        return Err("batches and the buffer need room for items".into());
+    }
+    Ok(file)
+}
+
+/// Items waiting to be exported, shared by the task receiving them and the
+/// one exporting them.
+#[derive(Debug)]
+struct Buffer {
+    items: Mutex<VecDeque<LogItem>>,
+    /// Notified once a batch is full
+    full: Notify,
+    capacity: usize,
+    batch_size: usize,
+}
+
+impl Buffer {
+    /// Buffers `item`, dropping the oldest item once full. Returns whether
+    /// an item was dropped.
+    fn push(&self, item: LogItem) -> bool {
+        let mut items = self.items.lock().expect("log export buffer lock");
+        let dropped = items.len() >= self.capacity;
+        if dropped {
+            let _ = items.pop_front();
+        }
+        items.push_back(item);
+        if items.len() >= self.batch_size {
+            self.full.notify_one();
+        }
+        dropped
+    }
+
+    /// Takes the oldest items, at most a batch of them.
+    fn take_batch(&self) -> Vec<LogItem> {
+        let mut items = self.items.lock().expect("log export buffer lock");
+        let len = items.len().min(self.batch_size);
+        items.drain(..len).collect()
+    }
+}
+
+/// Exports the items received from `rx` in batches, in the background.
+fn ship(
+    exporter: Arc<dyn LogExporter>,
+    batching: BatchingConfig,
+    mut rx: mpsc::Receiver<LogItem>,
+) {
+    let buffer = Arc::new(Buffer {
+        items: Mutex::new(VecDeque::new()),
+        full: Notify::new(),
+        capacity: batching.max_buffered,
+        batch_size: batching.max_batch_size,
+    });
+
+    // Keep receiving while a batch is exported, so the log channels are not
+    // held up by a slow exporter
+    let _ignored = tokio::spawn({
+        let buffer = buffer.clone();
+        let name = exporter.name();
+        async move {
+            let mut dropped: u64 = 0;
+            while let Some(item) = rx.recv().await {
+                if buffer.push(item) {
+                    dropped += 1;
+                    if dropped.is_power_of_two() {
+                        warn!("Dropped {dropped} log items not exported to {name} in time");
+                    }
+                }
+            }
+        }
+    });
+
+    let _ignored = tokio::spawn(async move {
+        let mut flush = tokio::time::interval(Duration::from_millis(
+            batching.flush_interval_ms.max(1),
+        ));
+        loop {
+            tokio::select! {
+                _ = flush.tick() => {}
+                _ = buffer.full.notified() => {}
+            }
+            loop {
+                let batch = buffer.take_batch();
+                if batch.is_empty() {
+                    break;
+                }
+                if let Err(e) = export(exporter.as_ref(), &batch).await {
+                    warn!(
+                        "Dropped {} log items failing to export to {}: {e}",
+                        batch.len(),
+                        exporter.name()
+                    );
+                }
+                if batch.len() < batching.max_batch_size {
+                    break;
+                }
+            }
+        }
+    });
+}
+
+/// Exports `batch`, retrying transient failures.
+async fn export(
+    exporter: &dyn LogExporter,
+    batch: &[LogItem],
+) -> Result<(), ExportError> {
+    let retry_strategy = backoff::ExponentialBackoffBuilder::new()
+        .with_initial_interval(Duration::from_secs(1))
+        .with_max_interval(Duration::from_secs(60))
+        .with_max_elapsed_time(Some(MAX_EXPORT_TIME))
+        .build();
+
+    backoff::future::retry(retry_strategy, || async {
+        exporter.export(batch).await.map_err(|e| match e {
+            ExportError::Transient(_) => backoff::Error::transient(e),
+            ExportError::Permanent(_) => backoff::Error::Permanent(e),
+        })
+    })
+    .await
+}
+
+/// The name of the severity of an item, as shipped.
+fn severity_name(item: &LogItem) -> &'static str {
+    use proto::observe::LogSeverity;
+    match LogSeverity::from_i32(item.severity) {
+        Some(LogSeverity::Trace) => "trace",
+        Some(LogSeverity::Debug) => "debug",
+        Some(LogSeverity::Info) => "info",
+        Some(LogSeverity::Warn) => "warn",
+        Some(LogSeverity::Error) => "error",
+        Some(LogSeverity::Unspecified) | None => "unknown",
+    }
+}
+
+/// The labels of an item that are set, by the name they are shipped with.
+fn labels(item: &LogItem) -> impl Iterator<Item = (&'static str, &str)> {
+    [
+        ("cell", item.cell_name.as_str()),
+        ("executable", item.executable_name.as_str()),
+        ("pod", item.pod_sandbox_id.as_str()),
+        ("container", item.container_id.as_str()),
+    ]
+    .into_iter()
+    .filter(|(_, value)| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_exporters() {
+        let file = parse(
+            br#"{"exporters": [
+                {"type": "loki", "url": "http://loki:3100/loki/api/v1/push"},
+                {"type": "fluent_forward", "address": "fluentd:24224"}
+            ], "batching": {"max_batch_size": 100}}"#,
+        )
+        .expect("valid config");
+        assert!(matches!(file.exporters[0], ExporterConfig::Loki(_)));
+        assert!(matches!(file.exporters[1], ExporterConfig::FluentForward(_)));
+        assert_eq!(file.batching.max_batch_size, 100);
+        assert_eq!(file.batching.flush_interval_ms, 1000);
+
+        assert!(parse(br#"{"exporters": [{"type": "kafka"}]}"#).is_err());
+        assert!(parse(br#"{"batching": {"max_batch_size": 0}}"#).is_err());
+    }
+
+    #[test]
+    fn buffer_drops_the_oldest_items() {
+        let buffer = Buffer {
+            items: Mutex::new(VecDeque::new()),
+            full: Notify::new(),
+            capacity: 3,
+            batch_size: 2,
+        };
+        for line in ["a", "b", "c", "d"] {
+            let _ = buffer
+                .push(LogItem { line: line.into(), ..Default::default() });
+        }
+        let lines = |batch: Vec<LogItem>| {
+            batch.into_iter().map(|item| item.line).collect::<Vec<_>>()
+        };
+        assert_eq!(lines(buffer.take_batch()), ["b", "c"]);
+        assert_eq!(lines(buffer.take_batch()), ["d"]);
+        assert!(buffer.take_batch().is_empty());
+    }
+}
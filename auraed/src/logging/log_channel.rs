@@ -13,10 +13,42 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use super::get_timestamp_sec;
-use proto::observe::LogItem;
+use super::{
+    get_timestamp_sec,
+    log_journal::{LogJournal, ReplayFrom},
+    log_ring::{LogRing, LogRingReader},
+};
+use once_cell::sync::OnceCell;
+use proto::observe::{LogItem, LogSeverity};
+use std::{collections::VecDeque, io, sync::Arc};
 use tokio::sync::broadcast::{self, Receiver, Sender};
 
+/// What writes to a [LogChannel]. The labels are copied to the items its
+/// consumers receive, so streams can be filtered by them.
+#[derive(Clone, Debug, Default)]
+pub struct LogLabels {
+    pub cell_name: String,
+    pub executable_name: String,
+    pub pod_sandbox_id: String,
+    pub container_id: String,
+    /// The severity of items sent without one
+    pub severity: LogSeverity,
+}
+
+impl LogLabels {
+    /// Copies the labels to `item`, keeping the severity it was sent with.
+    fn apply(&self, mut item: LogItem) -> LogItem {
+        item.cell_name.clone_from(&self.cell_name);
+        item.executable_name.clone_from(&self.executable_name);
+        item.pod_sandbox_id.clone_from(&self.pod_sandbox_id);
+        item.container_id.clone_from(&self.container_id);
+        if item.severity == LogSeverity::Unspecified as i32 {
+            item.severity = self.severity as i32;
+        }
+        item
+    }
+}
+
 /// Abstraction Layer for one log generating entity
 /// LogChannel provides channels between Log producers and log consumers
 #[derive(Clone, Debug)]
@@ -24,6 +56,13 @@ pub struct LogChannel {
     /// The human readable (public) name for this log channel.
     pub name: String,
     tx: Sender<LogItem>,
+    /// Shared ring the output is written to instead of `tx`
+    ring: Option<Arc<LogRing>>,
+    /// Copied to the items received by consumers subscribing to this handle
+    labels: LogLabels,
+    /// Journal the items are appended to before they are sent, shared by all
+    /// handles of the channel
+    journal: Arc<OnceCell<Arc<LogJournal>>>,
 }
 
 impl LogChannel {
@@ -31,23 +70,160 @@ impl LogChannel {
     pub fn new(name: String) -> LogChannel {
         // TODO: decide for a cap. 40 is arbitrary
         let (tx, _) = broadcast::channel(40);
-        LogChannel { name, tx }
+        LogChannel {
+            name,
+            tx,
+            ring: None,
+            labels: LogLabels::default(),
+            journal: Default::default(),
+        }
+    }
+
+    /// Constructor for very chatty producers, whose output is written to a
+    /// [LogRing] of `capacity` bytes shared by all consumers
+    pub fn with_ring(name: String, capacity: usize) -> LogChannel {
+        let (tx, _) = broadcast::channel(1);
+        LogChannel {
+            name,
+            tx,
+            ring: Some(Arc::new(LogRing::new(capacity))),
+            labels: LogLabels::default(),
+            journal: Default::default(),
+        }
+    }
+
+    /// Labels the items received by consumers subscribing to the returned
+    /// handle. Other handles of the channel keep their labels.
+    pub fn with_labels(mut self, labels: LogLabels) -> LogChannel {
+        self.labels = labels;
+        self
+    }
+
+    pub fn labels(&self) -> &LogLabels {
+        &self.labels
+    }
+
+    /// Journals the items sent from now on, so consumers can replay them
+    /// with [LogChannel::subscribe_from]. A channel keeps the first journal
+    /// set. The raw output of channels with a ring is not journaled.
+    pub fn set_journal(&self, journal: Arc<LogJournal>) {
+        let _ = self.journal.set(journal);
+    }
+
+    pub fn journal(&self) -> Option<&Arc<LogJournal>> {
+        self.journal.get()
+    }
+
+    /// The shared ring, if raw output is to be written to it directly
+    pub fn ring(&self) -> Option<&Arc<LogRing>> {
+        self.ring.as_ref()
     }
 
     /// Getter for consumer channel
-    pub fn subscribe(&self) -> Receiver<LogItem> {
-        self.tx.subscribe()
+    pub fn subscribe(&self) -> LogReceiver {
+        let source = match &self.ring {
+            Some(ring) => LogSource::Ring {
+                name: self.name.clone(),
+                reader: ring.subscribe(),
+            },
+            None => LogSource::Broadcast(self.tx.subscribe()),
+        };
+        LogReceiver {
+            source,
+            labels: self.labels.clone(),
+            replayed: VecDeque::new(),
+            replayed_up_to: 0,
+        }
+    }
+
+    /// Like [LogChannel::subscribe], but first receives the journaled items
+    /// from `from` on. Without a journal there is nothing to replay.
+    pub fn subscribe_from(&self, from: ReplayFrom) -> io::Result<LogReceiver> {
+        // Subscribe first to not miss the items journaled after the replay
+        let mut receiver = self.subscribe();
+        if let Some(journal) = self.journal() {
+            let replayed = journal.replay(from)?;
+            receiver.replayed_up_to =
+                replayed.last().map_or(0, |item| item.offset);
+            receiver.replayed = replayed.into();
+        }
+        Ok(receiver)
     }
 
     /// Wrapper that sends a log line to the channel
     pub fn send(&self, line: String) {
-        // send returns an Err if there are no receivers. We ignore that.
-        let _ = self.tx.send(LogItem {
+        self.log(LogSeverity::Unspecified, line);
+    }
+
+    /// Sends a log line of `severity` to the channel. Lines written to a
+    /// ring are raw output and have no severity of their own.
+    pub fn log(&self, severity: LogSeverity, line: String) {
+        if let Some(ring) = &self.ring {
+            let mut line = line;
+            line.push('\n');
+            ring.write(line.as_bytes());
+            return;
+        }
+        let mut item = LogItem {
             channel: self.name.clone(),
             line,
             // TODO: milliseconds type in protobuf requires 128bit type
             timestamp: get_timestamp_sec(),
-        });
+            severity: severity as i32,
+            ..Default::default()
+        };
+        if let Some(journal) = self.journal() {
+            // Not logged, as the logs of auraed are journaled as well
+            let _ = journal.append(&mut item);
+        }
+        // send returns an Err if there are no receivers. We ignore that.
+        let _ = self.tx.send(item);
+    }
+}
+
+/// Consumer end of a [LogChannel]
+#[derive(Debug)]
+pub struct LogReceiver {
+    source: LogSource,
+    labels: LogLabels,
+    /// Journaled items received before the ones sent
+    replayed: VecDeque<LogItem>,
+    /// The offset of the last replayed item, the sent items up to which
+    /// were replayed already
+    replayed_up_to: u64,
+}
+
+#[derive(Debug)]
+enum LogSource {
+    Broadcast(Receiver<LogItem>),
+    Ring { name: String, reader: LogRingReader },
+}
+
+impl LogReceiver {
+    /// Waits for the next log item. Returns [None] once the producer is
+    /// closed, or when lagging behind the broadcast channel.
+    pub async fn recv(&mut self) -> Option<LogItem> {
+        if let Some(item) = self.replayed.pop_front() {
+            return Some(self.labels.apply(item));
+        }
+        let item = match &mut self.source {
+            LogSource::Broadcast(rx) => loop {
+                let item = rx.recv().await.ok()?;
+                if item.offset == 0 || item.offset > self.replayed_up_to {
+                    break item;
+                }
+            },
+            LogSource::Ring { name, reader } => {
+                let line = reader.next_line().await?;
+                LogItem {
+                    channel: name.clone(),
+                    line,
+                    timestamp: get_timestamp_sec(),
+                    ..Default::default()
+                }
+            }
+        };
+        Some(self.labels.apply(item))
     }
 }
 
@@ -77,16 +253,78 @@ mod tests {
         channel.send("aurae".into());
         channel.send("bye".into());
 
-        let cur_item = rx.recv().await.ok();
+        let cur_item = rx.recv().await;
         assert!(cur_item.is_some());
         assert_eq!(cur_item.unwrap().line, "hello".to_string());
 
-        let cur_item = rx.recv().await.ok();
+        let cur_item = rx.recv().await;
         assert!(cur_item.is_some());
         assert_eq!(cur_item.unwrap().line, "aurae".to_string());
 
-        let cur_item = rx.recv().await.ok();
+        let cur_item = rx.recv().await;
         assert!(cur_item.is_some());
         assert_eq!(cur_item.unwrap().line, "bye".to_string());
     }
+
+    #[tokio::test]
+    async fn test_ring_channel() {
+        let channel = LogChannel::with_ring("Test".into(), 64);
+        let mut rx = channel.subscribe();
+
+        channel.send("hello".into());
+        channel.ring().expect("ring").write(b"aurae\nbye\n");
+
+        let cur_item = rx.recv().await.expect("item");
+        assert_eq!(cur_item.channel, "Test");
+        assert_eq!(cur_item.line, "hello");
+        assert_eq!(rx.recv().await.expect("item").line, "aurae");
+        assert_eq!(rx.recv().await.expect("item").line, "bye");
+    }
+
+    #[tokio::test]
+    async fn test_labels_apply_to_subscribed_handle() {
+        let channel = LogChannel::new("nginx::stderr".into());
+        let labelled = channel.clone().with_labels(LogLabels {
+            cell_name: "ae-1".into(),
+            executable_name: "nginx".into(),
+            severity: LogSeverity::Error,
+            ..Default::default()
+        });
+        let mut rx = channel.subscribe();
+        let mut labelled_rx = labelled.subscribe();
+
+        channel.send("failed".into());
+        channel.log(LogSeverity::Warn, "retrying".into());
+
+        let item = rx.recv().await.expect("item");
+        assert_eq!(item.cell_name, "");
+        assert_eq!(item.severity, LogSeverity::Unspecified as i32);
+
+        let item = labelled_rx.recv().await.expect("item");
+        assert_eq!(item.cell_name, "ae-1");
+        assert_eq!(item.executable_name, "nginx");
+        assert_eq!(item.severity, LogSeverity::Error as i32);
+        let item = labelled_rx.recv().await.expect("item");
+        assert_eq!(item.severity, LogSeverity::Warn as i32);
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_from_replays_journaled_items() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let journal =
+            LogJournal::open(dir.path().to_path_buf(), Default::default())
+                .expect("journal");
+        let channel = LogChannel::new("auraed".into());
+        channel.set_journal(Arc::new(journal));
+
+        channel.send("before".into());
+        let mut rx =
+            channel.subscribe_from(ReplayFrom::Offset(0)).expect("subscribe");
+        channel.send("after".into());
+
+        let item = rx.recv().await.expect("item");
+        assert_eq!((item.line.as_str(), item.offset), ("before", 1));
+        let item = rx.recv().await.expect("item");
+        assert_eq!((item.line.as_str(), item.offset), ("after", 2));
+    }
 }
\ No newline at end of file
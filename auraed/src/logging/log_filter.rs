@@ -0,0 +1,219 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use lazy_static::lazy_static;
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, Weak};
+use tracing::{
+    level_filters::LevelFilter,
+    span::{Attributes, Id, Record},
+    subscriber::Interest,
+    Event, Metadata, Subscriber,
+};
+use tracing_subscriber::{
+    filter::ParseError,
+    layer::{Context, Filter},
+    registry::LookupSpan,
+    EnvFilter,
+};
+
+lazy_static! {
+    /// The filter of the logs auraed writes to stdout and sends to the
+    /// observe API. Changed at runtime through the observe API.
+    pub static ref DAEMON_LOG_FILTER: DaemonLogFilter =
+        DaemonLogFilter::default();
+}
+
+/// Directives in the syntax of `RUST_LOG`, e.g.
+/// `auraed=info,auraed::cri=debug`, shared by the filters of several layers
+/// and changed while they log.
+#[derive(Debug, Default)]
+pub struct DaemonLogFilter {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The directives auraed started with, restored by setting none
+    default: String,
+    directives: String,
+    filters: Vec<Weak<RwLock<EnvFilter>>>,
+}
+
+impl DaemonLogFilter {
+    /// Sets the directives auraed starts with. Replaces the current ones.
+    pub(crate) fn init(&self, directives: String) {
+        let mut state = self.state();
+        state.default = directives;
+        drop(state);
+        let _ = self.set("");
+    }
+
+    /// The current directives.
+    pub fn directives(&self) -> String {
+        self.state().directives.clone()
+    }
+
+    /// A filter of a layer, following the directives as they change.
+    pub(crate) fn layer_filter(&self) -> LayerFilter {
+        let mut state = self.state();
+        let filter = Arc::new(RwLock::new(EnvFilter::new(&state.directives)));
+        state.filters.retain(|filter| filter.strong_count() > 0);
+        state.filters.push(Arc::downgrade(&filter));
+        LayerFilter(filter)
+    }
+
+    /// Replaces the directives, or restores the ones auraed started with
+    /// if `directives` is empty, and returns the previous ones.
+    pub fn set(&self, directives: &str) -> Result<String, ParseError> {
+        let mut state = self.state();
+        let directives = match directives.trim() {
+            "" => state.default.clone(),
+            directives => directives.to_string(),
+        };
+        // Parse once to reject invalid directives before any filter changes
+        let _ = EnvFilter::builder().parse(&directives)?;
+
+        state.filters.retain(|filter| filter.strong_count() > 0);
+        for filter in state.filters.iter().filter_map(Weak::upgrade) {
+            *filter.write().unwrap_or_else(PoisonError::into_inner) =
+                EnvFilter::new(&directives);
+        }
+        let previous = std::mem::replace(&mut state.directives, directives);
+        drop(state);
+
+        // Callsites cache whether they are enabled. Without a rebuild they
+        // would keep following the previous directives.
+        tracing::callsite::rebuild_interest_cache();
+        Ok(previous)
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The filter of one layer, replaced when the directives of the
+/// [DaemonLogFilter] it came from change.
+#[derive(Debug, Clone)]
+pub(crate) struct LayerFilter(Arc<RwLock<EnvFilter>>);
+
+impl LayerFilter {
+    fn read(&self) -> RwLockReadGuard<'_, EnvFilter> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<S> Filter<S> for LayerFilter
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    fn enabled(&self, metadata: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        Filter::<S>::enabled(&*self.read(), metadata, cx)
+    }
+
+    fn callsite_enabled(
+        &self,
+        metadata: &'static Metadata<'static>,
+    ) -> Interest {
+        Filter::<S>::callsite_enabled(&*self.read(), metadata)
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, cx: &Context<'_, S>) -> bool {
+        Filter::<S>::event_enabled(&*self.read(), event, cx)
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Filter::<S>::max_level_hint(&*self.read())
+    }
+
+    fn on_new_span(
+        &self,
+        attrs: &Attributes<'_>,
+        id: &Id,
+        ctx: Context<'_, S>,
+    ) {
+        Filter::<S>::on_new_span(&*self.read(), attrs, id, ctx)
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        Filter::<S>::on_record(&*self.read(), id, values, ctx)
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_enter(&*self.read(), id, ctx)
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_exit(&*self.read(), id, ctx)
+    }
+
+    fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        Filter::<S>::on_close(&*self.read(), id, ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing::{debug, info};
+    use tracing_subscriber::{layer::SubscriberExt, Layer};
+
+    #[test]
+    fn test_set_restores_default_when_empty() {
+        let log_filter = DaemonLogFilter::default();
+        log_filter.init("auraed=info".into());
+        assert_eq!(log_filter.directives(), "auraed=info");
+
+        let previous =
+            log_filter.set("auraed=debug").expect("valid directives");
+        assert_eq!(previous, "auraed=info");
+        assert_eq!(log_filter.set(" ").expect("default"), "auraed=debug");
+        assert_eq!(log_filter.directives(), "auraed=info");
+    }
+
+    #[test]
+    fn test_set_rejects_invalid_directives() {
+        let log_filter = DaemonLogFilter::default();
+        log_filter.init("auraed=info".into());
+        assert!(log_filter.set("auraed=loud").is_err());
+        assert_eq!(log_filter.directives(), "auraed=info");
+    }
+
+    #[test]
+    fn test_layer_filters_follow_directives() {
+        #[derive(Clone, Default)]
+        struct Count(Arc<Mutex<usize>>);
+
+        impl<S: Subscriber> Layer<S> for Count {
+            fn on_event(&self, _event: &Event<'_>, _ctx: Context<'_, S>) {
+                *self.0.lock().expect("lock") += 1;
+            }
+        }
+
+        let log_filter = DaemonLogFilter::default();
+        log_filter.init("info".into());
+        let count = Count::default();
+        let subscriber = tracing_subscriber::registry()
+            .with(count.clone().with_filter(log_filter.layer_filter()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            debug!("dropped");
+            info!("kept");
+            let _ = log_filter.set("debug").expect("valid directives");
+            debug!("kept");
+        });
+        assert_eq!(*count.0.lock().expect("lock"), 2);
+    }
+}
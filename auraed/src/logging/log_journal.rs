@@ -0,0 +1,314 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! A journal of the items sent to a log channel, kept on disk so that
+//! clients attaching later can replay what they missed.
+//!
+//! Items are appended to segment files named after the offset of their first
+//! item, `<offset>.journal`, as length delimited protobuf messages. The
+//! oldest segments are removed once the journal outgrows its size, or once
+//! they are older than its age. Offsets count the items ever journaled,
+//! from 1, and survive restarts of auraed.
+
+use prost::Message;
+use proto::observe::LogItem;
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// Segments a journal is split in, so the oldest items are removed a
+/// segment at a time.
+const SEGMENTS: u64 = 4;
+
+/// How much the journals of log channels keep.
+#[derive(Debug, Clone)]
+pub struct LogJournalConfig {
+    /// Size in bytes past which the oldest items of a journal are removed.
+    pub max_size: u64,
+    /// Age past which items are removed, whatever the size of the journal.
+    /// None to only bound journals by size.
+    pub max_age: Option<Duration>,
+}
+
+impl Default for LogJournalConfig {
+    fn default() -> Self {
+        Self { max_size: 8 * 1024 * 1024, max_age: None }
+    }
+}
+
+/// Where a replay of a journal starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayFrom {
+    /// The item at the offset, or the oldest item kept if it was removed
+    Offset(u64),
+    /// The first item logged at or after the timestamp, in seconds since the
+    /// epoch
+    Timestamp(i64),
+}
+
+/// The on-disk journal of a log channel.
+#[derive(Debug)]
+pub struct LogJournal {
+    state: Mutex<JournalState>,
+}
+
+#[derive(Debug)]
+struct JournalState {
+    dir: PathBuf,
+    config: LogJournalConfig,
+    /// Oldest first, the last one is appended to
+    segments: VecDeque<Segment>,
+    file: File,
+    /// The offset of the next item
+    next_offset: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    first_offset: u64,
+    path: PathBuf,
+    size: u64,
+    /// When the last item was appended
+    modified: SystemTime,
+}
+
+impl LogJournal {
+    /// Opens the journal in `dir`, creating it, to append after the items
+    /// journaled before.
+    pub fn open(dir: PathBuf, config: LogJournalConfig) -> io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        let mut segments = segments(&dir)?;
+        let next_offset = match segments.back() {
+            Some(last) => {
+                last.first_offset + read_items(&last.path)?.len() as u64
+            }
+            None => 1,
+        };
+        // A new segment, as the last item of the last one may be cut short
+        if segments.back().map_or(true, |last| last.size > 0) {
+            segments.push_back(Segment::new(&dir, next_offset));
+        }
+        let last = segments.back().expect("segment");
+        let file = open(&last.path)?;
+        let mut state =
+            JournalState { dir, config, segments, file, next_offset };
+        state.prune()?;
+        Ok(Self { state: Mutex::new(state) })
+    }
+
+    /// Appends `item`, setting its offset.
+    pub fn append(&self, item: &mut LogItem) -> io::Result<()> {
+        let mut state = self.state.lock().expect("log journal lock");
+        item.offset = state.next_offset;
+        let entry = item.encode_length_delimited_to_vec();
+
+        let segment_size = (state.config.max_size / SEGMENTS).max(1);
+        let last = state.segments.back().expect("segment");
+        if last.size > 0 && last.size + entry.len() as u64 > segment_size {
+            state.roll()?;
+        }
+        state.file.write_all(&entry)?;
+        state.next_offset += 1;
+        let last = state.segments.back_mut().expect("segment");
+        last.size += entry.len() as u64;
+        last.modified = SystemTime::now();
+        state.prune()
+    }
+
+    /// The items kept from `from` on, oldest first.
+    pub fn replay(&self, from: ReplayFrom) -> io::Result<Vec<LogItem>> {
+        let state = self.state.lock().expect("log journal lock");
+        let first = match from {
+            // Only the segment holding the offset and the newer ones
+            ReplayFrom::Offset(offset) => state
+                .segments
+                .iter()
+                .rposition(|segment| segment.first_offset <= offset)
+                .unwrap_or(0),
+            ReplayFrom::Timestamp(_) => 0,
+        };
+        let mut items = vec![];
+        for segment in state.segments.iter().skip(first) {
+            items.extend(read_items(&segment.path)?.into_iter().filter(
+                |item| match from {
+                    ReplayFrom::Offset(offset) => item.offset >= offset,
+                    ReplayFrom::Timestamp(timestamp) => {
+                        item.timestamp >= timestamp
+                    }
+                },
+            ));
+        }
+        Ok(items)
+    }
+}
+
+impl JournalState {
+    /// Starts a new segment, to append the next item to.
+    fn roll(&mut self) -> io::Result<()> {
+        let segment = Segment::new(&self.dir, self.next_offset);
+        self.file = open(&segment.path)?;
+        self.segments.push_back(segment);
+        Ok(())
+    }
+
+    /// Removes the oldest segments past the size or age of the journal. The
+    /// segment appended to is kept.
+    fn prune(&mut self) -> io::Result<()> {
+        let mut size: u64 = self.segments.iter().map(|s| s.size).sum();
+        while self.segments.len() > 1 {
+            let oldest = self.segments.front().expect("segment");
+            let aged = self.config.max_age.is_some_and(|age| {
+                oldest.modified.elapsed().is_ok_and(|elapsed| elapsed >= age)
+            });
+            if size <= self.config.max_size && !aged {
+                break;
+            }
+            size -= oldest.size;
+            std::fs::remove_file(&oldest.path)?;
+            let _ = self.segments.pop_front();
+        }
+        Ok(())
+    }
+}
+
+impl Segment {
+    fn new(dir: &Path, first_offset: u64) -> Self {
+        Self {
+            first_offset,
+            path: dir.join(format!("{first_offset:020}.journal")),
+            size: 0,
+            modified: SystemTime::now(),
+        }
+    }
+}
+
+/// The segments in `dir`, oldest first.
+fn segments(dir: &Path) -> io::Result<VecDeque<Segment>> {
+    let mut segments = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.extension().is_some_and(|e| e == "journal") {
+            continue;
+        }
+        let Some(first_offset) = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.parse().ok())
+        else {
+            continue;
+        };
+        let metadata = std::fs::metadata(&path)?;
+        segments.push(Segment {
+            first_offset,
+            path,
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        });
+    }
+    segments.sort_by_key(|segment| segment.first_offset);
+    Ok(segments.into())
+}
+
+/// The items of a segment. An item cut short, as by a crash while it was
+/// appended, ends the segment.
+fn read_items(path: &Path) -> io::Result<Vec<LogItem>> {
+    let data = std::fs::read(path)?;
+    let mut buf = data.as_slice();
+    let mut items = vec![];
+    while !buf.is_empty() {
+        match LogItem::decode_length_delimited(&mut buf) {
+            Ok(item) => items.push(item),
+            Err(_) => break,
+        }
+    }
+    Ok(items)
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn append(journal: &LogJournal, line: &str, timestamp: i64) {
+        let mut item =
+            LogItem { line: line.into(), timestamp, ..Default::default() };
+        journal.append(&mut item).expect("append");
+    }
+
+    fn lines(items: Vec<LogItem>) -> Vec<String> {
+        items.into_iter().map(|item| item.line).collect()
+    }
+
+    #[test]
+    fn replays_from_an_offset_or_timestamp() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let journal =
+            LogJournal::open(dir.path().to_path_buf(), Default::default())
+                .expect("open");
+        append(&journal, "a", 10);
+        append(&journal, "b", 20);
+        append(&journal, "c", 30);
+
+        let items = journal.replay(ReplayFrom::Offset(2)).expect("replay");
+        assert_eq!(items[0].offset, 2);
+        assert_eq!(lines(items), ["b", "c"]);
+        let items = journal.replay(ReplayFrom::Timestamp(30)).expect("replay");
+        assert_eq!(lines(items), ["c"]);
+    }
+
+    #[test]
+    fn continues_after_the_items_of_a_previous_run() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let dir = scratch.path().join("journal");
+        let journal =
+            LogJournal::open(dir.clone(), Default::default()).expect("open");
+        append(&journal, "a", 10);
+        drop(journal);
+
+        let journal = LogJournal::open(dir, Default::default()).expect("open");
+        append(&journal, "b", 20);
+        let items = journal.replay(ReplayFrom::Offset(0)).expect("replay");
+        assert_eq!(items.iter().map(|i| i.offset).collect::<Vec<_>>(), [1, 2]);
+    }
+
+    #[test]
+    fn removes_the_oldest_items_past_its_size() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let dir = scratch.path().join("journal");
+        let config = LogJournalConfig { max_size: 64, max_age: None };
+        let journal = LogJournal::open(dir.clone(), config).expect("open");
+        for i in 0..20 {
+            append(&journal, &format!("line {i}"), i);
+        }
+
+        let items = journal.replay(ReplayFrom::Offset(0)).expect("replay");
+        assert!(items.len() < 20);
+        assert_eq!(items.last().expect("item").line, "line 19");
+        let size: u64 = std::fs::read_dir(&dir)
+            .expect("dir")
+            .map(|entry| entry.expect("entry").metadata().expect("len").len())
+            .sum();
+        // The segment appended to may overshoot
+        assert!(size <= 64 + 64 / SEGMENTS + 16);
+    }
+}
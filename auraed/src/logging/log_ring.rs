@@ -0,0 +1,222 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use std::sync::{Arc, Mutex};
+use tokio::sync::watch;
+
+/// A ring of raw output shared by all subscribers of a log channel.
+///
+/// Output is copied into the ring once, and every subscriber splits lines
+/// off at its own position. Compared to the broadcast channel, no line is
+/// allocated and cloned for each subscriber. Subscribers falling behind by
+/// more than the capacity skip the overwritten output.
+#[derive(Debug)]
+pub struct LogRing {
+    state: Mutex<RingState>,
+    head: watch::Sender<u64>,
+}
+
+#[derive(Debug)]
+struct RingState {
+    buf: Box<[u8]>,
+    /// Number of bytes ever written, the position of the next byte
+    head: u64,
+    closed: bool,
+}
+
+impl RingState {
+    /// The oldest position still in the ring
+    fn tail(&self) -> u64 {
+        self.head.saturating_sub(self.buf.len() as u64)
+    }
+
+    /// The contiguous bytes from `pos` up to the head or the end of `buf`
+    fn slice_from(&self, pos: u64) -> &[u8] {
+        let cap = self.buf.len() as u64;
+        let start = (pos % cap) as usize;
+        let len = (self.head - pos).min(cap - pos % cap) as usize;
+        &self.buf[start..start + len]
+    }
+}
+
+impl LogRing {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "log ring needs a capacity");
+        Self {
+            state: Mutex::new(RingState {
+                buf: vec![0; capacity].into_boxed_slice(),
+                head: 0,
+                closed: false,
+            }),
+            head: watch::channel(0).0,
+        }
+    }
+
+    /// Appends `data`, overwriting the oldest output once the ring is full.
+    pub fn write(&self, mut data: &[u8]) {
+        let head = {
+            let mut state = self.state.lock().expect("log ring");
+            let cap = state.buf.len();
+            // Only the last `cap` bytes would survive the write anyway
+            if data.len() > cap {
+                state.head += (data.len() - cap) as u64;
+                data = &data[data.len() - cap..];
+            }
+            while !data.is_empty() {
+                let start = (state.head % cap as u64) as usize;
+                let len = data.len().min(cap - start);
+                state.buf[start..start + len].copy_from_slice(&data[..len]);
+                state.head += len as u64;
+                data = &data[len..];
+            }
+            state.head
+        };
+        let _ = self.head.send_replace(head);
+    }
+
+    /// Marks the end of the output, subscribers return the remaining lines
+    /// and then [None].
+    pub fn close(&self) {
+        let head = {
+            let mut state = self.state.lock().expect("log ring");
+            state.closed = true;
+            state.head
+        };
+        let _ = self.head.send_replace(head);
+    }
+
+    /// Subscribes to the output written from now on.
+    pub fn subscribe(self: &Arc<Self>) -> LogRingReader {
+        let head = self.head.subscribe();
+        let pos = *head.borrow();
+        LogRingReader {
+            ring: self.clone(),
+            head,
+            pos,
+            line: Vec::new(),
+            lagged: false,
+        }
+    }
+}
+
+/// Reads the lines of a [LogRing] from its own position
+#[derive(Debug)]
+pub struct LogRingReader {
+    ring: Arc<LogRing>,
+    head: watch::Receiver<u64>,
+    pos: u64,
+    /// The start of a line whose end has not been written yet
+    line: Vec<u8>,
+    /// Whether the start of the current line was overwritten
+    lagged: bool,
+}
+
+impl LogRingReader {
+    /// Waits for the next line, returns [None] once the ring is closed and
+    /// all lines have been read. Lines longer than the ring are split.
+    pub async fn next_line(&mut self) -> Option<String> {
+        loop {
+            // Writes from now on wake us up, so none is missed while scanning
+            let _ = self.head.borrow_and_update();
+            {
+                let state = self.ring.state.lock().expect("log ring");
+                if self.pos < state.tail() {
+                    // Skip the rest of the line whose start was overwritten
+                    self.pos = state.tail();
+                    self.line.clear();
+                    self.lagged = true;
+                }
+                while self.pos < state.head {
+                    let bytes = state.slice_from(self.pos);
+                    let room = state.buf.len() - self.line.len();
+                    let bytes = &bytes[..bytes.len().min(room)];
+                    let end = bytes.iter().position(|b| *b == b'\n');
+                    let taken = end.unwrap_or(bytes.len());
+                    self.pos += end.map_or(taken, |end| end + 1) as u64;
+                    if self.lagged {
+                        self.lagged = end.is_none();
+                        continue;
+                    }
+                    self.line.extend_from_slice(&bytes[..taken]);
+                    if end.is_some() || self.line.len() == state.buf.len() {
+                        return Some(take_line(&mut self.line));
+                    }
+                }
+                if state.closed {
+                    return (!self.line.is_empty())
+                        .then(|| take_line(&mut self.line));
+                }
+            }
+            // The sender lives in the ring we hold, so this never fails
+            let _ = self.head.changed().await;
+        }
+    }
+}
+
+fn take_line(line: &mut Vec<u8>) -> String {
+    String::from_utf8(std::mem::take(line))
+        .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn reads_lines_written_in_pieces() {
+        let ring = Arc::new(LogRing::new(16));
+        let mut reader = ring.subscribe();
+
+        ring.write(b"hel");
+        ring.write(b"lo\naurae\nb");
+        ring.write(b"ye");
+        ring.close();
+
+        assert_eq!(reader.next_line().await.as_deref(), Some("hello"));
+        assert_eq!(reader.next_line().await.as_deref(), Some("aurae"));
+        assert_eq!(reader.next_line().await.as_deref(), Some("bye"));
+        assert_eq!(reader.next_line().await, None);
+    }
+
+    #[tokio::test]
+    async fn lagging_reader_skips_overwritten_output() {
+        let ring = Arc::new(LogRing::new(8));
+        let mut reader = ring.subscribe();
+
+        ring.write(b"first\nsecond\nthird\n");
+        ring.close();
+
+        // Only "d\nthird\n" is left, the partial line is dropped
+        assert_eq!(reader.next_line().await.as_deref(), Some("third"));
+        assert_eq!(reader.next_line().await, None);
+    }
+
+    #[tokio::test]
+    async fn waits_for_lines() {
+        let ring = Arc::new(LogRing::new(16));
+        let mut reader = ring.subscribe();
+
+        let writer = {
+            let ring = ring.clone();
+            tokio::spawn(async move {
+                tokio::task::yield_now().await;
+                ring.write(b"hello\n");
+            })
+        };
+
+        assert_eq!(reader.next_line().await.as_deref(), Some("hello"));
+        writer.await.unwrap();
+    }
+}
@@ -22,7 +22,22 @@ use std::time::SystemTime;
 /// LogChannel provides channels between Log producers and log consumers
 pub mod log_channel;
 
-/// Implements Log trait. Used to add grpc API to log targets for rust internal logging
+/// On-disk journal of the items of a log channel, replayed to late consumers
+pub mod log_journal;
+
+/// Ring of raw output shared by all consumers of a chatty log channel
+pub mod log_ring;
+
+/// Forwards the items of all log channels to a syslog endpoint
+pub mod syslog_sink;
+
+/// Filter of the logs of auraed, changed at runtime
+pub mod log_filter;
+
+/// Formats of the logs auraed writes to stdout
+pub mod log_format;
+
+/// Implements a tracing Layer. Used to add grpc API to log targets for rust internal logging
 pub mod stream_logger;
 
 /// Get UNIX timestamp in seconds for logging
@@ -13,44 +13,64 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use log::Log;
-use proto::observe::LogItem;
-use tokio::sync::broadcast::Sender;
-
-/// Sends log messages generated in rust code to the logging channel
-/// The logging channel is consumed by the observe API
-#[derive(Debug)]
-pub struct StreamLogger {
-    /// Channel used to send log messages to grpc API
-    pub producer: Sender<LogItem>,
+use super::log_channel::LogChannel;
+use lazy_static::lazy_static;
+use proto::observe::LogSeverity;
+use std::fmt::{Debug, Write};
+use tracing::{field::Field, field::Visit, Event, Level, Subscriber};
+use tracing_subscriber::{layer::Context, Layer};
+
+lazy_static! {
+    /// The channel the logs of auraed itself are sent to.
+    /// The channel is consumed by the observe API
+    pub static ref DAEMON_LOG_CHANNEL: LogChannel =
+        LogChannel::new(String::from("auraed"));
 }
 
-impl StreamLogger {
-    #[allow(unused)]
-    /// Constructor requires channel between api and logger
-    pub fn new(producer: Sender<LogItem>) -> StreamLogger {
-        StreamLogger { producer }
+/// Sends the tracing events generated in rust code to [DAEMON_LOG_CHANNEL]
+#[derive(Debug, Default)]
+pub struct StreamLogger;
+
+impl<S: Subscriber> Layer<S> for StreamLogger {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let mut line = format!("{}:", metadata.target());
+        event.record(&mut LineVisitor(&mut line));
+
+        DAEMON_LOG_CHANNEL.log(severity(metadata.level()), line);
     }
 }
 
-impl Log for StreamLogger {
-    fn enabled(&self, _metadata: &log::Metadata) -> bool {
-        true
+fn severity(level: &Level) -> LogSeverity {
+    match *level {
+        Level::TRACE => LogSeverity::Trace,
+        Level::DEBUG => LogSeverity::Debug,
+        Level::INFO => LogSeverity::Info,
+        Level::WARN => LogSeverity::Warn,
+        Level::ERROR => LogSeverity::Error,
     }
+}
+
+/// Writes the message of an event, followed by its other fields
+struct LineVisitor<'a>(&'a mut String);
 
-    fn log(&self, record: &log::Record) {
-        // send returns an Err if there are no receivers. We ignore that.
-        let _ = self.producer.send(LogItem {
-            channel: "rust-logs".to_string(),
-            line: format!(
-                "{}:{} -- {}",
-                record.level(),
-                record.target(),
-                record.args()
-            ),
-            timestamp: 0,
-        });
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        let _ = match field.name() {
+            "message" => write!(self.0, " {value:?}"),
+            name => write!(self.0, " {name}={value:?}"),
+        };
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn flush(&self) {}
-}
\ No newline at end of file
+    #[test]
+    fn test_severity_follows_level() {
+        assert_eq!(severity(&Level::TRACE), LogSeverity::Trace);
+        assert_eq!(severity(&Level::INFO), LogSeverity::Info);
+        assert_eq!(severity(&Level::ERROR), LogSeverity::Error);
+    }
+}
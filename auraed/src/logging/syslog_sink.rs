@@ -0,0 +1,358 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Forwards the items of all log channels to a syslog endpoint, for hosts
+//! collecting their logs with syslog.
+//!
+//! Items are sent as RFC 5424 messages. The output of workloads is of the
+//! user facility and goes by the name of its executable or container, the
+//! logs of auraed are of the daemon facility. The cell, executable, pod and
+//! container of an item are sent as structured data. Messages are sent over
+//! TCP with octet counting framing (RFC 6587), and as datagrams otherwise.
+
+use chrono::{TimeZone, Utc};
+use proto::observe::{LogItem, LogSeverity};
+use std::{
+    fmt::Write as _,
+    io,
+    path::PathBuf,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{
+    io::AsyncWriteExt,
+    net::{TcpStream, UdpSocket, UnixDatagram},
+    sync::mpsc,
+};
+use tracing::warn;
+
+/// Items buffered while the endpoint is slow, past which items are dropped
+const BUFFERED_ITEMS: usize = 1024;
+/// Time waited before connecting again to an unreachable endpoint
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Longest datagram sent, RFC 5426 receivers should accept this much
+const MAX_DATAGRAM: usize = 2048;
+/// Private enterprise number of the structured data of items. The number
+/// is the one reserved for documentation by RFC 5612.
+const SD_ID: &str = "aurae@32473";
+
+const FACILITY_USER: u8 = 1;
+const FACILITY_DAEMON: u8 = 3;
+
+/// Where log items are forwarded to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogEndpoint {
+    /// A unix datagram socket, such as /dev/log
+    Unix(PathBuf),
+    /// The host:port of a UDP endpoint
+    Udp(String),
+    /// The host:port of a TCP endpoint
+    Tcp(String),
+}
+
+impl FromStr for SyslogEndpoint {
+    type Err = InvalidSyslogEndpoint;
+
+    /// Parses `unix:///path`, `udp://host:port` or `tcp://host:port`.
+    fn from_str(endpoint: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidSyslogEndpoint(endpoint.to_string());
+        let (scheme, address) =
+            endpoint.split_once("://").ok_or_else(invalid)?;
+        match scheme {
+            "unix" if address.starts_with('/') => {
+                Ok(Self::Unix(PathBuf::from(address)))
+            }
+            "udp" | "tcp"
+                if address.rsplit_once(':').is_some_and(|(host, port)| {
+                    !host.is_empty() && port.parse::<u16>().is_ok()
+                }) =>
+            {
+                Ok(if scheme == "udp" {
+                    Self::Udp(address.to_string())
+                } else {
+                    Self::Tcp(address.to_string())
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// A syslog endpoint auraed can not forward to.
+#[derive(Debug, Error)]
+#[error(
+    "invalid syslog endpoint '{0}', expected unix:///path, udp://host:port or tcp://host:port"
+)]
+pub struct InvalidSyslogEndpoint(String);
+
+/// Forwards the log items sent to it to a syslog endpoint.
+#[derive(Debug, Clone)]
+pub struct SyslogSink {
+    tx: mpsc::Sender<LogItem>,
+}
+
+impl SyslogSink {
+    /// Starts forwarding to `endpoint`. Items sent while the endpoint is
+    /// unreachable are dropped.
+    pub fn spawn(endpoint: SyslogEndpoint) -> Self {
+        let (tx, mut rx) = mpsc::channel::<LogItem>(BUFFERED_ITEMS);
+        let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+            .map(|hostname| hostname.trim().to_string())
+            .unwrap_or_default();
+
+        let _ignored = tokio::spawn(async move {
+            let mut connection = None;
+            let mut retry_at = Instant::now();
+            // Only the first of consecutive failures is logged, as the logs
+            // of auraed are forwarded as well
+            let mut failing = false;
+            while let Some(item) = rx.recv().await {
+                if connection.is_none() {
+                    if Instant::now() < retry_at {
+                        continue;
+                    }
+                    match Connection::open(&endpoint).await {
+                        Ok(opened) => connection = Some(opened),
+                        Err(e) => {
+                            if !failing {
+                                warn!(
+                                    "Failed to connect to syslog {endpoint:?}: {e}"
+                                );
+                            }
+                            failing = true;
+                            retry_at = Instant::now() + RECONNECT_DELAY;
+                            continue;
+                        }
+                    }
+                }
+                let message = format_message(&item, &hostname);
+                let sent = match &mut connection {
+                    Some(connection) => connection.send(&message).await,
+                    None => continue,
+                };
+                match sent {
+                    Ok(()) => failing = false,
+                    Err(e) => {
+                        if !failing {
+                            warn!("Failed to send to syslog {endpoint:?}: {e}");
+                        }
+                        failing = true;
+                        connection = None;
+                        retry_at = Instant::now() + RECONNECT_DELAY;
+                    }
+                }
+            }
+        });
+
+        Self { tx }
+    }
+
+    /// Where the items to forward are sent to.
+    pub fn sender(&self) -> mpsc::Sender<LogItem> {
+        self.tx.clone()
+    }
+}
+
+#[derive(Debug)]
+enum Connection {
+    Unix(UnixDatagram),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Connection {
+    async fn open(endpoint: &SyslogEndpoint) -> io::Result<Self> {
+        Ok(match endpoint {
+            SyslogEndpoint::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Connection::Unix(socket)
+            }
+            SyslogEndpoint::Udp(address) => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(address).await?;
+                Connection::Udp(socket)
+            }
+            SyslogEndpoint::Tcp(address) => {
+                Connection::Tcp(TcpStream::connect(address).await?)
+            }
+        })
+    }
+
+    async fn send(&mut self, message: &str) -> io::Result<()> {
+        match self {
+            Connection::Unix(socket) => {
+                let _ = socket.send(truncate(message, MAX_DATAGRAM)).await?;
+            }
+            Connection::Udp(socket) => {
+                let _ = socket.send(truncate(message, MAX_DATAGRAM)).await?;
+            }
+            Connection::Tcp(stream) => {
+                let frame = format!("{} {message}", message.len());
+                stream.write_all(frame.as_bytes()).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The first `max` bytes of `message`, cut at a character boundary.
+fn truncate(message: &str, max: usize) -> &[u8] {
+    let mut end = message.len().min(max);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    &message.as_bytes()[..end]
+}
+
+/// The RFC 5424 message of `item`.
+fn format_message(item: &LogItem, hostname: &str) -> String {
+    let daemon =
+        item.executable_name.is_empty() && item.container_id.is_empty();
+    let facility = if daemon { FACILITY_DAEMON } else { FACILITY_USER };
+    let severity = match LogSeverity::from_i32(item.severity) {
+        Some(LogSeverity::Error) => 3,
+        Some(LogSeverity::Warn) => 4,
+        Some(LogSeverity::Debug | LogSeverity::Trace) => 7,
+        _ => 6,
+    };
+    let timestamp = Utc
+        .timestamp_opt(item.timestamp, 0)
+        .single()
+        .map(|timestamp| timestamp.to_rfc3339())
+        .unwrap_or_else(|| "-".into());
+    let app_name = [&item.executable_name, &item.container_id]
+        .into_iter()
+        .find(|name| !name.is_empty())
+        .map_or("auraed", String::as_str);
+
+    let mut data = String::new();
+    for (name, value) in [
+        ("cell", &item.cell_name),
+        ("executable", &item.executable_name),
+        ("pod", &item.pod_sandbox_id),
+        ("container", &item.container_id),
+    ] {
+        if !value.is_empty() {
+            let _ = write!(data, " {name}=\"{}\"", escape_param(value));
+        }
+    }
+    let data =
+        if data.is_empty() { "-".into() } else { format!("[{SD_ID}{data}]") };
+
+    format!(
+        "<{}>1 {timestamp} {} {} - - {data} {}",
+        facility * 8 + severity,
+        header_field(hostname, 255),
+        header_field(app_name, 48),
+        item.line
+    )
+}
+
+/// A header field of printable ASCII of at most `max` characters, as RFC
+/// 5424 requires.
+fn header_field(value: &str, max: usize) -> String {
+    let field: String =
+        value.chars().filter(|c| c.is_ascii_graphic()).take(max).collect();
+    if field.is_empty() {
+        "-".into()
+    } else {
+        field
+    }
+}
+
+/// Escapes the characters a structured data parameter value can not hold.
+fn escape_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_endpoints() {
+        assert_eq!(
+            "unix:///dev/log".parse::<SyslogEndpoint>().expect("parse"),
+            SyslogEndpoint::Unix("/dev/log".into())
+        );
+        assert_eq!(
+            "udp://10.0.0.1:514".parse::<SyslogEndpoint>().expect("parse"),
+            SyslogEndpoint::Udp("10.0.0.1:514".into())
+        );
+        assert_eq!(
+            "tcp://logs:601".parse::<SyslogEndpoint>().expect("parse"),
+            SyslogEndpoint::Tcp("logs:601".into())
+        );
+        assert!("unix://dev/log".parse::<SyslogEndpoint>().is_err());
+        assert!("udp://10.0.0.1".parse::<SyslogEndpoint>().is_err());
+        assert!("http://logs:80".parse::<SyslogEndpoint>().is_err());
+    }
+
+    #[test]
+    fn formats_rfc5424_messages() {
+        let item = LogItem {
+            line: "listening".into(),
+            timestamp: 0,
+            severity: LogSeverity::Error as i32,
+            pod_sandbox_id: "pod".into(),
+            container_id: "nginx".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            format_message(&item, "node 1"),
+            "<11>1 1970-01-01T00:00:00+00:00 node1 nginx - - \
+             [aurae@32473 pod=\"pod\" container=\"nginx\"] listening"
+        );
+
+        let item = LogItem {
+            line: "started".into(),
+            severity: LogSeverity::Info as i32,
+            ..Default::default()
+        };
+        assert!(format_message(&item, "node")
+            .starts_with("<30>1 1970-01-01T00:00:00+00:00 node auraed - - - "));
+    }
+
+    #[test]
+    fn escapes_structured_data() {
+        assert_eq!(escape_param(r#"a"b\c]"#), r#"a\"b\\c\]"#);
+    }
+
+    #[tokio::test]
+    async fn forwards_items_over_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.expect("bind");
+        let address = server.local_addr().expect("address").to_string();
+        let sink = SyslogSink::spawn(SyslogEndpoint::Udp(address));
+
+        sink.sender()
+            .send(LogItem { line: "hello".into(), ..Default::default() })
+            .await
+            .expect("send");
+
+        let mut buf = [0u8; MAX_DATAGRAM];
+        let n = server.recv(&mut buf).await.expect("recv");
+        let message = String::from_utf8_lossy(&buf[..n]);
+        assert!(message.ends_with(" - hello"), "{message}");
+    }
+}
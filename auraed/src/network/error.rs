@@ -0,0 +1,55 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use thiserror::Error;
+use tonic::Status;
+use tracing::error;
+
+pub(crate) type Result<T> = std::result::Result<T, NetworkServiceError>;
+
+#[derive(Debug, Error)]
+pub(crate) enum NetworkServiceError {
+    #[error("invalid {field}: {reason}")]
+    Invalid { field: &'static str, reason: String },
+    #[error("{kind} '{name}' already exists")]
+    AlreadyExists { kind: &'static str, name: String },
+    #[error("{kind} '{name}' not found")]
+    NotFound { kind: &'static str, name: String },
+    #[error("failed to apply nftables ruleset: {source}")]
+    FailedToApply { source: anyhow::Error },
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+}
+
+impl From<NetworkServiceError> for Status {
+    fn from(err: NetworkServiceError) -> Self {
+        let msg = err.to_string();
+        error!("{msg}");
+        match err {
+            NetworkServiceError::Invalid { .. } => {
+                Status::invalid_argument(msg)
+            }
+            NetworkServiceError::AlreadyExists { .. } => {
+                Status::already_exists(msg)
+            }
+            NetworkServiceError::NotFound { .. } => Status::not_found(msg),
+            NetworkServiceError::FailedToApply { .. }
+            | NetworkServiceError::IO(_)
+            | NetworkServiceError::Serde(_) => Status::internal(msg),
+        }
+    }
+}
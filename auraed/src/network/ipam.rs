@@ -0,0 +1,455 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! IP address management for the network namespaces auraed attaches to the
+//! host itself: cells isolating their network and pod sandboxes while no CNI
+//! network is configured, see [super::veth].
+//!
+//! Each kind of workload is addressed from its own pool. The first address
+//! of a pool is the gateway of its namespaces and, like the reserved
+//! addresses of the [IpamConfig], never leased. Leases are persisted in the
+//! runtime directory along with the pid and start time of the process
+//! holding the namespace, so a restarted auraed does not hand out the
+//! addresses of the cells and pods that outlived it. Leases of processes that
+//! exited, including those whose pid was since reused, are dropped when the
+//! leases are loaded.
+
+use super::{veth::enable_forwarding, NetworkService};
+use crate::process_start::start_time;
+use anyhow::Context;
+use ipnetwork::Ipv4Network;
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::{self, Display},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use thiserror::Error;
+use tokio::{io::AsyncWriteExt, sync::Mutex};
+
+/// The pools addresses are leased from.
+#[derive(Debug, Clone)]
+pub struct IpamConfig {
+    /// Pool of the cells isolating their network. Defaults to
+    /// 10.250.0.0/16.
+    pub cell_pool: Ipv4Network,
+    /// Pool of the pod sandboxes while no CNI network is configured.
+    /// Defaults to 10.251.0.0/16.
+    pub pod_pool: Ipv4Network,
+    /// Addresses of the pools that are never leased, e.g. because they are
+    /// assigned by other means.
+    pub reserved: Vec<Ipv4Addr>,
+}
+
+impl Default for IpamConfig {
+    fn default() -> Self {
+        Self {
+            cell_pool: Ipv4Network::new(Ipv4Addr::new(10, 250, 0, 0), 16)
+                .expect("valid network"),
+            pod_pool: Ipv4Network::new(Ipv4Addr::new(10, 251, 0, 0), 16)
+                .expect("valid network"),
+            reserved: vec![],
+        }
+    }
+}
+
+impl IpamConfig {
+    fn network(&self, pool: Pool) -> Ipv4Network {
+        match pool {
+            Pool::Cells => self.cell_pool,
+            Pool::Pods => self.pod_pool,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum Pool {
+    Cells,
+    Pods,
+}
+
+impl Display for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pool::Cells => write!(f, "cells"),
+            Pool::Pods => write!(f, "pods"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum IpamError {
+    #[error("no address left in the {pool} pool {network}")]
+    Exhausted { pool: Pool, network: Ipv4Network },
+    #[error("failed to persist the leases to '{path}': {source}")]
+    Persist { path: String, source: anyhow::Error },
+    #[error("failed to read the start time of process {pid}: {source}")]
+    Process { pid: i32, source: procfs::ProcError },
+}
+
+/// An address leased to a network namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Lease {
+    pub address: Ipv4Addr,
+    /// The pool the address was leased from
+    pub network: Ipv4Network,
+}
+
+impl Lease {
+    /// The address the namespace routes through.
+    pub fn gateway(&self) -> Ipv4Addr {
+        gateway(self.network)
+    }
+}
+
+fn gateway(network: Ipv4Network) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(network.network()) + 1)
+}
+
+/// The lease of an address to an owner, a cell or pod sandbox.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Record {
+    pool: Pool,
+    owner: String,
+    address: Ipv4Addr,
+    /// The process holding the network namespace the address is assigned in
+    pid: i32,
+    /// The start time of the process in clock ticks after boot, which tells
+    /// the process apart from a later one reusing its pid
+    start_time: u64,
+}
+
+impl Record {
+    /// Whether the process holding the namespace is still running
+    fn is_running(&self) -> bool {
+        start_time(self.pid).ok() == Some(self.start_time)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Leases {
+    leases: Vec<Record>,
+}
+
+impl Leases {
+    /// Leases the first free address of `pool` to `owner`, or the address
+    /// it already holds.
+    fn allocate(
+        &mut self,
+        config: &IpamConfig,
+        pool: Pool,
+        owner: &str,
+        pid: i32,
+        start_time: u64,
+    ) -> Option<Ipv4Addr> {
+        if let Some(record) = self
+            .leases
+            .iter_mut()
+            .find(|record| record.pool == pool && record.owner == owner)
+        {
+            record.pid = pid;
+            record.start_time = start_time;
+            return Some(record.address);
+        }
+
+        let network = config.network(pool);
+        let gateway = gateway(network);
+        let address = network.iter().find(|address| {
+            *address != network.network()
+                && *address != network.broadcast()
+                && *address != gateway
+                && !config.reserved.contains(address)
+                && !self.leases.iter().any(|record| record.address == *address)
+        })?;
+        self.leases.push(Record {
+            pool,
+            owner: owner.to_string(),
+            address,
+            pid,
+            start_time,
+        });
+        Some(address)
+    }
+
+    /// Takes over the `records` whose owner holds no address of their pool
+    /// yet and whose address is free.
+    fn restore(&mut self, records: Vec<Record>) {
+        for record in records {
+            let taken = self.leases.iter().any(|lease| {
+                lease.address == record.address
+                    || (lease.pool == record.pool
+                        && lease.owner == record.owner)
+            });
+            if !taken {
+                self.leases.push(record);
+            }
+        }
+    }
+
+    /// Returns the address of `owner` to the pool, false if it held none.
+    fn release(&mut self, pool: Pool, owner: &str) -> bool {
+        let before = self.leases.len();
+        self.leases
+            .retain(|record| record.pool != pool || record.owner != owner);
+        self.leases.len() != before
+    }
+}
+
+/// The leases of the node, shared by the services attaching namespaces.
+#[derive(Debug, Clone)]
+pub(crate) struct Ipam {
+    config: IpamConfig,
+    path: PathBuf,
+    leases: Arc<Mutex<Leases>>,
+}
+
+impl Ipam {
+    /// Loads the leases persisted at `path`, keeping those of the processes
+    /// that are still running.
+    pub async fn open(
+        config: IpamConfig,
+        path: PathBuf,
+    ) -> Result<Self, IpamError> {
+        let persist_error = |source: anyhow::Error| IpamError::Persist {
+            path: path.display().to_string(),
+            source,
+        };
+
+        let mut leases = load(&path).await.map_err(persist_error)?;
+        leases.leases.retain(Record::is_running);
+        save(&path, &leases).await.map_err(persist_error)?;
+
+        Ok(Self { config, path, leases: Arc::new(Mutex::new(leases)) })
+    }
+
+    /// Leases an address of `pool` to `owner`, whose namespace is held by
+    /// `pid`.
+    pub async fn allocate(
+        &self,
+        pool: Pool,
+        owner: &str,
+        pid: i32,
+    ) -> Result<Lease, IpamError> {
+        let start_time = start_time(pid)
+            .map_err(|source| IpamError::Process { pid, source })?;
+        let mut leases = self.leases.lock().await;
+        let network = self.config.network(pool);
+        let address = leases
+            .allocate(&self.config, pool, owner, pid, start_time)
+            .ok_or(IpamError::Exhausted { pool, network })?;
+        self.save(&leases).await?;
+        Ok(Lease { address, network })
+    }
+
+    /// Returns the address of `owner` to `pool`, if it holds one.
+    pub async fn release(
+        &self,
+        pool: Pool,
+        owner: &str,
+    ) -> Result<(), IpamError> {
+        let mut leases = self.leases.lock().await;
+        if leases.release(pool, owner) {
+            self.save(&leases).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the leases to `path` for a node checkpoint, see
+    /// [Ipam::restore].
+    pub async fn checkpoint(&self, path: &Path) -> Result<(), IpamError> {
+        let leases = self.leases.lock().await;
+        save(path, &leases).await.map_err(|source| IpamError::Persist {
+            path: path.display().to_string(),
+            source,
+        })
+    }
+
+    /// Takes over the leases of a node checkpoint written to `path` by
+    /// [Ipam::checkpoint] that do not collide with the leases held. The
+    /// owners get their addresses back once their namespaces are attached
+    /// again.
+    pub async fn restore(&self, path: &Path) -> Result<(), IpamError> {
+        let checkpointed = load(path).await.map_err(|source| {
+            IpamError::Persist { path: path.display().to_string(), source }
+        })?;
+        let mut leases = self.leases.lock().await;
+        leases.restore(checkpointed.leases);
+        self.save(&leases).await
+    }
+
+    /// Lets the attached namespaces reach other networks through the host,
+    /// by forwarding their traffic and masquerading each pool with a
+    /// masquerade named after it in `network`.
+    pub async fn provision_network(
+        &self,
+        network: &NetworkService,
+    ) -> anyhow::Result<()> {
+        enable_forwarding()
+            .await
+            .context("failed to enable IPv4 forwarding")?;
+        for pool in [Pool::Cells, Pool::Pods] {
+            network
+                .ensure_masquerade(
+                    &pool.to_string(),
+                    self.config.network(pool).into(),
+                )
+                .await
+                .with_context(|| {
+                    format!("failed to masquerade the {pool} address pool")
+                })?;
+        }
+        Ok(())
+    }
+
+    async fn save(&self, leases: &Leases) -> Result<(), IpamError> {
+        save(&self.path, leases).await.map_err(|source| IpamError::Persist {
+            path: self.path.display().to_string(),
+            source,
+        })
+    }
+}
+
+async fn load(path: &Path) -> anyhow::Result<Leases> {
+    if !path.exists() {
+        return Ok(Leases::default());
+    }
+    let contents = tokio::fs::read(path).await?;
+    Ok(serde_json::from_slice(&contents)?)
+}
+
+async fn save(path: &Path, leases: &Leases) -> anyhow::Result<()> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // A crash while writing leaves the previous leases in place
+    let tmp = path.with_extension("tmp");
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(&serde_json::to_vec(leases)?).await?;
+    file.sync_all().await?;
+    tokio::fs::rename(&tmp, path).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> IpamConfig {
+        IpamConfig {
+            cell_pool: Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), 29)
+                .expect("network"),
+            pod_pool: Ipv4Network::new(Ipv4Addr::new(10, 0, 1, 0), 24)
+                .expect("network"),
+            reserved: vec![Ipv4Addr::new(10, 0, 0, 3)],
+        }
+    }
+
+    #[test]
+    fn test_allocate_skips_gateway_and_reserved_addresses() {
+        let config = config();
+        let mut leases = Leases::default();
+
+        let a = leases.allocate(&config, Pool::Cells, "a", 1, 0);
+        assert_eq!(a, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        // the same owner keeps its address
+        assert_eq!(leases.allocate(&config, Pool::Cells, "a", 2, 0), a);
+        assert_eq!(
+            leases.allocate(&config, Pool::Cells, "b", 1, 0),
+            Some(Ipv4Addr::new(10, 0, 0, 4))
+        );
+        // owners are distinct per pool
+        assert_eq!(
+            leases.allocate(&config, Pool::Pods, "a", 1, 0),
+            Some(Ipv4Addr::new(10, 0, 1, 2))
+        );
+
+        for owner in ["c", "d"] {
+            assert!(leases
+                .allocate(&config, Pool::Cells, owner, 1, 0)
+                .is_some());
+        }
+        assert_eq!(leases.allocate(&config, Pool::Cells, "e", 1, 0), None);
+
+        assert!(leases.release(Pool::Cells, "b"));
+        assert!(!leases.release(Pool::Cells, "b"));
+        assert_eq!(
+            leases.allocate(&config, Pool::Cells, "e", 1, 0),
+            Some(Ipv4Addr::new(10, 0, 0, 4))
+        );
+    }
+
+    #[test]
+    fn test_restored_owners_get_their_addresses_back() {
+        let config = config();
+        let mut checkpointed = Leases::default();
+        for owner in ["a", "b", "c"] {
+            let _ = checkpointed.allocate(&config, Pool::Cells, owner, 1, 0);
+        }
+
+        // after the reboot b leased the address a held
+        let mut leases = Leases::default();
+        let b = leases.allocate(&config, Pool::Cells, "b", 2, 0);
+        assert_eq!(b, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        leases.restore(checkpointed.leases);
+
+        assert_eq!(leases.allocate(&config, Pool::Cells, "b", 3, 0), b);
+        assert_eq!(
+            leases.allocate(&config, Pool::Cells, "c", 3, 0),
+            Some(Ipv4Addr::new(10, 0, 0, 5))
+        );
+        // the address of a was taken, it leases the first free one
+        assert_eq!(
+            leases.allocate(&config, Pool::Cells, "a", 3, 0),
+            Some(Ipv4Addr::new(10, 0, 0, 4))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_leases_of_running_processes_survive_restarts() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("leases.json");
+        let running = std::process::id() as i32;
+
+        let ipam = Ipam::open(config(), path.clone()).await.expect("open");
+        let lease =
+            ipam.allocate(Pool::Cells, "a", running).await.expect("lease");
+        {
+            let mut leases = ipam.leases.lock().await;
+            let start_time = start_time(running).expect("start time");
+            // a process that is gone, and one whose pid was reused
+            let _ =
+                leases.allocate(&ipam.config, Pool::Cells, "b", i32::MAX, 0);
+            let _ = leases.allocate(
+                &ipam.config,
+                Pool::Cells,
+                "c",
+                running,
+                start_time + 1,
+            );
+            ipam.save(&leases).await.expect("save");
+        }
+        drop(ipam);
+
+        let ipam = Ipam::open(config(), path).await.expect("reopen");
+        // the addresses of b and c were freed as their processes are gone
+        let d = ipam.allocate(Pool::Cells, "d", running).await.expect("lease");
+        assert_eq!(d.address, Ipv4Addr::new(10, 0, 0, 4));
+        let e = ipam.allocate(Pool::Cells, "e", running).await.expect("lease");
+        assert_eq!(e.address, Ipv4Addr::new(10, 0, 0, 5));
+        let a = ipam.allocate(Pool::Cells, "a", running).await.expect("lease");
+        assert_eq!(a, lease);
+    }
+}
@@ -0,0 +1,36 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Node level networking rules.
+//!
+//! All NAT and filtering rules auraed needs are owned by the [NetworkService]
+//! and kept in a single `aurae` nftables table. The desired state is persisted
+//! in the runtime directory and the table is replaced as a whole on every
+//! change, so rules are reconciled when auraed restarts.
+//!
+//! Cells isolating their network and pod sandboxes without a CNI network are
+//! attached to the host with [Veth] pairs, addressed from the pools of the
+//! node's [Ipam].
+
+pub use ipam::IpamConfig;
+pub(crate) use ipam::{Ipam, Pool};
+pub(crate) use network_service::NetworkService;
+pub(crate) use veth::Veth;
+
+mod error;
+mod ipam;
+mod network_service;
+mod ruleset;
+mod veth;
@@ -0,0 +1,453 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::{
+    error::{NetworkServiceError, Result},
+    ruleset::{EgressPolicy, Masquerade, PortMapping, Protocol, Ruleset},
+};
+use crate::cells::CellName;
+use ipnetwork::IpNetwork;
+use proto::network::{
+    self, network_service_server, NetworkServiceAddMasqueradeRequest,
+    NetworkServiceAddMasqueradeResponse, NetworkServiceAddPortMappingRequest,
+    NetworkServiceAddPortMappingResponse, NetworkServiceListRequest,
+    NetworkServiceListResponse, NetworkServiceRemoveEgressPolicyRequest,
+    NetworkServiceRemoveEgressPolicyResponse,
+    NetworkServiceRemoveMasqueradeRequest,
+    NetworkServiceRemoveMasqueradeResponse,
+    NetworkServiceRemovePortMappingRequest,
+    NetworkServiceRemovePortMappingResponse,
+    NetworkServiceSetEgressPolicyRequest,
+    NetworkServiceSetEgressPolicyResponse,
+};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+use tokio::sync::Mutex;
+use tonic::{Request, Response, Status};
+use tracing::info;
+use validation::ValidatedField;
+
+/// NetworkService owns the `aurae` nftables table of the node.
+#[derive(Debug, Clone)]
+pub struct NetworkService {
+    ruleset: Arc<Mutex<Ruleset>>,
+    state_path: PathBuf,
+}
+
+impl NetworkService {
+    /// Create a new NetworkService persisting its state below `state_dir`.
+    pub fn new(state_dir: PathBuf) -> Self {
+        Self {
+            ruleset: Default::default(),
+            state_path: state_dir.join("ruleset.json"),
+        }
+    }
+
+    /// Restore the ruleset persisted by a previous run of auraed and apply it,
+    /// replacing whatever the `aurae` table contains.
+    pub async fn reconcile(&self) -> Result<()> {
+        let Some(ruleset) = Ruleset::load(&self.state_path)
+            .await
+            .map_err(|e| NetworkServiceError::FailedToApply { source: e })?
+        else {
+            return Ok(());
+        };
+
+        info!("Reconciling nftables table with {}", self.state_path.display());
+        ruleset
+            .apply()
+            .await
+            .map_err(|e| NetworkServiceError::FailedToApply { source: e })?;
+        *self.ruleset.lock().await = ruleset;
+        Ok(())
+    }
+
+    /// Apply a change to the ruleset. The change only takes effect if the
+    /// resulting ruleset could be applied.
+    async fn update(
+        &self,
+        change: impl FnOnce(&mut Ruleset) -> Result<()>,
+    ) -> Result<()> {
+        let mut ruleset = self.ruleset.lock().await;
+        let mut next = ruleset.clone();
+        change(&mut next)?;
+
+        next.apply()
+            .await
+            .map_err(|e| NetworkServiceError::FailedToApply { source: e })?;
+        next.save(&self.state_path)
+            .await
+            .map_err(|e| NetworkServiceError::FailedToApply { source: e })?;
+
+        *ruleset = next;
+        Ok(())
+    }
+
+    /// Masquerade the traffic from `source` under `name`, for networks auraed
+    /// manages itself. Replaces the masquerade if it exists.
+    pub(crate) async fn ensure_masquerade(
+        &self,
+        name: &str,
+        source: IpNetwork,
+    ) -> Result<()> {
+        self.update(|ruleset| {
+            let _ = ruleset
+                .masquerades
+                .insert(name.to_string(), Masquerade { source });
+            Ok(())
+        })
+        .await
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn add_port_mapping(
+        &self,
+        request: NetworkServiceAddPortMappingRequest,
+    ) -> Result<NetworkServiceAddPortMappingResponse> {
+        let Some(mapping) = request.port_mapping else {
+            return Err(NetworkServiceError::Invalid {
+                field: "port_mapping",
+                reason: "required".into(),
+            });
+        };
+        let name = required_name(mapping.name.clone())?;
+        let mapping = PortMapping::try_from(mapping)?;
+
+        self.update(|ruleset| {
+            if ruleset.port_mappings.contains_key(&name) {
+                return Err(NetworkServiceError::AlreadyExists {
+                    kind: "port mapping",
+                    name,
+                });
+            }
+            let _ = ruleset.port_mappings.insert(name, mapping);
+            Ok(())
+        })
+        .await?;
+
+        Ok(NetworkServiceAddPortMappingResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_port_mapping(
+        &self,
+        request: NetworkServiceRemovePortMappingRequest,
+    ) -> Result<NetworkServiceRemovePortMappingResponse> {
+        self.update(|ruleset| {
+            ruleset.port_mappings.remove(&request.name).map(|_| ()).ok_or(
+                NetworkServiceError::NotFound {
+                    kind: "port mapping",
+                    name: request.name,
+                },
+            )
+        })
+        .await?;
+
+        Ok(NetworkServiceRemovePortMappingResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn add_masquerade(
+        &self,
+        request: NetworkServiceAddMasqueradeRequest,
+    ) -> Result<NetworkServiceAddMasqueradeResponse> {
+        let Some(masquerade) = request.masquerade else {
+            return Err(NetworkServiceError::Invalid {
+                field: "masquerade",
+                reason: "required".into(),
+            });
+        };
+        let name = required_name(masquerade.name)?;
+        let masquerade = Masquerade {
+            source: parse_cidr("source_cidr", &masquerade.source_cidr)?,
+        };
+
+        self.update(|ruleset| {
+            if ruleset.masquerades.contains_key(&name) {
+                return Err(NetworkServiceError::AlreadyExists {
+                    kind: "masquerade",
+                    name,
+                });
+            }
+            let _ = ruleset.masquerades.insert(name, masquerade);
+            Ok(())
+        })
+        .await?;
+
+        Ok(NetworkServiceAddMasqueradeResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_masquerade(
+        &self,
+        request: NetworkServiceRemoveMasqueradeRequest,
+    ) -> Result<NetworkServiceRemoveMasqueradeResponse> {
+        self.update(|ruleset| {
+            ruleset.masquerades.remove(&request.name).map(|_| ()).ok_or(
+                NetworkServiceError::NotFound {
+                    kind: "masquerade",
+                    name: request.name,
+                },
+            )
+        })
+        .await?;
+
+        Ok(NetworkServiceRemoveMasqueradeResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn set_egress_policy(
+        &self,
+        request: NetworkServiceSetEgressPolicyRequest,
+    ) -> Result<NetworkServiceSetEgressPolicyResponse> {
+        let Some(policy) = request.egress_policy else {
+            return Err(NetworkServiceError::Invalid {
+                field: "egress_policy",
+                reason: "required".into(),
+            });
+        };
+        let cell_name = parse_cell_name(policy.cell_name)?;
+        let policy = EgressPolicy {
+            allowed: policy
+                .allowed_cidrs
+                .iter()
+                .map(|cidr| parse_cidr("allowed_cidrs", cidr))
+                .collect::<Result<_>>()?,
+        };
+
+        self.update(|ruleset| {
+            let _ = ruleset.egress_policies.insert(cell_name, policy);
+            Ok(())
+        })
+        .await?;
+
+        Ok(NetworkServiceSetEgressPolicyResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn remove_egress_policy(
+        &self,
+        request: NetworkServiceRemoveEgressPolicyRequest,
+    ) -> Result<NetworkServiceRemoveEgressPolicyResponse> {
+        let cell_name = parse_cell_name(request.cell_name)?;
+        self.update(|ruleset| {
+            ruleset.egress_policies.remove(&cell_name).map(|_| ()).ok_or(
+                NetworkServiceError::NotFound {
+                    kind: "egress policy",
+                    name: cell_name,
+                },
+            )
+        })
+        .await?;
+
+        Ok(NetworkServiceRemoveEgressPolicyResponse {})
+    }
+
+    #[tracing::instrument(skip(self))]
+    async fn list(&self) -> Result<NetworkServiceListResponse> {
+        let ruleset = self.ruleset.lock().await;
+        Ok(NetworkServiceListResponse {
+            port_mappings: ruleset
+                .port_mappings
+                .iter()
+                .map(|(name, m)| network::PortMapping {
+                    name: name.clone(),
+                    protocol: match m.protocol {
+                        Protocol::Tcp => network::Protocol::Tcp,
+                        Protocol::Udp => network::Protocol::Udp,
+                    }
+                    .into(),
+                    host_port: m.host_port.into(),
+                    destination_address: m.destination.ip().to_string(),
+                    destination_port: m.destination.port().into(),
+                })
+                .collect(),
+            masquerades: ruleset
+                .masquerades
+                .iter()
+                .map(|(name, m)| network::Masquerade {
+                    name: name.clone(),
+                    source_cidr: m.source.to_string(),
+                })
+                .collect(),
+            egress_policies: ruleset
+                .egress_policies
+                .iter()
+                .map(|(cell_name, p)| network::EgressPolicy {
+                    cell_name: cell_name.clone(),
+                    allowed_cidrs: p
+                        .allowed
+                        .iter()
+                        .map(ToString::to_string)
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl TryFrom<network::PortMapping> for PortMapping {
+    type Error = NetworkServiceError;
+
+    fn try_from(mapping: network::PortMapping) -> Result<Self> {
+        let protocol = match mapping.protocol() {
+            network::Protocol::Tcp => Protocol::Tcp,
+            network::Protocol::Udp => Protocol::Udp,
+            network::Protocol::Unspecified => {
+                return Err(NetworkServiceError::Invalid {
+                    field: "protocol",
+                    reason: "required".into(),
+                })
+            }
+        };
+        let address: IpAddr =
+            mapping.destination_address.parse().map_err(|_| {
+                NetworkServiceError::Invalid {
+                    field: "destination_address",
+                    reason: format!(
+                        "'{}' is not an IP address",
+                        mapping.destination_address
+                    ),
+                }
+            })?;
+
+        Ok(PortMapping {
+            protocol,
+            host_port: parse_port("host_port", mapping.host_port)?,
+            destination: SocketAddr::new(
+                address,
+                parse_port("destination_port", mapping.destination_port)?,
+            ),
+        })
+    }
+}
+
+fn required_name(name: String) -> Result<String> {
+    if name.is_empty() {
+        return Err(NetworkServiceError::Invalid {
+            field: "name",
+            reason: "required".into(),
+        });
+    }
+    Ok(name)
+}
+
+/// Egress policies are rendered into nftables rules matching the cell's
+/// cgroup, so their cell names are held to the names cells can be created
+/// with.
+fn parse_cell_name(name: String) -> Result<String> {
+    CellName::validate(Some(name), "cell_name", None)
+        .map(|name| name.to_string())
+        .map_err(|e| NetworkServiceError::Invalid {
+            field: "cell_name",
+            reason: e.to_string(),
+        })
+}
+
+fn parse_port(field: &'static str, port: u32) -> Result<u16> {
+    match u16::try_from(port) {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(NetworkServiceError::Invalid {
+            field,
+            reason: format!("{port} is not a valid port"),
+        }),
+    }
+}
+
+fn parse_cidr(field: &'static str, cidr: &str) -> Result<IpNetwork> {
+    cidr.parse().map_err(|_| NetworkServiceError::Invalid {
+        field,
+        reason: format!("'{cidr}' is not a valid CIDR"),
+    })
+}
+
+#[tonic::async_trait]
+impl network_service_server::NetworkService for NetworkService {
+    async fn add_port_mapping(
+        &self,
+        request: Request<NetworkServiceAddPortMappingRequest>,
+    ) -> std::result::Result<
+        Response<NetworkServiceAddPortMappingResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.add_port_mapping(req).await?))
+    }
+
+    async fn remove_port_mapping(
+        &self,
+        request: Request<NetworkServiceRemovePortMappingRequest>,
+    ) -> std::result::Result<
+        Response<NetworkServiceRemovePortMappingResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.remove_port_mapping(req).await?))
+    }
+
+    async fn add_masquerade(
+        &self,
+        request: Request<NetworkServiceAddMasqueradeRequest>,
+    ) -> std::result::Result<
+        Response<NetworkServiceAddMasqueradeResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.add_masquerade(req).await?))
+    }
+
+    async fn remove_masquerade(
+        &self,
+        request: Request<NetworkServiceRemoveMasqueradeRequest>,
+    ) -> std::result::Result<
+        Response<NetworkServiceRemoveMasqueradeResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.remove_masquerade(req).await?))
+    }
+
+    async fn set_egress_policy(
+        &self,
+        request: Request<NetworkServiceSetEgressPolicyRequest>,
+    ) -> std::result::Result<
+        Response<NetworkServiceSetEgressPolicyResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.set_egress_policy(req).await?))
+    }
+
+    async fn remove_egress_policy(
+        &self,
+        request: Request<NetworkServiceRemoveEgressPolicyRequest>,
+    ) -> std::result::Result<
+        Response<NetworkServiceRemoveEgressPolicyResponse>,
+        Status,
+    > {
+        let req = request.into_inner();
+        Ok(Response::new(self.remove_egress_policy(req).await?))
+    }
+
+    async fn list(
+        &self,
+        _request: Request<NetworkServiceListRequest>,
+    ) -> std::result::Result<Response<NetworkServiceListResponse>, Status> {
+        Ok(Response::new(self.list().await?))
+    }
+}
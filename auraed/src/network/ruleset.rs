@@ -0,0 +1,310 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::veth::cell_interface;
+use anyhow::anyhow;
+use ipnetwork::IpNetwork;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, net::SocketAddr, path::Path, process::Stdio};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// The nftables table owned by auraed
+const TABLE: &str = "aurae";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct PortMapping {
+    pub protocol: Protocol,
+    pub host_port: u16,
+    pub destination: SocketAddr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Masquerade {
+    pub source: IpNetwork,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct EgressPolicy {
+    pub allowed: Vec<IpNetwork>,
+}
+
+/// The desired content of the `aurae` nftables table.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Ruleset {
+    /// Port mappings by name
+    pub port_mappings: BTreeMap<String, PortMapping>,
+    /// Masquerade rules by name
+    pub masquerades: BTreeMap<String, Masquerade>,
+    /// Egress policies by cell name
+    pub egress_policies: BTreeMap<String, EgressPolicy>,
+}
+
+impl Ruleset {
+    /// Load a previously saved ruleset, if any
+    pub async fn load(path: &Path) -> Result<Option<Self>, anyhow::Error> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let contents = tokio::fs::read(path).await?;
+        Ok(Some(serde_json::from_slice(&contents)?))
+    }
+
+    pub async fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(path, serde_json::to_vec(self)?).await?;
+        Ok(())
+    }
+
+    /// Replace the `aurae` table with the rules of this ruleset in a single
+    /// nftables transaction.
+    pub async fn apply(&self) -> Result<(), anyhow::Error> {
+        let mut child = Command::new("nft")
+            .args(["-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| anyhow!("failed to run nft: {e}"))?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        stdin.write_all(self.render().as_bytes()).await?;
+        drop(stdin);
+
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "nft exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Render the ruleset as an nftables script.
+    pub fn render(&self) -> String {
+        let mut dnat = vec![];
+        for mapping in self.port_mappings.values() {
+            let (nfproto, family) = match mapping.destination {
+                SocketAddr::V4(_) => ("ipv4", "ip"),
+                SocketAddr::V6(_) => ("ipv6", "ip6"),
+            };
+            dnat.push(format!(
+                "meta nfproto {nfproto} {} dport {} dnat {family} to {}",
+                mapping.protocol.as_str(),
+                mapping.host_port,
+                mapping.destination
+            ));
+        }
+
+        let mut masquerade = vec![];
+        for rule in self.masquerades.values() {
+            masquerade.push(format!(
+                "{} saddr {} masquerade",
+                family(&rule.source),
+                rule.source
+            ));
+        }
+
+        // The policies of nested cells come before those of their ancestors,
+        // whose rules also match the sockets of the nested cells
+        let mut policies: Vec<_> = self
+            .egress_policies
+            .iter()
+            .map(|(cell_name, policy)| {
+                (Path::new(cell_name).components().count(), cell_name, policy)
+            })
+            .collect();
+        policies.sort_by(|(a, ..), (b, ..)| b.cmp(a));
+
+        let mut egress = vec![];
+        let mut forward = vec![];
+        for (level, cell_name, policy) in policies {
+            // Matches the sockets of processes in the cell's cgroup or its
+            // descendants, which sits as deep in the hierarchy as the name
+            let cgroup =
+                format!("socket cgroupv2 level {level} \"{cell_name}\"");
+            // Cells isolating their network are routed by the host from the
+            // host end of their pair instead
+            let interface =
+                format!("iifname \"{}\"", cell_interface(cell_name));
+            for network in &policy.allowed {
+                let daddr = format!("{} daddr {network}", family(network));
+                egress.push(format!("{cgroup} {daddr} accept"));
+                forward.push(format!("{interface} {daddr} accept"));
+            }
+            egress.push(format!("{cgroup} reject"));
+            forward.push(format!("{interface} reject"));
+        }
+        if !forward.is_empty() {
+            // Replies to connections into the cells are let through
+            forward.insert(0, "ct state established,related accept".into());
+        }
+
+        // Creating and then deleting the table first makes sure the script
+        // replaces its previous content, without failing if it is missing.
+        let mut lines = vec![
+            format!("table inet {TABLE}"),
+            format!("delete table inet {TABLE}"),
+            format!("table inet {TABLE} {{"),
+        ];
+        lines.extend(chain(
+            "prerouting",
+            "type nat hook prerouting priority dstnat; policy accept;",
+            &dnat,
+        ));
+        // Port mappings also apply to connections from the host itself
+        lines.extend(chain(
+            "output_nat",
+            "type nat hook output priority -100; policy accept;",
+            &dnat
+                .iter()
+                .map(|rule| format!("fib daddr type local {rule}"))
+                .collect::<Vec<_>>(),
+        ));
+        lines.extend(chain(
+            "postrouting",
+            "type nat hook postrouting priority srcnat; policy accept;",
+            &masquerade,
+        ));
+        lines.extend(chain(
+            "egress",
+            "type filter hook output priority filter; policy accept;",
+            &egress,
+        ));
+        lines.extend(chain(
+            "forward",
+            "type filter hook forward priority filter; policy accept;",
+            &forward,
+        ));
+        lines.push("}".into());
+
+        let mut script = lines.join("\n");
+        script.push('\n');
+        script
+    }
+}
+
+fn chain(name: &str, hook: &str, rules: &[String]) -> Vec<String> {
+    let mut lines = vec![format!("  chain {name} {{"), format!("    {hook}")];
+    lines.extend(rules.iter().map(|rule| format!("    {rule}")));
+    lines.push("  }".into());
+    lines
+}
+
+fn family(network: &IpNetwork) -> &'static str {
+    match network {
+        IpNetwork::V4(_) => "ip",
+        IpNetwork::V6(_) => "ip6",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_ruleset_renders_empty_table() {
+        let script = Ruleset::default().render();
+        assert!(script.starts_with(
+            "table inet aurae\ndelete table inet aurae\ntable inet aurae {\n"
+        ));
+        assert!(!script.contains("dnat"));
+        assert!(!script.contains("masquerade"));
+        assert!(!script.contains("cgroupv2"));
+    }
+
+    #[test]
+    fn ruleset_renders_rules() {
+        let mut ruleset = Ruleset::default();
+        let _ = ruleset.port_mappings.insert(
+            "web".into(),
+            PortMapping {
+                protocol: Protocol::Tcp,
+                host_port: 8080,
+                destination: "192.168.249.2:80".parse().expect("addr"),
+            },
+        );
+        let _ = ruleset.masquerades.insert(
+            "vms".into(),
+            Masquerade { source: "192.168.249.0/24".parse().expect("cidr") },
+        );
+        let _ = ruleset.egress_policies.insert(
+            "sandbox".into(),
+            EgressPolicy { allowed: vec!["10.0.0.0/8".parse().expect("cidr")] },
+        );
+        let _ = ruleset
+            .egress_policies
+            .insert("sandbox/nested".into(), EgressPolicy { allowed: vec![] });
+
+        let script = ruleset.render();
+        assert!(script.contains(
+            "meta nfproto ipv4 tcp dport 8080 dnat ip to 192.168.249.2:80"
+        ));
+        assert!(script.contains(
+            "fib daddr type local meta nfproto ipv4 tcp dport 8080 dnat ip to 192.168.249.2:80"
+        ));
+        assert!(script.contains("ip saddr 192.168.249.0/24 masquerade"));
+        assert!(script.contains(
+            "socket cgroupv2 level 1 \"sandbox\" ip daddr 10.0.0.0/8 accept"
+        ));
+        assert!(script.contains("socket cgroupv2 level 1 \"sandbox\" reject"));
+        assert!(script
+            .contains("socket cgroupv2 level 2 \"sandbox/nested\" reject"));
+        let forward = format!(
+            "iifname \"{}\" ip daddr 10.0.0.0/8 accept",
+            cell_interface("sandbox")
+        );
+        assert!(script.contains(&forward));
+        assert!(script.contains("ct state established,related accept"));
+    }
+
+    #[test]
+    fn nested_cells_are_filtered_first() {
+        let mut ruleset = Ruleset::default();
+        for cell_name in ["a", "a/b", "a/b/c", "z"] {
+            let _ = ruleset
+                .egress_policies
+                .insert(cell_name.into(), EgressPolicy { allowed: vec![] });
+        }
+
+        let script = ruleset.render();
+        let position = |cell_name: &str| {
+            script
+                .find(&format!("\"{cell_name}\" reject"))
+                .expect("rule of the cell")
+        };
+        assert!(position("a/b/c") < position("a/b"));
+        assert!(position("a/b") < position("a"));
+        assert!(position("a/b") < position("z"));
+    }
+}
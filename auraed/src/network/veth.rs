@@ -0,0 +1,221 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Veth pairs attaching the network namespaces of cells and pod sandboxes to
+//! the host, addressed by [super::ipam::Ipam] rather than by CNI plugins.
+//!
+//! The end in the namespace is named `eth0` and takes the leased address
+//! with the prefix of its pool, routing everything else through the gateway
+//! of the pool. The end on the host has no address: the host routes the
+//! leased address to it and answers ARP requests for the gateway and the
+//! other addresses of the pool by proxy, so namespaces need no bridge to
+//! reach each other or, forwarded by the host, other networks.
+//!
+//! The host end of the pair of a cell is named after the cell, see
+//! [cell_interface], so the rules of the cell match its traffic whether it
+//! is running or not.
+
+use super::ipam::Lease;
+use anyhow::{anyhow, Context};
+use futures::TryStreamExt;
+use nix::sched::{setns, CloneFlags};
+use rtnetlink::Handle;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{fs::File, io, net::Ipv4Addr};
+
+/// Interface of the namespace end of the pair.
+const IFNAME: &str = "eth0";
+const PREFIX: &str = "aurae";
+
+/// A namespace attached to the host by a veth pair.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Veth {
+    /// Name of the end on the host
+    host: String,
+    lease: Lease,
+}
+
+impl Veth {
+    /// Attaches the network namespace of `pid` to the host with the address
+    /// of `lease`.
+    pub async fn attach(pid: i32, lease: Lease) -> anyhow::Result<Self> {
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        Self::attach_as(pid, format!("{PREFIX}{}", &suffix[..8]), lease).await
+    }
+
+    /// Attaches the network namespace of the cell `cell_name`, whose process
+    /// is `pid`, to the host with the address of `lease`.
+    pub async fn attach_cell(
+        pid: i32,
+        cell_name: &str,
+        lease: Lease,
+    ) -> anyhow::Result<Self> {
+        Self::attach_as(pid, cell_interface(cell_name), lease).await
+    }
+
+    /// Attaches the network namespace of `pid` with a pair whose host end is
+    /// named `host`.
+    async fn attach_as(
+        pid: i32,
+        host: String,
+        lease: Lease,
+    ) -> anyhow::Result<Self> {
+        let peer = format!("{host}p");
+
+        let handle = connect()?;
+        handle
+            .link()
+            .add()
+            .veth(host.clone(), peer.clone())
+            .execute()
+            .await
+            .with_context(|| format!("failed to add veth pair {host}"))?;
+        let veth = Self { host, lease };
+
+        let attached = async {
+            let peer_index = link_index(&handle, &peer).await?;
+            handle
+                .link()
+                .set(peer_index)
+                .setns_by_pid(pid as u32)
+                .execute()
+                .await
+                .context("failed to move veth into the namespace")?;
+
+            let host_index = link_index(&handle, &veth.host).await?;
+            handle.link().set(host_index).up().execute().await?;
+            tokio::fs::write(
+                format!("/proc/sys/net/ipv4/conf/{}/proxy_arp", veth.host),
+                "1",
+            )
+            .await
+            .context("failed to enable proxy ARP")?;
+            handle
+                .route()
+                .add()
+                .v4()
+                .destination_prefix(lease.address, 32)
+                .output_interface(host_index)
+                .execute()
+                .await
+                .context("failed to route the leased address")?;
+
+            configure_namespace(pid, peer, lease).await
+        }
+        .await;
+
+        match attached {
+            Ok(()) => Ok(veth),
+            Err(e) => {
+                let _best_effort = veth.remove().await;
+                Err(e)
+            }
+        }
+    }
+
+    /// The address of the namespace.
+    pub fn address(&self) -> Ipv4Addr {
+        self.lease.address
+    }
+
+    /// Deletes the pair, if it still exists. Pairs are also deleted by the
+    /// kernel along with their namespace.
+    pub async fn remove(&self) -> anyhow::Result<()> {
+        let handle = connect()?;
+        // The kernel reports a missing device as an error
+        let Ok(index) = link_index(&handle, &self.host).await else {
+            return Ok(());
+        };
+        handle.link().del(index).execute().await?;
+        Ok(())
+    }
+}
+
+/// The name of the host end of the pair of the cell `cell_name`, unique
+/// among the cells of the node but for hash collisions.
+pub(crate) fn cell_interface(cell_name: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(cell_name.as_bytes()));
+    format!("{PREFIX}c{}", &hash[..8])
+}
+
+/// Let the host route the traffic of the namespaces to other networks
+pub(super) async fn enable_forwarding() -> io::Result<()> {
+    tokio::fs::write("/proc/sys/net/ipv4/ip_forward", "1").await
+}
+
+fn connect() -> io::Result<Handle> {
+    let (connection, handle, _) = rtnetlink::new_connection()?;
+    let _ignored = tokio::spawn(connection);
+    Ok(handle)
+}
+
+/// Names the end of the pair in the namespace of `pid` [IFNAME] and
+/// assigns it the leased address, over a netlink connection opened from a
+/// thread spawned to enter the namespace. Sockets stay in the namespace
+/// they were created in, so the thread can exit once connected.
+async fn configure_namespace(
+    pid: i32,
+    peer: String,
+    lease: Lease,
+) -> anyhow::Result<()> {
+    let netns = File::open(format!("/proc/{pid}/ns/net"))?;
+    let runtime = tokio::runtime::Handle::current();
+    let (connection, handle, _) = tokio::task::spawn_blocking(move || {
+        std::thread::spawn(move || {
+            setns(&netns, CloneFlags::CLONE_NEWNET)?;
+            let _entered = runtime.enter();
+            rtnetlink::new_connection()
+        })
+        .join()
+        .map_err(|_| io::Error::other("netlink thread panicked"))?
+    })
+    .await??;
+    let _ignored = tokio::spawn(connection);
+
+    let lo = link_index(&handle, "lo").await?;
+    handle.link().set(lo).up().execute().await?;
+
+    let index = link_index(&handle, &peer).await?;
+    handle.link().set(index).name(IFNAME.to_string()).execute().await?;
+    handle
+        .address()
+        .add(index, lease.address.into(), lease.network.prefix())
+        .execute()
+        .await
+        .context("failed to assign the leased address")?;
+    handle.link().set(index).up().execute().await?;
+    handle
+        .route()
+        .add()
+        .v4()
+        .gateway(lease.gateway())
+        .output_interface(index)
+        .execute()
+        .await
+        .context("failed to add the default route")?;
+    Ok(())
+}
+
+async fn link_index(handle: &Handle, name: &str) -> anyhow::Result<u32> {
+    let link = handle
+        .link()
+        .get()
+        .match_name(name.to_string())
+        .execute()
+        .try_next()
+        .await?;
+    link.map(|link| link.header.index)
+        .ok_or_else(|| anyhow!("link {name} not found"))
+}
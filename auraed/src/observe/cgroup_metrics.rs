@@ -0,0 +1,330 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Periodic samples of the cpu.stat, memory.stat and io.stat of the cgroup of
+//! a cell, pod sandbox or VM.
+//!
+//! The cgroup is read every [READ_INTERVAL] and the reads of each interval of
+//! the client are downsampled into one sample: the counters are the ones of
+//! the last read, the gauges of memory.stat are the mean of all reads.
+
+use super::error::ObserveServiceError;
+use crate::cri::pod_cell;
+use crate::vms::vm_cgroup;
+use proto::observe::{CgroupIoMetrics, CgroupMetrics, WorkloadType};
+use std::collections::{BTreeMap, HashMap};
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::{Instant, MissedTickBehavior};
+use tonic::Status;
+
+const CGROUPFS_ROOT: &str = "/sys/fs/cgroup";
+const READ_INTERVAL: Duration = Duration::from_secs(1);
+const MIN_INTERVAL_MS: u32 = 1000;
+const DEFAULT_INTERVAL_MS: u32 = 5000;
+
+/// The interval of a stream from the `interval_ms` of its request, 0 for
+/// the default.
+pub(crate) fn interval(
+    interval_ms: u32,
+) -> Result<Duration, ObserveServiceError> {
+    match interval_ms {
+        0 => Ok(Duration::from_millis(DEFAULT_INTERVAL_MS.into())),
+        ms if ms < MIN_INTERVAL_MS => {
+            Err(ObserveServiceError::IntervalTooShort {
+                interval_ms,
+                min_ms: MIN_INTERVAL_MS,
+            })
+        }
+        ms => Ok(Duration::from_millis(ms.into())),
+    }
+}
+
+/// Resolve the cgroup of `workload` on the host.
+///
+/// Pod sandboxes run in a cell of their own, VMs in the threaded cgroup of
+/// their vCPUs below the cgroup of auraed.
+pub(crate) fn resolve(
+    workload: Option<(WorkloadType, String)>,
+) -> Result<PathBuf, ObserveServiceError> {
+    let Some((workload_type, id)) = workload else {
+        return Err(ObserveServiceError::MissingWorkload);
+    };
+    let mut components = Path::new(&id).components();
+    if id.is_empty() || !components.all(|c| matches!(c, Component::Normal(_))) {
+        return Err(ObserveServiceError::InvalidWorkloadId { id });
+    }
+
+    let no_cgroup = |id| ObserveServiceError::NoCgroup { workload_type, id };
+    let cgroup = match workload_type {
+        WorkloadType::Cell => Path::new(CGROUPFS_ROOT).join(&id),
+        WorkloadType::PodSandbox => {
+            Path::new(CGROUPFS_ROOT).join(pod_cell::cell_name(&id))
+        }
+        WorkloadType::Vm => {
+            vm_cgroup(&id).map_err(|_| no_cgroup(id.clone()))?
+        }
+        WorkloadType::Unspecified => {
+            return Err(ObserveServiceError::UnsupportedWorkloadType {
+                workload_type,
+            })
+        }
+    };
+    if cgroup.is_dir() {
+        Ok(cgroup)
+    } else {
+        Err(no_cgroup(id))
+    }
+}
+
+/// Start sampling `cgroup`, sending a sample each `interval` as mapped by
+/// `map_response` until the receiver is dropped or the cgroup is removed.
+pub(crate) fn sample<E: Send + 'static>(
+    cgroup: PathBuf,
+    interval: Duration,
+    map_response: fn(CgroupMetrics) -> E,
+) -> mpsc::Receiver<Result<E, Status>> {
+    let (tx, rx) = mpsc::channel(4);
+    let _ignored = tokio::spawn(async move {
+        let mut reads = tokio::time::interval(READ_INTERVAL);
+        reads.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        let mut samples =
+            tokio::time::interval_at(Instant::now() + interval, interval);
+        let mut downsampler = Downsampler::default();
+        loop {
+            tokio::select! {
+                _ = reads.tick() => {}
+                _ = samples.tick() => {
+                    let Some(metrics) = downsampler.take(now()) else {
+                        continue;
+                    };
+                    if tx.send(Ok(map_response(metrics))).await.is_err() {
+                        // receiver is gone
+                        break;
+                    }
+                    continue;
+                }
+                _ = tx.closed() => break,
+            }
+
+            let path = cgroup.clone();
+            let stats =
+                tokio::task::spawn_blocking(move || CgroupStats::read(&path))
+                    .await
+                    .map_err(std::io::Error::other)
+                    .and_then(|stats| stats);
+            match stats {
+                Ok(stats) => downsampler.add(stats),
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    // the workload is gone, send what was read of the interval
+                    if let Some(metrics) = downsampler.take(now()) {
+                        let _ = tx.send(Ok(map_response(metrics))).await;
+                    }
+                    break;
+                }
+                Err(e) => {
+                    let msg = format!(
+                        "failed to read cgroup '{}': {e}",
+                        cgroup.display()
+                    );
+                    let _ = tx.send(Err(Status::internal(msg))).await;
+                    break;
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// One read of the stat files of a cgroup. The memory and io controllers
+/// may not be enabled for the cgroup, leaving their stats empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct CgroupStats {
+    cpu: HashMap<String, u64>,
+    memory: HashMap<String, u64>,
+    io: BTreeMap<String, HashMap<String, u64>>,
+}
+
+impl CgroupStats {
+    fn read(cgroup: &Path) -> std::io::Result<Self> {
+        let optional = |file| match std::fs::read_to_string(cgroup.join(file)) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            contents => contents,
+        };
+        Ok(Self {
+            cpu: flat_keyed(&std::fs::read_to_string(cgroup.join("cpu.stat"))?),
+            memory: flat_keyed(&optional("memory.stat")?),
+            io: nested_keyed(&optional("io.stat")?),
+        })
+    }
+}
+
+/// The keys and values of a flat keyed cgroup file, e.g. "anon 4096".
+fn flat_keyed(contents: &str) -> HashMap<String, u64> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            Some((key.to_string(), value.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+/// The keys and values of a nested keyed cgroup file by its first column,
+/// e.g. "8:0 rbytes=4096 wbytes=0".
+fn nested_keyed(contents: &str) -> BTreeMap<String, HashMap<String, u64>> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?.to_string();
+            let values = fields
+                .filter_map(|field| {
+                    let (key, value) = field.split_once('=')?;
+                    Some((key.to_string(), value.parse().ok()?))
+                })
+                .collect();
+            Some((name, values))
+        })
+        .collect()
+}
+
+/// Whether a key of memory.stat counts events rather than bytes in use.
+fn is_memory_counter(key: &str) -> bool {
+    ["pg", "workingset_", "thp_", "zswpin", "zswpout", "zswpwb"]
+        .iter()
+        .any(|prefix| key.starts_with(prefix))
+}
+
+/// Folds the reads of one interval into a sample.
+#[derive(Debug, Default)]
+struct Downsampler {
+    reads: u32,
+    last: CgroupStats,
+    gauge_sums: HashMap<String, u64>,
+}
+
+impl Downsampler {
+    fn add(&mut self, stats: CgroupStats) {
+        for (key, value) in &stats.memory {
+            if !is_memory_counter(key) {
+                let sum = self.gauge_sums.entry(key.clone()).or_default();
+                *sum = sum.saturating_add(*value);
+            }
+        }
+        self.reads += 1;
+        self.last = stats;
+    }
+
+    /// The sample of the reads since the previous one, None without reads.
+    fn take(&mut self, timestamp: i64) -> Option<CgroupMetrics> {
+        if self.reads == 0 {
+            return None;
+        }
+        let Self { reads, last, gauge_sums } = std::mem::take(self);
+        let mut memory = last.memory;
+        for (key, sum) in gauge_sums {
+            // gauges missing from the last read are dropped
+            if let Some(value) = memory.get_mut(&key) {
+                *value = sum / u64::from(reads);
+            }
+        }
+        Some(CgroupMetrics {
+            timestamp,
+            reads,
+            cpu: last.cpu,
+            memory,
+            io: last
+                .io
+                .into_iter()
+                .map(|(device, stats)| CgroupIoMetrics { device, stats })
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat_files() {
+        let cpu = flat_keyed("usage_usec 120\nnr_throttled 3\n");
+        assert_eq!(cpu.get("usage_usec"), Some(&120));
+        assert_eq!(cpu.get("nr_throttled"), Some(&3));
+
+        let io = nested_keyed("8:0 rbytes=4096 wbytes=512 rios=1\n");
+        let device = io.get("8:0").expect("device 8:0");
+        assert_eq!(device.get("rbytes"), Some(&4096));
+        assert_eq!(device.get("wbytes"), Some(&512));
+        assert_eq!(device.get("rios"), Some(&1));
+    }
+
+    #[test]
+    fn downsampling_averages_gauges_and_keeps_last_counters() {
+        let stats = |anon, pgfault, usage_usec| CgroupStats {
+            cpu: HashMap::from([("usage_usec".to_string(), usage_usec)]),
+            memory: HashMap::from([
+                ("anon".to_string(), anon),
+                ("pgfault".to_string(), pgfault),
+            ]),
+            io: BTreeMap::new(),
+        };
+        let mut downsampler = Downsampler::default();
+        assert!(downsampler.take(0).is_none());
+
+        downsampler.add(stats(100, 1, 10));
+        downsampler.add(stats(300, 5, 20));
+        let sample = downsampler.take(42).expect("sample");
+        assert_eq!(sample.timestamp, 42);
+        assert_eq!(sample.reads, 2);
+        assert_eq!(sample.memory.get("anon"), Some(&200));
+        assert_eq!(sample.memory.get("pgfault"), Some(&5));
+        assert_eq!(sample.cpu.get("usage_usec"), Some(&20));
+
+        // the next interval starts afresh
+        downsampler.add(stats(50, 6, 30));
+        let sample = downsampler.take(43).expect("sample");
+        assert_eq!(sample.reads, 1);
+        assert_eq!(sample.memory.get("anon"), Some(&50));
+    }
+
+    #[test]
+    fn rejects_invalid_requests() {
+        assert!(matches!(
+            resolve(None),
+            Err(ObserveServiceError::MissingWorkload)
+        ));
+        assert!(matches!(
+            resolve(Some((WorkloadType::Cell, "../etc".into()))),
+            Err(ObserveServiceError::InvalidWorkloadId { .. })
+        ));
+        assert!(matches!(
+            interval(999),
+            Err(ObserveServiceError::IntervalTooShort { .. })
+        ));
+        assert_eq!(interval(0).ok(), Some(Duration::from_secs(5)));
+    }
+}
@@ -0,0 +1,279 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Capture of core dumps of processes crashing inside cells.
+//!
+//! auraed registers itself as the kernel's core dump pipe helper. The kernel
+//! runs the helper (`auraed core-dump ...`) with the core on stdin, the
+//! helper stores it below `<runtime_dir>/cores` within a size quota and
+//! notifies the running daemon over a datagram socket, which in turn emits
+//! the dump on the ObserveService core dump stream.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::net::UnixDatagram,
+    path::{Path, PathBuf},
+};
+use tokio::sync::broadcast;
+use tracing::{error, info, warn};
+
+/// Largest core dump kept, anything beyond is truncated.
+pub(crate) const MAX_CORE_DUMP_SIZE: u64 = 512 << 20;
+
+/// Space all core dumps may use together. The oldest dumps are removed
+/// once a new dump would exceed it.
+pub(crate) const MAX_CORE_DUMPS_SIZE: u64 = 2 << 30;
+
+const CORE_PATTERN: &str = "/proc/sys/kernel/core_pattern";
+
+/// Number of helpers the kernel runs in parallel. A non zero limit also
+/// makes the kernel wait for the helper, keeping /proc/<pid> readable.
+const CORE_PIPE_LIMIT: &str = "/proc/sys/kernel/core_pipe_limit";
+const CORE_PIPE_LIMIT_VALUE: &str = "16";
+
+/// Socket the daemon receives core dump notifications on.
+const EVENTS_SOCKET: &str = "events.sock";
+
+/// A captured core dump, also stored next to it as `<dump>.json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct CoreDump {
+    /// The cell the process ran in, `None` for host processes
+    pub cell_name: Option<String>,
+    /// The pid of the process in the initial pid namespace
+    pub pid: i32,
+    pub signal: i32,
+    /// Seconds since the epoch at which the process crashed
+    pub timestamp: i64,
+    pub executable: String,
+    pub path: PathBuf,
+    pub size: u64,
+    /// Whether the dump was cut off at [MAX_CORE_DUMP_SIZE]
+    pub truncated: bool,
+}
+
+/// Register `auraed` as the kernel's core dump helper. The helper is
+/// started with `runtime_dir` so it stores dumps where the daemon expects
+/// them.
+pub(crate) fn install(auraed: &Path, runtime_dir: &Path) -> io::Result<()> {
+    // %P: pid in the initial namespace, %s: signal, %t: time, %e: comm
+    let pattern = format!(
+        "|{} --runtime-dir {} core-dump %P %s %t %e",
+        auraed.display(),
+        runtime_dir.display()
+    );
+    fs::write(CORE_PIPE_LIMIT, CORE_PIPE_LIMIT_VALUE)?;
+    fs::write(CORE_PATTERN, pattern)
+}
+
+/// Prune old dumps, store the core dump read from `input` and notify the
+/// daemon. Runs in the helper process spawned by the kernel.
+pub(crate) fn capture(
+    cores_dir: &Path,
+    pid: i32,
+    signal: i32,
+    timestamp: i64,
+    executable: String,
+    input: impl Read,
+) -> anyhow::Result<CoreDump> {
+    fs::create_dir_all(cores_dir).with_context(|| {
+        format!("failed to create '{}'", cores_dir.display())
+    })?;
+
+    // Make room for the largest dump before writing, so the quota holds
+    // even while the dump is written
+    prune(cores_dir, MAX_CORE_DUMPS_SIZE - MAX_CORE_DUMP_SIZE)?;
+
+    let cell_name = fs::read_to_string(format!("/proc/{pid}/cgroup"))
+        .ok()
+        .and_then(|cgroup| cell_of(&cgroup));
+
+    let name = executable.replace('/', "_");
+    let path = cores_dir.join(format!("{timestamp}-{pid}-{name}.core"));
+    let mut file = File::create(&path)
+        .with_context(|| format!("failed to create '{}'", path.display()))?;
+
+    // Read one byte more than we keep to learn if the dump was truncated
+    let mut input = input.take(MAX_CORE_DUMP_SIZE + 1);
+    let mut size =
+        io::copy(&mut (&mut input).take(MAX_CORE_DUMP_SIZE), &mut file)?;
+    let truncated = input.read(&mut [0u8])? > 0;
+    if truncated {
+        size = MAX_CORE_DUMP_SIZE;
+    }
+
+    let dump = CoreDump {
+        cell_name,
+        pid,
+        signal,
+        timestamp,
+        executable,
+        path,
+        size,
+        truncated,
+    };
+    let metadata = serde_json::to_vec(&dump)?;
+    fs::write(dump.path.with_extension("json"), &metadata)?;
+
+    // The daemon may not be running, the dump is kept regardless
+    let _ = UnixDatagram::unbound()
+        .and_then(|s| s.send_to(&metadata, cores_dir.join(EVENTS_SOCKET)));
+
+    Ok(dump)
+}
+
+/// Forward notifications sent by the core dump helper to `sender`.
+pub(crate) fn listen(
+    cores_dir: &Path,
+    sender: broadcast::Sender<CoreDump>,
+) -> anyhow::Result<()> {
+    fs::create_dir_all(cores_dir)?;
+    let path = cores_dir.join(EVENTS_SOCKET);
+    let _ = fs::remove_file(&path);
+    let socket = tokio::net::UnixDatagram::bind(&path)
+        .with_context(|| format!("failed to bind '{}'", path.display()))?;
+
+    info!("Listening for core dumps on {}", path.display());
+    let _ignored = tokio::spawn(async move {
+        let mut buf = vec![0u8; 64 << 10];
+        loop {
+            let len = match socket.recv(&mut buf).await {
+                Ok(len) => len,
+                Err(e) => {
+                    error!("Failed to receive core dump notification: {e}");
+                    break;
+                }
+            };
+            match serde_json::from_slice::<CoreDump>(&buf[..len]) {
+                Ok(dump) => {
+                    info!(
+                        "Captured core dump of pid {} ({}) at {}",
+                        dump.pid,
+                        dump.executable,
+                        dump.path.display()
+                    );
+                    // No subscribers is not an error
+                    let _ = sender.send(dump);
+                }
+                Err(e) => warn!("Invalid core dump notification: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// The cell owning a process, given the contents of its /proc/<pid>/cgroup.
+/// Processes of a cell live in the `_` leaf of the cell's cgroup.
+pub(crate) fn cell_of(cgroup: &str) -> Option<String> {
+    cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::/"))
+        .and_then(|path| path.strip_suffix("/_"))
+        .map(Into::into)
+}
+
+/// Remove the oldest core dumps until all dumps use at most `quota` bytes.
+fn prune(cores_dir: &Path, quota: u64) -> io::Result<()> {
+    let mut dumps = vec![];
+    for entry in fs::read_dir(cores_dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.extension().is_some_and(|e| e == "core") {
+            let metadata = entry.metadata()?;
+            dumps.push((metadata.modified()?, metadata.len(), path));
+        }
+    }
+    dumps.sort();
+
+    let mut total: u64 = dumps.iter().map(|(_, len, _)| len).sum();
+    for (_, len, path) in dumps {
+        if total <= quota {
+            break;
+        }
+        fs::remove_file(&path)?;
+        let _ = fs::remove_file(path.with_extension("json"));
+        total -= len;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cell_is_read_from_cgroup() {
+        assert_eq!(cell_of("0::/ae-1/_\n").as_deref(), Some("ae-1"));
+        assert_eq!(
+            cell_of("0::/parent/child/_\n").as_deref(),
+            Some("parent/child")
+        );
+        assert_eq!(cell_of("0::/user.slice/session-3.scope\n"), None);
+    }
+
+    #[test]
+    fn capture_stores_dump_and_metadata() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let dump = capture(
+            dir.path(),
+            i32::MAX,
+            11,
+            1700000000,
+            "crash".into(),
+            &[7u8; 64][..],
+        )
+        .expect("capture");
+
+        assert_eq!(dump.size, 64);
+        assert!(!dump.truncated);
+        assert_eq!(fs::read(&dump.path).expect("read dump"), vec![7u8; 64]);
+        let metadata: CoreDump = serde_json::from_slice(
+            &fs::read(dump.path.with_extension("json")).expect("read json"),
+        )
+        .expect("parse json");
+        assert_eq!(metadata.signal, 11);
+    }
+
+    #[test]
+    fn prune_removes_oldest_dumps() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let dir = scratch.path();
+        for (i, name) in ["1-1-a", "2-2-b", "3-3-c"].iter().enumerate() {
+            let path = dir.join(name).with_extension("core");
+            fs::write(&path, vec![0u8; 10]).expect("write dump");
+            fs::write(path.with_extension("json"), "{}").expect("write json");
+            File::options()
+                .write(true)
+                .open(&path)
+                .and_then(|f| {
+                    f.set_modified(
+                        std::time::SystemTime::UNIX_EPOCH
+                            + std::time::Duration::from_secs(i as u64 + 1),
+                    )
+                })
+                .expect("set mtime");
+        }
+
+        prune(dir, 20).expect("prune");
+
+        assert!(!dir.join("1-1-a.core").exists());
+        assert!(!dir.join("1-1-a.json").exists());
+        assert!(dir.join("2-2-b.core").exists());
+        assert!(dir.join("3-3-c.core").exists());
+    }
+}
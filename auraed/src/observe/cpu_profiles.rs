@@ -0,0 +1,139 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! CPU profiles of workloads taken on demand, see [crate::ebpf::CpuProfile].
+
+use super::cgroup_metrics;
+use super::error::ObserveServiceError;
+use crate::ebpf::{fold_stacks, CpuProfile, ProfileTarget};
+use proto::observe::{
+    GetCpuProfileRequest, GetCpuProfileResponse, WorkloadType,
+};
+use std::time::Duration;
+
+const DEFAULT_DURATION_SECONDS: u32 = 10;
+const MAX_DURATION_SECONDS: u32 = 60;
+const DEFAULT_FREQUENCY_HZ: u32 = 99;
+const MAX_FREQUENCY_HZ: u32 = 1000;
+
+/// Samples the target of `request` for its duration, the profile is
+/// stopped early if the returned future is dropped.
+pub(crate) async fn profile(
+    request: GetCpuProfileRequest,
+) -> Result<GetCpuProfileResponse, ObserveServiceError> {
+    let duration = or_default(
+        "duration_seconds",
+        request.duration_seconds,
+        DEFAULT_DURATION_SECONDS,
+        MAX_DURATION_SECONDS,
+    )?;
+    let frequency_hz = or_default(
+        "frequency_hz",
+        request.frequency_hz,
+        DEFAULT_FREQUENCY_HZ,
+        MAX_FREQUENCY_HZ,
+    )?;
+    let workload = request.workload.map(|w| (w.workload_type(), w.id)).filter(
+        |(workload_type, _)| *workload_type != WorkloadType::Unspecified,
+    );
+    let target = match (workload, request.process_id) {
+        (Some(workload), 0) => {
+            let cgroup = cgroup_metrics::resolve(Some(workload))?;
+            ProfileTarget::cgroup(&cgroup).map_err(failed)?
+        }
+        (None, pid) if pid > 0 => ProfileTarget::Process { pid },
+        _ => return Err(ObserveServiceError::InvalidProfileTarget),
+    };
+
+    let profile = tokio::task::spawn_blocking(move || {
+        CpuProfile::start(target, frequency_hz.into())
+    })
+    .await
+    .map_err(|e| failed(e.into()))?
+    .map_err(failed)?;
+
+    tokio::time::sleep(Duration::from_secs(duration.into())).await;
+
+    // symbolizing reads the ELF files of the sampled processes
+    tokio::task::spawn_blocking(move || {
+        let stacks = profile.stop()?;
+        Ok(GetCpuProfileResponse {
+            folded_stacks: fold_stacks(&stacks),
+            samples: stacks.iter().map(|stack| stack.samples).sum(),
+        })
+    })
+    .await
+    .map_err(|e| failed(e.into()))?
+    .map_err(failed)
+}
+
+fn failed(source: anyhow::Error) -> ObserveServiceError {
+    ObserveServiceError::FailedToProfile { source }
+}
+
+/// `value`, or `default` if it is 0.
+fn or_default(
+    field: &'static str,
+    value: u32,
+    default: u32,
+    max: u32,
+) -> Result<u32, ObserveServiceError> {
+    match value {
+        0 => Ok(default),
+        value if value > max => {
+            Err(ObserveServiceError::OutOfRange { field, value, max })
+        }
+        value => Ok(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::observe::Workload;
+
+    #[tokio::test]
+    async fn rejects_invalid_requests() {
+        let both = GetCpuProfileRequest {
+            workload: Some(Workload {
+                workload_type: WorkloadType::Cell.into(),
+                id: "ae-1".into(),
+            }),
+            process_id: 1,
+            ..Default::default()
+        };
+        assert!(matches!(
+            profile(both).await,
+            Err(ObserveServiceError::InvalidProfileTarget)
+        ));
+        assert!(matches!(
+            profile(GetCpuProfileRequest::default()).await,
+            Err(ObserveServiceError::InvalidProfileTarget)
+        ));
+
+        let too_long = GetCpuProfileRequest {
+            process_id: 1,
+            duration_seconds: 61,
+            ..Default::default()
+        };
+        assert!(matches!(
+            profile(too_long).await,
+            Err(ObserveServiceError::OutOfRange {
+                field: "duration_seconds",
+                ..
+            })
+        ));
+    }
+}
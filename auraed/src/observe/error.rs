@@ -13,7 +13,8 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use proto::observe::LogChannelType;
+use proto::observe::{LogChannelType, WorkloadType};
+use std::path::PathBuf;
 use thiserror::Error;
 use tonic::Status;
 use tracing::error;
@@ -28,6 +29,39 @@ pub enum ObserveServiceError {
     ChannelNotRegistered { pid: i32, channel_type: LogChannelType },
     #[error("{channel_type} is not a valid LogChannelType")]
     InvalidLogChannelType { channel_type: i32 },
+    #[error("{severity} is not a valid LogSeverity")]
+    InvalidLogSeverity { severity: i32 },
+    #[error("a replay starts from either an offset or a timestamp")]
+    AmbiguousReplay,
+    #[error("failed to replay the journal of {channel}: {source}")]
+    FailedToReplay { channel: String, source: std::io::Error },
+    #[error("'{}' must be an absolute path without '..'", path.display())]
+    InvalidWatchPath { path: PathBuf },
+    #[error("cell '{cell_name}' has no processes to resolve paths through")]
+    NoProcessesInCell { cell_name: String },
+    #[error("{workload_type:?} workloads are not supported")]
+    UnsupportedWorkloadType { workload_type: WorkloadType },
+    #[error("failed to watch '{}': {source}", path.display())]
+    FailedToWatch { path: PathBuf, source: std::io::Error },
+    #[error("a workload to sample the cgroup of is required")]
+    MissingWorkload,
+    #[error("'{id}' is not a valid workload id")]
+    InvalidWorkloadId { id: String },
+    #[error("{workload_type:?} '{id}' has no cgroup")]
+    NoCgroup { workload_type: WorkloadType, id: String },
+    #[error("the interval of {interval_ms}ms is shorter than {min_ms}ms")]
+    IntervalTooShort { interval_ms: u32, min_ms: u32 },
+    #[error("either a workload or a process is to be profiled")]
+    InvalidProfileTarget,
+    #[error("{field} of {value} is more than {max}")]
+    OutOfRange { field: &'static str, value: u32, max: u32 },
+    #[error("failed to profile: {source}")]
+    FailedToProfile { source: anyhow::Error },
+    #[error("invalid log filter '{filter}': {source}")]
+    InvalidLogFilter {
+        filter: String,
+        source: tracing_subscriber::filter::ParseError,
+    },
 }
 
 impl From<ObserveServiceError> for Status {
@@ -42,9 +76,31 @@ impl From<ObserveServiceError> for Status {
             | ObserveServiceError::ChannelNotRegistered { .. } => {
                 Status::not_found(msg)
             }
-            ObserveServiceError::InvalidLogChannelType { .. } => {
+            ObserveServiceError::InvalidLogChannelType { .. }
+            | ObserveServiceError::InvalidLogSeverity { .. }
+            | ObserveServiceError::AmbiguousReplay
+            | ObserveServiceError::InvalidWatchPath { .. }
+            | ObserveServiceError::UnsupportedWorkloadType { .. }
+            | ObserveServiceError::MissingWorkload
+            | ObserveServiceError::InvalidWorkloadId { .. }
+            | ObserveServiceError::IntervalTooShort { .. }
+            | ObserveServiceError::InvalidProfileTarget
+            | ObserveServiceError::OutOfRange { .. }
+            | ObserveServiceError::InvalidLogFilter { .. } => {
                 Status::invalid_argument(msg)
             }
+            ObserveServiceError::NoProcessesInCell { .. }
+            | ObserveServiceError::NoCgroup { .. } => Status::not_found(msg),
+            ObserveServiceError::FailedToReplay { .. }
+            | ObserveServiceError::FailedToProfile { .. } => {
+                Status::internal(msg)
+            }
+            ObserveServiceError::FailedToWatch { source, .. } => {
+                match source.kind() {
+                    std::io::ErrorKind::NotFound => Status::not_found(msg),
+                    _ => Status::internal(msg),
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
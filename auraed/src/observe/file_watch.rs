@@ -0,0 +1,180 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::error::ObserveServiceError;
+use futures::StreamExt;
+use inotify::{EventMask, Inotify, WatchMask};
+use proto::observe::{FileEvent, FileEventType, WorkloadType};
+use std::path::{Component, Path, PathBuf};
+use tokio::sync::mpsc;
+use tonic::Status;
+
+const CGROUPFS_ROOT: &str = "/sys/fs/cgroup";
+
+/// Resolve `path`, as seen from within the workload, to a path on the host.
+///
+/// Cells may run in their own mount namespace, so the path is resolved
+/// through the root of one of the cell's processes rather than on the host.
+pub(crate) fn resolve(
+    workload: Option<(WorkloadType, String)>,
+    path: &str,
+) -> Result<PathBuf, ObserveServiceError> {
+    let path = Path::new(path);
+    if !path.is_absolute()
+        || path.components().any(|c| c == Component::ParentDir)
+    {
+        return Err(ObserveServiceError::InvalidWatchPath {
+            path: path.to_path_buf(),
+        });
+    }
+
+    match workload {
+        Some((WorkloadType::Cell, cell_name)) => {
+            let procs = PathBuf::from(CGROUPFS_ROOT)
+                .join(&cell_name)
+                .join("_")
+                .join("cgroup.procs");
+            let pid = std::fs::read_to_string(procs)
+                .ok()
+                .and_then(|p| p.lines().next().map(str::to_string))
+                .ok_or(ObserveServiceError::NoProcessesInCell { cell_name })?;
+            let relative = path.strip_prefix("/").unwrap_or(path);
+            Ok(PathBuf::from(format!("/proc/{pid}/root")).join(relative))
+        }
+        Some((workload_type, _))
+            if workload_type != WorkloadType::Unspecified =>
+        {
+            Err(ObserveServiceError::UnsupportedWorkloadType { workload_type })
+        }
+        _ => Ok(path.to_path_buf()),
+    }
+}
+
+/// Start watching `path`, sending an event for each change until the
+/// receiver is dropped or the path itself is removed.
+pub(crate) fn watch<E: Send + 'static>(
+    path: &Path,
+    map_response: fn(FileEvent) -> E,
+) -> Result<mpsc::Receiver<Result<E, Status>>, ObserveServiceError> {
+    let to_error = |source| ObserveServiceError::FailedToWatch {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    let inotify = Inotify::init().map_err(to_error)?;
+    let _ = inotify
+        .watches()
+        .add(
+            path,
+            WatchMask::CREATE
+                | WatchMask::CLOSE_WRITE
+                | WatchMask::DELETE
+                | WatchMask::MOVED_FROM
+                | WatchMask::MOVED_TO
+                | WatchMask::DELETE_SELF
+                | WatchMask::MOVE_SELF,
+        )
+        .map_err(to_error)?;
+    let mut events =
+        inotify.into_event_stream([0u8; 4096]).map_err(to_error)?;
+
+    let (tx, rx) = mpsc::channel(16);
+    let _ignored = tokio::spawn(async move {
+        while let Some(event) = events.next().await {
+            let event = match event {
+                Ok(event) => event,
+                Err(e) => {
+                    let _ = tx.send(Err(Status::internal(e.to_string()))).await;
+                    break;
+                }
+            };
+            let Some(event_type) = event_type(event.mask) else {
+                continue;
+            };
+            let done = event_type == FileEventType::Removed;
+            let file_event = FileEvent {
+                event_type: event_type.into(),
+                name: event
+                    .name
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+            };
+            if tx.send(Ok(map_response(file_event))).await.is_err() || done {
+                // receiver is gone, or there is nothing left to watch
+                break;
+            }
+        }
+    });
+
+    Ok(rx)
+}
+
+fn event_type(mask: EventMask) -> Option<FileEventType> {
+    if mask.contains(EventMask::CREATE) || mask.contains(EventMask::MOVED_TO) {
+        Some(FileEventType::Created)
+    } else if mask.contains(EventMask::CLOSE_WRITE) {
+        Some(FileEventType::Modified)
+    } else if mask.contains(EventMask::DELETE)
+        || mask.contains(EventMask::MOVED_FROM)
+    {
+        Some(FileEventType::Deleted)
+    } else if mask.contains(EventMask::DELETE_SELF)
+        || mask.contains(EventMask::MOVE_SELF)
+    {
+        Some(FileEventType::Removed)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_rejects_relative_paths() {
+        assert!(resolve(None, "etc/config").is_err());
+        assert!(resolve(None, "/etc/../../config").is_err());
+    }
+
+    #[test]
+    fn resolve_keeps_host_paths() {
+        assert_eq!(
+            resolve(None, "/etc/config").expect("resolved"),
+            PathBuf::from("/etc/config")
+        );
+    }
+
+    #[test]
+    fn resolve_fails_for_empty_cells() {
+        assert!(resolve(
+            Some((WorkloadType::Cell, "ae-no-such-cell".into())),
+            "/etc/config"
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn watch_reports_created_files() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+
+        let mut events = watch(dir.path(), |e| e).expect("watch");
+        std::fs::write(dir.path().join("ready"), "").expect("write file");
+
+        let event = events.recv().await.expect("event").expect("ok");
+        assert_eq!(event.event_type(), FileEventType::Created);
+        assert_eq!(event.name, "ready");
+    }
+}
@@ -0,0 +1,225 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The resource usage of the node as a whole, read from /proc.
+
+use proto::observe::{
+    HostDiskMetrics, HostMetrics, HostNetworkMetrics, LoadAverage, Pressure,
+    PressureStall,
+};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECTOR_SIZE: u64 = 512;
+
+/// A sample of the resource usage of the node. Files that cannot be read
+/// leave their metrics unset.
+pub(crate) fn sample() -> HostMetrics {
+    let read = |path: &str| std::fs::read_to_string(path).ok();
+    HostMetrics {
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default(),
+        load_average: read("/proc/loadavg").and_then(|l| parse_loadavg(&l)),
+        memory: read("/proc/meminfo")
+            .map(|m| parse_meminfo(&m))
+            .unwrap_or_default(),
+        disks: read("/proc/diskstats")
+            .map(|d| parse_diskstats(&d))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|disk| is_disk(&disk.device))
+            .collect(),
+        network_interfaces: read("/proc/net/dev")
+            .map(|n| parse_net_dev(&n))
+            .unwrap_or_default(),
+        cpu_pressure: read("/proc/pressure/cpu").map(|p| parse_pressure(&p)),
+        memory_pressure: read("/proc/pressure/memory")
+            .map(|p| parse_pressure(&p)),
+        io_pressure: read("/proc/pressure/io").map(|p| parse_pressure(&p)),
+    }
+}
+
+/// Whole disks have a directory in /sys/block, unlike their partitions.
+fn is_disk(device: &str) -> bool {
+    !device.starts_with("loop")
+        && !device.starts_with("ram")
+        && Path::new("/sys/block").join(device).exists()
+}
+
+/// e.g. "0.52 0.58 0.59 2/1093 12345"
+fn parse_loadavg(loadavg: &str) -> Option<LoadAverage> {
+    let mut fields = loadavg.split_whitespace();
+    let one = fields.next()?.parse().ok()?;
+    let five = fields.next()?.parse().ok()?;
+    let fifteen = fields.next()?.parse().ok()?;
+    let (runnable, tasks) = fields.next()?.split_once('/')?;
+    Some(LoadAverage {
+        one,
+        five,
+        fifteen,
+        runnable: runnable.parse().ok()?,
+        tasks: tasks.parse().ok()?,
+    })
+}
+
+/// e.g. "MemAvailable:   12000 kB", in bytes
+fn parse_meminfo(meminfo: &str) -> HashMap<String, u64> {
+    meminfo
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            let mut value = value.split_whitespace();
+            let amount: u64 = value.next()?.parse().ok()?;
+            let amount = match value.next() {
+                Some("kB") => amount * 1024,
+                _ => amount,
+            };
+            Some((key.to_string(), amount))
+        })
+        .collect()
+}
+
+/// e.g. "8 0 sda 5000 20 80000 1200 3000 10 64000 900 0 2000 2100", see
+/// <linux>/Documentation/admin-guide/iostats.rst
+fn parse_diskstats(diskstats: &str) -> Vec<HostDiskMetrics> {
+    diskstats
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let counter =
+                |i: usize| -> Option<u64> { fields.get(i)?.parse().ok() };
+            Some(HostDiskMetrics {
+                device: fields.get(2)?.to_string(),
+                read_ops: counter(3)?,
+                read_bytes: counter(5)? * SECTOR_SIZE,
+                read_time_ms: counter(6)?,
+                write_ops: counter(7)?,
+                write_bytes: counter(9)? * SECTOR_SIZE,
+                write_time_ms: counter(10)?,
+                io_in_progress: counter(11)?,
+                io_time_ms: counter(12)?,
+            })
+        })
+        .collect()
+}
+
+/// e.g. "  eth0: 1000 10 0 0 0 0 0 0 2000 20 0 0 0 0 0 0", after two lines of
+/// headers
+fn parse_net_dev(net_dev: &str) -> Vec<HostNetworkMetrics> {
+    net_dev
+        .lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (interface, counters) = line.split_once(':')?;
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(|c| c.parse().ok())
+                .collect::<Option<_>>()?;
+            Some(HostNetworkMetrics {
+                interface: interface.trim().to_string(),
+                rx_bytes: *counters.first()?,
+                rx_packets: *counters.get(1)?,
+                rx_errors: *counters.get(2)?,
+                rx_dropped: *counters.get(3)?,
+                tx_bytes: *counters.get(8)?,
+                tx_packets: *counters.get(9)?,
+                tx_errors: *counters.get(10)?,
+                tx_dropped: *counters.get(11)?,
+            })
+        })
+        .collect()
+}
+
+/// e.g. "some avg10=1.50 avg60=0.80 avg300=0.20 total=123456", followed by
+/// a "full" line
+fn parse_pressure(pressure: &str) -> Pressure {
+    let stall = |kind: &str| {
+        let line = pressure.lines().find_map(|l| l.strip_prefix(kind))?;
+        let mut stall = PressureStall::default();
+        for field in line.split_whitespace() {
+            match field.split_once('=')? {
+                ("avg10", value) => stall.avg10 = value.parse().ok()?,
+                ("avg60", value) => stall.avg60 = value.parse().ok()?,
+                ("avg300", value) => stall.avg300 = value.parse().ok()?,
+                ("total", value) => stall.total_usec = value.parse().ok()?,
+                _ => {}
+            }
+        }
+        Some(stall)
+    };
+    Pressure { some: stall("some "), full: stall("full ") }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_load_and_memory() {
+        let load =
+            parse_loadavg("0.52 0.58 0.59 2/1093 12345\n").expect("load");
+        assert_eq!(load.one, 0.52);
+        assert_eq!(load.fifteen, 0.59);
+        assert_eq!((load.runnable, load.tasks), (2, 1093));
+
+        let memory = parse_meminfo(
+            "MemTotal:       16000 kB\nHugePages_Total:       4\n",
+        );
+        assert_eq!(memory.get("MemTotal"), Some(&(16000 * 1024)));
+        assert_eq!(memory.get("HugePages_Total"), Some(&4));
+    }
+
+    #[test]
+    fn parses_disk_and_network_counters() {
+        let disks = parse_diskstats(
+            "   8       0 sda 5000 20 80000 1200 3000 10 64000 900 1 2000 2100 0 0 0 0\n",
+        );
+        assert_eq!(disks.len(), 1);
+        assert_eq!(disks[0].device, "sda");
+        assert_eq!(disks[0].read_bytes, 80000 * 512);
+        assert_eq!(disks[0].write_ops, 3000);
+        assert_eq!(disks[0].io_in_progress, 1);
+
+        let interfaces = parse_net_dev(
+            "Inter-|   Receive |  Transmit\n \
+             face |bytes packets errs drop fifo frame compressed multicast|bytes packets errs drop fifo colls carrier compressed\n  \
+             eth0: 1000 10 1 2 0 0 0 0 2000 20 3 4 0 0 0 0\n",
+        );
+        assert_eq!(interfaces.len(), 1);
+        assert_eq!(interfaces[0].interface, "eth0");
+        assert_eq!(
+            (interfaces[0].rx_bytes, interfaces[0].rx_dropped),
+            (1000, 2)
+        );
+        assert_eq!(
+            (interfaces[0].tx_packets, interfaces[0].tx_errors),
+            (20, 3)
+        );
+    }
+
+    #[test]
+    fn parses_pressure_stalls() {
+        let pressure = parse_pressure(
+            "some avg10=1.50 avg60=0.80 avg300=0.20 total=123456\n",
+        );
+        let some = pressure.some.expect("some");
+        assert_eq!(some.avg10, 1.5);
+        assert_eq!(some.total_usec, 123456);
+        assert!(pressure.full.is_none());
+    }
+}
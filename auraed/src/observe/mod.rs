@@ -15,9 +15,16 @@
 
 pub(crate) use error::ObserveServiceError;
 pub(crate) use observe_service::ObserveService;
+pub(crate) use workload_events::{Workload, WorkloadEvent, WorkloadEventKind};
 
-mod cgroup_cache;
+pub(crate) mod cgroup_cache;
+pub(crate) mod core_dumps;
+mod cgroup_metrics;
+mod cpu_profiles;
 mod error;
+mod file_watch;
+mod host_metrics;
 mod observe_service;
 mod observed_event_stream;
-mod proc_cache;
\ No newline at end of file
+mod proc_cache;
+mod workload_events;
@@ -17,28 +17,66 @@
 #![allow(dead_code)]
 
 use super::cgroup_cache;
+use super::cgroup_metrics;
+use super::core_dumps::{self, CoreDump};
+use super::cpu_profiles;
 use super::error::ObserveServiceError;
+use super::file_watch;
+use super::host_metrics;
 use super::observed_event_stream::ObservedEventStream;
 use super::proc_cache::{ProcCache, ProcfsProcessInfo};
+use super::workload_events::{
+    Workload, WorkloadEvent, WorkloadEventBus, WorkloadEventKind,
+};
 use crate::ebpf::tracepoint::PerfEventBroadcast;
-use crate::logging::log_channel::LogChannel;
-use aurae_ebpf_shared::{ForkedProcess, ProcessExit, Signal};
+use crate::ebpf::{CgroupFilter, NetworkAccounting};
+use crate::logging::log_channel::{LogChannel, LogLabels, LogReceiver};
+use crate::logging::log_filter::DAEMON_LOG_FILTER;
+use crate::logging::log_journal::{LogJournal, ReplayFrom};
+use crate::resumable::{impl_resumable, ResumableStream, ResumableStreams};
+use aurae_ebpf_shared::{
+    ForkedProcess, OomKill, ProcessExec, ProcessExit, Signal, TcpConnect,
+    NET_RX_BYTES, NET_RX_PACKETS, NET_TX_BYTES, NET_TX_PACKETS,
+    TCP_CONNECT_CONNECTING, TCP_CONNECT_ESTABLISHED, TCP_CONNECT_FAILED,
+};
 use cgroup_cache::CgroupCache;
 use proto::observe::{
-    observe_service_server, GetAuraeDaemonLogStreamRequest,
-    GetAuraeDaemonLogStreamResponse, GetPosixSignalsStreamRequest,
-    GetPosixSignalsStreamResponse, GetSubProcessStreamRequest,
-    GetSubProcessStreamResponse, LogChannelType, LogItem,
-    Signal as PosixSignal, WorkloadType,
+    observe_service_server, CellEvent, CellMetrics, CellNetworkMetrics,
+    CoreDump as CoreDumpEvent, GetAuraeDaemonLogStreamRequest,
+    GetAuraeDaemonLogStreamResponse, GetCellEventStreamRequest,
+    GetCellEventStreamResponse, GetCellMetricsStreamRequest,
+    GetCellMetricsStreamResponse, GetCgroupMetricsStreamRequest,
+    GetCgroupMetricsStreamResponse, GetCoreDumpStreamRequest,
+    GetCoreDumpStreamResponse, GetCpuProfileRequest, GetCpuProfileResponse,
+    GetHostMetricsRequest, GetHostMetricsResponse, GetLogStreamRequest,
+    GetLogStreamResponse, GetOomKillStreamRequest, GetOomKillStreamResponse,
+    GetPosixSignalsStreamRequest, GetPosixSignalsStreamResponse,
+    GetProcessExecStreamRequest, GetProcessExecStreamResponse,
+    GetSubProcessStreamRequest, GetSubProcessStreamResponse,
+    GetTcpConnectStreamRequest, GetTcpConnectStreamResponse,
+    GetVmMetricsStreamRequest, GetVmMetricsStreamResponse,
+    GetWorkloadEventStreamRequest, GetWorkloadEventStreamResponse,
+    LogChannelType, LogFilter, LogItem, LogSeverity, OomConstraint,
+    OomKill as OomKillEvent, ProcessExec as ExecEvent,
+    SetAuraeDaemonLogFilterRequest, SetAuraeDaemonLogFilterResponse,
+    Signal as PosixSignal, TcpConnect as TcpConnectEvent, TcpConnectState,
+    VmMetrics, WatchPathRequest, WatchPathResponse,
+    Workload as WorkloadMessage, WorkloadEvent as WorkloadEventMessage,
+    WorkloadEventType, WorkloadType,
 };
 use std::collections::HashMap;
-use std::time::Duration;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{ffi::OsString, sync::Arc};
 use tokio::sync::mpsc;
-use tokio::sync::{broadcast::Receiver, Mutex};
+use tokio::sync::{broadcast, Mutex};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
-use tracing::info;
+use tracing::{info, warn};
+
+/// How often the metrics of cells are sampled.
+const CELL_METRICS_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug, Clone)]
 pub struct ObserveService {
@@ -46,20 +84,115 @@ pub struct ObserveService {
     cgroup_cache: Arc<Mutex<CgroupCache>>,
     proc_cache: Option<Arc<Mutex<ProcCache>>>,
     posix_signals: Option<PerfEventBroadcast<Signal>>,
+    process_execs: Option<PerfEventBroadcast<ProcessExec>>,
+    tcp_connects: Option<PerfEventBroadcast<TcpConnect>>,
+    oom_kills: Option<PerfEventBroadcast<OomKill>>,
+    core_dumps: broadcast::Sender<CoreDump>,
+    cell_events: broadcast::Sender<CellEvent>,
+    vm_metrics: broadcast::Sender<VmMetrics>,
+    cell_metrics: broadcast::Sender<CellMetrics>,
+    /// Counts the network traffic of top-level cells, if the eBPF probes
+    /// are loaded
+    network_accounting: Option<Arc<NetworkAccounting>>,
+    /// Drops the events of processes outside of cells in the kernel, if
+    /// the eBPF probes only report the events of cells
+    cgroup_filter: Option<Arc<CgroupFilter>>,
+    /// Whether the eBPF probe sampling CPU profiles may be loaded
+    cpu_profiling: bool,
+    workload_events: WorkloadEventBus,
     sub_process_consumer_list:
         Arc<Mutex<HashMap<i32, HashMap<LogChannelType, LogChannel>>>>,
+    /// Channels of workloads without a process of their own, such as the
+    /// containers of CRI pods, by a key unique to the workload
+    log_channels: Arc<Mutex<HashMap<String, LogChannel>>>,
+    /// Announces the channels registered while log streams are open
+    new_log_channels: broadcast::Sender<LogChannel>,
+    /// The journals of the channels, by channel name. Channels registered
+    /// again under the same name keep appending to the same journal.
+    journals: Arc<std::sync::Mutex<HashMap<String, Arc<LogJournal>>>>,
+    streams: Streams,
+}
+
+impl_resumable!(
+    GetAuraeDaemonLogStreamResponse,
+    GetSubProcessStreamResponse,
+    GetLogStreamResponse,
+    GetPosixSignalsStreamResponse,
+    GetProcessExecStreamResponse,
+    GetTcpConnectStreamResponse,
+    GetOomKillStreamResponse,
+    GetCoreDumpStreamResponse,
+    GetCellEventStreamResponse,
+    GetVmMetricsStreamResponse,
+    GetCellMetricsStreamResponse,
+    GetWorkloadEventStreamResponse,
+    GetCgroupMetricsStreamResponse,
+    WatchPathResponse,
+);
+
+/// The streams of the calls clients can resume, by RPC.
+#[derive(Debug, Clone)]
+struct Streams {
+    daemon_log: ResumableStreams<GetAuraeDaemonLogStreamResponse>,
+    sub_process: ResumableStreams<GetSubProcessStreamResponse>,
+    logs: ResumableStreams<GetLogStreamResponse>,
+    posix_signals: ResumableStreams<GetPosixSignalsStreamResponse>,
+    process_execs: ResumableStreams<GetProcessExecStreamResponse>,
+    tcp_connects: ResumableStreams<GetTcpConnectStreamResponse>,
+    oom_kills: ResumableStreams<GetOomKillStreamResponse>,
+    core_dumps: ResumableStreams<GetCoreDumpStreamResponse>,
+    cell_events: ResumableStreams<GetCellEventStreamResponse>,
+    vm_metrics: ResumableStreams<GetVmMetricsStreamResponse>,
+    cell_metrics: ResumableStreams<GetCellMetricsStreamResponse>,
+    workload_events: ResumableStreams<GetWorkloadEventStreamResponse>,
+    cgroup_metrics: ResumableStreams<GetCgroupMetricsStreamResponse>,
+    watch_path: ResumableStreams<WatchPathResponse>,
+}
+
+impl Streams {
+    fn new() -> Self {
+        Self {
+            daemon_log: ResumableStreams::new(),
+            sub_process: ResumableStreams::new(),
+            logs: ResumableStreams::new(),
+            posix_signals: ResumableStreams::new(),
+            process_execs: ResumableStreams::new(),
+            tcp_connects: ResumableStreams::new(),
+            oom_kills: ResumableStreams::new(),
+            core_dumps: ResumableStreams::new(),
+            cell_events: ResumableStreams::new(),
+            vm_metrics: ResumableStreams::new(),
+            cell_metrics: ResumableStreams::new(),
+            workload_events: ResumableStreams::new(),
+            cgroup_metrics: ResumableStreams::new(),
+            watch_path: ResumableStreams::new(),
+        }
+    }
+}
+
+/// Resumes the stream of `request` if it carries a resume token.
+macro_rules! resume {
+    ($streams:expr, $request:expr) => {
+        if !$request.resume_token.is_empty() {
+            let (stream, ()) = $streams.resume(&$request.resume_token)?;
+            return Ok(Response::new(stream));
+        }
+    };
 }
 
 type PerfEvents = (
     Option<PerfEventBroadcast<ForkedProcess>>,
     Option<PerfEventBroadcast<ProcessExit>>,
     Option<PerfEventBroadcast<Signal>>,
+    Option<PerfEventBroadcast<ProcessExec>>,
+    Option<PerfEventBroadcast<TcpConnect>>,
+    Option<PerfEventBroadcast<OomKill>>,
 );
 
 impl ObserveService {
     pub fn new(aurae_logger: Arc<LogChannel>, perf_events: PerfEvents) -> Self {
         let proc_cache = match perf_events {
-            (Some(f), Some(e), _) => {
+            (Some(f), Some(e), ..) => {
                 Some(Arc::new(Mutex::new(ProcCache::new(
                     Duration::from_secs(60),
                     Duration::from_secs(60),
@@ -70,15 +203,69 @@ impl ObserveService {
             }
             _ => None,
         };
-        Self {
+        let svc = Self {
             aurae_logger,
             cgroup_cache: Arc::new(Mutex::new(CgroupCache::new(
                 OsString::from("/sys/fs/cgroup"),
             ))),
             proc_cache,
             posix_signals: perf_events.2,
+            process_execs: perf_events.3,
+            tcp_connects: perf_events.4,
+            oom_kills: perf_events.5,
+            core_dumps: broadcast::channel(16).0,
+            cell_events: broadcast::channel(16).0,
+            vm_metrics: broadcast::channel(64).0,
+            cell_metrics: broadcast::channel(64).0,
+            network_accounting: None,
+            cgroup_filter: None,
+            cpu_profiling: false,
+            workload_events: WorkloadEventBus::new(64),
             sub_process_consumer_list: Arc::new(Mutex::new(HashMap::new())),
+            log_channels: Arc::new(Mutex::new(HashMap::new())),
+            new_log_channels: broadcast::channel(16).0,
+            journals: Default::default(),
+            streams: Streams::new(),
+        };
+        svc.journal(&svc.aurae_logger);
+        svc
+    }
+
+    /// Journals the items of `channel` below the log journals directory of
+    /// the runtime, so clients can replay them. Without a runtime, as in
+    /// tests, channels are not journaled.
+    fn journal(&self, channel: &LogChannel) {
+        let Some(runtime) = crate::AURAED_RUNTIME.get() else {
+            return;
+        };
+        if channel.ring().is_some() {
+            return;
         }
+        let mut journals = self.journals.lock().expect("journals lock");
+        let journal = match journals.get(&channel.name) {
+            Some(journal) => journal.clone(),
+            None => {
+                let dir = runtime
+                    .log_journals_dir()
+                    .join(channel.name.replace('/', "_"));
+                match LogJournal::open(dir, runtime.log_journal.clone()) {
+                    Ok(journal) => {
+                        let journal = Arc::new(journal);
+                        let _ = journals
+                            .insert(channel.name.clone(), journal.clone());
+                        journal
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to open the log journal of {}: {e}",
+                            channel.name
+                        );
+                        return;
+                    }
+                }
+            }
+        };
+        channel.set_journal(journal);
     }
 
     pub async fn register_sub_process_channel(
@@ -88,6 +275,15 @@ impl ObserveService {
         channel: LogChannel,
     ) -> Result<(), ObserveServiceError> {
         info!("Registering channel for pid {pid} {channel_type:?}");
+        // the cell is only known once the process is running
+        let cell_name = std::fs::read_to_string(format!("/proc/{pid}/cgroup"))
+            .ok()
+            .and_then(|cgroup| core_dumps::cell_of(&cgroup))
+            .unwrap_or_default();
+        let labels = LogLabels { cell_name, ..channel.labels().clone() };
+        let channel = channel.with_labels(labels);
+        self.journal(&channel);
+
         let mut consumer_list = self.sub_process_consumer_list.lock().await;
         if consumer_list.get(&pid).is_none() {
             let _ = consumer_list.insert(pid, HashMap::new());
@@ -106,7 +302,9 @@ impl ObserveService {
         let _ = consumer_list
             .get_mut(&pid)
             .expect("pid channels")
-            .insert(channel_type, channel);
+            .insert(channel_type, channel.clone());
+        // an error only means no log stream is open
+        let _ = self.new_log_channels.send(channel);
         Ok(())
     }
 
@@ -130,8 +328,327 @@ impl ObserveService {
         Ok(())
     }
 
-    fn get_aurae_daemon_log_stream(&self) -> Receiver<LogItem> {
-        self.aurae_logger.subscribe()
+    /// Registers the channel of a workload without a process of its own, so
+    /// its output is part of the log stream. The labels of the channel are
+    /// what the stream can be filtered by.
+    pub async fn register_log_channel(&self, key: String, channel: LogChannel) {
+        info!("Registering log channel {key}");
+        self.journal(&channel);
+        let _ = self.log_channels.lock().await.insert(key, channel.clone());
+        // an error only means no log stream is open
+        let _ = self.new_log_channels.send(channel);
+    }
+
+    /// Removes a channel registered with [ObserveService::register_log_channel],
+    /// along with its journal.
+    pub async fn unregister_log_channel(&self, key: &str) {
+        info!("Unregistering log channel {key}");
+        let Some(channel) = self.log_channels.lock().await.remove(key) else {
+            return;
+        };
+        let removed = self
+            .journals
+            .lock()
+            .expect("journals lock")
+            .remove(&channel.name)
+            .and_then(|_| crate::AURAED_RUNTIME.get());
+        if let Some(runtime) = removed {
+            let dir =
+                runtime.log_journals_dir().join(channel.name.replace('/', "_"));
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                warn!("Failed to remove the log journal of {key}: {e}");
+            }
+        }
+    }
+
+    /// Subscribes to the channels matching the labels `filter` asks for. The
+    /// channels registered later are received from the returned receiver.
+    async fn subscribe_log_channels(
+        &self,
+        filter: &LogFilter,
+    ) -> Result<(Vec<LogReceiver>, broadcast::Receiver<LogChannel>), Status>
+    {
+        // subscribe first to not miss channels registered in between
+        let new_channels = self.new_log_channels.subscribe();
+
+        let mut channels = vec![self.aurae_logger.as_ref().clone()];
+        channels.extend(
+            self.sub_process_consumer_list
+                .lock()
+                .await
+                .values()
+                .flat_map(|channels| channels.values().cloned()),
+        );
+        channels.extend(self.log_channels.lock().await.values().cloned());
+
+        let receivers = channels
+            .iter()
+            .filter(|channel| labels_match(channel.labels(), filter))
+            .map(|channel| subscribe_since(channel, filter.since_timestamp))
+            .collect::<Result<_, _>>()?;
+        Ok((receivers, new_channels))
+    }
+
+    /// Emit the core dumps stored below `cores_dir` by the core dump helper,
+    /// each one also as a crashed workload event.
+    pub fn listen_for_core_dumps(
+        &self,
+        cores_dir: &Path,
+    ) -> anyhow::Result<()> {
+        core_dumps::listen(cores_dir, self.core_dumps.clone())?;
+
+        let mut dumps = self.core_dumps.subscribe();
+        let workload_events = self.workload_events.clone();
+        let _ignored = tokio::spawn(async move {
+            loop {
+                match dumps.recv().await {
+                    Ok(dump) => workload_events.publish((&dump).into()),
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Workload events missed {missed} core dumps")
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Notify the consumers of workload events, such as webhooks, about a
+    /// workload lifecycle event.
+    pub fn emit_workload_event(&self, event: WorkloadEvent) {
+        self.workload_events.publish(event);
+    }
+
+    /// Subscribe to the workload lifecycle events.
+    pub(crate) fn subscribe_workload_events(
+        &self,
+    ) -> broadcast::Receiver<WorkloadEvent> {
+        self.workload_events.subscribe()
+    }
+
+    /// Whether the OOM kills of processes are reported by the eBPF probes,
+    /// see [ObserveService::report_oom_kills].
+    pub(crate) fn has_oom_kill_probe(&self) -> bool {
+        self.oom_kills.is_some()
+    }
+
+    /// Reports the processes killed by the OOM killer as OOM-killed workload
+    /// events, if the eBPF probes are loaded.
+    pub(crate) fn report_oom_kills(&self) {
+        let Some(oom_kills) = &self.oom_kills else {
+            return;
+        };
+        let mut oom_kills = oom_kills.subscribe();
+        let cgroup_cache = self.cgroup_cache.clone();
+        let workload_events = self.workload_events.clone();
+
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let kill = match oom_kills.recv().await {
+                    Ok(kill) => kill,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let cgroup = cgroup_cache.lock().await.get(kill.cgroup_id);
+                let cell_name = cgroup
+                    .and_then(|cgroup| cell_of_cgroup(Path::new(&cgroup)));
+                // the victim may already be gone
+                let executable =
+                    std::fs::read_to_string(format!("/proc/{}/comm", kill.pid))
+                        .map(|comm| comm.trim_end().to_string())
+                        .unwrap_or_default();
+
+                let message = match &cell_name {
+                    Some(cell_name) => format!(
+                        "OOM killer killed process {} of {cell_name}",
+                        kill.pid
+                    ),
+                    None => format!("OOM killer killed process {}", kill.pid),
+                };
+                workload_events.publish(WorkloadEvent::new(
+                    WorkloadEventKind::OomKilled,
+                    Workload::Process { cell_name, pid: kill.pid, executable },
+                    message,
+                ));
+            }
+        });
+    }
+
+    /// Notify subscribers of the cell event stream about a cell lifecycle event.
+    pub fn emit_cell_event(&self, event: CellEvent) {
+        // an error only means nobody is subscribed
+        let _ = self.cell_events.send(event);
+    }
+
+    /// Counts the network traffic of the top-level cells attached to
+    /// `network_accounting` in the cell metrics.
+    pub fn with_network_accounting(
+        mut self,
+        network_accounting: NetworkAccounting,
+    ) -> Self {
+        self.network_accounting = Some(Arc::new(network_accounting));
+        self
+    }
+
+    /// Serves CPU profiles, sampled by an eBPF probe loaded for each profile.
+    pub fn with_cpu_profiling(mut self) -> Self {
+        self.cpu_profiling = true;
+        self
+    }
+
+    /// Only reports the events of the processes of the top-level cells
+    /// attached to `cgroup_filter`, see [ObserveService::attach_cell].
+    pub fn with_cgroup_filter(mut self, cgroup_filter: CgroupFilter) -> Self {
+        self.cgroup_filter = Some(Arc::new(cgroup_filter));
+        self
+    }
+
+    /// Starts counting the network traffic of the top-level cell
+    /// `cell_name`, and reporting the events of its processes if the eBPF
+    /// probes only report the events of cells. Does nothing without the
+    /// eBPF probes.
+    pub(crate) fn attach_cell(&self, cell_name: &str) {
+        if let Some(network_accounting) = &self.network_accounting {
+            if let Err(e) = network_accounting.attach(cell_name) {
+                warn!(
+                    "Failed to count the network traffic of cell {cell_name}: {e}"
+                );
+            }
+        }
+        if let Some(cgroup_filter) = &self.cgroup_filter {
+            if let Err(e) = cgroup_filter.add(cell_name) {
+                warn!("Failed to report the events of cell {cell_name}: {e}");
+            }
+        }
+    }
+
+    /// Stops observing the top-level cell `cell_name`, once freed.
+    pub(crate) fn detach_cell(&self, cell_name: &str) {
+        if let Some(network_accounting) = &self.network_accounting {
+            network_accounting.detach(cell_name);
+        }
+        if let Some(cgroup_filter) = &self.cgroup_filter {
+            if let Err(e) = cgroup_filter.remove(cell_name) {
+                warn!("Failed to stop reporting the events of cell {cell_name}: {e}");
+            }
+        }
+    }
+
+    /// Periodically samples the metrics of the top-level cells and
+    /// publishes them while anybody is subscribed to them.
+    pub(crate) fn publish_cell_metrics(&self) {
+        let Some(network_accounting) = self.network_accounting.clone() else {
+            return;
+        };
+        let cell_metrics = self.cell_metrics.clone();
+
+        let _ignored = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(CELL_METRICS_INTERVAL);
+            loop {
+                let _ = interval.tick().await;
+                if cell_metrics.receiver_count() == 0 {
+                    continue;
+                }
+
+                let network_accounting = network_accounting.clone();
+                let sample = tokio::task::spawn_blocking(move || {
+                    network_accounting.sample()
+                })
+                .await;
+                let counters = match sample
+                    .map_err(anyhow::Error::from)
+                    .and_then(|sample| sample)
+                {
+                    Ok(counters) => counters,
+                    Err(e) => {
+                        warn!("Failed to sample the network traffic of cells: {e}");
+                        continue;
+                    }
+                };
+
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as i64)
+                    .unwrap_or_default();
+                for (cell_name, counters) in counters {
+                    // an error only means nobody is subscribed
+                    let _ = cell_metrics.send(CellMetrics {
+                        cell_name,
+                        timestamp,
+                        network: Some(CellNetworkMetrics {
+                            rx_bytes: counters[NET_RX_BYTES],
+                            rx_packets: counters[NET_RX_PACKETS],
+                            tx_bytes: counters[NET_TX_BYTES],
+                            tx_packets: counters[NET_TX_PACKETS],
+                        }),
+                    });
+                }
+            }
+        });
+    }
+
+    /// Notify subscribers of the VM metrics stream about a sample of the
+    /// metrics of a VM.
+    pub fn emit_vm_metrics(&self, metrics: VmMetrics) {
+        // an error only means nobody is subscribed
+        let _ = self.vm_metrics.send(metrics);
+    }
+
+    /// Whether anybody is subscribed to the VM metrics stream, so sampling
+    /// can be skipped otherwise.
+    pub fn has_vm_metrics_subscribers(&self) -> bool {
+        self.vm_metrics.receiver_count() > 0
+    }
+
+    /// Forwards the items of the channels matching `filter`, including the
+    /// channels registered later, as mapped by `map` to `tx`, until `tx` is
+    /// closed.
+    pub(crate) async fn forward_logs<T: Send + 'static>(
+        &self,
+        filter: LogFilter,
+        tx: mpsc::Sender<T>,
+        map: fn(LogItem) -> T,
+    ) -> Result<(), Status> {
+        let (log_consumers, mut new_channels) =
+            self.subscribe_log_channels(&filter).await?;
+        for log_consumer in log_consumers {
+            forward_log_items(log_consumer, filter.clone(), tx.clone(), map);
+        }
+
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let channel = tokio::select! {
+                    channel = new_channels.recv() => channel,
+                    _ = tx.closed() => break,
+                };
+                match channel {
+                    Ok(channel) if labels_match(channel.labels(), &filter) => {
+                        // without its journal, the channel is still streamed
+                        let log_consumer =
+                            subscribe_since(&channel, filter.since_timestamp)
+                                .unwrap_or_else(|_| channel.subscribe());
+                        forward_log_items(
+                            log_consumer,
+                            filter.clone(),
+                            tx.clone(),
+                            map,
+                        );
+                    }
+                    Ok(_) => continue,
+                    // the output of the missed channels is lost
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn get_aurae_daemon_log_stream(
+        &self,
+        replay: Option<ReplayFrom>,
+    ) -> Result<LogReceiver, ObserveServiceError> {
+        subscribe_from(&self.aurae_logger, replay)
     }
 
     async fn get_posix_signals_stream(
@@ -148,6 +665,274 @@ impl ObserveService {
 
         ReceiverStream::new(events)
     }
+
+    fn get_process_exec_stream(
+        &self,
+        filter: Option<(WorkloadType, String)>,
+    ) -> ReceiverStream<Result<GetProcessExecStreamResponse, Status>> {
+        let mut events = ObservedEventStream::new(
+            self.process_execs.as_ref().expect("process execs"),
+        );
+        let _ = events.filter_by_workload(filter);
+        // Without the fork and exit probes, host PIDs are reported
+        if let Some(proc_cache) = &self.proc_cache {
+            let _ = events.map_pids(proc_cache.clone());
+        }
+
+        ReceiverStream::new(
+            events.subscribe(map_get_process_exec_stream_response),
+        )
+    }
+
+    fn get_tcp_connect_stream(
+        &self,
+        filter: Option<(WorkloadType, String)>,
+    ) -> ReceiverStream<Result<GetTcpConnectStreamResponse, Status>> {
+        let mut events = ObservedEventStream::new(
+            self.tcp_connects.as_ref().expect("tcp connects"),
+        );
+        let _ = events.filter_by_workload(filter);
+        if let Some(proc_cache) = &self.proc_cache {
+            let _ = events.map_pids(proc_cache.clone());
+        }
+
+        ReceiverStream::new(
+            events.subscribe(map_get_tcp_connect_stream_response),
+        )
+    }
+
+    fn get_oom_kill_stream(
+        &self,
+        filter: Option<(WorkloadType, String)>,
+    ) -> ReceiverStream<Result<GetOomKillStreamResponse, Status>> {
+        let mut events = ObservedEventStream::new(
+            self.oom_kills.as_ref().expect("oom kills"),
+        );
+        let _ = events.filter_by_workload(filter);
+        if let Some(proc_cache) = &self.proc_cache {
+            let _ = events.map_pids(proc_cache.clone());
+        }
+
+        ReceiverStream::new(events.subscribe(map_get_oom_kill_stream_response))
+    }
+}
+
+/// Where the replay of the journal of a channel starts, if it is replayed.
+fn replay_from(
+    from_offset: Option<u64>,
+    from_timestamp: Option<i64>,
+) -> Result<Option<ReplayFrom>, ObserveServiceError> {
+    match (from_offset, from_timestamp) {
+        (Some(_), Some(_)) => Err(ObserveServiceError::AmbiguousReplay),
+        (Some(offset), None) => Ok(Some(ReplayFrom::Offset(offset))),
+        (None, Some(timestamp)) => Ok(Some(ReplayFrom::Timestamp(timestamp))),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Subscribes to `channel`, replaying its journal from `replay` on first.
+fn subscribe_from(
+    channel: &LogChannel,
+    replay: Option<ReplayFrom>,
+) -> Result<LogReceiver, ObserveServiceError> {
+    match replay {
+        Some(from) => channel.subscribe_from(from).map_err(|source| {
+            ObserveServiceError::FailedToReplay {
+                channel: channel.name.clone(),
+                source,
+            }
+        }),
+        None => Ok(channel.subscribe()),
+    }
+}
+
+/// Subscribes to `channel` for a log stream, replaying the items journaled
+/// since `since_timestamp` if it is set.
+fn subscribe_since(
+    channel: &LogChannel,
+    since_timestamp: i64,
+) -> Result<LogReceiver, ObserveServiceError> {
+    let replay =
+        (since_timestamp > 0).then_some(ReplayFrom::Timestamp(since_timestamp));
+    subscribe_from(channel, replay)
+}
+
+/// Whether the items of a channel labelled `labels` can match `filter`.
+/// Empty fields of the filter match any label.
+fn labels_match(labels: &LogLabels, filter: &LogFilter) -> bool {
+    let matches =
+        |wanted: &str, label: &str| wanted.is_empty() || wanted == label;
+    matches(&filter.cell_name, &labels.cell_name)
+        && matches(&filter.executable_name, &labels.executable_name)
+        && matches(&filter.pod_sandbox_id, &labels.pod_sandbox_id)
+        && matches(&filter.container_id, &labels.container_id)
+}
+
+/// Whether a log item of a channel matching `filter` is severe and recent
+/// enough to be streamed.
+fn log_item_matches(item: &LogItem, filter: &LogFilter) -> bool {
+    item.severity >= filter.min_severity
+        && item.timestamp >= filter.since_timestamp
+}
+
+/// Forwards the items of `log_consumer` matching `filter`, as mapped by
+/// `map`, to `tx`, until either the channel or `tx` is closed.
+fn forward_log_items<T: Send + 'static>(
+    mut log_consumer: LogReceiver,
+    filter: LogFilter,
+    tx: mpsc::Sender<T>,
+    map: fn(LogItem) -> T,
+) {
+    let _ignored = tokio::spawn(async move {
+        loop {
+            let log_item = tokio::select! {
+                log_item = log_consumer.recv() => log_item,
+                // quiet channels would otherwise outlive the stream
+                _ = tx.closed() => break,
+            };
+            let Some(log_item) = log_item else {
+                break;
+            };
+            if !log_item_matches(&log_item, &filter) {
+                continue;
+            }
+            if tx.send(map(log_item)).await.is_err() {
+                // receiver is gone
+                break;
+            }
+        }
+    });
+}
+
+/// Whether a core dump belongs to the workload a stream is scoped to.
+fn core_dump_matches(
+    dump: &CoreDump,
+    filter: &Option<(WorkloadType, String)>,
+) -> bool {
+    match filter {
+        Some((WorkloadType::Cell, id)) => {
+            dump.cell_name.as_deref() == Some(id.as_str())
+        }
+        // Rejected when the stream is requested
+        Some((WorkloadType::PodSandbox | WorkloadType::Vm, _)) => false,
+        Some((WorkloadType::Unspecified, _)) | None => true,
+    }
+}
+
+/// Whether a cell event belongs to the workload a stream is scoped to.
+fn cell_event_matches(
+    event: &CellEvent,
+    filter: &Option<(WorkloadType, String)>,
+) -> bool {
+    match filter {
+        Some((WorkloadType::Cell, id)) => event.cell_name == *id,
+        _ => true,
+    }
+}
+
+/// Whether a VM metrics sample belongs to the workload a stream is scoped to.
+fn vm_metrics_match(
+    metrics: &VmMetrics,
+    filter: &Option<(WorkloadType, String)>,
+) -> bool {
+    match filter {
+        Some((WorkloadType::Vm, id)) => metrics.vm_id == *id,
+        _ => true,
+    }
+}
+
+/// The cell, VM or pod sandbox a workload event happened to, None for
+/// processes outside of cells.
+fn workload_of(workload: &Workload) -> Option<(WorkloadType, &str)> {
+    match workload {
+        Workload::Executable { cell_name, .. }
+        | Workload::Cell { cell_name } => {
+            Some((WorkloadType::Cell, cell_name.as_str()))
+        }
+        Workload::Process { cell_name, .. } => cell_name
+            .as_deref()
+            .map(|cell_name| (WorkloadType::Cell, cell_name)),
+        Workload::Vm { vm_id } | Workload::VmSnapshot { vm_id, .. } => {
+            Some((WorkloadType::Vm, vm_id.as_str()))
+        }
+        Workload::PodSandbox { pod_sandbox_id }
+        | Workload::Container { pod_sandbox_id, .. } => {
+            Some((WorkloadType::PodSandbox, pod_sandbox_id.as_str()))
+        }
+    }
+}
+
+/// Whether a workload event belongs to the workload a stream is scoped to.
+fn workload_event_matches(
+    event: &WorkloadEvent,
+    filter: &Option<(WorkloadType, String)>,
+) -> bool {
+    match filter {
+        Some((WorkloadType::Unspecified, _)) | None => true,
+        Some((workload_type, id)) => {
+            workload_of(&event.workload) == Some((*workload_type, id.as_str()))
+        }
+    }
+}
+
+fn map_get_workload_event_stream_response(
+    event: WorkloadEvent,
+) -> GetWorkloadEventStreamResponse {
+    let event_type = match event.kind {
+        WorkloadEventKind::Started => WorkloadEventType::Started,
+        WorkloadEventKind::Stopped => WorkloadEventType::Stopped,
+        WorkloadEventKind::Crashed => WorkloadEventType::Crashed,
+        WorkloadEventKind::OomKilled => WorkloadEventType::OomKilled,
+        WorkloadEventKind::Migrated => WorkloadEventType::Migrated,
+        WorkloadEventKind::SnapshotTaken => WorkloadEventType::SnapshotTaken,
+    };
+    let mut resp = WorkloadEventMessage {
+        sequence: event.sequence,
+        event_type: event_type as i32,
+        timestamp: event.timestamp,
+        workload: workload_of(&event.workload).map(|(workload_type, id)| {
+            WorkloadMessage {
+                workload_type: workload_type as i32,
+                id: id.into(),
+            }
+        }),
+        message: event.message,
+        ..Default::default()
+    };
+    match event.workload {
+        Workload::Executable { executable_name, .. } => {
+            resp.executable_name = executable_name;
+        }
+        Workload::Process { pid, .. } => resp.process_id = pid,
+        Workload::VmSnapshot { snapshot_id, .. } => {
+            resp.snapshot_id = snapshot_id;
+        }
+        Workload::Container { container_id, .. } => {
+            resp.container_id = container_id;
+        }
+        Workload::Cell { .. }
+        | Workload::Vm { .. }
+        | Workload::PodSandbox { .. } => {}
+    }
+    GetWorkloadEventStreamResponse { event: Some(resp), ..Default::default() }
+}
+
+fn map_get_core_dump_stream_response(
+    dump: CoreDump,
+) -> GetCoreDumpStreamResponse {
+    GetCoreDumpStreamResponse {
+        core_dump: Some(CoreDumpEvent {
+            cell_name: dump.cell_name.unwrap_or_default(),
+            process_id: dump.pid,
+            signal: dump.signal,
+            timestamp: dump.timestamp,
+            executable: dump.executable,
+            path: dump.path.to_string_lossy().to_string(),
+            size: dump.size,
+            truncated: dump.truncated,
+        }),
+        ..Default::default()
+    }
 }
 
 fn map_get_posix_signals_stream_response(
@@ -156,21 +941,107 @@ fn map_get_posix_signals_stream_response(
 ) -> GetPosixSignalsStreamResponse {
     GetPosixSignalsStreamResponse {
         signal: Some(PosixSignal { signal: signal.signum, process_id: pid }),
+        ..Default::default()
+    }
+}
+
+fn map_get_process_exec_stream_response(
+    exec: ProcessExec,
+    pid: i32,
+) -> GetProcessExecStreamResponse {
+    GetProcessExecStreamResponse {
+        exec: Some(ExecEvent {
+            process_id: pid,
+            path: String::from_utf8_lossy(exec.filename()).into_owned(),
+            argv_hash: exec.argv_hash,
+        }),
+        ..Default::default()
+    }
+}
+
+fn map_get_tcp_connect_stream_response(
+    connect: TcpConnect,
+    pid: i32,
+) -> GetTcpConnectStreamResponse {
+    let state = match connect.state {
+        TCP_CONNECT_CONNECTING => TcpConnectState::Connecting,
+        TCP_CONNECT_ESTABLISHED => TcpConnectState::Established,
+        TCP_CONNECT_FAILED => TcpConnectState::Failed,
+        _ => TcpConnectState::Unspecified,
+    };
+    GetTcpConnectStreamResponse {
+        connect: Some(TcpConnectEvent {
+            process_id: pid,
+            state: state as i32,
+            source_address: ip_addr(connect.family, connect.saddr),
+            source_port: connect.sport.into(),
+            destination_address: ip_addr(connect.family, connect.daddr),
+            destination_port: connect.dport.into(),
+        }),
+        ..Default::default()
+    }
+}
+
+fn map_get_oom_kill_stream_response(
+    kill: OomKill,
+    pid: i32,
+) -> GetOomKillStreamResponse {
+    // enum oom_constraint of <linux>/include/linux/oom.h
+    let constraint = match kill.constraint {
+        0 => OomConstraint::None,
+        1 => OomConstraint::Cpuset,
+        2 => OomConstraint::MemoryPolicy,
+        3 => OomConstraint::Memcg,
+        _ => OomConstraint::Unspecified,
+    };
+    let page_size = procfs::page_size();
+    GetOomKillStreamResponse {
+        oom_kill: Some(OomKillEvent {
+            process_id: pid,
+            constraint: constraint as i32,
+            total_bytes: kill.total_pages.saturating_mul(page_size),
+            victim_bytes: kill.victim_points.saturating_mul(page_size as i64),
+        }),
+        ..Default::default()
+    }
+}
+
+/// The cell of the processes of `cgroup`, None outside of cells.
+fn cell_of_cgroup(cgroup: &Path) -> Option<String> {
+    cgroup
+        .strip_prefix("/sys/fs/cgroup")
+        .ok()?
+        .to_str()?
+        .strip_suffix("/_")
+        .map(Into::into)
+}
+
+/// Formats an address of a [TcpConnect], IPv4 addresses take its first 4
+/// bytes.
+fn ip_addr(family: u16, addr: [u8; 16]) -> String {
+    if i32::from(family) == libc::AF_INET {
+        Ipv4Addr::new(addr[0], addr[1], addr[2], addr[3]).to_string()
+    } else {
+        Ipv6Addr::from(addr).to_string()
     }
 }
 
 #[tonic::async_trait]
 impl observe_service_server::ObserveService for ObserveService {
     type GetAuraeDaemonLogStreamStream =
-        ReceiverStream<Result<GetAuraeDaemonLogStreamResponse, Status>>;
+        ResumableStream<GetAuraeDaemonLogStreamResponse>;
 
     async fn get_aurae_daemon_log_stream(
         &self,
-        _request: Request<GetAuraeDaemonLogStreamRequest>,
+        request: Request<GetAuraeDaemonLogStreamRequest>,
     ) -> Result<Response<Self::GetAuraeDaemonLogStreamStream>, Status> {
+        resume!(self.streams.daemon_log, request.get_ref());
+        let request = request.into_inner();
+        let replay = replay_from(request.from_offset, request.from_timestamp)?;
+
         let (tx, rx) =
             mpsc::channel::<Result<GetAuraeDaemonLogStreamResponse, Status>>(4);
-        let mut log_consumer = self.get_aurae_daemon_log_stream();
+        let mut log_consumer = self.get_aurae_daemon_log_stream(replay)?;
 
         // TODO: error handling. Warning: recursively logging if error message is also send to this grpc api endpoint
         //  .. thus disabled logging here.
@@ -178,9 +1049,11 @@ impl observe_service_server::ObserveService for ObserveService {
             // Log consumer will error if:
             //  the producer is closed (no more logs)
             //  the receiver is lagging
-            while let Ok(log_item) = log_consumer.recv().await {
-                let resp =
-                    GetAuraeDaemonLogStreamResponse { item: Some(log_item) };
+            while let Some(log_item) = log_consumer.recv().await {
+                let resp = GetAuraeDaemonLogStreamResponse {
+                    item: Some(log_item),
+                    ..Default::default()
+                };
                 if tx.send(Ok(resp)).await.is_err() {
                     // receiver is gone
                     break;
@@ -188,26 +1061,34 @@ impl observe_service_server::ObserveService for ObserveService {
             }
         });
 
-        Ok(Response::new(ReceiverStream::new(rx)))
+        Ok(Response::new(
+            self.streams.daemon_log.start(ReceiverStream::new(rx), ()),
+        ))
     }
 
     type GetSubProcessStreamStream =
-        ReceiverStream<Result<GetSubProcessStreamResponse, Status>>;
+        ResumableStream<GetSubProcessStreamResponse>;
 
     async fn get_sub_process_stream(
         &self,
         request: Request<GetSubProcessStreamRequest>,
     ) -> Result<Response<Self::GetSubProcessStreamStream>, Status> {
+        resume!(self.streams.sub_process, request.get_ref());
+
         let channel = LogChannelType::from_i32(request.get_ref().channel_type)
             .ok_or(ObserveServiceError::InvalidLogChannelType {
                 channel_type: request.get_ref().channel_type,
             })?;
         let pid: i32 = request.get_ref().process_id;
+        let replay = replay_from(
+            request.get_ref().from_offset,
+            request.get_ref().from_timestamp,
+        )?;
 
         println!("Requested Channel {channel:?}");
         println!("Requested Process ID {pid}");
 
-        let mut log_consumer = {
+        let log_channel = {
             let mut consumer_list = self.sub_process_consumer_list.lock().await;
             consumer_list
                 .get_mut(&pid)
@@ -218,8 +1099,8 @@ impl observe_service_server::ObserveService for ObserveService {
                     channel_type: channel,
                 })?
                 .clone()
-        }
-        .subscribe();
+        };
+        let mut log_consumer = subscribe_from(&log_channel, replay)?;
 
         let (tx, rx) =
             mpsc::channel::<Result<GetSubProcessStreamResponse, Status>>(4);
@@ -229,9 +1110,12 @@ impl observe_service_server::ObserveService for ObserveService {
         let _ignored = tokio::spawn(async move {
             // Log consumer will error if:
             //  the producer is closed (no more logs)
-            //  the receiver is lagging
-            while let Ok(log_item) = log_consumer.recv().await {
-                let resp = GetSubProcessStreamResponse { item: Some(log_item) };
+            //  the receiver is lagging behind the broadcast channel
+            while let Some(log_item) = log_consumer.recv().await {
+                let resp = GetSubProcessStreamResponse {
+                    item: Some(log_item),
+                    ..Default::default()
+                };
                 if tx.send(Ok(resp)).await.is_err() {
                     // receiver is gone
                     break;
@@ -239,44 +1123,444 @@ impl observe_service_server::ObserveService for ObserveService {
             }
         });
 
-        Ok(Response::new(ReceiverStream::new(rx)))
+        Ok(Response::new(
+            self.streams.sub_process.start(ReceiverStream::new(rx), ()),
+        ))
+    }
+
+    type GetLogStreamStream = ResumableStream<GetLogStreamResponse>;
+
+    async fn get_log_stream(
+        &self,
+        request: Request<GetLogStreamRequest>,
+    ) -> Result<Response<Self::GetLogStreamStream>, Status> {
+        resume!(self.streams.logs, request.get_ref());
+        let filter = request.into_inner().filter.unwrap_or_default();
+        if LogSeverity::from_i32(filter.min_severity).is_none() {
+            return Err(ObserveServiceError::InvalidLogSeverity {
+                severity: filter.min_severity,
+            }
+            .into());
+        }
+
+        let (tx, rx) =
+            mpsc::channel::<Result<GetLogStreamResponse, Status>>(16);
+        self.forward_logs(filter, tx, |item| {
+            Ok(GetLogStreamResponse { item: Some(item), ..Default::default() })
+        })
+        .await?;
+
+        Ok(Response::new(self.streams.logs.start(ReceiverStream::new(rx), ())))
     }
 
     type GetPosixSignalsStreamStream =
-        ReceiverStream<Result<GetPosixSignalsStreamResponse, Status>>;
+        ResumableStream<GetPosixSignalsStreamResponse>;
 
     async fn get_posix_signals_stream(
         &self,
         request: Request<GetPosixSignalsStreamRequest>,
     ) -> Result<Response<Self::GetPosixSignalsStreamStream>, Status> {
+        resume!(self.streams.posix_signals, request.get_ref());
         if self.posix_signals.is_none() {
             return Err(Status::unimplemented("GetPosixSignalStream is not implemented for nested Aurae daemons"));
         }
 
-        Ok(Response::new(
-            self.get_posix_signals_stream(
+        let events = self
+            .get_posix_signals_stream(
                 request
                     .into_inner()
                     .workload
                     .map(|w| (w.workload_type(), w.id)),
             )
-            .await,
+            .await;
+
+        Ok(Response::new(self.streams.posix_signals.start(events, ())))
+    }
+
+    type GetProcessExecStreamStream =
+        ResumableStream<GetProcessExecStreamResponse>;
+
+    async fn get_process_exec_stream(
+        &self,
+        request: Request<GetProcessExecStreamRequest>,
+    ) -> Result<Response<Self::GetProcessExecStreamStream>, Status> {
+        resume!(self.streams.process_execs, request.get_ref());
+        if self.process_execs.is_none() {
+            return Err(Status::unimplemented(
+                "GetProcessExecStream requires the sched_process_exec eBPF probe of the host Aurae daemon",
+            ));
+        }
+
+        let events = self.get_process_exec_stream(
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id)),
+        );
+
+        Ok(Response::new(self.streams.process_execs.start(events, ())))
+    }
+
+    type GetTcpConnectStreamStream =
+        ResumableStream<GetTcpConnectStreamResponse>;
+
+    async fn get_tcp_connect_stream(
+        &self,
+        request: Request<GetTcpConnectStreamRequest>,
+    ) -> Result<Response<Self::GetTcpConnectStreamStream>, Status> {
+        resume!(self.streams.tcp_connects, request.get_ref());
+        if self.tcp_connects.is_none() {
+            return Err(Status::unimplemented(
+                "GetTcpConnectStream requires the inet_sock_set_state eBPF probe of the host Aurae daemon",
+            ));
+        }
+
+        let events = self.get_tcp_connect_stream(
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id)),
+        );
+
+        Ok(Response::new(self.streams.tcp_connects.start(events, ())))
+    }
+
+    type GetOomKillStreamStream = ResumableStream<GetOomKillStreamResponse>;
+
+    async fn get_oom_kill_stream(
+        &self,
+        request: Request<GetOomKillStreamRequest>,
+    ) -> Result<Response<Self::GetOomKillStreamStream>, Status> {
+        resume!(self.streams.oom_kills, request.get_ref());
+        if self.oom_kills.is_none() {
+            return Err(Status::unimplemented(
+                "GetOomKillStream requires the oom_kill_process eBPF probe of the host Aurae daemon",
+            ));
+        }
+
+        let events = self.get_oom_kill_stream(
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id)),
+        );
+
+        Ok(Response::new(self.streams.oom_kills.start(events, ())))
+    }
+
+    type GetCoreDumpStreamStream = ResumableStream<GetCoreDumpStreamResponse>;
+
+    async fn get_core_dump_stream(
+        &self,
+        request: Request<GetCoreDumpStreamRequest>,
+    ) -> Result<Response<Self::GetCoreDumpStreamStream>, Status> {
+        resume!(self.streams.core_dumps, request.get_ref());
+        let filter =
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id));
+        if let Some((WorkloadType::PodSandbox | WorkloadType::Vm, _)) = filter {
+            return Err(Status::unimplemented(
+                "GetCoreDumpStream can only be scoped to cells",
+            ));
+        }
+
+        let (tx, rx) =
+            mpsc::channel::<Result<GetCoreDumpStreamResponse, Status>>(4);
+        let mut core_dumps = self.core_dumps.subscribe();
+
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let dump = match core_dumps.recv().await {
+                    Ok(dump) => dump,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Core dump stream missed {missed} core dumps");
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !core_dump_matches(&dump, &filter) {
+                    continue;
+                }
+                let resp = map_get_core_dump_stream_response(dump);
+                if tx.send(Ok(resp)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            self.streams.core_dumps.start(ReceiverStream::new(rx), ()),
+        ))
+    }
+
+    type GetCellEventStreamStream = ResumableStream<GetCellEventStreamResponse>;
+
+    async fn get_cell_event_stream(
+        &self,
+        request: Request<GetCellEventStreamRequest>,
+    ) -> Result<Response<Self::GetCellEventStreamStream>, Status> {
+        resume!(self.streams.cell_events, request.get_ref());
+        let filter =
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id));
+
+        let (tx, rx) =
+            mpsc::channel::<Result<GetCellEventStreamResponse, Status>>(4);
+        let mut cell_events = self.cell_events.subscribe();
+
+        let _ignored = tokio::spawn(async move {
+            while let Ok(event) = cell_events.recv().await {
+                if !cell_event_matches(&event, &filter) {
+                    continue;
+                }
+                let resp = GetCellEventStreamResponse {
+                    cell_event: Some(event),
+                    ..Default::default()
+                };
+                if tx.send(Ok(resp)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            self.streams.cell_events.start(ReceiverStream::new(rx), ()),
+        ))
+    }
+
+    type GetVmMetricsStreamStream = ResumableStream<GetVmMetricsStreamResponse>;
+
+    async fn get_vm_metrics_stream(
+        &self,
+        request: Request<GetVmMetricsStreamRequest>,
+    ) -> Result<Response<Self::GetVmMetricsStreamStream>, Status> {
+        resume!(self.streams.vm_metrics, request.get_ref());
+        let filter =
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id));
+
+        let (tx, rx) =
+            mpsc::channel::<Result<GetVmMetricsStreamResponse, Status>>(4);
+        let mut vm_metrics = self.vm_metrics.subscribe();
+
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let metrics = match vm_metrics.recv().await {
+                    Ok(metrics) => metrics,
+                    // a slow receiver only misses samples
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !vm_metrics_match(&metrics, &filter) {
+                    continue;
+                }
+                let resp = GetVmMetricsStreamResponse {
+                    vm_metrics: Some(metrics),
+                    ..Default::default()
+                };
+                if tx.send(Ok(resp)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            self.streams.vm_metrics.start(ReceiverStream::new(rx), ()),
+        ))
+    }
+
+    type GetCellMetricsStreamStream =
+        ResumableStream<GetCellMetricsStreamResponse>;
+
+    async fn get_cell_metrics_stream(
+        &self,
+        request: Request<GetCellMetricsStreamRequest>,
+    ) -> Result<Response<Self::GetCellMetricsStreamStream>, Status> {
+        resume!(self.streams.cell_metrics, request.get_ref());
+        let cell_name = request.into_inner().cell_name;
+
+        let (tx, rx) =
+            mpsc::channel::<Result<GetCellMetricsStreamResponse, Status>>(4);
+        let mut cell_metrics = self.cell_metrics.subscribe();
+
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let metrics = match cell_metrics.recv().await {
+                    Ok(metrics) => metrics,
+                    // a slow receiver only misses samples
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !cell_name.is_empty() && metrics.cell_name != cell_name {
+                    continue;
+                }
+                let resp = GetCellMetricsStreamResponse {
+                    cell_metrics: Some(metrics),
+                    ..Default::default()
+                };
+                if tx.send(Ok(resp)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            self.streams.cell_metrics.start(ReceiverStream::new(rx), ()),
+        ))
+    }
+
+    type WatchPathStream = ResumableStream<WatchPathResponse>;
+
+    async fn watch_path(
+        &self,
+        request: Request<WatchPathRequest>,
+    ) -> Result<Response<Self::WatchPathStream>, Status> {
+        let request = request.into_inner();
+        resume!(self.streams.watch_path, request);
+        let path = file_watch::resolve(
+            request.workload.map(|w| (w.workload_type(), w.id)),
+            &request.path,
+        )?;
+        let events = file_watch::watch(&path, |event| WatchPathResponse {
+            event: Some(event),
+            ..Default::default()
+        })?;
+
+        Ok(Response::new(
+            self.streams.watch_path.start(ReceiverStream::new(events), ()),
+        ))
+    }
+
+    type GetCgroupMetricsStreamStream =
+        ResumableStream<GetCgroupMetricsStreamResponse>;
+
+    async fn get_cgroup_metrics_stream(
+        &self,
+        request: Request<GetCgroupMetricsStreamRequest>,
+    ) -> Result<Response<Self::GetCgroupMetricsStreamStream>, Status> {
+        let request = request.into_inner();
+        resume!(self.streams.cgroup_metrics, request);
+        let interval = cgroup_metrics::interval(request.interval_ms)?;
+        let cgroup = cgroup_metrics::resolve(
+            request.workload.map(|w| (w.workload_type(), w.id)),
+        )?;
+        let samples =
+            cgroup_metrics::sample(cgroup, interval, |cgroup_metrics| {
+                GetCgroupMetricsStreamResponse {
+                    cgroup_metrics: Some(cgroup_metrics),
+                    ..Default::default()
+                }
+            });
+
+        Ok(Response::new(
+            self.streams.cgroup_metrics.start(ReceiverStream::new(samples), ()),
+        ))
+    }
+
+    async fn get_cpu_profile(
+        &self,
+        request: Request<GetCpuProfileRequest>,
+    ) -> Result<Response<GetCpuProfileResponse>, Status> {
+        if !self.cpu_profiling {
+            return Err(Status::unimplemented(
+                "GetCpuProfile requires the cpu_profiles eBPF probe of the host Aurae daemon",
+            ));
+        }
+        let profile = cpu_profiles::profile(request.into_inner()).await?;
+        Ok(Response::new(profile))
+    }
+
+    async fn get_host_metrics(
+        &self,
+        _request: Request<GetHostMetricsRequest>,
+    ) -> Result<Response<GetHostMetricsResponse>, Status> {
+        let host_metrics = tokio::task::spawn_blocking(host_metrics::sample)
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(Response::new(GetHostMetricsResponse {
+            host_metrics: Some(host_metrics),
+        }))
+    }
+
+    async fn set_aurae_daemon_log_filter(
+        &self,
+        request: Request<SetAuraeDaemonLogFilterRequest>,
+    ) -> Result<Response<SetAuraeDaemonLogFilterResponse>, Status> {
+        let filter = request.into_inner().filter;
+        let previous_filter =
+            DAEMON_LOG_FILTER.set(&filter).map_err(|source| {
+                ObserveServiceError::InvalidLogFilter { filter, source }
+            })?;
+        info!(
+            "log filter changed from '{previous_filter}' to '{}'",
+            DAEMON_LOG_FILTER.directives()
+        );
+        Ok(Response::new(SetAuraeDaemonLogFilterResponse { previous_filter }))
+    }
+
+    type GetWorkloadEventStreamStream =
+        ResumableStream<GetWorkloadEventStreamResponse>;
+
+    async fn get_workload_event_stream(
+        &self,
+        request: Request<GetWorkloadEventStreamRequest>,
+    ) -> Result<Response<Self::GetWorkloadEventStreamStream>, Status> {
+        resume!(self.streams.workload_events, request.get_ref());
+        let filter =
+            request.into_inner().workload.map(|w| (w.workload_type(), w.id));
+
+        let (tx, rx) =
+            mpsc::channel::<Result<GetWorkloadEventStreamResponse, Status>>(4);
+        let mut workload_events = self.workload_events.subscribe();
+
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let event = match workload_events.recv().await {
+                    Ok(event) => event,
+                    // the gap in the sequence numbers tells the client
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !workload_event_matches(&event, &filter) {
+                    continue;
+                }
+                let resp = map_get_workload_event_stream_response(event);
+                if tx.send(Ok(resp)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(
+            self.streams.workload_events.start(ReceiverStream::new(rx), ()),
         ))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::ObserveService;
-    use crate::logging::log_channel::LogChannel;
-    use proto::observe::LogChannelType;
+    use super::{
+        cell_of_cgroup, labels_match, log_item_matches,
+        map_get_oom_kill_stream_response, map_get_process_exec_stream_response,
+        map_get_tcp_connect_stream_response,
+        map_get_workload_event_stream_response, replay_from,
+        workload_event_matches, ObserveService, Workload, WorkloadEvent,
+        WorkloadEventKind,
+    };
+    use crate::logging::log_channel::{LogChannel, LogLabels};
+    use crate::logging::log_journal::ReplayFrom;
+    use aurae_ebpf_shared::{
+        argv_hash, OomKill, ProcessExec, TcpConnect, EXEC_FILENAME_LEN,
+        TCP_CONNECT_ESTABLISHED, TCP_CONNECT_FAILED,
+    };
+    use proto::observe::{
+        observe_service_server::ObserveService as _, GetLogStreamRequest,
+        LogChannelType, LogFilter, LogItem, LogSeverity, OomConstraint,
+        TcpConnectState, WorkloadEventType, WorkloadType,
+    };
+    use std::path::Path;
     use std::sync::Arc;
+    use tokio_stream::StreamExt;
+    use tonic::Request;
 
     #[tokio::test]
     async fn test_register_sub_process_channel_success() {
         let svc = ObserveService::new(
             Arc::new(LogChannel::new(String::from("auraed"))),
-            (None, None, None),
+            (None, None, None, None, None, None),
         );
         assert!(svc
             .register_sub_process_channel(
@@ -294,7 +1578,7 @@ mod tests {
     async fn test_register_sub_process_channel_duplicate_error() {
         let svc = ObserveService::new(
             Arc::new(LogChannel::new(String::from("auraed"))),
-            (None, None, None),
+            (None, None, None, None, None, None),
         );
         assert!(svc
             .register_sub_process_channel(
@@ -320,7 +1604,7 @@ mod tests {
     async fn test_unregister_sub_process_channel_success() {
         let svc = ObserveService::new(
             Arc::new(LogChannel::new(String::from("auraed"))),
-            (None, None, None),
+            (None, None, None, None, None, None),
         );
         assert!(svc
             .register_sub_process_channel(
@@ -342,7 +1626,7 @@ mod tests {
     async fn test_unregister_sub_process_channel_no_pid_error() {
         let svc = ObserveService::new(
             Arc::new(LogChannel::new(String::from("auraed"))),
-            (None, None, None),
+            (None, None, None, None, None, None),
         );
         assert!(svc
             .unregister_sub_process_channel(42, LogChannelType::Stdout)
@@ -356,7 +1640,7 @@ mod tests {
     async fn test_unregister_sub_process_channel_no_channel_type_error() {
         let svc = ObserveService::new(
             Arc::new(LogChannel::new(String::from("auraed"))),
-            (None, None, None),
+            (None, None, None, None, None, None),
         );
         assert!(svc
             .register_sub_process_channel(
@@ -373,4 +1657,281 @@ mod tests {
 
         svc.sub_process_consumer_list.lock().await.clear();
     }
+
+    #[test]
+    fn test_log_filter_matches_labels() {
+        let labels = LogLabels {
+            pod_sandbox_id: "pod".into(),
+            container_id: "nginx".into(),
+            ..Default::default()
+        };
+        assert!(labels_match(&labels, &LogFilter::default()));
+        assert!(labels_match(
+            &labels,
+            &LogFilter { container_id: "nginx".into(), ..Default::default() }
+        ));
+        assert!(!labels_match(
+            &labels,
+            &LogFilter { container_id: "redis".into(), ..Default::default() }
+        ));
+        assert!(!labels_match(
+            &labels,
+            &LogFilter { cell_name: "ae-1".into(), ..Default::default() }
+        ));
+    }
+
+    #[test]
+    fn test_log_filter_matches_severity_and_timestamp() {
+        let item = LogItem {
+            severity: LogSeverity::Warn as i32,
+            timestamp: 100,
+            ..Default::default()
+        };
+        assert!(log_item_matches(&item, &LogFilter::default()));
+        assert!(log_item_matches(
+            &item,
+            &LogFilter {
+                min_severity: LogSeverity::Warn as i32,
+                since_timestamp: 100,
+                ..Default::default()
+            }
+        ));
+        assert!(!log_item_matches(
+            &item,
+            &LogFilter {
+                min_severity: LogSeverity::Error as i32,
+                ..Default::default()
+            }
+        ));
+        assert!(!log_item_matches(
+            &item,
+            &LogFilter { since_timestamp: 101, ..Default::default() }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_log_stream_is_filtered() {
+        let svc = ObserveService::new(
+            Arc::new(LogChannel::new(String::from("auraed"))),
+            (None, None, None, None, None, None),
+        );
+        let labels = |container_id: &str| LogLabels {
+            pod_sandbox_id: "pod".into(),
+            container_id: container_id.into(),
+            severity: LogSeverity::Info,
+            ..Default::default()
+        };
+        let nginx =
+            LogChannel::new("nginx".into()).with_labels(labels("nginx"));
+        svc.register_log_channel("pod/nginx".into(), nginx.clone()).await;
+
+        let mut stream = svc
+            .get_log_stream(Request::new(GetLogStreamRequest {
+                filter: Some(LogFilter {
+                    pod_sandbox_id: "pod".into(),
+                    min_severity: LogSeverity::Warn as i32,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+            .await
+            .expect("stream")
+            .into_inner();
+
+        // registered while the stream is open
+        let redis =
+            LogChannel::new("redis".into()).with_labels(labels("redis"));
+        svc.register_log_channel("pod/redis".into(), redis.clone()).await;
+        tokio::task::yield_now().await;
+
+        nginx.send("ready".into());
+        nginx.log(LogSeverity::Error, "nginx failed".into());
+        let item = stream.next().await.expect("item").expect("ok").item;
+        assert_eq!(item.expect("log item").line, "nginx failed");
+
+        redis.log(LogSeverity::Warn, "redis slow".into());
+        let item = stream.next().await.expect("item").expect("ok").item;
+        let item = item.expect("log item");
+        assert_eq!(item.line, "redis slow");
+        assert_eq!(item.container_id, "redis");
+    }
+
+    #[test]
+    fn test_replay_starts_from_an_offset_or_timestamp() {
+        assert_eq!(replay_from(None, None).expect("replay"), None);
+        assert_eq!(
+            replay_from(Some(3), None).expect("replay"),
+            Some(ReplayFrom::Offset(3))
+        );
+        assert_eq!(
+            replay_from(None, Some(100)).expect("replay"),
+            Some(ReplayFrom::Timestamp(100))
+        );
+        assert!(replay_from(Some(3), Some(100)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_log_stream_rejects_invalid_severity() {
+        let svc = ObserveService::new(
+            Arc::new(LogChannel::new(String::from("auraed"))),
+            (None, None, None, None, None, None),
+        );
+        let result = svc
+            .get_log_stream(Request::new(GetLogStreamRequest {
+                filter: Some(LogFilter {
+                    min_severity: 42,
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }))
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_process_exec_response_carries_the_path_and_argv_hash() {
+        let mut filename = [0; EXEC_FILENAME_LEN];
+        filename[..9].copy_from_slice(b"/bin/tail");
+        let exec = ProcessExec {
+            cgroup_id: 1,
+            argv_hash: argv_hash([&b"tail"[..], b"-f", b"/dev/null"]),
+            pid: 42,
+            filename_len: 9,
+            filename,
+        };
+
+        let exec =
+            map_get_process_exec_stream_response(exec, 7).exec.expect("exec");
+        assert_eq!(exec.process_id, 7);
+        assert_eq!(exec.path, "/bin/tail");
+        assert_ne!(exec.argv_hash, argv_hash([&b"tail"[..], b"-f"]));
+    }
+
+    #[test]
+    fn test_tcp_connect_response_formats_the_addresses() {
+        let mut connect = TcpConnect {
+            cgroup_id: 1,
+            pid: 42,
+            state: TCP_CONNECT_ESTABLISHED,
+            family: libc::AF_INET as u16,
+            sport: 40000,
+            dport: 443,
+            padding: 0,
+            saddr: [0; 16],
+            daddr: [0; 16],
+        };
+        connect.saddr[..4].copy_from_slice(&[10, 0, 0, 2]);
+        connect.daddr[..4].copy_from_slice(&[1, 1, 1, 1]);
+
+        let event = map_get_tcp_connect_stream_response(connect, 7)
+            .connect
+            .expect("connect");
+        assert_eq!(event.process_id, 7);
+        assert_eq!(event.state(), TcpConnectState::Established);
+        assert_eq!(event.source_address, "10.0.0.2");
+        assert_eq!(event.source_port, 40000);
+        assert_eq!(event.destination_address, "1.1.1.1");
+        assert_eq!(event.destination_port, 443);
+
+        connect.family = libc::AF_INET6 as u16;
+        connect.daddr = std::net::Ipv6Addr::LOCALHOST.octets();
+        connect.state = TCP_CONNECT_FAILED;
+        let event = map_get_tcp_connect_stream_response(connect, 7)
+            .connect
+            .expect("connect");
+        assert_eq!(event.state(), TcpConnectState::Failed);
+        assert_eq!(event.destination_address, "::1");
+    }
+
+    #[test]
+    fn test_oom_kill_response_carries_bytes_and_constraint() {
+        let kill = OomKill {
+            cgroup_id: 1,
+            total_pages: 1024,
+            victim_points: 256,
+            pid: 42,
+            constraint: 3,
+        };
+
+        let event =
+            map_get_oom_kill_stream_response(kill, 7).oom_kill.expect("kill");
+        let page_size = procfs::page_size();
+        assert_eq!(event.process_id, 7);
+        assert_eq!(event.constraint(), OomConstraint::Memcg);
+        assert_eq!(event.total_bytes, 1024 * page_size);
+        assert_eq!(event.victim_bytes, 256 * page_size as i64);
+    }
+
+    #[test]
+    fn test_oom_kills_belong_to_the_cell_of_their_cgroup() {
+        let cell_of = |cgroup: &str| cell_of_cgroup(Path::new(cgroup));
+        assert_eq!(cell_of("/sys/fs/cgroup/ae-1/_").as_deref(), Some("ae-1"));
+        assert_eq!(
+            cell_of("/sys/fs/cgroup/ae-1/ae-2/_").as_deref(),
+            Some("ae-1/ae-2")
+        );
+        assert_eq!(cell_of("/sys/fs/cgroup/system.slice"), None);
+    }
+    #[test]
+    fn test_workload_events_reference_their_resource() {
+        let mut event = WorkloadEvent::new(
+            WorkloadEventKind::SnapshotTaken,
+            Workload::VmSnapshot {
+                vm_id: "vm-1".into(),
+                snapshot_id: "snap-1".into(),
+            },
+            "took snapshot snap-1 of vm vm-1",
+        );
+        event.sequence = 7;
+
+        let resp =
+            map_get_workload_event_stream_response(event).event.expect("event");
+        assert_eq!(resp.sequence, 7);
+        assert_eq!(resp.event_type(), WorkloadEventType::SnapshotTaken);
+        let workload = resp.workload.expect("workload");
+        assert_eq!(workload.workload_type(), WorkloadType::Vm);
+        assert_eq!(workload.id, "vm-1");
+        assert_eq!(resp.snapshot_id, "snap-1");
+    }
+
+    #[test]
+    fn test_workload_events_are_scoped_to_their_workload() {
+        let event = WorkloadEvent::new(
+            WorkloadEventKind::Stopped,
+            Workload::Container {
+                pod_sandbox_id: "pod-1".into(),
+                container_id: "ctr-1".into(),
+            },
+            "stopped container ctr-1",
+        );
+        let scope = |workload_type, id: &str| Some((workload_type, id.into()));
+
+        assert!(workload_event_matches(&event, &None));
+        assert!(workload_event_matches(
+            &event,
+            &scope(WorkloadType::PodSandbox, "pod-1")
+        ));
+        assert!(!workload_event_matches(
+            &event,
+            &scope(WorkloadType::PodSandbox, "pod-2")
+        ));
+        assert!(!workload_event_matches(
+            &event,
+            &scope(WorkloadType::Cell, "pod-1")
+        ));
+
+        let host_process = WorkloadEvent::new(
+            WorkloadEventKind::Crashed,
+            Workload::Process {
+                cell_name: None,
+                pid: 42,
+                executable: "sh".into(),
+            },
+            "sh dumped its core on signal 11",
+        );
+        assert!(!workload_event_matches(
+            &host_process,
+            &scope(WorkloadType::Cell, "ae-1")
+        ));
+    }
 }
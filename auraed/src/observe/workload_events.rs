@@ -0,0 +1,178 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Lifecycle events of workloads, published by CellService, RuntimeService
+//! and VmService through [ObserveService] to the consumers within auraed,
+//! such as the webhooks notifier, and to the clients of
+//! GetWorkloadEventStream.
+//!
+//! [ObserveService]: super::ObserveService
+
+use super::core_dumps::CoreDump;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+
+/// What happened to a workload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum WorkloadEventKind {
+    Started,
+    /// The workload was stopped, or exited successfully
+    Stopped,
+    Crashed,
+    OomKilled,
+    Migrated,
+    SnapshotTaken,
+}
+
+/// The workload an event happened to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum Workload {
+    Executable {
+        cell_name: String,
+        executable_name: String,
+    },
+    Cell {
+        cell_name: String,
+    },
+    Vm {
+        vm_id: String,
+    },
+    /// A snapshot taken of a VM.
+    VmSnapshot {
+        vm_id: String,
+        snapshot_id: String,
+    },
+    PodSandbox {
+        pod_sandbox_id: String,
+    },
+    Container {
+        pod_sandbox_id: String,
+        container_id: String,
+    },
+    /// A process that is not known as an executable, such as one that
+    /// dumped its core. `cell_name` is None for host processes.
+    Process {
+        cell_name: Option<String>,
+        pid: i32,
+        executable: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct WorkloadEvent {
+    /// Numbers the events in the order they were published, from 1 on each
+    /// start of auraed, 0 until the event is published
+    pub sequence: u64,
+    pub kind: WorkloadEventKind,
+    pub workload: Workload,
+    /// Seconds since the epoch at which the event occurred
+    pub timestamp: i64,
+    /// What happened, for humans
+    pub message: String,
+}
+
+impl WorkloadEvent {
+    /// An event that just occurred.
+    pub fn new(
+        kind: WorkloadEventKind,
+        workload: Workload,
+        message: impl Into<String>,
+    ) -> Self {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs() as i64);
+        Self { sequence: 0, kind, workload, timestamp, message: message.into() }
+    }
+}
+
+impl From<&CoreDump> for WorkloadEvent {
+    fn from(dump: &CoreDump) -> Self {
+        Self {
+            sequence: 0,
+            kind: WorkloadEventKind::Crashed,
+            workload: Workload::Process {
+                cell_name: dump.cell_name.clone(),
+                pid: dump.pid,
+                executable: dump.executable.clone(),
+            },
+            timestamp: dump.timestamp,
+            message: format!(
+                "{} dumped its core on signal {}",
+                dump.executable, dump.signal
+            ),
+        }
+    }
+}
+
+/// Publishes workload events to its subscribers in a single order, which
+/// the sequence numbers of the events follow.
+#[derive(Debug, Clone)]
+pub(crate) struct WorkloadEventBus {
+    sender: broadcast::Sender<WorkloadEvent>,
+    /// The sequence number of the last event, locked while the event is
+    /// sent so the events of concurrent publishers are sent in order
+    sequence: Arc<Mutex<u64>>,
+}
+
+impl WorkloadEventBus {
+    /// A bus whose subscribers lag once `capacity` events are queued for
+    /// them.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            sender: broadcast::channel(capacity).0,
+            sequence: Default::default(),
+        }
+    }
+
+    /// Numbers `event` and sends it to the subscribers.
+    pub fn publish(&self, mut event: WorkloadEvent) {
+        let mut sequence = self.sequence.lock().expect("sequence lock");
+        *sequence += 1;
+        event.sequence = *sequence;
+        // an error only means nobody is subscribed
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<WorkloadEvent> {
+        self.sender.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bus_must_number_events_in_order() {
+        let bus = WorkloadEventBus::new(4);
+        let mut events = bus.subscribe();
+        for vm_id in ["a", "b"] {
+            bus.publish(WorkloadEvent::new(
+                WorkloadEventKind::Started,
+                Workload::Vm { vm_id: vm_id.into() },
+                format!("started vm {vm_id}"),
+            ));
+        }
+
+        let first = events.try_recv().expect("first event");
+        let second = events.try_recv().expect("second event");
+        assert_eq!(first.sequence, 1);
+        assert_eq!(first.workload, Workload::Vm { vm_id: "a".into() });
+        assert_eq!(second.sequence, 2);
+    }
+}
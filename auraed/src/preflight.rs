@@ -0,0 +1,328 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Pre-flight checks of the host, printed by `auraed check`.
+//!
+//! The daemon runs the same checks on startup: a subsystem whose checks fail
+//! is not started, or stays NOT_SERVING, see the readiness module. Kernel
+//! options are read from /proc/config.gz or /boot/config-<release> when the
+//! kernel exposes its config, and probed for at runtime otherwise.
+
+use crate::{readiness, vms, AuraedRuntime};
+use anyhow::anyhow;
+use caps::{CapSet, Capability};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::File,
+    io::Read,
+    path::Path,
+};
+
+/// Name of the check of the kernel release.
+pub(crate) const KERNEL: &str = "kernel";
+/// Name of the check of the cgroup v2 hierarchy.
+pub(crate) const CGROUP_V2: &str = "cgroup-v2";
+/// Name of the check of the namespaces cells and containers run in.
+pub(crate) const NAMESPACES: &str = "namespaces";
+/// Name of the check of the seccomp filters of the VMM threads.
+pub(crate) const SECCOMP: &str = "seccomp";
+/// Name of the check of the vsock devices of VMs.
+pub(crate) const VSOCK: &str = "vsock";
+/// Name of the check of KVM, which VMs run on.
+pub(crate) const KVM: &str = "kvm";
+/// Name of the check of the eBPF probes of ObserveService.
+pub(crate) const BPF: &str = "bpf";
+/// Name of the check of the certificates of the gRPC server.
+pub(crate) const PKI: &str = "pki";
+
+/// Oldest kernel release auraed supports.
+const MIN_KERNEL: (u32, u32) = (5, 10);
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    /// Name of the check, such as `cgroup-v2`
+    pub name: &'static str,
+    /// What depends on the check passing
+    pub required_by: &'static str,
+    /// Whether the host passed the check
+    pub passed: bool,
+    /// What was found, or why the check failed
+    pub detail: String,
+}
+
+/// The outcome of all checks of the host.
+#[derive(Debug, Clone, Serialize)]
+pub struct Preflight {
+    /// The checks, in the order they ran
+    pub checks: Vec<Check>,
+}
+
+impl Preflight {
+    /// Runs all checks against the host and the PKI files of `runtime`.
+    pub fn run(runtime: &AuraedRuntime) -> Self {
+        let config = KernelConfig::load();
+        let checks = vec![
+            check(KERNEL, "cells", kernel_release()),
+            check(
+                CGROUP_V2,
+                "cells",
+                readiness::cgroup_tree().map(|()| "cell controllers available"),
+            ),
+            check(NAMESPACES, "cells, pod sandboxes", namespaces(&config)),
+            check(SECCOMP, "VMM jailer", seccomp(&config)),
+            check(VSOCK, "VMs", vsock(&config)),
+            check(KVM, "VMs", kvm(&config)),
+            check(BPF, "eBPF probes", bpf(&config)),
+            check(PKI, "gRPC server", pki(runtime)),
+        ];
+        Self { checks }
+    }
+
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.passed)
+    }
+
+    /// Fails with the first failed check of `names`.
+    pub(crate) fn require(&self, names: &[&str]) -> anyhow::Result<()> {
+        match self.checks.iter().find(|c| !c.passed && names.contains(&c.name))
+        {
+            Some(c) => Err(anyhow!("{} check failed: {}", c.name, c.detail)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Display for Preflight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in &self.checks {
+            let outcome = if c.passed { "PASS" } else { "FAIL" };
+            writeln!(
+                f,
+                "[{outcome}] {:<11} {:<21} {}",
+                c.name, c.required_by, c.detail
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn check(
+    name: &'static str,
+    required_by: &'static str,
+    res: anyhow::Result<impl Into<String>>,
+) -> Check {
+    match res {
+        Ok(detail) => {
+            Check { name, required_by, passed: true, detail: detail.into() }
+        }
+        Err(e) => {
+            Check { name, required_by, passed: false, detail: format!("{e:#}") }
+        }
+    }
+}
+
+/// The options the kernel was built with, if it exposes them.
+struct KernelConfig(Option<HashMap<String, String>>);
+
+impl KernelConfig {
+    fn load() -> Self {
+        let config = File::open("/proc/config.gz")
+            .and_then(|file| {
+                let mut config = String::new();
+                let _ = GzDecoder::new(file).read_to_string(&mut config)?;
+                Ok(config)
+            })
+            .or_else(|_| {
+                let release =
+                    std::fs::read_to_string("/proc/sys/kernel/osrelease")?;
+                std::fs::read_to_string(format!(
+                    "/boot/config-{}",
+                    release.trim()
+                ))
+            });
+        Self(config.ok().map(|config| parse_config(&config)))
+    }
+
+    /// Checks that `options` are built in or modules, then runs `probe`,
+    /// which must pass when the config is not exposed.
+    fn require(
+        &self,
+        options: &[&str],
+        probe: impl FnOnce() -> anyhow::Result<()>,
+    ) -> anyhow::Result<String> {
+        let Some(config) = &self.0 else {
+            probe()?;
+            return Ok("probed, the kernel config is not exposed".into());
+        };
+        let missing: Vec<&str> = options
+            .iter()
+            .copied()
+            .filter(|o| {
+                !matches!(config.get(*o).map(String::as_str), Some("y" | "m"))
+            })
+            .collect();
+        if !missing.is_empty() {
+            return Err(anyhow!("kernel built without {}", missing.join(", ")));
+        }
+        probe()?;
+        Ok(options.join(", "))
+    }
+}
+
+/// Parses `CONFIG_FOO=y` lines, skipping comments.
+fn parse_config(config: &str) -> HashMap<String, String> {
+    config
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(option, value)| (option.to_string(), value.to_string()))
+        .collect()
+}
+
+/// Parses the major and minor version of a release such as `6.1.0-13-amd64`.
+pub(crate) fn parse_release(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.trim().split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn kernel_release() -> anyhow::Result<String> {
+    let release = std::fs::read_to_string("/proc/sys/kernel/osrelease")?;
+    let release = release.trim();
+    let version = parse_release(release)
+        .ok_or_else(|| anyhow!("unknown kernel release {release}"))?;
+    if version < MIN_KERNEL {
+        return Err(anyhow!(
+            "kernel {release} is older than {}.{}",
+            MIN_KERNEL.0,
+            MIN_KERNEL.1
+        ));
+    }
+    Ok(release.to_string())
+}
+
+fn exists(path: &str) -> anyhow::Result<()> {
+    if Path::new(path).exists() {
+        Ok(())
+    } else {
+        Err(anyhow!("{path} does not exist"))
+    }
+}
+
+fn namespaces(config: &KernelConfig) -> anyhow::Result<String> {
+    config.require(
+        &[
+            "CONFIG_NAMESPACES",
+            "CONFIG_UTS_NS",
+            "CONFIG_IPC_NS",
+            "CONFIG_PID_NS",
+            "CONFIG_NET_NS",
+            "CONFIG_CGROUPS",
+        ],
+        || {
+            ["cgroup", "ipc", "mnt", "net", "pid", "uts"]
+                .into_iter()
+                .try_for_each(|ns| exists(&format!("/proc/self/ns/{ns}")))
+        },
+    )
+}
+
+fn seccomp(config: &KernelConfig) -> anyhow::Result<String> {
+    config.require(&["CONFIG_SECCOMP", "CONFIG_SECCOMP_FILTER"], || {
+        exists("/proc/sys/kernel/seccomp/actions_avail")
+    })
+}
+
+fn vsock(config: &KernelConfig) -> anyhow::Result<String> {
+    config.require(&["CONFIG_VSOCKETS", "CONFIG_VHOST_VSOCK"], || {
+        exists("/dev/vhost-vsock")
+    })
+}
+
+fn kvm(config: &KernelConfig) -> anyhow::Result<String> {
+    config.require(&["CONFIG_KVM"], || {
+        if vms::kvm_available() {
+            Ok(())
+        } else {
+            Err(anyhow!("/dev/kvm cannot be opened"))
+        }
+    })
+}
+
+fn bpf(config: &KernelConfig) -> anyhow::Result<String> {
+    config.require(
+        &["CONFIG_BPF_SYSCALL", "CONFIG_BPF_EVENTS", "CONFIG_KPROBES"],
+        || {
+            let capable =
+                |c| caps::has_cap(None, CapSet::Effective, c).unwrap_or(false);
+            if !capable(Capability::CAP_BPF)
+                && !capable(Capability::CAP_SYS_ADMIN)
+            {
+                return Err(anyhow!("auraed lacks CAP_BPF and CAP_SYS_ADMIN"));
+            }
+            exists("/sys/kernel/tracing/events")
+                .or_else(|_| exists("/sys/kernel/debug/tracing/events"))
+                .map_err(|_| anyhow!("tracefs is not mounted"))
+        },
+    )
+}
+
+fn pki(runtime: &AuraedRuntime) -> anyhow::Result<String> {
+    let paths = [&runtime.ca_crt, &runtime.server_crt, &runtime.server_key];
+    for path in paths {
+        let pem = std::fs::read_to_string(path).map_err(|e| {
+            anyhow!(
+                "failed to read {}: {e}, see https://aurae.io/certs/",
+                path.display()
+            )
+        })?;
+        if !pem.contains("-----BEGIN ") {
+            return Err(anyhow!("{} is not PEM encoded", path.display()));
+        }
+    }
+    let paths: Vec<String> =
+        paths.iter().map(|p| p.display().to_string()).collect();
+    Ok(paths.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_kernel_releases() {
+        assert_eq!(parse_release("6.1.0-13-amd64\n"), Some((6, 1)));
+        assert_eq!(parse_release("5.15.153.1-microsoft"), Some((5, 15)));
+        assert_eq!(parse_release("unknown"), None);
+    }
+
+    #[test]
+    fn parses_kernel_configs() {
+        let config = parse_config(
+            "# CONFIG_KVM is not set\nCONFIG_NET_NS=y\nCONFIG_VSOCKETS=m\n",
+        );
+        assert_eq!(config.get("CONFIG_NET_NS").map(String::as_str), Some("y"));
+        assert_eq!(
+            config.get("CONFIG_VSOCKETS").map(String::as_str),
+            Some("m")
+        );
+        assert!(!config.contains_key("CONFIG_KVM"));
+    }
+}
@@ -0,0 +1,24 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Tells a process apart from later ones reusing its pid, for the records
+//! auraed keeps of the processes it leaves running or leases addresses to.
+
+/// The start time of the process `pid` in clock ticks after boot, which a
+/// later process reusing the pid does not share.
+pub(crate) fn start_time(pid: i32) -> procfs::ProcResult<u64> {
+    procfs::process::Process::new(pid)
+        .and_then(|process| process.stat())
+        .map(|stat| stat.starttime)
+}
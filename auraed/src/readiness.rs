@@ -0,0 +1,83 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Checks for the subsystems the gRPC services depend on.
+//!
+//! Every service starts out as NOT_SERVING in the health service and is only
+//! reported as SERVING once the check for its dependencies passed, so that
+//! health does not go green before auraed can actually do work. Services of
+//! disabled subsystems are not reported at all.
+
+use anyhow::{bail, Context};
+use tonic::server::NamedService;
+use tonic_health::server::HealthReporter;
+use tracing::{info, warn};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+/// Controllers the cgroups of cells are configured with.
+const CELL_CONTROLLERS: [&str; 3] = ["cpu", "cpuset", "memory"];
+
+/// Checks that the unified cgroup hierarchy is mounted and offers the
+/// controllers cells are created with.
+pub(crate) fn cgroup_tree() -> anyhow::Result<()> {
+    let path = format!("{CGROUP_ROOT}/cgroup.controllers");
+    let controllers = std::fs::read_to_string(&path).with_context(|| {
+        format!(
+            "cgroup v2 is not mounted at {CGROUP_ROOT}: failed to read {path}"
+        )
+    })?;
+
+    let available: Vec<&str> = controllers.split_whitespace().collect();
+    let missing: Vec<&str> = CELL_CONTROLLERS
+        .into_iter()
+        .filter(|c| !available.contains(c))
+        .collect();
+    if !missing.is_empty() {
+        bail!(
+            "cgroup controllers {missing:?} are not available in {CGROUP_ROOT}"
+        );
+    }
+
+    Ok(())
+}
+
+/// Reports the service `S` as SERVING if `ready` is Ok, and logs why it stays
+/// NOT_SERVING otherwise. Returns whether the service is ready.
+pub(crate) async fn report<S: NamedService>(
+    health_reporter: &mut HealthReporter,
+    ready: anyhow::Result<()>,
+) -> bool {
+    match ready {
+        Ok(()) => {
+            info!("{} is ready", S::NAME);
+            health_reporter.set_serving::<S>().await;
+            true
+        }
+        Err(e) => {
+            warn!("{} is not serving: {e:#}", S::NAME);
+            health_reporter.set_not_serving::<S>().await;
+            false
+        }
+    }
+}
+
+/// Removes the disabled service `S` from the health service, which reports
+/// it as unknown from then on.
+pub(crate) async fn disabled<S: NamedService>(
+    health_reporter: &mut HealthReporter,
+) {
+    info!("{} is disabled", S::NAME);
+    health_reporter.clear_service_status(S::NAME).await;
+}
@@ -0,0 +1,340 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Server streams clients can resume after losing their connection.
+//!
+//! Every response of a resumable stream carries a resume token, naming the
+//! stream and the position of the response in it. A stream outlives its
+//! client for [GRACE_PERIOD], still producing responses and keeping the last
+//! [BUFFERED] of them. A client reconnecting within that period with the
+//! token of the last response it received continues right after it: the
+//! responses it missed are replayed, those it received are not. Streams are
+//! kept in memory only, they do not outlive auraed.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, Instant},
+};
+use tokio::{sync::mpsc, task::AbortHandle};
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::Status;
+use uuid::Uuid;
+
+/// How long a stream is kept after its client went away.
+pub(crate) const GRACE_PERIOD: Duration = Duration::from_secs(60);
+/// How many of its last responses a stream keeps for replaying them.
+const BUFFERED: usize = 1024;
+/// Responses queued for a client before the stream waits for it.
+const CLIENT_BUFFER: usize = 4;
+
+/// A response of a resumable stream.
+pub(crate) trait Resumable: Clone + Send + 'static {
+    fn set_resume_token(&mut self, token: String);
+}
+
+/// Implements [Resumable] for responses with a `resume_token` field.
+macro_rules! impl_resumable {
+    ($($response:ty),* $(,)?) => {
+        $(
+            impl $crate::resumable::Resumable for $response {
+                fn set_resume_token(&mut self, token: String) {
+                    self.resume_token = token;
+                }
+            }
+        )*
+    };
+}
+pub(crate) use impl_resumable;
+
+/// The stream of a call, as returned to tonic.
+pub(crate) type ResumableStream<T> = ReceiverStream<Result<T, Status>>;
+
+/// The resumable streams of one RPC, each with a `state` of type `S` that a
+/// call resuming the stream gets back, e.g. to continue writing to the
+/// console the stream reads from.
+#[derive(Debug)]
+pub(crate) struct ResumableStreams<T, S = ()> {
+    streams: Arc<Mutex<HashMap<Uuid, Entry<T, S>>>>,
+}
+
+impl<T, S> Clone for ResumableStreams<T, S> {
+    fn clone(&self) -> Self {
+        Self { streams: self.streams.clone() }
+    }
+}
+
+#[derive(Debug)]
+struct Entry<T, S> {
+    /// The last responses, by their position in the stream
+    buffered: VecDeque<(u64, T)>,
+    /// Position of the next response
+    next: u64,
+    /// Sends the responses to the client, None while no client is attached
+    client: Option<mpsc::Sender<Result<T, Status>>>,
+    /// Since when no client is attached
+    detached_since: Option<Instant>,
+    /// Set once the responses ended, to the error they ended with if any
+    ended: Option<Option<Status>>,
+    state: S,
+    producer: AbortHandle,
+}
+
+impl<T: Resumable, S: Clone + Send + 'static> ResumableStreams<T, S> {
+    /// Must be called within a tokio runtime, which streams whose client
+    /// did not come back are dropped on.
+    pub fn new() -> Self {
+        let streams = Arc::new(Mutex::new(HashMap::new()));
+        let _ignored = tokio::spawn(reap(Arc::downgrade(&streams)));
+        Self { streams }
+    }
+
+    /// Streams `responses` to the client of a new call.
+    pub fn start(
+        &self,
+        responses: impl Stream<Item = Result<T, Status>> + Send + 'static,
+        state: S,
+    ) -> ResumableStream<T> {
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::channel(CLIENT_BUFFER);
+
+        let mut streams = self.streams.lock().expect("streams lock");
+        let producer =
+            tokio::spawn(produce(Arc::downgrade(&self.streams), id, responses));
+        let _ = streams.insert(
+            id,
+            Entry {
+                buffered: VecDeque::new(),
+                next: 0,
+                client: Some(tx),
+                detached_since: None,
+                ended: None,
+                state,
+                producer: producer.abort_handle(),
+            },
+        );
+        ReceiverStream::new(rx)
+    }
+
+    /// Resumes the stream `token` was sent with, right after the response
+    /// it was sent with, for the client of a new call. Fails if the stream
+    /// is gone, or the responses following the token are not buffered
+    /// anymore.
+    pub fn resume(
+        &self,
+        token: &str,
+    ) -> Result<(ResumableStream<T>, S), Status> {
+        let (id, position) = parse_token(token).ok_or_else(|| {
+            Status::invalid_argument(format!("invalid resume token '{token}'"))
+        })?;
+
+        let mut streams = self.streams.lock().expect("streams lock");
+        let Some(entry) = streams.get_mut(&id) else {
+            return Err(Status::not_found(format!(
+                "stream of resume token '{token}' is gone"
+            )));
+        };
+        if position >= entry.next {
+            return Err(Status::invalid_argument(format!(
+                "resume token '{token}' was not issued yet"
+            )));
+        }
+        let oldest = entry.buffered.front().map_or(entry.next, |(p, _)| *p);
+        if position + 1 < oldest {
+            return Err(Status::out_of_range(format!(
+                "responses after resume token '{token}' were dropped"
+            )));
+        }
+
+        // Replaying does not wait, so the client is attached before the
+        // producer sends the next response.
+        let missed = entry.buffered.iter().filter(|(p, _)| *p > position);
+        let (tx, rx) = mpsc::channel(BUFFERED + CLIENT_BUFFER);
+        for (_, response) in missed {
+            let _ = tx.try_send(Ok(response.clone()));
+        }
+        let state = entry.state.clone();
+        match &entry.ended {
+            Some(error) => {
+                if let Some(status) = error {
+                    let _ = tx.try_send(Err(status.clone()));
+                }
+                let _ = streams.remove(&id);
+            }
+            None => {
+                entry.client = Some(tx);
+                entry.detached_since = None;
+            }
+        }
+        Ok((ReceiverStream::new(rx), state))
+    }
+}
+
+/// Records the `responses` of the stream `id` and sends them to its client.
+async fn produce<T: Resumable, S>(
+    streams: Weak<Mutex<HashMap<Uuid, Entry<T, S>>>>,
+    id: Uuid,
+    responses: impl Stream<Item = Result<T, Status>> + Send + 'static,
+) {
+    let mut responses = Box::pin(responses);
+    let mut error = None;
+    while let Some(response) = responses.next().await {
+        let Some(streams) = streams.upgrade() else {
+            return;
+        };
+        let (client, response) = {
+            let mut streams = streams.lock().expect("streams lock");
+            let Some(entry) = streams.get_mut(&id) else {
+                return;
+            };
+            let response = match response {
+                Ok(mut response) => {
+                    response.set_resume_token(token(id, entry.next));
+                    entry.buffered.push_back((entry.next, response.clone()));
+                    if entry.buffered.len() > BUFFERED {
+                        let _ = entry.buffered.pop_front();
+                    }
+                    entry.next += 1;
+                    Ok(response)
+                }
+                Err(status) => {
+                    error = Some(status.clone());
+                    Err(status)
+                }
+            };
+            (entry.client.clone(), response)
+        };
+        // Fails if the client went away, it can resume from the buffer
+        if let Some(client) = client {
+            let _ = client.send(response).await;
+        }
+        if error.is_some() {
+            break;
+        }
+    }
+
+    let Some(streams) = streams.upgrade() else {
+        return;
+    };
+    let mut streams = streams.lock().expect("streams lock");
+    let Some(entry) = streams.get_mut(&id) else {
+        return;
+    };
+    if entry.client.as_ref().is_some_and(|client| !client.is_closed()) {
+        // The client got all responses and sees the stream end
+        let _ = streams.remove(&id);
+    } else {
+        entry.client = None;
+        entry.ended = Some(error);
+    }
+}
+
+/// Periodically drops the streams whose client went away more than
+/// [GRACE_PERIOD] ago, which stops their producer.
+async fn reap<T, S>(streams: Weak<Mutex<HashMap<Uuid, Entry<T, S>>>>) {
+    let mut interval = tokio::time::interval(GRACE_PERIOD / 4);
+    loop {
+        let _ = interval.tick().await;
+        let Some(streams) = streams.upgrade() else {
+            return;
+        };
+        let now = Instant::now();
+        streams.lock().expect("streams lock").retain(|_, entry| {
+            if entry.client.as_ref().is_some_and(|client| !client.is_closed()) {
+                entry.detached_since = None;
+                return true;
+            }
+            let since = *entry.detached_since.get_or_insert(now);
+            let keep = now.duration_since(since) < GRACE_PERIOD;
+            if !keep {
+                entry.producer.abort();
+            }
+            keep
+        });
+    }
+}
+
+fn token(id: Uuid, position: u64) -> String {
+    format!("{id}:{position}")
+}
+
+fn parse_token(token: &str) -> Option<(Uuid, u64)> {
+    let (id, position) = token.split_once(':')?;
+    Some((Uuid::parse_str(id).ok()?, position.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Response {
+        value: u32,
+        resume_token: String,
+    }
+
+    impl_resumable!(Response);
+
+    fn responses(
+        values: Vec<u32>,
+    ) -> impl Stream<Item = Result<Response, Status>> {
+        tokio_stream::iter(
+            values.into_iter().map(|value| {
+                Ok(Response { value, resume_token: String::new() })
+            }),
+        )
+    }
+
+    #[tokio::test]
+    async fn resumes_after_the_last_received_response() {
+        let streams = ResumableStreams::<Response>::new();
+        let (tx, rx) = mpsc::channel(8);
+        let mut stream = streams.start(ReceiverStream::new(rx), ());
+
+        for value in 0..3 {
+            tx.send(Ok(Response { value, resume_token: String::new() }))
+                .await
+                .expect("send");
+        }
+        let first = stream.next().await.expect("first").expect("ok");
+        assert_eq!(first.value, 0);
+        drop(stream);
+
+        let (mut stream, ()) =
+            streams.resume(&first.resume_token).expect("resumable");
+        tx.send(Ok(Response { value: 3, resume_token: String::new() }))
+            .await
+            .expect("send");
+        let mut values = vec![];
+        for _ in 0..3 {
+            values.push(stream.next().await.expect("next").expect("ok").value);
+        }
+        assert_eq!(values, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn forgets_streams_whose_end_was_received() {
+        let streams = ResumableStreams::<Response>::new();
+        let mut stream = streams.start(responses((0..3).collect()), ());
+        let first = stream.next().await.expect("first").expect("ok");
+        while stream.next().await.is_some() {}
+
+        let status = streams.resume(&first.resume_token).expect_err("ended");
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(
+            streams.resume("not a token").expect_err("invalid").code(),
+            tonic::Code::InvalidArgument
+        );
+    }
+}
@@ -0,0 +1,36 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use thiserror::Error;
+
+pub(crate) type Result<T> = std::result::Result<T, SnapshotStoreError>;
+
+#[derive(Debug, Error)]
+pub(crate) enum SnapshotStoreError {
+    #[error("'{id}' is not a valid snapshot id")]
+    InvalidId { id: String },
+    #[error("snapshot '{id}' already exists")]
+    AlreadyExists { id: String },
+    #[error("snapshot '{id}' not found")]
+    NotFound { id: String },
+    #[error("snapshot '{id}' is the parent of {children:?}")]
+    HasChildren { id: String, children: Vec<String> },
+    #[error("snapshot '{id}' references missing page '{digest}'")]
+    MissingPage { id: String, digest: String },
+    #[error("snapshot manifest is invalid: {0}")]
+    InvalidManifest(#[from] serde_json::Error),
+    #[error(transparent)]
+    IO(#[from] std::io::Error),
+}
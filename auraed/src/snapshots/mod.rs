@@ -0,0 +1,28 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Storage for VM snapshots and checkpoint images.
+//!
+//! Full-memory images are large and mostly identical between snapshots of the
+//! same workload. Instead of keeping every image on disk as-is, the
+//! [PageStore] splits files into fixed size pages, compresses each page with
+//! zstd, and stores it once under its content digest. A snapshot is then only
+//! a small manifest listing the digests that make up each of its files.
+
+pub(crate) use error::SnapshotStoreError;
+pub(crate) use page_store::{validate_id, DirtyPages, PageStore};
+
+mod error;
+mod page_store;
@@ -0,0 +1,769 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use super::error::{Result, SnapshotStoreError};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use tracing::{debug, info};
+use walkdir::WalkDir;
+
+/// Size of the chunks files are split into. Matches the guest page size so
+/// that identical guest pages across snapshots map to the same chunk.
+pub(crate) const PAGE_SIZE: usize = 4096;
+
+/// zstd compression level used for stored pages. Level 3 is the zstd default
+/// and keeps snapshotting fast enough to not noticeably extend VM pauses.
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// The pages of a file written since the parent snapshot was taken, as
+/// tracked by the VMM. Pages not marked are taken from the parent without
+/// reading or hashing them.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct DirtyPages<'a> {
+    /// Path of the file relative to the snapshot directory.
+    pub path: &'a Path,
+    /// One bit per page, page `n` being bit `n % 8` of byte `n / 8`.
+    pub bitmap: &'a [u8],
+}
+
+impl DirtyPages<'_> {
+    fn covers(&self, pages: u64) -> bool {
+        self.bitmap.len() as u64 == pages.div_ceil(8)
+    }
+
+    fn is_dirty(&self, page: u64) -> bool {
+        self.bitmap[(page / 8) as usize] & (1 << (page % 8)) != 0
+    }
+}
+
+/// A single file captured in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileManifest {
+    /// Path of the file relative to the snapshot directory.
+    pub path: PathBuf,
+    /// Size of the file in bytes.
+    pub size: u64,
+    /// Digests of the pages making up the file, keyed by page index. For a
+    /// differential snapshot only the pages that changed since the parent
+    /// are listed.
+    pub pages: BTreeMap<u64, String>,
+}
+
+/// The list of files (and their pages) that make up a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    pub id: String,
+    /// The snapshot this one is a differential of, if any.
+    #[serde(default)]
+    pub parent: Option<String>,
+    pub files: Vec<FileManifest>,
+}
+
+/// A content addressed store of zstd compressed pages.
+///
+/// Layout on disk:
+///
+/// ```text
+/// <root>/manifests/<snapshot id>.json
+/// <root>/pages/<digest[..2]>/<digest[2..]>.zst
+/// ```
+///
+/// The clones of a store share a lock serializing its mutations, so the
+/// pages a snapshot being ingested deduplicates against or just wrote are
+/// not collected before its manifest references them.
+#[derive(Debug, Clone)]
+pub(crate) struct PageStore {
+    root: PathBuf,
+    lock: Arc<RwLock<()>>,
+}
+
+impl PageStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into(), lock: Default::default() }
+    }
+
+    fn write_lock(&self) -> RwLockWriteGuard<'_, ()> {
+        self.lock.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn read_lock(&self) -> RwLockReadGuard<'_, ()> {
+        self.lock.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Create the store below its root if it does not exist yet and check
+    /// that the manifests it holds can be read. Returns the number of
+    /// snapshots in the store.
+    pub fn open(&self) -> Result<usize> {
+        fs::create_dir_all(self.root.join("manifests"))?;
+        fs::create_dir_all(self.root.join("pages"))?;
+        Ok(self.manifests()?.len())
+    }
+
+    /// Store every regular file below `dir` as a new snapshot with `id`.
+    /// Pages already present in the store are not written again.
+    ///
+    /// If a `parent` is given the snapshot is stored as a differential of it,
+    /// and its manifest only records the pages that differ from the parent.
+    /// Knowing the `dirty` pages of a file written since the parent, only
+    /// those are read and compared.
+    pub fn ingest(
+        &self,
+        id: &str,
+        dir: &Path,
+        parent: Option<&str>,
+        dirty: Option<DirtyPages<'_>>,
+    ) -> Result<Manifest> {
+        let _locked = self.write_lock();
+        if self.manifest_path(id)?.exists() {
+            return Err(SnapshotStoreError::AlreadyExists { id: id.into() });
+        }
+
+        let base: HashMap<PathBuf, FileManifest> = match parent {
+            Some(parent) => self
+                .resolve(parent)?
+                .files
+                .into_iter()
+                .map(|f| (f.path.clone(), f))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut files = vec![];
+        let mut written = 0usize;
+        let mut total = 0usize;
+        for entry in WalkDir::new(dir).sort_by_file_name() {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+
+            let path = entry
+                .path()
+                .strip_prefix(dir)
+                .expect("walkdir entry is below its root")
+                .to_path_buf();
+
+            let size = entry.metadata().map_err(std::io::Error::from)?.len();
+            let count = size.div_ceil(PAGE_SIZE as u64);
+            let base_file = base.get(&path);
+            let base_pages = base_file.map(|f| &f.pages);
+            // The dirty pages only describe the file if its layout did not
+            // change since the parent
+            let dirty = dirty.filter(|d| {
+                d.path == path
+                    && d.covers(count)
+                    && base_file.is_some_and(|f| f.size == size)
+            });
+
+            let mut file = File::open(entry.path())?;
+            let mut pages = BTreeMap::new();
+            let mut buf = vec![0u8; PAGE_SIZE];
+            let mut seek = false;
+            for index in 0..count {
+                total += 1;
+                let clean = dirty.is_some_and(|d| !d.is_dirty(index))
+                    && base_pages.is_some_and(|p| p.contains_key(&index));
+                if clean {
+                    seek = true;
+                    continue;
+                }
+                if seek {
+                    let _ =
+                        file.seek(SeekFrom::Start(index * PAGE_SIZE as u64))?;
+                    seek = false;
+                }
+
+                let n = read_page(&mut file, &mut buf)?;
+                if n == 0 {
+                    break;
+                }
+
+                let digest = format!("{:x}", Sha256::digest(&buf[..n]));
+                let unchanged = base_pages
+                    .and_then(|p| p.get(&index))
+                    .is_some_and(|d| *d == digest);
+                if !unchanged {
+                    if self.write_page(&digest, &buf[..n])? {
+                        written += 1;
+                    }
+                    let _ = pages.insert(index, digest);
+                }
+            }
+
+            files.push(FileManifest { path, size, pages });
+        }
+
+        let manifest =
+            Manifest { id: id.into(), parent: parent.map(Into::into), files };
+        self.write_manifest(&manifest)?;
+
+        info!(
+            "Stored snapshot '{id}': {written} new of {total} pages ({} deduplicated)",
+            total - written
+        );
+        Ok(manifest)
+    }
+
+    /// Recreate the files of snapshot `id` below `dest`, applying the
+    /// differentials of its whole chain.
+    pub fn materialize(&self, id: &str, dest: &Path) -> Result<()> {
+        let _locked = self.read_lock();
+        let manifest = self.resolve(id)?;
+        for entry in &manifest.files {
+            let path = dest.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            let mut file = File::create(&path)?;
+            for digest in entry.pages.values() {
+                let page = self.read_page(id, digest)?;
+                file.write_all(&page)?;
+            }
+            file.set_len(entry.size)?;
+        }
+        Ok(())
+    }
+
+    /// Read the manifest of snapshot `id`.
+    pub fn manifest(&self, id: &str) -> Result<Manifest> {
+        let path = self.manifest_path(id)?;
+        if !path.exists() {
+            return Err(SnapshotStoreError::NotFound { id: id.into() });
+        }
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// Collapse the chain of snapshot `id` into the snapshot itself so it no
+    /// longer depends on its parents, which may then be removed.
+    pub fn flatten(&self, id: &str) -> Result<Manifest> {
+        let _locked = self.write_lock();
+        let manifest = self.resolve(id)?;
+        self.write_manifest(&manifest)?;
+        Ok(manifest)
+    }
+
+    /// Resolve snapshot `id` and its chain of parents into a manifest that
+    /// lists every page of every file.
+    pub fn resolve(&self, id: &str) -> Result<Manifest> {
+        let mut chain = vec![self.manifest(id)?];
+        while let Some(parent) = chain.last().and_then(|m| m.parent.clone()) {
+            chain.push(self.manifest(&parent)?);
+        }
+
+        let mut resolved: HashMap<PathBuf, FileManifest> = HashMap::new();
+        let mut files = vec![];
+        for manifest in chain.into_iter().rev() {
+            files = manifest
+                .files
+                .into_iter()
+                .map(|file| {
+                    let mut pages = resolved
+                        .remove(&file.path)
+                        .map(|f| f.pages)
+                        .unwrap_or_default();
+                    pages.extend(file.pages);
+                    let count = file.size.div_ceil(PAGE_SIZE as u64);
+                    let _ = pages.split_off(&count);
+                    FileManifest { path: file.path, size: file.size, pages }
+                })
+                .collect::<Vec<_>>();
+            resolved =
+                files.iter().map(|f| (f.path.clone(), f.clone())).collect();
+        }
+
+        Ok(Manifest { id: id.into(), parent: None, files })
+    }
+
+    /// Remove snapshot `id` and any pages no other snapshot references.
+    pub fn remove(&self, id: &str) -> Result<()> {
+        let _locked = self.write_lock();
+        let path = self.manifest_path(id)?;
+        if !path.exists() {
+            return Err(SnapshotStoreError::NotFound { id: id.into() });
+        }
+        let children: Vec<String> = self
+            .manifests()?
+            .into_iter()
+            .filter(|m| m.parent.as_deref() == Some(id))
+            .map(|m| m.id)
+            .collect();
+        if !children.is_empty() {
+            return Err(SnapshotStoreError::HasChildren {
+                id: id.into(),
+                children,
+            });
+        }
+        fs::remove_file(path)?;
+        let _ = self.collect_garbage()?;
+        Ok(())
+    }
+
+    /// Delete all pages that are not referenced by any manifest, returning
+    /// the number of pages removed.
+    pub fn gc(&self) -> Result<usize> {
+        let _locked = self.write_lock();
+        self.collect_garbage()
+    }
+
+    /// [PageStore::gc], for callers holding the lock of the store.
+    fn collect_garbage(&self) -> Result<usize> {
+        let mut live = HashSet::new();
+        for manifest in self.manifests()? {
+            for file in manifest.files {
+                live.extend(file.pages.into_values());
+            }
+        }
+
+        let mut removed = 0;
+        let pages_dir = self.root.join("pages");
+        if !pages_dir.exists() {
+            return Ok(removed);
+        }
+
+        for entry in WalkDir::new(&pages_dir).min_depth(2).max_depth(2) {
+            let entry = entry.map_err(std::io::Error::from)?;
+            let Some(digest) = digest_of(entry.path()) else {
+                continue;
+            };
+            if !live.contains(&digest) {
+                fs::remove_file(entry.path())?;
+                removed += 1;
+            }
+        }
+
+        debug!("Removed {removed} unreferenced pages");
+        Ok(removed)
+    }
+
+    fn manifests(&self) -> Result<Vec<Manifest>> {
+        let dir = self.root.join("manifests");
+        if !dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut manifests = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                manifests.push(serde_json::from_slice(&fs::read(path)?)?);
+            }
+        }
+        Ok(manifests)
+    }
+
+    fn write_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.manifest_path(&manifest.id)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_atomic(&path, &serde_json::to_vec(manifest)?)?;
+        Ok(())
+    }
+
+    fn manifest_path(&self, id: &str) -> Result<PathBuf> {
+        validate_id(id)?;
+        Ok(self.root.join("manifests").join(format!("{id}.json")))
+    }
+
+    fn page_path(&self, digest: &str) -> PathBuf {
+        let (prefix, rest) = digest.split_at(2);
+        self.root.join("pages").join(prefix).join(format!("{rest}.zst"))
+    }
+
+    /// Returns true if the page was not yet in the store.
+    fn write_page(&self, digest: &str, page: &[u8]) -> Result<bool> {
+        let path = self.page_path(digest);
+        if path.exists() {
+            return Ok(false);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let compressed = zstd::bulk::compress(page, COMPRESSION_LEVEL)?;
+        write_atomic(&path, &compressed)?;
+        Ok(true)
+    }
+
+    fn read_page(&self, id: &str, digest: &str) -> Result<Vec<u8>> {
+        let path = self.page_path(digest);
+        if !path.exists() {
+            return Err(SnapshotStoreError::MissingPage {
+                id: id.into(),
+                digest: digest.into(),
+            });
+        }
+        Ok(zstd::bulk::decompress(&fs::read(path)?, PAGE_SIZE)?)
+    }
+}
+
+/// Snapshot ids name files and directories, so only a single normal path
+/// component is a valid id.
+pub(crate) fn validate_id(id: &str) -> Result<()> {
+    let mut components = Path::new(id).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(name)), None) if name == id => Ok(()),
+        _ => Err(SnapshotStoreError::InvalidId { id: id.into() }),
+    }
+}
+
+/// Fill `buf` from `reader`, only returning less than a full page at EOF.
+fn read_page(reader: &mut impl Read, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+/// Write to a temporary file first so a crash never leaves a truncated page
+/// or manifest behind under its final name.
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, contents)?;
+    fs::rename(tmp, path)
+}
+
+fn digest_of(path: &Path) -> Option<String> {
+    let prefix = path.parent()?.file_name()?.to_str()?;
+    let rest = path.file_name()?.to_str()?.strip_suffix(".zst")?;
+    Some(format!("{prefix}{rest}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn scratch_dir(scratch: &TempDir, name: &str) -> PathBuf {
+        let dir = scratch.path().join(name);
+        fs::create_dir_all(&dir).expect("create scratch dir");
+        dir
+    }
+
+    fn page_count(root: &Path) -> usize {
+        WalkDir::new(root.join("pages"))
+            .min_depth(2)
+            .into_iter()
+            .filter(|e| e.as_ref().is_ok_and(|e| e.file_type().is_file()))
+            .count()
+    }
+
+    #[test]
+    fn ingest_and_materialize_roundtrip() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        let dest = scratch_dir(&scratch, "dest");
+
+        let memory: Vec<u8> =
+            (0..(PAGE_SIZE * 3 + 17)).map(|i| (i % 251) as u8).collect();
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+        fs::write(src.join("config.json"), b"{}").expect("write config");
+
+        let store = PageStore::new(&root);
+        let manifest = store.ingest("snap", &src, None, None).expect("ingest");
+        assert_eq!(manifest.files.len(), 2);
+
+        store.materialize("snap", &dest).expect("materialize");
+        assert_eq!(
+            fs::read(dest.join("memory-ranges")).expect("read memory"),
+            memory
+        );
+        assert_eq!(fs::read(dest.join("config.json")).expect("read"), b"{}");
+    }
+
+    #[test]
+    fn open_rejects_unreadable_manifests() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root").join("store");
+        let src = scratch_dir(&scratch, "src");
+        fs::write(src.join("config.json"), b"{}").expect("write config");
+
+        let store = PageStore::new(&root);
+        assert_eq!(store.open().expect("open empty store"), 0);
+
+        let _ = store.ingest("snap", &src, None, None).expect("ingest");
+        assert_eq!(store.open().expect("open store"), 1);
+
+        fs::write(root.join("manifests").join("broken.json"), b"{")
+            .expect("write broken manifest");
+        assert!(matches!(
+            store.open(),
+            Err(SnapshotStoreError::InvalidManifest(_))
+        ));
+    }
+
+    #[test]
+    fn identical_pages_are_stored_once() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+
+        // Four pages, two of which are identical zero pages
+        let mut memory = vec![0u8; PAGE_SIZE * 2];
+        memory.extend(vec![1u8; PAGE_SIZE]);
+        memory.extend(vec![2u8; PAGE_SIZE]);
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+
+        let store = PageStore::new(&root);
+        let _ = store.ingest("first", &src, None, None).expect("ingest first");
+        assert_eq!(page_count(&root), 3);
+
+        let _ =
+            store.ingest("second", &src, None, None).expect("ingest second");
+        assert_eq!(page_count(&root), 3);
+    }
+
+    #[test]
+    fn remove_collects_unreferenced_pages() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let a = scratch_dir(&scratch, "a");
+        let b = scratch_dir(&scratch, "b");
+        fs::write(a.join("memory-ranges"), vec![1u8; PAGE_SIZE]).expect("a");
+        fs::write(b.join("memory-ranges"), vec![2u8; PAGE_SIZE]).expect("b");
+
+        let store = PageStore::new(&root);
+        let _ = store.ingest("a", &a, None, None).expect("ingest a");
+        let _ = store.ingest("b", &b, None, None).expect("ingest b");
+        assert_eq!(page_count(&root), 2);
+
+        store.remove("a").expect("remove a");
+        assert_eq!(page_count(&root), 1);
+        assert!(matches!(
+            store.manifest("a"),
+            Err(SnapshotStoreError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn concurrent_ingest_keeps_pages_of_removed_snapshots() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        // pages shared by both snapshots, and pages of the second one only
+        let mut contents = vec![1u8; 64 * PAGE_SIZE];
+        contents.extend(vec![2u8; 64 * PAGE_SIZE]);
+        fs::write(src.join("memory-ranges"), &contents).expect("src");
+
+        let store = PageStore::new(&root);
+        for round in 0..16 {
+            let removed = format!("removed-{round}");
+            let ingested = format!("ingested-{round}");
+            let shared = scratch_dir(&scratch, &removed);
+            fs::write(shared.join("memory-ranges"), vec![1u8; PAGE_SIZE])
+                .expect("shared");
+            let _ =
+                store.ingest(&removed, &shared, None, None).expect("ingest");
+
+            std::thread::scope(|scope| {
+                let _ = scope.spawn(|| store.remove(&removed).expect("remove"));
+                let _ = store
+                    .ingest(&ingested, &src, None, None)
+                    .expect("ingest concurrently");
+            });
+
+            let dest = scratch_dir(&scratch, &format!("dest-{round}"));
+            store.materialize(&ingested, &dest).expect("materialize");
+            assert_eq!(
+                fs::read(dest.join("memory-ranges")).expect("read"),
+                contents
+            );
+            store.remove(&ingested).expect("remove");
+        }
+    }
+
+    #[test]
+    fn differential_snapshot_only_records_dirty_pages() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        let dest = scratch_dir(&scratch, "dest");
+
+        let mut memory = vec![0u8; PAGE_SIZE * 4];
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+
+        let store = PageStore::new(&root);
+        let _ = store.ingest("base", &src, None, None).expect("ingest base");
+
+        // Dirty a single page and grow the image by one page
+        memory[PAGE_SIZE * 2] = 1;
+        memory.extend(vec![0u8; PAGE_SIZE]);
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+
+        let delta = store
+            .ingest("delta", &src, Some("base"), None)
+            .expect("ingest delta");
+        assert_eq!(delta.parent.as_deref(), Some("base"));
+        assert_eq!(
+            delta.files[0].pages.keys().copied().collect::<Vec<_>>(),
+            vec![2, 4]
+        );
+
+        store.materialize("delta", &dest).expect("materialize");
+        assert_eq!(
+            fs::read(dest.join("memory-ranges")).expect("read memory"),
+            memory
+        );
+    }
+
+    #[test]
+    fn differential_snapshot_only_reads_tracked_dirty_pages() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        let dest = scratch_dir(&scratch, "dest");
+
+        let mut memory = vec![0u8; PAGE_SIZE * 4];
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+        fs::write(src.join("state.json"), "{}").expect("write state");
+
+        let store = PageStore::new(&root);
+        let _ = store.ingest("base", &src, None, None).expect("ingest base");
+
+        // Only page 2 is tracked as dirty, so the change to page 1 is not
+        // even read
+        memory[PAGE_SIZE] = 1;
+        memory[PAGE_SIZE * 2] = 1;
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+        fs::write(src.join("state.json"), "{\"a\":1}").expect("write state");
+
+        let dirty =
+            DirtyPages { path: Path::new("memory-ranges"), bitmap: &[0b0100] };
+        let delta = store
+            .ingest("delta", &src, Some("base"), Some(dirty))
+            .expect("ingest delta");
+        let memory_file = delta
+            .files
+            .iter()
+            .find(|f| f.path == Path::new("memory-ranges"))
+            .expect("memory file");
+        assert_eq!(memory_file.pages.keys().copied().collect::<Vec<_>>(), [2]);
+        let state_file = delta
+            .files
+            .iter()
+            .find(|f| f.path == Path::new("state.json"))
+            .expect("state file");
+        assert_eq!(state_file.pages.len(), 1);
+
+        store.materialize("delta", &dest).expect("materialize");
+        memory[PAGE_SIZE] = 0;
+        assert_eq!(
+            fs::read(dest.join("memory-ranges")).expect("read memory"),
+            memory
+        );
+
+        // Dirty pages of a file whose layout changed are not trusted
+        memory[PAGE_SIZE] = 1;
+        memory.extend(vec![1u8; PAGE_SIZE]);
+        fs::write(src.join("memory-ranges"), &memory).expect("write memory");
+        let regrown = store
+            .ingest("regrown", &src, Some("delta"), Some(dirty))
+            .expect("ingest regrown");
+        let memory_file = regrown
+            .files
+            .iter()
+            .find(|f| f.path == Path::new("memory-ranges"))
+            .expect("memory file");
+        assert_eq!(
+            memory_file.pages.keys().copied().collect::<Vec<_>>(),
+            [1, 4]
+        );
+    }
+
+    #[test]
+    fn flatten_detaches_snapshot_from_its_parent() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        let dest = scratch_dir(&scratch, "dest");
+
+        fs::write(src.join("memory-ranges"), vec![1u8; PAGE_SIZE * 2])
+            .expect("write memory");
+        let store = PageStore::new(&root);
+        let _ = store.ingest("base", &src, None, None).expect("ingest base");
+
+        fs::write(src.join("memory-ranges"), vec![2u8; PAGE_SIZE])
+            .expect("write memory");
+        let _ =
+            store.ingest("delta", &src, Some("base"), None).expect("ingest");
+
+        assert!(matches!(
+            store.remove("base"),
+            Err(SnapshotStoreError::HasChildren { .. })
+        ));
+
+        let flat = store.flatten("delta").expect("flatten");
+        assert_eq!(flat.parent, None);
+        store.remove("base").expect("remove base");
+
+        store.materialize("delta", &dest).expect("materialize");
+        assert_eq!(
+            fs::read(dest.join("memory-ranges")).expect("read memory"),
+            vec![2u8; PAGE_SIZE]
+        );
+    }
+
+    #[test]
+    fn ids_must_be_a_single_path_component() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        fs::write(src.join("memory-ranges"), vec![1u8; PAGE_SIZE])
+            .expect("write memory");
+
+        let store = PageStore::new(&root);
+        for id in ["", ".", "..", "../escape", "a/b", "/abs", "snap/"] {
+            assert!(
+                matches!(
+                    store.ingest(id, &src, None, None),
+                    Err(SnapshotStoreError::InvalidId { .. })
+                ),
+                "{id:?} should be invalid"
+            );
+            assert!(matches!(
+                store.remove(id),
+                Err(SnapshotStoreError::InvalidId { .. })
+            ));
+        }
+        assert!(validate_id("snap-1.v2").is_ok());
+    }
+
+    #[test]
+    fn ingest_rejects_duplicate_id() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let root = scratch_dir(&scratch, "root");
+        let src = scratch_dir(&scratch, "src");
+        fs::write(src.join("state.json"), b"{}").expect("write state");
+
+        let store = PageStore::new(&root);
+        let _ = store.ingest("snap", &src, None, None).expect("ingest");
+        assert!(matches!(
+            store.ingest("snap", &src, None, None),
+            Err(SnapshotStoreError::AlreadyExists { .. })
+        ));
+    }
+}
@@ -13,25 +13,123 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
-use anyhow::Context;
+use anyhow::{anyhow, bail, Context};
+use std::fmt::{self, Display};
 use std::fs;
 use std::fs::Permissions;
 use std::os::unix::prelude::PermissionsExt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 const PROC_SELF_EXE: &str = "/proc/self/exe";
 //const SPAWN_CONFIG: &[u8] = include_bytes!("config.json");
 
+/// CPU architectures spawn bundles can be assembled for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    X86_64,
+    Aarch64,
+}
+
+impl Arch {
+    /// The architecture this auraed runs on.
+    pub fn host() -> Self {
+        if cfg!(target_arch = "aarch64") {
+            Self::Aarch64
+        } else {
+            Self::X86_64
+        }
+    }
+
+    /// The e_machine of ELF executables for the architecture.
+    fn elf_machine(self) -> u16 {
+        match self {
+            Self::X86_64 => 62,
+            Self::Aarch64 => 183,
+        }
+    }
+}
+
+impl Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X86_64 => write!(f, "x86_64"),
+            Self::Aarch64 => write!(f, "aarch64"),
+        }
+    }
+}
+
+impl FromStr for Arch {
+    type Err = anyhow::Error;
+
+    /// Accepts the names of the kernel and of OCI image platforms.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x86_64" | "amd64" => Ok(Self::X86_64),
+            "aarch64" | "arm64" => Ok(Self::Aarch64),
+            _ => Err(anyhow!("unsupported architecture '{s}'")),
+        }
+    }
+}
+
+/// The auraed binary to package for `arch`: this auraed on its own
+/// architecture, otherwise the binary provided at
+/// `<library_dir>/bin/<arch>/auraed`.
+pub fn auraed_binary(
+    arch: Arch,
+    library_dir: &Path,
+) -> Result<PathBuf, anyhow::Error> {
+    if arch == Arch::host() {
+        // /proc/self/exe is a symbolic link to our binary
+        return fs::read_link(PROC_SELF_EXE)
+            .context("reading auraed sym link from procfs");
+    }
+
+    let binary = library_dir.join("bin").join(arch.to_string()).join("auraed");
+    if !binary.is_file() {
+        bail!(
+            "no auraed binary for {arch}, expected one at {}",
+            binary.display()
+        );
+    }
+    Ok(binary)
+}
+
+/// Checks that `binary` is a 64-bit ELF executable for `arch`, so that a
+/// bundle never packages an auraed its target cannot run.
+fn check_elf_arch(binary: &[u8], arch: Arch) -> Result<(), anyhow::Error> {
+    if binary.len() < 20 || &binary[..4] != b"\x7fELF" {
+        bail!("auraed binary is not an ELF executable");
+    }
+    if binary[4] != 2 {
+        bail!("auraed binary is not a 64-bit executable");
+    }
+    let machine = [binary[18], binary[19]];
+    let machine = match binary[5] {
+        1 => u16::from_le_bytes(machine),
+        2 => u16::from_be_bytes(machine),
+        data => bail!("auraed binary has an invalid ELF data encoding {data}"),
+    };
+    if machine != arch.elf_machine() {
+        bail!("auraed binary is built for ELF machine {machine}, not {arch}");
+    }
+    Ok(())
+}
+
+/// Assembles a bundle running the `auraed` binary for `arch` in `output`.
 // TODO accept a OCI config from calling code (CRI has linux config that will need to be mapped to spec)
 pub fn spawn_auraed_oci_to(
     output: PathBuf,
     spec: oci_spec::runtime::Spec,
+    arch: Arch,
+    auraed: &Path,
 ) -> Result<(), anyhow::Error> {
-    // Here we read /proc/self/exe which will be a symbolic link to our binary.
-    let auraed_path = fs::read_link(PROC_SELF_EXE)
-        .context("reading auraed sym link from procfs")?;
-    let binary_data = fs::read(auraed_path)
-        .context("reading auraed executable from procfs")?;
+    let binary_data = fs::read(auraed).with_context(|| {
+        format!("reading auraed executable {}", auraed.display())
+    })?;
+    check_elf_arch(&binary_data, arch).with_context(|| {
+        format!("packaging {} for {arch}", auraed.display())
+    })?;
 
     // Reset the output directory (if exists)
     let _ = fs::remove_dir_all(&output).context("remove output dir clean");
@@ -91,4 +189,46 @@ pub fn spawn_auraed_oci_to(
     .expect("linking /bin/auraed to /bin/init");
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf_header(class: u8, data: u8, machine: u16) -> Vec<u8> {
+        let mut header = b"\x7fELF".to_vec();
+        header.extend([class, data, 1]);
+        header.resize(18, 0);
+        match data {
+            2 => header.extend(machine.to_be_bytes()),
+            _ => header.extend(machine.to_le_bytes()),
+        }
+        header.resize(64, 0);
+        header
+    }
+
+    #[test]
+    fn elf_arch_must_match_the_target() {
+        assert!(check_elf_arch(&elf_header(2, 1, 62), Arch::X86_64).is_ok());
+        assert!(check_elf_arch(&elf_header(2, 1, 183), Arch::Aarch64).is_ok());
+        assert!(check_elf_arch(&elf_header(2, 2, 183), Arch::Aarch64).is_ok());
+        assert!(check_elf_arch(&elf_header(2, 1, 62), Arch::Aarch64).is_err());
+        assert!(check_elf_arch(&elf_header(1, 1, 62), Arch::X86_64).is_err());
+        assert!(check_elf_arch(b"#!/bin/sh\n", Arch::X86_64).is_err());
+    }
+
+    #[test]
+    fn this_auraed_is_packaged_for_the_host() {
+        let library_dir = tempfile::tempdir().expect("scratch dir");
+        let auraed = auraed_binary(Arch::host(), library_dir.path())
+            .expect("host auraed");
+        let binary = fs::read(auraed).expect("read auraed");
+        assert!(check_elf_arch(&binary, Arch::host()).is_ok());
+
+        let other = match Arch::host() {
+            Arch::X86_64 => Arch::Aarch64,
+            Arch::Aarch64 => Arch::X86_64,
+        };
+        assert!(auraed_binary(other, library_dir.path()).is_err());
+        assert_eq!("arm64".parse::<Arch>().expect("arch"), Arch::Aarch64);
+    }
+}
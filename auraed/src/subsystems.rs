@@ -0,0 +1,97 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Subsystems of auraed that can be disabled, for deployments such as small
+//! edge nodes that neither want to pay for their startup nor expose them.
+//!
+//! The gRPC services of a disabled subsystem are not served at all, so the
+//! health service reports them as unknown (NOT_FOUND) rather than
+//! NOT_SERVING, which is left to subsystems that failed to start.
+
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Which subsystems auraed starts. All of them by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubsystemsConfig {
+    /// VmService and the VMM, along with KVM and the network of VMs.
+    pub vms: bool,
+    /// The CRI runtime and image services, and the CRI streaming server.
+    pub cri: bool,
+    /// The eBPF probes behind the process and signal events of
+    /// ObserveService.
+    pub ebpf: bool,
+    /// DiscoveryService.
+    pub discovery: bool,
+}
+
+impl Default for SubsystemsConfig {
+    fn default() -> Self {
+        Self { vms: true, cri: true, ebpf: true, discovery: true }
+    }
+}
+
+impl SubsystemsConfig {
+    fn disable(&mut self, name: &str) -> Result<(), UnknownSubsystem> {
+        let enabled = match name {
+            "vms" => &mut self.vms,
+            "cri" => &mut self.cri,
+            "ebpf" => &mut self.ebpf,
+            "discovery" => &mut self.discovery,
+            _ => return Err(UnknownSubsystem(name.to_string())),
+        };
+        *enabled = false;
+        Ok(())
+    }
+}
+
+impl FromStr for SubsystemsConfig {
+    type Err = UnknownSubsystem;
+
+    /// Parses a comma separated list of the subsystems to disable.
+    fn from_str(disabled: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for name in disabled.split(',').map(str::trim).filter(|n| !n.is_empty())
+        {
+            config.disable(name)?;
+        }
+        Ok(config)
+    }
+}
+
+/// A subsystem auraed does not know of.
+#[derive(Debug, Error)]
+#[error("unknown subsystem '{0}', expected one of vms, cri, ebpf or discovery")]
+pub struct UnknownSubsystem(String);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_disabled_subsystems() {
+        let config: SubsystemsConfig = "vms, ebpf".parse().expect("parse");
+        assert_eq!(
+            config,
+            SubsystemsConfig {
+                vms: false,
+                cri: true,
+                ebpf: false,
+                discovery: true
+            }
+        );
+        assert!("".parse::<SubsystemsConfig>().is_ok());
+        assert!("pods".parse::<SubsystemsConfig>().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use once_cell::sync::OnceCell;
+use std::io;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// Lives as long as auraed, so it is never dropped from within a runtime.
+static VM_RUNTIME: OnceCell<Runtime> = OnceCell::new();
+
+/// Thread topology of the tokio runtimes auraed runs on, to tune the daemon
+/// for large hosts as well as small edge nodes.
+#[derive(Debug, Clone, Default)]
+pub struct TokioConfig {
+    /// Worker threads of the main runtime. Defaults to the number of CPUs.
+    pub worker_threads: Option<usize>,
+    /// Limit of the blocking thread pool of the main runtime, used for file
+    /// system access and other blocking calls. Defaults to 512.
+    pub max_blocking_threads: Option<usize>,
+    /// Worker threads of a separate runtime driving the VMM, so blocking VM
+    /// operations cannot stall the workers serving other requests. VM
+    /// operations share the main runtime if unset.
+    pub vm_worker_threads: Option<usize>,
+}
+
+impl TokioConfig {
+    /// Builds the main runtime of auraed.
+    pub fn build(&self) -> io::Result<Runtime> {
+        let mut builder = Builder::new_multi_thread();
+        let _ = builder.thread_name("auraed-worker").enable_all();
+        if let Some(threads) = self.worker_threads {
+            let _ = builder.worker_threads(at_least_one(threads, "workers")?);
+        }
+        if let Some(threads) = self.max_blocking_threads {
+            let _ = builder.max_blocking_threads(at_least_one(
+                threads,
+                "blocking threads",
+            )?);
+        }
+        builder.build()
+    }
+
+    /// The runtime VM operations are spawned on, built on first use. [None]
+    /// if they run on the runtime of the caller.
+    pub(crate) fn vm_runtime(&self) -> io::Result<Option<Handle>> {
+        let Some(threads) = self.vm_worker_threads else {
+            return Ok(None);
+        };
+        let runtime = VM_RUNTIME.get_or_try_init(|| {
+            Builder::new_multi_thread()
+                .thread_name("auraed-vm")
+                .worker_threads(at_least_one(threads, "vm workers")?)
+                .enable_all()
+                .build()
+        })?;
+        Ok(Some(runtime.handle().clone()))
+    }
+}
+
+fn at_least_one(threads: usize, what: &str) -> io::Result<usize> {
+    if threads == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("the number of {what} must be at least 1"),
+        ));
+    }
+    Ok(threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_zero_threads() {
+        let config =
+            TokioConfig { worker_threads: Some(0), ..Default::default() };
+        assert!(config.build().is_err());
+
+        let config =
+            TokioConfig { vm_worker_threads: Some(0), ..Default::default() };
+        assert!(config.vm_runtime().is_err());
+    }
+}
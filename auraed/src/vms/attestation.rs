@@ -0,0 +1,119 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Attestation reports of the confidential guest auraed runs in, produced
+//! through the configfs-tsm interface of the kernel, which serves TDX quotes
+//! and SEV-SNP attestation reports alike.
+//!
+//! A report is requested by creating a directory below [TSM_REPORT_DIR],
+//! writing the report data to its `inblob` and reading its `outblob`, which
+//! the kernel generates on read. Its `generation` changes with every write,
+//! so a report is only returned if nobody else wrote to the directory in
+//! between.
+
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+/// Where the kernel of a confidential guest serves reports
+const TSM_REPORT_DIR: &str = "/sys/kernel/config/tsm/report";
+/// Size of the report data of both TDX and SEV-SNP reports
+pub(crate) const REPORT_DATA_SIZE: usize = 64;
+
+/// An attestation report, as produced by its provider.
+#[derive(Debug)]
+pub(crate) struct Report {
+    /// `tdx_guest` or `sev_guest`
+    pub provider: String,
+    pub report: Vec<u8>,
+    /// Certificates sent along by the provider, if any
+    pub certificates: Vec<u8>,
+}
+
+/// Requests a report binding `report_data`, of at most [REPORT_DATA_SIZE]
+/// bytes, from the kernel. Fails with [ErrorKind::NotFound] outside of a
+/// confidential guest.
+pub(crate) fn request(report_data: &[u8]) -> io::Result<Report> {
+    let dir = Path::new(TSM_REPORT_DIR)
+        .join(format!("aurae-{}", uuid::Uuid::new_v4().simple()));
+    fs::create_dir(&dir)?;
+    let report = read_report(&dir, report_data);
+    let _best_effort = fs::remove_dir(&dir);
+    report
+}
+
+/// Writes `report_data` to the report request `dir` and reads the report.
+fn read_report(dir: &Path, report_data: &[u8]) -> io::Result<Report> {
+    if report_data.len() > REPORT_DATA_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("report data is longer than {REPORT_DATA_SIZE} bytes"),
+        ));
+    }
+    let mut inblob = [0u8; REPORT_DATA_SIZE];
+    inblob[..report_data.len()].copy_from_slice(report_data);
+    fs::write(dir.join("inblob"), inblob)?;
+
+    let generation = fs::read_to_string(dir.join("generation"))?;
+    let report = fs::read(dir.join("outblob"))?;
+    let certificates = match fs::read(dir.join("auxblob")) {
+        Ok(certificates) => certificates,
+        // only providers with certificates have an auxblob
+        Err(e) if e.kind() == ErrorKind::NotFound => vec![],
+        Err(e) => return Err(e),
+    };
+    let provider = fs::read_to_string(dir.join("provider"))?;
+    if fs::read_to_string(dir.join("generation"))? != generation {
+        return Err(io::Error::new(
+            ErrorKind::Interrupted,
+            "report request was written to by someone else",
+        ));
+    }
+
+    Ok(Report { provider: provider.trim().to_owned(), report, certificates })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_data_is_padded_and_the_report_read() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        fs::write(dir.path().join("generation"), "1\n").expect("generation");
+        fs::write(dir.path().join("outblob"), b"quote").expect("outblob");
+        fs::write(dir.path().join("provider"), "tdx_guest\n")
+            .expect("provider");
+
+        let report = read_report(dir.path(), b"nonce").expect("report");
+        assert_eq!(report.provider, "tdx_guest");
+        assert_eq!(report.report, b"quote");
+        assert!(report.certificates.is_empty());
+
+        let inblob = fs::read(dir.path().join("inblob")).expect("inblob");
+        assert_eq!(inblob.len(), REPORT_DATA_SIZE);
+        assert_eq!(&inblob[..5], b"nonce");
+        assert!(inblob[5..].iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn overlong_report_data_is_rejected() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let e = read_report(dir.path(), &[0; REPORT_DATA_SIZE + 1])
+            .expect_err("overlong report data");
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,148 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Node checkpoints bundle the snapshots of all VMs on a node into a single
+//! zstd compressed tarball, so they can be restored after the host reboots.
+//! The archive holds one directory per VM, named after its ID. Besides the
+//! VMs, the archives of CheckpointNode hold the cells of the node in
+//! [CELLS_DIR] and its state in [STATE_DIR], whose names are no valid VM
+//! IDs.
+
+use anyhow::Context;
+use std::{
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+/// Name of the cloud-init seed image within the directory of a VM, as the
+/// seed lives in the runtime directory which does not survive a reboot.
+pub(crate) const SEED_FILE: &str = "seed.img";
+/// Directory of the cells, see [crate::cri::checkpoint::dump_cell]
+pub(crate) const CELLS_DIR: &str = "_cells";
+/// Directory of the state of the node, which lives in the runtime directory
+pub(crate) const STATE_DIR: &str = "_state";
+/// The address leases of the node within [STATE_DIR]
+pub(crate) const LEASES_FILE: &str = "leases.json";
+
+/// The path of the archive `archive_path` below the checkpoint directory
+/// `dir`, given relative to it or below it. [None] if it would be outside.
+pub(crate) fn confine(dir: &Path, archive_path: &str) -> Option<PathBuf> {
+    let path = Path::new(archive_path);
+    let relative = path.strip_prefix(dir).unwrap_or(path);
+    let normal =
+        relative.components().all(|c| matches!(c, Component::Normal(_)));
+    (normal && !relative.as_os_str().is_empty()).then(|| dir.join(relative))
+}
+
+/// Pack `source` into the archive at `archive`. The archive is written next
+/// to its destination first, so an interrupted checkpoint never replaces a
+/// previous one.
+pub(crate) fn pack(source: &Path, archive: &Path) -> anyhow::Result<()> {
+    if let Some(parent) = archive.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let partial = archive.with_extension("partial");
+    let file = File::create(&partial)
+        .with_context(|| format!("failed to create '{}'", partial.display()))?;
+
+    let mut builder = tar::Builder::new(zstd::Encoder::new(file, 0)?);
+    builder.append_dir_all(".", source)?;
+    builder.into_inner()?.finish()?.sync_all()?;
+
+    std::fs::rename(&partial, archive).with_context(|| {
+        format!("failed to move checkpoint to '{}'", archive.display())
+    })
+}
+
+/// Unpack the archive at `archive` into `dest` and return the IDs of the VMs
+/// it holds.
+pub(crate) fn unpack(
+    archive: &Path,
+    dest: &Path,
+) -> anyhow::Result<Vec<String>> {
+    let file = File::open(archive)
+        .with_context(|| format!("failed to open '{}'", archive.display()))?;
+    tar::Archive::new(zstd::Decoder::new(file)?).unpack(dest)?;
+
+    let mut ids = vec![];
+    for entry in std::fs::read_dir(dest)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type()?.is_dir() && name != CELLS_DIR && name != STATE_DIR
+        {
+            ids.push(name);
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archives_are_confined_to_the_checkpoint_directory() {
+        let dir = Path::new("/var/lib/aurae/checkpoints");
+        let archive = dir.join("node.tar.zst");
+        assert_eq!(confine(dir, "node.tar.zst"), Some(archive.clone()));
+        assert_eq!(
+            confine(dir, "/var/lib/aurae/checkpoints/node.tar.zst"),
+            Some(archive)
+        );
+        for outside in [
+            "",
+            "/var/lib/aurae/checkpoints",
+            "/etc/shadow",
+            "../node.tar.zst",
+            "/var/lib/aurae/checkpoints/../node.tar.zst",
+            "nested/../../node.tar.zst",
+        ] {
+            assert_eq!(confine(dir, outside), None, "{outside}");
+        }
+    }
+
+    #[test]
+    fn checkpoint_round_trips() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(source.join("vm-a")).expect("create vm-a");
+        std::fs::create_dir_all(source.join("vm-b")).expect("create vm-b");
+        std::fs::create_dir_all(source.join(CELLS_DIR)).expect("create cells");
+        std::fs::write(source.join("vm-a").join("config.json"), "{}")
+            .expect("write config");
+        std::fs::write(source.join("vm-b").join(SEED_FILE), [1, 2, 3])
+            .expect("write seed");
+
+        let archive = dir.path().join("checkpoint.tar.zst");
+        pack(&source, &archive).expect("pack");
+        assert!(!archive.with_extension("partial").exists());
+
+        let dest = dir.path().join("dest");
+        let ids = unpack(&archive, &dest).expect("unpack");
+        assert_eq!(ids, vec!["vm-a".to_string(), "vm-b".to_string()]);
+        assert!(dest.join(CELLS_DIR).is_dir());
+        assert_eq!(
+            std::fs::read_to_string(dest.join("vm-a").join("config.json"))
+                .expect("read config"),
+            "{}"
+        );
+        assert_eq!(
+            std::fs::read(dest.join("vm-b").join(SEED_FILE))
+                .expect("read seed"),
+            vec![1, 2, 3]
+        );
+    }
+}
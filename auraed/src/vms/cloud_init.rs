@@ -0,0 +1,150 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use anyhow::Context;
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+/// Size of the seed image. The files on it are small, a FAT12 volume of
+/// this size leaves plenty of room for large user-data scripts.
+const SEED_IMAGE_SIZE: u64 = 4 << 20;
+
+/// Volume label the cloud-init NoCloud datasource looks for.
+const SEED_VOLUME_LABEL: [u8; 11] = *b"CIDATA     ";
+
+/// The contents of a cloud-init NoCloud seed.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct CloudInitSpec {
+    pub instance_id: String,
+    pub hostname: Option<String>,
+    pub ssh_authorized_keys: Vec<String>,
+    pub user_data: Option<String>,
+    pub network_config: Option<String>,
+}
+
+/// The NoCloud `meta-data` file. JSON is a subset of YAML, so there is no
+/// need for a YAML serializer.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct MetaData<'a> {
+    instance_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    local_hostname: Option<&'a str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    public_keys: &'a Vec<String>,
+}
+
+impl CloudInitSpec {
+    /// Write a vfat NoCloud seed image to `path`, replacing any existing one.
+    pub fn write_seed(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).with_context(|| {
+                format!("failed to create '{}'", parent.display())
+            })?;
+        }
+
+        let mut image = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("failed to open '{}'", path.display()))?;
+        image.set_len(SEED_IMAGE_SIZE)?;
+
+        fatfs::format_volume(
+            &mut image,
+            fatfs::FormatVolumeOptions::new().volume_label(SEED_VOLUME_LABEL),
+        )
+        .context("failed to format seed image")?;
+        let _ = image.seek(SeekFrom::Start(0))?;
+
+        let fs = fatfs::FileSystem::new(&mut image, fatfs::FsOptions::new())
+            .context("failed to open seed image")?;
+        let root = fs.root_dir();
+
+        let meta_data = serde_json::to_vec_pretty(&MetaData {
+            instance_id: &self.instance_id,
+            local_hostname: self.hostname.as_deref(),
+            public_keys: &self.ssh_authorized_keys,
+        })?;
+        root.create_file("meta-data")?.write_all(&meta_data)?;
+
+        // cloud-init refuses to use a NoCloud seed without user-data
+        let user_data = self.user_data.as_deref().unwrap_or("#cloud-config\n");
+        root.create_file("user-data")?.write_all(user_data.as_bytes())?;
+
+        if let Some(network_config) = &self.network_config {
+            root.create_file("network-config")?
+                .write_all(network_config.as_bytes())?;
+        }
+
+        fs.unmount().context("failed to flush seed image")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    fn read(fs: &fatfs::FileSystem<&mut File>, name: &str) -> String {
+        let mut contents = String::new();
+        let _ = fs
+            .root_dir()
+            .open_file(name)
+            .expect("open file")
+            .read_to_string(&mut contents)
+            .expect("read file");
+        contents
+    }
+
+    #[test]
+    fn writes_nocloud_seed() {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("seed.img");
+
+        let spec = CloudInitSpec {
+            instance_id: "vm-1".into(),
+            hostname: Some("node".into()),
+            ssh_authorized_keys: vec!["ssh-ed25519 AAAA test".into()],
+            user_data: None,
+            network_config: Some("version: 2\n".into()),
+        };
+        spec.write_seed(&path).expect("write seed");
+
+        let mut image = File::options()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .expect("open seed");
+        let fs = fatfs::FileSystem::new(&mut image, fatfs::FsOptions::new())
+            .expect("mount seed");
+        assert_eq!(fs.volume_label().trim_end(), "CIDATA");
+
+        let meta_data: serde_json::Value =
+            serde_json::from_str(&read(&fs, "meta-data")).expect("meta-data");
+        assert_eq!(meta_data["instance-id"], "vm-1");
+        assert_eq!(meta_data["local-hostname"], "node");
+        assert_eq!(meta_data["public-keys"][0], "ssh-ed25519 AAAA test");
+        assert_eq!(read(&fs, "user-data"), "#cloud-config\n");
+        assert_eq!(read(&fs, "network-config"), "version: 2\n");
+    }
+}
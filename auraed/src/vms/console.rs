@@ -0,0 +1,199 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Serial consoles of VMs, as captured by auraed.
+//!
+//! The VMM serves a single connection on the console socket of a VM, so
+//! auraed holds that connection for as long as the VM runs. The output of
+//! the guest is kept in a ring buffer of its last [HISTORY_SIZE] bytes, so
+//! that boot failures can be diagnosed when nobody was attached at the time,
+//! and is fanned out to the clients attached to the console, which write
+//! their input to the same connection.
+
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::{Arc, Mutex as StdMutex},
+};
+use tokio::{
+    io::AsyncReadExt,
+    net::{unix::OwnedWriteHalf, UnixStream},
+    sync::{broadcast, Mutex},
+    task::AbortHandle,
+};
+
+/// How many bytes of the output of each VM are kept
+pub(crate) const HISTORY_SIZE: usize = 64 * 1024;
+/// How many chunks of output an attached client may fall behind before it
+/// misses some of them
+const OUTPUT_CAPACITY: usize = 256;
+
+/// The input side of a console, shared by the clients attached to it
+pub(crate) type ConsoleWriter = Arc<Mutex<OwnedWriteHalf>>;
+
+/// The serial console of a VM. The history outlives the connection, it is
+/// kept until the VM is freed and appended to when the VM runs again.
+#[derive(Debug)]
+pub(crate) struct Console {
+    shared: Arc<StdMutex<Shared>>,
+    writer: ConsoleWriter,
+    reader: AbortHandle,
+}
+
+#[derive(Debug)]
+struct Shared {
+    history: History,
+    /// Output is sent to the attached clients while the console is connected
+    output: Option<broadcast::Sender<Vec<u8>>>,
+}
+
+impl Console {
+    /// Connects to the console `socket` of a VM with an empty history.
+    pub(crate) async fn connect(socket: &Path) -> std::io::Result<Self> {
+        let shared = Arc::new(StdMutex::new(Shared {
+            history: History::new(HISTORY_SIZE),
+            output: None,
+        }));
+        let (writer, reader) = attach(socket, shared.clone()).await?;
+        Ok(Self { shared, writer, reader })
+    }
+
+    /// Connects to the console `socket` again once the VM runs again, e.g.
+    /// after it was stopped and started, keeping the history.
+    pub(crate) async fn reconnect(
+        &mut self,
+        socket: &Path,
+    ) -> std::io::Result<()> {
+        let (writer, reader) = attach(socket, self.shared.clone()).await?;
+        self.reader.abort();
+        self.writer = writer;
+        self.reader = reader;
+        Ok(())
+    }
+
+    /// Whether the connection to the VMM is still open.
+    pub(crate) fn is_connected(&self) -> bool {
+        self.shared.lock().expect("poisoned console").output.is_some()
+    }
+
+    /// The output kept of the console, and whether older output was dropped
+    /// to make room for it.
+    pub(crate) fn history(&self) -> (Vec<u8>, bool) {
+        let shared = self.shared.lock().expect("poisoned console");
+        (
+            shared.history.output.iter().copied().collect(),
+            shared.history.truncated,
+        )
+    }
+
+    /// Subscribes to the output of the console from now on, the subscription
+    /// ends once the connection to the VMM is closed.
+    pub(crate) fn subscribe(&self) -> Option<broadcast::Receiver<Vec<u8>>> {
+        let shared = self.shared.lock().expect("poisoned console");
+        shared.output.as_ref().map(broadcast::Sender::subscribe)
+    }
+
+    /// The input side of the console.
+    pub(crate) fn writer(&self) -> ConsoleWriter {
+        self.writer.clone()
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+/// Connects to the console `socket` and keeps reading its output into
+/// `shared`, until the VMM closes the connection.
+async fn attach(
+    socket: &Path,
+    shared: Arc<StdMutex<Shared>>,
+) -> std::io::Result<(ConsoleWriter, AbortHandle)> {
+    let stream = UnixStream::connect(socket).await?;
+    let (mut reader, writer) = stream.into_split();
+    shared.lock().expect("poisoned console").output =
+        Some(broadcast::channel(OUTPUT_CAPACITY).0);
+
+    let reader = tokio::spawn(async move {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = match reader.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let mut console = shared.lock().expect("poisoned console");
+            console.history.push(&buf[..n]);
+            if let Some(output) = &console.output {
+                // nobody may be attached
+                let _ = output.send(buf[..n].to_vec());
+            }
+        }
+        // dropping the sender ends the subscriptions of attached clients
+        shared.lock().expect("poisoned console").output = None;
+    })
+    .abort_handle();
+
+    Ok((Arc::new(Mutex::new(writer)), reader))
+}
+
+/// The last bytes of output of a console, up to its capacity
+#[derive(Debug)]
+struct History {
+    output: VecDeque<u8>,
+    capacity: usize,
+    /// Whether older output was dropped to make room
+    truncated: bool,
+}
+
+impl History {
+    fn new(capacity: usize) -> Self {
+        Self { output: VecDeque::new(), capacity, truncated: false }
+    }
+
+    fn push(&mut self, mut output: &[u8]) {
+        if output.len() > self.capacity {
+            output = &output[output.len() - self.capacity..];
+            self.truncated = true;
+        }
+        let excess =
+            (self.output.len() + output.len()).saturating_sub(self.capacity);
+        if excess > 0 {
+            drop(self.output.drain(..excess));
+            self.truncated = true;
+        }
+        self.output.extend(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_keeps_the_latest_output() {
+        let mut history = History::new(8);
+        history.push(b"boot");
+        assert_eq!(history.output, b"boot".to_vec());
+        assert!(!history.truncated);
+
+        history.push(b"ing...");
+        assert_eq!(history.output, b"oting...".to_vec());
+        assert!(history.truncated);
+
+        history.push(b"kernel panic");
+        assert_eq!(history.output, b"el panic".to_vec());
+    }
+}
@@ -0,0 +1,197 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Masking of CPUID feature bits of VM guests.
+//!
+//! The VMM derives the CPUID of a guest from the CPUID the hypervisor reports
+//! as supported, and then patches in the topology and the bits it emulates
+//! itself. Masks are applied to the supported CPUID by wrapping the
+//! hypervisor handed to the VMM, so a masked feature is never offered to the
+//! guest, while the bits the VMM sets (e.g. the hypervisor bit of leaf 0x1)
+//! stay as they are.
+
+#[cfg(target_arch = "x86_64")]
+use hypervisor::{
+    arch::x86::CpuIdEntry, Hypervisor, HypervisorError, HypervisorType, Vm,
+};
+#[cfg(target_arch = "x86_64")]
+use std::sync::Arc;
+
+/// A register returned by the CPUID instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuidRegister {
+    Eax,
+    Ebx,
+    Ecx,
+    Edx,
+}
+
+/// Bits of a CPUID register hidden from the guest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuidMask {
+    pub leaf: u32,
+    /// 0 for leaves without subleaves
+    pub subleaf: u32,
+    pub register: CpuidRegister,
+    pub bits: u32,
+}
+
+/// Clears the bits of `masks` from the matching `entries`. Masks of leaves
+/// missing from `entries` have nothing to hide and are skipped.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn apply(entries: &mut [CpuIdEntry], masks: &[CpuidMask]) {
+    for mask in masks {
+        for entry in entries
+            .iter_mut()
+            .filter(|e| e.function == mask.leaf && e.index == mask.subleaf)
+        {
+            let register = match mask.register {
+                CpuidRegister::Eax => &mut entry.eax,
+                CpuidRegister::Ebx => &mut entry.ebx,
+                CpuidRegister::Ecx => &mut entry.ecx,
+                CpuidRegister::Edx => &mut entry.edx,
+            };
+            *register &= !mask.bits;
+        }
+    }
+}
+
+/// A hypervisor reporting its supported CPUID with the bits of `masks`
+/// cleared, everything else is passed through
+#[cfg(target_arch = "x86_64")]
+pub(crate) struct MaskedCpuid {
+    hypervisor: Arc<dyn Hypervisor>,
+    masks: Vec<CpuidMask>,
+}
+
+#[cfg(target_arch = "x86_64")]
+impl MaskedCpuid {
+    pub(crate) fn new(
+        hypervisor: Arc<dyn Hypervisor>,
+        masks: Vec<CpuidMask>,
+    ) -> Self {
+        Self { hypervisor, masks }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+impl Hypervisor for MaskedCpuid {
+    fn hypervisor_type(&self) -> HypervisorType {
+        self.hypervisor.hypervisor_type()
+    }
+
+    fn create_vm_with_type(
+        &self,
+        vm_type: u64,
+    ) -> Result<Arc<dyn Vm>, HypervisorError> {
+        self.hypervisor.create_vm_with_type(vm_type)
+    }
+
+    fn create_vm(&self) -> Result<Arc<dyn Vm>, HypervisorError> {
+        self.hypervisor.create_vm()
+    }
+
+    fn get_supported_cpuid(&self) -> Result<Vec<CpuIdEntry>, HypervisorError> {
+        let mut entries = self.hypervisor.get_supported_cpuid()?;
+        apply(&mut entries, &self.masks);
+        Ok(entries)
+    }
+
+    fn check_required_extensions(&self) -> Result<(), HypervisorError> {
+        self.hypervisor.check_required_extensions()
+    }
+
+    fn get_max_vcpus(&self) -> u32 {
+        self.hypervisor.get_max_vcpus()
+    }
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::*;
+
+    const AVX: u32 = 1 << 28;
+    const AVX512F: u32 = 1 << 16;
+
+    fn entry(
+        function: u32,
+        index: u32,
+        [eax, ebx, ecx, edx]: [u32; 4],
+    ) -> CpuIdEntry {
+        CpuIdEntry { function, index, flags: 0, eax, ebx, ecx, edx }
+    }
+
+    fn masks() -> Vec<CpuidMask> {
+        vec![
+            CpuidMask {
+                leaf: 0x1,
+                subleaf: 0,
+                register: CpuidRegister::Ecx,
+                bits: AVX,
+            },
+            CpuidMask {
+                leaf: 0x7,
+                subleaf: 0,
+                register: CpuidRegister::Ebx,
+                bits: AVX512F,
+            },
+            CpuidMask {
+                leaf: 0x8000_0021,
+                subleaf: 0,
+                register: CpuidRegister::Eax,
+                bits: u32::MAX,
+            },
+        ]
+    }
+
+    #[test]
+    fn masks_clear_the_bits_of_matching_leaves() {
+        let mut entries = vec![
+            entry(0x1, 0, [0x000806f8, 0x00100800, 0xfffa3203, 0x178bfbff]),
+            entry(0x7, 0, [0x00000001, 0xf1bf07ab, 0x1b415f6e, 0xa4000010]),
+            entry(0x7, 1, [0x00001c30, 0x00010000, 0x00000000, 0x00000000]),
+        ];
+        apply(&mut entries, &masks());
+
+        let registers: Vec<_> =
+            entries.iter().map(|e| [e.eax, e.ebx, e.ecx, e.edx]).collect();
+        assert_eq!(
+            registers,
+            vec![
+                [0x000806f8, 0x00100800, 0xeffa3203, 0x178bfbff],
+                [0x00000001, 0xf1be07ab, 0x1b415f6e, 0xa4000010],
+                [0x00001c30, 0x00010000, 0x00000000, 0x00000000],
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn masked_bits_are_not_supported_for_guests() {
+        let hypervisor =
+            MaskedCpuid::new(hypervisor::new().expect("kvm"), masks());
+        let entries =
+            hypervisor.get_supported_cpuid().expect("supported cpuid");
+        let leaf = |function, index| {
+            entries
+                .iter()
+                .find(|e| e.function == function && e.index == index)
+                .expect("leaf")
+        };
+
+        assert_eq!(leaf(0x1, 0).ecx & AVX, 0);
+        assert_eq!(leaf(0x7, 0).ebx & AVX512F, 0);
+    }
+}
@@ -0,0 +1,324 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Dirty page tracking of guest memory.
+//!
+//! The VMM runs inside auraed, so guest memory is mapped into auraed and
+//! guest writes set the soft-dirty bits of its page tables. Soft-dirty bits
+//! can only be cleared for the whole process through /proc/self/clear_refs,
+//! so the bits of all tracked VMs are harvested into bitmaps of their own
+//! before they are cleared. Guest writes racing the harvest would be lost,
+//! the vCPUs of the tracked VMs have to be paused during
+//! [DirtyPageTracker::collect].
+
+use super::virtual_machine::VmID;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io,
+    os::unix::fs::FileExt,
+};
+
+/// Size of the pages tracked, huge pages are not supported
+pub(crate) const PAGE_SIZE: u64 = 4096;
+
+/// Bit of a /proc/<pid>/pagemap entry set when the page has been written
+/// since the soft-dirty bits were last cleared
+const PAGEMAP_SOFT_DIRTY: u64 = 1 << 55;
+
+/// Pagemap entries read at once
+const PAGEMAP_BATCH: u64 = 8192;
+
+/// Start of the hole below 4GiB the x86_64 VMM leaves for devices, guest
+/// memory above it is mapped as a second region
+#[cfg(target_arch = "x86_64")]
+const MEM_32BIT_RESERVED_START: u64 = 0xc000_0000;
+
+/// A range of the address space of auraed backing guest memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Mapping {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl Mapping {
+    fn pages(&self) -> u64 {
+        (self.end - self.start) / PAGE_SIZE
+    }
+}
+
+/// The writable anonymous mappings of auraed, guest memory among them
+pub(crate) fn anonymous_mappings() -> io::Result<HashSet<Mapping>> {
+    Ok(parse_maps(&fs::read_to_string("/proc/self/maps")?))
+}
+
+fn parse_maps(maps: &str) -> HashSet<Mapping> {
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (start, end) = fields.next()?.split_once('-')?;
+            let writable = fields.next()?.starts_with("rw");
+            // Shared guest memory is backed by a memfd
+            let anonymous =
+                fields.nth(3).map_or(true, |path| path.starts_with("/memfd:"));
+            if !writable || !anonymous {
+                return None;
+            }
+            Some(Mapping {
+                start: u64::from_str_radix(start, 16).ok()?,
+                end: u64::from_str_radix(end, 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+/// The sizes of the regions the VMM maps guest memory of `memory_size`
+/// bytes in, in guest physical address order
+fn region_sizes(memory_size: u64) -> Vec<u64> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let low = memory_size.min(MEM_32BIT_RESERVED_START);
+        [low, memory_size - low].into_iter().filter(|s| *s > 0).collect()
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        vec![memory_size]
+    }
+}
+
+/// The guest memory of `memory_size` bytes mapped since `before` was taken
+/// with [anonymous_mappings], in guest physical address order. [None] unless
+/// exactly one new mapping matches each region.
+pub(crate) fn new_guest_memory(
+    before: &HashSet<Mapping>,
+    memory_size: u64,
+) -> Option<Vec<Mapping>> {
+    let after = anonymous_mappings().ok()?;
+    find_guest_memory(after.difference(before).copied(), memory_size)
+}
+
+fn find_guest_memory(
+    created: impl Iterator<Item = Mapping>,
+    memory_size: u64,
+) -> Option<Vec<Mapping>> {
+    let sizes = region_sizes(memory_size);
+    let mut candidates: Vec<Mapping> =
+        created.filter(|m| sizes.contains(&(m.end - m.start))).collect();
+    if candidates.len() != sizes.len() {
+        return None;
+    }
+    // Regions of the same size are mapped in guest physical address order,
+    // top down in the address space of auraed
+    candidates.sort_by_key(|m| std::cmp::Reverse(m.start));
+    sizes
+        .iter()
+        .map(|size| {
+            let i = candidates.iter().position(|m| m.end - m.start == *size)?;
+            Some(candidates.remove(i))
+        })
+        .collect()
+}
+
+/// The pages of guest memory written since the previous collection, one bit
+/// per page in guest physical address order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DirtyBitmap {
+    pub pages: u64,
+    bits: Vec<u64>,
+}
+
+impl DirtyBitmap {
+    fn new(pages: u64, dirty: bool) -> Self {
+        let word = if dirty { u64::MAX } else { 0 };
+        Self { pages, bits: vec![word; pages.div_ceil(64) as usize] }
+    }
+
+    fn set(&mut self, page: u64) {
+        self.bits[(page / 64) as usize] |= 1 << (page % 64);
+    }
+
+    /// The bitmap as bytes, page `n` being bit `n % 8` of byte `n / 8`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> =
+            self.bits.iter().flat_map(|word| word.to_le_bytes()).collect();
+        bytes.truncate(self.pages.div_ceil(8) as usize);
+        if self.pages % 8 != 0 {
+            if let Some(last) = bytes.last_mut() {
+                *last &= (1 << (self.pages % 8)) - 1;
+            }
+        }
+        bytes
+    }
+}
+
+#[derive(Debug)]
+struct Tracked {
+    memory: Vec<Mapping>,
+    dirty: DirtyBitmap,
+    /// The snapshot the pages were last collected for, if they were not
+    /// collected for anything else since
+    snapshot: Option<String>,
+}
+
+/// The VMs whose dirty pages are tracked, see the module documentation
+#[derive(Debug, Default)]
+pub(crate) struct DirtyPageTracker {
+    tracked: HashMap<VmID, Tracked>,
+}
+
+impl DirtyPageTracker {
+    /// The VMs tracked, whose vCPUs have to be paused while collecting
+    pub fn tracked(&self) -> impl Iterator<Item = &VmID> {
+        self.tracked.keys()
+    }
+
+    /// Starts tracking the guest memory `memory` of the VM `id`, all of it
+    /// is reported dirty by the first collection.
+    pub fn track(&mut self, id: &VmID, memory: &[Mapping]) {
+        let _ = self.tracked.entry(id.clone()).or_insert_with(|| {
+            let pages = memory.iter().map(Mapping::pages).sum();
+            Tracked {
+                memory: memory.to_vec(),
+                dirty: DirtyBitmap::new(pages, true),
+                snapshot: None,
+            }
+        });
+    }
+
+    /// Stops tracking the VM `id`, e.g. when its memory is unmapped
+    pub fn untrack(&mut self, id: &VmID) {
+        let _ = self.tracked.remove(id);
+    }
+
+    /// Returns the pages the VM `id` wrote since the previous collection.
+    pub fn collect(&mut self, id: &VmID) -> io::Result<DirtyBitmap> {
+        self.collect_for(id, None)
+    }
+
+    /// Collects the pages of the VM `id` for its snapshot `snapshot`, only
+    /// returning them if the previous collection was for its snapshot
+    /// `parent`, i.e. they are exactly the pages written since the parent.
+    pub fn collect_for_snapshot(
+        &mut self,
+        id: &VmID,
+        snapshot: &str,
+        parent: Option<&str>,
+    ) -> io::Result<Option<DirtyBitmap>> {
+        let since_parent = parent.is_some_and(|parent| {
+            self.tracked
+                .get(id)
+                .is_some_and(|t| t.snapshot.as_deref() == Some(parent))
+        });
+        let dirty = self.collect_for(id, Some(snapshot.into()))?;
+        Ok(since_parent.then_some(dirty))
+    }
+
+    fn collect_for(
+        &mut self,
+        id: &VmID,
+        snapshot: Option<String>,
+    ) -> io::Result<DirtyBitmap> {
+        if !self.tracked.contains_key(id) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("dirty pages of vm '{id}' are not tracked"),
+            ));
+        }
+
+        let pagemap = File::open("/proc/self/pagemap")?;
+        for tracked in self.tracked.values_mut() {
+            harvest(&pagemap, tracked)?;
+        }
+        fs::write("/proc/self/clear_refs", "4")?;
+
+        let tracked = self.tracked.get_mut(id).expect("tracked vm");
+        tracked.snapshot = snapshot;
+        let clean = DirtyBitmap::new(tracked.dirty.pages, false);
+        Ok(std::mem::replace(&mut tracked.dirty, clean))
+    }
+}
+
+/// Adds the pages of `tracked` with their soft-dirty bit set to its bitmap
+fn harvest(pagemap: &File, tracked: &mut Tracked) -> io::Result<()> {
+    let mut page = 0;
+    let mut buf = vec![0u8; (PAGEMAP_BATCH * 8) as usize];
+    for mapping in &tracked.memory {
+        let first = mapping.start / PAGE_SIZE;
+        let mut done = 0;
+        while done < mapping.pages() {
+            let batch = (mapping.pages() - done).min(PAGEMAP_BATCH);
+            let buf = &mut buf[..(batch * 8) as usize];
+            pagemap.read_exact_at(buf, (first + done) * 8)?;
+            for (i, entry) in buf.chunks_exact(8).enumerate() {
+                let entry = u64::from_ne_bytes(entry.try_into().expect("u64"));
+                if entry & PAGEMAP_SOFT_DIRTY != 0 {
+                    tracked.dirty.set(page + done + i as u64);
+                }
+            }
+            done += batch;
+        }
+        page += mapping.pages();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPS: &str = "\
+55d4c8a00000-55d4c8a21000 rw-p 00000000 00:00 0                          [heap]
+7f0000000000-7f0040000000 rw-p 00000000 00:00 0
+7f0040000000-7f0080000000 rw-s 00000000 00:01 1234                       /memfd:ch_ram (deleted)
+7f0080000000-7f0080001000 r--p 00000000 00:00 0
+7f0090000000-7f0090001000 rw-p 00000000 08:01 42                         /usr/lib/libc.so.6
+";
+
+    #[test]
+    fn parses_writable_anonymous_mappings() {
+        let mappings = parse_maps(MAPS);
+        assert_eq!(mappings.len(), 2);
+        assert!(mappings.contains(&Mapping {
+            start: 0x7f00_4000_0000,
+            end: 0x7f00_8000_0000,
+        }));
+        // Named mappings such as the heap are not guest memory
+        assert!(!mappings.iter().any(|m| m.start == 0x55d4_c8a0_0000));
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn finds_guest_memory_regions() {
+        let low = Mapping { start: 0x7f01_0000_0000, end: 0x7f01_c000_0000 };
+        let high = Mapping { start: 0x7f00_0000_0000, end: 0x7f00_4000_0000 };
+        let stack = Mapping { start: 0x7e00_0000_0000, end: 0x7e00_0080_0000 };
+
+        let memory = find_guest_memory([stack, high, low].into_iter(), 4 << 30);
+        assert_eq!(memory, Some(vec![low, high]));
+
+        let memory = find_guest_memory([stack].into_iter(), 4 << 30);
+        assert_eq!(memory, None);
+    }
+
+    #[test]
+    fn bitmap_bytes_cover_pages() {
+        let mut bitmap = DirtyBitmap::new(10, false);
+        bitmap.set(0);
+        bitmap.set(9);
+        assert_eq!(bitmap.to_bytes(), vec![0b1, 0b10]);
+
+        assert_eq!(DirtyBitmap::new(10, true).to_bytes(), vec![0xff, 0b11]);
+    }
+}
@@ -13,11 +13,14 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use std::path::PathBuf;
 use thiserror::Error;
 use tonic::Status;
 use tracing::error;
+use validation::ValidationError;
 
 use super::virtual_machine::VmID;
+use crate::{cordon::NodeCordoned, snapshots::SnapshotStoreError};
 
 pub(crate) type Result<T> = std::result::Result<T, VmServiceError>;
 
@@ -25,16 +28,104 @@ pub(crate) type Result<T> = std::result::Result<T, VmServiceError>;
 pub(crate) enum VmServiceError {
     #[error("vm '{id}' could not be allocated: {source}")]
     FailedToAllocateError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be snapshotted: {source}")]
+    FailedToSnapshotError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be restored: {source}")]
+    FailedToRestoreError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be migrated: {source}")]
+    FailedToMigrateError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be relocated: {source}")]
+    FailedToRelocateError { id: VmID, source: anyhow::Error },
+    #[error(transparent)]
+    SnapshotStoreError(#[from] SnapshotStoreError),
+    #[error("vm '{id}' could not be imported: {source}")]
+    FailedToImportError { id: VmID, source: anyhow::Error },
     #[error("vm '{id}' could not be freed: {source}")]
     FailedToFreeError { id: VmID, source: anyhow::Error },
     #[error("vm '{id}' could not be started: {source}")]
     FailedToStartError { id: VmID, source: anyhow::Error },
     #[error("vm '{id}' could not be stopped: {source}")]
     FailedToStopError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be paused: {source}")]
+    FailedToPauseError { id: VmID, source: anyhow::Error },
+    #[error("dirty pages of vm '{id}' could not be collected: {source}")]
+    FailedToCollectDirtyPagesError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be resumed: {source}")]
+    FailedToResumeError { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' could not be exported: {source}")]
+    FailedToExportError { id: VmID, source: anyhow::Error },
+    #[error("attestation report of vm '{id}' could not be produced: {source}")]
+    FailedToAttestError { id: VmID, source: anyhow::Error },
+    #[error(
+        "attestation report of this guest could not be produced: {source}"
+    )]
+    FailedToAttestGuestError { source: std::io::Error },
+    #[error("vm '{id}' is not a confidential guest")]
+    NotConfidential { id: VmID },
+    #[error("report data is longer than {max} bytes")]
+    InvalidReportData { max: usize },
+    #[error("vm '{id}' console could not be attached: {source}")]
+    FailedToAttachConsoleError { id: VmID, source: anyhow::Error },
+    #[error("checkpoint '{archive}' could not be written: {source}")]
+    FailedToCheckpointError { archive: String, source: anyhow::Error },
+    #[error("checkpoint '{archive}' could not be restored: {source}")]
+    FailedToRestoreCheckpointError { archive: String, source: anyhow::Error },
+    #[error("vm '{id}' would be rejected by the VMM: {source}")]
+    InvalidVmConfig { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' kernel cannot be booted: {source}")]
+    InvalidKernel { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' device '{device}' cannot be passed through: {source}")]
+    InvalidDevice { id: VmID, device: String, source: anyhow::Error },
+    #[error("vm '{id}' cannot be launched with {technology}: not supported by this build of auraed")]
+    UnsupportedConfidentialComputing { id: VmID, technology: String },
+    #[error("vm '{id}' requires nested virtualization, which is disabled on this host")]
+    NestedVirtualizationUnavailable { id: VmID },
+    #[error("'{reference}' is not a valid image reference")]
+    InvalidImageReference { reference: String },
+    #[error("'{address}' is not a valid node address")]
+    InvalidNodeAddress { address: String },
+    #[error("vm '{id}' has an invalid vcpu affinity: {source}")]
+    InvalidVcpuAffinity { id: VmID, source: anyhow::Error },
+    #[error("vm '{id}' has an invalid cpu topology: {reason}")]
+    InvalidCpuTopology { id: VmID, reason: String },
+    #[error("vm '{id}' has an invalid cpuid mask: {reason}")]
+    InvalidCpuidMask { id: VmID, reason: String },
+    #[error("vm '{id}' has an invalid uuid '{uuid}'")]
+    InvalidUuid { id: VmID, uuid: String },
+    #[error("vm '{id}' has an invalid network interface: {reason}")]
+    InvalidNetworkInterface { id: VmID, reason: String },
+    #[error("vm '{id}' has an invalid guest memory config: {reason}")]
+    InvalidGuestMemory { id: VmID, reason: String },
+    #[error("vm '{id}' needs {needed} huge pages of {size} bytes, but only {free} are free on this host")]
+    InsufficientHugepages { id: VmID, size: u64, needed: u64, free: u64 },
+    #[error("vm '{id}' needs {needed} bytes of memory, but only {available} are available on this host")]
+    InsufficientMemory { id: VmID, needed: u64, available: u64 },
+    #[error("vm '{id}' cannot run on this host: /dev/kvm cannot be opened")]
+    KvmUnavailable { id: VmID },
+    #[error("vm '{id}' already exists")]
+    VmExists { id: VmID },
+    #[error("vm '{id}' does not support the {policy} shutdown policy")]
+    UnsupportedShutdownPolicy { id: VmID, policy: String },
     #[error("vm config has no machine specified")]
     MissingMachineConfig,
     #[error("vm '{id}' config has no root drive specified")]
     MissingRootDrive { id: VmID },
+    #[error("vm '{id}' has no console history")]
+    NoConsoleHistory { id: VmID },
+    #[error("console stream has no request naming the vm")]
+    MissingConsoleRequest,
+    #[error("migration tunnel has no request naming the vm")]
+    MissingMigrationTunnelRequest,
+    #[error("vm '{id}' is not being received")]
+    NotReceivingMigration { id: VmID },
+    #[error("checkpoint '{archive}' is not below '{}'", dir.display())]
+    InvalidArchivePath { archive: String, dir: PathBuf },
+    #[error("node checkpoints are not available")]
+    NodeCheckpointsUnavailable,
+    #[error(transparent)]
+    Cordoned(#[from] NodeCordoned),
+    #[error(transparent)]
+    ValidationError(#[from] ValidationError),
 }
 
 impl From<VmServiceError> for Status {
@@ -45,11 +136,77 @@ impl From<VmServiceError> for Status {
             VmServiceError::FailedToAllocateError { .. }
             | VmServiceError::FailedToFreeError { .. }
             | VmServiceError::FailedToStartError { .. }
-            | VmServiceError::FailedToStopError { .. } => Status::internal(msg),
-            VmServiceError::MissingMachineConfig { .. }
-            | VmServiceError::MissingRootDrive { .. } => {
-                Status::failed_precondition(msg)
+            | VmServiceError::FailedToStopError { .. }
+            | VmServiceError::FailedToPauseError { .. }
+            | VmServiceError::FailedToResumeError { .. }
+            | VmServiceError::FailedToCollectDirtyPagesError { .. }
+            | VmServiceError::FailedToSnapshotError { .. }
+            | VmServiceError::FailedToRestoreError { .. }
+            | VmServiceError::FailedToMigrateError { .. }
+            | VmServiceError::FailedToRelocateError { .. }
+            | VmServiceError::FailedToExportError { .. }
+            | VmServiceError::FailedToAttachConsoleError { .. }
+            | VmServiceError::FailedToAttestError { .. }
+            | VmServiceError::FailedToCheckpointError { .. }
+            | VmServiceError::FailedToRestoreCheckpointError { .. } => {
+                Status::internal(msg)
             }
+            VmServiceError::FailedToAttestGuestError { source } => {
+                // outside of a confidential guest there is no report
+                if source.kind() == std::io::ErrorKind::NotFound {
+                    Status::failed_precondition(msg)
+                } else {
+                    Status::internal(msg)
+                }
+            }
+            VmServiceError::VmExists { .. } => Status::already_exists(msg),
+            VmServiceError::NoConsoleHistory { .. } => Status::not_found(msg),
+            VmServiceError::SnapshotStoreError(e) => match e {
+                SnapshotStoreError::AlreadyExists { .. } => {
+                    Status::already_exists(msg)
+                }
+                SnapshotStoreError::InvalidId { .. } => {
+                    Status::invalid_argument(msg)
+                }
+                SnapshotStoreError::NotFound { .. } => Status::not_found(msg),
+                SnapshotStoreError::HasChildren { .. } => {
+                    Status::failed_precondition(msg)
+                }
+                SnapshotStoreError::MissingPage { .. }
+                | SnapshotStoreError::InvalidManifest(_)
+                | SnapshotStoreError::IO(_) => Status::internal(msg),
+            },
+            VmServiceError::InvalidDevice { .. }
+            | VmServiceError::InvalidKernel { .. }
+            | VmServiceError::InvalidVmConfig { .. }
+            | VmServiceError::InvalidImageReference { .. }
+            | VmServiceError::InvalidNodeAddress { .. }
+            | VmServiceError::InvalidVcpuAffinity { .. }
+            | VmServiceError::InvalidUuid { .. }
+            | VmServiceError::InvalidNetworkInterface { .. }
+            | VmServiceError::InvalidCpuTopology { .. }
+            | VmServiceError::InvalidCpuidMask { .. }
+            | VmServiceError::InvalidGuestMemory { .. }
+            | VmServiceError::UnsupportedShutdownPolicy { .. }
+            | VmServiceError::MissingConsoleRequest
+            | VmServiceError::MissingMigrationTunnelRequest
+            | VmServiceError::InvalidArchivePath { .. }
+            | VmServiceError::InvalidReportData { .. }
+            | VmServiceError::ValidationError(_) => {
+                Status::invalid_argument(msg)
+            }
+            VmServiceError::FailedToImportError { .. }
+            | VmServiceError::UnsupportedConfidentialComputing { .. }
+            | VmServiceError::NestedVirtualizationUnavailable { .. }
+            | VmServiceError::InsufficientHugepages { .. }
+            | VmServiceError::InsufficientMemory { .. }
+            | VmServiceError::KvmUnavailable { .. }
+            | VmServiceError::MissingMachineConfig { .. }
+            | VmServiceError::MissingRootDrive { .. }
+            | VmServiceError::NotReceivingMigration { .. }
+            | VmServiceError::NodeCheckpointsUnavailable
+            | VmServiceError::NotConfidential { .. }
+            | VmServiceError::Cordoned(_) => Status::failed_precondition(msg),
         }
     }
 }
@@ -0,0 +1,171 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use crate::images::distribution::{
+    manifest::{OciDescriptor, OciImageManifest, OCI_IMAGE_MEDIA_TYPE},
+    Client, Reference, RegistryAuth, RegistryOperation,
+};
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+use tracing::info;
+
+/// Media type of the config blob of an exported VM drive.
+pub(crate) const DRIVE_CONFIG_MEDIA_TYPE: &str =
+    "application/vnd.aurae.vm.drive.config.v1+json";
+
+/// Media type of an exported VM drive: the raw disk image compressed with zstd.
+pub(crate) const DRIVE_LAYER_MEDIA_TYPE: &str =
+    "application/vnd.aurae.vm.drive.raw.v1+zstd";
+
+/// Config blob of an exported VM drive.
+const DRIVE_CONFIG: &[u8] = b"{}";
+
+/// Package the drive image at `path` as a single layer OCI artifact and push
+/// it to `reference` with `client`, authenticating with `auth`. Returns the
+/// URL of the pushed manifest.
+///
+/// The image is compressed into a file below `staging` first, which is then
+/// uploaded in chunks, so the drive is never held in memory.
+pub(crate) async fn push_drive(
+    client: &Client,
+    auth: &RegistryAuth,
+    path: PathBuf,
+    staging: &Path,
+    reference: &Reference,
+) -> Result<String, anyhow::Error> {
+    info!("Exporting drive {} to {reference}", path.display());
+
+    tokio::fs::create_dir_all(staging).await?;
+    let compressed =
+        staging.join(format!("export-{}.zst", uuid::Uuid::new_v4()));
+    let res = push_compressed(client, auth, path, &compressed, reference).await;
+    let _ = tokio::fs::remove_file(&compressed).await;
+    res
+}
+
+async fn push_compressed(
+    client: &Client,
+    auth: &RegistryAuth,
+    path: PathBuf,
+    compressed: &Path,
+    reference: &Reference,
+) -> Result<String, anyhow::Error> {
+    let layer = {
+        let compressed = compressed.to_owned();
+        tokio::task::spawn_blocking(move || compress(&path, &compressed))
+            .await??
+    };
+    let config = OciDescriptor {
+        media_type: DRIVE_CONFIG_MEDIA_TYPE.into(),
+        digest: format!("sha256:{:x}", Sha256::digest(DRIVE_CONFIG)),
+        size: DRIVE_CONFIG.len() as i64,
+        ..Default::default()
+    };
+
+    client.authenticate(reference, auth, RegistryOperation::Push).await?;
+    client.push_blob(reference, &config.digest, DRIVE_CONFIG).await?;
+    let file = tokio::fs::File::open(compressed).await?;
+    client.push_blob(reference, &layer.digest, file).await?;
+
+    let manifest = OciImageManifest {
+        media_type: Some(OCI_IMAGE_MEDIA_TYPE.into()),
+        config,
+        layers: vec![layer],
+        ..Default::default()
+    };
+    Ok(client.push_manifest(reference, &manifest).await?)
+}
+
+/// Compress the drive image at `path` into `dest`, returning the descriptor
+/// of the compressed layer.
+fn compress(path: &Path, dest: &Path) -> Result<OciDescriptor, anyhow::Error> {
+    let mut drive = File::open(path).with_context(|| {
+        format!("Failed to open drive image {}", path.display())
+    })?;
+    let out = HashingWriter {
+        inner: File::create(dest)?,
+        hasher: Sha256::new(),
+        written: 0,
+    };
+
+    let mut encoder = zstd::stream::Encoder::new(out, 0)?;
+    let _ = io::copy(&mut drive, &mut encoder).with_context(|| {
+        format!("Failed to compress drive image {}", path.display())
+    })?;
+    let mut out = encoder.finish()?;
+    out.inner.sync_all()?;
+
+    Ok(OciDescriptor {
+        media_type: DRIVE_LAYER_MEDIA_TYPE.into(),
+        digest: format!("sha256:{:x}", out.hasher.finalize()),
+        size: out.written as i64,
+        ..Default::default()
+    })
+}
+
+/// Hashes and counts the bytes written through it.
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    written: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn compressed_layer_describes_the_file_written() {
+        let scratch = tempfile::tempdir().expect("scratch dir");
+        let drive = scratch.path().join("rootfs.raw");
+        let dest = scratch.path().join("rootfs.zst");
+        let contents: Vec<u8> = (0..1 << 20).map(|i| (i % 251) as u8).collect();
+        std::fs::write(&drive, &contents).expect("write drive");
+
+        let layer = compress(&drive, &dest).expect("compress");
+        let compressed = std::fs::read(&dest).expect("read layer");
+        assert_eq!(layer.size, compressed.len() as i64);
+        assert_eq!(
+            layer.digest,
+            format!("sha256:{:x}", Sha256::digest(&compressed))
+        );
+
+        let mut decompressed = vec![];
+        let _ = zstd::stream::Decoder::new(&compressed[..])
+            .expect("decoder")
+            .read_to_end(&mut decompressed)
+            .expect("decompress");
+        assert_eq!(decompressed, contents);
+    }
+}
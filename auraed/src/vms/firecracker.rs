@@ -0,0 +1,333 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Import of VMs defined using Firecracker conventions.
+//!
+//! Firecracker VMs are described by the JSON file passed to
+//! `firecracker --config-file`. The kernel, drives and TAP devices it refers
+//! to can be used as-is by the Cloud Hypervisor VMM, but a few guest
+//! conventions differ and are translated here:
+//!
+//! * Firecracker has no PCI bus and defaults to `pci=off`, while virtio
+//!   devices are attached over PCI in aurae VMs.
+//! * The Firecracker serial console (`ttyS0`) is replaced by the virtio
+//!   console (`hvc0`).
+//! * Firecracker appends `root=/dev/vda` for the root device itself.
+//!
+//! Firecracker snapshots (`snapshot_path`/`mem_file_path`) store vCPU and
+//! device state in a Firecracker specific format and can not be restored.
+//! Guests need to be booted from their configuration instead.
+
+use anyhow::anyhow;
+use net_util::MacAddr;
+use serde::Deserialize;
+use std::{
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
+
+use super::virtual_machine::{
+    CpuTopology, GuestMemorySpec, MountSpec, NetSpec, VmSpec,
+};
+
+/// Boot arguments Firecracker uses if none are configured, minus the ones
+/// that only apply to Firecracker's machine model.
+const DEFAULT_BOOT_ARGS: &str = "reboot=k panic=1";
+
+/// Boot arguments that only apply to Firecracker's machine model.
+const DROPPED_BOOT_ARGS: &[&str] = &["pci=off", "8250.nr_uarts=0"];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct FirecrackerConfig {
+    boot_source: BootSource,
+    #[serde(default)]
+    drives: Vec<Drive>,
+    machine_config: MachineConfig,
+    #[serde(default)]
+    network_interfaces: Vec<NetworkInterface>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BootSource {
+    kernel_image_path: PathBuf,
+    boot_args: Option<String>,
+    initrd_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Drive {
+    path_on_host: PathBuf,
+    is_root_device: bool,
+    #[serde(default)]
+    is_read_only: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct MachineConfig {
+    vcpu_count: u32,
+    mem_size_mib: u32,
+    /// "None" or "2M"
+    #[serde(default)]
+    huge_pages: Option<String>,
+    #[serde(default)]
+    smt: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct NetworkInterface {
+    host_dev_name: String,
+    guest_mac: Option<String>,
+}
+
+impl FirecrackerConfig {
+    /// Read a Firecracker configuration file
+    pub fn from_file(path: &Path) -> Result<Self, anyhow::Error> {
+        let contents = std::fs::read(path)
+            .map_err(|e| anyhow!("Failed to read {}: {e}", path.display()))?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+}
+
+impl TryFrom<FirecrackerConfig> for VmSpec {
+    type Error = anyhow::Error;
+
+    fn try_from(config: FirecrackerConfig) -> Result<Self, Self::Error> {
+        // The root device is always attached first, so it shows up as vda
+        let mut drives = config.drives;
+        drives.sort_by_key(|d| !d.is_root_device);
+        let root = drives.first().filter(|d| d.is_root_device);
+
+        let mut kernel_args: Vec<String> = config
+            .boot_source
+            .boot_args
+            .as_deref()
+            .unwrap_or(DEFAULT_BOOT_ARGS)
+            .split_whitespace()
+            .filter(|arg| !DROPPED_BOOT_ARGS.contains(arg))
+            .map(|arg| match arg {
+                "console=ttyS0" => "console=hvc0".to_string(),
+                _ => arg.to_string(),
+            })
+            .collect();
+        if !kernel_args.iter().any(|arg| arg.starts_with("console=")) {
+            kernel_args.push("console=hvc0".into());
+        }
+        if let Some(root) = root {
+            if !kernel_args.iter().any(|arg| arg.starts_with("root=")) {
+                kernel_args.push("root=/dev/vda".into());
+                kernel_args
+                    .push(if root.is_read_only { "ro" } else { "rw" }.into());
+            }
+        }
+
+        let huge_pages = match config.machine_config.huge_pages.as_deref() {
+            None | Some("None") => false,
+            Some("2M") => true,
+            Some(huge_pages) => {
+                return Err(anyhow!("Invalid huge pages '{huge_pages}'"))
+            }
+        };
+
+        // Firecracker presents a single socket, with two threads per core
+        // when SMT is enabled
+        let vcpu_count = config.machine_config.vcpu_count;
+        let cpu_topology = if config.machine_config.smt {
+            if vcpu_count % 2 != 0 {
+                return Err(anyhow!("SMT requires an even vcpu_count"));
+            }
+            Some(CpuTopology {
+                sockets: 1,
+                dies_per_socket: 1,
+                cores_per_die: u8::try_from(vcpu_count / 2)?,
+                threads_per_core: 2,
+            })
+        } else {
+            None
+        };
+
+        let net = config
+            .network_interfaces
+            .into_iter()
+            .map(|iface| {
+                let mac = match iface.guest_mac {
+                    Some(mac) => MacAddr::parse_str(&mac)
+                        .map_err(|_| anyhow!("Invalid guest MAC '{mac}'"))?,
+                    None => MacAddr::local_random(),
+                };
+                Ok(NetSpec {
+                    tap: Some(iface.host_dev_name),
+                    ip: Ipv4Addr::UNSPECIFIED,
+                    mask: Ipv4Addr::UNSPECIFIED,
+                    mac,
+                    host_mac: None,
+                    vhost_socket: None,
+                    queue_pairs: None,
+                    mtu: None,
+                })
+            })
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+
+        Ok(VmSpec {
+            memory_size: config.machine_config.mem_size_mib,
+            vcpu_count,
+            kernel_image_path: config.boot_source.kernel_image_path,
+            initramfs_path: config.boot_source.initrd_path,
+            kernel_args,
+            mounts: drives
+                .into_iter()
+                .map(|d| MountSpec {
+                    host_path: d.path_on_host,
+                    read_only: d.is_read_only,
+                })
+                .collect(),
+            net,
+            vfio_devices: vec![],
+            vdpa: vec![],
+            confidential: None,
+            firmware_path: None,
+            serial_socket: None,
+            vsock_socket: None,
+            vcpu_affinity: vec![],
+            cpu_topology,
+            guest_memory: GuestMemorySpec {
+                hugepages: huge_pages,
+                hugepage_size: huge_pages.then_some(2 << 20),
+                prefault: false,
+            },
+            smbios: Default::default(),
+            cpuid_masks: vec![],
+            watchdog: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"{
+        "boot-source": {
+            "kernel_image_path": "/srv/vmlinux",
+            "boot_args": "console=ttyS0 reboot=k panic=1 pci=off"
+        },
+        "drives": [
+            {
+                "drive_id": "data",
+                "path_on_host": "/srv/data.ext4",
+                "is_root_device": false,
+                "is_read_only": true
+            },
+            {
+                "drive_id": "rootfs",
+                "path_on_host": "/srv/rootfs.ext4",
+                "is_root_device": true,
+                "is_read_only": false
+            }
+        ],
+        "machine-config": {
+            "vcpu_count": 2,
+            "mem_size_mib": 1024
+        },
+        "network-interfaces": [
+            {
+                "iface_id": "eth0",
+                "guest_mac": "AA:FC:00:00:00:01",
+                "host_dev_name": "tap0"
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn firecracker_config_translates_to_vm_spec() {
+        let config: FirecrackerConfig =
+            serde_json::from_str(CONFIG).expect("valid config");
+        let spec = VmSpec::try_from(config).expect("valid spec");
+
+        assert_eq!(spec.vcpu_count, 2);
+        assert_eq!(spec.memory_size, 1024);
+        assert_eq!(
+            spec.kernel_args,
+            vec!["console=hvc0", "reboot=k", "panic=1", "root=/dev/vda", "rw"]
+        );
+        assert_eq!(spec.mounts[0].host_path, PathBuf::from("/srv/rootfs.ext4"));
+        assert!(spec.mounts[1].read_only);
+        assert_eq!(spec.net[0].tap.as_deref(), Some("tap0"));
+        assert_eq!(spec.net[0].mac.to_string(), "aa:fc:00:00:00:01");
+    }
+
+    #[test]
+    fn firecracker_config_with_initrd_is_translated() {
+        let config: FirecrackerConfig = serde_json::from_str(
+            r#"{
+                "boot-source": {
+                    "kernel_image_path": "/srv/vmlinux",
+                    "initrd_path": "/srv/initrd"
+                },
+                "machine-config": { "vcpu_count": 1, "mem_size_mib": 128 }
+            }"#,
+        )
+        .expect("valid config");
+        let spec = VmSpec::try_from(config).expect("valid spec");
+
+        assert_eq!(spec.initramfs_path, Some(PathBuf::from("/srv/initrd")));
+        assert!(spec.mounts.is_empty());
+        assert!(!spec.kernel_args.iter().any(|arg| arg.starts_with("root=")));
+    }
+
+    #[test]
+    fn firecracker_config_with_huge_pages_is_translated() {
+        let config: FirecrackerConfig = serde_json::from_str(
+            r#"{
+                "boot-source": { "kernel_image_path": "/srv/vmlinux" },
+                "machine-config": {
+                    "vcpu_count": 1,
+                    "mem_size_mib": 128,
+                    "huge_pages": "2M"
+                }
+            }"#,
+        )
+        .expect("valid config");
+        let spec = VmSpec::try_from(config).expect("valid spec");
+
+        assert!(spec.guest_memory.hugepages);
+        assert_eq!(spec.guest_memory.hugepage_size, Some(2 << 20));
+    }
+
+    #[test]
+    fn firecracker_config_with_smt_is_translated() {
+        let config = |vcpu_count: u32| -> FirecrackerConfig {
+            serde_json::from_str(&format!(
+                r#"{{
+                    "boot-source": {{ "kernel_image_path": "/srv/vmlinux" }},
+                    "machine-config": {{
+                        "vcpu_count": {vcpu_count},
+                        "mem_size_mib": 128,
+                        "smt": true
+                    }}
+                }}"#
+            ))
+            .expect("valid config")
+        };
+
+        let spec = VmSpec::try_from(config(4)).expect("valid spec");
+        let topology = spec.cpu_topology.expect("topology");
+        assert_eq!(topology.cores_per_die, 2);
+        assert_eq!(topology.threads_per_core, 2);
+        assert_eq!(topology.vcpus(), 4);
+
+        assert!(VmSpec::try_from(config(3)).is_err());
+    }
+}
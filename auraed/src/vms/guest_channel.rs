@@ -0,0 +1,349 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Reverse channel from the guests of VMs to the host auraed.
+//!
+//! The VMM attaches a vsock device to VMs that have a [VmSpec::vsock_socket]
+//! and forwards connections the guest opens to port `N` of the host to the
+//! unix socket `<vsock_socket>_N` (see [listener_path]). The host auraed
+//! serves [VmGuestService] on it for [GUEST_EVENTS_PORT], so the auraed
+//! running in the guest can push its events (see [report_to_host]) without
+//! the host polling dozens of guests for them.
+//!
+//! In the other direction, the auraed running in the guest forwards the
+//! connections the host opens to [GUEST_AGENT_PORT] of the guest to its own
+//! API (see [serve_agent]), so the host drives it as the agent of a pod VM
+//! without a network path to the guest.
+//!
+//! [VmSpec::vsock_socket]: super::virtual_machine::VmSpec::vsock_socket
+
+use nix::sys::socket::{
+    accept4, bind, connect, listen, socket, AddressFamily, Backlog, SockFlag,
+    SockType, VsockAddr,
+};
+use proto::vms::{
+    vm_guest_event,
+    vm_guest_service_client::VmGuestServiceClient,
+    vm_guest_service_server::{VmGuestService, VmGuestServiceServer},
+    VmGuestEvent, VmGuestMetrics, VmGuestOom, VmGuestReady,
+    VmGuestServicePushResponse, VmServiceGuestEventsResponse,
+};
+use std::{
+    collections::HashMap,
+    net::Ipv6Addr,
+    os::fd::{AsRawFd, FromRawFd, OwnedFd},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    io::unix::AsyncFd,
+    net::{TcpStream, UnixListener, UnixStream},
+    sync::{broadcast, mpsc},
+    task::AbortHandle,
+};
+use tokio_stream::wrappers::{ReceiverStream, UnixListenerStream};
+use tonic::{
+    transport::{Endpoint, Server, Uri},
+    Request, Response, Status, Streaming,
+};
+use tower::service_fn;
+use tracing::{info, warn};
+
+use super::virtual_machine::VmID;
+use crate::{cells::CellService, logging::get_timestamp_sec};
+
+/// Port of the host the guests push their events to
+pub(crate) const GUEST_EVENTS_PORT: u32 = 1025;
+/// Port of the guest the host reaches the API of the auraed in the guest on
+pub(crate) const GUEST_AGENT_PORT: u32 = 1024;
+/// Context ID of the guest end of the vsock device of every VM, each VM has
+/// a vsock device of its own
+pub(crate) const GUEST_CID: u32 = 3;
+/// How often the guest pushes its utilization and the OOM kills since the
+/// previous push
+const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+/// How long the guest waits before it connects to the host again
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Path of the unix socket the VMM forwards the connections of the guest to
+/// [GUEST_EVENTS_PORT] of the host to, for the vsock device `socket`.
+pub(crate) fn listener_path(socket: &Path) -> PathBuf {
+    let mut path = socket.as_os_str().to_owned();
+    path.push(format!("_{GUEST_EVENTS_PORT}"));
+    PathBuf::from(path)
+}
+
+/// Serves [VmGuestService] to the guest of the VM `vm_id` and broadcasts the
+/// events it pushes to `events`, until the returned handle is aborted.
+pub(crate) fn listen(
+    vm_id: VmID,
+    socket: &Path,
+    events: broadcast::Sender<VmServiceGuestEventsResponse>,
+) -> std::io::Result<AbortHandle> {
+    let path = listener_path(socket);
+    // left behind by a previous auraed
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(&path)?;
+
+    let guest_events = GuestEvents { vm_id: vm_id.clone(), events };
+    let handle = tokio::spawn(async move {
+        let served = Server::builder()
+            .add_service(VmGuestServiceServer::new(guest_events))
+            .serve_with_incoming(UnixListenerStream::new(listener))
+            .await;
+        if let Err(e) = served {
+            warn!("Stopped serving the guest channel of vm '{vm_id}': {e}");
+        }
+    });
+    Ok(handle.abort_handle())
+}
+
+/// [VmGuestService] of the guest of one VM
+#[derive(Debug, Clone)]
+struct GuestEvents {
+    vm_id: VmID,
+    events: broadcast::Sender<VmServiceGuestEventsResponse>,
+}
+
+#[tonic::async_trait]
+impl VmGuestService for GuestEvents {
+    async fn push(
+        &self,
+        request: Request<Streaming<VmGuestEvent>>,
+    ) -> Result<Response<VmGuestServicePushResponse>, Status> {
+        let mut events = request.into_inner();
+        while let Some(event) = events.message().await? {
+            if let Some(vm_guest_event::Event::Ready(ready)) = &event.event {
+                info!(
+                    "Guest of vm '{}' is ready, running auraed {}",
+                    self.vm_id, ready.auraed_version
+                );
+            }
+            // nobody may be subscribed
+            let _ = self.events.send(VmServiceGuestEventsResponse {
+                vm_id: self.vm_id.to_string(),
+                timestamp: get_timestamp_sec(),
+                event: Some(event),
+                ..Default::default()
+            });
+        }
+        Ok(Response::new(VmGuestServicePushResponse {}))
+    }
+}
+
+/// Pushes the events of the auraed running in the guest of a VM to the host,
+/// reconnecting whenever the connection is lost. The utilization of the
+/// guest is taken from `cell_service`.
+pub(crate) fn report_to_host(cell_service: CellService) {
+    let _ignored = tokio::spawn(async move {
+        loop {
+            if let Err(e) = push(&cell_service).await {
+                warn!("Failed to push events to the host: {e}");
+            }
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    });
+}
+
+/// Pushes the events of the guest over one connection to the host, until
+/// the connection is lost.
+async fn push(cell_service: &CellService) -> anyhow::Result<()> {
+    // The URI is not used, the connector always connects to the host
+    let channel = Endpoint::from_static("http://[::]:50051")
+        .connect_with_connector(service_fn(|_: Uri| {
+            connect_host(GUEST_EVENTS_PORT)
+        }))
+        .await?;
+    let mut client = VmGuestServiceClient::new(channel);
+
+    let (tx, rx) = mpsc::channel(4);
+    let cell_service = cell_service.clone();
+    let producer = tokio::spawn(async move {
+        let ready = vm_guest_event::Event::Ready(VmGuestReady {
+            auraed_version: env!("CARGO_PKG_VERSION").into(),
+        });
+        if tx.send(VmGuestEvent { event: Some(ready) }).await.is_err() {
+            return;
+        }
+
+        // Only the kills since connecting are pushed
+        let mut oom_kills = OomKills::default();
+        let _ = oom_kills.since(&cell_service).await;
+        let mut interval = tokio::time::interval(METRICS_INTERVAL);
+        loop {
+            let _ = interval.tick().await;
+            for oom in oom_kills.since(&cell_service).await {
+                let oom = vm_guest_event::Event::Oom(oom);
+                if tx.send(VmGuestEvent { event: Some(oom) }).await.is_err() {
+                    return;
+                }
+            }
+            let Some(utilization) = cell_service.node_utilization().await
+            else {
+                continue;
+            };
+            let metrics = vm_guest_event::Event::Metrics(VmGuestMetrics {
+                cpu_percent: utilization.cpu_percent,
+                memory_bytes: utilization.memory_bytes,
+            });
+            if tx.send(VmGuestEvent { event: Some(metrics) }).await.is_err() {
+                // the connection is gone
+                break;
+            }
+        }
+    });
+
+    let pushed = client.push(ReceiverStream::new(rx)).await;
+    producer.abort();
+    let _ = pushed?;
+    Ok(())
+}
+
+/// The OOM kills of the guest counted at the previous push, to push the
+/// ones since.
+#[derive(Debug, Default)]
+struct OomKills {
+    /// Of the whole guest, including the cells
+    node: u64,
+    /// Of each top-level cell
+    cells: HashMap<String, u64>,
+}
+
+impl OomKills {
+    /// The processes killed since the previous call, by top-level cell.
+    /// Kills outside of cells, and in cells deleted since the previous call,
+    /// are pushed with an empty cell name.
+    async fn since(&mut self, cell_service: &CellService) -> Vec<VmGuestOom> {
+        let cells = cell_service.oom_kills().await;
+        let node = node_oom_kills().unwrap_or(self.node);
+
+        let mut ooms: Vec<VmGuestOom> = cells
+            .iter()
+            .filter_map(|(cell_name, kills)| {
+                // cells created since the previous call had no kills then
+                let seen = self.cells.get(cell_name).copied().unwrap_or(0);
+                let kills = kills.saturating_sub(seen);
+                (kills > 0)
+                    .then(|| VmGuestOom { cell_name: cell_name.clone(), kills })
+            })
+            .collect();
+        let in_cells: u64 = ooms.iter().map(|oom| oom.kills).sum();
+        let outside = node.saturating_sub(self.node).saturating_sub(in_cells);
+        if outside > 0 {
+            ooms.push(VmGuestOom { cell_name: String::new(), kills: outside });
+        }
+
+        self.node = node;
+        self.cells = cells;
+        ooms
+    }
+}
+
+/// How often the OOM killer killed a process of the guest, from
+/// /proc/vmstat.
+fn node_oom_kills() -> Option<u64> {
+    std::fs::read_to_string("/proc/vmstat")
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+}
+
+/// Forwards the connections the host opens to [GUEST_AGENT_PORT] of the
+/// guest to the API the auraed running in the guest serves on `api_port`,
+/// over the loopback interface. The API authenticates the host as it does
+/// any other client.
+pub(crate) fn serve_agent(api_port: u16) -> std::io::Result<()> {
+    let fd = socket(
+        AddressFamily::Vsock,
+        SockType::Stream,
+        SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+        None,
+    )?;
+    bind(
+        fd.as_raw_fd(),
+        &VsockAddr::new(libc::VMADDR_CID_ANY, GUEST_AGENT_PORT),
+    )?;
+    listen(&fd, Backlog::MAXCONN)?;
+    // Tokio has no vsock listeners, and its unix listener rejects the
+    // addresses of the accepted connections
+    let listener = AsyncFd::new(fd)?;
+
+    let _ignored = tokio::spawn(async move {
+        loop {
+            let accepted = match listener.readable().await {
+                Ok(mut guard) => guard.try_io(|fd| {
+                    Ok(accept4(
+                        fd.as_raw_fd(),
+                        SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+                    )?)
+                }),
+                Err(e) => Ok(Err(e)),
+            };
+            let fd = match accepted {
+                Ok(Ok(fd)) => fd,
+                Ok(Err(e)) => {
+                    warn!("Failed to accept a connection of the host: {e}");
+                    continue;
+                }
+                // not readable after all
+                Err(_would_block) => continue,
+            };
+            // SAFETY: accept4 returned a new descriptor nothing else owns
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            let _ignored = tokio::spawn(async move {
+                if let Err(e) = forward_to_api(fd, api_port).await {
+                    warn!("Failed to forward a connection of the host: {e}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Copies between the connection of the host `fd` and a new connection to
+/// the API on `api_port`, until either is closed.
+async fn forward_to_api(fd: OwnedFd, api_port: u16) -> std::io::Result<()> {
+    // driven as a unix stream, see connect_host
+    let mut host =
+        UnixStream::from_std(std::os::unix::net::UnixStream::from(fd))?;
+    let mut api = TcpStream::connect((Ipv6Addr::LOCALHOST, api_port)).await?;
+    let _ = tokio::io::copy_bidirectional(&mut host, &mut api).await?;
+    Ok(())
+}
+
+/// Connects to `port` of the host over vsock. Tokio has no vsock streams,
+/// the connected socket is driven as a unix stream instead, which only
+/// relies on the socket being a non-blocking stream socket.
+async fn connect_host(port: u32) -> std::io::Result<UnixStream> {
+    let fd = tokio::task::spawn_blocking(move || {
+        let fd = socket(
+            AddressFamily::Vsock,
+            SockType::Stream,
+            SockFlag::SOCK_CLOEXEC,
+            None,
+        )?;
+        connect(fd.as_raw_fd(), &VsockAddr::new(libc::VMADDR_CID_HOST, port))?;
+        Ok::<_, nix::Error>(fd)
+    })
+    .await?
+    .map_err(std::io::Error::from)?;
+
+    let stream = std::os::unix::net::UnixStream::from(fd);
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
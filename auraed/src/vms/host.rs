@@ -0,0 +1,89 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Probes for host capabilities VMs may depend on.
+
+use std::path::Path;
+
+/// Module parameters controlling nested virtualization, per vendor.
+const NESTED_PARAMETERS: [&str; 2] = [
+    "/sys/module/kvm_intel/parameters/nested",
+    "/sys/module/kvm_amd/parameters/nested",
+];
+
+/// Whether the loaded KVM module lets guests use VMX/SVM themselves.
+pub(crate) fn nested_virtualization_enabled() -> bool {
+    NESTED_PARAMETERS.iter().any(|p| is_enabled(Path::new(p)))
+}
+
+/// Whether KVM is available to auraed, which all VMs run on.
+pub(crate) fn kvm_available() -> bool {
+    std::fs::OpenOptions::new().read(true).write(true).open("/dev/kvm").is_ok()
+}
+
+/// Whether processes may set up io_uring instances, which the block devices
+/// of VMs use to keep many requests in flight. Kernels without the
+/// `io_uring_disabled` sysctl (before 6.6) do not restrict io_uring.
+pub(crate) fn io_uring_enabled() -> bool {
+    std::fs::read_to_string("/proc/sys/kernel/io_uring_disabled")
+        .map_or(true, |v| v.trim() == "0")
+}
+
+/// The default huge page size of the host in bytes.
+pub(crate) fn default_hugepage_size() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("Hugepagesize:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb << 10)
+}
+
+/// The memory of the host available to new workloads in bytes.
+pub(crate) fn mem_available() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kb << 10)
+}
+
+/// The number of huge pages of `size` bytes reserved on the host and not in
+/// use, or 0 if the host does not support the size.
+pub(crate) fn free_hugepages(size: u64) -> u64 {
+    let path = format!(
+        "/sys/kernel/mm/hugepages/hugepages-{}kB/free_hugepages",
+        size >> 10
+    );
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+/// Module parameters are either `Y`/`N` or `1`/`0` depending on the vendor.
+fn is_enabled(parameter: &Path) -> bool {
+    std::fs::read_to_string(parameter)
+        .is_ok_and(|v| matches!(v.trim(), "Y" | "y" | "1"))
+}
@@ -0,0 +1,183 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! IP address management for the network interfaces auraed provisions for
+//! virtual machines.
+//!
+//! Each interface gets its own point-to-point /30 subnet of the pool: the host
+//! end of its TAP device takes the first address of the subnet, the guest the
+//! second. The host routes between the subnets and masquerades the pool, so
+//! guests neither see each other's traffic nor need a bridge.
+
+use super::virtual_machine::VmID;
+use ipnetwork::Ipv4Network;
+use std::{
+    collections::{HashMap, HashSet},
+    net::Ipv4Addr,
+};
+
+/// The addresses VMs are provisioned from, room for 16384 interfaces
+pub(crate) fn default_pool() -> Ipv4Network {
+    Ipv4Network::new(Ipv4Addr::new(10, 249, 0, 0), 16).expect("valid network")
+}
+
+/// The addressing of one provisioned interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Lease {
+    /// Address of the host end of the TAP device, the gateway of the guest
+    pub host: Ipv4Addr,
+    /// Address of the guest end
+    pub guest: Ipv4Addr,
+}
+
+impl Lease {
+    pub const MASK: Ipv4Addr = Ipv4Addr::new(255, 255, 255, 252);
+
+    /// The kernel argument configuring the guest end statically on boot
+    pub fn kernel_arg(&self, device: &str) -> String {
+        format!("ip={}::{}:{}::{device}:off", self.guest, self.host, Self::MASK)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Ipam {
+    pool: Ipv4Network,
+    /// Indices of the subnets of the pool leased to each VM
+    leases: HashMap<VmID, Vec<u32>>,
+}
+
+impl Ipam {
+    pub fn new(pool: Ipv4Network) -> Self {
+        Self { pool, leases: Default::default() }
+    }
+
+    pub fn pool(&self) -> Ipv4Network {
+        self.pool
+    }
+
+    /// Lease the first free subnet of the pool to the VM `id`
+    pub fn allocate(&mut self, id: &VmID) -> Option<Lease> {
+        let used: HashSet<u32> =
+            self.leases.values().flatten().copied().collect();
+        let index = (0..self.pool.size() / 4).find(|i| !used.contains(i))?;
+        self.leases.entry(id.clone()).or_default().push(index);
+        Some(self.lease(index))
+    }
+
+    /// Record that the VM `id` uses the subnet the host address `host`
+    /// belongs to, e.g. after it was restored from a snapshot. Returns false
+    /// if the address was not provisioned from the pool or is taken.
+    pub fn reserve(&mut self, id: &VmID, host: Ipv4Addr) -> bool {
+        if !self.pool.contains(host) {
+            return false;
+        }
+        let offset = u32::from(host) - u32::from(self.pool.network());
+        if offset % 4 != 1 {
+            return false;
+        }
+        let index = offset / 4;
+        if self.leases.values().flatten().any(|i| *i == index) {
+            return false;
+        }
+        self.leases.entry(id.clone()).or_default().push(index);
+        true
+    }
+
+    /// The subnets leased to the VM `id`, in the order they were leased
+    pub fn leases(&self, id: &VmID) -> Vec<Lease> {
+        self.leases
+            .get(id)
+            .map(|l| l.iter().map(|i| self.lease(*i)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Return the subnets leased to the VM `id` to the pool
+    pub fn release(&mut self, id: &VmID) {
+        let _ = self.leases.remove(id);
+    }
+
+    fn lease(&self, index: u32) -> Lease {
+        let subnet = u32::from(self.pool.network()) + 4 * index;
+        Lease {
+            host: Ipv4Addr::from(subnet + 1),
+            guest: Ipv4Addr::from(subnet + 2),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipam(prefix: u8) -> Ipam {
+        Ipam::new(
+            Ipv4Network::new(Ipv4Addr::new(10, 0, 0, 0), prefix)
+                .expect("network"),
+        )
+    }
+
+    #[test]
+    fn test_allocate_leases_distinct_subnets() {
+        let mut ipam = ipam(29);
+        let a = VmID::new("a");
+        let b = VmID::new("b");
+
+        let lease = ipam.allocate(&a).expect("lease");
+        assert_eq!(lease.host, Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(lease.guest, Ipv4Addr::new(10, 0, 0, 2));
+        let lease = ipam.allocate(&b).expect("lease");
+        assert_eq!(lease.host, Ipv4Addr::new(10, 0, 0, 5));
+        assert!(ipam.allocate(&b).is_none());
+
+        ipam.release(&a);
+        assert_eq!(
+            ipam.allocate(&b).map(|l| l.host),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(ipam.leases(&b).len(), 2);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let mut ipam = ipam(24);
+        let a = VmID::new("a");
+        let b = VmID::new("b");
+
+        assert!(ipam.reserve(&a, Ipv4Addr::new(10, 0, 0, 5)));
+        assert!(!ipam.reserve(&b, Ipv4Addr::new(10, 0, 0, 5)));
+        assert!(!ipam.reserve(&b, Ipv4Addr::new(10, 0, 0, 6)));
+        assert!(!ipam.reserve(&b, Ipv4Addr::new(10, 0, 1, 1)));
+        assert_eq!(
+            ipam.allocate(&b).map(|l| l.host),
+            Some(Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(
+            ipam.allocate(&b).map(|l| l.host),
+            Some(Ipv4Addr::new(10, 0, 0, 9))
+        );
+    }
+
+    #[test]
+    fn test_kernel_arg() {
+        let lease = Lease {
+            host: Ipv4Addr::new(10, 0, 0, 1),
+            guest: Ipv4Addr::new(10, 0, 0, 2),
+        };
+        assert_eq!(
+            lease.kernel_arg("eth0"),
+            "ip=10.0.0.2::10.0.0.1:255.255.255.252::eth0:off"
+        );
+    }
+}
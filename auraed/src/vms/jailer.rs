@@ -0,0 +1,173 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Confines the threads of the VMM of each VM, mirroring the Firecracker
+//! jailer: a seccomp allow-list, a reduced set of capabilities and,
+//! optionally, a chroot entered once the guest resources are set up.
+//!
+//! The VMM runs in the auraed process, so its threads still share the
+//! address space and file descriptors of auraed. The jail narrows the
+//! syscalls and privileges a compromised guest reaches through the VMM, it
+//! does not isolate the VMM like a separate process would.
+
+use anyhow::anyhow;
+use caps::{CapSet, Capability, CapsHashSet};
+use nix::sched::CloneFlags;
+use seccompiler::SeccompAction;
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Sender},
+};
+
+/// The capabilities the VMM threads keep.
+const VMM_CAPABILITIES: [Capability; 4] = [
+    // Opening disk images and sockets not owned by root
+    Capability::CAP_DAC_OVERRIDE,
+    // Creating and configuring TAP devices
+    Capability::CAP_NET_ADMIN,
+    // Pinning guest memory for VFIO devices
+    Capability::CAP_IPC_LOCK,
+    Capability::CAP_SYS_RESOURCE,
+];
+
+/// How the threads of the VMM of each VM are confined.
+#[derive(Debug, Clone)]
+pub struct JailerConfig {
+    /// Applies the seccomp filters of the VMM to its threads. Syscalls
+    /// outside of them fail with EPERM, so a violation cannot kill auraed.
+    pub seccomp: bool,
+    /// Drops every capability of the VMM threads but those needed to set up
+    /// guest resources.
+    pub drop_capabilities: bool,
+    /// Empty directory the VMM threads are chrooted into once their VM
+    /// booted. The VMM cannot reach files outside of it afterwards, so
+    /// chrooted VMs cannot be snapshotted, checkpointed or migrated over
+    /// unix sockets.
+    pub chroot: Option<PathBuf>,
+}
+
+impl Default for JailerConfig {
+    fn default() -> Self {
+        Self { seccomp: true, drop_capabilities: true, chroot: None }
+    }
+}
+
+impl JailerConfig {
+    /// The action the seccomp filters of the VMM take on a violation.
+    pub(crate) fn seccomp_action(&self) -> SeccompAction {
+        if self.seccomp {
+            SeccompAction::Errno(libc::EPERM as u32)
+        } else {
+            SeccompAction::Allow
+        }
+    }
+
+    /// Starts the VMM threads through `start` from a dedicated thread which
+    /// confines itself first, so that the threads of the VMM inherit its
+    /// capabilities and file system root. The seccomp filters are applied
+    /// by the VMM itself (see [JailerConfig::seccomp_action]).
+    pub(crate) fn spawn<T, F>(&self, start: F) -> anyhow::Result<(T, Jail)>
+    where
+        F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let drop_capabilities = self.drop_capabilities;
+        let chroot = self.chroot.clone();
+        let (started_tx, started_rx) = channel();
+
+        let _ = std::thread::Builder::new().name("vmm-jailer".into()).spawn(
+            move || {
+                let started = confine(drop_capabilities, chroot)
+                    .and_then(|jail| Ok((start()?, jail)));
+                let _ = started_tx.send(started);
+            },
+        )?;
+
+        started_rx.recv().map_err(|_| anyhow!("the VMM jailer exited"))?
+    }
+}
+
+/// The chroot of VMM threads that is entered once their VM booted.
+#[derive(Debug, Default)]
+pub(crate) struct Jail {
+    chroot: Option<Sender<Sender<std::io::Result<()>>>>,
+}
+
+impl Jail {
+    /// Chroots the VMM threads, if configured. Called once the guest
+    /// resources are set up, later calls do nothing.
+    pub(crate) fn lock(&mut self) -> anyhow::Result<()> {
+        let Some(chroot) = self.chroot.take() else {
+            return Ok(());
+        };
+        let (done_tx, done_rx) = channel();
+        chroot.send(done_tx).map_err(|_| anyhow!("the VMM jail exited"))?;
+        done_rx
+            .recv()
+            .map_err(|_| anyhow!("the VMM jail exited"))?
+            .map_err(|e| anyhow!("failed to chroot the VMM: {e}"))
+    }
+}
+
+/// Confines the calling thread and the threads it spawns afterwards. The
+/// chroot is entered later by a thread spawned before the capabilities are
+/// dropped, as it needs CAP_SYS_CHROOT, which the VMM threads must not have.
+fn confine(
+    drop_capabilities: bool,
+    chroot: Option<PathBuf>,
+) -> anyhow::Result<Jail> {
+    let mut jail = Jail::default();
+
+    if let Some(dir) = chroot {
+        // The file system root is shared by all threads of auraed unless the
+        // threads of the VMM get their own
+        nix::sched::unshare(CloneFlags::CLONE_FS)?;
+        let (chroot_tx, chroot_rx) = channel::<Sender<std::io::Result<()>>>();
+        let _ = std::thread::Builder::new().name("vmm-chroot".into()).spawn(
+            move || {
+                // Exits without a chroot if the VM is gone before it booted
+                if let Ok(done) = chroot_rx.recv() {
+                    let _ = done.send(enter_chroot(&dir));
+                }
+            },
+        )?;
+        jail.chroot = Some(chroot_tx);
+    }
+
+    if drop_capabilities {
+        let keep: CapsHashSet = VMM_CAPABILITIES
+            .into_iter()
+            .filter(|c| {
+                caps::has_cap(None, CapSet::Permitted, *c).unwrap_or(false)
+            })
+            .collect();
+        // The bounding set can only be reduced while holding CAP_SETPCAP
+        let bounding = caps::read(None, CapSet::Bounding)?;
+        for capability in bounding.difference(&keep) {
+            caps::drop(None, CapSet::Bounding, *capability)?;
+        }
+        caps::clear(None, CapSet::Ambient)?;
+        caps::set(None, CapSet::Inheritable, &CapsHashSet::new())?;
+        caps::set(None, CapSet::Effective, &keep)?;
+        caps::set(None, CapSet::Permitted, &keep)?;
+    }
+
+    Ok(jail)
+}
+
+fn enter_chroot(dir: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::chroot(dir)?;
+    std::env::set_current_dir("/")
+}
@@ -0,0 +1,181 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use anyhow::{bail, Context};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+const ELF_MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const PT_NOTE: u32 = 4;
+/// Note carrying the 32-bit PVH entry point, emitted with CONFIG_PVH.
+const XEN_ELFNOTE_PHYS32_ENTRY: u32 = 18;
+/// Note segments of kernels hold a few small notes, the size in the program
+/// header is not trusted beyond this.
+const MAX_NOTES_SIZE: u64 = 64 * 1024;
+
+/// Check that the kernel at `path` can be booted through its PVH entry
+/// point, which is how the VMM loads a kernel without firmware on x86_64.
+///
+/// Without this check, a bzImage or a vmlinux built without CONFIG_PVH is
+/// only rejected once the VM is booted, with a much less helpful error.
+pub(crate) fn check_pvh(path: &Path) -> anyhow::Result<()> {
+    let mut kernel = File::open(path)
+        .with_context(|| format!("failed to open '{}'", path.display()))?;
+
+    let mut header = [0u8; 64];
+    kernel.read_exact(&mut header).context("kernel is too small")?;
+    if &header[..4] != ELF_MAGIC {
+        bail!(
+            "'{}' is not an ELF vmlinux, compressed kernels are not supported",
+            path.display()
+        );
+    }
+    if header[4] != ELFCLASS64 || header[5] != ELFDATA2LSB {
+        bail!("'{}' is not a 64-bit little endian ELF", path.display());
+    }
+
+    let phoff = u64_at(&header, 0x20);
+    let phentsize = u16_at(&header, 0x36) as u64;
+    let phnum = u16_at(&header, 0x38) as u64;
+
+    for i in 0..phnum {
+        let mut phdr = [0u8; 56];
+        let _ = kernel.seek(SeekFrom::Start(phoff + i * phentsize))?;
+        kernel.read_exact(&mut phdr)?;
+        if u32_at(&phdr, 0) != PT_NOTE {
+            continue;
+        }
+
+        let mut notes = vec![];
+        let _ = kernel.seek(SeekFrom::Start(u64_at(&phdr, 0x08)))?;
+        let _ = (&mut kernel)
+            .take(u64_at(&phdr, 0x20).min(MAX_NOTES_SIZE))
+            .read_to_end(&mut notes)?;
+        if has_pvh_note(&notes) {
+            return Ok(());
+        }
+    }
+
+    bail!(
+        "'{}' has no PVH entry point, build the kernel with CONFIG_PVH",
+        path.display()
+    )
+}
+
+/// Walk an ELF note segment looking for the Xen PVH entry point note.
+fn has_pvh_note(mut notes: &[u8]) -> bool {
+    let align = |n: usize| (n + 3) & !3;
+    while notes.len() >= 12 {
+        let namesz = u32_at(notes, 0) as usize;
+        let descsz = u32_at(notes, 4) as usize;
+        let kind = u32_at(notes, 8);
+        let name = notes.get(12..12 + namesz).unwrap_or_default();
+        if kind == XEN_ELFNOTE_PHYS32_ENTRY && name == b"Xen\0" {
+            return true;
+        }
+        let next = 12 + align(namesz) + align(descsz);
+        notes = notes.get(next..).unwrap_or_default();
+    }
+    false
+}
+
+fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    bytes.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(name: &[u8], kind: u32, desc: &[u8]) -> Vec<u8> {
+        let mut note = vec![];
+        note.extend((name.len() as u32).to_le_bytes());
+        note.extend((desc.len() as u32).to_le_bytes());
+        note.extend(kind.to_le_bytes());
+        note.extend(name);
+        note.resize((note.len() + 3) & !3, 0);
+        note.extend(desc);
+        note.resize((note.len() + 3) & !3, 0);
+        note
+    }
+
+    /// A minimal ELF64 with a single PT_NOTE segment holding `notes`.
+    fn elf(notes: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0u8; 64 + 56];
+        elf[..4].copy_from_slice(ELF_MAGIC);
+        elf[4] = ELFCLASS64;
+        elf[5] = ELFDATA2LSB;
+        elf[0x20..0x28].copy_from_slice(&64u64.to_le_bytes());
+        elf[0x36..0x38].copy_from_slice(&56u16.to_le_bytes());
+        elf[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        elf[64..68].copy_from_slice(&PT_NOTE.to_le_bytes());
+        elf[72..80].copy_from_slice(&120u64.to_le_bytes());
+        elf[96..104].copy_from_slice(&(notes.len() as u64).to_le_bytes());
+        elf.extend(notes);
+        elf
+    }
+
+    fn check(contents: &[u8]) -> anyhow::Result<()> {
+        let dir = tempfile::tempdir().expect("scratch dir");
+        let path = dir.path().join("vmlinux");
+        std::fs::write(&path, contents).expect("write kernel");
+        check_pvh(&path)
+    }
+
+    #[test]
+    fn accepts_kernel_with_pvh_note() {
+        let mut notes = note(b"GNU\0", 3, &[0; 20]);
+        notes.extend(note(b"Xen\0", XEN_ELFNOTE_PHYS32_ENTRY, &[0; 4]));
+        assert!(check(&elf(&notes)).is_ok());
+    }
+
+    #[test]
+    fn rejects_kernel_without_pvh_note() {
+        let notes = note(b"GNU\0", 3, &[0; 20]);
+        assert!(check(&elf(&notes)).is_err());
+    }
+
+    #[test]
+    fn bounds_oversized_note_segment() {
+        let notes = note(b"Xen\0", XEN_ELFNOTE_PHYS32_ENTRY, &[0; 4]);
+        let mut kernel = elf(&notes);
+        kernel[96..104].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(check(&kernel).is_ok());
+    }
+
+    #[test]
+    fn rejects_compressed_kernel() {
+        let mut bzimage = vec![0u8; 1024];
+        bzimage[0x202..0x206].copy_from_slice(b"HdrS");
+        assert!(check(&bzimage).is_err());
+    }
+}
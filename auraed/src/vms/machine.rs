@@ -0,0 +1,456 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Conversion of VMs defined with the `aurae.vms` API into a [VmSpec], from
+//! which the config of the VMM is built.
+//!
+//! Each field of the machine is checked as it is converted, so an invalid
+//! machine is rejected with the field at fault. The resulting VMM config is
+//! then validated the way the VMM validates it on create, so that a machine
+//! the VMM would refuse is rejected before anything is allocated for it.
+
+use super::{
+    cpuid::{CpuidMask, CpuidRegister},
+    error::{Result, VmServiceError},
+    host, vcpus, vfio,
+    virtual_machine::{
+        ConfidentialSpec, CpuTopology, GuestMemorySpec, MountSpec, NetSpec,
+        SmbiosSpec, VcpuAffinity, VdpaSpec, VmID, VmSpec,
+    },
+};
+use net_util::MacAddr;
+use proto::vms::{self, ConfidentialTechnology, VirtualMachine};
+use std::{net::Ipv4Addr, path::PathBuf};
+use tracing::warn;
+
+/// Builds the [VmSpec] of `machine`, whose serial console is exposed on
+/// `serial_socket` and which gets the cloud-init seed image at `seed`
+/// attached after its drive mounts, and checks it with [check].
+pub(crate) fn vm_spec(
+    id: &VmID,
+    machine: VirtualMachine,
+    serial_socket: PathBuf,
+    seed: Option<PathBuf>,
+) -> Result<VmSpec> {
+    let Some(root_drive) = machine.root_drive else {
+        return Err(VmServiceError::MissingRootDrive { id: id.clone() });
+    };
+
+    let mut mounts = vec![MountSpec {
+        host_path: PathBuf::from(root_drive.image_path.as_str()),
+        read_only: root_drive.read_only,
+    }];
+    mounts.extend(machine.drive_mounts.into_iter().map(|m| MountSpec {
+        host_path: PathBuf::from(m.image_path.as_str()),
+        read_only: m.read_only,
+    }));
+    mounts
+        .extend(seed.map(|host_path| MountSpec { host_path, read_only: true }));
+
+    let (vdpa, net): (Vec<_>, Vec<_>) = machine
+        .network_interfaces
+        .into_iter()
+        .partition(|n| !n.vdpa_device.is_empty());
+
+    let vdpa = vdpa
+        .into_iter()
+        .map(|n| VdpaSpec {
+            path: PathBuf::from(n.vdpa_device),
+            queue_pairs: (n.num_queue_pairs > 0).then_some(n.num_queue_pairs),
+        })
+        .collect();
+
+    let net = net
+        .into_iter()
+        .map(|n| {
+            Ok(NetSpec {
+                mac: guest_mac(id, &n.mac_address)?,
+                mtu: mtu(id, n.mtu)?,
+                tap: (!n.tap_name.is_empty()).then_some(n.tap_name),
+                ip: Ipv4Addr::UNSPECIFIED,
+                mask: Ipv4Addr::UNSPECIFIED,
+                host_mac: None,
+                vhost_socket: (!n.vhost_user_socket.is_empty())
+                    .then_some(n.vhost_user_socket),
+                queue_pairs: (n.num_queue_pairs > 0)
+                    .then_some(n.num_queue_pairs),
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let vfio_devices = machine
+        .vfio_devices
+        .iter()
+        .map(|address| {
+            vfio::sysfs_path(address).map_err(|e| {
+                VmServiceError::InvalidDevice {
+                    id: id.clone(),
+                    device: address.clone(),
+                    source: e,
+                }
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if machine.nested_virtualization && !host::nested_virtualization_enabled() {
+        return Err(VmServiceError::NestedVirtualizationUnavailable {
+            id: id.clone(),
+        });
+    }
+
+    if !host::io_uring_enabled() {
+        warn!("io_uring is disabled on this host, block devices of vm '{id}' fall back to AIO");
+    }
+
+    let vcpu_count = machine.vcpu_count;
+    let vcpu_affinity = machine
+        .vcpu_affinity
+        .into_iter()
+        .map(|a| {
+            if a.vcpu >= vcpu_count {
+                return Err(anyhow::anyhow!("vcpu {} does not exist", a.vcpu));
+            }
+            Ok(VcpuAffinity {
+                vcpu: a.vcpu as u8,
+                host_cpus: vcpus::parse_cpu_list(&a.host_cpus)?,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()
+        .map_err(|e| VmServiceError::InvalidVcpuAffinity {
+            id: id.clone(),
+            source: e,
+        })?;
+
+    let cpu_topology = machine
+        .cpu_topology
+        .map(|t| -> Result<CpuTopology> {
+            let count = |n: u32, default: u32| {
+                u8::try_from(if n == 0 { default } else { n }).map_err(|_| {
+                    VmServiceError::InvalidCpuTopology {
+                        id: id.clone(),
+                        reason: format!("{n} exceeds 255"),
+                    }
+                })
+            };
+            Ok(CpuTopology {
+                sockets: count(t.sockets, 0)?,
+                dies_per_socket: count(t.dies_per_socket, 1)?,
+                cores_per_die: count(t.cores_per_die, 0)?,
+                threads_per_core: count(t.threads_per_core, 1)?,
+            })
+        })
+        .transpose()?;
+
+    let cpuid_masks = cpuid_masks(id, &machine.cpuid_masks)?;
+
+    let guest_memory = machine.guest_memory.unwrap_or_default();
+    let guest_memory = GuestMemorySpec {
+        hugepages: guest_memory.hugepages,
+        hugepage_size: (guest_memory.hugepage_size_mb > 0)
+            .then(|| u64::from(guest_memory.hugepage_size_mb) << 20),
+        prefault: guest_memory.prefault,
+    };
+
+    let uuid = match machine.uuid.as_str() {
+        "" => None,
+        uuid => Some(uuid.parse().map_err(|_| {
+            VmServiceError::InvalidUuid { id: id.clone(), uuid: uuid.into() }
+        })?),
+    };
+    let smbios = SmbiosSpec::new(
+        id,
+        uuid,
+        (!machine.serial_number.is_empty()).then_some(machine.serial_number),
+    );
+
+    let confidential = match machine.confidential_computing {
+        Some(c) => match c.technology() {
+            ConfidentialTechnology::Unspecified => None,
+            ConfidentialTechnology::Tdx if cfg!(feature = "tdx") => {
+                Some(ConfidentialSpec::Tdx {
+                    firmware: PathBuf::from(c.firmware_path),
+                })
+            }
+            technology => {
+                return Err(VmServiceError::UnsupportedConfidentialComputing {
+                    id: id.clone(),
+                    technology: technology.as_str_name().into(),
+                })
+            }
+        },
+        None => None,
+    };
+
+    let spec = VmSpec {
+        memory_size: machine.mem_size_mb,
+        vcpu_count,
+        kernel_image_path: PathBuf::from(machine.kernel_img_path.as_str()),
+        initramfs_path: (!machine.initrd_path.is_empty())
+            .then(|| PathBuf::from(machine.initrd_path)),
+        kernel_args: machine.kernel_args,
+        mounts,
+        net,
+        vfio_devices,
+        vdpa,
+        confidential,
+        firmware_path: (!machine.firmware_path.is_empty())
+            .then(|| PathBuf::from(machine.firmware_path)),
+        serial_socket: Some(serial_socket),
+        vsock_socket: None,
+        vcpu_affinity,
+        cpu_topology,
+        guest_memory,
+        smbios,
+        cpuid_masks,
+        watchdog: machine.watchdog.is_some(),
+    };
+
+    check(id, &spec)?;
+    Ok(spec)
+}
+
+/// Checks that the VM `id` of `spec` can be booted: its kernel, CPU topology
+/// and guest memory are checked by auraed, everything else by the VMM.
+pub(crate) fn check(id: &VmID, spec: &VmSpec) -> Result<()> {
+    check_kernel(id, spec)?;
+    check_cpu_topology(id, spec)?;
+    check_guest_memory(id, spec)?;
+
+    let mut config = vmm::vm_config::VmConfig::from(spec.clone());
+    let _ = config.validate().map_err(|e| VmServiceError::InvalidVmConfig {
+        id: id.clone(),
+        source: anyhow::anyhow!("{e}"),
+    })?;
+    Ok(())
+}
+
+/// Check that the VMM can boot the kernel of `spec` directly, which on
+/// x86_64 requires a PVH entry point. Guests booted by a firmware are left
+/// to it.
+fn check_kernel(id: &VmID, spec: &VmSpec) -> Result<()> {
+    if spec.confidential.is_some() || spec.firmware_path.is_some() {
+        return Ok(());
+    }
+    if spec.kernel_image_path.as_os_str().is_empty() {
+        return Err(VmServiceError::InvalidKernel {
+            id: id.clone(),
+            source: anyhow::anyhow!("neither a kernel nor a firmware is set"),
+        });
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
+        super::kernel::check_pvh(&spec.kernel_image_path).map_err(|e| {
+            VmServiceError::InvalidKernel { id: id.clone(), source: e }
+        })?;
+    }
+    Ok(())
+}
+
+/// The MAC address `mac` requested for a NIC of the VM `id`, or a random one.
+/// Multicast addresses cannot be assigned to a NIC.
+fn guest_mac(id: &VmID, mac: &str) -> Result<MacAddr> {
+    if mac.is_empty() {
+        return Ok(MacAddr::local_random());
+    }
+    let invalid = |reason: &str| VmServiceError::InvalidNetworkInterface {
+        id: id.clone(),
+        reason: format!("MAC address '{mac}' {reason}"),
+    };
+    let parsed =
+        MacAddr::parse_str(mac).map_err(|_| invalid("cannot be parsed"))?;
+    if parsed.get_bytes()[0] & 1 != 0 {
+        return Err(invalid("is a multicast address"));
+    }
+    if parsed.get_bytes().iter().all(|b| *b == 0) {
+        return Err(invalid("is unspecified"));
+    }
+    Ok(parsed)
+}
+
+/// The CPUID `masks` requested for the VM `id`, which need a register and
+/// the hypervisor of an x86_64 host.
+fn cpuid_masks(id: &VmID, masks: &[vms::CpuidMask]) -> Result<Vec<CpuidMask>> {
+    if !masks.is_empty() && !cfg!(target_arch = "x86_64") {
+        return Err(VmServiceError::InvalidCpuidMask {
+            id: id.clone(),
+            reason: "cpuid is only masked on x86_64 hosts".into(),
+        });
+    }
+    masks
+        .iter()
+        .map(|m| {
+            let register = match m.register() {
+                vms::CpuidRegister::Eax => CpuidRegister::Eax,
+                vms::CpuidRegister::Ebx => CpuidRegister::Ebx,
+                vms::CpuidRegister::Ecx => CpuidRegister::Ecx,
+                vms::CpuidRegister::Edx => CpuidRegister::Edx,
+                vms::CpuidRegister::Unspecified => {
+                    return Err(VmServiceError::InvalidCpuidMask {
+                        id: id.clone(),
+                        reason: format!("no register for leaf {:#x}", m.leaf),
+                    })
+                }
+            };
+            Ok(CpuidMask {
+                leaf: m.leaf,
+                subleaf: m.subleaf,
+                register,
+                bits: m.bits,
+            })
+        })
+        .collect()
+}
+
+/// The MTU requested for a NIC of the VM `id`, 0 keeps the MTU of the TAP.
+fn mtu(id: &VmID, mtu: u32) -> Result<Option<u16>> {
+    if mtu == 0 {
+        return Ok(None);
+    }
+    // The minimum MTU of IPv4 and the maximum of the virtio config space
+    match u16::try_from(mtu) {
+        Ok(mtu) if mtu >= 68 => Ok(Some(mtu)),
+        _ => Err(VmServiceError::InvalidNetworkInterface {
+            id: id.clone(),
+            reason: format!("MTU {mtu} is not between 68 and 65535"),
+        }),
+    }
+}
+
+/// Check that the CPU topology of `spec`, if any, adds up to its vCPUs.
+fn check_cpu_topology(id: &VmID, spec: &VmSpec) -> Result<()> {
+    let Some(topology) = spec.cpu_topology else {
+        return Ok(());
+    };
+    if topology.vcpus() != spec.vcpu_count {
+        return Err(VmServiceError::InvalidCpuTopology {
+            id: id.clone(),
+            reason: format!(
+                "{} sockets x {} dies x {} cores x {} threads is not {} vcpus",
+                topology.sockets,
+                topology.dies_per_socket,
+                topology.cores_per_die,
+                topology.threads_per_core,
+                spec.vcpu_count
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Check that the guest memory of `spec` can be backed by huge pages if it
+/// asks for them. The huge pages still free when the VM boots are not
+/// reserved for it.
+fn check_guest_memory(id: &VmID, spec: &VmSpec) -> Result<()> {
+    let memory = &spec.guest_memory;
+    let invalid = |reason: &str| VmServiceError::InvalidGuestMemory {
+        id: id.clone(),
+        reason: reason.into(),
+    };
+
+    if !memory.hugepages {
+        return match memory.hugepage_size {
+            Some(_) => Err(invalid("a huge page size requires hugepages")),
+            None => Ok(()),
+        };
+    }
+
+    let Some(size) = memory.hugepage_size.or_else(host::default_hugepage_size)
+    else {
+        return Err(invalid("the host does not support huge pages"));
+    };
+    if !size.is_power_of_two() {
+        return Err(invalid("the huge page size must be a power of two"));
+    }
+    let memory_size = u64::from(spec.memory_size) << 20;
+    if memory_size % size != 0 {
+        return Err(invalid(
+            "the memory size must be a multiple of the huge page size",
+        ));
+    }
+
+    let needed = memory_size / size;
+    let free = host::free_hugepages(size);
+    if free < needed {
+        return Err(VmServiceError::InsufficientHugepages {
+            id: id.clone(),
+            size,
+            needed,
+            free,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guest_mac() {
+        let id = VmID::new("test_vm");
+        assert!(guest_mac(&id, "").is_ok());
+        assert_eq!(
+            guest_mac(&id, "02:00:00:00:00:01").unwrap().to_string(),
+            "02:00:00:00:00:01"
+        );
+        assert!(guest_mac(&id, "01:00:5e:00:00:01").is_err());
+        assert!(guest_mac(&id, "00:00:00:00:00:00").is_err());
+        assert!(guest_mac(&id, "not a mac").is_err());
+    }
+
+    #[test]
+    fn test_mtu() {
+        let id = VmID::new("test_vm");
+        assert_eq!(mtu(&id, 0).unwrap(), None);
+        assert_eq!(mtu(&id, 1500).unwrap(), Some(1500));
+        assert!(mtu(&id, 67).is_err());
+        assert!(mtu(&id, 65536).is_err());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_cpuid_masks() {
+        let id = VmID::new("test_vm");
+        let mut mask = vms::CpuidMask {
+            leaf: 0x7,
+            subleaf: 0,
+            register: 0,
+            bits: 1 << 16,
+        };
+        assert!(cpuid_masks(&id, &[mask.clone()]).is_err());
+
+        mask.set_register(vms::CpuidRegister::Ebx);
+        assert_eq!(
+            cpuid_masks(&id, &[mask]).unwrap(),
+            vec![CpuidMask {
+                leaf: 0x7,
+                subleaf: 0,
+                register: CpuidRegister::Ebx,
+                bits: 1 << 16,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_vm_spec_requires_root_drive() {
+        let id = VmID::new("test_vm");
+        let res = vm_spec(
+            &id,
+            VirtualMachine::default(),
+            PathBuf::from("/tmp/test_vm.sock"),
+            None,
+        );
+        assert!(matches!(res, Err(VmServiceError::MissingRootDrive { .. })));
+    }
+}
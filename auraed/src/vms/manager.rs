@@ -17,17 +17,24 @@ use std::sync::{
     Arc,
 };
 
+use anyhow::anyhow;
 use hypervisor::Hypervisor;
 use libc::EFD_NONBLOCK;
 use vmm::{api::ApiRequest, VmmThreadHandle};
 use vmm_sys_util::eventfd::EventFd;
 
+use super::{
+    cpuid::CpuidMask,
+    jailer::{Jail, JailerConfig},
+};
+
 pub struct Manager {
     pub events: EventFd,
     pub sender: Option<Sender<ApiRequest>>,
     hypervisor: Arc<dyn Hypervisor>,
     debug: EventFd,
     vmm_thread: Option<VmmThreadHandle>,
+    pub jail: Jail,
 }
 
 impl Manager {
@@ -46,29 +53,54 @@ impl Manager {
             sender: None,
             events: api_evt,
             vmm_thread: None,
+            jail: Jail::default(),
         }
     }
 
-    pub fn start(&mut self) -> Result<(), anyhow::Error> {
+    /// Hides the CPUID feature bits of `masks` from the guest. Must be called
+    /// before [Manager::start], as the VMM takes the hypervisor with it.
+    pub fn mask_cpuid(&mut self, masks: &[CpuidMask]) {
+        #[cfg(target_arch = "x86_64")]
+        if !masks.is_empty() {
+            self.hypervisor = Arc::new(super::cpuid::MaskedCpuid::new(
+                self.hypervisor.clone(),
+                masks.to_vec(),
+            ));
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = masks;
+    }
+
+    /// Starts the VMM threads, confined as configured by `jailer`
+    pub fn start(
+        &mut self,
+        jailer: &JailerConfig,
+    ) -> Result<(), anyhow::Error> {
         let (sender, receiver) = channel();
         self.sender = Some(sender.clone());
 
         let version =
             vmm::VmmVersionInfo::new("auraed", env!("CARGO_PKG_VERSION"));
-        self.vmm_thread = Some(
+        let events = self.events.try_clone()?;
+        let debug = self.debug.try_clone()?;
+        let seccomp_action = jailer.seccomp_action();
+        let hypervisor = self.hypervisor.clone();
+        let (vmm_thread, jail) = jailer.spawn(move || {
             vmm::start_vmm_thread(
                 version,
                 &None,
                 None,
-                self.events.try_clone()?,
+                events,
                 sender,
                 receiver,
-                self.debug.try_clone()?,
-                &seccompiler::SeccompAction::Allow,
-                self.hypervisor.clone(),
+                debug,
+                &seccomp_action,
+                hypervisor,
             )
-            .expect("Failed to start VMM thread"),
-        );
+            .map_err(|e| anyhow!("Failed to start VMM thread: {e:?}"))
+        })?;
+        self.vmm_thread = Some(vmm_thread);
+        self.jail = jail;
         Ok(())
     }
 }
@@ -0,0 +1,168 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Runtime metrics of VMs.
+//!
+//! Device counters are reported by the VMM. vCPU exits and the guest memory
+//! backed by host memory are read from the statistics KVM keeps for each VM
+//! in debugfs, `/sys/kernel/debug/kvm/<pid>-<vm fd>`. The VMM runs within
+//! auraed, so the file descriptor of every VM is one of the auraed process.
+
+use proto::observe::{BlockDeviceMetrics, NetDeviceMetrics};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::Path,
+};
+
+const KVM_DEBUGFS: &str = "/sys/kernel/debug/kvm";
+
+/// The statistics KVM keeps for a VM
+#[derive(Debug, Default)]
+pub(crate) struct KvmStats {
+    /// The exits of all vCPUs by statistic, e.g. `io_exits`
+    pub vcpu_exits: HashMap<String, u64>,
+    /// Guest memory mapped by KVM in bytes
+    pub guest_memory_rss: u64,
+}
+
+impl KvmStats {
+    /// Reads the statistics of the VM with the file descriptor `vm_fd`
+    pub fn read(vm_fd: i32) -> anyhow::Result<Self> {
+        let dir = Path::new(KVM_DEBUGFS)
+            .join(format!("{}-{vm_fd}", std::process::id()));
+
+        let read = |name: &str| -> anyhow::Result<u64> {
+            Ok(fs::read_to_string(dir.join(name))?.trim().parse()?)
+        };
+
+        let mut stats = Self::default();
+        for entry in fs::read_dir(&dir)? {
+            let file_name = entry?.file_name();
+            let Some(name) = file_name.to_str() else {
+                continue;
+            };
+            match name {
+                "pages_4k" => stats.guest_memory_rss += read(name)? << 12,
+                "pages_2m" => stats.guest_memory_rss += read(name)? << 21,
+                "pages_1g" => stats.guest_memory_rss += read(name)? << 30,
+                _ if name == "exits" || name.ends_with("_exits") => {
+                    let _ = stats.vcpu_exits.insert(name.into(), read(name)?);
+                }
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// The file descriptors of the KVM VMs of the auraed process
+pub(crate) fn kvm_vm_fds() -> anyhow::Result<HashSet<i32>> {
+    Ok(fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let target = fs::read_link(entry.path()).ok()?;
+            if target.as_os_str() != "anon_inode:kvm-vm" {
+                return None;
+            }
+            entry.file_name().to_str()?.parse().ok()
+        })
+        .collect())
+}
+
+/// The file descriptor of the KVM VM created since `before` was taken with
+/// [kvm_vm_fds], unless there is none or more than one
+pub(crate) fn new_kvm_vm_fd(before: &HashSet<i32>) -> Option<i32> {
+    let after = kvm_vm_fds().ok()?;
+    let mut created = after.difference(before);
+    match (created.next(), created.next()) {
+        (Some(fd), None) => Some(*fd),
+        _ => None,
+    }
+}
+
+/// Splits the device counters reported by the VMM, counters by name by
+/// device id, into block and net devices
+pub(crate) fn device_metrics(
+    counters: &[u8],
+) -> anyhow::Result<(Vec<BlockDeviceMetrics>, Vec<NetDeviceMetrics>)> {
+    let counters: HashMap<String, HashMap<String, u64>> =
+        serde_json::from_slice(counters)?;
+
+    let mut block_devices = vec![];
+    let mut net_devices = vec![];
+    for (id, counters) in counters {
+        let counter = |name: &str| counters.get(name).copied().unwrap_or(0);
+        if counters.contains_key("read_bytes") {
+            block_devices.push(BlockDeviceMetrics {
+                id,
+                read_bytes: counter("read_bytes"),
+                write_bytes: counter("write_bytes"),
+                read_ops: counter("read_ops"),
+                write_ops: counter("write_ops"),
+            });
+        } else if counters.contains_key("rx_bytes") {
+            net_devices.push(NetDeviceMetrics {
+                id,
+                rx_bytes: counter("rx_bytes"),
+                tx_bytes: counter("tx_bytes"),
+                rx_frames: counter("rx_frames"),
+                tx_frames: counter("tx_frames"),
+            });
+        }
+    }
+
+    block_devices.sort_by(|a, b| a.id.cmp(&b.id));
+    net_devices.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok((block_devices, net_devices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_metrics_must_split_block_and_net_devices() {
+        let counters = br#"{
+            "_disk0": {"read_bytes": 512, "write_bytes": 1024, "read_ops": 1, "write_ops": 2, "read_latency_min": 3},
+            "_net1": {"rx_bytes": 64, "tx_bytes": 128, "rx_frames": 4, "tx_frames": 8},
+            "_rng2": {"entropy_bytes": 16}
+        }"#;
+
+        let (block_devices, net_devices) =
+            device_metrics(counters).expect("valid counters");
+
+        assert_eq!(
+            block_devices,
+            vec![BlockDeviceMetrics {
+                id: "_disk0".into(),
+                read_bytes: 512,
+                write_bytes: 1024,
+                read_ops: 1,
+                write_ops: 2,
+            }]
+        );
+        assert_eq!(
+            net_devices,
+            vec![NetDeviceMetrics {
+                id: "_net1".into(),
+                rx_bytes: 64,
+                tx_bytes: 128,
+                rx_frames: 4,
+                tx_frames: 8,
+            }]
+        );
+    }
+}
@@ -12,11 +12,42 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
+//! Virtual machines run by the Cloud Hypervisor VMM linked into auraed.
+//!
+//! auraed only describes the devices of a VM in its VmConfig. The device
+//! manager of the VMM allocates their MMIO ranges and interrupts, registers
+//! their event handlers and tears them down again, so auraed keeps no device
+//! bookkeeping of its own. The VMM also describes the devices to the guest,
+//! through ACPI or, on aarch64, the flattened device tree with its PCIe host
+//! bridge.
 
+mod attestation;
+mod checkpoint;
+mod cloud_init;
+mod console;
+mod cpuid;
+mod dirty_pages;
 mod error;
+mod export;
+mod firecracker;
+mod guest_channel;
+mod host;
+mod ipam;
+mod jailer;
+#[cfg(target_arch = "x86_64")]
+mod kernel;
+mod machine;
 mod manager;
+mod metrics;
+mod tap;
+mod vcpus;
+mod vfio;
 mod virtual_machine;
 mod virtual_machines;
 mod vm_service;
 
+pub(crate) use guest_channel::{report_to_host, serve_agent};
+pub(crate) use host::kvm_available;
+pub use jailer::JailerConfig;
+pub(crate) use vcpus::vm_cgroup;
 pub(crate) use vm_service::VmService;
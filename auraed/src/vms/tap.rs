@@ -0,0 +1,66 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! The TAP devices auraed provisions for virtual machines requested without
+//! network interfaces.
+//!
+//! The VMM creates the device when the VM boots and assigns it the host
+//! address leased by [super::ipam::Ipam]. The device is not persistent, so it
+//! normally disappears with the VMM. [remove] deletes it in case it did not,
+//! e.g. because another process still holds it open.
+
+use anyhow::anyhow;
+use futures::TryStreamExt;
+use vmm_sys_util::rand;
+
+const PREFIX: &str = "auraed-";
+
+/// A fresh name for a TAP device provisioned by auraed
+pub(crate) fn name() -> anyhow::Result<String> {
+    let suffix = rand::rand_alphanumerics(6)
+        .into_string()
+        .map_err(|_| anyhow!("Error generating TAP device name"))?;
+    Ok(format!("{PREFIX}{suffix}"))
+}
+
+/// Returns true if the TAP device `name` was provisioned by auraed
+pub(crate) fn is_provisioned(name: &str) -> bool {
+    name.starts_with(PREFIX)
+}
+
+/// Delete the TAP device `name`, if it still exists
+pub(crate) async fn remove(name: &str) -> anyhow::Result<()> {
+    let (connection, handle, _) = rtnetlink::new_connection()?;
+    let _ignored = tokio::spawn(connection);
+
+    let link = handle
+        .link()
+        .get()
+        .match_name(name.to_string())
+        .execute()
+        .try_next()
+        .await;
+    // The kernel reports a missing device as an error
+    let Ok(Some(link)) = link else {
+        return Ok(());
+    };
+    handle.link().del(link.header.index).execute().await?;
+    Ok(())
+}
+
+/// Let the host route the traffic of the guests to other networks
+pub(crate) async fn enable_forwarding() -> std::io::Result<()> {
+    tokio::fs::write("/proc/sys/net/ipv4/ip_forward", "1").await
+}
@@ -0,0 +1,204 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+//! Dedicated cgroups for the vCPU threads of VMs.
+//!
+//! The VMM runs within auraed, so the vCPU threads of every VM are threads of
+//! the auraed process. Threads can only be moved between the cgroups of a
+//! threaded subtree, so each vCPU gets a threaded cgroup below the cgroup of
+//! auraed, `<auraed>/vms/<vm id>/vcpu<n>`. The cpu and cpuset controllers
+//! support threaded cgroups and govern them just like the cgroups of cells.
+
+use super::virtual_machine::{VcpuAffinity, VmID};
+use anyhow::{anyhow, Context};
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const CONTROLLERS: &str = "+cpu +cpuset";
+const VMS_CGROUP: &str = "vms";
+
+/// The threaded cgroups holding the vCPU threads of one VM
+#[derive(Debug, Clone)]
+pub(crate) struct VcpuCgroups {
+    dir: PathBuf,
+}
+
+impl VcpuCgroups {
+    /// Creates a cgroup for each vCPU thread of the VM `id` that was started
+    /// after `before` was taken with [threads], limits it to the pinned host
+    /// CPUs of the vCPU and moves the thread into it.
+    pub fn place(
+        id: &VmID,
+        before: &HashSet<i32>,
+        affinity: &[VcpuAffinity],
+    ) -> anyhow::Result<Self> {
+        let own = own_cgroup()?;
+        let vms = own.join(VMS_CGROUP);
+        let dir = vms.join(id.to_string());
+
+        // The cgroup of auraed becomes the threaded domain of the subtree
+        create_threaded(&vms)?;
+        enable_controllers(&own)?;
+        create_threaded(&dir)?;
+        enable_controllers(&vms)?;
+        enable_controllers(&dir)?;
+
+        let cgroups = Self { dir };
+        for tid in threads()?.difference(before) {
+            let Some(vcpu) = vcpu_index(*tid) else {
+                continue;
+            };
+
+            let cgroup = cgroups.dir.join(format!("vcpu{vcpu}"));
+            create_threaded(&cgroup)?;
+            if let Some(pinned) = affinity.iter().find(|a| a.vcpu == vcpu) {
+                write(
+                    &cgroup.join("cpuset.cpus"),
+                    &to_cpu_list(&pinned.host_cpus),
+                )?;
+            }
+            write(&cgroup.join("cgroup.threads"), &tid.to_string())?;
+        }
+
+        Ok(cgroups)
+    }
+
+    /// Removes the cgroups once the vCPU threads have exited
+    pub fn remove(&self) -> anyhow::Result<()> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                fs::remove_dir(entry.path())?;
+            }
+        }
+        fs::remove_dir(&self.dir)?;
+        Ok(())
+    }
+}
+
+/// The threaded cgroup holding the vCPU cgroups of the VM `id`
+pub(crate) fn vm_cgroup(id: &str) -> anyhow::Result<PathBuf> {
+    Ok(own_cgroup()?.join(VMS_CGROUP).join(id))
+}
+
+/// Thread ids of the auraed process
+pub(crate) fn threads() -> anyhow::Result<HashSet<i32>> {
+    Ok(fs::read_dir("/proc/self/task")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect())
+}
+
+/// Thread ids of the vCPUs started after `before` was taken with [threads]
+pub(crate) fn vcpu_threads(
+    before: &HashSet<i32>,
+) -> anyhow::Result<HashSet<i32>> {
+    Ok(threads()?
+        .difference(before)
+        .copied()
+        .filter(|tid| vcpu_index(*tid).is_some())
+        .collect())
+}
+
+/// Parses a cpuset list such as `0-3,7` into the listed CPUs
+pub(crate) fn parse_cpu_list(list: &str) -> anyhow::Result<Vec<usize>> {
+    let mut cpus = vec![];
+    for range in list.split(',').map(str::trim) {
+        let (first, last) = match range.split_once('-') {
+            Some((first, last)) => (first.parse()?, last.parse()?),
+            None => {
+                let cpu = range.parse()?;
+                (cpu, cpu)
+            }
+        };
+        if first > last {
+            return Err(anyhow!("'{range}' is not a valid range of cpus"));
+        }
+        cpus.extend(first..=last);
+    }
+    Ok(cpus)
+}
+
+fn to_cpu_list(cpus: &[usize]) -> String {
+    cpus.iter().map(ToString::to_string).collect::<Vec<_>>().join(",")
+}
+
+/// The cloud-hypervisor vCPU threads are named `vcpu<n>`
+fn vcpu_index(tid: i32) -> Option<u8> {
+    fs::read_to_string(format!("/proc/self/task/{tid}/comm"))
+        .ok()?
+        .trim()
+        .strip_prefix("vcpu")?
+        .parse()
+        .ok()
+}
+
+/// The cgroup v2 directory auraed runs in
+fn own_cgroup() -> anyhow::Result<PathBuf> {
+    let cgroups = fs::read_to_string("/proc/self/cgroup")?;
+    let path =
+        cgroups.lines().find_map(|line| line.strip_prefix("0::")).ok_or_else(
+            || anyhow!("auraed does not run in a cgroup v2 hierarchy"),
+        )?;
+    Ok(Path::new(CGROUP_ROOT).join(path.trim_start_matches('/')))
+}
+
+fn create_threaded(cgroup: &Path) -> anyhow::Result<()> {
+    match fs::create_dir(cgroup) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => {
+            return Err(e).with_context(|| {
+                format!("failed to create cgroup {}", cgroup.display())
+            })
+        }
+        _ => {}
+    }
+    write(&cgroup.join("cgroup.type"), "threaded")
+}
+
+fn enable_controllers(cgroup: &Path) -> anyhow::Result<()> {
+    write(&cgroup.join("cgroup.subtree_control"), CONTROLLERS)
+}
+
+fn write(path: &Path, contents: &str) -> anyhow::Result<()> {
+    fs::write(path, contents)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cpu_list() {
+        assert_eq!(parse_cpu_list("0-3,7").unwrap(), vec![0, 1, 2, 3, 7]);
+        assert_eq!(parse_cpu_list("5").unwrap(), vec![5]);
+    }
+
+    #[test]
+    fn test_parse_cpu_list_invalid() {
+        assert!(parse_cpu_list("").is_err());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+}
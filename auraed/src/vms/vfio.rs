@@ -0,0 +1,94 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use anyhow::{anyhow, bail, Context};
+use std::path::PathBuf;
+
+/// Root of the PCI devices in sysfs.
+const PCI_DEVICES: &str = "/sys/bus/pci/devices";
+
+/// Driver a device has to be bound to before it can be passed to a VM.
+const VFIO_DRIVER: &str = "vfio-pci";
+
+/// Resolve the sysfs path of the host PCI device at `address` (e.g.
+/// `0000:01:00.0`) and check that it is ready for passthrough.
+///
+/// Cloud Hypervisor takes care of the IOMMU group, region mapping and
+/// interrupt routing; all we need to ensure is that the device exists and
+/// that it is bound to `vfio-pci`, as rebinding it is a host level decision.
+pub(crate) fn sysfs_path(address: &str) -> anyhow::Result<PathBuf> {
+    if !is_pci_address(address) {
+        bail!("'{address}' is not a PCI address (dddd:bb:dd.f)");
+    }
+
+    let path = PathBuf::from(PCI_DEVICES).join(address);
+    if !path.exists() {
+        bail!("PCI device '{address}' does not exist");
+    }
+
+    let driver = std::fs::read_link(path.join("driver"))
+        .with_context(|| format!("PCI device '{address}' has no driver"))?;
+    let driver = driver
+        .file_name()
+        .and_then(|d| d.to_str())
+        .ok_or_else(|| anyhow!("PCI device '{address}' has no driver"))?;
+    if driver != VFIO_DRIVER {
+        bail!(
+            "PCI device '{address}' is bound to '{driver}', expected '{VFIO_DRIVER}'"
+        );
+    }
+
+    Ok(path)
+}
+
+/// Check that `address` has the `domain:bus:device.function` form.
+fn is_pci_address(address: &str) -> bool {
+    let parts: Vec<&str> = address.split([':', '.']).collect();
+    let [domain, bus, device, function] = parts[..] else {
+        return false;
+    };
+    let is_hex = |s: &str, len: usize| {
+        s.len() == len && s.chars().all(|c| c.is_ascii_hexdigit())
+    };
+    is_hex(domain, 4)
+        && is_hex(bus, 2)
+        && is_hex(device, 2)
+        && is_hex(function, 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_pci_addresses() {
+        assert!(is_pci_address("0000:01:00.0"));
+        assert!(is_pci_address("0000:af:1f.7"));
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        assert!(!is_pci_address(""));
+        assert!(!is_pci_address("01:00.0"));
+        assert!(!is_pci_address("0000:01:00"));
+        assert!(!is_pci_address("0000:01:00.0/../.."));
+        assert!(!is_pci_address("0000:0g:00.0"));
+    }
+
+    #[test]
+    fn rejects_missing_devices() {
+        assert!(sysfs_path("ffff:ff:1f.7").is_err());
+    }
+}
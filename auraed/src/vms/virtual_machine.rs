@@ -14,24 +14,43 @@
 \* -------------------------------------------------------------------------- */
 use anyhow::anyhow;
 use net_util::MacAddr;
+use proto::observe::VmMetrics;
 use std::{
+    collections::HashSet,
     fmt::{self, Display},
     net::{Ipv4Addr, SocketAddr},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
 };
+use tracing::warn;
+use uuid::Uuid;
+use validation::{ValidatedField, ValidationError};
 use vmm::{
-    api::ApiAction,
+    api::{
+        ApiAction, VmReceiveMigrationData, VmSendMigrationData,
+        VmSnapshotConfig,
+    },
     config::{
-        default_console, default_serial, CpuFeatures, CpusConfig,
-        DebugConsoleConfig, HotplugMethod, MemoryConfig, PayloadConfig,
-        RngConfig, VhostMode, DEFAULT_DISK_NUM_QUEUES, DEFAULT_DISK_QUEUE_SIZE,
+        default_console, default_serial, ConsoleConfig, ConsoleOutputMode,
+        CpuFeatures, CpusConfig, DebugConsoleConfig, DeviceConfig,
+        HotplugMethod, MemoryConfig, PayloadConfig, PlatformConfig,
+        RestoreConfig, RngConfig, VhostMode, VsockConfig,
+        DEFAULT_DISK_NUM_QUEUES, DEFAULT_DISK_QUEUE_SIZE,
         DEFAULT_MAX_PHYS_BITS, DEFAULT_NET_NUM_QUEUES, DEFAULT_NET_QUEUE_SIZE,
     },
     vm::VmState,
 };
 
-use crate::vms::manager::Manager;
+use crate::vms::{
+    cpuid::CpuidMask,
+    dirty_pages::{self, Mapping},
+    guest_channel::GUEST_CID,
+    jailer::JailerConfig,
+    manager::Manager,
+    metrics::{self, KvmStats},
+    vcpus::{self, VcpuCgroups},
+};
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub struct VmID(String);
@@ -42,6 +61,37 @@ impl VmID {
     }
 }
 
+impl ValidatedField<String> for VmID {
+    fn validate(
+        input: Option<String>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Self, ValidationError> {
+        let input =
+            validation::required_not_empty(input, field_name, parent_name)?;
+
+        Ok(Self(input))
+    }
+
+    fn validate_for_creation(
+        input: Option<String>,
+        field_name: &str,
+        parent_name: Option<&str>,
+    ) -> Result<Self, ValidationError> {
+        let input = Self::validate(input, field_name, parent_name)?;
+
+        // The ID names the files auraed keeps for the VM, e.g. its seed image
+        validation::allow_regex(
+            &input.0,
+            &validation::DOMAIN_NAME_LABEL_REGEX,
+            field_name,
+            parent_name,
+        )?;
+
+        Ok(input)
+    }
+}
+
 impl Display for VmID {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -52,22 +102,210 @@ impl Display for VmID {
 pub struct VmSpec {
     pub memory_size: u32,
     pub vcpu_count: u32,
+    /// Kernel booted directly by the VMM, empty when booting `firmware_path`
     pub kernel_image_path: PathBuf,
+    /// Initramfs loaded next to the kernel, for kernels that cannot mount
+    /// their root on their own
+    pub initramfs_path: Option<PathBuf>,
     pub kernel_args: Vec<String>,
     pub mounts: Vec<MountSpec>,
     pub net: Vec<NetSpec>,
+    /// Sysfs paths of host PCI devices passed through with VFIO
+    pub vfio_devices: Vec<PathBuf>,
+    /// Network interfaces backed by vhost-vdpa devices
+    pub vdpa: Vec<VdpaSpec>,
+    /// Launch the VM as a confidential guest
+    pub confidential: Option<ConfidentialSpec>,
+    /// UEFI firmware (e.g. OVMF) loading the bootloader from the drives
+    pub firmware_path: Option<PathBuf>,
+    /// Unix socket the VMM exposes the serial console of the guest on,
+    /// instead of the terminal of auraed
+    pub serial_socket: Option<PathBuf>,
+    /// Host end of the vsock device of the VM, the reverse channel of the
+    /// guest to auraed is served next to it, see [super::guest_channel]
+    pub vsock_socket: Option<PathBuf>,
+    /// Host CPUs vCPUs are pinned to, vCPUs without an entry may run on any
+    pub vcpu_affinity: Vec<VcpuAffinity>,
+    /// One socket with a single threaded core per vCPU if unset
+    pub cpu_topology: Option<CpuTopology>,
+    pub guest_memory: GuestMemorySpec,
+    pub smbios: SmbiosSpec,
+    /// CPUID feature bits hidden from the guest, see [super::cpuid]
+    pub cpuid_masks: Vec<CpuidMask>,
+    /// Attach the watchdog device of the VMM, which resets the guest once
+    /// it stops pinging the device
+    pub watchdog: bool,
+}
+
+/// Identity of the VM in its SMBIOS tables, read by cloud-init and inventory
+/// agents in the guest. The VMM fills in the BIOS (type 0) and system
+/// (type 1) vendor strings itself, so the platform is named in the OEM
+/// strings (type 11) instead.
+#[derive(Debug, Clone, Default)]
+pub struct SmbiosSpec {
+    pub uuid: Option<Uuid>,
+    pub serial_number: Option<String>,
+    pub oem_strings: Vec<String>,
+}
+
+impl SmbiosSpec {
+    /// Identifies the VM `id` as an Aurae VM, generating a random UUID and
+    /// using the id as serial number unless they are given.
+    pub fn new(
+        id: &VmID,
+        uuid: Option<Uuid>,
+        serial_number: Option<String>,
+    ) -> Self {
+        Self {
+            uuid: Some(uuid.unwrap_or_else(Uuid::new_v4)),
+            serial_number: Some(
+                serial_number.unwrap_or_else(|| id.to_string()),
+            ),
+            oem_strings: vec!["Aurae".into(), format!("aurae.vm.id={id}")],
+        }
+    }
+}
+
+/// How guest memory is backed by host memory
+#[derive(Debug, Clone, Default)]
+pub struct GuestMemorySpec {
+    /// Back guest memory with huge pages from hugetlbfs
+    pub hugepages: bool,
+    /// Huge page size in bytes, the default huge page size of the host if
+    /// unset
+    pub hugepage_size: Option<u64>,
+    /// Populate guest memory on boot instead of on first access
+    pub prefault: bool,
+}
+
+/// CPU topology of a VM, the product of its fields is the number of vCPUs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CpuTopology {
+    pub sockets: u8,
+    pub dies_per_socket: u8,
+    pub cores_per_die: u8,
+    pub threads_per_core: u8,
+}
+
+impl CpuTopology {
+    pub fn vcpus(&self) -> u32 {
+        [
+            self.sockets,
+            self.dies_per_socket,
+            self.cores_per_die,
+            self.threads_per_core,
+        ]
+        .into_iter()
+        .map(u32::from)
+        .product()
+    }
+}
+
+impl From<CpuTopology> for vmm::vm_config::CpuTopology {
+    fn from(topology: CpuTopology) -> Self {
+        vmm::vm_config::CpuTopology {
+            threads_per_core: topology.threads_per_core,
+            cores_per_die: topology.cores_per_die,
+            dies_per_package: topology.dies_per_socket,
+            packages: topology.sockets,
+        }
+    }
+}
+
+impl From<&vmm::vm_config::CpuTopology> for CpuTopology {
+    fn from(config: &vmm::vm_config::CpuTopology) -> Self {
+        CpuTopology {
+            sockets: config.packages,
+            dies_per_socket: config.dies_per_package,
+            cores_per_die: config.cores_per_die,
+            threads_per_core: config.threads_per_core,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VcpuAffinity {
+    pub vcpu: u8,
+    pub host_cpus: Vec<usize>,
+}
+
+impl From<VcpuAffinity> for vmm::vm_config::CpuAffinity {
+    fn from(spec: VcpuAffinity) -> Self {
+        vmm::vm_config::CpuAffinity {
+            vcpu: spec.vcpu,
+            host_cpus: spec.host_cpus,
+        }
+    }
+}
+
+impl From<&vmm::vm_config::CpuAffinity> for VcpuAffinity {
+    fn from(config: &vmm::vm_config::CpuAffinity) -> Self {
+        VcpuAffinity { vcpu: config.vcpu, host_cpus: config.host_cpus.clone() }
+    }
+}
+
+/// Confidential computing technologies a VM can be launched with.
+#[derive(Debug, Clone)]
+pub enum ConfidentialSpec {
+    /// An Intel TDX trust domain, booted through a TDX aware firmware (TDVF
+    /// or td-shim) which is measured into the launch measurement
+    Tdx { firmware: PathBuf },
 }
 
 impl From<VmSpec> for vmm::vm_config::VmConfig {
     fn from(spec: VmSpec) -> Self {
+        // vhost-user backends access guest memory directly
+        let shared_memory = spec.net.iter().any(|n| n.vhost_socket.is_some());
+        // Default to one queue (pair) per vCPU for disks and network
+        // interfaces so that vCPUs can submit requests concurrently
+        let vcpus = spec.vcpu_count.max(1);
+        let (firmware, platform) = match spec.confidential {
+            Some(ConfidentialSpec::Tdx { firmware }) => (
+                Some(firmware),
+                PlatformConfig {
+                    #[cfg(feature = "tdx")]
+                    tdx: true,
+                    ..Default::default()
+                },
+            ),
+            None => (spec.firmware_path, PlatformConfig::default()),
+        };
+        let platform = PlatformConfig {
+            uuid: spec.smbios.uuid.map(|uuid| uuid.to_string()),
+            serial_number: spec.smbios.serial_number,
+            oem_strings: (!spec.smbios.oem_strings.is_empty())
+                .then_some(spec.smbios.oem_strings),
+            ..platform
+        };
+        let kernel = (!spec.kernel_image_path.as_os_str().is_empty())
+            .then_some(spec.kernel_image_path);
+        let (serial, console) = match spec.serial_socket {
+            Some(socket) => (
+                ConsoleConfig {
+                    file: None,
+                    mode: ConsoleOutputMode::Socket,
+                    iommu: false,
+                    socket: Some(socket),
+                },
+                ConsoleConfig {
+                    file: None,
+                    mode: ConsoleOutputMode::Off,
+                    iommu: false,
+                    socket: None,
+                },
+            ),
+            None => (default_serial(), default_console()),
+        };
         vmm::vm_config::VmConfig {
             cpus: CpusConfig {
                 boot_vcpus: spec.vcpu_count as u8,
                 max_vcpus: spec.vcpu_count as u8,
-                topology: None,
+                topology: spec.cpu_topology.map(Into::into),
                 kvm_hyperv: false,
                 max_phys_bits: DEFAULT_MAX_PHYS_BITS,
-                affinity: None,
+                affinity: (!spec.vcpu_affinity.is_empty()).then(|| {
+                    spec.vcpu_affinity.into_iter().map(Into::into).collect()
+                }),
                 features: CpuFeatures::default(),
             },
             memory: MemoryConfig {
@@ -76,46 +314,158 @@ impl From<VmSpec> for vmm::vm_config::VmConfig {
                 hotplug_method: HotplugMethod::default(),
                 hotplug_size: None,
                 hotplugged_size: None,
-                shared: false,
-                hugepages: false,
-                hugepage_size: None,
-                prefault: false,
+                shared: shared_memory,
+                hugepages: spec.guest_memory.hugepages,
+                hugepage_size: spec.guest_memory.hugepage_size,
+                prefault: spec.guest_memory.prefault,
                 zones: None,
                 thp: false,
             },
             payload: Some(PayloadConfig {
-                firmware: None,
-                kernel: Some(spec.kernel_image_path),
-                cmdline: Some(spec.kernel_args.join(" ")),
-                initramfs: None,
+                firmware,
+                cmdline: kernel.is_some().then(|| spec.kernel_args.join(" ")),
+                kernel,
+                initramfs: spec.initramfs_path,
             }),
             rate_limit_groups: None,
-            disks: Some(spec.mounts.into_iter().map(Into::into).collect()),
-            net: Some(spec.net.into_iter().map(Into::into).collect()),
+            disks: Some(
+                spec.mounts
+                    .into_iter()
+                    .map(|m| vmm::vm_config::DiskConfig {
+                        num_queues: vcpus as usize,
+                        ..m.into()
+                    })
+                    .collect(),
+            ),
+            net: Some(
+                spec.net
+                    .into_iter()
+                    .map(|mut n| {
+                        let _ = n.queue_pairs.get_or_insert(vcpus);
+                        n.into()
+                    })
+                    .collect(),
+            ),
             rng: RngConfig::default(),
             balloon: None,
             fs: None,
             pmem: None,
-            serial: default_serial(),
-            console: default_console(),
+            serial,
+            console,
             debug_console: DebugConsoleConfig::default(),
-            devices: None,
+            devices: (!spec.vfio_devices.is_empty()).then(|| {
+                spec.vfio_devices
+                    .into_iter()
+                    .map(|path| DeviceConfig {
+                        path,
+                        iommu: false,
+                        id: None,
+                        pci_segment: 0,
+                        x_nv_gpudirect_clique: None,
+                    })
+                    .collect()
+            }),
             user_devices: None,
-            vdpa: None,
-            vsock: None,
+            vdpa: (!spec.vdpa.is_empty())
+                .then(|| spec.vdpa.into_iter().map(Into::into).collect()),
+            vsock: spec.vsock_socket.map(|socket| VsockConfig {
+                cid: GUEST_CID,
+                socket,
+                iommu: false,
+                id: None,
+                pci_segment: 0,
+            }),
             pvpanic: false,
             iommu: false,
             sgx_epc: None,
             numa: None,
-            watchdog: false,
+            watchdog: spec.watchdog,
             pci_segments: None,
-            platform: None,
+            platform: Some(platform),
             tpm: None,
             preserved_fds: None,
         }
     }
 }
 
+impl From<&vmm::vm_config::VmConfig> for VmSpec {
+    fn from(config: &vmm::vm_config::VmConfig) -> Self {
+        let payload = config.payload.clone().unwrap_or_default();
+        #[cfg(feature = "tdx")]
+        let tdx = config.platform.as_ref().is_some_and(|p| p.tdx);
+        #[cfg(not(feature = "tdx"))]
+        let tdx = false;
+        let (confidential, firmware_path) = if tdx {
+            let firmware = payload.firmware.clone().unwrap_or_default();
+            (Some(ConfidentialSpec::Tdx { firmware }), None)
+        } else {
+            (None, payload.firmware.clone())
+        };
+        VmSpec {
+            memory_size: (config.memory.size >> 20) as u32,
+            vcpu_count: config.cpus.boot_vcpus as u32,
+            kernel_image_path: payload.kernel.unwrap_or_default(),
+            initramfs_path: payload.initramfs,
+            kernel_args: payload
+                .cmdline
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(Into::into)
+                .collect(),
+            mounts: config
+                .disks
+                .iter()
+                .flatten()
+                .map(|d| MountSpec {
+                    host_path: d.path.clone().unwrap_or_default(),
+                    read_only: d.readonly,
+                })
+                .collect(),
+            net: config.net.iter().flatten().map(Into::into).collect(),
+            vfio_devices: config
+                .devices
+                .iter()
+                .flatten()
+                .map(|d| d.path.clone())
+                .collect(),
+            vdpa: config.vdpa.iter().flatten().map(Into::into).collect(),
+            confidential,
+            firmware_path,
+            serial_socket: config.serial.socket.clone(),
+            vsock_socket: config
+                .vsock
+                .as_ref()
+                .map(|vsock| vsock.socket.clone()),
+            vcpu_affinity: config
+                .cpus
+                .affinity
+                .iter()
+                .flatten()
+                .map(Into::into)
+                .collect(),
+            cpu_topology: config.cpus.topology.as_ref().map(Into::into),
+            guest_memory: GuestMemorySpec {
+                hugepages: config.memory.hugepages,
+                hugepage_size: config.memory.hugepage_size,
+                prefault: config.memory.prefault,
+            },
+            smbios: config
+                .platform
+                .as_ref()
+                .map(|p| SmbiosSpec {
+                    uuid: p.uuid.as_deref().and_then(|u| u.parse().ok()),
+                    serial_number: p.serial_number.clone(),
+                    oem_strings: p.oem_strings.clone().unwrap_or_default(),
+                })
+                .unwrap_or_default(),
+            // Applied to the hypervisor, not part of the VMM config. A
+            // restored or migrated guest keeps the CPUID in its vCPU state.
+            cpuid_masks: vec![],
+            watchdog: config.watchdog,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NetSpec {
     pub tap: Option<String>,
@@ -123,30 +473,87 @@ pub struct NetSpec {
     pub mask: Ipv4Addr,
     pub mac: MacAddr,
     pub host_mac: Option<MacAddr>,
+    /// Socket of a vhost-user-net backend handling the data path
+    pub vhost_socket: Option<String>,
+    /// Number of RX/TX queue pairs, defaults to one per vCPU
+    pub queue_pairs: Option<u32>,
+    /// MTU advertised to the guest, defaults to the MTU of the TAP device
+    pub mtu: Option<u16>,
 }
 
 impl From<NetSpec> for vmm::vm_config::NetConfig {
     fn from(spec: NetSpec) -> Self {
+        let vhost_user = spec.vhost_socket.is_some();
         vmm::vm_config::NetConfig {
-            tap: spec.tap,
+            tap: if vhost_user { None } else { spec.tap },
             ip: spec.ip,
             mask: spec.mask,
             mac: spec.mac,
             host_mac: spec.host_mac,
-            mtu: None,
+            mtu: spec.mtu,
             iommu: false,
-            num_queues: DEFAULT_NET_NUM_QUEUES,
+            num_queues: spec
+                .queue_pairs
+                .map_or(DEFAULT_NET_NUM_QUEUES, |p| 2 * p as usize),
             queue_size: DEFAULT_NET_QUEUE_SIZE,
-            vhost_user: false,
-            vhost_socket: None,
+            vhost_user,
+            vhost_socket: spec.vhost_socket,
             vhost_mode: VhostMode::default(),
             id: None,
             fds: None,
             rate_limiter_config: None,
             pci_segment: 0,
-            offload_tso: false,
-            offload_ufo: false,
-            offload_csum: false,
+            // Cloud Hypervisor has no in-kernel vhost-net backend, the TAP
+            // data path stays in the VMM. Let the guest hand it large,
+            // unchecksummed segments so it is not bound by per-packet copies.
+            offload_tso: true,
+            offload_ufo: true,
+            offload_csum: true,
+        }
+    }
+}
+
+impl From<&vmm::vm_config::NetConfig> for NetSpec {
+    fn from(config: &vmm::vm_config::NetConfig) -> Self {
+        NetSpec {
+            tap: config.tap.clone(),
+            ip: config.ip,
+            mask: config.mask,
+            mac: config.mac,
+            host_mac: config.host_mac,
+            vhost_socket: config.vhost_socket.clone(),
+            queue_pairs: Some((config.num_queues / 2) as u32),
+            mtu: config.mtu,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VdpaSpec {
+    /// The vhost-vdpa character device, e.g. /dev/vhost-vdpa-0
+    pub path: PathBuf,
+    /// Number of RX/TX queue pairs, defaults to one as the number of
+    /// queues is bounded by the hardware
+    pub queue_pairs: Option<u32>,
+}
+
+impl From<VdpaSpec> for vmm::vm_config::VdpaConfig {
+    fn from(spec: VdpaSpec) -> Self {
+        vmm::vm_config::VdpaConfig {
+            path: spec.path,
+            num_queues: 2 * spec.queue_pairs.unwrap_or(1) as usize,
+            iommu: false,
+            id: None,
+            pci_segment: 0,
+        }
+    }
+}
+
+impl From<&vmm::vm_config::VdpaConfig> for VdpaSpec {
+    fn from(config: &vmm::vm_config::VdpaConfig) -> Self {
+        VdpaSpec {
+            path: config.path.clone(),
+            queue_pairs: Some((config.num_queues / 2) as u32),
         }
     }
 }
@@ -186,6 +593,14 @@ pub struct VirtualMachine {
     pub vm: VmSpec,
     pub status: VmStatus,
     manager: Arc<Mutex<Manager>>,
+    vcpu_cgroups: Option<VcpuCgroups>,
+    kvm_vm_fd: Option<i32>,
+    /// Where guest memory is mapped in auraed, empty if unknown
+    guest_memory: Vec<Mapping>,
+    /// Thread ids of the vCPUs, recreated by the VMM when the guest is reset
+    vcpu_threads: HashSet<i32>,
+    /// Times the guest was reset since auraed started managing the VM
+    pub resets: u32,
 }
 
 impl fmt::Debug for VirtualMachine {
@@ -204,9 +619,14 @@ impl Display for VmStatus {
 }
 
 impl VirtualMachine {
-    pub fn new(id: VmID, spec: VmSpec) -> Result<Self, anyhow::Error> {
+    pub fn new(
+        id: VmID,
+        spec: VmSpec,
+        jailer: &JailerConfig,
+    ) -> Result<Self, anyhow::Error> {
         let mut manager = Manager::new();
-        manager.start()?;
+        manager.mask_cpuid(&spec.cpuid_masks);
+        manager.start(jailer)?;
 
         if let Some(sender) = &manager.sender {
             vmm::api::VmCreate
@@ -225,9 +645,137 @@ impl VirtualMachine {
             vm: spec,
             status: VmStatus(VmState::Created),
             manager: Arc::new(Mutex::new(manager)),
+            vcpu_cgroups: None,
+            kvm_vm_fd: None,
+            guest_memory: vec![],
+            vcpu_threads: HashSet::new(),
+            resets: 0,
         })
     }
 
+    /// Restore a VM from a snapshot directory into a new VMM instance. The
+    /// restored VM is resumed before it is returned.
+    pub fn restore(
+        id: VmID,
+        source: &Path,
+        prefault: bool,
+        jailer: &JailerConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let mut manager = Manager::new();
+        manager.start(jailer)?;
+
+        let Some(sender) = manager.sender.clone() else {
+            return Err(anyhow!("Virtual machine manager not initialized"));
+        };
+
+        let before = vcpus::threads()?;
+        let before_fds = metrics::kvm_vm_fds()?;
+        let before_mappings = dirty_pages::anonymous_mappings()?;
+        let _ = vmm::api::VmRestore
+            .send(
+                manager.events.try_clone()?,
+                sender.clone(),
+                RestoreConfig {
+                    source_url: PathBuf::from(format!(
+                        "file://{}",
+                        source.display()
+                    )),
+                    prefault,
+                    ..Default::default()
+                },
+            )
+            .map_err(|e| anyhow!("Failed to send restore request: {e}"))?;
+
+        let res = vmm::api::VmInfo
+            .send(manager.events.try_clone()?, sender, ())
+            .map_err(|e| anyhow!("Failed to send info request: {e}"))?;
+        let spec = VmSpec::from(
+            &*res
+                .config
+                .lock()
+                .map_err(|_| anyhow!("Failed to aquire lock for vm config"))?,
+        );
+
+        let mut vm = VirtualMachine {
+            id,
+            vm: spec,
+            status: VmStatus(VmState::Paused),
+            manager: Arc::new(Mutex::new(manager)),
+            vcpu_cgroups: None,
+            kvm_vm_fd: metrics::new_kvm_vm_fd(&before_fds),
+            guest_memory: vec![],
+            vcpu_threads: HashSet::new(),
+            resets: 0,
+        };
+        vm.find_guest_memory(&before_mappings);
+        vm.place_vcpus(&before);
+        vm.resume()?;
+        vm.lock_jail()?;
+        Ok(vm)
+    }
+
+    /// Receive a VM migrated from another node into a new VMM instance,
+    /// listening on `receiver_url`. Blocks until the migration completes.
+    pub fn receive_migration(
+        id: VmID,
+        receiver_url: &str,
+        jailer: &JailerConfig,
+    ) -> Result<Self, anyhow::Error> {
+        let mut manager = Manager::new();
+        manager.start(jailer)?;
+
+        let Some(sender) = manager.sender.clone() else {
+            return Err(anyhow!("Virtual machine manager not initialized"));
+        };
+
+        let before = vcpus::threads()?;
+        let before_fds = metrics::kvm_vm_fds()?;
+        let before_mappings = dirty_pages::anonymous_mappings()?;
+        let _ = vmm::api::VmReceiveMigration
+            .send(
+                manager.events.try_clone()?,
+                sender.clone(),
+                VmReceiveMigrationData { receiver_url: receiver_url.into() },
+            )
+            .map_err(|e| anyhow!("Failed to receive migration: {e}"))?;
+
+        let res = vmm::api::VmInfo
+            .send(manager.events.try_clone()?, sender, ())
+            .map_err(|e| anyhow!("Failed to send info request: {e}"))?;
+        let spec = VmSpec::from(
+            &*res
+                .config
+                .lock()
+                .map_err(|_| anyhow!("Failed to aquire lock for vm config"))?,
+        );
+
+        let mut vm = VirtualMachine {
+            id,
+            vm: spec,
+            status: VmStatus(VmState::Running),
+            manager: Arc::new(Mutex::new(manager)),
+            vcpu_cgroups: None,
+            kvm_vm_fd: metrics::new_kvm_vm_fd(&before_fds),
+            guest_memory: vec![],
+            vcpu_threads: HashSet::new(),
+            resets: 0,
+        };
+        vm.find_guest_memory(&before_mappings);
+        vm.place_vcpus(&before);
+        vm.lock_jail()?;
+        Ok(vm)
+    }
+
+    /// Chroots the VMM threads, if configured, once the guest resources are
+    /// set up.
+    fn lock_jail(&self) -> Result<(), anyhow::Error> {
+        self.manager
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?
+            .jail
+            .lock()
+    }
+
     pub fn start(&mut self) -> Result<(), anyhow::Error> {
         if let VmState::Running = self.status.0 {
             return Err(anyhow!("Virtual machine already running"));
@@ -237,6 +785,9 @@ impl VirtualMachine {
             .lock()
             .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?;
 
+        let before = vcpus::threads()?;
+        let before_fds = metrics::kvm_vm_fds()?;
+        let before_mappings = dirty_pages::anonymous_mappings()?;
         if let Some(sender) = &manager.sender {
             let _ = vmm::api::VmBoot
                 .send(manager.events.try_clone()?, sender.clone(), ())
@@ -245,20 +796,17 @@ impl VirtualMachine {
         } else {
             return Err(anyhow!("Virtual machine manager not initialized"))?;
         }
+        drop(manager);
+        // The guest resources are set up once the VM booted
+        self.lock_jail()?;
+        self.place_vcpus(&before);
+        self.kvm_vm_fd = metrics::new_kvm_vm_fd(&before_fds);
+        self.find_guest_memory(&before_mappings);
 
         // Update the VM with the network device information if it wasn't provided
         if self.vm.net.is_empty() {
             if let Some(net) = &self.info()?.net {
-                self.vm.net = net
-                    .iter()
-                    .map(|n| NetSpec {
-                        tap: n.tap.clone(),
-                        ip: n.ip,
-                        mask: n.mask,
-                        mac: n.mac,
-                        host_mac: n.host_mac,
-                    })
-                    .collect();
+                self.vm.net = net.iter().map(Into::into).collect();
             }
         }
 
@@ -279,6 +827,8 @@ impl VirtualMachine {
                 .send(manager.events.try_clone()?, sender.clone(), ())
                 .map_err(|e| anyhow!("Failed to send stop request: {e}"))?;
             self.status = VmStatus(VmState::Shutdown);
+            self.kvm_vm_fd = None;
+            self.guest_memory.clear();
         } else {
             return Err(anyhow!("Virtual machine manager not initialized"));
         }
@@ -286,6 +836,147 @@ impl VirtualMachine {
         Ok(())
     }
 
+    /// Pause the vCPUs of a running VM. Devices are quiesced by the VMM
+    /// before the call returns.
+    pub fn pause(&mut self) -> Result<(), anyhow::Error> {
+        if self.status.0 != VmState::Running {
+            return Err(anyhow!("Virtual machine is not running"));
+        }
+        let manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?;
+
+        if let Some(sender) = &manager.sender {
+            let _ = vmm::api::VmPause
+                .send(manager.events.try_clone()?, sender.clone(), ())
+                .map_err(|e| anyhow!("Failed to send pause request: {e}"))?;
+            self.status = VmStatus(VmState::Paused);
+        } else {
+            return Err(anyhow!("Virtual machine manager not initialized"));
+        }
+
+        Ok(())
+    }
+
+    /// Resume a paused VM
+    pub fn resume(&mut self) -> Result<(), anyhow::Error> {
+        if self.status.0 != VmState::Paused {
+            return Err(anyhow!("Virtual machine is not paused"));
+        }
+        let manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?;
+
+        if let Some(sender) = &manager.sender {
+            let _ = vmm::api::VmResume
+                .send(manager.events.try_clone()?, sender.clone(), ())
+                .map_err(|e| anyhow!("Failed to send resume request: {e}"))?;
+            self.status = VmStatus(VmState::Running);
+        } else {
+            return Err(anyhow!("Virtual machine manager not initialized"));
+        }
+
+        Ok(())
+    }
+
+    /// Write the guest memory, vCPU and device state of the VM to the
+    /// `destination` directory. A running VM is paused while the snapshot is
+    /// taken and resumed afterwards.
+    pub fn snapshot(
+        &mut self,
+        destination: &Path,
+    ) -> Result<(), anyhow::Error> {
+        let running = self.status.0 == VmState::Running;
+        if running {
+            self.pause()?;
+        } else if self.status.0 != VmState::Paused {
+            return Err(anyhow!("Virtual machine is not running"));
+        }
+
+        let res = self.send_snapshot(destination);
+        if running {
+            self.resume()?;
+        }
+        res
+    }
+
+    /// Snapshot the VM into `destination` and leave it paused, so that it
+    /// does not diverge from the snapshot until the host goes down
+    pub fn checkpoint(
+        &mut self,
+        destination: &Path,
+    ) -> Result<(), anyhow::Error> {
+        if self.status.0 == VmState::Running {
+            self.pause()?;
+        } else if self.status.0 != VmState::Paused {
+            return Err(anyhow!("Virtual machine is not running"));
+        }
+
+        self.send_snapshot(destination)
+    }
+
+    fn send_snapshot(&self, destination: &Path) -> Result<(), anyhow::Error> {
+        let manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?;
+
+        if let Some(sender) = &manager.sender {
+            let _ = vmm::api::VmSnapshot
+                .send(
+                    manager.events.try_clone()?,
+                    sender.clone(),
+                    VmSnapshotConfig {
+                        destination_url: format!(
+                            "file://{}",
+                            destination.display()
+                        ),
+                    },
+                )
+                .map_err(|e| anyhow!("Failed to send snapshot request: {e}"))?;
+            return Ok(());
+        }
+        Err(anyhow!("Virtual machine manager not initialized"))
+    }
+
+    /// Live migrate the VM to the node listening on `destination_url`.
+    /// Blocks until the migration completes, after which the VM is shut down
+    /// on this node, see [VirtualMachine::migrated].
+    pub fn migrate(&self, destination_url: &str) -> Result<(), anyhow::Error> {
+        if self.status.0 != VmState::Running {
+            return Err(anyhow!("Virtual machine is not running"));
+        }
+        let manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?;
+
+        if let Some(sender) = &manager.sender {
+            let _ = vmm::api::VmSendMigration
+                .send(
+                    manager.events.try_clone()?,
+                    sender.clone(),
+                    VmSendMigrationData {
+                        destination_url: destination_url.into(),
+                        local: false,
+                    },
+                )
+                .map_err(|e| anyhow!("Failed to send migration: {e}"))?;
+        } else {
+            return Err(anyhow!("Virtual machine manager not initialized"));
+        }
+
+        Ok(())
+    }
+
+    /// Marks the VM as shut down after it was migrated to another node
+    pub fn migrated(&mut self) {
+        self.status = VmStatus(VmState::Shutdown);
+        self.guest_memory.clear();
+    }
+
     pub fn delete(&mut self) -> Result<(), anyhow::Error> {
         if self.status.0 != VmState::Shutdown {
             self.stop()?;
@@ -299,11 +990,148 @@ impl VirtualMachine {
             let _ = vmm::api::VmDelete
                 .send(manager.events.try_clone()?, sender.clone(), ())
                 .map_err(|e| anyhow!("Failed to send destroy request: {e}"))?;
+            if let Some(cgroups) = self.vcpu_cgroups.take() {
+                if let Err(e) = cgroups.remove() {
+                    warn!(
+                        "Failed to remove vcpu cgroups of vm '{}': {e}",
+                        self.id
+                    );
+                }
+            }
             return Ok(());
         }
         Err(anyhow!("Virtual machine manager not initialized"))
     }
 
+    /// Moves the vCPU threads started since `before` into dedicated cgroups.
+    /// The VM keeps running in the cgroup of auraed if that fails.
+    fn place_vcpus(&mut self, before: &HashSet<i32>) {
+        match vcpus::vcpu_threads(before) {
+            Ok(threads) => self.vcpu_threads = threads,
+            Err(e) => {
+                warn!("Failed to find the vcpus of vm '{}': {e}", self.id)
+            }
+        }
+        match VcpuCgroups::place(&self.id, before, &self.vm.vcpu_affinity) {
+            Ok(cgroups) => self.vcpu_cgroups = Some(cgroups),
+            Err(e) => {
+                warn!(
+                    "Failed to place vcpus of vm '{}' in cgroups: {e}",
+                    self.id
+                )
+            }
+        }
+    }
+
+    /// Thread ids of the vCPUs, empty if unknown
+    pub(crate) fn vcpu_threads(&self) -> &HashSet<i32> {
+        &self.vcpu_threads
+    }
+
+    /// Checks whether the guest of the running VM was reset, e.g. by the
+    /// watchdog, since its vCPUs were last placed. The VMM recreates the
+    /// KVM VM and its vCPU threads on reset, so the new vCPU threads, those
+    /// not in `known`, are placed in the vCPU cgroups again, while KVM
+    /// statistics and guest memory are unknown from then on. Should several
+    /// VMs be reset at once, their new vCPUs are all placed with the first.
+    /// A guest that powered off has no new vCPU threads and is not reset.
+    pub(crate) fn check_reset(
+        &mut self,
+        known: &HashSet<i32>,
+    ) -> Result<bool, anyhow::Error> {
+        if !matches!(self.status.0, VmState::Running)
+            || self.vcpu_threads.is_empty()
+        {
+            return Ok(false);
+        }
+        let threads = vcpus::threads()?;
+        if !self.vcpu_threads.is_disjoint(&threads) {
+            return Ok(false);
+        }
+        let new = vcpus::vcpu_threads(known)?;
+        if new.is_empty() {
+            return Ok(false);
+        }
+
+        let before = threads.difference(&new).copied().collect();
+        self.place_vcpus(&before);
+        self.kvm_vm_fd = None;
+        self.guest_memory.clear();
+        self.resets += 1;
+        Ok(true)
+    }
+
+    /// Looks up the guest memory mapped since `before` was taken. Dirty
+    /// pages of the VM cannot be tracked if that fails.
+    fn find_guest_memory(&mut self, before: &HashSet<Mapping>) {
+        let memory_size = u64::from(self.vm.memory_size) << 20;
+        match dirty_pages::new_guest_memory(before, memory_size) {
+            Some(memory) => self.guest_memory = memory,
+            None => {
+                warn!("Failed to find the guest memory of vm '{}'", self.id)
+            }
+        }
+    }
+
+    /// Where guest memory is mapped in auraed, empty if unknown
+    pub(crate) fn guest_memory(&self) -> &[Mapping] {
+        &self.guest_memory
+    }
+
+    /// Samples the runtime metrics of the VM. vCPU exits and guest memory
+    /// RSS are left empty if the KVM debugfs cannot be read.
+    pub fn metrics(&self) -> Result<VmMetrics, anyhow::Error> {
+        let manager = self
+            .manager
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm manager"))?;
+        let Some(sender) = &manager.sender else {
+            return Err(anyhow!("Virtual machine manager not initialized"));
+        };
+
+        let counters = vmm::api::VmCounters
+            .send(manager.events.try_clone()?, sender.clone(), ())
+            .map_err(|e| anyhow!("Failed to send counters request: {e}"))?;
+        let info = vmm::api::VmInfo
+            .send(manager.events.try_clone()?, sender.clone(), ())
+            .map_err(|e| anyhow!("Failed to send info request: {e}"))?;
+        drop(manager);
+
+        let (block_devices, net_devices) = match counters {
+            Some(counters) => metrics::device_metrics(&counters)?,
+            None => Default::default(),
+        };
+        let memory_size = info
+            .config
+            .lock()
+            .map_err(|_| anyhow!("Failed to aquire lock for vm config"))?
+            .memory
+            .total_size();
+        let kvm_stats = self
+            .kvm_vm_fd
+            .and_then(|fd| KvmStats::read(fd).ok())
+            .unwrap_or_default();
+
+        Ok(VmMetrics {
+            vm_id: self.id.to_string(),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or_default(),
+            vcpu_exits: kvm_stats.vcpu_exits,
+            guest_memory_rss: kvm_stats.guest_memory_rss,
+            block_devices,
+            net_devices,
+            balloon_size: memory_size.saturating_sub(info.memory_actual_size),
+            resets: self.resets,
+        })
+    }
+
+    /// Returns true if the VM has not been booted or has been shut down
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.status.0, VmState::Created | VmState::Shutdown)
+    }
+
     fn info(&self) -> Result<vmm::vm_config::VmConfig, anyhow::Error> {
         let manager = self
             .manager
@@ -352,9 +1180,71 @@ mod tests {
     use net_util::MacAddr;
 
     use crate::vms::virtual_machine::{
-        MountSpec, NetSpec, VirtualMachine, VmID, VmSpec,
+        MountSpec, NetSpec, SmbiosSpec, VirtualMachine, VmID, VmSpec,
     };
 
+    #[test]
+    fn smbios_identity_survives_vm_config() {
+        let id = VmID::new("test_vm");
+        let smbios = SmbiosSpec::new(&id, None, None);
+        let spec = VmSpec {
+            memory_size: 1024,
+            vcpu_count: 1,
+            kernel_image_path: PathBuf::from(
+                "/var/lib/aurae/vm/kernel/vmlinux.bin",
+            ),
+            initramfs_path: None,
+            kernel_args: vec![],
+            mounts: vec![],
+            net: vec![],
+            vfio_devices: vec![],
+            vdpa: vec![],
+            confidential: None,
+            firmware_path: None,
+            serial_socket: None,
+            vsock_socket: None,
+            vcpu_affinity: vec![],
+            cpu_topology: None,
+            guest_memory: Default::default(),
+            smbios: smbios.clone(),
+            cpuid_masks: vec![],
+            watchdog: false,
+        };
+
+        let config = vmm::vm_config::VmConfig::from(spec);
+        let platform = config.platform.as_ref().unwrap();
+        assert_eq!(platform.serial_number.as_deref(), Some("test_vm"));
+        assert_eq!(
+            platform.oem_strings.as_deref(),
+            Some(&["Aurae".to_string(), "aurae.vm.id=test_vm".to_string()][..])
+        );
+
+        let spec = VmSpec::from(&config);
+        assert!(spec.smbios.uuid.is_some());
+        assert_eq!(spec.smbios.uuid, smbios.uuid);
+        assert_eq!(spec.smbios.serial_number, smbios.serial_number);
+        assert_eq!(spec.smbios.oem_strings, smbios.oem_strings);
+    }
+
+    #[test]
+    fn tap_interfaces_offload_to_the_host() {
+        let config = vmm::vm_config::NetConfig::from(NetSpec {
+            tap: Some("tap0".to_string()),
+            ip: Ipv4Addr::new(192, 168, 249, 1),
+            mask: Ipv4Addr::new(255, 255, 255, 255),
+            mac: MacAddr::local_random(),
+            host_mac: None,
+            vhost_socket: None,
+            queue_pairs: Some(4),
+            mtu: None,
+        });
+        assert!(!config.vhost_user);
+        assert_eq!(config.num_queues, 8);
+        assert!(config.offload_tso);
+        assert!(config.offload_ufo);
+        assert!(config.offload_csum);
+    }
+
     #[test]
     #[ignore]
     fn test_create_vm() {
@@ -365,6 +1255,7 @@ mod tests {
             kernel_image_path: PathBuf::from(
                 "/var/lib/aurae/vm/kernel/vmlinux.bin",
             ),
+            initramfs_path: None,
             kernel_args: vec![
                 "console=hvc0".to_string(),
                 "root=/dev/vda1".to_string(),
@@ -379,10 +1270,27 @@ mod tests {
                 mask: Ipv4Addr::new(255, 255, 255, 255),
                 mac: MacAddr::local_random(),
                 host_mac: None,
+                vhost_socket: None,
+                queue_pairs: None,
+                mtu: None,
             }],
+            vfio_devices: vec![],
+            vdpa: vec![],
+            confidential: None,
+            firmware_path: None,
+            serial_socket: None,
+            vsock_socket: None,
+            vcpu_affinity: vec![],
+            cpu_topology: None,
+            guest_memory: Default::default(),
+            smbios: SmbiosSpec::new(&id, None, None),
+            cpuid_masks: vec![],
+            watchdog: false,
         };
 
-        let mut vm = VirtualMachine::new(id.clone(), spec).unwrap();
+        let mut vm =
+            VirtualMachine::new(id.clone(), spec, &JailerConfig::default())
+                .unwrap();
         assert_eq!(vm.id, id);
 
         assert!(vm.start().is_ok(), "{:?}", vm);
@@ -12,14 +12,24 @@
  * Copyright 2022 - 2024, the aurae contributors                              *
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
-use std::{collections::HashMap, net::Ipv4Addr};
+use std::{
+    collections::{HashMap, HashSet},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+};
 
 use anyhow::anyhow;
 use net_util::MacAddr;
-use tracing::error;
-use vmm_sys_util::{rand, signal::block_signal};
+use tracing::{error, warn};
+use vmm_sys_util::signal::block_signal;
 
-use super::virtual_machine::{NetSpec, VirtualMachine, VmID, VmSpec};
+use super::{
+    dirty_pages::{DirtyBitmap, DirtyPageTracker},
+    ipam::{self, Ipam, Lease},
+    jailer::JailerConfig,
+    tap,
+    virtual_machine::{NetSpec, VirtualMachine, VmID, VmSpec},
+};
 
 type Cache = HashMap<VmID, VirtualMachine>;
 
@@ -27,17 +37,25 @@ type Cache = HashMap<VmID, VirtualMachine>;
 #[derive(Debug)]
 pub struct VirtualMachines {
     cache: Cache,
+    dirty_pages: DirtyPageTracker,
+    jailer: JailerConfig,
+    ipam: Ipam,
+    /// IDs reserved for virtual machines being received from another node
+    incoming: HashSet<VmID>,
+    /// IDs of virtual machines being migrated to another node
+    outgoing: HashSet<VmID>,
 }
 
 impl Default for VirtualMachines {
     fn default() -> Self {
-        Self::new()
+        Self::new(JailerConfig::default())
     }
 }
 
 impl VirtualMachines {
-    /// Create a new instance of the virtual machines cache.
-    pub fn new() -> Self {
+    /// Create a new instance of the virtual machines cache, whose VMMs are
+    /// confined as configured by `jailer`.
+    pub fn new(jailer: JailerConfig) -> Self {
         unsafe {
             let _ = libc::signal(libc::SIGCHLD, libc::SIG_IGN);
         }
@@ -55,15 +73,56 @@ impl VirtualMachines {
             }
         }
 
-        Self { cache: Cache::new() }
+        Self {
+            cache: Cache::new(),
+            dirty_pages: Default::default(),
+            jailer,
+            ipam: Ipam::new(ipam::default_pool()),
+            incoming: HashSet::new(),
+            outgoing: HashSet::new(),
+        }
+    }
+
+    /// How the VMMs of the virtual machines are confined
+    pub fn jailer(&self) -> &JailerConfig {
+        &self.jailer
+    }
+
+    /// The network the addresses of provisioned interfaces are leased from
+    pub fn address_pool(&self) -> ipnetwork::Ipv4Network {
+        self.ipam.pool()
     }
 
-    /// Allocate an IP address for a new virtual machine
-    ///
-    /// Use the hard-coded Cloud Hypervisor default address as starting IP
-    /// https://github.com/cloud-hypervisor/cloud-hypervisor/blob/165c2c476f752909aba41d4e319f12ade20b72d3/vmm/src/vm_config.rs#L313-L319
-    fn allocate_ip(&self) -> Ipv4Addr {
-        Ipv4Addr::new(192, 168, 249, self.cache.len() as u8 + 1)
+    /// The addressing leased to the interfaces of a virtual machine by its ID
+    pub(crate) fn leases(&self, id: &VmID) -> Vec<Lease> {
+        self.ipam.leases(id)
+    }
+
+    /// The TAP devices auraed provisioned for a virtual machine by its ID
+    pub fn provisioned_taps(&self, id: &VmID) -> Vec<String> {
+        self.cache
+            .get(id)
+            .map(|vm| {
+                vm.vm
+                    .net
+                    .iter()
+                    .filter_map(|n| n.tap.clone())
+                    .filter(|t| tap::is_provisioned(t))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Record the addresses of a restored or received virtual machine, so
+    /// they are not leased twice
+    fn reserve_addresses(&mut self, vm: &VirtualMachine) {
+        for net in &vm.vm.net {
+            if self.ipam.pool().contains(net.ip)
+                && !self.ipam.reserve(&vm.id, net.ip)
+            {
+                warn!("Address {} of vm '{}' is leased twice", net.ip, vm.id);
+            }
+        }
     }
 
     /// Create a new virtual machine
@@ -79,62 +138,420 @@ impl VirtualMachines {
                 vm.vm,
             ));
         }
+        if self.incoming.contains(&id) {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' is being received",
+                &id
+            ));
+        }
 
-        // Populate the default network configuration if it's empty
-        if spec.net.is_empty() {
+        // Provision a TAP device if the VM has no network interfaces
+        if spec.net.is_empty() && spec.vdpa.is_empty() {
             spec.net.push(NetSpec {
-                tap: Some(format!(
-                    "auraed-{}",
-                    rand::rand_alphanumerics(6).into_string().map_err(
-                        |_| anyhow!("Error generating TAP device name")
-                    )?,
-                )),
-                ip: self.allocate_ip(),
-                mask: Ipv4Addr::new(255, 255, 255, 0),
+                tap: Some(tap::name()?),
+                ip: Ipv4Addr::UNSPECIFIED,
+                mask: Ipv4Addr::UNSPECIFIED,
                 mac: MacAddr::local_random(),
                 host_mac: None,
+                vhost_socket: None,
+                queue_pairs: None,
+                mtu: None,
             });
         }
 
-        let vm = VirtualMachine::new(id.clone(), spec)?;
+        // Interfaces requested without addressing get a subnet of the pool
+        for net in spec.net.iter_mut().filter(|n| n.ip.is_unspecified()) {
+            let Some(lease) = self.ipam.allocate(&id) else {
+                self.ipam.release(&id);
+                return Err(anyhow!(
+                    "No addresses left in {}",
+                    self.ipam.pool()
+                ));
+            };
+            net.ip = lease.host;
+            net.mask = Lease::MASK;
+        }
+
+        // Directly booted guests configure their first interface on boot,
+        // others have to be told their address, e.g. through cloud-init
+        if let Some(lease) = self.ipam.leases(&id).first() {
+            if !spec.kernel_image_path.as_os_str().is_empty()
+                && !spec.kernel_args.iter().any(|a| a.starts_with("ip="))
+            {
+                spec.kernel_args.push(lease.kernel_arg("eth0"));
+            }
+        }
+
+        let vm = match VirtualMachine::new(id.clone(), spec, &self.jailer) {
+            Ok(vm) => vm,
+            Err(e) => {
+                self.ipam.release(&id);
+                return Err(e);
+            }
+        };
         let _ = self.cache.insert(id, vm.clone()).is_none();
         Ok(vm)
     }
 
+    /// Restore a new virtual machine from the snapshot in `source`
+    pub fn restore(
+        &mut self,
+        id: VmID,
+        source: &Path,
+        prefault: bool,
+    ) -> Result<VirtualMachine, anyhow::Error> {
+        if let Some(vm) = self.cache.get(&id) {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' already exists: {:?}",
+                &id,
+                vm.vm,
+            ));
+        }
+        if self.incoming.contains(&id) {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' is being received",
+                &id
+            ));
+        }
+
+        let vm = VirtualMachine::restore(
+            id.clone(),
+            source,
+            prefault,
+            &self.jailer,
+        )?;
+        self.reserve_addresses(&vm);
+        let _ = self.cache.insert(id, vm.clone());
+        Ok(vm)
+    }
+
+    /// Reserve the ID of a virtual machine about to be received from
+    /// another node until it is adopted, see [VirtualMachines::adopt]
+    pub fn reserve_incoming(&mut self, id: &VmID) -> Result<(), anyhow::Error> {
+        if self.cache.contains_key(id) || !self.incoming.insert(id.clone()) {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' already exists",
+                id
+            ));
+        }
+        Ok(())
+    }
+
+    /// Release the ID reserved for a virtual machine that was not received
+    pub fn release_incoming(&mut self, id: &VmID) {
+        let _ = self.incoming.remove(id);
+    }
+
+    /// Whether the ID is reserved for a virtual machine being received from
+    /// another node
+    pub fn is_incoming(&self, id: &VmID) -> bool {
+        self.incoming.contains(id)
+    }
+
+    /// Add a virtual machine received from another node, releasing the
+    /// reservation of its ID
+    pub fn adopt(&mut self, vm: VirtualMachine) -> Result<(), anyhow::Error> {
+        if self.cache.contains_key(&vm.id) {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' already exists",
+                vm.id
+            ));
+        }
+        self.release_incoming(&vm.id);
+        self.reserve_addresses(&vm);
+        let _ = self.cache.insert(vm.id.clone(), vm);
+        Ok(())
+    }
+
+    /// Start the live migration of a virtual machine by its ID to another
+    /// node. The returned VM shares the VMM of the cached one, so it is sent
+    /// with [VirtualMachine::migrate] without holding on to the cache, which
+    /// rejects other operations on the VM until
+    /// [VirtualMachines::finish_migration].
+    pub fn start_migration(
+        &mut self,
+        id: &VmID,
+    ) -> Result<VirtualMachine, anyhow::Error> {
+        let vm = self.get_mut(id)?.clone();
+        let _ = self.outgoing.insert(id.clone());
+        Ok(vm)
+    }
+
+    /// Finish the live migration of a virtual machine by its ID, removing
+    /// it from this node if it was `migrated`
+    pub fn finish_migration(&mut self, id: &VmID, migrated: bool) {
+        let _ = self.outgoing.remove(id);
+        if !migrated {
+            return;
+        }
+        self.dirty_pages.untrack(id);
+        self.ipam.release(id);
+        // The VM now runs on the destination node, so failing to clean up
+        // its remains here must not fail the migration
+        if let Some(mut vm) = self.cache.remove(id) {
+            vm.migrated();
+            if let Err(e) = vm.delete() {
+                error!("Failed to delete migrated vm '{id}': {e}");
+            }
+        }
+    }
+
+    /// The cached virtual machine with the ID, unless it is being migrated,
+    /// in which case its VMM is busy sending it
+    fn get_mut(
+        &mut self,
+        id: &VmID,
+    ) -> Result<&mut VirtualMachine, anyhow::Error> {
+        if self.outgoing.contains(id) {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' is being migrated",
+                id
+            ));
+        }
+        self.cache.get_mut(id).ok_or_else(|| {
+            anyhow!("Virtual machine with ID '{:?}' not found", id)
+        })
+    }
+
+    /// Returns true if a virtual machine with the ID exists
+    pub fn contains(&self, id: &VmID) -> bool {
+        self.cache.contains_key(id) || self.incoming.contains(id)
+    }
+
+    /// Snapshot a virtual machine by its ID into `destination` as its
+    /// snapshot `snapshot_id`. Returns the pages of guest memory written
+    /// since its snapshot `parent`, if they were tracked.
+    pub fn snapshot(
+        &mut self,
+        id: &VmID,
+        destination: &Path,
+        snapshot_id: &str,
+        parent: Option<&str>,
+    ) -> Result<Option<DirtyBitmap>, anyhow::Error> {
+        let _ = self.get_mut(id)?;
+
+        // The pages are collected while the VM is paused for the snapshot,
+        // guest writes in between would be missing from either
+        let resume = self.pause(id).is_ok();
+        let dirty = match self.collect_dirty_pages(id, |tracker| {
+            tracker.collect_for_snapshot(id, snapshot_id, parent)
+        }) {
+            Ok(dirty) => dirty,
+            Err(e) => {
+                warn!("Not tracking dirty pages of vm '{id}': {e}");
+                None
+            }
+        };
+
+        let res = self.get_mut(id).and_then(|vm| vm.snapshot(destination));
+        if resume {
+            if let Err(e) = self.resume(id) {
+                error!("Failed to resume vm '{id}': {e}");
+            }
+        }
+        res.map(|()| dirty)
+    }
+
+    /// Snapshot a virtual machine by its ID into `destination`, leaving it
+    /// paused
+    pub fn checkpoint(
+        &mut self,
+        id: &VmID,
+        destination: &Path,
+    ) -> Result<(), anyhow::Error> {
+        self.get_mut(id)?.checkpoint(destination)
+    }
+
     /// Stop a virtual machine by its ID
     pub fn stop(&mut self, id: &VmID) -> Result<(), anyhow::Error> {
-        if let Some(vm) = self.cache.get_mut(id) {
-            vm.stop()?;
-            Ok(())
+        self.get_mut(id)?.stop()?;
+        self.dirty_pages.untrack(id);
+        Ok(())
+    }
+
+    /// Pause a virtual machine by its ID
+    pub fn pause(&mut self, id: &VmID) -> Result<(), anyhow::Error> {
+        self.get_mut(id)?.pause()
+    }
+
+    /// Resume a paused virtual machine by its ID
+    pub fn resume(&mut self, id: &VmID) -> Result<(), anyhow::Error> {
+        self.get_mut(id)?.resume()
+    }
+
+    /// Start a virtual machine by its ID, returning the addres of its TAP device
+    pub fn start(&mut self, id: &VmID) -> Result<String, anyhow::Error> {
+        let vm = self.get_mut(id)?;
+        vm.start()?;
+        match vm.tap() {
+            Some(tap) => Ok(tap.to_string()),
+            None => Ok("".into()),
+        }
+    }
+
+    /// Delete a virtual machine by its ID
+    pub fn delete(&mut self, id: &VmID) -> Result<(), anyhow::Error> {
+        self.get_mut(id)?.delete()?;
+        self.dirty_pages.untrack(id);
+        self.ipam.release(id);
+        let _ = self.cache.remove(id);
+        Ok(())
+    }
+
+    /// Get the root drive image of a stopped virtual machine by its ID
+    pub fn root_drive(&self, id: &VmID) -> Result<PathBuf, anyhow::Error> {
+        if let Some(vm) = self.cache.get(id) {
+            if !vm.is_stopped() {
+                return Err(anyhow!(
+                    "Virtual machine with ID '{:?}' is not stopped",
+                    id
+                ));
+            }
+            vm.vm.mounts.first().map(|m| m.host_path.clone()).ok_or_else(|| {
+                anyhow!("Virtual machine with ID '{:?}' has no drives", id)
+            })
         } else {
             Err(anyhow!("Virtual machine with ID '{:?}' not found", id))
         }
     }
 
-    /// Start a virtual machine by its ID, returning the addres of its TAP device
-    pub fn start(&mut self, id: &VmID) -> Result<String, anyhow::Error> {
-        if let Some(vm) = self.cache.get_mut(id) {
-            vm.start()?;
-            match vm.tap() {
-                Some(tap) => Ok(tap.to_string()),
-                None => Ok("".into()),
+    /// Get the serial console socket of a running virtual machine by its ID
+    pub fn console_socket(&self, id: &VmID) -> Result<PathBuf, anyhow::Error> {
+        if let Some(vm) = self.cache.get(id) {
+            if vm.is_stopped() {
+                return Err(anyhow!(
+                    "Virtual machine with ID '{:?}' is not running",
+                    id
+                ));
             }
+            vm.vm.serial_socket.clone().ok_or_else(|| {
+                anyhow!(
+                    "Virtual machine with ID '{:?}' has no console socket",
+                    id
+                )
+            })
         } else {
             Err(anyhow!("Virtual machine with ID '{:?}' not found", id))
         }
     }
 
-    /// Delete a virtual machine by its ID
-    pub fn delete(&mut self, id: &VmID) -> Result<(), anyhow::Error> {
-        if let Some(vm) = self.cache.get_mut(id) {
-            vm.delete()?;
-            let _ = self.cache.remove(id);
-            Ok(())
+    /// Whether a running virtual machine was launched as a confidential
+    /// guest
+    pub fn is_confidential(&self, id: &VmID) -> Result<bool, anyhow::Error> {
+        if let Some(vm) = self.cache.get(id) {
+            if vm.is_stopped() {
+                return Err(anyhow!(
+                    "Virtual machine with ID '{:?}' is not running",
+                    id
+                ));
+            }
+            Ok(vm.vm.confidential.is_some())
         } else {
             Err(anyhow!("Virtual machine with ID '{:?}' not found", id))
         }
     }
 
+    /// Collect the pages of guest memory a running virtual machine wrote
+    /// since the previous collection. The first collection reports all pages
+    /// and starts tracking the VM. All tracked virtual machines are paused
+    /// briefly, as the soft-dirty bits are shared by the whole process.
+    pub fn dirty_pages(
+        &mut self,
+        id: &VmID,
+    ) -> Result<DirtyBitmap, anyhow::Error> {
+        self.collect_dirty_pages(id, |tracker| tracker.collect(id))
+    }
+
+    fn collect_dirty_pages<T>(
+        &mut self,
+        id: &VmID,
+        collect: impl FnOnce(&mut DirtyPageTracker) -> std::io::Result<T>,
+    ) -> Result<T, anyhow::Error> {
+        let _ = self.get_mut(id)?;
+        let Some(vm) = self.cache.get(id) else {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' not found",
+                id
+            ));
+        };
+        if vm.is_stopped() {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' is not running",
+                id
+            ));
+        }
+        if vm.vm.guest_memory.hugepages {
+            return Err(anyhow!(
+                "Virtual machine with ID '{:?}' is backed by huge pages",
+                id
+            ));
+        }
+        if vm.guest_memory().is_empty() {
+            return Err(anyhow!(
+                "Guest memory of virtual machine with ID '{:?}' is unknown",
+                id
+            ));
+        }
+        self.dirty_pages.track(id, vm.guest_memory());
+
+        let mut paused = vec![];
+        let tracked: Vec<VmID> = self.dirty_pages.tracked().cloned().collect();
+        for tracked in tracked {
+            if self.outgoing.contains(&tracked) {
+                continue;
+            }
+            let Some(vm) = self.cache.get_mut(&tracked) else { continue };
+            if vm.is_stopped() {
+                continue;
+            }
+            // Paused VMs stay paused, others fail to pause only if they are
+            // not running, in which case they write no pages either
+            if vm.pause().is_ok() {
+                paused.push(tracked);
+            }
+        }
+
+        let res = collect(&mut self.dirty_pages).map_err(Into::into);
+
+        for id in paused {
+            if let Some(vm) = self.cache.get_mut(&id) {
+                if let Err(e) = vm.resume() {
+                    error!("Failed to resume vm '{id}': {e}");
+                }
+            }
+        }
+        res
+    }
+
+    /// Finds the running virtual machines whose guest was reset since the
+    /// last call, see [VirtualMachine::check_reset]. The dirty pages of reset
+    /// VMs are no longer tracked, as their guest memory was remapped.
+    pub fn find_resets(&mut self) -> Vec<VmID> {
+        let known: HashSet<i32> = self
+            .cache
+            .values()
+            .flat_map(|vm| vm.vcpu_threads().iter().copied())
+            .collect();
+
+        let mut reset = vec![];
+        for vm in self.cache.values_mut() {
+            match vm.check_reset(&known) {
+                Ok(true) => reset.push(vm.id.clone()),
+                Ok(false) => {}
+                Err(e) => {
+                    warn!(
+                        "Failed to check whether vm '{}' was reset: {e}",
+                        vm.id
+                    )
+                }
+            }
+        }
+        for id in &reset {
+            self.dirty_pages.untrack(id);
+        }
+        reset
+    }
+
     /// List all virtual machines
     pub fn list(&self) -> Vec<VirtualMachine> {
         self.cache.values().cloned().collect()
@@ -13,82 +13,652 @@
  * SPDX-License-Identifier: Apache-2.0                                        *
 \* -------------------------------------------------------------------------- */
 
+use anyhow::Context;
+use client::{
+    vms::vm_service::VmServiceClient, AuraeConfig, AuraeSocket, AuthConfig,
+    Client, SystemConfig,
+};
 use proto::vms::{
-    vm_service_server, VirtualMachineSummary, VmServiceAllocateRequest,
-    VmServiceAllocateResponse, VmServiceFreeRequest, VmServiceFreeResponse,
-    VmServiceListRequest, VmServiceListResponse, VmServiceStartRequest,
-    VmServiceStartResponse, VmServiceStopRequest, VmServiceStopResponse,
+    vm_service_server, ShutdownPolicy, VirtualMachineSummary,
+    VmServiceAllocateRequest, VmServiceAllocateResponse,
+    VmServiceAttestationReportRequest, VmServiceAttestationReportResponse,
+    VmServiceCheckpointNodeRequest, VmServiceCheckpointNodeResponse,
+    VmServiceConsoleLogRequest, VmServiceConsoleLogResponse,
+    VmServiceConsoleRequest, VmServiceConsoleResponse,
+    VmServiceDeleteSnapshotRequest, VmServiceDeleteSnapshotResponse,
+    VmServiceDirtyPagesRequest, VmServiceDirtyPagesResponse,
+    VmServiceExportRequest, VmServiceExportResponse,
+    VmServiceFlattenSnapshotRequest, VmServiceFlattenSnapshotResponse,
+    VmServiceFreeRequest, VmServiceFreeResponse, VmServiceGuestEventsRequest,
+    VmServiceGuestEventsResponse, VmServiceImportFirecrackerRequest,
+    VmServiceImportFirecrackerResponse, VmServiceListRequest,
+    VmServiceListResponse, VmServiceMigrateRequest, VmServiceMigrateResponse,
+    VmServiceMigrationTunnelRequest, VmServiceMigrationTunnelResponse,
+    VmServicePauseRequest, VmServicePauseResponse,
+    VmServiceReceiveMigrationRequest, VmServiceReceiveMigrationResponse,
+    VmServiceRelocateRequest, VmServiceRelocateResponse,
+    VmServiceRestoreNodeRequest, VmServiceRestoreNodeResponse,
+    VmServiceRestoreRequest, VmServiceRestoreResponse, VmServiceResumeRequest,
+    VmServiceResumeResponse, VmServiceSnapshotRequest,
+    VmServiceSnapshotResponse, VmServiceStartRequest, VmServiceStartResponse,
+    VmServiceStopRequest, VmServiceStopResponse, WatchdogAction,
+};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    future::Future,
+    net::{Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
 };
-use std::{path::PathBuf, sync::Arc};
-use tokio::sync::Mutex;
-use tonic::{Request, Response, Status};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+    runtime::Handle,
+    sync::{broadcast, mpsc, Mutex},
+    task::AbortHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{error, info, warn};
+use validation::ValidatedField;
 
 use super::{
+    attestation, checkpoint,
+    cloud_init::CloudInitSpec,
+    console::{Console, ConsoleWriter},
+    dirty_pages,
     error::{Result, VmServiceError},
-    virtual_machine::{MountSpec, VmID, VmSpec},
+    export::push_drive,
+    firecracker::FirecrackerConfig,
+    guest_channel, host,
+    ipam::Lease,
+    jailer::JailerConfig,
+    machine, tap,
+    virtual_machine::{SmbiosSpec, VirtualMachine, VmID, VmSpec},
     virtual_machines::VirtualMachines,
 };
+use crate::{
+    cells::CellService,
+    cordon::Cordon,
+    images::{
+        distribution::{Client as RegistryClient, Reference, RegistryAuth},
+        RegistryFiles,
+    },
+    network::{Ipam, NetworkService},
+    observe::{ObserveService, Workload, WorkloadEvent, WorkloadEventKind},
+    resumable::{impl_resumable, ResumableStream, ResumableStreams},
+    snapshots::{self, DirtyPages, PageStore, SnapshotStoreError},
+    AURAED_RUNTIME,
+};
+
+/// File of a snapshot the VMM writes guest memory to.
+const SNAPSHOT_MEMORY_FILE: &str = "memory-ranges";
+/// How often and how long to wait for the VMM receiving a tunneled migration
+/// to start listening for it.
+const MIGRATION_CONNECT_ATTEMPTS: u32 = 10;
+const MIGRATION_CONNECT_INTERVAL: Duration = Duration::from_millis(500);
+/// Most bytes of a migration carried by a message of a migration tunnel.
+const MIGRATION_TUNNEL_CHUNK: usize = 64 * 1024;
+/// How often and how long to wait for a relocated VM to run on the
+/// destination.
+const RELOCATION_HEALTH_ATTEMPTS: u32 = 30;
+const RELOCATION_HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the metrics of running VMs are sampled while anybody is
+/// subscribed to them.
+const METRICS_INTERVAL: Duration = Duration::from_secs(5);
+/// How often running VMs are checked for guests that were reset.
+const RESET_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// How often the guest channels are served to new VMs.
+const GUEST_CHANNEL_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the consoles of VMs that were restored, migrated to the node or
+/// restarted are captured.
+const CONSOLE_CAPTURE_INTERVAL: Duration = Duration::from_secs(1);
+
+type ConsoleStream = ResumableStream<VmServiceConsoleResponse>;
+type GuestEventsStream = ResumableStream<VmServiceGuestEventsResponse>;
+type MigrationTunnelStream = ReceiverStream<
+    std::result::Result<VmServiceMigrationTunnelResponse, Status>,
+>;
+
+impl_resumable!(VmServiceConsoleResponse, VmServiceGuestEventsResponse);
+
+/// Where the archives of CheckpointNode are kept and what they hold besides
+/// the VMs, see [VmService::with_node_checkpoints].
+#[derive(Debug, Clone)]
+struct NodeCheckpoints {
+    dir: PathBuf,
+    cell_service: CellService,
+    ipam: Option<Ipam>,
+}
 
 /// VmService struct manages the lifecycle of virtual machines.
 #[derive(Debug, Clone)]
 pub struct VmService {
     vms: Arc<Mutex<VirtualMachines>>,
-    // TODO: ObserveService
+    snapshots: PageStore,
+    staging_dir: PathBuf,
+    /// Credentials and insecure registries, shared with image pulls
+    registries: RegistryFiles,
+    seeds_dir: PathBuf,
+    consoles_dir: PathBuf,
+    checkpoint_on_shutdown: Arc<Mutex<HashSet<VmID>>>,
+    /// VMs stopped once their guest was reset, see [WatchdogAction::Stop]
+    stop_on_reset: Arc<Mutex<HashSet<VmID>>>,
+    shutdown_checkpoint: PathBuf,
+    shutdown_policies: PathBuf,
+    observe_service: ObserveService,
+    /// Dedicated runtime VM operations are spawned on, if any
+    runtime: Option<Handle>,
+    /// New VMs are rejected while the node is cordoned
+    cordon: Cordon,
+    node_checkpoints: Option<NodeCheckpoints>,
+    /// Events pushed by the guests of VMs, see [super::guest_channel]
+    guest_events: broadcast::Sender<VmServiceGuestEventsResponse>,
+    /// The guest channels served to VMs, see [VmService::serve_guest_channels]
+    guest_channels: Arc<Mutex<HashMap<VmID, AbortHandle>>>,
+    /// The serial consoles of VMs, see [VmService::capture_consoles]
+    console_captures: Arc<Mutex<HashMap<VmID, Console>>>,
+    /// Console streams clients can resume, see [crate::resumable]
+    consoles: ResumableStreams<VmServiceConsoleResponse, ConsoleWriter>,
+    guest_event_streams: ResumableStreams<VmServiceGuestEventsResponse>,
 }
 
 impl VmService {
-    /// Allocates a new instance of VmService.
-    pub fn new() -> Self {
-        Self { vms: Default::default() }
+    /// Allocates a new instance of VmService, storing snapshots below
+    /// `snapshots_dir` and per VM state such as cloud-init seeds and console
+    /// sockets below `vms_dir`. VM metrics are published to
+    /// `observe_service`, see [VmService::publish_metrics]. Operations
+    /// driving the VMM run on `runtime` if given, the VMMs are confined as
+    /// configured by `jailer`. No VMs are allocated, imported, restored or
+    /// migrated to the node while it is cordoned by `cordon`. Drives are
+    /// exported to the `registries` images are pulled from.
+    pub fn new(
+        snapshots_dir: PathBuf,
+        vms_dir: PathBuf,
+        observe_service: ObserveService,
+        runtime: Option<Handle>,
+        jailer: JailerConfig,
+        cordon: Cordon,
+        registries: RegistryFiles,
+    ) -> Self {
+        Self {
+            vms: Arc::new(Mutex::new(VirtualMachines::new(jailer))),
+            snapshots: PageStore::new(snapshots_dir.join("store")),
+            staging_dir: snapshots_dir.join("staging"),
+            registries,
+            seeds_dir: vms_dir.join("seeds"),
+            consoles_dir: vms_dir.join("consoles"),
+            checkpoint_on_shutdown: Default::default(),
+            stop_on_reset: Default::default(),
+            shutdown_checkpoint: vms_dir.join("shutdown.tar.zst"),
+            shutdown_policies: vms_dir.join("checkpoint_on_shutdown.json"),
+            observe_service,
+            runtime,
+            cordon,
+            node_checkpoints: None,
+            guest_events: broadcast::channel(64).0,
+            guest_channels: Default::default(),
+            console_captures: Default::default(),
+            consoles: ResumableStreams::new(),
+            guest_event_streams: ResumableStreams::new(),
+        }
+    }
+
+    /// Writes the archives of CheckpointNode to `dir` and restores them from
+    /// it, including the cells of `cell_service` and the address leases of
+    /// `ipam` besides the VMs. Without, the node cannot be checkpointed.
+    pub(crate) fn with_node_checkpoints(
+        mut self,
+        dir: PathBuf,
+        cell_service: CellService,
+        ipam: Option<Ipam>,
+    ) -> Self {
+        self.node_checkpoints =
+            Some(NodeCheckpoints { dir, cell_service, ipam });
+        self
+    }
+
+    /// Runs the VM operation `op` on the dedicated runtime, so that its
+    /// blocking calls into the VMM do not stall the workers serving other
+    /// requests. Without a dedicated runtime, `op` runs in place.
+    async fn on_vm_runtime<T, F, Fut>(
+        &self,
+        op: F,
+    ) -> std::result::Result<T, Status>
+    where
+        F: FnOnce(VmService) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+        T: Send + 'static,
+    {
+        let op = op(self.clone());
+        match &self.runtime {
+            Some(runtime) => runtime
+                .spawn(op)
+                .await
+                .map_err(|e| Status::internal(e.to_string()))?
+                .map_err(Into::into),
+            None => op.await.map_err(Into::into),
+        }
+    }
+
+    /// Opens the snapshot store, checks that KVM is usable and creates the
+    /// chroot of the VMMs if they are jailed in one. Until this succeeds the
+    /// service is not reported as SERVING.
+    pub(crate) async fn ready(&self) -> anyhow::Result<()> {
+        let snapshots = self
+            .snapshots
+            .open()
+            .context("failed to open the snapshot store")?;
+        info!("Opened snapshot store with {snapshots} snapshots");
+
+        tokio::fs::create_dir_all(&self.staging_dir).await.with_context(
+            || {
+                format!(
+                    "failed to create snapshot staging directory {}",
+                    self.staging_dir.display()
+                )
+            },
+        )?;
+
+        if !host::kvm_available() {
+            anyhow::bail!("/dev/kvm cannot be opened");
+        }
+
+        let chroot = self.vms.lock().await.jailer().chroot.clone();
+        if let Some(chroot) = chroot {
+            tokio::fs::create_dir_all(&chroot).await.with_context(|| {
+                format!("failed to create VMM chroot {}", chroot.display())
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets the VMs reach other networks through the host, by forwarding
+    /// their traffic and masquerading the pool their addresses are leased
+    /// from with a masquerade named `vms` in `network`.
+    pub(crate) async fn provision_network(
+        &self,
+        network: &NetworkService,
+    ) -> anyhow::Result<()> {
+        tap::enable_forwarding()
+            .await
+            .context("failed to enable IPv4 forwarding")?;
+        let pool = self.vms.lock().await.address_pool();
+        network
+            .ensure_masquerade("vms", pool.into())
+            .await
+            .context("failed to masquerade the vm address pool")?;
+        Ok(())
+    }
+
+    /// Periodically samples the metrics of the running VMs and publishes
+    /// them to the [ObserveService] while anybody is subscribed to them.
+    pub(crate) fn publish_metrics(&self) {
+        let vms = self.vms.clone();
+        let observe_service = self.observe_service.clone();
+        let runtime = self.runtime.clone().unwrap_or_else(Handle::current);
+
+        let _ignored = runtime.spawn(async move {
+            let mut interval = tokio::time::interval(METRICS_INTERVAL);
+            loop {
+                let _ = interval.tick().await;
+                if !observe_service.has_vm_metrics_subscribers() {
+                    continue;
+                }
+
+                let running: Vec<VirtualMachine> = vms
+                    .lock()
+                    .await
+                    .list()
+                    .into_iter()
+                    .filter(|vm| !vm.is_stopped())
+                    .collect();
+                for vm in running {
+                    match vm.metrics() {
+                        Ok(metrics) => observe_service.emit_vm_metrics(metrics),
+                        Err(e) => {
+                            warn!(
+                                "Failed to sample metrics of vm '{}': {e}",
+                                vm.id
+                            )
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Periodically checks whether the guests of running VMs were reset,
+    /// e.g. by the watchdog after the guest hung, and stops those VMs whose
+    /// watchdog action is [WatchdogAction::Stop].
+    pub(crate) fn supervise_resets(&self) {
+        let vms = self.vms.clone();
+        let stop_on_reset = self.stop_on_reset.clone();
+        let runtime = self.runtime.clone().unwrap_or_else(Handle::current);
+
+        let _ignored = runtime.spawn(async move {
+            let mut interval = tokio::time::interval(RESET_CHECK_INTERVAL);
+            loop {
+                let _ = interval.tick().await;
+
+                let mut vms = vms.lock().await;
+                for id in vms.find_resets() {
+                    if !stop_on_reset.lock().await.contains(&id) {
+                        warn!("Guest of vm '{id}' was reset");
+                        continue;
+                    }
+                    warn!("Guest of vm '{id}' was reset, stopping the vm");
+                    if let Err(e) = vms.stop(&id) {
+                        error!("Failed to stop vm '{id}' after a reset: {e}");
+                    }
+                }
+            }
+        });
+    }
+
+    /// Serves the guest channel to every VM with a vsock device, so their
+    /// guests can push events to [VmService::guest_events], and stops
+    /// serving it to VMs that were freed.
+    pub(crate) fn serve_guest_channels(&self) {
+        let vms = self.vms.clone();
+        let guest_channels = self.guest_channels.clone();
+        let guest_events = self.guest_events.clone();
+        let runtime = self.runtime.clone().unwrap_or_else(Handle::current);
+
+        let _ignored = runtime.spawn(async move {
+            let mut interval = tokio::time::interval(GUEST_CHANNEL_INTERVAL);
+            loop {
+                let _ = interval.tick().await;
+
+                let sockets: HashMap<VmID, PathBuf> = vms
+                    .lock()
+                    .await
+                    .list()
+                    .into_iter()
+                    .filter_map(|vm| Some((vm.id, vm.vm.vsock_socket?)))
+                    .collect();
+                let mut guest_channels = guest_channels.lock().await;
+                guest_channels.retain(|id, channel| {
+                    let keep = sockets.contains_key(id);
+                    if !keep {
+                        channel.abort();
+                    }
+                    keep
+                });
+                for (id, socket) in sockets {
+                    if guest_channels.contains_key(&id) {
+                        continue;
+                    }
+                    match guest_channel::listen(
+                        id.clone(),
+                        &socket,
+                        guest_events.clone(),
+                    ) {
+                        Ok(channel) => {
+                            let _ = guest_channels.insert(id, channel);
+                        }
+                        Err(e) => {
+                            error!(
+                                "Failed to serve guest channel of '{id}': {e}"
+                            )
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Captures the serial consoles of running VMs, so that their output is
+    /// kept for [VmService::console_log] whether or not anybody is attached.
+    /// Consoles of VMs started through [VmService::start] are captured right
+    /// away, those of VMs that were restored or migrated to the node within
+    /// [CONSOLE_CAPTURE_INTERVAL]. The output of freed VMs is dropped.
+    pub(crate) fn capture_consoles(&self) {
+        let vms = self.vms.clone();
+        let console_captures = self.console_captures.clone();
+        let runtime = self.runtime.clone().unwrap_or_else(Handle::current);
+
+        let _ignored = runtime.spawn(async move {
+            let mut interval = tokio::time::interval(CONSOLE_CAPTURE_INTERVAL);
+            loop {
+                let _ = interval.tick().await;
+
+                let sockets: HashMap<VmID, Option<PathBuf>> = vms
+                    .lock()
+                    .await
+                    .list()
+                    .into_iter()
+                    .map(|vm| {
+                        let running = !vm.is_stopped();
+                        (vm.id, vm.vm.serial_socket.filter(|_| running))
+                    })
+                    .collect();
+                let mut captures = console_captures.lock().await;
+                captures.retain(|id, _| sockets.contains_key(id));
+                for (id, socket) in sockets {
+                    let Some(socket) = socket else {
+                        continue;
+                    };
+                    if let Err(e) =
+                        capture_console(&mut captures, &id, &socket).await
+                    {
+                        warn!("Failed to capture the console of '{id}': {e}");
+                    }
+                }
+            }
+        });
+    }
+
+    /// Live migrates the VM `id` to the node listening on `destination_url`.
+    /// The VMM blocks until the whole VM is sent, so the lock on the VMs is
+    /// only held to start and finish the migration.
+    async fn send_migration(
+        &self,
+        id: &VmID,
+        destination_url: &str,
+    ) -> anyhow::Result<()> {
+        let vm = self.vms.lock().await.start_migration(id)?;
+        let destination_url = destination_url.to_string();
+        let sent =
+            tokio::task::spawn_blocking(move || vm.migrate(&destination_url))
+                .await
+                .map_err(anyhow::Error::from)
+                .and_then(|sent| sent);
+        self.vms.lock().await.finish_migration(id, sent.is_ok());
+        sent
+    }
+
+    /// Path of the cloud-init seed image attached to the VM `id`.
+    fn seed_path(&self, id: &VmID) -> PathBuf {
+        self.seeds_dir.join(format!("{id}.img"))
+    }
+
+    /// Path of the socket the serial console of the VM `id` is exposed on.
+    fn console_path(&self, id: &VmID) -> PathBuf {
+        self.consoles_dir.join(format!("{id}.sock"))
     }
 
-    // TODO: validate requestts
-    /// Allocates a new VM based on the provided request.
+    /// Path of the socket the VMM exposes the vsock device of the VM `id`
+    /// on, see [super::guest_channel].
+    fn vsock_path(&self, id: &VmID) -> PathBuf {
+        self.consoles_dir.join(format!("{id}.vsock"))
+    }
+
+    /// Path of the socket the VMM sends or receives the tunneled migration
+    /// of the VM `id` on, see [VmService::migration_tunnel].
+    fn migration_path(&self, id: &VmID) -> PathBuf {
+        self.consoles_dir.join(format!("{id}.migration"))
+    }
+
+    /// Connects to the auraed running in the guest of the VM `id` over its
+    /// vsock device, see [guest_channel::serve_agent]. The guest auraed
+    /// authenticates the host auraed with its client certificate.
+    pub(crate) async fn connect_agent(
+        &self,
+        id: &str,
+    ) -> anyhow::Result<Client> {
+        connect(AuraeSocket::Vsock {
+            path: self.vsock_path(&VmID::new(id.to_string())),
+            port: guest_channel::GUEST_AGENT_PORT,
+        })
+        .await
+    }
+
+    /// Allocates a new VM based on the provided request. The machine is
+    /// checked as it is converted (see [machine::vm_spec]), a dry run also
+    /// checks that the VM could run on this host (see [check_host]).
     ///
     /// # Arguments
-    /// * `request` - A (currently unvalidated) request to allocate a VM
+    /// * `request` - A request to allocate a VM
     ///
     /// # Returns
     /// A result containing the VmServiceAllocateResponse or an error.
     #[tracing::instrument(skip(self))]
-    async fn allocate(
+    pub(crate) async fn allocate(
         &self,
         request: VmServiceAllocateRequest,
     ) -> Result<VmServiceAllocateResponse> {
+        self.cordon.check()?;
         let mut vms = self.vms.lock().await;
 
+        let shutdown_policy = request.shutdown_policy();
+        let dry_run = request.dry_run;
         let Some(vm) = request.machine else {
             return Err(VmServiceError::MissingMachineConfig {});
         };
+        let id = VmID::validate_for_creation(Some(vm.id.clone()), "id", None)?;
 
-        let id = VmID::new(vm.id);
-        let Some(root_drive) = vm.root_drive else {
-            return Err(VmServiceError::MissingRootDrive { id: id.clone() });
-        };
+        if shutdown_policy == ShutdownPolicy::LeaveRunning {
+            return Err(VmServiceError::UnsupportedShutdownPolicy {
+                id,
+                policy: shutdown_policy.as_str_name().into(),
+            });
+        }
+
+        let seed = vm.cloud_init.clone().map(|cloud_init| CloudInitSpec {
+            instance_id: id.to_string(),
+            hostname: (!cloud_init.hostname.is_empty())
+                .then_some(cloud_init.hostname),
+            ssh_authorized_keys: cloud_init.ssh_authorized_keys,
+            user_data: (!cloud_init.user_data.is_empty())
+                .then_some(cloud_init.user_data),
+            network_config: (!cloud_init.network_config.is_empty())
+                .then_some(cloud_init.network_config),
+        });
+        let machine = vm.clone();
+        let mut spec = machine::vm_spec(
+            &id,
+            vm,
+            self.console_path(&id),
+            seed.is_some().then(|| self.seed_path(&id)),
+        )?;
+        spec.vsock_socket = Some(self.vsock_path(&id));
+
+        let mut machine = normalize(machine, &spec);
 
-        let mut mounts = vec![MountSpec {
-            host_path: PathBuf::from(root_drive.image_path.as_str()),
-            read_only: root_drive.read_only,
-        }];
-        mounts.extend(vm.drive_mounts.into_iter().map(|m| MountSpec {
-            host_path: PathBuf::from(m.image_path.as_str()),
-            read_only: m.read_only,
-        }));
+        // The seed image of an existing VM must not be overwritten
+        if vms.contains(&id) {
+            return Err(VmServiceError::VmExists { id });
+        }
+
+        if dry_run {
+            check_host(&id, &spec)?;
+            return Ok(VmServiceAllocateResponse {
+                vm_id: id.to_string(),
+                machine: Some(machine),
+            });
+        }
+
+        std::fs::create_dir_all(&self.consoles_dir).map_err(|e| {
+            VmServiceError::FailedToAllocateError {
+                id: id.clone(),
+                source: e.into(),
+            }
+        })?;
 
-        let spec = VmSpec {
-            memory_size: vm.mem_size_mb,
-            vcpu_count: vm.vcpu_count,
-            kernel_image_path: PathBuf::from(vm.kernel_img_path.as_str()),
-            kernel_args: vm.kernel_args,
-            mounts,
-            net: vec![],
+        let seed_path = self.seed_path(&id);
+        if let Some(seed) = seed {
+            seed.write_seed(&seed_path).map_err(|e| {
+                VmServiceError::FailedToAllocateError {
+                    id: id.clone(),
+                    source: e,
+                }
+            })?;
+        }
+
+        let vm = match vms.create(id.clone(), spec) {
+            Ok(vm) => vm,
+            Err(e) => {
+                let _ = std::fs::remove_file(&seed_path);
+                return Err(VmServiceError::FailedToAllocateError {
+                    id,
+                    source: e,
+                });
+            }
         };
+        machine.network_interfaces =
+            network_interfaces(&vm.vm, &vms.leases(&id));
+
+        if machine
+            .watchdog
+            .as_ref()
+            .is_some_and(|watchdog| watchdog.action() == WatchdogAction::Stop)
+        {
+            let _ = self.stop_on_reset.lock().await.insert(id.clone());
+        }
+        if shutdown_policy == ShutdownPolicy::Checkpoint {
+            let _ = self.checkpoint_on_shutdown.lock().await.insert(id);
+        }
+
+        Ok(VmServiceAllocateResponse {
+            vm_id: vm.id.to_string(),
+            machine: Some(machine),
+        })
+    }
+
+    /// Allocates a new VM from a Firecracker configuration file
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to import a Firecracker VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceImportFirecrackerResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn import_firecracker(
+        &self,
+        request: VmServiceImportFirecrackerRequest,
+    ) -> Result<VmServiceImportFirecrackerResponse> {
+        self.cordon.check()?;
+        let id =
+            VmID::validate_for_creation(Some(request.vm_id), "vm_id", None)?;
 
+        let mut spec =
+            FirecrackerConfig::from_file(&PathBuf::from(request.config_path))
+                .and_then(VmSpec::try_from)
+                .map_err(|e| VmServiceError::FailedToImportError {
+                    id: id.clone(),
+                    source: e,
+                })?;
+        machine::check(&id, &spec)?;
+
+        std::fs::create_dir_all(&self.consoles_dir).map_err(|e| {
+            VmServiceError::FailedToImportError {
+                id: id.clone(),
+                source: e.into(),
+            }
+        })?;
+        spec.serial_socket = Some(self.console_path(&id));
+        spec.vsock_socket = Some(self.vsock_path(&id));
+        spec.smbios = SmbiosSpec::new(&id, None, None);
+
+        let mut vms = self.vms.lock().await;
         let vm = vms.create(id.clone(), spec).map_err(|e| {
             VmServiceError::FailedToAllocateError { id, source: e }
         })?;
 
-        Ok(VmServiceAllocateResponse { vm_id: vm.id.to_string() })
+        Ok(VmServiceImportFirecrackerResponse { vm_id: vm.id.to_string() })
     }
 
     /// Frees a VM
@@ -99,19 +669,60 @@ impl VmService {
     /// # Returns
     /// A result containing VmServiceFreeResponse or an error.
     #[tracing::instrument(skip(self))]
-    async fn free(
+    pub(crate) async fn free(
         &self,
         request: VmServiceFreeRequest,
     ) -> Result<VmServiceFreeResponse> {
         let id = VmID::new(request.vm_id);
 
         let mut vms = self.vms.lock().await;
-        vms.delete(&id)
-            .map_err(|e| VmServiceError::FailedToFreeError { id, source: e })?;
+        let taps = vms.provisioned_taps(&id);
+        vms.delete(&id).map_err(|e| VmServiceError::FailedToFreeError {
+            id: id.clone(),
+            source: e,
+        })?;
+        drop(vms);
+
+        for name in taps {
+            if let Err(e) = tap::remove(&name).await {
+                error!(
+                    "Failed to remove TAP device '{name}' of vm '{id}': {e}"
+                );
+            }
+        }
+
+        let _ = self.checkpoint_on_shutdown.lock().await.remove(&id);
+        let _ = self.stop_on_reset.lock().await.remove(&id);
+        if let Some(channel) = self.guest_channels.lock().await.remove(&id) {
+            channel.abort();
+        }
+        let _ = self.console_captures.lock().await.remove(&id);
+        self.remove_files(&id).map_err(|e| {
+            VmServiceError::FailedToFreeError { id, source: e.into() }
+        })?;
 
         Ok(VmServiceFreeResponse {})
     }
 
+    /// Removes the files auraed keeps for the VM `id` outside of its drives.
+    fn remove_files(&self, id: &VmID) -> std::io::Result<()> {
+        let vsock_path = self.vsock_path(id);
+        for path in [
+            self.seed_path(id),
+            self.console_path(id),
+            guest_channel::listener_path(&vsock_path),
+            vsock_path,
+        ] {
+            match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(e)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     /// Starts a VM
     ///
     /// # Arguments
@@ -120,7 +731,7 @@ impl VmService {
     /// # Returns
     /// A result containing VmServiceStartResponse or an error.
     #[tracing::instrument(skip(self))]
-    async fn start(
+    pub(crate) async fn start(
         &self,
         request: VmServiceStartRequest,
     ) -> Result<VmServiceStartResponse> {
@@ -128,9 +739,23 @@ impl VmService {
 
         let mut vms = self.vms.lock().await;
         let addr = vms.start(&id).map_err(|e| {
-            VmServiceError::FailedToStartError { id, source: e }
+            VmServiceError::FailedToStartError { id: id.clone(), source: e }
         })?;
 
+        // capture the console before the guest gets far into booting
+        if let Ok(socket) = vms.console_socket(&id) {
+            let mut captures = self.console_captures.lock().await;
+            if let Err(e) = capture_console(&mut captures, &id, &socket).await {
+                warn!("Failed to capture the console of '{id}': {e}");
+            }
+        }
+
+        self.observe_service.emit_workload_event(WorkloadEvent::new(
+            WorkloadEventKind::Started,
+            Workload::Vm { vm_id: id.to_string() },
+            format!("started vm {id}"),
+        ));
+
         Ok(VmServiceStartResponse { auraed_address: addr })
     }
 
@@ -142,25 +767,1213 @@ impl VmService {
     /// # Returns
     /// A result containing VmServiceStopResponse or an error.
     #[tracing::instrument(skip(self))]
-    async fn stop(
+    pub(crate) async fn stop(
         &self,
         request: VmServiceStopRequest,
     ) -> Result<VmServiceStopResponse> {
         let id = VmID::new(request.vm_id);
 
         let mut vms = self.vms.lock().await;
-        vms.stop(&id)
-            .map_err(|e| VmServiceError::FailedToStopError { id, source: e })?;
+        vms.stop(&id).map_err(|e| VmServiceError::FailedToStopError {
+            id: id.clone(),
+            source: e,
+        })?;
+
+        self.observe_service.emit_workload_event(WorkloadEvent::new(
+            WorkloadEventKind::Stopped,
+            Workload::Vm { vm_id: id.to_string() },
+            format!("stopped vm {id}"),
+        ));
 
         Ok(VmServiceStopResponse {})
     }
 
+    /// Pauses a running VM
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to pause a VM
+    ///
+    /// # Returns
+    /// A result containing VmServicePauseResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn pause(
+        &self,
+        request: VmServicePauseRequest,
+    ) -> Result<VmServicePauseResponse> {
+        let id = VmID::new(request.vm_id);
+
+        let mut vms = self.vms.lock().await;
+        vms.pause(&id).map_err(|e| VmServiceError::FailedToPauseError {
+            id,
+            source: e,
+        })?;
+
+        Ok(VmServicePauseResponse {})
+    }
+
+    /// Collects the pages of guest memory a running VM wrote since the
+    /// previous collection
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to collect dirty pages
+    ///
+    /// # Returns
+    /// A result containing VmServiceDirtyPagesResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn dirty_pages(
+        &self,
+        request: VmServiceDirtyPagesRequest,
+    ) -> Result<VmServiceDirtyPagesResponse> {
+        let id = VmID::new(request.vm_id);
+
+        let mut vms = self.vms.lock().await;
+        let dirty = vms.dirty_pages(&id).map_err(|e| {
+            VmServiceError::FailedToCollectDirtyPagesError { id, source: e }
+        })?;
+
+        Ok(VmServiceDirtyPagesResponse {
+            page_size: dirty_pages::PAGE_SIZE,
+            pages: dirty.pages,
+            bitmap: dirty.to_bytes(),
+        })
+    }
+
+    /// Resumes a paused VM
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to resume a VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceResumeResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn resume(
+        &self,
+        request: VmServiceResumeRequest,
+    ) -> Result<VmServiceResumeResponse> {
+        let id = VmID::new(request.vm_id);
+
+        let mut vms = self.vms.lock().await;
+        vms.resume(&id).map_err(|e| VmServiceError::FailedToResumeError {
+            id,
+            source: e,
+        })?;
+
+        Ok(VmServiceResumeResponse {})
+    }
+
+    /// Snapshots a VM into the snapshot store
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to snapshot a VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceSnapshotResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn snapshot(
+        &self,
+        request: VmServiceSnapshotRequest,
+    ) -> Result<VmServiceSnapshotResponse> {
+        let id = VmID::new(request.vm_id);
+        let snapshot_id = request.snapshot_id;
+        snapshots::validate_id(&snapshot_id)?;
+        if self.snapshots.manifest(&snapshot_id).is_ok() {
+            return Err(
+                SnapshotStoreError::AlreadyExists { id: snapshot_id }.into()
+            );
+        }
+
+        // The VMM writes the snapshot to a plain directory which is then
+        // compressed and deduplicated into the store.
+        let staging = self.staging_dir.join(&snapshot_id);
+        tokio::fs::create_dir_all(&staging).await.map_err(|e| {
+            VmServiceError::FailedToSnapshotError {
+                id: id.clone(),
+                source: e.into(),
+            }
+        })?;
+
+        let parent = (!request.parent_snapshot_id.is_empty())
+            .then_some(request.parent_snapshot_id);
+        let snapshot = self.vms.lock().await.snapshot(
+            &id,
+            &staging,
+            &snapshot_id,
+            parent.as_deref(),
+        );
+        let dirty = match snapshot {
+            Ok(dirty) => dirty.map(|dirty| dirty.to_bytes()),
+            Err(e) => {
+                let _ = tokio::fs::remove_dir_all(&staging).await;
+                return Err(VmServiceError::FailedToSnapshotError {
+                    id,
+                    source: e,
+                });
+            }
+        };
+
+        // Guest memory pages not written since the parent are taken from it
+        // without hashing them
+        let store = self.snapshots.clone();
+        let ingested = snapshot_id.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            let dirty = dirty.as_deref().map(|bitmap| DirtyPages {
+                path: Path::new(SNAPSHOT_MEMORY_FILE),
+                bitmap,
+            });
+            let res =
+                store.ingest(&ingested, &staging, parent.as_deref(), dirty);
+            let _ = std::fs::remove_dir_all(&staging);
+            res
+        })
+        .await
+        .map_err(|e| VmServiceError::FailedToSnapshotError {
+            id: id.clone(),
+            source: e.into(),
+        })??;
+
+        self.observe_service.emit_workload_event(WorkloadEvent::new(
+            WorkloadEventKind::SnapshotTaken,
+            Workload::VmSnapshot {
+                vm_id: id.to_string(),
+                snapshot_id: snapshot_id.clone(),
+            },
+            format!("took snapshot {snapshot_id} of vm {id}"),
+        ));
+
+        Ok(VmServiceSnapshotResponse {})
+    }
+
+    /// Restores a snapshot into a new VM
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to restore a VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceRestoreResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn restore(
+        &self,
+        request: VmServiceRestoreRequest,
+    ) -> Result<VmServiceRestoreResponse> {
+        self.cordon.check()?;
+        let id =
+            VmID::validate_for_creation(Some(request.vm_id), "vm_id", None)?;
+        let staging =
+            self.staging_dir.join(format!("restore-{}", uuid::Uuid::new_v4()));
+
+        let store = self.snapshots.clone();
+        let dest = staging.clone();
+        let materialized = tokio::task::spawn_blocking(move || {
+            store.materialize(&request.snapshot_id, &dest)
+        })
+        .await
+        .map_err(|e| VmServiceError::FailedToRestoreError {
+            id: id.clone(),
+            source: e.into(),
+        })?;
+        if let Err(e) = materialized {
+            let _ = tokio::fs::remove_dir_all(&staging).await;
+            return Err(e.into());
+        }
+
+        let res = self.vms.lock().await.restore(
+            id.clone(),
+            &staging,
+            request.prefault,
+        );
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        let vm = res.map_err(|e| VmServiceError::FailedToRestoreError {
+            id,
+            source: e,
+        })?;
+
+        Ok(VmServiceRestoreResponse {
+            auraed_address: vm.tap().map(|t| t.to_string()).unwrap_or_default(),
+        })
+    }
+
+    /// Pauses all running VMs and writes their snapshots into one archive,
+    /// along with the cells and the address leases of the node
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to checkpoint the node
+    ///
+    /// # Returns
+    /// A result containing VmServiceCheckpointNodeResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn checkpoint_node(
+        &self,
+        request: VmServiceCheckpointNodeRequest,
+    ) -> Result<VmServiceCheckpointNodeResponse> {
+        let (node, archive) = self.node_checkpoint(&request.archive_path)?;
+        let staging = self
+            .staging_dir
+            .join(format!("checkpoint-{}", uuid::Uuid::new_v4()));
+
+        let res = self.write_node_checkpoint(node, &staging, &archive).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+
+        let (vm_ids, cell_names) = res?;
+        Ok(VmServiceCheckpointNodeResponse { vm_ids, cell_names })
+    }
+
+    /// The node checkpoints and the path of their archive `archive_path`,
+    /// which is confined to their directory.
+    fn node_checkpoint(
+        &self,
+        archive_path: &str,
+    ) -> Result<(&NodeCheckpoints, PathBuf)> {
+        let node = self
+            .node_checkpoints
+            .as_ref()
+            .ok_or(VmServiceError::NodeCheckpointsUnavailable)?;
+
+        let archive =
+            checkpoint::confine(&node.dir, archive_path).ok_or_else(|| {
+                VmServiceError::InvalidArchivePath {
+                    archive: archive_path.to_string(),
+                    dir: node.dir.clone(),
+                }
+            })?;
+        Ok((node, archive))
+    }
+
+    /// Checkpoints the VMs, the cells and the address leases of the node
+    /// below `staging` and packs it into `archive`. The leases are written
+    /// after the cells so they hold those of all checkpointed cells.
+    async fn write_node_checkpoint(
+        &self,
+        node: &NodeCheckpoints,
+        staging: &Path,
+        archive: &Path,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let archive = archive.to_string_lossy();
+        let failed =
+            |source: anyhow::Error| VmServiceError::FailedToCheckpointError {
+                archive: archive.to_string(),
+                source,
+            };
+
+        let vm_ids = self.checkpoint_vms(staging, &archive, |_| true).await?;
+        let cell_names = node
+            .cell_service
+            .checkpoint(&staging.join(checkpoint::CELLS_DIR))
+            .await
+            .map_err(|e| failed(e.into()))?;
+        if let Some(ipam) = &node.ipam {
+            let leases = staging
+                .join(checkpoint::STATE_DIR)
+                .join(checkpoint::LEASES_FILE);
+            ipam.checkpoint(&leases).await.map_err(|e| failed(e.into()))?;
+        }
+        self.pack_checkpoint(staging, &archive).await?;
+
+        Ok((vm_ids, cell_names))
+    }
+
+    /// Checkpoints the VMs allocated with [ShutdownPolicy::Checkpoint] for
+    /// the next auraed to restore (see [VmService::restore_shutdown_checkpoint]).
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn checkpoint_for_shutdown(&self) -> Result<()> {
+        let ids = self.checkpoint_on_shutdown.lock().await.clone();
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let staging = self
+            .staging_dir
+            .join(format!("checkpoint-{}", uuid::Uuid::new_v4()));
+        let archive = self.shutdown_checkpoint.to_string_lossy();
+
+        let res = self
+            .write_checkpoint(&staging, &archive, |id| ids.contains(id))
+            .await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+
+        info!("Checkpointed vms {:?} to {archive}", res?);
+        Ok(())
+    }
+
+    /// Checkpoints all VMs for the next auraed to restore when auraed exits
+    /// for an upgrade. VMs run inside the auraed process and cannot outlive
+    /// it, the checkpoint is how they survive the upgrade. The VMs allocated
+    /// with [ShutdownPolicy::Checkpoint] are recorded so only they are
+    /// checkpointed again when the next auraed shuts down.
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn checkpoint_for_upgrade(&self) -> Result<()> {
+        let staging = self
+            .staging_dir
+            .join(format!("checkpoint-{}", uuid::Uuid::new_v4()));
+        let archive = self.shutdown_checkpoint.to_string_lossy();
+
+        let res = self.write_checkpoint(&staging, &archive, |_| true).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+        let ids = res?;
+        if ids.is_empty() {
+            return Ok(());
+        }
+
+        let policies: Vec<String> = self
+            .checkpoint_on_shutdown
+            .lock()
+            .await
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        let failed =
+            |source: anyhow::Error| VmServiceError::FailedToCheckpointError {
+                archive: archive.to_string(),
+                source,
+            };
+        let policies =
+            serde_json::to_vec(&policies).map_err(|e| failed(e.into()))?;
+        tokio::fs::write(&self.shutdown_policies, policies)
+            .await
+            .map_err(|e| failed(e.into()))?;
+
+        info!("Checkpointed vms {ids:?} to {archive} for upgrade");
+        Ok(())
+    }
+
+    /// Restores the VMs checkpointed by a previous auraed when it exited (see
+    /// [VmService::checkpoint_for_shutdown] and
+    /// [VmService::checkpoint_for_upgrade]).
+    #[tracing::instrument(skip(self))]
+    pub(crate) async fn restore_shutdown_checkpoint(&self) -> Result<()> {
+        if !self.shutdown_checkpoint.exists() {
+            return Ok(());
+        }
+
+        let staging =
+            self.staging_dir.join(format!("restore-{}", uuid::Uuid::new_v4()));
+        let archive = self.shutdown_checkpoint.to_string_lossy();
+
+        let res = self.read_checkpoint(&staging, &archive).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+
+        // On failure the checkpoint is kept to be restored with RestoreNode
+        let ids = res?;
+        let _ = tokio::fs::remove_file(&self.shutdown_checkpoint).await;
+
+        info!("Restored vms {ids:?} from {archive}");
+
+        // After an upgrade only the VMs recorded with the checkpoint keep
+        // their policy, otherwise all of them were checkpointed for it
+        let ids = match tokio::fs::read(&self.shutdown_policies).await {
+            Ok(policies) => {
+                let _ = tokio::fs::remove_file(&self.shutdown_policies).await;
+                let policies: Vec<String> = serde_json::from_slice(&policies)
+                    .unwrap_or_else(|e| {
+                        warn!("Ignoring unreadable vm shutdown policies: {e}");
+                        vec![]
+                    });
+                ids.into_iter().filter(|id| policies.contains(id)).collect()
+            }
+            Err(_) => ids,
+        };
+        self.checkpoint_on_shutdown
+            .lock()
+            .await
+            .extend(ids.into_iter().map(VmID::new));
+        Ok(())
+    }
+
+    /// Snapshots every running or paused VM matching `select` into its own
+    /// directory below `staging`, together with its cloud-init seed, and
+    /// packs `staging` into `archive`.
+    async fn write_checkpoint(
+        &self,
+        staging: &Path,
+        archive: &str,
+        select: impl Fn(&VmID) -> bool,
+    ) -> Result<Vec<String>> {
+        let ids = self.checkpoint_vms(staging, archive, select).await?;
+        self.pack_checkpoint(staging, archive).await?;
+        Ok(ids)
+    }
+
+    /// Snapshots every running or paused VM matching `select` into its own
+    /// directory below `staging`, together with its cloud-init seed, for
+    /// the checkpoint `archive`.
+    async fn checkpoint_vms(
+        &self,
+        staging: &Path,
+        archive: &str,
+        select: impl Fn(&VmID) -> bool,
+    ) -> Result<Vec<String>> {
+        let failed =
+            |source: anyhow::Error| VmServiceError::FailedToCheckpointError {
+                archive: archive.to_string(),
+                source,
+            };
+
+        tokio::fs::create_dir_all(staging)
+            .await
+            .map_err(|e| failed(e.into()))?;
+
+        let mut vms = self.vms.lock().await;
+        let ids: Vec<VmID> = vms
+            .list()
+            .into_iter()
+            .filter(|vm| !vm.is_stopped() && select(&vm.id))
+            .map(|vm| vm.id)
+            .collect();
+        for id in &ids {
+            let dir = staging.join(id.to_string());
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .map_err(|e| failed(e.into()))?;
+            vms.checkpoint(id, &dir).map_err(|e| {
+                VmServiceError::FailedToSnapshotError {
+                    id: id.clone(),
+                    source: e,
+                }
+            })?;
+
+            let seed = self.seed_path(id);
+            if seed.exists() {
+                let _ = tokio::fs::copy(&seed, dir.join(checkpoint::SEED_FILE))
+                    .await
+                    .map_err(|e| failed(e.into()))?;
+            }
+        }
+        drop(vms);
+
+        Ok(ids.iter().map(ToString::to_string).collect())
+    }
+
+    /// Packs `staging` into the checkpoint `archive`.
+    async fn pack_checkpoint(
+        &self,
+        staging: &Path,
+        archive: &str,
+    ) -> Result<()> {
+        let failed =
+            |source: anyhow::Error| VmServiceError::FailedToCheckpointError {
+                archive: archive.to_string(),
+                source,
+            };
+
+        let source = staging.to_path_buf();
+        let dest = PathBuf::from(archive);
+        tokio::task::spawn_blocking(move || checkpoint::pack(&source, &dest))
+            .await
+            .map_err(|e| failed(e.into()))?
+            .map_err(failed)
+    }
+
+    /// Restores the address leases and the cells of a node checkpoint, and
+    /// restores and resumes its VMs
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to restore a node checkpoint
+    ///
+    /// # Returns
+    /// A result containing VmServiceRestoreNodeResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn restore_node(
+        &self,
+        request: VmServiceRestoreNodeRequest,
+    ) -> Result<VmServiceRestoreNodeResponse> {
+        self.cordon.check()?;
+        let (node, archive) = self.node_checkpoint(&request.archive_path)?;
+        let staging =
+            self.staging_dir.join(format!("restore-{}", uuid::Uuid::new_v4()));
+
+        let res = self.read_node_checkpoint(node, &staging, &archive).await;
+        let _ = tokio::fs::remove_dir_all(&staging).await;
+
+        let (vm_ids, cell_names) = res?;
+        Ok(VmServiceRestoreNodeResponse { vm_ids, cell_names })
+    }
+
+    /// Unpacks `archive` into `staging` and restores the address leases,
+    /// the cells and the VMs it holds, in this order so the cells get their
+    /// addresses back.
+    async fn read_node_checkpoint(
+        &self,
+        node: &NodeCheckpoints,
+        staging: &Path,
+        archive: &Path,
+    ) -> Result<(Vec<String>, Vec<String>)> {
+        let archive = archive.to_string_lossy();
+        let failed = |source: anyhow::Error| {
+            VmServiceError::FailedToRestoreCheckpointError {
+                archive: archive.to_string(),
+                source,
+            }
+        };
+
+        let ids = self.unpack_checkpoint(staging, &archive).await?;
+        let leases =
+            staging.join(checkpoint::STATE_DIR).join(checkpoint::LEASES_FILE);
+        if let (Some(ipam), true) = (&node.ipam, leases.exists()) {
+            ipam.restore(&leases).await.map_err(|e| failed(e.into()))?;
+        }
+        let cell_names = node
+            .cell_service
+            .restore_checkpoint(&staging.join(checkpoint::CELLS_DIR))
+            .await
+            .map_err(|e| failed(e.into()))?;
+        let vm_ids = self.restore_vms(staging, &archive, ids).await?;
+
+        Ok((vm_ids, cell_names))
+    }
+
+    /// Unpacks `archive` into `staging` and restores every VM it holds,
+    /// putting their cloud-init seeds back in place first.
+    async fn read_checkpoint(
+        &self,
+        staging: &Path,
+        archive: &str,
+    ) -> Result<Vec<String>> {
+        let ids = self.unpack_checkpoint(staging, archive).await?;
+        self.restore_vms(staging, archive, ids).await
+    }
+
+    /// Unpacks the checkpoint `archive` into `staging` and returns the IDs
+    /// of the VMs it holds.
+    async fn unpack_checkpoint(
+        &self,
+        staging: &Path,
+        archive: &str,
+    ) -> Result<Vec<String>> {
+        let failed = |source: anyhow::Error| {
+            VmServiceError::FailedToRestoreCheckpointError {
+                archive: archive.to_string(),
+                source,
+            }
+        };
+
+        let source = PathBuf::from(archive);
+        let dest = staging.to_path_buf();
+        tokio::task::spawn_blocking(move || checkpoint::unpack(&source, &dest))
+            .await
+            .map_err(|e| failed(e.into()))?
+            .map_err(failed)
+    }
+
+    /// Restores the VMs `ids` of the checkpoint `archive` unpacked into
+    /// `staging`, putting their cloud-init seeds back in place first.
+    async fn restore_vms(
+        &self,
+        staging: &Path,
+        archive: &str,
+        ids: Vec<String>,
+    ) -> Result<Vec<String>> {
+        let failed = |source: anyhow::Error| {
+            VmServiceError::FailedToRestoreCheckpointError {
+                archive: archive.to_string(),
+                source,
+            }
+        };
+
+        for dir in [&self.seeds_dir, &self.consoles_dir] {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| failed(e.into()))?;
+        }
+
+        let mut vms = self.vms.lock().await;
+        for id in &ids {
+            let id = VmID::new(id.as_str());
+            let dir = staging.join(id.to_string());
+
+            let seed = dir.join(checkpoint::SEED_FILE);
+            if seed.exists() {
+                let _ = tokio::fs::copy(&seed, self.seed_path(&id))
+                    .await
+                    .map_err(|e| failed(e.into()))?;
+            }
+
+            let _ = vms.restore(id.clone(), &dir, false).map_err(|e| {
+                VmServiceError::FailedToRestoreError { id, source: e }
+            })?;
+        }
+
+        Ok(ids)
+    }
+
+    /// Flattens a differential snapshot
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to flatten a snapshot
+    ///
+    /// # Returns
+    /// A result containing VmServiceFlattenSnapshotResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn flatten_snapshot(
+        &self,
+        request: VmServiceFlattenSnapshotRequest,
+    ) -> Result<VmServiceFlattenSnapshotResponse> {
+        let store = self.snapshots.clone();
+        let _ = tokio::task::spawn_blocking(move || {
+            store.flatten(&request.snapshot_id)
+        })
+        .await
+        .map_err(|e| SnapshotStoreError::IO(e.into()))??;
+
+        Ok(VmServiceFlattenSnapshotResponse {})
+    }
+
+    /// Deletes a snapshot
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to delete a snapshot
+    ///
+    /// # Returns
+    /// A result containing VmServiceDeleteSnapshotResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn delete_snapshot(
+        &self,
+        request: VmServiceDeleteSnapshotRequest,
+    ) -> Result<VmServiceDeleteSnapshotResponse> {
+        let store = self.snapshots.clone();
+        tokio::task::spawn_blocking(move || store.remove(&request.snapshot_id))
+            .await
+            .map_err(|e| SnapshotStoreError::IO(e.into()))??;
+
+        Ok(VmServiceDeleteSnapshotResponse {})
+    }
+
+    /// Prepares to receive a VM migrated from another node
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to receive a migration
+    ///
+    /// # Returns
+    /// A result containing VmServiceReceiveMigrationResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn receive_migration(
+        &self,
+        request: VmServiceReceiveMigrationRequest,
+    ) -> Result<VmServiceReceiveMigrationResponse> {
+        self.cordon.check()?;
+        let id =
+            VmID::validate_for_creation(Some(request.vm_id), "vm_id", None)?;
+        let mut vms = self.vms.lock().await;
+        vms.reserve_incoming(&id).map_err(|e| {
+            VmServiceError::FailedToMigrateError { id: id.clone(), source: e }
+        })?;
+        let jailer = vms.jailer().clone();
+        drop(vms);
+
+        // Without an address the VMM listens on a socket of its own, which
+        // the migration is tunneled to by the auraed of the source node
+        let migration_path = self.migration_path(&id);
+        let receiver_url = if request.receiver_url.is_empty() {
+            let _best_effort = std::fs::remove_file(&migration_path);
+            format!("unix:{}", migration_path.display())
+        } else {
+            request.receiver_url
+        };
+
+        // Receiving blocks until the source node has sent the whole VM
+        let vms = self.vms.clone();
+        let observe_service = self.observe_service.clone();
+        let _ = tokio::spawn(async move {
+            let received = tokio::task::spawn_blocking({
+                let id = id.clone();
+                move || {
+                    VirtualMachine::receive_migration(
+                        id,
+                        &receiver_url,
+                        &jailer,
+                    )
+                }
+            })
+            .await;
+            let _best_effort = std::fs::remove_file(&migration_path);
+
+            let mut vms = vms.lock().await;
+            match received {
+                Ok(Ok(vm)) => match vms.adopt(vm) {
+                    Ok(_) => {
+                        info!("Received migrated vm '{id}'");
+                        observe_service.emit_workload_event(
+                            WorkloadEvent::new(
+                                WorkloadEventKind::Migrated,
+                                Workload::Vm { vm_id: id.to_string() },
+                                format!("received migrated vm {id}"),
+                            ),
+                        );
+                    }
+                    Err(e) => {
+                        vms.release_incoming(&id);
+                        error!("Failed to add migrated vm: {e}");
+                    }
+                },
+                Ok(Err(e)) => {
+                    vms.release_incoming(&id);
+                    error!("Failed to receive vm migration: {e}");
+                }
+                Err(e) => {
+                    vms.release_incoming(&id);
+                    error!("Failed to receive vm migration: {e}");
+                }
+            }
+        });
+
+        Ok(VmServiceReceiveMigrationResponse {})
+    }
+
+    /// Live migrates a VM to another node
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to migrate a VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceMigrateResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn migrate(
+        &self,
+        request: VmServiceMigrateRequest,
+    ) -> Result<VmServiceMigrateResponse> {
+        let id = VmID::new(request.vm_id);
+
+        self.send_migration(&id, &request.destination_url).await.map_err(
+            |e| VmServiceError::FailedToMigrateError {
+                id: id.clone(),
+                source: e,
+            },
+        )?;
+
+        self.observe_service.emit_workload_event(WorkloadEvent::new(
+            WorkloadEventKind::Migrated,
+            Workload::Vm { vm_id: id.to_string() },
+            format!("migrated vm {id} to {}", request.destination_url),
+        ));
+
+        Ok(VmServiceMigrateResponse {})
+    }
+
+    /// Relocates a running VM to another node
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to relocate a VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceRelocateResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn relocate(
+        &self,
+        request: VmServiceRelocateRequest,
+    ) -> Result<VmServiceRelocateResponse> {
+        let id = VmID::new(request.vm_id);
+        let failed = |source: anyhow::Error| {
+            VmServiceError::FailedToRelocateError { id: id.clone(), source }
+        };
+
+        let destination: SocketAddr = request
+            .destination_address
+            .parse()
+            .map_err(|_| VmServiceError::InvalidNodeAddress {
+                address: request.destination_address.clone(),
+            })?;
+        if !self.vms.lock().await.contains(&id) {
+            return Err(failed(anyhow::anyhow!("VM not found")));
+        }
+
+        let client =
+            connect(AuraeSocket::Addr(destination)).await.map_err(failed)?;
+        let _ = client
+            .receive_migration(VmServiceReceiveMigrationRequest {
+                vm_id: id.to_string(),
+                receiver_url: String::new(),
+            })
+            .await
+            .map_err(|e| failed(e.into()))?;
+
+        // The VMM sends the VM to a socket of auraed, which tunnels it to the
+        // destination over the mTLS channel of the auraed of both nodes
+        let migration_path = self.migration_path(&id);
+        let _best_effort = std::fs::remove_file(&migration_path);
+        let listener = UnixListener::bind(&migration_path)
+            .map_err(|e| failed(e.into()))?;
+        let tunnel = tokio::spawn(tunnel_migration(
+            client.clone(),
+            id.to_string(),
+            listener,
+        ));
+        let sent = self
+            .send_migration(&id, &format!("unix:{}", migration_path.display()))
+            .await;
+        let tunneled = match sent {
+            Ok(()) => tunnel.await.map_err(anyhow::Error::from),
+            Err(e) => {
+                tunnel.abort();
+                Err(e)
+            }
+        };
+        let _best_effort = std::fs::remove_file(&migration_path);
+        tunneled.and_then(|tunneled| tunneled).map_err(failed)?;
+
+        // The VM no longer runs here, but keep its files until it is known
+        // to run on the destination
+        let mut attempt = 0;
+        let auraed_address = loop {
+            attempt += 1;
+            let machines = client
+                .list(VmServiceListRequest {})
+                .await
+                .map_err(|e| failed(e.into()))?
+                .into_inner()
+                .machines;
+            if let Some(vm) = machines
+                .into_iter()
+                .find(|m| m.id == id.to_string() && m.status == "Running")
+            {
+                break vm.auraed_address;
+            }
+            if attempt >= RELOCATION_HEALTH_ATTEMPTS {
+                return Err(failed(anyhow::anyhow!(
+                    "VM is not running on the destination node"
+                )));
+            }
+            tokio::time::sleep(RELOCATION_HEALTH_INTERVAL).await;
+        };
+
+        self.remove_files(&id).map_err(|e| failed(e.into()))?;
+
+        self.observe_service.emit_workload_event(WorkloadEvent::new(
+            WorkloadEventKind::Migrated,
+            Workload::Vm { vm_id: id.to_string() },
+            format!("relocated vm {id} to {destination}"),
+        ));
+
+        Ok(VmServiceRelocateResponse { auraed_address })
+    }
+
+    /// Exports the root drive of a stopped VM as an OCI artifact
+    ///
+    /// # Arguments
+    /// * `request` - An (unvalidated) request to export a VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceExportResponse or an error.
+    #[tracing::instrument(
+        skip(self, request),
+        fields(vm_id = %request.vm_id)
+    )]
+    async fn export(
+        &self,
+        request: VmServiceExportRequest,
+    ) -> Result<VmServiceExportResponse> {
+        let id = VmID::new(request.vm_id);
+        let reference: Reference =
+            request.image_reference.parse().map_err(|_| {
+                VmServiceError::InvalidImageReference {
+                    reference: request.image_reference.clone(),
+                }
+            })?;
+
+        let drive = self.vms.lock().await.root_drive(&id).map_err(|e| {
+            VmServiceError::FailedToExportError { id: id.clone(), source: e }
+        })?;
+
+        let registries = self.registries.load().map_err(|e| {
+            VmServiceError::FailedToExportError {
+                id: id.clone(),
+                source: e.into(),
+            }
+        })?;
+        let auth = if request.username.is_empty() {
+            registries.auth(reference.registry())
+        } else {
+            RegistryAuth::Basic(request.username, request.password)
+        };
+        let client =
+            RegistryClient::new(registries.client_config()).map_err(|e| {
+                VmServiceError::FailedToExportError {
+                    id: id.clone(),
+                    source: e.into(),
+                }
+            })?;
+
+        let manifest_url =
+            push_drive(&client, &auth, drive, &self.staging_dir, &reference)
+                .await
+                .map_err(|e| VmServiceError::FailedToExportError {
+                    id,
+                    source: e,
+                })?;
+
+        Ok(VmServiceExportResponse { manifest_url })
+    }
+
+    /// Attaches to the serial console of a running VM
+    ///
+    /// # Arguments
+    /// * `first` - The first request of the console stream, naming the VM
+    /// * `requests` - The remaining requests, carrying input for the guest
+    ///
+    /// # Returns
+    /// A result containing the stream of console output or an error.
+    #[tracing::instrument(skip(self, requests))]
+    async fn console(
+        &self,
+        first: VmServiceConsoleRequest,
+        requests: Streaming<VmServiceConsoleRequest>,
+    ) -> Result<ConsoleStream> {
+        let id = VmID::new(first.vm_id);
+
+        let socket =
+            self.vms.lock().await.console_socket(&id).map_err(|e| {
+                VmServiceError::FailedToAttachConsoleError {
+                    id: id.clone(),
+                    source: e,
+                }
+            })?;
+        let (mut output, writer) = {
+            let mut captures = self.console_captures.lock().await;
+            let console = capture_console(&mut captures, &id, &socket)
+                .await
+                .map_err(|e| VmServiceError::FailedToAttachConsoleError {
+                    id: id.clone(),
+                    source: e.into(),
+                })?;
+            let Some(output) = console.subscribe() else {
+                return Err(VmServiceError::FailedToAttachConsoleError {
+                    id,
+                    source: anyhow::anyhow!("console was disconnected"),
+                });
+            };
+            (output, console.writer())
+        };
+
+        let (tx, rx) = mpsc::channel(4);
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let output = match output.recv().await {
+                    Ok(output) => output,
+                    // a slow client only misses output, the history keeps it
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                let resp =
+                    VmServiceConsoleResponse { output, ..Default::default() };
+                if tx.send(Ok(resp)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        forward_console_input(writer.clone(), first.input, requests);
+        Ok(self.consoles.start(ReceiverStream::new(rx), writer))
+    }
+
+    /// Carries the migration of a VM this node is receiving between the
+    /// source node and the VMM receiving it, see [VmService::relocate]. The
+    /// source node is authenticated by the mTLS of the auraed server, and the
+    /// VMM only accepts a single connection.
+    ///
+    /// # Arguments
+    /// * `first` - The first request of the tunnel, naming the VM
+    /// * `requests` - The remaining requests, carrying the migration
+    ///
+    /// # Returns
+    /// A result containing the stream of the answers of the VMM or an error.
+    #[tracing::instrument(skip(self, first, requests))]
+    async fn migration_tunnel(
+        &self,
+        first: VmServiceMigrationTunnelRequest,
+        mut requests: Streaming<VmServiceMigrationTunnelRequest>,
+    ) -> Result<MigrationTunnelStream> {
+        let id = VmID::new(first.vm_id);
+        if !self.vms.lock().await.is_incoming(&id) {
+            return Err(VmServiceError::NotReceivingMigration { id });
+        }
+
+        // The VMM starts listening in the background, so the first attempts
+        // may be refused
+        let migration_path = self.migration_path(&id);
+        let mut attempt = 0;
+        let stream = loop {
+            attempt += 1;
+            match UnixStream::connect(&migration_path).await {
+                Ok(stream) => break stream,
+                Err(e) if attempt >= MIGRATION_CONNECT_ATTEMPTS => {
+                    return Err(VmServiceError::FailedToMigrateError {
+                        id,
+                        source: e.into(),
+                    });
+                }
+                Err(_) => tokio::time::sleep(MIGRATION_CONNECT_INTERVAL).await,
+            }
+        };
+        let (mut reader, mut writer) = stream.into_split();
+
+        let _ignored = tokio::spawn(async move {
+            let mut data = first.data;
+            loop {
+                if writer.write_all(&data).await.is_err() {
+                    break;
+                }
+                match requests.message().await {
+                    Ok(Some(req)) => data = req.data,
+                    _ => break,
+                }
+            }
+        });
+
+        let (tx, rx) = mpsc::channel(4);
+        let _ignored = tokio::spawn(async move {
+            let mut buf = vec![0; MIGRATION_TUNNEL_CHUNK];
+            loop {
+                let data = match reader.read(&mut buf).await {
+                    Ok(0) => break,
+                    Ok(n) => buf[..n].to_vec(),
+                    Err(e) => {
+                        let _ =
+                            tx.send(Err(Status::internal(e.to_string()))).await;
+                        break;
+                    }
+                };
+                let resp = VmServiceMigrationTunnelResponse { data };
+                if tx.send(Ok(resp)).await.is_err() {
+                    // the source node is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(ReceiverStream::new(rx))
+    }
+
+    /// Reads the output kept of the serial console of a VM, see
+    /// [VmService::capture_consoles].
+    ///
+    /// # Arguments
+    /// * `request` - A request naming the VM
+    ///
+    /// # Returns
+    /// A result containing VmServiceConsoleLogResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn console_log(
+        &self,
+        request: VmServiceConsoleLogRequest,
+    ) -> Result<VmServiceConsoleLogResponse> {
+        let id = VmID::new(request.vm_id);
+
+        let captures = self.console_captures.lock().await;
+        let Some(console) = captures.get(&id) else {
+            return Err(VmServiceError::NoConsoleHistory { id });
+        };
+        let (output, truncated) = console.history();
+        Ok(VmServiceConsoleLogResponse { output, truncated })
+    }
+
+    /// Gets an attestation report of the guest of a running confidential
+    /// VM from the auraed running in the guest, which produces it through
+    /// its kernel (see [attestation]). Without a VM, the report is of the
+    /// guest this auraed runs in.
+    ///
+    /// # Arguments
+    /// * `request` - A request naming the VM and the report data to bind
+    ///
+    /// # Returns
+    /// A result containing VmServiceAttestationReportResponse or an error.
+    #[tracing::instrument(skip(self))]
+    async fn attestation_report(
+        &self,
+        request: VmServiceAttestationReportRequest,
+    ) -> Result<VmServiceAttestationReportResponse> {
+        if request.report_data.len() > attestation::REPORT_DATA_SIZE {
+            return Err(VmServiceError::InvalidReportData {
+                max: attestation::REPORT_DATA_SIZE,
+            });
+        }
+
+        if request.vm_id.is_empty() {
+            let report_data = request.report_data;
+            let report = tokio::task::spawn_blocking(move || {
+                attestation::request(&report_data)
+            })
+            .await
+            .map_err(std::io::Error::other)
+            .and_then(|report| report)
+            .map_err(|source| {
+                VmServiceError::FailedToAttestGuestError { source }
+            })?;
+            return Ok(VmServiceAttestationReportResponse {
+                provider: report.provider,
+                report: report.report,
+                certificates: report.certificates,
+            });
+        }
+
+        let id = VmID::new(request.vm_id);
+        let confidential =
+            self.vms.lock().await.is_confidential(&id).map_err(|e| {
+                VmServiceError::FailedToAttestError {
+                    id: id.clone(),
+                    source: e,
+                }
+            })?;
+        if !confidential {
+            return Err(VmServiceError::NotConfidential { id });
+        }
+
+        let agent = self.connect_agent(&id).await.map_err(|e| {
+            VmServiceError::FailedToAttestError { id: id.clone(), source: e }
+        })?;
+        let response = agent
+            .attestation_report(VmServiceAttestationReportRequest {
+                vm_id: String::new(),
+                report_data: request.report_data,
+            })
+            .await
+            .map_err(|e| VmServiceError::FailedToAttestError {
+                id,
+                source: e.into(),
+            })?;
+        Ok(response.into_inner())
+    }
+
+    /// Streams the events the guests of VMs push over their guest channel.
+    ///
+    /// # Arguments
+    /// * `request` - A request naming the VM to stream the events of, if any
+    ///
+    /// # Returns
+    /// A result containing the stream of guest events or an error.
+    #[tracing::instrument(skip(self))]
+    async fn guest_events(
+        &self,
+        request: VmServiceGuestEventsRequest,
+    ) -> Result<GuestEventsStream> {
+        let filter = (!request.vm_id.is_empty()).then_some(request.vm_id);
+
+        let (tx, rx) = mpsc::channel(4);
+        let mut guest_events = self.guest_events.subscribe();
+        let _ignored = tokio::spawn(async move {
+            loop {
+                let event = match guest_events.recv().await {
+                    Ok(event) => event,
+                    // a slow receiver only misses events
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if filter.as_ref().is_some_and(|id| *id != event.vm_id) {
+                    continue;
+                }
+                if tx.send(Ok(event)).await.is_err() {
+                    // receiver is gone
+                    break;
+                }
+            }
+        });
+
+        Ok(self.guest_event_streams.start(ReceiverStream::new(rx), ()))
+    }
+
     /// List VMs
     ///
     /// # Returns
     /// A result containing VmServiceListResponse or an error.
     #[tracing::instrument(skip(self))]
-    async fn list(&self) -> Result<VmServiceListResponse> {
+    pub(crate) async fn list(&self) -> Result<VmServiceListResponse> {
         let vms = self.vms.lock().await;
         Ok(VmServiceListResponse {
             machines: vms
@@ -184,12 +1997,217 @@ impl VmService {
                         .map(|t| t.to_string())
                         .unwrap_or_default(),
                     status: m.status.to_string(),
+                    uuid: m
+                        .vm
+                        .smbios
+                        .uuid
+                        .map(|u| u.to_string())
+                        .unwrap_or_default(),
+                    resets: m.resets,
                 })
                 .collect(),
         })
     }
 }
 
+/// Writes the `first` input and that of the following `requests` to the
+/// console of `writer`, until the client closes its stream. The console
+/// stays attached while the client may resume its stream, it is detached
+/// once the stream is dropped, and the VMM waits for the next connection.
+fn forward_console_input(
+    writer: ConsoleWriter,
+    first: Vec<u8>,
+    mut requests: Streaming<VmServiceConsoleRequest>,
+) {
+    let _ignored = tokio::spawn(async move {
+        let mut input = first;
+        loop {
+            if writer.lock().await.write_all(&input).await.is_err() {
+                break;
+            }
+            match requests.message().await {
+                Ok(Some(req)) => input = req.input,
+                _ => break,
+            }
+        }
+    });
+}
+
+/// Tunnels the migration of the VM `vm_id` the VMM sends to `listener` to the
+/// destination node of `client`, see [VmService::migration_tunnel]. Returns
+/// once the VMM receiving the VM closed the tunnel.
+async fn tunnel_migration(
+    client: Client,
+    vm_id: String,
+    listener: UnixListener,
+) -> anyhow::Result<()> {
+    let (stream, _) = listener.accept().await?;
+    let (mut reader, mut writer) = stream.into_split();
+
+    let (tx, rx) = mpsc::channel(4);
+    tx.send(VmServiceMigrationTunnelRequest { vm_id, data: vec![] }).await?;
+    let _ignored = tokio::spawn(async move {
+        let mut buf = vec![0; MIGRATION_TUNNEL_CHUNK];
+        loop {
+            let data = match reader.read(&mut buf).await {
+                Ok(0) => break,
+                Ok(n) => buf[..n].to_vec(),
+                Err(e) => {
+                    warn!("Failed to read the migration of the vmm: {e}");
+                    break;
+                }
+            };
+            let req =
+                VmServiceMigrationTunnelRequest { vm_id: String::new(), data };
+            if tx.send(req).await.is_err() {
+                // the tunnel is closed
+                break;
+            }
+        }
+    });
+
+    let mut responses = client
+        .migration_tunnel(Box::pin(ReceiverStream::new(rx)))
+        .await?
+        .into_inner();
+    while let Some(response) = responses.message().await? {
+        writer.write_all(&response.data).await?;
+    }
+    Ok(())
+}
+
+/// Captures the console of the VM `id` on `socket` in `captures`, unless it
+/// is captured already. A console whose connection was closed, e.g. because
+/// the VM was stopped, is connected again and keeps its history.
+async fn capture_console<'a>(
+    captures: &'a mut HashMap<VmID, Console>,
+    id: &VmID,
+    socket: &Path,
+) -> std::io::Result<&'a Console> {
+    match captures.entry(id.clone()) {
+        Entry::Occupied(entry) => {
+            let console = entry.into_mut();
+            if !console.is_connected() {
+                console.reconnect(socket).await?;
+            }
+            Ok(console)
+        }
+        Entry::Vacant(entry) => {
+            Ok(entry.insert(Console::connect(socket).await?))
+        }
+    }
+}
+
+/// Connect to the auraed at `socket`, authenticating with the certificate
+/// of this node.
+async fn connect(socket: AuraeSocket) -> anyhow::Result<Client> {
+    let runtime = AURAED_RUNTIME
+        .get()
+        .ok_or_else(|| anyhow::anyhow!("auraed runtime is not initialized"))?;
+
+    Ok(Client::new(AuraeConfig {
+        auth: AuthConfig {
+            ca_crt: runtime.ca_crt.to_string_lossy().to_string(),
+            client_crt: runtime.server_crt.to_string_lossy().to_string(),
+            client_key: runtime.server_key.to_string_lossy().to_string(),
+        },
+        system: SystemConfig { socket },
+    })
+    .await?)
+}
+
+/// Check that this host could run the VM of `spec` right now: KVM is usable
+/// and the memory not backed by huge pages is available. Nothing is reserved
+/// for the VM.
+fn check_host(id: &VmID, spec: &VmSpec) -> Result<()> {
+    if !host::kvm_available() {
+        return Err(VmServiceError::KvmUnavailable { id: id.clone() });
+    }
+    if spec.guest_memory.hugepages {
+        return Ok(());
+    }
+    let needed = u64::from(spec.memory_size) << 20;
+    match host::mem_available() {
+        Some(available) if available < needed => {
+            Err(VmServiceError::InsufficientMemory {
+                id: id.clone(),
+                needed,
+                available,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Fills the defaults auraed applied to `spec` into the `machine` it was
+/// allocated from.
+fn normalize(
+    mut machine: proto::vms::VirtualMachine,
+    spec: &VmSpec,
+) -> proto::vms::VirtualMachine {
+    machine.cpu_topology = spec.cpu_topology.map(|t| proto::vms::CpuTopology {
+        sockets: t.sockets.into(),
+        dies_per_socket: t.dies_per_socket.into(),
+        cores_per_die: t.cores_per_die.into(),
+        threads_per_core: t.threads_per_core.into(),
+    });
+    if let Some(memory) = machine
+        .guest_memory
+        .as_mut()
+        .filter(|m| m.hugepages && m.hugepage_size_mb == 0)
+    {
+        memory.hugepage_size_mb =
+            host::default_hugepage_size().map_or(0, |size| (size >> 20) as u32);
+    }
+    machine.uuid = spec.smbios.uuid.map(|u| u.to_string()).unwrap_or_default();
+    machine.serial_number =
+        spec.smbios.serial_number.clone().unwrap_or_default();
+    if let Some(watchdog) = machine
+        .watchdog
+        .as_mut()
+        .filter(|w| w.action() == WatchdogAction::Unspecified)
+    {
+        watchdog.set_action(WatchdogAction::Reset);
+    }
+    machine
+}
+
+/// The network interfaces of `spec` with the addressing auraed assigned to
+/// them when the VM was created.
+fn network_interfaces(
+    spec: &VmSpec,
+    leases: &[Lease],
+) -> Vec<proto::vms::NetworkInterface> {
+    let address = |ip: Ipv4Addr| {
+        if ip.is_unspecified() {
+            String::new()
+        } else {
+            ip.to_string()
+        }
+    };
+    let net = spec.net.iter().map(|n| proto::vms::NetworkInterface {
+        tap_name: n.tap.clone().unwrap_or_default(),
+        vhost_user_socket: n.vhost_socket.clone().unwrap_or_default(),
+        num_queue_pairs: n.queue_pairs.unwrap_or_default(),
+        vdpa_device: String::new(),
+        ip_address: address(n.ip),
+        netmask: address(n.mask),
+        mac_address: n.mac.to_string(),
+        mtu: n.mtu.map(u32::from).unwrap_or_default(),
+        guest_ip_address: leases
+            .iter()
+            .find(|l| l.host == n.ip)
+            .map(|l| l.guest.to_string())
+            .unwrap_or_default(),
+    });
+    let vdpa = spec.vdpa.iter().map(|v| proto::vms::NetworkInterface {
+        vdpa_device: v.path.display().to_string(),
+        num_queue_pairs: v.queue_pairs.unwrap_or_default(),
+        ..Default::default()
+    });
+    net.chain(vdpa).collect()
+}
+
 #[tonic::async_trait]
 impl vm_service_server::VmService for VmService {
     async fn allocate(
@@ -198,7 +2216,24 @@ impl vm_service_server::VmService for VmService {
     ) -> std::result::Result<Response<VmServiceAllocateResponse>, Status> {
         let req = request.into_inner();
         // TODO: validate the request
-        Ok(Response::new(self.allocate(req).await?))
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.allocate(req).await })
+                .await?,
+        ))
+    }
+
+    async fn import_firecracker(
+        &self,
+        request: Request<VmServiceImportFirecrackerRequest>,
+    ) -> std::result::Result<Response<VmServiceImportFirecrackerResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move {
+                vms.import_firecracker(req).await
+            })
+            .await?,
+        ))
     }
 
     async fn free(
@@ -207,7 +2242,10 @@ impl vm_service_server::VmService for VmService {
     ) -> std::result::Result<Response<VmServiceFreeResponse>, Status> {
         let req = request.into_inner();
         // TODO: validate request
-        Ok(Response::new(self.free(req).await?))
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.free(req).await })
+                .await?,
+        ))
     }
 
     async fn start(
@@ -215,7 +2253,10 @@ impl vm_service_server::VmService for VmService {
         request: Request<VmServiceStartRequest>,
     ) -> std::result::Result<Response<VmServiceStartResponse>, Status> {
         let req = request.into_inner();
-        Ok(Response::new(self.start(req).await?))
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.start(req).await })
+                .await?,
+        ))
     }
 
     async fn stop(
@@ -223,7 +2264,109 @@ impl vm_service_server::VmService for VmService {
         request: Request<VmServiceStopRequest>,
     ) -> std::result::Result<Response<VmServiceStopResponse>, Status> {
         let req = request.into_inner();
-        Ok(Response::new(self.stop(req).await?))
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.stop(req).await })
+                .await?,
+        ))
+    }
+
+    async fn pause(
+        &self,
+        request: Request<VmServicePauseRequest>,
+    ) -> std::result::Result<Response<VmServicePauseResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.pause(req).await })
+                .await?,
+        ))
+    }
+
+    async fn resume(
+        &self,
+        request: Request<VmServiceResumeRequest>,
+    ) -> std::result::Result<Response<VmServiceResumeResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.resume(req).await })
+                .await?,
+        ))
+    }
+
+    async fn dirty_pages(
+        &self,
+        request: Request<VmServiceDirtyPagesRequest>,
+    ) -> std::result::Result<Response<VmServiceDirtyPagesResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.dirty_pages(req).await })
+                .await?,
+        ))
+    }
+
+    async fn snapshot(
+        &self,
+        request: Request<VmServiceSnapshotRequest>,
+    ) -> std::result::Result<Response<VmServiceSnapshotResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.snapshot(req).await })
+                .await?,
+        ))
+    }
+
+    async fn restore(
+        &self,
+        request: Request<VmServiceRestoreRequest>,
+    ) -> std::result::Result<Response<VmServiceRestoreResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.restore(req).await })
+                .await?,
+        ))
+    }
+
+    async fn flatten_snapshot(
+        &self,
+        request: Request<VmServiceFlattenSnapshotRequest>,
+    ) -> std::result::Result<Response<VmServiceFlattenSnapshotResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(self.flatten_snapshot(req).await?))
+    }
+
+    async fn delete_snapshot(
+        &self,
+        request: Request<VmServiceDeleteSnapshotRequest>,
+    ) -> std::result::Result<Response<VmServiceDeleteSnapshotResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(self.delete_snapshot(req).await?))
+    }
+
+    async fn receive_migration(
+        &self,
+        request: Request<VmServiceReceiveMigrationRequest>,
+    ) -> std::result::Result<Response<VmServiceReceiveMigrationResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move {
+                vms.receive_migration(req).await
+            })
+            .await?,
+        ))
+    }
+
+    async fn migrate(
+        &self,
+        request: Request<VmServiceMigrateRequest>,
+    ) -> std::result::Result<Response<VmServiceMigrateResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.migrate(req).await })
+                .await?,
+        ))
     }
 
     async fn list(
@@ -232,4 +2375,114 @@ impl vm_service_server::VmService for VmService {
     ) -> std::result::Result<Response<VmServiceListResponse>, Status> {
         Ok(Response::new(self.list().await?))
     }
+
+    async fn export(
+        &self,
+        request: Request<VmServiceExportRequest>,
+    ) -> std::result::Result<Response<VmServiceExportResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(self.export(req).await?))
+    }
+
+    async fn relocate(
+        &self,
+        request: Request<VmServiceRelocateRequest>,
+    ) -> std::result::Result<Response<VmServiceRelocateResponse>, Status> {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(|vms| async move { vms.relocate(req).await })
+                .await?,
+        ))
+    }
+
+    async fn checkpoint_node(
+        &self,
+        request: Request<VmServiceCheckpointNodeRequest>,
+    ) -> std::result::Result<Response<VmServiceCheckpointNodeResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(
+                |vms| async move { vms.checkpoint_node(req).await },
+            )
+            .await?,
+        ))
+    }
+
+    async fn restore_node(
+        &self,
+        request: Request<VmServiceRestoreNodeRequest>,
+    ) -> std::result::Result<Response<VmServiceRestoreNodeResponse>, Status>
+    {
+        let req = request.into_inner();
+        Ok(Response::new(
+            self.on_vm_runtime(
+                |vms| async move { vms.restore_node(req).await },
+            )
+            .await?,
+        ))
+    }
+
+    type ConsoleStream = ConsoleStream;
+
+    async fn console(
+        &self,
+        request: Request<Streaming<VmServiceConsoleRequest>>,
+    ) -> std::result::Result<Response<Self::ConsoleStream>, Status> {
+        let mut requests = request.into_inner();
+        let Some(first) = requests.message().await? else {
+            return Err(VmServiceError::MissingConsoleRequest.into());
+        };
+        if !first.resume_token.is_empty() {
+            let (stream, writer) = self.consoles.resume(&first.resume_token)?;
+            forward_console_input(writer, first.input, requests);
+            return Ok(Response::new(stream));
+        }
+        Ok(Response::new(self.console(first, requests).await?))
+    }
+
+    async fn console_log(
+        &self,
+        request: Request<VmServiceConsoleLogRequest>,
+    ) -> std::result::Result<Response<VmServiceConsoleLogResponse>, Status>
+    {
+        Ok(Response::new(self.console_log(request.into_inner()).await?))
+    }
+
+    async fn attestation_report(
+        &self,
+        request: Request<VmServiceAttestationReportRequest>,
+    ) -> std::result::Result<Response<VmServiceAttestationReportResponse>, Status>
+    {
+        Ok(Response::new(self.attestation_report(request.into_inner()).await?))
+    }
+
+    type MigrationTunnelStream = MigrationTunnelStream;
+
+    async fn migration_tunnel(
+        &self,
+        request: Request<Streaming<VmServiceMigrationTunnelRequest>>,
+    ) -> std::result::Result<Response<Self::MigrationTunnelStream>, Status>
+    {
+        let mut requests = request.into_inner();
+        let Some(first) = requests.message().await? else {
+            return Err(VmServiceError::MissingMigrationTunnelRequest.into());
+        };
+        Ok(Response::new(self.migration_tunnel(first, requests).await?))
+    }
+
+    type GuestEventsStream = GuestEventsStream;
+
+    async fn guest_events(
+        &self,
+        request: Request<VmServiceGuestEventsRequest>,
+    ) -> std::result::Result<Response<Self::GuestEventsStream>, Status> {
+        let req = request.into_inner();
+        if !req.resume_token.is_empty() {
+            let (stream, ()) =
+                self.guest_event_streams.resume(&req.resume_token)?;
+            return Ok(Response::new(stream));
+        }
+        Ok(Response::new(self.guest_events(req).await?))
+    }
 }
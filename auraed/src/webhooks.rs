@@ -0,0 +1,278 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Webhooks notified about the lifecycle events of workloads, so external
+//! systems can react to them without holding a gRPC stream of the
+//! [ObserveService] open.
+//!
+//! Endpoints are configured in a JSON file, which may not exist:
+//!
+//! ```json
+//! {
+//!   "endpoints": [
+//!     {
+//!       "url": "https://hooks.example.com/aurae",
+//!       "secret": "secret",
+//!       "events": ["crashed", "oom_killed"]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Each event is POSTed as JSON to the endpoints that list its kind, or to
+//! every endpoint without `events`. The body is signed with HMAC-SHA256
+//! using the secret of the endpoint, in the `X-Aurae-Signature-256` header
+//! as `sha256=<hex>`. Deliveries that fail with a network error, a 429 or a
+//! 5xx response are retried with an exponential backoff, carrying the same
+//! `X-Aurae-Delivery` id so that receivers can drop duplicates.
+
+use crate::observe::{ObserveService, WorkloadEvent, WorkloadEventKind};
+use hmac::{Hmac, Mac};
+use reqwest::{header::CONTENT_TYPE, Client, StatusCode, Url};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::{io::ErrorKind, path::Path, sync::Arc, time::Duration};
+use thiserror::Error;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+const SIGNATURE_HEADER: &str = "X-Aurae-Signature-256";
+const DELIVERY_HEADER: &str = "X-Aurae-Delivery";
+/// Time a single attempt of a delivery may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// Time after which a delivery is given up.
+const MAX_DELIVERY_TIME: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Error)]
+pub(crate) enum WebhooksError {
+    #[error("failed to read webhooks config '{path}': {source}")]
+    Read { path: String, source: std::io::Error },
+    #[error("invalid webhooks config '{path}': {reason}")]
+    InvalidConfig { path: String, reason: String },
+    #[error("failed to build the webhooks client: {0}")]
+    Client(#[from] reqwest::Error),
+}
+
+/// The webhooks config of auraed.
+#[derive(Debug, Default, Deserialize)]
+struct WebhooksFile {
+    #[serde(default)]
+    endpoints: Vec<Endpoint>,
+}
+
+/// An HTTPS endpoint events are POSTed to.
+#[derive(Debug, Clone, Deserialize)]
+struct Endpoint {
+    url: String,
+    /// Key of the HMAC signature of the events
+    secret: String,
+    /// Kinds of events sent to the endpoint, all of them if empty
+    #[serde(default)]
+    events: Vec<WorkloadEventKind>,
+}
+
+impl Endpoint {
+    fn wants(&self, kind: WorkloadEventKind) -> bool {
+        self.events.is_empty() || self.events.contains(&kind)
+    }
+}
+
+/// The configured webhooks of the node.
+#[derive(Debug)]
+pub(crate) struct Webhooks {
+    client: Client,
+    endpoints: Vec<Endpoint>,
+}
+
+impl Webhooks {
+    /// Reads the endpoints configured in `path`, none if it does not exist.
+    pub fn load(path: &Path) -> Result<Self, WebhooksError> {
+        let file = match std::fs::read(path) {
+            Ok(contents) => parse(&contents).map_err(|reason| {
+                WebhooksError::InvalidConfig {
+                    path: path.display().to_string(),
+                    reason,
+                }
+            })?,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                WebhooksFile::default()
+            }
+            Err(source) => {
+                return Err(WebhooksError::Read {
+                    path: path.display().to_string(),
+                    source,
+                })
+            }
+        };
+
+        let client = Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .https_only(true)
+            .build()?;
+
+        Ok(Self { client, endpoints: file.endpoints })
+    }
+
+    /// Delivers the workload events of `observe_service` until it is
+    /// dropped. Does nothing if no endpoint is configured.
+    pub fn notify(self, observe_service: &ObserveService) {
+        if self.endpoints.is_empty() {
+            return;
+        }
+        info!("Notifying {} webhook endpoints", self.endpoints.len());
+
+        let mut events = observe_service.subscribe_workload_events();
+        let webhooks = Arc::new(self);
+        let _ignored = tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => webhooks.deliver(&event),
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Webhooks missed {missed} workload events")
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+    }
+
+    /// Posts the event to each endpoint that wants it, in the background.
+    fn deliver(&self, event: &WorkloadEvent) {
+        let body = match serde_json::to_vec(event) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Failed to serialize workload event: {e}");
+                return;
+            }
+        };
+
+        for endpoint in self.endpoints.iter().filter(|e| e.wants(event.kind)) {
+            let client = self.client.clone();
+            let endpoint = endpoint.clone();
+            let body = body.clone();
+            let _ignored = tokio::spawn(async move {
+                let delivery = uuid::Uuid::new_v4().to_string();
+                if let Err(e) = post(&client, &endpoint, &delivery, body).await
+                {
+                    warn!(
+                        "Failed to deliver webhook {delivery} to {}: {e}",
+                        endpoint.url
+                    );
+                }
+            });
+        }
+    }
+}
+
+fn parse(contents: &[u8]) -> Result<WebhooksFile, String> {
+    let file: WebhooksFile =
+        serde_json::from_slice(contents).map_err(|e| e.to_string())?;
+    for endpoint in &file.endpoints {
+        let url = Url::parse(&endpoint.url)
+            .map_err(|e| format!("{}: {e}", endpoint.url))?;
+        if url.scheme() != "https" {
+            return Err(format!("{} is not an https url", endpoint.url));
+        }
+        if endpoint.secret.is_empty() {
+            return Err(format!("{} has no secret", endpoint.url));
+        }
+    }
+    Ok(file)
+}
+
+/// The `X-Aurae-Signature-256` header of a body.
+fn signature(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret)
+        .expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+async fn post(
+    client: &Client,
+    endpoint: &Endpoint,
+    delivery: &str,
+    body: Vec<u8>,
+) -> Result<(), reqwest::Error> {
+    let signature = signature(endpoint.secret.as_bytes(), &body);
+    let retry_strategy = backoff::ExponentialBackoffBuilder::new()
+        .with_initial_interval(Duration::from_secs(1))
+        .with_max_interval(Duration::from_secs(60))
+        .with_max_elapsed_time(Some(MAX_DELIVERY_TIME))
+        .build();
+
+    backoff::future::retry(retry_strategy, || async {
+        // network errors are transient
+        let response = client
+            .post(&endpoint.url)
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header(DELIVERY_HEADER, delivery)
+            .body(body.clone())
+            .send()
+            .await?;
+
+        match response.error_for_status() {
+            Ok(_) => Ok(()),
+            Err(e) if e.status().is_some_and(retryable) => {
+                Err(backoff::Error::transient(e))
+            }
+            Err(e) => Err(backoff::Error::Permanent(e)),
+        }
+    })
+    .await
+}
+
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_the_body_with_hmac_sha256() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            signature(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c7\
+             5a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn parses_endpoints_and_rejects_plain_http() {
+        let file = parse(
+            br#"{"endpoints": [
+                {"url": "https://hooks.example.com", "secret": "s"},
+                {"url": "https://oom.example.com", "secret": "s",
+                 "events": ["oom_killed"]}
+            ]}"#,
+        )
+        .expect("valid config");
+        assert!(file.endpoints[0].wants(WorkloadEventKind::Started));
+        assert!(file.endpoints[1].wants(WorkloadEventKind::OomKilled));
+        assert!(!file.endpoints[1].wants(WorkloadEventKind::Crashed));
+
+        assert!(parse(
+            br#"{"endpoints": [{"url": "http://a.example.com", "secret": "s"}]}"#
+        )
+        .is_err());
+        assert!(parse(
+            br#"{"endpoints": [{"url": "https://a.example.com", "secret": ""}]}"#
+        )
+        .is_err());
+    }
+}
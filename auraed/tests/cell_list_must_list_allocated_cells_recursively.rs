@@ -89,6 +89,7 @@ async fn cells_list_must_list_allocated_cells_recursively() {
                     memory: None,
                     isolate_process: false,
                     isolate_network: false,
+                    time_namespace: None,
                 }),
                 children: vec![],
             },
@@ -100,6 +101,7 @@ async fn cells_list_must_list_allocated_cells_recursively() {
                     memory: None,
                     isolate_process: false,
                     isolate_network: false,
+                    time_namespace: None,
                 }),
                 children: vec![CellGraphNode {
                     cell: Some(Cell {
@@ -109,6 +111,7 @@ async fn cells_list_must_list_allocated_cells_recursively() {
                         memory: None,
                         isolate_process: false,
                         isolate_network: false,
+                        time_namespace: None,
                     }),
                     children: vec![CellGraphNode {
                         cell: Some(Cell {
@@ -118,6 +121,7 @@ async fn cells_list_must_list_allocated_cells_recursively() {
                             memory: None,
                             isolate_process: false,
                             isolate_network: false,
+                            time_namespace: None,
                         }),
                         children: vec![],
                     }],
@@ -0,0 +1,70 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use client::cells::cell_service::CellServiceClient;
+use common::cells::CellServiceAllocateRequestBuilder;
+use pretty_assertions::assert_eq;
+use proto::cells::{Cell, CellServiceListRequest};
+use test_helpers::*;
+
+mod common;
+
+#[test_helpers_macros::shared_runtime_test]
+async fn cells_allocate_dry_run_must_not_allocate_cell() {
+    skip_if_not_root!("cells_allocate_dry_run_must_not_allocate_cell");
+    skip_if_seccomp!("cells_allocate_dry_run_must_not_allocate_cell");
+
+    let client = common::auraed_client().await;
+
+    // Dry run the allocation of a cell
+    let response = retry!(
+        client
+            .allocate(
+                CellServiceAllocateRequestBuilder::new().dry_run().build()
+            )
+            .await
+    )
+    .unwrap()
+    .into_inner();
+
+    // The normalized cell is returned
+    assert_eq!(
+        response.cell,
+        Some(Cell {
+            name: response.cell_name.clone(),
+            cpu: None,
+            cpuset: None,
+            memory: None,
+            isolate_process: false,
+            isolate_network: false,
+            time_namespace: None,
+        })
+    );
+
+    // The cgroup the cell would be allocated in is returned
+    assert_eq!(
+        response.cgroup_path,
+        format!("/sys/fs/cgroup/{}", response.cell_name)
+    );
+
+    // The cell has not been allocated
+    let list_response = retry!(client.list(CellServiceListRequest {}).await)
+        .unwrap()
+        .into_inner();
+    assert!(!list_response.cells.iter().any(|node| node
+        .cell
+        .as_ref()
+        .is_some_and(|cell| cell.name == response.cell_name)));
+}
@@ -0,0 +1,79 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+use client::cells::cell_service::CellServiceClient;
+use common::cells::{
+    CellServiceAllocateRequestBuilder, CellServiceStartRequestBuilder,
+};
+use pretty_assertions::assert_eq;
+use proto::cells::{CellServiceFreeRequest, CellServiceStopRequest};
+use test_helpers::*;
+
+mod common;
+
+#[test_helpers_macros::shared_runtime_test]
+async fn cells_stop_must_return_exit_record() {
+    skip_if_not_root!("cells_stop_must_return_exit_record");
+    skip_if_seccomp!("cells_stop_must_return_exit_record");
+
+    let client = common::auraed_client().await;
+
+    let cell_name = retry!(
+        client.allocate(CellServiceAllocateRequestBuilder::new().build()).await
+    )
+    .unwrap()
+    .into_inner()
+    .cell_name;
+
+    let executable_name = format!("ae-exit-record-{}", uuid::Uuid::new_v4());
+    let _ = retry!(
+        client
+            .start(
+                CellServiceStartRequestBuilder::new()
+                    .cell_name(cell_name.clone())
+                    .executable_name(executable_name.clone())
+                    .build(),
+            )
+            .await
+    )
+    .unwrap();
+
+    let exit_record = retry!(
+        client
+            .stop(CellServiceStopRequest {
+                cell_name: Some(cell_name.clone()),
+                executable_name: executable_name.clone(),
+            })
+            .await
+    )
+    .unwrap()
+    .into_inner()
+    .exit_record
+    .expect("stop returns an exit record");
+
+    // The executable was killed and reaped by the nested auraed
+    assert_eq!(exit_record.executable_name, executable_name);
+    assert_eq!(exit_record.exit_code, -1);
+    assert_eq!(exit_record.signal, 9);
+    assert!(exit_record.max_rss_bytes > 0, "{exit_record:?}");
+    assert!(exit_record.started_at > 0, "{exit_record:?}");
+    assert!(exit_record.exited_at >= exit_record.started_at);
+
+    let _ = retry!(
+        client
+            .free(CellServiceFreeRequest { cell_name: cell_name.clone() })
+            .await
+    )
+    .unwrap();
+}
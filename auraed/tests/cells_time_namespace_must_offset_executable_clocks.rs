@@ -0,0 +1,85 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use client::cells::cell_service::CellServiceClient;
+use common::cells::{
+    CellServiceAllocateRequestBuilder, CellServiceStartRequestBuilder,
+};
+use pretty_assertions::assert_eq;
+use proto::cells::{CellServiceFreeRequest, TimeNamespace};
+use test_helpers::*;
+
+mod common;
+
+#[test_helpers_macros::shared_runtime_test]
+async fn cells_time_namespace_must_offset_executable_clocks() {
+    skip_if_not_root!("cells_time_namespace_must_offset_executable_clocks");
+    skip_if_seccomp!("cells_time_namespace_must_offset_executable_clocks");
+
+    let client = common::auraed_client().await;
+
+    let time_namespace = TimeNamespace {
+        monotonic_offset_seconds: 3600,
+        boottime_offset_seconds: 86400,
+    };
+
+    // Allocate a cell with its clocks shifted
+    let response = retry!(
+        client
+            .allocate(
+                CellServiceAllocateRequestBuilder::new()
+                    .time_namespace(time_namespace.clone())
+                    .build()
+            )
+            .await
+    )
+    .unwrap()
+    .into_inner();
+    assert_eq!(
+        response.cell.and_then(|cell| cell.time_namespace),
+        Some(time_namespace)
+    );
+    let cell_name = response.cell_name;
+
+    // Start an executable in the cell
+    let pid = retry!(
+        client
+            .start(
+                CellServiceStartRequestBuilder::new()
+                    .cell_name(cell_name.clone())
+                    .build(),
+            )
+            .await
+    )
+    .unwrap()
+    .into_inner()
+    .pid;
+
+    // The executable runs in a time namespace with the requested offsets
+    let offsets =
+        std::fs::read_to_string(format!("/proc/{pid}/timens_offsets"))
+            .expect("failed to read the time namespace offsets");
+    let offsets: Vec<Vec<&str>> =
+        offsets.lines().map(|line| line.split_whitespace().collect()).collect();
+    assert!(offsets.contains(&vec!["monotonic", "3600", "0"]), "{offsets:?}");
+    assert!(offsets.contains(&vec!["boottime", "86400", "0"]), "{offsets:?}");
+
+    let _ = retry!(
+        client
+            .free(CellServiceFreeRequest { cell_name: cell_name.clone() })
+            .await
+    )
+    .unwrap();
+}
@@ -16,6 +16,7 @@
 
 use proto::cells::{
     Cell, CellServiceAllocateRequest, CellServiceStartRequest, Executable,
+    TimeNamespace,
 };
 
 fn generate_cell_name(parent_name: Option<&str>) -> String {
@@ -29,11 +30,12 @@ fn generate_cell_name(parent_name: Option<&str>) -> String {
 struct CellBuilder {
     parent: Option<String>,
     isolate_process: bool,
+    time_namespace: Option<TimeNamespace>,
 }
 
 impl CellBuilder {
     pub fn new() -> Self {
-        Self { parent: None, isolate_process: false }
+        Self { parent: None, isolate_process: false, time_namespace: None }
     }
 
     pub fn parent_cell_name(&mut self, parent_cell_name: String) -> &mut Self {
@@ -46,6 +48,14 @@ impl CellBuilder {
         self
     }
 
+    pub fn time_namespace(
+        &mut self,
+        time_namespace: TimeNamespace,
+    ) -> &mut Self {
+        self.time_namespace = Some(time_namespace);
+        self
+    }
+
     pub fn build(&self) -> Cell {
         let cell_name = generate_cell_name(self.parent.as_deref());
         Cell {
@@ -55,17 +65,19 @@ impl CellBuilder {
             memory: None,
             isolate_network: false,
             isolate_process: self.isolate_process,
+            time_namespace: self.time_namespace.clone(),
         }
     }
 }
 
 pub struct CellServiceAllocateRequestBuilder {
     cell_builder: CellBuilder,
+    dry_run: bool,
 }
 
 impl CellServiceAllocateRequestBuilder {
     pub fn new() -> Self {
-        Self { cell_builder: CellBuilder::new() }
+        Self { cell_builder: CellBuilder::new(), dry_run: false }
     }
 
     pub fn parent_cell_name(&mut self, parent_cell_name: String) -> &mut Self {
@@ -78,8 +90,28 @@ impl CellServiceAllocateRequestBuilder {
         self
     }
 
+    pub fn time_namespace(
+        &mut self,
+        time_namespace: TimeNamespace,
+    ) -> &mut Self {
+        let _ = self.cell_builder.time_namespace(time_namespace);
+        self
+    }
+
+    pub fn dry_run(&mut self) -> &mut Self {
+        self.dry_run = true;
+        self
+    }
+
     pub fn build(&self) -> CellServiceAllocateRequest {
-        CellServiceAllocateRequest { cell: Some(self.cell_builder.build()) }
+        CellServiceAllocateRequest {
+            cell: Some(self.cell_builder.build()),
+            ttl_seconds: None,
+            ttl_grace_period_seconds: None,
+            shutdown_policy: 0,
+            dry_run: self.dry_run,
+            autoscaler: None,
+        }
     }
 }
 
@@ -108,6 +140,7 @@ impl ExecutableBuilder {
             name: self.name.clone(),
             command: self.command.clone(),
             description: self.description.clone(),
+            log_ring_size_kb: 0,
         }
     }
 }
@@ -156,6 +189,7 @@ impl CellServiceStartRequestBuilder {
             executable: Some(self.executable_builder.build()),
             uid: self.uid,
             gid: self.gid,
+            dry_run: false,
         }
     }
 }
@@ -69,6 +69,22 @@ async fn run_auraed() -> Client {
         .to_string_lossy()
         .to_string();
 
+    tokio::spawn({
+        let socket = socket.clone();
+        async move {
+            let runtime = AuraedRuntime {
+                auraed: AuraedPath::from_path("auraed"),
+                ..Default::default()
+            };
+            auraed::run(runtime, Some(socket), false, false).await.unwrap()
+        }
+    });
+
+    connect(socket).await
+}
+
+/// Connects to the auraed listening on `socket`, retrying while it starts.
+pub async fn connect(socket: String) -> Client {
     // TODO: using "~/.aurae/pki/ca.crt" errors with file not found (confirmed it exists)
     //   even though that is the default in default.config.toml in auraescript.
     let client_config = AuraeConfig {
@@ -77,19 +93,9 @@ async fn run_auraed() -> Client {
             client_crt: "/etc/aurae/pki/_signed.client.nova.crt".to_string(),
             client_key: "/etc/aurae/pki/client.nova.key".to_string(),
         },
-        system: SystemConfig {
-            socket: AuraeSocket::Path(socket.clone().into()),
-        },
+        system: SystemConfig { socket: AuraeSocket::Path(socket.into()) },
     };
 
-    tokio::spawn(async move {
-        let runtime = AuraedRuntime {
-            auraed: AuraedPath::from_path("auraed"),
-            ..Default::default()
-        };
-        auraed::run(runtime, Some(socket), false, false).await.unwrap()
-    });
-
     let mut retry_strategy = default_retry_strategy();
 
     loop {
@@ -17,7 +17,8 @@
 use crate::retry;
 use client::{observe::observe_service::ObserveServiceClient, Client};
 use proto::observe::{
-    GetPosixSignalsStreamRequest, Signal, Workload, WorkloadType,
+    GetPosixSignalsStreamRequest, GetProcessExecStreamRequest, ProcessExec,
+    Signal, Workload, WorkloadType,
 };
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -46,6 +47,29 @@ pub async fn intercept_posix_signals_stream(
     intercepted
 }
 
+pub async fn intercept_process_exec_stream(
+    client: &Client,
+    req: GetProcessExecStreamRequest,
+) -> Arc<Mutex<Vec<ProcessExec>>> {
+    let res = retry!(client.get_process_exec_stream(req.clone()).await);
+    assert!(res.is_ok());
+
+    let mut execs = res.expect("GetProcessExecStreamResponse").into_inner();
+
+    let intercepted = Arc::new(Mutex::new(Vec::new()));
+    let intercepted_in_thread = intercepted.clone();
+
+    let _ignored = tokio::spawn(async move {
+        while let Some(res) = futures_util::StreamExt::next(&mut execs).await {
+            let res = res.expect("exec");
+            let mut guard = intercepted_in_thread.lock().await;
+            guard.push(res.exec.expect("exec"));
+        }
+    });
+
+    intercepted
+}
+
 pub(crate) struct GetPosixSignalsStreamRequestBuilder {
     workload: Option<Workload>,
 }
@@ -64,6 +88,9 @@ impl GetPosixSignalsStreamRequestBuilder {
     }
 
     pub fn build(&self) -> GetPosixSignalsStreamRequest {
-        GetPosixSignalsStreamRequest { workload: self.workload.clone() }
+        GetPosixSignalsStreamRequest {
+            workload: self.workload.clone(),
+            ..Default::default()
+        }
     }
 }
\ No newline at end of file
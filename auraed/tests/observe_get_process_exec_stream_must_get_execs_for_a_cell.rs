@@ -0,0 +1,97 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use client::cells::cell_service::CellServiceClient;
+use common::{
+    cells::{
+        CellServiceAllocateRequestBuilder, CellServiceStartRequestBuilder,
+    },
+    observe::intercept_process_exec_stream,
+};
+use proto::{
+    cells::CellServiceStopRequest,
+    observe::{GetProcessExecStreamRequest, Workload, WorkloadType},
+};
+use std::time::Duration;
+use test_helpers::*;
+
+mod common;
+
+#[test_helpers_macros::shared_runtime_test]
+#[ignore = "we can not run eBPF tests in Github actions"]
+async fn observe_get_process_exec_stream_must_get_execs_for_a_cell() {
+    skip_if_not_root!("must_get_execs_for_a_cell");
+    skip_if_seccomp!("must_get_execs_for_a_cell");
+
+    let client = common::auraed_client().await;
+
+    // Allocate a cell
+    let cell_name = retry!(
+        client.allocate(CellServiceAllocateRequestBuilder::new().build()).await
+    )
+    .unwrap()
+    .into_inner()
+    .cell_name;
+
+    // Start intercepting execs in the cell
+    let intercepted_execs = intercept_process_exec_stream(
+        &client,
+        GetProcessExecStreamRequest {
+            workload: Some(Workload {
+                workload_type: WorkloadType::Cell.into(),
+                id: cell_name.clone(),
+            }),
+            ..Default::default()
+        },
+    )
+    .await;
+
+    // Start an executable, which runs tail
+    let exe_name = format!("ae-e2e-{}", uuid::Uuid::new_v4());
+    let pid = retry!(
+        client
+            .start(
+                CellServiceStartRequestBuilder::new()
+                    .cell_name(cell_name.clone())
+                    .executable_name(exe_name.clone())
+                    .build(),
+            )
+            .await
+    )
+    .unwrap()
+    .into_inner()
+    .pid;
+
+    // Wait for a little for the execs to arrive
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let _ = retry!(
+        client
+            .stop(CellServiceStopRequest {
+                cell_name: Some(cell_name.clone()),
+                executable_name: exe_name.clone(),
+            })
+            .await
+    );
+
+    let guard = intercepted_execs.lock().await;
+
+    // Assert we intercepted the exec of tail
+    assert!(
+        guard.iter().any(|e| e.process_id == pid && e.path.ends_with("tail")),
+        "exec not found\nexpected pid: {pid}\nintercepted: {guard:#?}",
+    );
+    assert!(guard.iter().all(|e| e.argv_hash != 0), "execs without argv hash");
+}
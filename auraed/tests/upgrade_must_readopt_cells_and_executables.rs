@@ -0,0 +1,149 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+
+use client::cells::cell_service::CellServiceClient;
+use common::cells::{
+    CellServiceAllocateRequestBuilder, CellServiceStartRequestBuilder,
+};
+use nix::{
+    sys::signal::{kill, Signal},
+    unistd::Pid,
+};
+use proto::cells::{
+    CellServiceFreeRequest, CellServiceListRequest, CellServiceStartRequest,
+    CellServiceStopRequest, Executable,
+};
+use std::{
+    path::Path,
+    process::{Child, Command},
+};
+use test_helpers::*;
+
+mod common;
+
+fn spawn_auraed(runtime_dir: &Path, socket: &str) -> Child {
+    Command::new(env!("CARGO_BIN_EXE_auraed"))
+        .arg("--runtime-dir")
+        .arg(runtime_dir)
+        .arg("--socket")
+        .arg(socket)
+        .spawn()
+        .expect("failed to spawn auraed")
+}
+
+fn send_signal(auraed: &mut Child, signal: Signal) {
+    kill(Pid::from_raw(auraed.id() as i32), signal).unwrap();
+    let _ = auraed.wait().unwrap();
+}
+
+#[test_helpers_macros::shared_runtime_test]
+async fn upgrade_must_readopt_cells_and_executables() {
+    skip_if_not_root!("upgrade_must_readopt_cells_and_executables");
+    skip_if_seccomp!("upgrade_must_readopt_cells_and_executables");
+
+    let scratch = tempfile::tempdir().unwrap();
+    let runtime_dir = scratch.path();
+    let socket = runtime_dir.join("aurae.sock").to_string_lossy().to_string();
+
+    let mut auraed = spawn_auraed(runtime_dir, &socket);
+    let client = common::connect(socket.clone()).await;
+
+    // Allocate a cell and start an executable in it
+    let cell_name = retry!(
+        client.allocate(CellServiceAllocateRequestBuilder::new().build()).await
+    )
+    .unwrap()
+    .into_inner()
+    .cell_name;
+
+    let _ = retry!(
+        client
+            .start(
+                CellServiceStartRequestBuilder::new()
+                    .cell_name(cell_name.clone())
+                    .build(),
+            )
+            .await
+    )
+    .unwrap();
+
+    // Start an executable directly on the host
+    let exe_name = format!("ae-e2e-{}", uuid::Uuid::new_v4());
+    let pid = retry!(
+        client
+            .start(CellServiceStartRequest {
+                cell_name: None,
+                executable: Some(Executable {
+                    name: exe_name.clone(),
+                    command: "tail -f /dev/null".to_string(),
+                    description: String::from("description"),
+                    log_ring_size_kb: 0,
+                }),
+                uid: None,
+                gid: None,
+                dry_run: false,
+            })
+            .await
+    )
+    .unwrap()
+    .into_inner()
+    .pid;
+
+    // Exit for an upgrade, the executable must outlive auraed
+    send_signal(&mut auraed, Signal::SIGUSR2);
+    assert!(
+        kill(Pid::from_raw(pid), None).is_ok(),
+        "executable was stopped when auraed exited for an upgrade"
+    );
+
+    // The next auraed must adopt the cell and the executable
+    let mut auraed = spawn_auraed(runtime_dir, &socket);
+    let client = common::connect(socket.clone()).await;
+
+    let cells = retry!(client.list(CellServiceListRequest {}).await)
+        .unwrap()
+        .into_inner()
+        .cells;
+    assert!(
+        cells
+            .iter()
+            .filter_map(|node| node.cell.as_ref())
+            .any(|cell| cell.name == cell_name),
+        "cell {cell_name} was not adopted, got {cells:?}"
+    );
+
+    let _ = retry!(
+        client
+            .stop(CellServiceStopRequest {
+                cell_name: None,
+                executable_name: exe_name.clone(),
+            })
+            .await
+    )
+    .unwrap();
+    assert!(
+        kill(Pid::from_raw(pid), None).is_err(),
+        "adopted executable was not stopped"
+    );
+
+    let _ = retry!(
+        client
+            .free(CellServiceFreeRequest { cell_name: cell_name.clone() })
+            .await
+    )
+    .unwrap();
+
+    send_signal(&mut auraed, Signal::SIGTERM);
+}
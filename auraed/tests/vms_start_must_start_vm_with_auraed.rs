@@ -56,8 +56,24 @@ async fn vms_with_auraed() {
                         read_only: false,
                     }),
                     drive_mounts: vec![],
-                    auraed_address: String::new()
+                    auraed_address: String::new(),
+                    network_interfaces: vec![],
+                    vfio_devices: vec![],
+                    cloud_init: None,
+                    confidential_computing: None,
+                    initrd_path: String::new(),
+                    nested_virtualization: false,
+                    firmware_path: String::new(),
+                    vcpu_affinity: vec![],
+                    guest_memory: None,
+                    uuid: String::new(),
+                    serial_number: String::new(),
+                    cpu_topology: None,
+                    watchdog: None,
+                    cpuid_masks: vec![],
                 }),
+                shutdown_policy: 0,
+                dry_run: false,
             }
         )
         .await
@@ -93,6 +93,8 @@ mod cells;
 mod cri;
 mod discovery;
 mod health;
+mod images;
+mod network;
 mod observe;
 mod vms;
 
@@ -140,6 +142,8 @@ fn stdlib() -> Vec<deno_core::OpDecl> {
     ops.extend(cri::op_decls());
     ops.extend(discovery::op_decls());
     ops.extend(health::op_decls());
+    ops.extend(images::op_decls());
+    ops.extend(network::op_decls());
     ops.extend(observe::op_decls());
     ops.extend(vms::op_decls());
     ops
@@ -20,7 +20,9 @@
 
 use crate::config::{AuraeConfig, CertMaterial, ClientCertDetails};
 use crate::AuraeSocket;
+use std::path::PathBuf;
 use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpStream, UnixStream};
 use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity, Uri};
 use tower::service_fn;
@@ -111,8 +113,45 @@ impl Client {
                     }))
                     .await
             }
+            AuraeSocket::Vsock { path, port } => {
+                endpoint
+                    .connect_with_connector(service_fn(move |_: Uri| {
+                        connect_vsock(path.clone(), port)
+                    }))
+                    .await
+            }
         }?;
 
         Ok(channel)
     }
+}
+
+/// Connects to `port` of the guest behind the vsock socket `path` of a VMM.
+/// The VMM forwards the stream to the guest once it was sent
+/// `CONNECT <port>` and replied `OK <host port>`.
+async fn connect_vsock(
+    path: PathBuf,
+    port: u32,
+) -> std::io::Result<UnixStream> {
+    let mut stream = UnixStream::connect(path).await?;
+    stream.write_all(format!("CONNECT {port}\n").as_bytes()).await?;
+
+    // Read the reply byte by byte, so nothing past it is consumed
+    let mut reply = Vec::new();
+    loop {
+        match stream.read_u8().await? {
+            b'\n' => break,
+            byte => reply.push(byte),
+        }
+    }
+    if !reply.starts_with(b"OK ") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::ConnectionRefused,
+            format!(
+                "vsock port {port} refused: {}",
+                String::from_utf8_lossy(&reply)
+            ),
+        ));
+    }
+    Ok(stream)
 }
\ No newline at end of file
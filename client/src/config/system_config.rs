@@ -40,6 +40,12 @@ pub struct SystemConfig {
 pub enum AuraeSocket {
     Path(PathBuf),
     Addr(SocketAddr),
+    /// A port of the guest of a VM, reached through the unix socket the VMM
+    /// exposes the vsock device of the VM on. Never deserialized.
+    Vsock {
+        path: PathBuf,
+        port: u32,
+    },
 }
 
 impl<'de> Deserialize<'de> for AuraeSocket {
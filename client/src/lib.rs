@@ -21,5 +21,8 @@ mod config;
 pub mod cri;
 pub mod discovery;
 pub mod grpc;
+pub mod images;
+pub mod network;
 pub mod observe;
+pub mod quota;
 pub mod vms;
\ No newline at end of file
@@ -0,0 +1,270 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+//! Quotas rejecting requests, as reported by auraed.
+//!
+//! A request rejected by a quota (a size or rate limit, the disk running
+//! full) fails with `RESOURCE_EXHAUSTED`. The status details, in the
+//! `google.rpc` error model, name the quota in a `QuotaFailure`, its usage
+//! and limit in an `ErrorInfo`, and when to retry in a `RetryInfo` if
+//! retrying later can succeed. [Quota] encodes and decodes these details.
+
+use prost::{bytes::Bytes, Message};
+use std::{collections::HashMap, fmt, time::Duration};
+use tonic::{Code, Status};
+
+/// Domain of the `ErrorInfo` of quotas.
+const DOMAIN: &str = "aurae.io";
+/// Reason of the `ErrorInfo` of quotas.
+const REASON: &str = "QUOTA_EXCEEDED";
+
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/";
+const QUOTA_FAILURE: &str = "google.rpc.QuotaFailure";
+const ERROR_INFO: &str = "google.rpc.ErrorInfo";
+const RETRY_INFO: &str = "google.rpc.RetryInfo";
+
+/// A quota that rejected a request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Quota {
+    /// Which quota, e.g. `max_decoding_message_size`
+    pub name: String,
+    /// What the quota applies to, e.g. a directory for a disk quota
+    pub subject: String,
+    /// Usage of the quota, including the request if it could be measured
+    pub current: u64,
+    pub limit: u64,
+    /// When the request can succeed if retried, None if only changing the
+    /// request or the quota helps
+    pub retry_after: Option<Duration>,
+}
+
+impl Quota {
+    /// A `RESOURCE_EXHAUSTED` status with `message`, detailing the quota.
+    pub fn into_status(self, message: impl Into<String>) -> Status {
+        let message = message.into();
+        let mut details = vec![
+            any(
+                QUOTA_FAILURE,
+                QuotaFailure {
+                    violations: vec![Violation {
+                        subject: self.subject,
+                        description: message.clone(),
+                        quota_id: self.name.clone(),
+                        quota_value: i64::try_from(self.limit)
+                            .unwrap_or(i64::MAX),
+                    }],
+                },
+            ),
+            any(
+                ERROR_INFO,
+                ErrorInfo {
+                    reason: REASON.into(),
+                    domain: DOMAIN.into(),
+                    metadata: HashMap::from([
+                        ("quota".into(), self.name),
+                        ("current".into(), self.current.to_string()),
+                        ("limit".into(), self.limit.to_string()),
+                    ]),
+                },
+            ),
+        ];
+        if let Some(retry_after) = self.retry_after {
+            details.push(any(
+                RETRY_INFO,
+                RetryInfo {
+                    retry_delay: Some(ProtoDuration {
+                        seconds: retry_after.as_secs() as i64,
+                        nanos: retry_after.subsec_nanos() as i32,
+                    }),
+                },
+            ));
+        }
+
+        let status = RpcStatus {
+            code: Code::ResourceExhausted as i32,
+            message: message.clone(),
+            details,
+        };
+        Status::with_details(
+            Code::ResourceExhausted,
+            message,
+            Bytes::from(status.encode_to_vec()),
+        )
+    }
+
+    /// The quota detailed by `status`, None if it was not rejected by one.
+    pub fn from_status(status: &Status) -> Option<Self> {
+        if status.code() != Code::ResourceExhausted {
+            return None;
+        }
+        let status = RpcStatus::decode(status.details()).ok()?;
+        let detail = |name: &str| {
+            status.details.iter().find(|detail| {
+                detail.type_url.strip_prefix(TYPE_URL_PREFIX) == Some(name)
+            })
+        };
+
+        let violation = detail(QUOTA_FAILURE)
+            .and_then(|any| QuotaFailure::decode(&*any.value).ok())?
+            .violations
+            .into_iter()
+            .next()?;
+        let info = detail(ERROR_INFO)
+            .and_then(|any| ErrorInfo::decode(&*any.value).ok())
+            .filter(|info| info.domain == DOMAIN);
+        let usage = |key: &str| {
+            info.as_ref()?.metadata.get(key).and_then(|v| v.parse().ok())
+        };
+        let retry_after = detail(RETRY_INFO)
+            .and_then(|any| RetryInfo::decode(&*any.value).ok())
+            .and_then(|info| info.retry_delay)
+            .map(|delay| {
+                Duration::new(
+                    u64::try_from(delay.seconds).unwrap_or(0),
+                    u32::try_from(delay.nanos).unwrap_or(0),
+                )
+            });
+
+        Some(Self {
+            current: usage("current").unwrap_or(0),
+            limit: usage("limit")
+                .unwrap_or(u64::try_from(violation.quota_value).unwrap_or(0)),
+            name: violation.quota_id,
+            subject: violation.subject,
+            retry_after,
+        })
+    }
+
+    /// The quota detailed by the status `err` was caused by, if any.
+    pub fn from_error(err: &anyhow::Error) -> Option<Self> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<Status>())
+            .and_then(Self::from_status)
+    }
+}
+
+impl fmt::Display for Quota {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "quota '{}' of {} is exhausted: {} of {}",
+            self.name, self.subject, self.current, self.limit
+        )?;
+        match self.retry_after {
+            Some(retry_after) => {
+                write!(f, ", retry in {:.1}s", retry_after.as_secs_f64())
+            }
+            None => write!(f, ", retrying will not help"),
+        }
+    }
+}
+
+fn any(name: &str, message: impl Message) -> Any {
+    Any {
+        type_url: format!("{TYPE_URL_PREFIX}{name}"),
+        value: message.encode_to_vec(),
+    }
+}
+
+// The messages of the `google.rpc` error model used, as far as needed.
+
+/// `google.rpc.Status`
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(int32, tag = "1")]
+    code: i32,
+    #[prost(string, tag = "2")]
+    message: String,
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<Any>,
+}
+
+/// `google.protobuf.Any`
+#[derive(Clone, PartialEq, Message)]
+struct Any {
+    #[prost(string, tag = "1")]
+    type_url: String,
+    #[prost(bytes = "vec", tag = "2")]
+    value: Vec<u8>,
+}
+
+/// `google.protobuf.Duration`
+#[derive(Clone, PartialEq, Message)]
+struct ProtoDuration {
+    #[prost(int64, tag = "1")]
+    seconds: i64,
+    #[prost(int32, tag = "2")]
+    nanos: i32,
+}
+
+/// `google.rpc.QuotaFailure`
+#[derive(Clone, PartialEq, Message)]
+struct QuotaFailure {
+    #[prost(message, repeated, tag = "1")]
+    violations: Vec<Violation>,
+}
+
+/// `google.rpc.QuotaFailure.Violation`
+#[derive(Clone, PartialEq, Message)]
+struct Violation {
+    #[prost(string, tag = "1")]
+    subject: String,
+    #[prost(string, tag = "2")]
+    description: String,
+    #[prost(string, tag = "5")]
+    quota_id: String,
+    #[prost(int64, tag = "7")]
+    quota_value: i64,
+}
+
+/// `google.rpc.ErrorInfo`
+#[derive(Clone, PartialEq, Message)]
+struct ErrorInfo {
+    #[prost(string, tag = "1")]
+    reason: String,
+    #[prost(string, tag = "2")]
+    domain: String,
+    #[prost(map = "string, string", tag = "3")]
+    metadata: HashMap<String, String>,
+}
+
+/// `google.rpc.RetryInfo`
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<ProtoDuration>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_a_status() {
+        let quota = Quota {
+            name: "max_decoding_message_size".into(),
+            subject: "/aurae.cells.v0.CellService/Allocate".into(),
+            current: 5000,
+            limit: 4096,
+            retry_after: Some(Duration::from_millis(1500)),
+        };
+        let status = quota.clone().into_status("request too large");
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(Quota::from_status(&status), Some(quota));
+        assert_eq!(
+            Quota::from_status(&Status::resource_exhausted("no details")),
+            None
+        );
+    }
+}
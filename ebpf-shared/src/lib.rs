@@ -53,4 +53,172 @@ pub struct ForkedProcess {
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct ProcessExit {
     pub pid: i32,
-}
\ No newline at end of file
+}
+/// Bytes of the path of an executed binary a [ProcessExec] carries, longer
+/// paths are truncated.
+pub const EXEC_FILENAME_LEN: usize = 256;
+/// Arguments hashed into [ProcessExec::argv_hash], later ones are ignored.
+pub const EXEC_MAX_ARGS: usize = 32;
+/// Bytes of each argument hashed into [ProcessExec::argv_hash], longer
+/// arguments are truncated.
+pub const EXEC_MAX_ARG_LEN: usize = 127;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProcessExec {
+    pub cgroup_id: u64,
+    /// See [crate::argv_hash], 0 if the arguments could not be read
+    pub argv_hash: u64,
+    pub pid: i32,
+    pub filename_len: u32,
+    pub filename: [u8; EXEC_FILENAME_LEN],
+}
+
+impl ProcessExec {
+    /// The path of the executed binary, as passed to execve.
+    pub fn filename(&self) -> &[u8] {
+        let len = (self.filename_len as usize).min(EXEC_FILENAME_LEN);
+        &self.filename[..len]
+    }
+}
+
+impl HasCgroup for ProcessExec {
+    fn cgroup_id(&self) -> u64 {
+        self.cgroup_id
+    }
+}
+
+impl HasHostPid for ProcessExec {
+    fn host_pid(&self) -> i32 {
+        self.pid
+    }
+}
+
+/// The FNV-1a hash of the arguments of an exec, each followed by a NUL.
+/// Only the first [EXEC_MAX_ARGS] arguments and their first
+/// [EXEC_MAX_ARG_LEN] bytes are hashed, as by the eBPF probe.
+pub fn argv_hash<'a>(args: impl IntoIterator<Item = &'a [u8]>) -> u64 {
+    args.into_iter().take(EXEC_MAX_ARGS).fold(argv_hash_start(), |hash, arg| {
+        let arg = &arg[..arg.len().min(EXEC_MAX_ARG_LEN)];
+        argv_hash_update(argv_hash_update(hash, arg), &[0])
+    })
+}
+
+/// The hash of no arguments.
+pub const fn argv_hash_start() -> u64 {
+    FNV_OFFSET_BASIS
+}
+
+/// Adds `bytes` to `hash`.
+pub fn argv_hash_update(hash: u64, bytes: &[u8]) -> u64 {
+    let mut hash = hash;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// A [TcpConnect] of a socket starting to connect.
+pub const TCP_CONNECT_CONNECTING: u32 = 0;
+/// A [TcpConnect] of a socket completing the handshake.
+pub const TCP_CONNECT_ESTABLISHED: u32 = 1;
+/// A [TcpConnect] of a socket closed before completing the handshake.
+pub const TCP_CONNECT_FAILED: u32 = 2;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TcpConnect {
+    /// The cgroup of the process connecting
+    pub cgroup_id: u64,
+    pub pid: i32,
+    /// One of the TCP_CONNECT_* constants
+    pub state: u32,
+    /// AF_INET or AF_INET6
+    pub family: u16,
+    pub sport: u16,
+    pub dport: u16,
+    pub padding: u16,
+    /// IPv4 addresses take the first 4 bytes
+    pub saddr: [u8; 16],
+    pub daddr: [u8; 16],
+}
+
+impl HasCgroup for TcpConnect {
+    fn cgroup_id(&self) -> u64 {
+        self.cgroup_id
+    }
+}
+
+impl HasHostPid for TcpConnect {
+    fn host_pid(&self) -> i32 {
+        self.pid
+    }
+}
+
+/// Network counters of a cgroup, indexed by the NET_* constants. An array,
+/// as the values of maps read by auraed.
+pub type NetworkCounters = [u64; 4];
+pub const NET_RX_BYTES: usize = 0;
+pub const NET_RX_PACKETS: usize = 1;
+pub const NET_TX_BYTES: usize = 2;
+pub const NET_TX_PACKETS: usize = 3;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OomKill {
+    /// The cgroup of the task that ran out of memory, which is within the
+    /// cgroup running out of memory for cgroup OOMs
+    pub cgroup_id: u64,
+    /// Pages of memory available to the OOM killer, the limit of the cgroup
+    /// for cgroup OOMs
+    pub total_pages: u64,
+    /// Pages of memory of the victim as weighed by the OOM killer: its RSS,
+    /// swap and page tables adjusted by its oom_score_adj
+    pub victim_points: i64,
+    /// The victim
+    pub pid: i32,
+    /// enum oom_constraint, 3 for cgroup OOMs
+    pub constraint: u32,
+}
+
+impl HasCgroup for OomKill {
+    fn cgroup_id(&self) -> u64 {
+        self.cgroup_id
+    }
+}
+
+impl HasHostPid for OomKill {
+    fn host_pid(&self) -> i32 {
+        self.pid
+    }
+}
+
+/// Top-level cells whose processes the probes report events of, by the id of
+/// their cgroup. Once [CGROUP_FILTER_ENABLED] is set, the events of processes
+/// outside of these cells are dropped in the kernel.
+pub const CGROUP_FILTER_MAP: &str = "CGROUP_FILTER";
+pub const CGROUP_FILTER_MAX_ENTRIES: u32 = 4096;
+/// Key of the entry of [CGROUP_FILTER_MAP] turning the filter on, no cgroup
+/// has the id 0.
+pub const CGROUP_FILTER_ENABLED: u64 = 0;
+/// Level of the cgroups of top-level cells in the cgroup hierarchy, right
+/// below the root cgroup.
+pub const CELL_CGROUP_LEVEL: i32 = 1;
+
+/// A stack sampled by the CPU profiler, indexed by the PROFILE_* constants:
+/// the sampled process and the ids of its user and kernel stacks in
+/// [PROFILE_STACK_TRACES_MAP], negative if the stack could not be walked.
+pub type ProfileStack = [i32; 3];
+pub const PROFILE_TGID: usize = 0;
+pub const PROFILE_USER_STACK: usize = 1;
+pub const PROFILE_KERNEL_STACK: usize = 2;
+/// The samples of each [ProfileStack], per CPU.
+pub const PROFILE_STACK_COUNTS_MAP: &str = "PROFILE_STACK_COUNTS";
+/// The instruction pointers of the frames of the sampled stacks, by stack id.
+pub const PROFILE_STACK_TRACES_MAP: &str = "PROFILE_STACK_TRACES";
+/// Distinct stacks a profile holds, further stacks are not sampled.
+pub const PROFILE_MAX_STACKS: u32 = 16384;
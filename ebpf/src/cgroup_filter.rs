@@ -0,0 +1,63 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+//! The cgroup filter of the probes reporting the events of processes, see
+//! [aurae_ebpf_shared::CGROUP_FILTER_MAP].
+
+use aurae_ebpf_shared::{
+    CELL_CGROUP_LEVEL, CGROUP_FILTER_ENABLED, CGROUP_FILTER_MAX_ENTRIES,
+};
+use aya_ebpf::helpers;
+use aya_ebpf::macros::map;
+use aya_ebpf::maps::HashMap;
+
+#[map(name = "CGROUP_FILTER")]
+static mut CGROUP_FILTER: HashMap<u64, u8> =
+    HashMap::<u64, u8>::with_max_entries(CGROUP_FILTER_MAX_ENTRIES, 0);
+
+/// Whether the events of the current task are reported: always, unless the
+/// filter is turned on, then only within the top-level cells of the filter.
+/// Requires Linux 5.7 or later.
+#[inline(always)]
+pub fn is_observed() -> bool {
+    unsafe {
+        if CGROUP_FILTER.get(&CGROUP_FILTER_ENABLED).is_none() {
+            return true;
+        }
+        let cell =
+            helpers::bpf_get_current_ancestor_cgroup_id(CELL_CGROUP_LEVEL);
+        CGROUP_FILTER.get(&cell).is_some()
+    }
+}
@@ -0,0 +1,47 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+//! The offsets of the kernel fields the probes read, relocated by auraed when
+//! it loads a probe: each offset is a `#[no_mangle]` static defaulting to the
+//! layout the probe was written against, which auraed overwrites with the
+//! layout of the running kernel, read from the formats of tracepoints in
+//! tracefs and from the BTF of the kernel.
+
+/// Reads a relocated offset.
+#[inline(always)]
+pub fn get(offset: &u32) -> usize {
+    // A volatile read, the compiler would otherwise use the default
+    unsafe { core::ptr::read_volatile(offset) as usize }
+}
@@ -0,0 +1,100 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+#![no_std]
+#![no_main]
+
+use aurae_ebpf_shared::{
+    NetworkCounters, NET_RX_BYTES, NET_RX_PACKETS, NET_TX_BYTES, NET_TX_PACKETS,
+};
+use aya_ebpf::bindings::__sk_buff;
+use aya_ebpf::helpers;
+use aya_ebpf::macros::{cgroup_skb, map};
+use aya_ebpf::maps::PerCpuHashMap;
+use aya_ebpf::programs::SkBuffContext;
+use aya_ebpf::EbpfContext;
+
+#[link_section = "license"]
+#[used]
+pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
+
+/// The counters of the cgroups of the sockets sending and receiving, by
+/// cgroup id. The programs are attached to the cgroups of cells, so only
+/// cgroups of cells and their descendants are counted.
+#[map(name = "NETWORK_COUNTERS")]
+static mut NETWORK_COUNTERS: PerCpuHashMap<u64, NetworkCounters> =
+    PerCpuHashMap::<u64, NetworkCounters>::with_max_entries(10240, 0);
+
+/// Lets the packet pass, the programs only count.
+const ALLOW: i32 = 1;
+
+#[cgroup_skb(ingress)]
+pub fn cgroup_skb_ingress(ctx: SkBuffContext) -> i32 {
+    count(&ctx, NET_RX_BYTES, NET_RX_PACKETS);
+    ALLOW
+}
+
+#[cgroup_skb(egress)]
+pub fn cgroup_skb_egress(ctx: SkBuffContext) -> i32 {
+    count(&ctx, NET_TX_BYTES, NET_TX_PACKETS);
+    ALLOW
+}
+
+fn count(ctx: &SkBuffContext, bytes: usize, packets: usize) {
+    let cgroup_id =
+        unsafe { helpers::bpf_skb_cgroup_id(ctx.as_ptr() as *mut __sk_buff) };
+    let len = ctx.len() as u64;
+
+    // The map is per CPU, no other program updates the counters meanwhile
+    match unsafe { NETWORK_COUNTERS.get_ptr_mut(&cgroup_id) } {
+        Some(counters) => unsafe {
+            (*counters)[bytes] += len;
+            (*counters)[packets] += 1;
+        },
+        None => {
+            let mut counters: NetworkCounters = [0; 4];
+            counters[bytes] = len;
+            counters[packets] = 1;
+            // A cgroup not fitting the map is not counted
+            let _ =
+                unsafe { NETWORK_COUNTERS.insert(&cgroup_id, &counters, 0) };
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { core::hint::unreachable_unchecked() }
+}
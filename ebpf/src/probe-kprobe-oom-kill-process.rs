@@ -0,0 +1,164 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+#![no_std]
+#![no_main]
+
+use aurae_ebpf_shared::OomKill;
+use aya_ebpf::helpers;
+use aya_ebpf::macros::{kprobe, map, tracepoint};
+use aya_ebpf::maps::{LruHashMap, PerfEventArray};
+use aya_ebpf::programs::{ProbeContext, TracePointContext};
+
+mod offsets;
+
+#[link_section = "license"]
+#[used]
+pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
+
+#[map(name = "OOM_KILLS")]
+static mut OOM_KILLS: PerfEventArray<OomKill> =
+    PerfEventArray::<OomKill>::with_max_entries(1024, 0);
+
+/// The OOM kills in progress, by the thread running the OOM killer. The
+/// victim is only known by its task_struct in oom_kill_process, whose layout
+/// varies, so the kill is reported once the victim is marked.
+#[map(name = "PENDING_OOM_KILLS")]
+static mut PENDING_OOM_KILLS: LruHashMap<u64, PendingOomKill> =
+    LruHashMap::<u64, PendingOomKill>::with_max_entries(1024, 0);
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct PendingOomKill {
+    /// bpf_ktime_get_ns of oom_kill_process
+    started_at: u64,
+    kill: OomKill,
+}
+
+/// Time within which oom_kill_process marks its victims. Entries of kills
+/// are not removed, as cgroups with memory.oom.group set have several
+/// victims, older ones are ignored.
+const MARK_WINDOW_NS: u64 = 1_000_000_000;
+
+// Offsets in struct oom_control of <linux>/include/linux/oom.h, unchanged
+// since 4.19, relocated by auraed from the BTF of the kernel
+#[no_mangle]
+static TOTALPAGES_OFFSET: u32 = 32;
+#[no_mangle]
+static CHOSEN_POINTS_OFFSET: u32 = 48;
+#[no_mangle]
+static CONSTRAINT_OFFSET: u32 = 56;
+
+// Offset in <linux>/include/trace/events/oom.h, relocated by auraed
+#[no_mangle]
+static VICTIM_PID_OFFSET: u32 = 8;
+
+#[kprobe]
+pub fn kprobe_oom_kill_process(ctx: ProbeContext) -> u32 {
+    match try_oom_kill_process(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_oom_kill_process(ctx: ProbeContext) -> Result<u32, u32> {
+    let oc: *const u8 = ctx.arg(0).ok_or(1u32)?;
+    let read = |offset: &u32| unsafe {
+        helpers::bpf_probe_read_kernel(
+            oc.add(offsets::get(offset)) as *const u64
+        )
+        .map_err(|errn| errn as u32)
+    };
+
+    let kill = OomKill {
+        cgroup_id: unsafe { helpers::bpf_get_current_cgroup_id() },
+        total_pages: read(&TOTALPAGES_OFFSET)?,
+        victim_points: read(&CHOSEN_POINTS_OFFSET)? as i64,
+        pid: 0,
+        constraint: read(&CONSTRAINT_OFFSET)? as u32,
+    };
+    let pending = PendingOomKill {
+        started_at: unsafe { helpers::bpf_ktime_get_ns() },
+        kill,
+    };
+    let tid = helpers::bpf_get_current_pid_tgid();
+    unsafe {
+        PENDING_OOM_KILLS
+            .insert(&tid, &pending, 0)
+            .map_err(|errn| errn as u32)?;
+    }
+    Ok(0)
+}
+
+#[tracepoint(name = "oom_mark_victim", category = "oom")]
+pub fn oom_mark_victim(ctx: TracePointContext) -> u32 {
+    match try_oom_mark_victim(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_oom_mark_victim(ctx: TracePointContext) -> Result<u32, u32> {
+    let pid: i32 = unsafe {
+        match ctx.read_at(offsets::get(&VICTIM_PID_OFFSET)) {
+            Ok(s) => s,
+            Err(errn) => return Err(errn as u32),
+        }
+    };
+
+    // Victims are also marked outside of oom_kill_process, such as tasks
+    // exiting while out of memory, which are not killed
+    let tid = helpers::bpf_get_current_pid_tgid();
+    let pending = match unsafe { PENDING_OOM_KILLS.get(&tid) } {
+        Some(pending) => *pending,
+        None => return Ok(0),
+    };
+    let now = unsafe { helpers::bpf_ktime_get_ns() };
+    if now.saturating_sub(pending.started_at) > MARK_WINDOW_NS {
+        return Ok(0);
+    }
+    let mut kill = pending.kill;
+    kill.pid = pid;
+
+    unsafe {
+        OOM_KILLS.output(&ctx, &kill, 0);
+    }
+    Ok(0)
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { core::hint::unreachable_unchecked() }
+}
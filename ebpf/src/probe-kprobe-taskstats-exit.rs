@@ -43,6 +43,8 @@ use aya_ebpf::macros::map;
 use aya_ebpf::maps::PerfEventArray;
 use aya_ebpf::programs::ProbeContext;
 
+mod cgroup_filter;
+
 #[link_section = "license"]
 #[used]
 pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
@@ -53,6 +55,10 @@ static mut PROCESS_EXITS: PerfEventArray<ProcessExit> =
 
 #[kprobe]
 pub fn kprobe_taskstats_exit(ctx: ProbeContext) -> u32 {
+    if !cgroup_filter::is_observed() {
+        return 0;
+    }
+
     let pid = helpers::bpf_get_current_pid_tgid() as i32;
     let e = ProcessExit { pid };
 
@@ -0,0 +1,126 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+#![no_std]
+#![no_main]
+
+use aurae_ebpf_shared::{
+    ProfileStack, PROFILE_KERNEL_STACK, PROFILE_MAX_STACKS, PROFILE_TGID,
+    PROFILE_USER_STACK,
+};
+use aya_ebpf::bindings::BPF_F_USER_STACK;
+use aya_ebpf::helpers;
+use aya_ebpf::macros::{map, perf_event};
+use aya_ebpf::maps::{PerCpuHashMap, StackTrace};
+use aya_ebpf::programs::PerfEventContext;
+use aya_ebpf::EbpfContext;
+
+#[link_section = "license"]
+#[used]
+pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
+
+#[map(name = "PROFILE_STACK_TRACES")]
+static mut PROFILE_STACK_TRACES: StackTrace =
+    StackTrace::with_max_entries(PROFILE_MAX_STACKS, 0);
+
+#[map(name = "PROFILE_STACK_COUNTS")]
+static mut PROFILE_STACK_COUNTS: PerCpuHashMap<ProfileStack, u64> =
+    PerCpuHashMap::<ProfileStack, u64>::with_max_entries(PROFILE_MAX_STACKS, 0);
+
+// The target of the profile, set by auraed when it loads the probe: the
+// process TARGET_TGID if set, the processes within the cgroup
+// TARGET_CGROUP_ID at TARGET_CGROUP_LEVEL of the hierarchy otherwise
+#[no_mangle]
+static TARGET_TGID: u64 = 0;
+#[no_mangle]
+static TARGET_CGROUP_ID: u64 = 0;
+#[no_mangle]
+static TARGET_CGROUP_LEVEL: u64 = 0;
+
+#[perf_event]
+pub fn perf_event_cpu_profile(ctx: PerfEventContext) -> u32 {
+    if is_target(&ctx) {
+        sample(&ctx);
+    }
+    0
+}
+
+#[inline(always)]
+fn target(global: &u64) -> u64 {
+    // A volatile read, the compiler would otherwise use the default
+    unsafe { core::ptr::read_volatile(global) }
+}
+
+fn is_target(ctx: &PerfEventContext) -> bool {
+    let tgid = ctx.tgid();
+    // The idle task of the CPU
+    if tgid == 0 {
+        return false;
+    }
+    let target_tgid = target(&TARGET_TGID);
+    if target_tgid != 0 {
+        return u64::from(tgid) == target_tgid;
+    }
+    let level = target(&TARGET_CGROUP_LEVEL) as i32;
+    let cgroup_id =
+        unsafe { helpers::bpf_get_current_ancestor_cgroup_id(level) };
+    cgroup_id == target(&TARGET_CGROUP_ID)
+}
+
+fn sample(ctx: &PerfEventContext) {
+    let mut stack: ProfileStack = [0; 3];
+    stack[PROFILE_TGID] = ctx.tgid() as i32;
+    unsafe {
+        stack[PROFILE_USER_STACK] = PROFILE_STACK_TRACES
+            .get_stackid(ctx, BPF_F_USER_STACK as u64)
+            .map_or(-1, |id| id as i32);
+        stack[PROFILE_KERNEL_STACK] =
+            PROFILE_STACK_TRACES.get_stackid(ctx, 0).map_or(-1, |id| id as i32);
+    }
+
+    // The map is per CPU, no other program updates the count meanwhile
+    match unsafe { PROFILE_STACK_COUNTS.get_ptr_mut(&stack) } {
+        Some(count) => unsafe { *count += 1 },
+        None => {
+            // A stack not fitting the map is not counted
+            let _ = unsafe { PROFILE_STACK_COUNTS.insert(&stack, &1, 0) };
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { core::hint::unreachable_unchecked() }
+}
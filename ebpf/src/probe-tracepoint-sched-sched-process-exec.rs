@@ -0,0 +1,195 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+#![no_std]
+#![no_main]
+
+use aurae_ebpf_shared::{
+    argv_hash_start, argv_hash_update, ProcessExec, EXEC_MAX_ARGS,
+    EXEC_MAX_ARG_LEN,
+};
+use aya_ebpf::helpers;
+use aya_ebpf::macros::map;
+use aya_ebpf::macros::tracepoint;
+use aya_ebpf::maps::{HashMap, PerCpuArray, PerfEventArray};
+use aya_ebpf::programs::TracePointContext;
+use aya_ebpf::EbpfContext;
+
+mod cgroup_filter;
+mod offsets;
+
+#[link_section = "license"]
+#[used]
+pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
+
+#[map(name = "PROCESS_EXECS")]
+static mut PROCESS_EXECS: PerfEventArray<ProcessExec> =
+    PerfEventArray::<ProcessExec>::with_max_entries(1024, 0);
+
+/// The hash of the arguments of the execs in progress, by the thread
+/// calling exec. The arguments are gone with the old memory of the process
+/// by the time sched_process_exec fires, so they are hashed on entry.
+#[map(name = "ARGV_HASHES")]
+static mut ARGV_HASHES: HashMap<u32, u64> =
+    HashMap::<u32, u64>::with_max_entries(10240, 0);
+
+/// Scratch space for events, too large for the stack of eBPF programs.
+#[map(name = "EXEC_SCRATCH")]
+static mut EXEC_SCRATCH: PerCpuArray<ProcessExec> =
+    PerCpuArray::<ProcessExec>::with_max_entries(1, 0);
+
+#[map(name = "ARG_SCRATCH")]
+static mut ARG_SCRATCH: PerCpuArray<[u8; EXEC_MAX_ARG_LEN + 1]> =
+    PerCpuArray::<[u8; EXEC_MAX_ARG_LEN + 1]>::with_max_entries(1, 0);
+
+// Offsets in <linux>/include/trace/events/sched.h, relocated by auraed
+#[no_mangle]
+static FILENAME_OFFSET: u32 = 8;
+#[no_mangle]
+static PID_OFFSET: u32 = 12;
+#[no_mangle]
+static OLD_PID_OFFSET: u32 = 16;
+
+// Offsets of argv in the syscalls/sys_enter_execve(at) events, generated
+// from the syscall definitions, relocated by auraed
+#[no_mangle]
+static EXECVE_ARGV_OFFSET: u32 = 24;
+#[no_mangle]
+static EXECVEAT_ARGV_OFFSET: u32 = 32;
+
+#[tracepoint(name = "sys_enter_execve", category = "syscalls")]
+pub fn sys_enter_execve(ctx: TracePointContext) -> u32 {
+    // An exec whose arguments can not be read is reported without a hash
+    let _ = try_hash_argv(&ctx, &EXECVE_ARGV_OFFSET);
+    0
+}
+
+#[tracepoint(name = "sys_enter_execveat", category = "syscalls")]
+pub fn sys_enter_execveat(ctx: TracePointContext) -> u32 {
+    let _ = try_hash_argv(&ctx, &EXECVEAT_ARGV_OFFSET);
+    0
+}
+
+fn try_hash_argv(
+    ctx: &TracePointContext,
+    argv_offset: &u32,
+) -> Result<(), i64> {
+    if !cgroup_filter::is_observed() {
+        return Ok(());
+    }
+    let argv: *const *const u8 =
+        unsafe { ctx.read_at(offsets::get(argv_offset))? };
+    let arg = unsafe { &mut *ARG_SCRATCH.get_ptr_mut(0).ok_or(0)? };
+
+    let mut hash = argv_hash_start();
+    for i in 0..EXEC_MAX_ARGS {
+        let ptr: *const u8 =
+            unsafe { helpers::bpf_probe_read_user(argv.add(i))? };
+        if ptr.is_null() {
+            break;
+        }
+        let bytes =
+            unsafe { helpers::bpf_probe_read_user_str_bytes(ptr, arg)? };
+        hash = argv_hash_update(argv_hash_update(hash, bytes), &[0]);
+    }
+
+    let tid = helpers::bpf_get_current_pid_tgid() as u32;
+    unsafe { ARGV_HASHES.insert(&tid, &hash, 0) }
+}
+
+#[tracepoint(name = "sched_process_exec", category = "sched")]
+pub fn sched_process_exec(ctx: TracePointContext) -> u32 {
+    match try_process_exec(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_process_exec(ctx: TracePointContext) -> Result<u32, u32> {
+    let filename_loc: u32 = unsafe {
+        match ctx.read_at(offsets::get(&FILENAME_OFFSET)) {
+            Ok(s) => s,
+            Err(errn) => return Err(errn as u32),
+        }
+    };
+
+    let pid: i32 = unsafe {
+        match ctx.read_at(offsets::get(&PID_OFFSET)) {
+            Ok(s) => s,
+            Err(errn) => return Err(errn as u32),
+        }
+    };
+
+    let old_pid: u32 = unsafe {
+        match ctx.read_at(offsets::get(&OLD_PID_OFFSET)) {
+            Ok(s) => s,
+            Err(errn) => return Err(errn as u32),
+        }
+    };
+
+    if !cgroup_filter::is_observed() {
+        return Ok(0);
+    }
+
+    let exec = match unsafe { EXEC_SCRATCH.get_ptr_mut(0) } {
+        Some(exec) => unsafe { &mut *exec },
+        None => return Err(0),
+    };
+    exec.cgroup_id = unsafe { helpers::bpf_get_current_cgroup_id() };
+    exec.pid = pid;
+    exec.argv_hash = unsafe { ARGV_HASHES.get(&old_pid).copied().unwrap_or(0) };
+    unsafe {
+        let _ = ARGV_HASHES.remove(&old_pid);
+    }
+
+    // The lower 16 bits of a __data_loc field are the offset of its data in
+    // the event
+    let filename = unsafe {
+        let src =
+            (ctx.as_ptr() as *const u8).add((filename_loc & 0xffff) as usize);
+        helpers::bpf_probe_read_kernel_str_bytes(src, &mut exec.filename)
+    };
+    exec.filename_len = filename.map(|f| f.len() as u32).unwrap_or(0);
+
+    unsafe {
+        PROCESS_EXECS.output(&ctx, exec, 0);
+    }
+    Ok(0)
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { core::hint::unreachable_unchecked() }
+}
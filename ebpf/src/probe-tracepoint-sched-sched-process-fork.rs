@@ -42,6 +42,9 @@ use aya_ebpf::macros::tracepoint;
 use aya_ebpf::maps::PerfEventArray;
 use aya_ebpf::programs::TracePointContext;
 
+mod cgroup_filter;
+mod offsets;
+
 #[link_section = "license"]
 #[used]
 pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
@@ -50,8 +53,11 @@ pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
 static mut FORKED_PROCESSES: PerfEventArray<ForkedProcess> =
     PerfEventArray::<ForkedProcess>::with_max_entries(1024, 0);
 
-const PARENT_PID_OFFSET: usize = 24;
-const CHILD_PID_OFFSET: usize = 44;
+// Offsets in <linux>/include/trace/events/sched.h, relocated by auraed
+#[no_mangle]
+static PARENT_PID_OFFSET: u32 = 24;
+#[no_mangle]
+static CHILD_PID_OFFSET: u32 = 44;
 
 #[tracepoint(name = "sched_process_fork", category = "sched")]
 pub fn sched_process_fork(ctx: TracePointContext) -> i32 {
@@ -62,15 +68,20 @@ pub fn sched_process_fork(ctx: TracePointContext) -> i32 {
 }
 
 fn try_forked_process(ctx: TracePointContext) -> Result<i32, i32> {
+    // The child starts in the cgroup of its parent, the current task
+    if !cgroup_filter::is_observed() {
+        return Ok(0);
+    }
+
     let parent_pid: i32 = unsafe {
-        match ctx.read_at(PARENT_PID_OFFSET) {
+        match ctx.read_at(offsets::get(&PARENT_PID_OFFSET)) {
             Ok(s) => s,
             Err(errn) => return Err(errn as i32),
         }
     };
 
     let child_pid: i32 = unsafe {
-        match ctx.read_at(CHILD_PID_OFFSET) {
+        match ctx.read_at(offsets::get(&CHILD_PID_OFFSET)) {
             Ok(s) => s,
             Err(errn) => return Err(errn as i32),
         }
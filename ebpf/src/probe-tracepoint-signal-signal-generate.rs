@@ -43,6 +43,9 @@ use aya_ebpf::macros::tracepoint;
 use aya_ebpf::maps::PerfEventArray;
 use aya_ebpf::programs::TracePointContext;
 
+mod cgroup_filter;
+mod offsets;
+
 #[link_section = "license"]
 #[used]
 pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
@@ -51,8 +54,7 @@ pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
 static mut SIGNALS: PerfEventArray<Signal> =
     PerfEventArray::<Signal>::with_max_entries(1024, 0);
 
-// TODO (jeroensoeters): figure out how stable these offsets are and if we want
-//    to read from /sys/kernel/debug/tracing/events/signal/signal_generate/format
+// Relocated by auraed from /sys/kernel/tracing/events/signal/signal_generate/format
 //
 // @krisnova Checked going back to kernel version 5.0 these offsets remain unchanged:
 //    <linux>/include/trace/events/signal.h
@@ -60,8 +62,10 @@ static mut SIGNALS: PerfEventArray<Signal> =
 //      - 5.18 https://github.com/torvalds/linux/blob/v5.18/include/trace/events/signal.h
 //      - 5.4  https://github.com/torvalds/linux/blob/v5.4/include/trace/events/signal.h
 //      - 5.0  https://github.com/torvalds/linux/blob/v5.0/include/trace/events/signal.h
-const SIGNAL_OFFSET: usize = 8;
-const PID_OFFSET: usize = 36;
+#[no_mangle]
+static SIGNAL_OFFSET: u32 = 8;
+#[no_mangle]
+static PID_OFFSET: u32 = 36;
 
 #[tracepoint(name = "signal_signal_generate", category = "signal")]
 pub fn signals(ctx: TracePointContext) -> u32 {
@@ -72,15 +76,19 @@ pub fn signals(ctx: TracePointContext) -> u32 {
 }
 
 fn try_signals(ctx: TracePointContext) -> Result<u32, u32> {
+    if !cgroup_filter::is_observed() {
+        return Ok(0);
+    }
+
     let signum: i32 = unsafe {
-        match ctx.read_at(SIGNAL_OFFSET) {
+        match ctx.read_at(offsets::get(&SIGNAL_OFFSET)) {
             Ok(s) => s,
             Err(errn) => return Err(errn as u32),
         }
     };
 
     let pid: i32 = unsafe {
-        match ctx.read_at(PID_OFFSET) {
+        match ctx.read_at(offsets::get(&PID_OFFSET)) {
             Ok(s) => s,
             Err(errn) => return Err(errn as u32),
         }
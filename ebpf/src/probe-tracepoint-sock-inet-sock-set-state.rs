@@ -0,0 +1,184 @@
+/* -------------------------------------------------------------------------- *\
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ * -------------------------------------------------------------------------- *
+ * Copyright 2022 - 2024, the aurae contributors                              *
+ * SPDX-License-Identifier: Apache-2.0                                        *
+\* -------------------------------------------------------------------------- */
+/* -------------------------------------------------------------------------- *\
+ *                      SPDX-License-Identifier: GPL-2.0                      *
+ *                      SPDX-License-Identifier: MIT                          *
+ *                                                                            *
+ *                +--------------------------------------------+              *
+ *                |   █████╗ ██╗   ██╗██████╗  █████╗ ███████╗ |              *
+ *                |  ██╔══██╗██║   ██║██╔══██╗██╔══██╗██╔════╝ |              *
+ *                |  ███████║██║   ██║██████╔╝███████║█████╗   |              *
+ *                |  ██╔══██║██║   ██║██╔══██╗██╔══██║██╔══╝   |              *
+ *                |  ██║  ██║╚██████╔╝██║  ██║██║  ██║███████╗ |              *
+ *                |  ╚═╝  ╚═╝ ╚═════╝ ╚═╝  ╚═╝╚═╝  ╚═╝╚══════╝ |              *
+ *                +--------------------------------------------+              *
+ *                                                                            *
+ *                         Distributed Systems Runtime                        *
+ *                                                                            *
+ * -------------------------------------------------------------------------- *
+ * Dual Licensed: GNU GENERAL PUBLIC LICENSE 2.0                              *
+ * Dual Licensed: MIT License                                                 *
+ * Copyright 2023 The Aurae Authors (The Nivenly Foundation)                  *
+\* -------------------------------------------------------------------------- */
+
+#![no_std]
+#![no_main]
+
+use aurae_ebpf_shared::{
+    TcpConnect, TCP_CONNECT_CONNECTING, TCP_CONNECT_ESTABLISHED,
+    TCP_CONNECT_FAILED,
+};
+use aya_ebpf::helpers;
+use aya_ebpf::macros::map;
+use aya_ebpf::macros::tracepoint;
+use aya_ebpf::maps::{HashMap, PerfEventArray};
+use aya_ebpf::programs::TracePointContext;
+
+mod cgroup_filter;
+mod offsets;
+
+#[link_section = "license"]
+#[used]
+pub static LICENSE: [u8; 13] = *b"Dual MIT/GPL\0";
+
+#[map(name = "TCP_CONNECTS")]
+static mut TCP_CONNECTS: PerfEventArray<TcpConnect> =
+    PerfEventArray::<TcpConnect>::with_max_entries(1024, 0);
+
+/// The connects in progress, by socket. The handshake completes in softirq
+/// context, where the current task is not the one connecting, so the task
+/// is remembered when the connect starts.
+#[map(name = "PENDING_CONNECTS")]
+static mut PENDING_CONNECTS: HashMap<u64, TcpConnect> =
+    HashMap::<u64, TcpConnect>::with_max_entries(10240, 0);
+
+// Offsets in <linux>/include/trace/events/sock.h, as of 5.6 which added the
+// protocol field, relocated by auraed
+#[no_mangle]
+static SKADDR_OFFSET: u32 = 8;
+#[no_mangle]
+static OLDSTATE_OFFSET: u32 = 16;
+#[no_mangle]
+static NEWSTATE_OFFSET: u32 = 20;
+#[no_mangle]
+static SPORT_OFFSET: u32 = 24;
+#[no_mangle]
+static DPORT_OFFSET: u32 = 26;
+#[no_mangle]
+static FAMILY_OFFSET: u32 = 28;
+#[no_mangle]
+static PROTOCOL_OFFSET: u32 = 30;
+#[no_mangle]
+static SADDR_OFFSET: u32 = 32;
+#[no_mangle]
+static DADDR_OFFSET: u32 = 36;
+#[no_mangle]
+static SADDR_V6_OFFSET: u32 = 40;
+#[no_mangle]
+static DADDR_V6_OFFSET: u32 = 56;
+
+const AF_INET: u16 = 2;
+const IPPROTO_TCP: u16 = 6;
+
+// <linux>/include/net/tcp_states.h
+const TCP_ESTABLISHED: i32 = 1;
+const TCP_SYN_SENT: i32 = 2;
+const TCP_CLOSE: i32 = 7;
+
+#[tracepoint(name = "inet_sock_set_state", category = "sock")]
+pub fn inet_sock_set_state(ctx: TracePointContext) -> u32 {
+    match try_inet_sock_set_state(ctx) {
+        Ok(ret) => ret,
+        Err(ret) => ret,
+    }
+}
+
+fn try_inet_sock_set_state(ctx: TracePointContext) -> Result<u32, u32> {
+    let protocol: u16 = read(&ctx, &PROTOCOL_OFFSET)?;
+    if protocol != IPPROTO_TCP {
+        return Ok(0);
+    }
+    let skaddr: u64 = read(&ctx, &SKADDR_OFFSET)?;
+    let oldstate: i32 = read(&ctx, &OLDSTATE_OFFSET)?;
+    let newstate: i32 = read(&ctx, &NEWSTATE_OFFSET)?;
+
+    match (oldstate, newstate) {
+        // An active open, in the context of the task connecting
+        (TCP_CLOSE, TCP_SYN_SENT) => {
+            // The outcome of connects that are not remembered is not
+            // reported either
+            if !cgroup_filter::is_observed() {
+                return Ok(0);
+            }
+            let family: u16 = read(&ctx, &FAMILY_OFFSET)?;
+            let mut connect = TcpConnect {
+                cgroup_id: unsafe { helpers::bpf_get_current_cgroup_id() },
+                pid: (helpers::bpf_get_current_pid_tgid() >> 32) as i32,
+                state: TCP_CONNECT_CONNECTING,
+                family,
+                sport: read(&ctx, &SPORT_OFFSET)?,
+                dport: read(&ctx, &DPORT_OFFSET)?,
+                padding: 0,
+                saddr: [0; 16],
+                daddr: [0; 16],
+            };
+            if family == AF_INET {
+                let saddr: [u8; 4] = read(&ctx, &SADDR_OFFSET)?;
+                let daddr: [u8; 4] = read(&ctx, &DADDR_OFFSET)?;
+                connect.saddr[..4].copy_from_slice(&saddr);
+                connect.daddr[..4].copy_from_slice(&daddr);
+            } else {
+                connect.saddr = read(&ctx, &SADDR_V6_OFFSET)?;
+                connect.daddr = read(&ctx, &DADDR_V6_OFFSET)?;
+            }
+
+            // A connect that can not be remembered is reported without its
+            // outcome
+            unsafe {
+                let _ = PENDING_CONNECTS.insert(&skaddr, &connect, 0);
+                TCP_CONNECTS.output(&ctx, &connect, 0);
+            }
+        }
+        (TCP_SYN_SENT, TCP_ESTABLISHED | TCP_CLOSE) => {
+            let mut connect = match unsafe { PENDING_CONNECTS.get(&skaddr) } {
+                Some(connect) => *connect,
+                None => return Ok(0),
+            };
+            // Unbound sockets get their source port after entering
+            // SYN_SENT, so the connecting event may lack it
+            connect.sport = read(&ctx, &SPORT_OFFSET)?;
+            connect.state = if newstate == TCP_ESTABLISHED {
+                TCP_CONNECT_ESTABLISHED
+            } else {
+                TCP_CONNECT_FAILED
+            };
+            unsafe {
+                let _ = PENDING_CONNECTS.remove(&skaddr);
+                TCP_CONNECTS.output(&ctx, &connect, 0);
+            }
+        }
+        _ => {}
+    }
+    Ok(0)
+}
+
+fn read<T>(ctx: &TracePointContext, offset: &u32) -> Result<T, u32> {
+    unsafe { ctx.read_at(offsets::get(offset)) }.map_err(|errn| errn as u32)
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { core::hint::unreachable_unchecked() }
+}
@@ -65,6 +65,12 @@ wait(5000);
 let machines = await vmService.list(<vms.VmServiceListRequest>{});
 console.log('Listed VMs:', machines)
 
+// Pause and resume the VM
+await vmService.pause(<vms.VmServicePauseRequest>{ vmId: "ae-sleeper-vm" });
+console.log('Paused VM')
+await vmService.resume(<vms.VmServiceResumeRequest>{ vmId: "ae-sleeper-vm" });
+console.log('Resumed VM')
+
 // Stop the VM
 await vmService.stop(<vms.VmServiceStopRequest>{ vmId: "ae-sleeper-vm" });
 
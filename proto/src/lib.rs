@@ -38,6 +38,14 @@ pub mod cri {
     include!("../gen/runtime.v1.rs");
 }
 
+pub mod images {
+    include!("../gen/aurae.images.v0.rs");
+}
+
+pub mod network {
+    include!("../gen/aurae.network.v0.rs");
+}
+
 pub mod observe {
     include!("../gen/aurae.observe.v0.rs");
 }